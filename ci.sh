@@ -25,6 +25,8 @@ cargo batch \
     --- build --release --manifest-path host/Cargo.toml --no-default-features --features gatt,central \
     --- build --release --manifest-path host/Cargo.toml --no-default-features --features gatt,peripheral,central,scan \
     --- build --release --manifest-path host/Cargo.toml --no-default-features --features gatt,peripheral,central,scan,controller-host-flow-control \
+    --- build --release --manifest-path host/Cargo.toml --no-default-features --features gatt,peripheral,central,security \
+    --- build --release --manifest-path host/Cargo.toml --no-default-features --features gatt,peripheral,central,security-debug-keys \
     --- build --release --manifest-path examples/nrf-sdc/Cargo.toml --target thumbv7em-none-eabihf --features nrf52840 \
     --- build --release --manifest-path examples/nrf-sdc/Cargo.toml --target thumbv7em-none-eabihf --features nrf52833 --artifact-dir tests/nrf-sdc \
     --- build --release --manifest-path examples/nrf-sdc/Cargo.toml --target thumbv7em-none-eabihf --features nrf52832 \
@@ -36,6 +38,14 @@ cargo batch \
     --- build --release --manifest-path examples/rp-pico-2-w/Cargo.toml --target thumbv8m.main-none-eabihf --features skip-cyw43-firmware
 #    --- build --release --manifest-path examples/apache-nimble/Cargo.toml --target thumbv7em-none-eabihf
 
+# The controller adapter crates patch their controller crates from git, which needs network access.
+if [[ -z "${CARGO_NET_OFFLINE}" ]]; then
+    cargo batch \
+        --- build --release --manifest-path nrf-sdc/Cargo.toml --target thumbv7em-none-eabihf --features nrf52840 \
+        --- build --release --manifest-path nrf-sdc/Cargo.toml --target thumbv7em-none-eabihf --no-default-features --features nrf52840,peripheral \
+        --- build --release --manifest-path esp/Cargo.toml --features esp32c3 --target riscv32imc-unknown-none-elf
+fi
+
 cargo fmt --check --manifest-path ./host/Cargo.toml
 cargo clippy --manifest-path ./host/Cargo.toml --features gatt,peripheral,central
 cargo test --manifest-path ./host/Cargo.toml --lib -- --nocapture
@@ -0,0 +1,50 @@
+//! The BLE controller of the ESP32 chips as the controller of a trouble host.
+//!
+//! The `esp-wifi` [`BleConnector`] is a HCI transport to the controller of the chip. This crate
+//! wraps it in an [`ExternalController`] implementing the traits of the `bt-hci` crate, and gives
+//! the L2CAP MTU the host must use on these chips.
+//!
+//! ```rust,ignore
+//! let timg0 = TimerGroup::new(peripherals.TIMG0);
+//! let init = esp_wifi::init(timg0.timer0, Rng::new(peripherals.RNG), peripherals.RADIO_CLK).unwrap();
+//! let controller = trouble_esp::controller(&init, peripherals.BT);
+//!
+//! let mut resources: HostResources<1, 2, { trouble_esp::L2CAP_MTU }> = HostResources::new();
+//! let stack = trouble_host::new(controller, &mut resources);
+//! ```
+//!
+//! `esp-wifi` needs a heap, set up with `esp-alloc`, and the embassy time driver must be
+//! initialized with `esp-hal-embassy`, from a timer other than the one given to `esp_wifi::init`.
+//! The `esp32` examples show the setup on each chip.
+#![no_std]
+#![warn(missing_docs)]
+
+pub use bt_hci::controller::ExternalController;
+use esp_hal::peripheral::Peripheral;
+use esp_hal::peripherals::BT;
+use esp_wifi::EspWifiController;
+pub use esp_wifi::ble::controller::BleConnector;
+
+/// Number of commands the controller can have in flight.
+pub const SLOTS: usize = 20;
+
+/// The L2CAP MTU of the host.
+///
+/// Some chips reject the buffer configuration of the host with "Invalid HCI Command Parameters"
+/// at launch if the L2CAP MTU is lower than 255:
+///
+///   - ESP32-C6: x..<255, so 128 or 251 fail.
+///   - ESP32-S3: 251, presumably x..<255 as well.
+///   - ESP32, ESP32-C3: claimed not to be affected.
+///   - ESP32-C2, ESP32-H2: not known.
+///
+/// 255 works on all of them.
+pub const L2CAP_MTU: usize = 255;
+
+/// The controller of an ESP32 chip.
+pub type EspController<'d> = ExternalController<BleConnector<'d>, SLOTS>;
+
+/// Connect to the BLE controller of the chip, once `esp-wifi` is initialized.
+pub fn controller<'d>(init: &'d EspWifiController<'d>, bt: impl Peripheral<P = BT> + 'd) -> EspController<'d> {
+    ExternalController::new(BleConnector::new(init, bt))
+}
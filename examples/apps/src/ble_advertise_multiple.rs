@@ -16,6 +16,7 @@ where
         + for<'t> ControllerCmdSync<LeSetExtAdvData<'t>>
         + ControllerCmdSync<LeClearAdvSets>
         + ControllerCmdSync<LeSetExtAdvParams>
+        + ControllerCmdSync<trouble_host::hci::LeSetExtAdvParamsV2>
         + ControllerCmdSync<LeSetAdvSetRandomAddr>
         + ControllerCmdSync<LeReadNumberOfSupportedAdvSets>
         + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
@@ -48,6 +49,7 @@ where
                 tx_power: TxPower::Plus8dBm,
                 primary_phy: PhyKind::Le1M,
                 secondary_phy: PhyKind::Le1M,
+                coded_phy_options: PhyOptions::NoPreferredCoding,
                 max_events: Some(1), // Advertise set only once
                 timeout: None,
                 interval_min: Duration::from_secs(1),
@@ -55,6 +57,7 @@ where
                 channel_map: None,
                 filter_policy: AdvFilterPolicy::Unfiltered,
                 fragment: false,
+                scan_request_notification: false,
             },
             data: Advertisement::ExtNonconnectableScannableUndirected {
                 scan_data: &adv_data[..len],
@@ -65,6 +68,7 @@ where
                 tx_power: TxPower::Plus8dBm,
                 primary_phy: PhyKind::LeCoded,
                 secondary_phy: PhyKind::LeCoded,
+                coded_phy_options: PhyOptions::S8CodingPreferred, // Favour range over throughput
                 max_events: None,
                 timeout: Some(Duration::from_secs(4)), // Advertise this set for 4 seconds
                 interval_min: Duration::from_secs(1),
@@ -72,6 +76,7 @@ where
                 channel_map: None,
                 fragment: false,
                 filter_policy: AdvFilterPolicy::Unfiltered,
+                scan_request_notification: false,
             },
             data: Advertisement::ExtNonconnectableScannableUndirected {
                 scan_data: &adv_data[..len],
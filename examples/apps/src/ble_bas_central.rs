@@ -1,6 +1,7 @@
 use embassy_futures::join::join;
 use embassy_time::{Duration, Timer};
 use trouble_host::prelude::*;
+use trouble_host::services::battery::BatteryClient;
 
 /// Max number of connections
 const CONNECTIONS_MAX: usize = 1;
@@ -48,31 +49,23 @@ where
 
         let _ = join(client.task(), async {
             info!("Looking for battery service");
-            let services = client.services_by_uuid(&Uuid::new_short(0x180f)).await.unwrap();
-            let service = services.first().unwrap().clone();
-
-            info!("Looking for value handle");
-            let c: Characteristic<u8> = client
-                .characteristic_by_uuid(&service, &Uuid::new_short(0x2a19))
-                .await
-                .unwrap();
+            let battery = BatteryClient::new(&client).await.unwrap();
 
             info!("Subscribing notifications");
-            let mut listener = client.subscribe(&c, false).await.unwrap();
+            let mut listener = battery.subscribe_level().await.unwrap();
 
             let _ = join(
                 async {
                     loop {
-                        let mut data = [0; 1];
-                        client.read_characteristic(&c, &mut data[..]).await.unwrap();
-                        info!("Read value: {}", data[0]);
+                        let level = battery.read_level().await.unwrap();
+                        info!("Read value: {}", level);
                         Timer::after(Duration::from_secs(10)).await;
                     }
                 },
                 async {
                     loop {
-                        let data = listener.next().await;
-                        info!("Got notification: {:?} (val: {})", data.as_ref(), data.as_ref()[0]);
+                        let level = listener.next().await;
+                        info!("Got notification: {}", level);
                     }
                 },
             )
@@ -109,6 +109,9 @@ async fn gatt_events_task(server: &Server<'_>, conn: &Connection<'_>) -> Result<
                 info!("[gatt] disconnected: {:?}", reason);
                 break;
             }
+            ConnectionEvent::SecurityChanged { security_level } => {
+                info!("[gatt] security level changed: {:?}", security_level);
+            }
             ConnectionEvent::Gatt { data } => {
                 // We can choose to handle event directly without an attribute table
                 // let req = data.request();
@@ -151,6 +154,7 @@ async fn gatt_events_task(server: &Server<'_>, conn: &Connection<'_>) -> Result<
                     }
                 }
             }
+            _ => {}
         }
     }
     info!("[gatt] task finished");
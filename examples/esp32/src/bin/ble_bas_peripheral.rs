@@ -1,16 +1,11 @@
 #![no_std]
 #![no_main]
 
-use bt_hci::controller::ExternalController;
 use embassy_executor::Spawner;
 use esp_hal::{clock::CpuClock, timer::timg::TimerGroup};
-use esp_wifi::ble::controller::BleConnector;
 use trouble_example_apps::ble_bas_peripheral;
 use {esp_alloc as _, esp_backtrace as _};
 
-#[path = "../consts.rs"]
-mod consts;
-
 #[esp_hal_embassy::main]
 async fn main(_s: Spawner) {
     esp_println::logger::init_logger_from_env();
@@ -39,9 +34,7 @@ async fn main(_s: Spawner) {
         esp_hal_embassy::init(timg0.timer1);
     }
 
-    let bluetooth = peripherals.BT;
-    let connector = BleConnector::new(&init, bluetooth);
-    let controller: ExternalController<_, 20> = ExternalController::new(connector);
+    let controller = trouble_esp::controller(&init, peripherals.BT);
 
-    ble_bas_peripheral::run::<_, { consts::L2CAP_MTU }>(controller).await;
+    ble_bas_peripheral::run::<_, { trouble_esp::L2CAP_MTU }>(controller).await;
 }
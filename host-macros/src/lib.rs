@@ -17,11 +17,15 @@ use proc_macro::TokenStream;
 use server::{ServerArgs, ServerBuilder};
 use service::{ServiceArgs, ServiceBuilder};
 use syn::spanned::Spanned;
-use syn::{parse_macro_input, Error};
+use syn::{Error, parse_macro_input};
 
-/// Gatt Service attribute macro.
-///
+/// Gatt Server attribute macro.
 ///
+/// The values of the services of the server are kept in a storage type named after the server, such as
+/// `MyGattServerStorage` below. The `new`, `new_default`, `new_with_config` and `new_with_storage`
+/// constructors allocate it statically, so the server can only be constructed once in the application.
+/// The constructors suffixed with `_in` take the storage from the application instead, so the same server
+/// and service types can be used by several servers, each with its own storage.
 ///
 /// # Example
 /// ```rust no_run
@@ -56,6 +60,11 @@ pub fn gatt_server(args: TokenStream, item: TokenStream) -> TokenStream {
 
 /// Gatt Service attribute macro.
 ///
+/// The values of the characteristics and descriptors are kept in a storage type named after the
+/// service, such as `HeartRateServiceStorage` below. `new` allocates it statically, so it can only be
+/// called once in the application and panics when called again, while `new_in` takes the storage along
+/// with the attribute table.
+///
 /// # Example
 ///
 /// ```rust no_run
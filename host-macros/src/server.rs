@@ -4,11 +4,14 @@
 //! It should contain one or more Gatt Services, which are decorated with the `#[gatt_service(uuid = "...")]` attribute.
 
 use darling::Error;
+use inflector::cases::screamingsnakecase::to_screaming_snake_case;
 use proc_macro2::TokenStream as TokenStream2;
-use quote::{quote, quote_spanned};
+use quote::{format_ident, quote, quote_spanned};
 use syn::meta::ParseNestedMeta;
 use syn::spanned::Spanned;
-use syn::{parse_quote, Expr, Result};
+use syn::{Expr, Result, parse_quote};
+
+use crate::service::storage_ident;
 
 #[derive(Default)]
 pub(crate) struct ServerArgs {
@@ -42,11 +45,9 @@ impl ServerArgs {
                 })?;
                 self.attribute_table_size = Some(buffer.parse()?);
             }
-            other => {
-                return Err(meta.error(format!(
+            other => return Err(meta.error(format!(
                 "Unsupported server property: '{other}'.\nSupported properties are: mutex_type, attribute_table_size"
-            )))
-            }
+            ))),
         }
         Ok(())
     }
@@ -66,6 +67,10 @@ impl ServerBuilder {
     pub fn build(self) -> TokenStream2 {
         let name = &self.properties.ident;
         let visibility = &self.properties.vis;
+        // Named after the server, so that several servers can be defined in one module.
+        let table_size = format_ident!("_{}_ATTRIBUTE_TABLE_SIZE", to_screaming_snake_case(&name.to_string()));
+        let storage_name = storage_ident(name);
+        let storage_doc = format!("Storage of the values of the services of [`{name}`].");
 
         let mutex_type = self.arguments.mutex_type.unwrap_or(syn::Type::Verbatim(quote!(
             embassy_sync::blocking_mutex::raw::NoopRawMutex
@@ -75,6 +80,8 @@ impl ServerBuilder {
         let mut code_service_init = TokenStream2::new();
         let mut code_server_populate = TokenStream2::new();
         let mut code_attribute_summation = TokenStream2::new();
+        let mut code_storage_fields = TokenStream2::new();
+        let mut code_storage_init = TokenStream2::new();
         for service in &self.properties.fields {
             let vis = &service.vis;
             let service_span = service.span();
@@ -85,8 +92,19 @@ impl ServerBuilder {
                 #vis #service_name: #service_type,
             });
 
+            let storage_type = match storage_type(service_type) {
+                Ok(storage_type) => storage_type,
+                Err(e) => return e.to_compile_error(),
+            };
+            code_storage_fields.extend(quote_spanned! {service_span=>
+                #service_name: #storage_type,
+            });
+            code_storage_init.extend(quote_spanned! {service_span=>
+                #service_name: #storage_type::new(),
+            });
+
             code_service_init.extend(quote_spanned! {service_span=>
-                let #service_name = #service_type::new(&mut table);
+                let #service_name = #service_type::new_in(&mut table, &mut storage.#service_name);
             });
 
             code_server_populate.extend(quote_spanned! {service_span=>
@@ -105,24 +123,99 @@ impl ServerBuilder {
         };
 
         quote! {
-            const _ATTRIBUTE_TABLE_SIZE: usize = #attribute_table_size;
+            const #table_size: usize = #attribute_table_size;
             // This pattern causes the assertion to happen at compile time
             const _: () = {
-                core::assert!(_ATTRIBUTE_TABLE_SIZE >= trouble_host::gap::GAP_SERVICE_ATTRIBUTE_COUNT #code_attribute_summation, "Specified attribute table size is insufficient. Please increase attribute_table_size or remove the argument entirely to allow automatic sizing of the attribute table.");
+                core::assert!(#table_size >= trouble_host::gap::GAP_SERVICE_ATTRIBUTE_COUNT #code_attribute_summation, "Specified attribute table size is insufficient. Please increase attribute_table_size or remove the argument entirely to allow automatic sizing of the attribute table.");
             };
 
+            #[doc = #storage_doc]
+            #visibility struct #storage_name {
+                #code_storage_fields
+            }
+
+            impl #storage_name {
+                /// Create the storage.
+                #visibility const fn new() -> Self {
+                    Self {
+                        #code_storage_init
+                    }
+                }
+            }
+
+            impl Default for #storage_name {
+                fn default() -> Self {
+                    Self::new()
+                }
+            }
+
             #visibility struct #name<'values>
             {
-                server: trouble_host::prelude::AttributeServer<'values, #mutex_type, _ATTRIBUTE_TABLE_SIZE>,
+                server: trouble_host::prelude::AttributeServer<'values, #mutex_type, #table_size>,
                 #code_service_definition
             }
 
             impl<'values> #name<'values>
             {
-                /// Create a new Gatt Server instance.
+                /// The storage of the server type, allocated statically, and so only once.
+                fn static_storage() -> &'static mut #storage_name {
+                    static STORAGE: static_cell::StaticCell<#storage_name> = static_cell::StaticCell::new();
+                    STORAGE.init(#storage_name::new())
+                }
+
+                /// Create a new Gatt Server instance, keeping the values of its services in static storage.
                 ///
-                /// Requires you to add your own GAP Service.  Use `new_default(name)` or `new_with_config(name, gap_config)` if you want to add a GAP Service.
-                #visibility fn new(mut table: trouble_host::attribute::AttributeTable<'values, #mutex_type, _ATTRIBUTE_TABLE_SIZE>) -> Self {
+                /// Requires you to add your own GAP Service.  Use `new_default(name)` or `new_with_config(gap_config)` if you want to add a GAP Service.
+                /// The storage is allocated once for the server type, so this panics when a server of the type
+                /// was already created. Use `new_in` to pass the storage instead.
+                #visibility fn new(table: trouble_host::attribute::AttributeTable<'values, #mutex_type, #table_size>) -> Self {
+                    Self::new_in(table, Self::static_storage())
+                }
+
+                /// Create a new Gatt Server instance, keeping the values of its services in static storage.
+                ///
+                /// This function will add a Generic GAP Service with the given name.
+                /// The maximum length which the name can be is 22 bytes (limited by the size of the advertising packet).
+                /// If a name longer than this is passed, Err() is returned.
+                /// The storage is allocated once for the server type, so this panics when a server of the type
+                /// was already created. Use `new_default_in` to pass the storage instead.
+                #visibility fn new_default(name: &'values str) -> Result<Self, &'static str> {
+                    Self::new_default_in(name, Self::static_storage())
+                }
+
+                /// Create a new Gatt Server instance, keeping the values of its services in static storage.
+                ///
+                /// This function will add a GAP Service.
+                /// The maximum length which the device name can be is 22 bytes (limited by the size of the advertising packet).
+                /// If a name longer than this is passed, Err() is returned.
+                /// The storage is allocated once for the server type, so this panics when a server of the type
+                /// was already created. Use `new_with_config_in` to pass the storage instead.
+                #visibility fn new_with_config(gap: impl Into<trouble_host::gap::GapServiceConfig<'values>>) -> Result<Self, &'static str> {
+                    Self::new_with_config_in(gap, Self::static_storage())
+                }
+
+                /// Create a new Gatt Server instance, keeping the values of its services in static storage.
+                ///
+                /// This function will add a GAP Service whose name and appearance are held in the storage,
+                /// returning the service to update them at runtime.
+                /// The maximum length which the device name can be is 22 bytes (limited by the size of the advertising packet).
+                /// If a name longer than this is passed, Err() is returned.
+                /// The storage of the services is allocated once for the server type, so this panics when a server
+                /// of the type was already created. Use `new_with_storage_in` to pass the storage instead.
+                #visibility fn new_with_storage(
+                    gap: impl Into<trouble_host::gap::GapServiceConfig<'values>>,
+                    storage: &'values mut trouble_host::gap::GapStorage,
+                ) -> Result<(Self, trouble_host::gap::GapService), &'static str> {
+                    Self::new_with_storage_in(gap, storage, Self::static_storage())
+                }
+
+                /// Create a new Gatt Server instance, keeping the values of its services in the storage.
+                ///
+                /// Requires you to add your own GAP Service.  Use `new_default_in(name, storage)` or `new_with_config_in(gap_config, storage)` if you want to add a GAP Service.
+                #visibility fn new_in(
+                    mut table: trouble_host::attribute::AttributeTable<'values, #mutex_type, #table_size>,
+                    storage: &'values mut #storage_name,
+                ) -> Self {
 
                     #code_service_init
 
@@ -131,13 +224,13 @@ impl ServerBuilder {
                         #code_server_populate
                     }
                 }
-                /// Create a new Gatt Server instance.
+                /// Create a new Gatt Server instance, keeping the values of its services in the storage.
                 ///
                 /// This function will add a Generic GAP Service with the given name.
                 /// The maximum length which the name can be is 22 bytes (limited by the size of the advertising packet).
                 /// If a name longer than this is passed, Err() is returned.
-                #visibility fn new_default(name: &'values str) -> Result<Self, &'static str> {
-                    let mut table: trouble_host::attribute::AttributeTable<'_, #mutex_type, _ATTRIBUTE_TABLE_SIZE> = trouble_host::attribute::AttributeTable::new();
+                #visibility fn new_default_in(name: &'values str, storage: &'values mut #storage_name) -> Result<Self, &'static str> {
+                    let mut table: trouble_host::attribute::AttributeTable<'_, #mutex_type, #table_size> = trouble_host::attribute::AttributeTable::new();
 
                     trouble_host::gap::GapConfig::default(name).build(&mut table)?;
 
@@ -149,15 +242,18 @@ impl ServerBuilder {
                     })
                 }
 
-                /// Create a new Gatt Server instance.
+                /// Create a new Gatt Server instance, keeping the values of its services in the storage.
                 ///
                 /// This function will add a GAP Service.
                 /// The maximum length which the device name can be is 22 bytes (limited by the size of the advertising packet).
                 /// If a name longer than this is passed, Err() is returned.
-                #visibility fn new_with_config(gap: trouble_host::gap::GapConfig<'values>) -> Result<Self, &'static str> {
-                    let mut table: trouble_host::attribute::AttributeTable<'_, #mutex_type, _ATTRIBUTE_TABLE_SIZE> = trouble_host::attribute::AttributeTable::new();
+                #visibility fn new_with_config_in(
+                    gap: impl Into<trouble_host::gap::GapServiceConfig<'values>>,
+                    storage: &'values mut #storage_name,
+                ) -> Result<Self, &'static str> {
+                    let mut table: trouble_host::attribute::AttributeTable<'_, #mutex_type, #table_size> = trouble_host::attribute::AttributeTable::new();
 
-                    gap.build(&mut table)?;
+                    gap.into().build(&mut table)?;
 
                     #code_service_init
 
@@ -167,6 +263,32 @@ impl ServerBuilder {
                     })
                 }
 
+                /// Create a new Gatt Server instance, keeping the values of its services in the storage.
+                ///
+                /// This function will add a GAP Service whose name and appearance are held in the GAP storage,
+                /// returning the service to update them at runtime.
+                /// The maximum length which the device name can be is 22 bytes (limited by the size of the advertising packet).
+                /// If a name longer than this is passed, Err() is returned.
+                #visibility fn new_with_storage_in(
+                    gap: impl Into<trouble_host::gap::GapServiceConfig<'values>>,
+                    gap_storage: &'values mut trouble_host::gap::GapStorage,
+                    storage: &'values mut #storage_name,
+                ) -> Result<(Self, trouble_host::gap::GapService), &'static str> {
+                    let mut table: trouble_host::attribute::AttributeTable<'_, #mutex_type, #table_size> = trouble_host::attribute::AttributeTable::new();
+
+                    let gap = gap.into().build_with_storage(&mut table, gap_storage)?;
+
+                    #code_service_init
+
+                    Ok((
+                        Self {
+                            server: trouble_host::prelude::AttributeServer::new(table),
+                            #code_server_populate
+                        },
+                        gap,
+                    ))
+                }
+
                 #visibility fn get<T: trouble_host::attribute::AttributeHandle<Value = V>, V: FromGatt>(&self, attribute_handle: &T) -> Result<T::Value, Error> {
                     self.server.table().get(attribute_handle)
                 }
@@ -179,7 +301,7 @@ impl ServerBuilder {
 
             impl<'values> core::ops::Deref for #name<'values>
             {
-                type Target = trouble_host::prelude::AttributeServer<'values, #mutex_type, _ATTRIBUTE_TABLE_SIZE>;
+                type Target = trouble_host::prelude::AttributeServer<'values, #mutex_type, #table_size>;
 
                 fn deref(&self) -> &Self::Target {
                     &self.server
@@ -189,3 +311,17 @@ impl ServerBuilder {
         }
     }
 }
+
+/// The storage type generated for a service type by the `gatt_service` macro, named after it.
+fn storage_type(service_type: &syn::Type) -> Result<syn::Type> {
+    let syn::Type::Path(path) = service_type else {
+        return Err(syn::Error::new(
+            service_type.span(),
+            "Service must be a type defined with #[gatt_service]",
+        ));
+    };
+    let mut path = path.clone();
+    let last = path.path.segments.last_mut().expect("Paths have at least one segment");
+    last.ident = storage_ident(&last.ident);
+    Ok(syn::Type::Path(path))
+}
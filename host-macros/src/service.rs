@@ -5,9 +5,8 @@
 //! generate the code required to create the service.
 
 use darling::{Error, FromMeta};
-use inflector::cases::screamingsnakecase::to_screaming_snake_case;
 use proc_macro2::TokenStream as TokenStream2;
-use quote::{format_ident, quote, quote_spanned, ToTokens};
+use quote::{ToTokens, format_ident, quote, quote_spanned};
 use syn::parse::Result;
 use syn::spanned::Spanned;
 use syn::{Expr, Meta, Token};
@@ -88,7 +87,7 @@ impl syn::parse::Parse for ServiceArgs {
                                 "Unsupported service property: '{other}'.\nSupported properties are: uuid"
                             ))
                             .with_span(&name_value.span())
-                            .into())
+                            .into());
                         }
                     }
                 }
@@ -113,6 +112,8 @@ pub(crate) struct ServiceBuilder {
     code_build_chars: TokenStream2,
     code_struct_init: TokenStream2,
     code_fields: TokenStream2,
+    code_storage_fields: TokenStream2,
+    code_storage_init: TokenStream2,
 }
 
 impl ServiceBuilder {
@@ -125,6 +126,8 @@ impl ServiceBuilder {
             code_impl: TokenStream2::new(),
             code_fields: TokenStream2::new(),
             code_build_chars: TokenStream2::new(),
+            code_storage_fields: TokenStream2::new(),
+            code_storage_init: TokenStream2::new(),
         }
     }
     /// Increment the number of access arguments required for this characteristic
@@ -150,17 +153,59 @@ impl ServiceBuilder {
         let code_build_chars = self.code_build_chars;
         let uuid = self.args.uuid;
         let attribute_count = self.attribute_count;
+        let storage_name = storage_ident(struct_name);
+        let storage_doc = format!("Storage of the values of the characteristics and descriptors of [`{struct_name}`].");
+        let storage_fields = self.code_storage_fields;
+        let storage_init = self.code_storage_init;
         quote! {
             #visibility struct #struct_name {
                 #fields
                 handle: u16,
             }
 
+            #[doc = #storage_doc]
+            #visibility struct #storage_name {
+                #storage_fields
+            }
+
+            impl #storage_name {
+                /// Create the storage.
+                #visibility const fn new() -> Self {
+                    Self {
+                        #storage_init
+                    }
+                }
+            }
+
+            impl Default for #storage_name {
+                fn default() -> Self {
+                    Self::new()
+                }
+            }
+
             #[allow(unused)]
             impl #struct_name {
                 #visibility const ATTRIBUTE_COUNT: usize = #attribute_count;
 
-                #visibility fn new<M, const MAX_ATTRIBUTES: usize>(table: &mut trouble_host::attribute::AttributeTable<'_, M, MAX_ATTRIBUTES>) -> Self
+                /// Add the service to the attribute table, keeping its values in static storage.
+                ///
+                /// The storage is allocated once for the service type, so this panics when called again.
+                /// Use `new_in` to pass the storage instead.
+                #visibility fn new<M, const MAX_ATTRIBUTES: usize>(
+                    table: &mut trouble_host::attribute::AttributeTable<'_, M, MAX_ATTRIBUTES>,
+                ) -> Self
+                where
+                    M: embassy_sync::blocking_mutex::raw::RawMutex,
+                {
+                    static STORAGE: static_cell::StaticCell<#storage_name> = static_cell::StaticCell::new();
+                    Self::new_in(table, STORAGE.init(#storage_name::new()))
+                }
+
+                /// Add the service to the attribute table, keeping its values in the storage.
+                #visibility fn new_in<'d, M, const MAX_ATTRIBUTES: usize>(
+                    table: &mut trouble_host::attribute::AttributeTable<'d, M, MAX_ATTRIBUTES>,
+                    storage: &'d mut #storage_name,
+                ) -> Self
                 where
                     M: embassy_sync::blocking_mutex::raw::RawMutex,
                 {
@@ -177,10 +222,9 @@ impl ServiceBuilder {
         }
     }
 
-    /// Construct instructions for adding a characteristic to the service, with static storage.
-    fn construct_characteristic_static(&mut self, characteristic: Characteristic) {
+    /// Construct instructions for adding a characteristic to the service, with its value in the storage of the service.
+    fn construct_characteristic(&mut self, characteristic: Characteristic) {
         let (code_descriptors, named_descriptors) = self.build_descriptors(&characteristic);
-        let name_screaming = format_ident!("{}", to_screaming_snake_case(characteristic.name.as_str()));
         let char_name = format_ident!("{}", characteristic.name);
         let ty = characteristic.ty;
         let access = &characteristic.args.access;
@@ -191,12 +235,18 @@ impl ServiceBuilder {
             None => quote!(<#ty>::default()), // or default otherwise
         };
 
+        self.code_storage_fields.extend(quote_spanned! {characteristic.span=>
+            #char_name: [u8; <#ty as trouble_host::types::gatt_traits::AsGatt>::MAX_SIZE],
+        });
+        self.code_storage_init.extend(quote_spanned! {characteristic.span=>
+            #char_name: [0; <#ty as trouble_host::types::gatt_traits::AsGatt>::MAX_SIZE],
+        });
+
         self.code_build_chars.extend(quote_spanned! {characteristic.span=>
             let (#char_name, #(#named_descriptors),*) = {
-                static #name_screaming: static_cell::StaticCell<[u8; <#ty as trouble_host::types::gatt_traits::AsGatt>::MAX_SIZE]> = static_cell::StaticCell::new();
                 let mut val = <#ty>::default(); // constrain the type of the value here
                 val = #default_value; // update the temporary value with our new default
-                let store = #name_screaming.init([0; <#ty as trouble_host::types::gatt_traits::AsGatt>::MAX_SIZE]);
+                let store = &mut storage.#char_name;
                 let mut builder = service
                     .add_characteristic(#uuid, &[#(#properties),*], val, store);
                 #code_descriptors
@@ -211,7 +261,7 @@ impl ServiceBuilder {
     }
 
     /// Consume the lists of fields and fields marked as characteristics and prepare the code to add them to the service
-    /// by generating the macro blueprints for any methods, fields, and static storage required.
+    /// by generating the macro blueprints for any methods, fields, and storage required.
     pub fn process_characteristics_and_fields(
         mut self,
         mut fields: Vec<syn::Field>,
@@ -245,7 +295,7 @@ impl ServiceBuilder {
 
             self.increment_attributes(&ch.args.access);
 
-            self.construct_characteristic_static(ch);
+            self.construct_characteristic(ch);
         }
         assert_eq!(fields.len(), doc_strings.len());
         // Processing common to all fields
@@ -273,15 +323,18 @@ impl ServiceBuilder {
     /// Generate token stream for any descriptors tagged against this characteristic.
     fn build_descriptors(&mut self, characteristic: &Characteristic) -> (TokenStream2, Vec<TokenStream2>) {
         let mut named_descriptors = Vec::<TokenStream2>::new();
-        (characteristic
+        (
+            characteristic
                 .args
                 .descriptors
                 .iter()
                 .enumerate()
                 .map(|(index, args)| {
-                    let name_screaming =
-                        format_ident!("DESC_{index}_{}", to_screaming_snake_case(characteristic.name.as_str()));
-                    let identifier = args.name.as_ref().map(|name| format_ident!("{}_{}_descriptor", characteristic.name.as_str(), name.value()));
+                    let storage_field = format_ident!("{}_descriptor_{index}", characteristic.name.as_str());
+                    let identifier = args
+                        .name
+                        .as_ref()
+                        .map(|name| format_ident!("{}_{}_descriptor", characteristic.name.as_str(), name.value()));
                     let access = &args.access;
                     let properties = set_access_properties(access);
                     let uuid = &args.uuid;
@@ -291,12 +344,12 @@ impl ServiceBuilder {
                     };
                     let capacity = match &args.capacity {
                         Some(cap) => quote!(#cap),
-                        None => quote!(#default_value.len() as usize)
+                        None => quote!(#default_value.len() as usize),
                     };
 
                     let mut identifier_assignment = None;
                     if let Some(name) = &identifier {
-                        self.code_fields.extend(quote_spanned!{ identifier.span() =>
+                        self.code_fields.extend(quote_spanned! { identifier.span() =>
                             #name: trouble_host::attribute::Descriptor<&'static [u8]>,
                         });
                         self.code_struct_init.extend(quote_spanned! { identifier.span() =>
@@ -308,12 +361,19 @@ impl ServiceBuilder {
 
                     self.attribute_count += 1; // descriptors should always only be one attribute.
 
+                    // minimum capacity is 16 bytes
+                    let capacity = quote!(if (#capacity) < 16 { 16 } else { #capacity });
+                    self.code_storage_fields.extend(quote_spanned! {characteristic.span=>
+                        #storage_field: [u8; #capacity],
+                    });
+                    self.code_storage_init.extend(quote_spanned! {characteristic.span=>
+                        #storage_field: [0; #capacity],
+                    });
+
                     quote_spanned! {characteristic.span=>
                         #identifier_assignment {
                             let value = #default_value;
-                            const CAPACITY: usize = if (#capacity) < 16 { 16 } else { #capacity }; // minimum capacity is 16 bytes
-                            static #name_screaming: static_cell::StaticCell<[u8; CAPACITY]> = static_cell::StaticCell::new();
-                            let store = #name_screaming.init([0; CAPACITY]);
+                            let store = &mut storage.#storage_field;
                             let value = trouble_host::types::gatt_traits::AsGatt::as_gatt(&value);
                             store[..value.len()].copy_from_slice(value);
                             builder.add_descriptor::<&[u8], _>(
@@ -325,10 +385,16 @@ impl ServiceBuilder {
                     }
                 })
                 .collect(),
-            named_descriptors)
+            named_descriptors,
+        )
     }
 }
 
+/// The name of the storage type generated for a service type.
+pub(crate) fn storage_ident(service: &syn::Ident) -> syn::Ident {
+    format_ident!("{}Storage", service)
+}
+
 fn parse_property_into_list(property: bool, variant: TokenStream2, properties: &mut Vec<TokenStream2>) {
     if property {
         properties.push(variant);
@@ -13,6 +13,15 @@ static CONFIGS: &[(&str, usize)] = &[
     ("L2CAP_TX_PACKET_POOL_SIZE", 8),
     ("GATT_CLIENT_NOTIFICATION_MAX_SUBSCRIBERS", 1),
     ("GATT_CLIENT_NOTIFICATION_QUEUE_SIZE", 1),
+    ("HOST_EVENT_QUEUE_SIZE", 4),
+    ("HOST_EVENT_MAX_SUBSCRIBERS", 1),
+    ("BOND_TABLE_SIZE", 4),
+    ("PAIRING_ATTEMPTS_TABLE_SIZE", 4),
+    ("FILTER_ACCEPT_LIST_SIZE", 8),
+    ("SCAN_REASSEMBLY_SIZE", 2),
+    ("SCAN_REPORT_DATA_SIZE", 1650),
+    ("ISO_CHANNELS_MAX", 2),
+    ("ISO_RX_QUEUE_SIZE", 2),
     // END AUTOGENERATED CONFIG FEATURES
 ];
 
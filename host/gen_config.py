@@ -42,6 +42,15 @@ feature("l2cap_rx_packet_pool_size", default=8, min=1, max=512, pow2=True)
 feature("l2cap_tx_packet_pool_size", default=8, min=1, max=512, pow2=True)
 feature("gatt_client_notification_max_subscribers", default=1, min=1, max=512, pow2=True)
 feature("gatt_client_notification_queue_size", default=1, min=1, max=512, pow2=True)
+feature("host_event_queue_size", default=4, min=1, max=64, pow2=True)
+feature("host_event_max_subscribers", default=1, min=1, max=8, pow2=True)
+feature("bond_table_size", default=4, min=1, max=32, pow2=True)
+feature("pairing_attempts_table_size", default=4, min=1, max=32, pow2=True)
+feature("filter_accept_list_size", default=8, min=1, max=64, pow2=True)
+feature("scan_reassembly_size", default=2, min=1, max=16, pow2=True)
+feature("scan_report_data_size", default=1650, vals=[31, 229, 255, 512, 1024, 1650])
+feature("iso_channels_max", default=2, min=1, max=32, pow2=True)
+feature("iso_rx_queue_size", default=2, min=1, max=32, pow2=True)
 
 # ========= Update Cargo.toml
 
@@ -1,11 +1,14 @@
 //! Advertisement config.
-use bt_hci::param::AdvEventProps;
 pub use bt_hci::param::{AdvChannelMap, AdvFilterPolicy, AdvHandle, AdvSet, PhyKind};
+use bt_hci::param::{AdvEventProps, PhyOptions};
+use bt_hci::{FromHciBytes, FromHciBytesError};
 use embassy_time::Duration;
 
 use crate::cursor::{ReadCursor, WriteCursor};
 use crate::types::uuid::Uuid;
-use crate::{Address, codec};
+use crate::{Address, Error, codec};
+
+pub mod beacon;
 
 /// Transmit power levels.
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -72,19 +75,36 @@ impl<'d> AdvertisementSet<'d> {
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(Copy, Clone, Debug)]
 pub struct AdvertisementParameters {
-    /// Phy selection
+    /// Phy used on the primary advertising channels.
+    ///
+    /// Must be `Le1M` or `LeCoded` (long range). Only used by extended advertising.
     pub primary_phy: PhyKind,
 
-    /// Secondary phy selection
+    /// Phy used on the secondary advertising channels.
+    ///
+    /// Must be `Le1M`, `Le2M` or `LeCoded`. Only used by extended advertising.
     pub secondary_phy: PhyKind,
 
+    /// Preferred coding of the coded PHY, S=2 or S=8, on the primary and secondary channels
+    /// using it.
+    ///
+    /// A preference needs one of the PHYs to be `LeCoded`, and a controller supporting version 2
+    /// of the LE Set Extended Advertising Parameters command.
+    pub coded_phy_options: PhyOptions,
+
     /// Transmission power
+    ///
+    /// For extended advertising this is the maximum power requested, and the power selected by
+    /// the controller can be read back with `Advertiser::tx_power`.
     pub tx_power: TxPower,
 
-    /// Timeout duration
+    /// Stop advertising after this duration if no connection is made.
     pub timeout: Option<Duration>,
 
-    /// Max advertising events
+    /// Stop advertising after this many advertising events.
+    ///
+    /// Only supported by extended advertising, legacy advertising rejects it with
+    /// [`Error::InvalidValue`].
     pub max_events: Option<u8>,
 
     /// Minimum advertising interval
@@ -101,6 +121,28 @@ pub struct AdvertisementParameters {
 
     /// Fragmentation preference
     pub fragment: bool,
+
+    /// Report scan requests received by this set to `EventHandler::on_scan_request`.
+    ///
+    /// Only supported by extended advertising.
+    pub scan_request_notification: bool,
+}
+
+impl AdvertisementParameters {
+    pub(crate) fn validate_phys(&self, props: AdvEventProps) -> Result<(), Error> {
+        let primary = matches!(self.primary_phy, PhyKind::Le1M | PhyKind::LeCoded);
+        let secondary = matches!(self.secondary_phy, PhyKind::Le1M | PhyKind::Le2M | PhyKind::LeCoded);
+        // Legacy advertising PDUs can only be sent on the 1M phy
+        let legacy = !props.legacy_adv() || self.primary_phy == PhyKind::Le1M;
+        let coded = self.coded_phy_options == PhyOptions::NoPreferredCoding
+            || self.primary_phy == PhyKind::LeCoded
+            || self.secondary_phy == PhyKind::LeCoded;
+        if primary && secondary && legacy && coded {
+            Ok(())
+        } else {
+            Err(Error::InvalidValue)
+        }
+    }
 }
 
 impl Default for AdvertisementParameters {
@@ -108,6 +150,7 @@ impl Default for AdvertisementParameters {
         Self {
             primary_phy: PhyKind::Le1M,
             secondary_phy: PhyKind::Le1M,
+            coded_phy_options: PhyOptions::NoPreferredCoding,
             tx_power: TxPower::ZerodBm,
             timeout: None,
             max_events: None,
@@ -116,6 +159,241 @@ impl Default for AdvertisementParameters {
             filter_policy: AdvFilterPolicy::default(),
             channel_map: None,
             fragment: false,
+            scan_request_notification: false,
+        }
+    }
+}
+
+/// Parameters for periodic advertising attached to an extended advertisement set.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Debug)]
+pub struct PeriodicAdvertisementParameters {
+    /// Minimum periodic advertising interval
+    pub interval_min: Duration,
+
+    /// Maximum periodic advertising interval
+    pub interval_max: Duration,
+
+    /// Include the transmit power in the advertising PDUs
+    pub include_tx_power: bool,
+}
+
+impl Default for PeriodicAdvertisementParameters {
+    fn default() -> Self {
+        Self {
+            interval_min: Duration::from_millis(100),
+            interval_max: Duration::from_millis(100),
+            include_tx_power: false,
+        }
+    }
+}
+
+/// Parameters for periodic advertising with responses attached to an extended advertisement set.
+///
+/// Each periodic advertising event is divided into subevents, each followed by slots in which
+/// synchronized devices may respond.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Debug)]
+pub struct PawrParameters {
+    /// Minimum periodic advertising interval
+    pub interval_min: Duration,
+
+    /// Maximum periodic advertising interval
+    pub interval_max: Duration,
+
+    /// Include the transmit power in the advertising PDUs
+    pub include_tx_power: bool,
+
+    /// Number of subevents in each periodic advertising event, from 1 to 128
+    pub num_subevents: u8,
+
+    /// Interval between subevents, from 7.5 ms to 318.75 ms
+    ///
+    /// Rounded down to a multiple of 1.25 ms.
+    pub subevent_interval: Duration,
+
+    /// Delay between the start of a subevent and its first response slot, from 1.25 ms to 318.75 ms
+    ///
+    /// Rounded down to a multiple of 1.25 ms.
+    pub response_slot_delay: Duration,
+
+    /// Interval between response slots, from 0.25 ms to 31.875 ms
+    ///
+    /// Rounded down to a multiple of 0.125 ms.
+    pub response_slot_spacing: Duration,
+
+    /// Number of response slots of each subevent
+    pub num_response_slots: u8,
+}
+
+impl Default for PawrParameters {
+    fn default() -> Self {
+        Self {
+            interval_min: Duration::from_millis(100),
+            interval_max: Duration::from_millis(100),
+            include_tx_power: false,
+            num_subevents: 4,
+            subevent_interval: Duration::from_millis(20),
+            response_slot_delay: Duration::from_millis(5),
+            response_slot_spacing: Duration::from_millis(1),
+            num_response_slots: 8,
+        }
+    }
+}
+
+/// Data of a subevent of periodic advertising with responses.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Debug)]
+pub struct PawrSubevent<'d> {
+    /// Number of the subevent
+    pub subevent: u8,
+
+    /// First response slot listened to after the subevent
+    pub response_slot_start: u8,
+
+    /// Number of response slots listened to after the subevent
+    pub response_slot_count: u8,
+
+    /// Data sent in the subevent, up to 251 bytes
+    pub data: &'d [u8],
+}
+
+/// LE Periodic Advertising Subevent Data Request subevent code.
+const LE_PERIODIC_ADV_SUBEVENT_DATA_REQUEST: u8 = 0x27;
+/// LE Periodic Advertising Response Report subevent code.
+const LE_PERIODIC_ADV_RESPONSE_REPORT: u8 = 0x28;
+
+/// A request of the controller for the data of subevents of periodic advertising with responses,
+/// to be set with `set_subevent_data` before they are sent.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PawrSubeventDataRequest {
+    /// Advertisement set of the periodic advertising
+    pub handle: AdvHandle,
+
+    /// First subevent to set the data of
+    pub subevent_start: u8,
+
+    /// Number of subevents to set the data of, from the first one and wrapping around
+    pub subevent_count: u8,
+}
+
+/// The responses received in the response slots of a subevent of periodic advertising with responses.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Debug)]
+pub struct PawrResponseReport<'d> {
+    /// Advertisement set of the periodic advertising
+    pub handle: AdvHandle,
+
+    /// Number of the subevent
+    pub subevent: u8,
+
+    /// Whether the subevent was sent, its responses not being listened to otherwise
+    pub transmitted: bool,
+
+    num_responses: u8,
+    responses: &'d [u8],
+}
+
+impl<'d> PawrResponseReport<'d> {
+    /// The responses of the report.
+    pub fn responses(&self) -> impl Iterator<Item = PawrResponseData<'d>> {
+        let mut data = self.responses;
+        (0..self.num_responses).map(move |_| {
+            // The responses are checked when parsing the report.
+            let (response, rest) = unwrap!(PawrResponseData::parse(data).ok());
+            data = rest;
+            response
+        })
+    }
+}
+
+/// A response received in a response slot of a subevent of periodic advertising with responses.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PawrResponseData<'d> {
+    /// Transmit power of the response, if known, in dBm
+    pub tx_power: Option<i8>,
+
+    /// Signal strength of the response, if known, in dBm
+    pub rssi: Option<i8>,
+
+    /// Response slot of the response
+    pub response_slot: u8,
+
+    /// Whether more data of the response is to come in the next report
+    pub incomplete: bool,
+
+    /// Data of the response, or `None` if the slot was listened to but the response failed to be received
+    pub data: Option<&'d [u8]>,
+}
+
+impl<'d> PawrResponseData<'d> {
+    fn parse(data: &'d [u8]) -> Result<(Self, &'d [u8]), FromHciBytesError> {
+        const NOT_AVAILABLE: i8 = 0x7f;
+        let (tx_power, data) = i8::from_hci_bytes(data)?;
+        let (rssi, data) = i8::from_hci_bytes(data)?;
+        // The constant tone extension is not supported.
+        let (_cte_type, data) = u8::from_hci_bytes(data)?;
+        let (response_slot, data) = u8::from_hci_bytes(data)?;
+        let (status, data) = u8::from_hci_bytes(data)?;
+        let (len, data) = u8::from_hci_bytes(data)?;
+        if data.len() < usize::from(len) {
+            return Err(FromHciBytesError::InvalidSize);
+        }
+        let (value, data) = data.split_at(usize::from(len));
+        let response = Self {
+            tx_power: (tx_power != NOT_AVAILABLE).then_some(tx_power),
+            rssi: (rssi != NOT_AVAILABLE).then_some(rssi),
+            response_slot,
+            incomplete: status == 0x01,
+            data: (status != 0xff).then_some(value),
+        };
+        Ok((response, data))
+    }
+}
+
+/// An event of periodic advertising with responses, parsed by the host from the parameters of an
+/// LE meta event.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum PawrEvent<'d> {
+    SubeventDataRequest(PawrSubeventDataRequest),
+    ResponseReport(PawrResponseReport<'d>),
+}
+
+impl<'d> PawrEvent<'d> {
+    /// Parse the parameters of an LE meta event, returning `None` if it is not a PAwR event.
+    pub(crate) fn from_le_meta(params: &'d [u8]) -> Result<Option<Self>, FromHciBytesError> {
+        let (subevent, data) = u8::from_hci_bytes(params)?;
+        match subevent {
+            LE_PERIODIC_ADV_SUBEVENT_DATA_REQUEST => {
+                let (handle, data) = AdvHandle::from_hci_bytes(data)?;
+                let (subevent_start, data) = u8::from_hci_bytes(data)?;
+                let (subevent_count, _) = u8::from_hci_bytes(data)?;
+                Ok(Some(Self::SubeventDataRequest(PawrSubeventDataRequest {
+                    handle,
+                    subevent_start,
+                    subevent_count,
+                })))
+            }
+            LE_PERIODIC_ADV_RESPONSE_REPORT => {
+                let (handle, data) = AdvHandle::from_hci_bytes(data)?;
+                let (subevent, data) = u8::from_hci_bytes(data)?;
+                let (tx_status, data) = u8::from_hci_bytes(data)?;
+                let (num_responses, responses) = u8::from_hci_bytes(data)?;
+                let mut rest = responses;
+                for _ in 0..num_responses {
+                    rest = PawrResponseData::parse(rest)?.1;
+                }
+                Ok(Some(Self::ResponseReport(PawrResponseReport {
+                    handle,
+                    subevent,
+                    transmitted: tx_status == 0x00,
+                    num_responses,
+                    responses: &responses[..responses.len() - rest.len()],
+                })))
+            }
+            _ => Ok(None),
         }
     }
 }
@@ -129,6 +407,19 @@ pub(crate) struct RawAdvertisement<'d> {
     pub(crate) peer: Option<Address>,
 }
 
+impl RawAdvertisement<'_> {
+    /// Anonymous advertisements omit the advertiser address, so they can only be used for
+    /// extended advertisements that cannot be connected to or scanned.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let props = self.props;
+        if props.anonymous_adv() && (props.legacy_adv() || props.connectable_adv() || props.scannable_adv()) {
+            Err(Error::InvalidValue)
+        } else {
+            Ok(())
+        }
+    }
+}
+
 impl Default for RawAdvertisement<'_> {
     fn default() -> Self {
         Self {
@@ -154,12 +445,17 @@ pub enum Advertisement<'d> {
         /// Scan data.
         scan_data: &'d [u8],
     },
-    /// Connectable and non-scannable directed advertisement.
+    /// Connectable and non-scannable directed advertisement with low duty cycle.
+    ///
+    /// The advertisement continues until a connection is made or the advertiser is stopped.
     ConnectableNonscannableDirected {
         /// Address of the peer to direct the advertisement to.
         peer: Address,
     },
     /// Connectable and non-scannable directed advertisement with high duty cycle.
+    ///
+    /// The controller stops advertising after at most 1.28 seconds if the peer does not
+    /// connect, in which case accepting a connection fails with a timeout.
     ConnectableNonscannableDirectedHighDuty {
         /// Address of the peer to direct the advertisement to.
         peer: Address,
@@ -203,6 +499,9 @@ pub enum Advertisement<'d> {
     /// Extended nonconnectable and nonscannable undirected advertisement.
     ExtNonconnectableNonscannableUndirected {
         /// Whether the advertisement is anonymous.
+        ///
+        /// Anonymous advertisements do not include the advertiser address, which hides the
+        /// device identity from observers.
         anonymous: bool,
         /// Advertisement data.
         adv_data: &'d [u8],
@@ -210,6 +509,9 @@ pub enum Advertisement<'d> {
     /// Extended nonconnectable and nonscannable directed advertisement.
     ExtNonconnectableNonscannableDirected {
         /// Whether the advertisement is anonymous.
+        ///
+        /// Anonymous advertisements do not include the advertiser address, which hides the
+        /// device identity from observers.
         anonymous: bool,
         /// Address of the peer to direct the advertisement to.
         peer: Address,
@@ -246,6 +548,7 @@ impl<'d> From<Advertisement<'d>> for RawAdvertisement<'d> {
                 props: AdvEventProps::new()
                     .set_connectable_adv(true)
                     .set_scannable_adv(false)
+                    .set_directed_adv(true)
                     .set_high_duty_cycle_directed_connectable_adv(true)
                     .set_anonymous_adv(false)
                     .set_legacy_adv(true),
@@ -280,7 +583,10 @@ impl<'d> From<Advertisement<'d>> for RawAdvertisement<'d> {
                 peer: None,
             },
             Advertisement::ExtConnectableNonscannableDirected { adv_data, peer } => RawAdvertisement {
-                props: AdvEventProps::new().set_connectable_adv(true).set_scannable_adv(false),
+                props: AdvEventProps::new()
+                    .set_connectable_adv(true)
+                    .set_scannable_adv(false)
+                    .set_directed_adv(true),
                 adv_data,
                 scan_data: &[],
                 peer: Some(peer),
@@ -350,11 +656,16 @@ pub const SIMUL_LE_BR_HOST: u8 = 0b00010000;
 pub enum AdvertisementDataError {
     /// Advertisement data too long for buffer.
     TooLong,
+    /// Advertisement data contains a value that cannot be encoded.
+    InvalidValue,
 }
 
 /// Advertisement data structure.
+///
+/// New AD types are added as they are supported, so matches must have a wildcard arm.
 #[derive(Debug, Copy, Clone)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum AdStructure<'a> {
     /// Device flags and baseband capabilities.
     ///
@@ -365,13 +676,27 @@ pub enum AdStructure<'a> {
     Flags(u8),
 
     /// List of 16-bit service UUIDs.
+    ///
+    /// Both complete and incomplete lists are decoded into this variant.
     /// The UUID data matches the ble network's endian order (should be little endian).
     ServiceUuids16(&'a [[u8; 2]]),
 
     /// List of 128-bit service UUIDs.
+    ///
+    /// Both complete and incomplete lists are decoded into this variant.
     /// The UUID data matches the ble network's endian order (should be little endian).
     ServiceUuids128(&'a [[u8; 16]]),
 
+    /// List of 16-bit service solicitation UUIDs, of services the device wants to use on a peer.
+    /// The UUID data matches the ble network's endian order (should be little endian).
+    ServiceSolicitationUuids16(&'a [[u8; 2]]),
+
+    /// List of 128-bit service solicitation UUIDs, of services the device wants to use on a peer.
+    ///
+    /// Used for instance to be listed as a client of the Apple Notification Center Service.
+    /// The UUID data matches the ble network's endian order (should be little endian).
+    ServiceSolicitationUuids128(&'a [[u8; 16]]),
+
     /// Service data with 16-bit service UUID.
     /// The UUID data matches the ble network's endian order (should be little endian).
     ServiceData16 {
@@ -381,6 +706,21 @@ pub enum AdStructure<'a> {
         data: &'a [u8],
     },
 
+    /// Service data with 128-bit service UUID.
+    /// The UUID data matches the ble network's endian order (should be little endian).
+    ServiceData128 {
+        /// The 128-bit service UUID.
+        uuid: [u8; 16],
+        /// The associated service data. May be empty.
+        data: &'a [u8],
+    },
+
+    /// Transmit power level in dBm.
+    TxPowerLevel(i8),
+
+    /// External appearance of the device, as defined by the assigned numbers.
+    Appearance(u16),
+
     /// Sets the full (unabbreviated) device name.
     ///
     /// This will be shown to the user when this device is found.
@@ -433,6 +773,18 @@ impl AdStructure<'_> {
                     w.write_ref(&Uuid::Uuid128(*uuid))?;
                 }
             }
+            AdStructure::ServiceSolicitationUuids16(uuids) => {
+                w.append(&[(uuids.len() * 2 + 1) as u8, 0x14])?;
+                for uuid in uuids.iter() {
+                    w.write_ref(&Uuid::Uuid16(*uuid))?;
+                }
+            }
+            AdStructure::ServiceSolicitationUuids128(uuids) => {
+                w.append(&[(uuids.len() * 16 + 1) as u8, 0x15])?;
+                for uuid in uuids.iter() {
+                    w.write_ref(&Uuid::Uuid128(*uuid))?;
+                }
+            }
             AdStructure::ShortenedLocalName(name) => {
                 w.append(&[(name.len() + 1) as u8, 0x08])?;
                 w.append(name)?;
@@ -446,6 +798,18 @@ impl AdStructure<'_> {
                 w.write(Uuid::Uuid16(*uuid))?;
                 w.append(data)?;
             }
+            AdStructure::ServiceData128 { uuid, data } => {
+                w.append(&[(data.len() + 17) as u8, 0x21])?;
+                w.write(Uuid::Uuid128(*uuid))?;
+                w.append(data)?;
+            }
+            AdStructure::TxPowerLevel(level) => {
+                w.append(&[0x02, 0x0a, *level as u8])?;
+            }
+            AdStructure::Appearance(appearance) => {
+                w.append(&[0x03, 0x19])?;
+                w.write(*appearance)?;
+            }
             AdStructure::ManufacturerSpecificData {
                 company_identifier,
                 payload,
@@ -463,13 +827,155 @@ impl AdStructure<'_> {
     }
 
     /// Decode a slice of advertisement structures from a buffer.
-    pub fn decode(data: &[u8]) -> impl Iterator<Item = Result<AdStructure<'_>, codec::Error>> {
+    ///
+    /// Decoding stops at the first malformed structure, which is reported as an error, or at
+    /// a zero length structure marking the end of the significant data.
+    pub fn decode(data: &[u8]) -> AdStructureIter<'_> {
         AdStructureIter {
             cursor: ReadCursor::new(data),
         }
     }
 }
 
+/// Maximum length of legacy advertising and scan response data.
+pub const MAX_LEGACY_ADV_DATA_LEN: usize = 31;
+
+/// Maximum length of extended advertising and scan response data in a single advertising PDU.
+pub const MAX_EXT_ADV_DATA_LEN: usize = 254;
+
+/// Builder for advertisement and scan response data.
+///
+/// The builder writes AD structures into the provided buffer, and fails with
+/// [`AdvertisementDataError::TooLong`] when a structure does not fit within the
+/// advertisement limit, leaving the previously added structures intact.
+pub struct AdvertisementDataBuilder<'d> {
+    w: WriteCursor<'d>,
+}
+
+impl<'d> AdvertisementDataBuilder<'d> {
+    /// Create a builder for legacy advertisement data, limited to 31 bytes.
+    pub fn legacy(buf: &'d mut [u8]) -> Self {
+        Self::with_limit(buf, MAX_LEGACY_ADV_DATA_LEN)
+    }
+
+    /// Create a builder for extended advertisement data, limited to 254 bytes.
+    pub fn extended(buf: &'d mut [u8]) -> Self {
+        Self::with_limit(buf, MAX_EXT_ADV_DATA_LEN)
+    }
+
+    fn with_limit(buf: &'d mut [u8], limit: usize) -> Self {
+        let len = buf.len().min(limit);
+        Self {
+            w: WriteCursor::new(&mut buf[..len]),
+        }
+    }
+
+    /// Append an AD structure.
+    pub fn push(&mut self, item: AdStructure<'_>) -> Result<&mut Self, AdvertisementDataError> {
+        let pos = self.w.len();
+        if item.encode(&mut self.w).is_err() {
+            self.w.truncate(pos);
+            return Err(AdvertisementDataError::TooLong);
+        }
+        Ok(self)
+    }
+
+    /// Append the flags AD structure.
+    pub fn flags(&mut self, flags: u8) -> Result<&mut Self, AdvertisementDataError> {
+        self.push(AdStructure::Flags(flags))
+    }
+
+    /// Append a list of 16-bit service UUIDs.
+    pub fn service_uuids16(&mut self, uuids: &[[u8; 2]]) -> Result<&mut Self, AdvertisementDataError> {
+        self.push(AdStructure::ServiceUuids16(uuids))
+    }
+
+    /// Append a list of 128-bit service UUIDs.
+    pub fn service_uuids128(&mut self, uuids: &[[u8; 16]]) -> Result<&mut Self, AdvertisementDataError> {
+        self.push(AdStructure::ServiceUuids128(uuids))
+    }
+
+    /// Append a list of 128-bit service solicitation UUIDs.
+    pub fn service_solicitation_uuids128(&mut self, uuids: &[[u8; 16]]) -> Result<&mut Self, AdvertisementDataError> {
+        self.push(AdStructure::ServiceSolicitationUuids128(uuids))
+    }
+
+    /// Append service data for a 16-bit service UUID.
+    pub fn service_data16(&mut self, uuid: [u8; 2], data: &[u8]) -> Result<&mut Self, AdvertisementDataError> {
+        self.push(AdStructure::ServiceData16 { uuid, data })
+    }
+
+    /// Append service data for a 128-bit service UUID.
+    pub fn service_data128(&mut self, uuid: [u8; 16], data: &[u8]) -> Result<&mut Self, AdvertisementDataError> {
+        self.push(AdStructure::ServiceData128 { uuid, data })
+    }
+
+    /// Append manufacturer specific data.
+    pub fn manufacturer_data(
+        &mut self,
+        company_identifier: u16,
+        payload: &[u8],
+    ) -> Result<&mut Self, AdvertisementDataError> {
+        self.push(AdStructure::ManufacturerSpecificData {
+            company_identifier,
+            payload,
+        })
+    }
+
+    /// Append the transmit power level.
+    pub fn tx_power_level(&mut self, level: i8) -> Result<&mut Self, AdvertisementDataError> {
+        self.push(AdStructure::TxPowerLevel(level))
+    }
+
+    /// Append the device appearance.
+    pub fn appearance(&mut self, appearance: u16) -> Result<&mut Self, AdvertisementDataError> {
+        self.push(AdStructure::Appearance(appearance))
+    }
+
+    /// Append the complete local name.
+    pub fn complete_name(&mut self, name: &[u8]) -> Result<&mut Self, AdvertisementDataError> {
+        self.push(AdStructure::CompleteLocalName(name))
+    }
+
+    /// Append a shortened local name.
+    pub fn shortened_name(&mut self, name: &[u8]) -> Result<&mut Self, AdvertisementDataError> {
+        self.push(AdStructure::ShortenedLocalName(name))
+    }
+
+    /// Append the local name, shortening it to fit the remaining space if needed.
+    pub fn name(&mut self, name: &[u8]) -> Result<&mut Self, AdvertisementDataError> {
+        // Two bytes are used by the length and type of the AD structure.
+        let available = self.remaining().saturating_sub(2);
+        if name.len() <= available {
+            self.complete_name(name)
+        } else if available > 0 {
+            self.shortened_name(&name[..available])
+        } else {
+            Err(AdvertisementDataError::TooLong)
+        }
+    }
+
+    /// Number of bytes written so far.
+    pub fn len(&self) -> usize {
+        self.w.len()
+    }
+
+    /// Check if no AD structures have been added.
+    pub fn is_empty(&self) -> bool {
+        self.w.len() == 0
+    }
+
+    /// Number of bytes still available for AD structures.
+    pub fn remaining(&self) -> usize {
+        self.w.available()
+    }
+
+    /// Return the encoded advertisement data.
+    pub fn build(self) -> &'d [u8] {
+        self.w.finish()
+    }
+}
+
 /// Iterator over advertisement structures.
 pub struct AdStructureIter<'d> {
     cursor: ReadCursor<'d>,
@@ -489,9 +995,8 @@ impl<'d> AdStructureIter<'d> {
             // Flags
             0x01 => Ok(AdStructure::Flags(data[0])),
             // Incomplete List of 16-bit Service or Service Class UUIDs
-            // 0x02 =>
             // Complete List of 16-bit Service or Service Class UUIDs
-            0x03 => match zerocopy::FromBytes::ref_from_bytes(data) {
+            0x02 | 0x03 => match zerocopy::FromBytes::ref_from_bytes(data) {
                 Ok(x) => Ok(AdStructure::ServiceUuids16(x)),
                 Err(e) => {
                     let _ = zerocopy::SizeError::from(e);
@@ -503,9 +1008,8 @@ impl<'d> AdStructureIter<'d> {
             // Complete List of 32-bit Service or Service Class UUIDs
             // 0x05
             // Incomplete List of 128-bit Service or Service Class UUIDs
-            // 0x06
             // Complete List of 128-bit Service or Service Class UUIDs
-            0x07 => match zerocopy::FromBytes::ref_from_bytes(data) {
+            0x06 | 0x07 => match zerocopy::FromBytes::ref_from_bytes(data) {
                 Ok(x) => Ok(AdStructure::ServiceUuids128(x)),
                 Err(e) => {
                     let _ = zerocopy::SizeError::from(e);
@@ -516,8 +1020,9 @@ impl<'d> AdStructureIter<'d> {
             0x08 => Ok(AdStructure::ShortenedLocalName(data)),
             // Complete Local Name
             0x09 => Ok(AdStructure::CompleteLocalName(data)),
+            // Tx Power Level
+            0x0A if data.len() == 1 => Ok(AdStructure::TxPowerLevel(data[0] as i8)),
             /*
-            0x0A Tx Power Level
             0x0D Class of Device
             0x0E Simple Pairing Hash C-192
             0x0F Simple Pairing Randomizer R-192
@@ -525,9 +1030,23 @@ impl<'d> AdStructureIter<'d> {
             0x10 Security Manager TK Value when used in OOB data blocks
             0x11 Security Manager Out of Band Flags
             0x12 Peripheral Connection Interval Range
-            0x14 List of 16-bit Service Solicitation UUIDs
-            0x15 List of 128-bit Service Solicitation UUIDs
             */
+            // List of 16-bit Service Solicitation UUIDs
+            0x14 => match zerocopy::FromBytes::ref_from_bytes(data) {
+                Ok(x) => Ok(AdStructure::ServiceSolicitationUuids16(x)),
+                Err(e) => {
+                    let _ = zerocopy::SizeError::from(e);
+                    Err(codec::Error::InvalidValue)
+                }
+            },
+            // List of 128-bit Service Solicitation UUIDs
+            0x15 => match zerocopy::FromBytes::ref_from_bytes(data) {
+                Ok(x) => Ok(AdStructure::ServiceSolicitationUuids128(x)),
+                Err(e) => {
+                    let _ = zerocopy::SizeError::from(e);
+                    Err(codec::Error::InvalidValue)
+                }
+            },
             // Service Data - 16-bit UUID
             0x16 => {
                 if data.len() < 2 {
@@ -539,7 +1058,10 @@ impl<'d> AdStructureIter<'d> {
             /*
             0x17 Public Target Address
             0x18 Random Target Address
-            0x19 Appearance
+            */
+            // Appearance
+            0x19 if data.len() == 2 => Ok(AdStructure::Appearance(u16::from_le_bytes([data[0], data[1]]))),
+            /*
             0x1A Advertising Interval
             0x1B LE Bluetooth Device Address
             0x1C LE Role
@@ -547,7 +1069,19 @@ impl<'d> AdStructureIter<'d> {
             0x1E Simple Pairing Randomizer R-256
             0x1F List of 32-bit Service Solicitation UUIDs
             0x20 Service Data - 32-bit UUID
-            0x21 Service Data - 128-bit UUID
+            */
+            // Service Data - 128-bit UUID
+            0x21 => {
+                if data.len() < 16 {
+                    return Err(codec::Error::InvalidValue);
+                }
+                let uuid = data[0..16].try_into().unwrap();
+                Ok(AdStructure::ServiceData128 {
+                    uuid,
+                    data: &data[16..],
+                })
+            }
+            /*
             0x22 LE Secure Connections Confirmation Value
             0x23 LE Secure Connections Random Value
             0x24 URI
@@ -581,10 +1115,15 @@ impl<'d> AdStructureIter<'d> {
 impl<'d> Iterator for AdStructureIter<'d> {
     type Item = Result<AdStructure<'d>, codec::Error>;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.cursor.available() == 0 {
+        // A zero length structure terminates the data early, the remainder is padding.
+        if matches!(self.cursor.clone().remaining().first(), None | Some(0)) {
             return None;
         }
-        Some(self.read())
+        let item = self.read();
+        if item.is_err() {
+            self.cursor = ReadCursor::new(&[]);
+        }
+        Some(item)
     }
 }
 
@@ -592,6 +1131,167 @@ impl<'d> Iterator for AdStructureIter<'d> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn parse_pawr_events() {
+        let params = [0x27, 0x02, 0x01, 0x03];
+        let Ok(Some(PawrEvent::SubeventDataRequest(request))) = PawrEvent::from_le_meta(&params) else {
+            panic!("expected a subevent data request");
+        };
+        assert_eq!(
+            request,
+            PawrSubeventDataRequest {
+                handle: AdvHandle::new(2),
+                subevent_start: 1,
+                subevent_count: 3,
+            }
+        );
+
+        // A response received in the first slot, more of it to come, and one failed in the third.
+        let params = [
+            0x28, 0x02, 0x01, 0x00, 2, 0x7f, 0xc4, 0xff, 0, 0x01, 2, 0xaa, 0xbb, 0x04, 0x7f, 0xff, 2, 0xff, 0,
+        ];
+        let Ok(Some(PawrEvent::ResponseReport(report))) = PawrEvent::from_le_meta(&params) else {
+            panic!("expected a response report");
+        };
+        assert_eq!(
+            (report.handle, report.subevent, report.transmitted),
+            (AdvHandle::new(2), 1, true)
+        );
+        let mut responses = report.responses();
+        assert_eq!(
+            responses.next(),
+            Some(PawrResponseData {
+                tx_power: None,
+                rssi: Some(-60),
+                response_slot: 0,
+                incomplete: true,
+                data: Some(&[0xaa, 0xbb]),
+            })
+        );
+        assert_eq!(
+            responses.next(),
+            Some(PawrResponseData {
+                tx_power: Some(4),
+                rssi: None,
+                response_slot: 2,
+                incomplete: false,
+                data: None,
+            })
+        );
+        assert_eq!(responses.next(), None);
+
+        // A truncated response, and another LE meta event.
+        assert!(PawrEvent::from_le_meta(&params[..params.len() - 1]).is_err());
+        assert!(matches!(PawrEvent::from_le_meta(&[0x1e, 0x01, 0x13]), Ok(None)));
+    }
+
+    #[test]
+    fn builder_rejects_too_long() {
+        let mut buf = [0; 64];
+        let mut builder = AdvertisementDataBuilder::legacy(&mut buf);
+        builder
+            .flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED)
+            .unwrap()
+            .service_uuids16(&[[0x0f, 0x18]])
+            .unwrap();
+        assert_eq!(builder.remaining(), 24);
+        assert!(matches!(
+            builder.complete_name(b"12345678901234567890123"),
+            Err(AdvertisementDataError::TooLong)
+        ));
+        // Failed structures must not leave partial data behind
+        assert_eq!(builder.remaining(), 24);
+        builder.name(b"12345678901234567890123").unwrap();
+        assert_eq!(builder.remaining(), 0);
+
+        let data = builder.build();
+        assert_eq!(data.len(), 31);
+        let mut items = AdStructure::decode(data);
+        assert!(matches!(items.next(), Some(Ok(AdStructure::Flags(_)))));
+        assert!(matches!(items.next(), Some(Ok(_))));
+        assert!(matches!(
+            items.next(),
+            Some(Ok(AdStructure::ShortenedLocalName(b"1234567890123456789012")))
+        ));
+        assert!(items.next().is_none());
+    }
+
+    #[test]
+    fn builder_encodes_tx_power_and_appearance() {
+        let mut buf = [0; 254];
+        let mut builder = AdvertisementDataBuilder::extended(&mut buf);
+        builder.tx_power_level(-4).unwrap().appearance(0x03c1).unwrap();
+        let data = builder.build();
+        assert_eq!(data, &[0x02, 0x0a, 0xfc, 0x03, 0x19, 0xc1, 0x03]);
+        let mut items = AdStructure::decode(data);
+        assert!(matches!(items.next(), Some(Ok(AdStructure::TxPowerLevel(-4)))));
+        assert!(matches!(items.next(), Some(Ok(AdStructure::Appearance(0x03c1)))));
+    }
+
+    #[test]
+    fn directed_advertisement_props() {
+        let peer = Address::random([1, 2, 3, 4, 5, 6]);
+        let raw: RawAdvertisement = Advertisement::ConnectableNonscannableDirectedHighDuty { peer }.into();
+        assert!(raw.props.connectable_adv());
+        assert!(raw.props.directed_adv());
+        assert!(raw.props.high_duty_cycle_directed_connectable_adv());
+        assert!(raw.props.legacy_adv());
+
+        let raw: RawAdvertisement = Advertisement::ExtConnectableNonscannableDirected { peer, adv_data: &[] }.into();
+        assert!(raw.props.directed_adv());
+        assert!(!raw.props.legacy_adv());
+        assert_eq!(raw.peer.map(|p| p.addr), Some(peer.addr));
+    }
+
+    #[test]
+    fn anonymous_advertisement_validation() {
+        let raw: RawAdvertisement = Advertisement::ExtNonconnectableNonscannableUndirected {
+            anonymous: true,
+            adv_data: &[],
+        }
+        .into();
+        assert!(raw.props.anonymous_adv());
+        assert!(raw.validate().is_ok());
+
+        let connectable = RawAdvertisement {
+            props: raw.props.set_connectable_adv(true),
+            ..raw
+        };
+        assert!(connectable.validate().is_err());
+
+        let legacy = RawAdvertisement {
+            props: raw.props.set_legacy_adv(true),
+            ..raw
+        };
+        assert!(legacy.validate().is_err());
+    }
+
+    #[test]
+    fn coded_phy_parameters() {
+        let ext = AdvEventProps::new().set_connectable_adv(true);
+        let legacy = ext.set_legacy_adv(true);
+        let mut params = AdvertisementParameters {
+            primary_phy: PhyKind::LeCoded,
+            secondary_phy: PhyKind::LeCoded,
+            ..Default::default()
+        };
+        assert!(params.validate_phys(ext).is_ok());
+        assert!(params.validate_phys(legacy).is_err());
+
+        params.secondary_phy = PhyKind::Le2M;
+        assert!(params.validate_phys(ext).is_ok());
+
+        params.coded_phy_options = PhyOptions::S8CodingPreferred;
+        assert!(params.validate_phys(ext).is_ok());
+        params.primary_phy = PhyKind::Le1M;
+        params.secondary_phy = PhyKind::Le2M;
+        assert!(params.validate_phys(ext).is_err());
+
+        params.coded_phy_options = PhyOptions::NoPreferredCoding;
+        params.primary_phy = PhyKind::Le2M;
+        assert!(params.validate_phys(ext).is_err());
+    }
+
     #[test]
     fn adv_name_truncate() {
         let mut adv_data = [0; 31];
@@ -607,4 +1307,57 @@ mod tests {
             .is_err()
         );
     }
+
+    #[test]
+    fn decode_typed_structures() {
+        let data = [
+            0x02, 0x01, 0x06, 0x03, 0x02, 0x0d, 0x18, 0x05, 0xff, 0x59, 0x00, 0x01, 0x02, 0x04, 0x16, 0x0f, 0x18, 0x64,
+            0x00, 0x00,
+        ];
+        let mut items = AdStructure::decode(&data);
+        assert!(matches!(items.next(), Some(Ok(AdStructure::Flags(0x06)))));
+        assert!(matches!(
+            items.next(),
+            Some(Ok(AdStructure::ServiceUuids16(&[[0x0d, 0x18]])))
+        ));
+        assert!(matches!(
+            items.next(),
+            Some(Ok(AdStructure::ManufacturerSpecificData {
+                company_identifier: 0x0059,
+                payload: &[0x01, 0x02]
+            }))
+        ));
+        assert!(matches!(
+            items.next(),
+            Some(Ok(AdStructure::ServiceData16 {
+                uuid: [0x0f, 0x18],
+                data: &[0x64]
+            }))
+        ));
+        // Zero padding terminates the data
+        assert!(items.next().is_none());
+    }
+
+    #[test]
+    fn service_solicitation_roundtrip() {
+        let uuids = [[0xab; 16]];
+        let mut data = [0; 31];
+        let len = AdStructure::encode_slice(&[AdStructure::ServiceSolicitationUuids128(&uuids)], &mut data).unwrap();
+        assert_eq!(&data[..2], &[0x11, 0x15]);
+        let mut items = AdStructure::decode(&data[..len]);
+        assert!(matches!(
+            items.next(),
+            Some(Ok(AdStructure::ServiceSolicitationUuids128(decoded))) if decoded == uuids
+        ));
+        assert!(items.next().is_none());
+    }
+
+    #[test]
+    fn decode_stops_at_malformed_structure() {
+        let data = [0x02, 0x01, 0x06, 0x05, 0x09, b'a', b'b'];
+        let mut items = AdStructure::decode(&data);
+        assert!(matches!(items.next(), Some(Ok(AdStructure::Flags(0x06)))));
+        assert!(matches!(items.next(), Some(Err(_))));
+        assert!(items.next().is_none());
+    }
 }
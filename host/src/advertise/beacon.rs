@@ -0,0 +1,255 @@
+//! iBeacon and Eddystone advertisement payloads.
+//!
+//! Each frame encodes to complete legacy advertisement data (including the flags AD structure)
+//! into a buffer sized by the frame type, so that the frame always fits.
+use embassy_time::Duration;
+
+use super::{
+    AdStructure, AdvertisementDataBuilder, AdvertisementDataError, BR_EDR_NOT_SUPPORTED, LE_GENERAL_DISCOVERABLE,
+    MAX_LEGACY_ADV_DATA_LEN,
+};
+
+const APPLE_COMPANY_IDENTIFIER: u16 = 0x004c;
+const IBEACON_TYPE: [u8; 2] = [0x02, 0x15];
+const EDDYSTONE_UUID: [u8; 2] = [0xaa, 0xfe];
+
+const EDDYSTONE_UID_FRAME: u8 = 0x00;
+const EDDYSTONE_URL_FRAME: u8 = 0x10;
+const EDDYSTONE_TLM_FRAME: u8 = 0x20;
+
+const FLAGS: u8 = LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED;
+
+// Flags, complete list of 16-bit service UUIDs and the service data header.
+const EDDYSTONE_OVERHEAD: usize = 3 + 4 + 4;
+
+const _: () = core::assert!(IBeacon::LEN <= MAX_LEGACY_ADV_DATA_LEN);
+const _: () = core::assert!(EddystoneUid::LEN <= MAX_LEGACY_ADV_DATA_LEN);
+const _: () = core::assert!(EddystoneUrl::MAX_LEN <= MAX_LEGACY_ADV_DATA_LEN);
+const _: () = core::assert!(EddystoneTlm::LEN <= MAX_LEGACY_ADV_DATA_LEN);
+
+/// An Apple iBeacon frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IBeacon {
+    /// Proximity UUID identifying the beacon deployment.
+    pub uuid: [u8; 16],
+    /// Major value, usually identifying a group of beacons.
+    pub major: u16,
+    /// Minor value, usually identifying a single beacon.
+    pub minor: u16,
+    /// Received signal strength at 1 meter, in dBm.
+    pub measured_power: i8,
+}
+
+impl IBeacon {
+    /// Length of the encoded advertisement data.
+    pub const LEN: usize = 30;
+
+    /// Encode the frame as advertisement data.
+    pub fn encode<'d>(&self, buf: &'d mut [u8; Self::LEN]) -> &'d [u8] {
+        let mut payload = [0; 23];
+        payload[..2].copy_from_slice(&IBEACON_TYPE);
+        payload[2..18].copy_from_slice(&self.uuid);
+        payload[18..20].copy_from_slice(&self.major.to_be_bytes());
+        payload[20..22].copy_from_slice(&self.minor.to_be_bytes());
+        payload[22] = self.measured_power as u8;
+
+        let mut builder = AdvertisementDataBuilder::legacy(buf);
+        // Cannot fail, the buffer is sized for the frame.
+        let _ = builder
+            .flags(FLAGS)
+            .and_then(|b| b.manufacturer_data(APPLE_COMPANY_IDENTIFIER, &payload));
+        builder.build()
+    }
+}
+
+/// An Eddystone-UID frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EddystoneUid {
+    /// Calibrated transmit power at 0 meters, in dBm.
+    pub tx_power: i8,
+    /// Namespace identifying the beacon deployment.
+    pub namespace: [u8; 10],
+    /// Instance identifying the beacon within the namespace.
+    pub instance: [u8; 6],
+}
+
+impl EddystoneUid {
+    /// Length of the encoded advertisement data.
+    pub const LEN: usize = EDDYSTONE_OVERHEAD + 20;
+
+    /// Encode the frame as advertisement data.
+    pub fn encode<'d>(&self, buf: &'d mut [u8; Self::LEN]) -> &'d [u8] {
+        let mut frame = [0; 20];
+        frame[0] = EDDYSTONE_UID_FRAME;
+        frame[1] = self.tx_power as u8;
+        frame[2..12].copy_from_slice(&self.namespace);
+        frame[12..18].copy_from_slice(&self.instance);
+        eddystone(buf, &frame)
+    }
+}
+
+/// An Eddystone-URL frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EddystoneUrl {
+    tx_power: i8,
+    scheme: u8,
+    url: [u8; 17],
+    len: usize,
+}
+
+const URL_SCHEMES: [&str; 4] = ["http://www.", "https://www.", "http://", "https://"];
+const URL_EXPANSIONS: [&str; 14] = [
+    ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/", ".com", ".org", ".edu", ".net", ".info", ".biz",
+    ".gov",
+];
+
+impl EddystoneUrl {
+    /// Maximum length of the encoded advertisement data.
+    pub const MAX_LEN: usize = EDDYSTONE_OVERHEAD + 20;
+
+    /// Create a frame for the given URL, compressing it using the Eddystone URL encoding.
+    ///
+    /// The URL must start with `http://` or `https://`, and is limited to 17 bytes once encoded.
+    pub fn new(tx_power: i8, url: &str) -> Result<Self, AdvertisementDataError> {
+        let (scheme, prefix) = URL_SCHEMES
+            .iter()
+            .enumerate()
+            .find(|(_, s)| url.starts_with(*s))
+            .ok_or(AdvertisementDataError::InvalidValue)?;
+        let mut rest = &url[prefix.len()..];
+
+        let mut encoded = [0; 17];
+        let mut len = 0;
+        while !rest.is_empty() {
+            if len == encoded.len() {
+                return Err(AdvertisementDataError::TooLong);
+            }
+            if let Some((code, expansion)) = URL_EXPANSIONS.iter().enumerate().find(|(_, e)| rest.starts_with(*e)) {
+                encoded[len] = code as u8;
+                rest = &rest[expansion.len()..];
+            } else {
+                let c = rest.as_bytes()[0];
+                if !(0x21..0x7f).contains(&c) {
+                    return Err(AdvertisementDataError::InvalidValue);
+                }
+                encoded[len] = c;
+                rest = &rest[1..];
+            }
+            len += 1;
+        }
+
+        Ok(Self {
+            tx_power,
+            scheme: scheme as u8,
+            url: encoded,
+            len,
+        })
+    }
+
+    /// Encode the frame as advertisement data.
+    pub fn encode<'d>(&self, buf: &'d mut [u8; Self::MAX_LEN]) -> &'d [u8] {
+        let mut frame = [0; 20];
+        frame[0] = EDDYSTONE_URL_FRAME;
+        frame[1] = self.tx_power as u8;
+        frame[2] = self.scheme;
+        frame[3..3 + self.len].copy_from_slice(&self.url[..self.len]);
+        eddystone(buf, &frame[..3 + self.len])
+    }
+}
+
+/// An unencrypted Eddystone-TLM frame, carrying beacon telemetry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EddystoneTlm {
+    /// Battery voltage in millivolts, or 0 if not supported.
+    pub battery_voltage: u16,
+    /// Temperature in degrees Celsius as signed 8.8 fixed point, or `i16::MIN` if not supported.
+    pub temperature: i16,
+    /// Number of advertising frames sent since power-up.
+    pub adv_count: u32,
+    /// Time since power-up.
+    pub uptime: Duration,
+}
+
+impl EddystoneTlm {
+    /// Length of the encoded advertisement data.
+    pub const LEN: usize = EDDYSTONE_OVERHEAD + 14;
+
+    /// Encode the frame as advertisement data.
+    pub fn encode<'d>(&self, buf: &'d mut [u8; Self::LEN]) -> &'d [u8] {
+        // Uptime is reported in units of 0.1 seconds.
+        let uptime = (self.uptime.as_millis() / 100) as u32;
+        let mut frame = [0; 14];
+        frame[0] = EDDYSTONE_TLM_FRAME;
+        frame[2..4].copy_from_slice(&self.battery_voltage.to_be_bytes());
+        frame[4..6].copy_from_slice(&self.temperature.to_be_bytes());
+        frame[6..10].copy_from_slice(&self.adv_count.to_be_bytes());
+        frame[10..14].copy_from_slice(&uptime.to_be_bytes());
+        eddystone(buf, &frame)
+    }
+}
+
+fn eddystone<'d>(buf: &'d mut [u8], frame: &[u8]) -> &'d [u8] {
+    let mut builder = AdvertisementDataBuilder::legacy(buf);
+    // Cannot fail, the buffer is sized for the largest frame.
+    let _ = builder
+        .flags(FLAGS)
+        // Eddystone requires the complete list of 16-bit service UUIDs AD type.
+        .and_then(|b| {
+            b.push(AdStructure::Unknown {
+                ty: 0x03,
+                data: &EDDYSTONE_UUID,
+            })
+        })
+        .and_then(|b| b.service_data16(EDDYSTONE_UUID, frame));
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ibeacon_fills_expected_layout() {
+        let beacon = IBeacon {
+            uuid: [0x11; 16],
+            major: 0x0102,
+            minor: 0x0304,
+            measured_power: -59,
+        };
+        let mut buf = [0; IBeacon::LEN];
+        let data = beacon.encode(&mut buf);
+        assert_eq!(data.len(), IBeacon::LEN);
+        assert_eq!(&data[..9], &[0x02, 0x01, 0x06, 0x1a, 0xff, 0x4c, 0x00, 0x02, 0x15]);
+        assert_eq!(&data[25..], &[0x01, 0x02, 0x03, 0x04, 0xc5]);
+    }
+
+    #[test]
+    fn eddystone_url_is_compressed() {
+        let url = EddystoneUrl::new(-20, "https://www.example.com/").unwrap();
+        let mut buf = [0; EddystoneUrl::MAX_LEN];
+        let data = url.encode(&mut buf);
+        assert_eq!(
+            data,
+            &[
+                0x02, 0x01, 0x06, 0x03, 0x03, 0xaa, 0xfe, 0x0e, 0x16, 0xaa, 0xfe, 0x10, 0xec, 0x01, b'e', b'x', b'a',
+                b'm', b'p', b'l', b'e', 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn eddystone_url_rejects_invalid() {
+        assert!(matches!(
+            EddystoneUrl::new(0, "ftp://example.com"),
+            Err(AdvertisementDataError::InvalidValue)
+        ));
+        assert!(matches!(
+            EddystoneUrl::new(0, "https://a-very-long-host-name.com"),
+            Err(AdvertisementDataError::TooLong)
+        ));
+    }
+}
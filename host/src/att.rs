@@ -15,6 +15,7 @@ pub(crate) const ATT_READ_REQ: u8 = 0x0a;
 pub(crate) const ATT_READ_RSP: u8 = 0x0b;
 pub(crate) const ATT_WRITE_REQ: u8 = 0x12;
 pub(crate) const ATT_WRITE_CMD: u8 = 0x52;
+pub(crate) const ATT_SIGNED_WRITE_CMD: u8 = 0xd2;
 pub(crate) const ATT_WRITE_RSP: u8 = 0x13;
 pub(crate) const ATT_EXCHANGE_MTU_REQ: u8 = 0x02;
 pub(crate) const ATT_EXCHANGE_MTU_RSP: u8 = 0x03;
@@ -94,6 +95,24 @@ impl AttErrorCode {
     pub const PROCEDURE_ALREADY_IN_PROGRESS: Self = Self { value: 0xFE };
     /// The attribute value is out of range as defined by a profile or service specification
     pub const OUT_OF_RANGE: Self = Self { value: 0xFF };
+
+    /// Application error code, defined by a profile or service in the range 0x80 to 0x9F
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is outside of the range, at compile time when used in a constant.
+    pub const fn application(value: u8) -> Self {
+        core::assert!(
+            value >= 0x80 && value <= 0x9F,
+            "application error codes are 0x80 to 0x9F"
+        );
+        Self { value }
+    }
+
+    /// Error code outside of the ranges of the specification, as used by proprietary services.
+    pub(crate) const fn proprietary(value: u8) -> Self {
+        Self { value }
+    }
 }
 
 impl Display for AttErrorCode {
@@ -1,12 +1,15 @@
 //! Attribute protocol implementation.
 use core::cell::RefCell;
 use core::fmt;
+use core::future::poll_fn;
 use core::marker::PhantomData;
 
+use bt_hci::param::ConnHandle;
 use bt_hci::uuid::declarations::{CHARACTERISTIC, PRIMARY_SERVICE};
 use bt_hci::uuid::descriptors::CLIENT_CHARACTERISTIC_CONFIGURATION;
 use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::{Duration, with_timeout};
 use heapless::Vec;
 
 use crate::Error;
@@ -20,6 +23,7 @@ pub use crate::types::uuid::Uuid;
 /// Characteristic properties
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum CharacteristicProp {
     /// Broadcast
     Broadcast = 0x01,
@@ -378,6 +382,7 @@ impl<'d, M: RawMutex, const MAX: usize> AttributeTable<'d, M, MAX> {
                     {
                         if value.len() == input.len() {
                             value.copy_from_slice(input);
+                            *len = input.len() as u16;
                             return Ok(());
                         } else if *variable_len && input.len() <= value.len() {
                             value[..input.len()].copy_from_slice(input);
@@ -592,6 +597,16 @@ impl<'d, M: RawMutex, const MAX: usize> ServiceBuilder<'_, 'd, M, MAX> {
         )
     }
 
+    /// Add a characteristic to this service with an immutable value.
+    pub(crate) fn add_characteristic_ro_bytes<U: Into<Uuid>>(
+        &mut self,
+        uuid: U,
+        value: &'d [u8],
+    ) -> CharacteristicBuilder<'_, 'd, &'d [u8], M, MAX> {
+        let props = [CharacteristicProp::Read].into();
+        self.add_characteristic_internal(uuid.into(), props, AttributeData::ReadOnlyData { props, value })
+    }
+
     /// Finish construction of the service and return a handle.
     pub fn build(self) -> u16 {
         self.handle
@@ -612,6 +627,21 @@ impl<M: RawMutex, const MAX: usize> Drop for ServiceBuilder<'_, '_, M, MAX> {
     }
 }
 
+/// Time for a client to confirm an indication.
+const ATT_TRANSACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An indication started, ended when dropped so that the next one can start.
+struct PendingIndication<'a, 'd, M: RawMutex, const MAX: usize> {
+    server: &'a AttributeServer<'d, M, MAX>,
+    conn: ConnHandle,
+}
+
+impl<M: RawMutex, const MAX: usize> Drop for PendingIndication<'_, '_, M, MAX> {
+    fn drop(&mut self) {
+        self.server.end_indication(self.conn);
+    }
+}
+
 /// A characteristic in the attribute table.
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -635,6 +665,71 @@ impl<T: FromGatt> Characteristic<T> {
         connection: &Connection<'_>,
         value: &T,
     ) -> Result<(), Error> {
+        self.send_value(server, connection, value, crate::att::ATT_HANDLE_VALUE_NTF)
+            .await?;
+        Ok(())
+    }
+
+    /// Write a value to a characteristic, and indicate a connection with the new value of the characteristic.
+    ///
+    /// If the provided connection has not subscribed for this characteristic, it will not be indicated.
+    /// The confirmation of the indication by the client is not awaited, see
+    /// [`Characteristic::indicate_confirmed`] to await it.
+    ///
+    /// If the characteristic does not support indications, an error is returned.
+    pub async fn indicate<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.send_value(server, connection, value, crate::att::ATT_HANDLE_VALUE_IND)
+            .await?;
+        Ok(())
+    }
+
+    /// Write a value to a characteristic, indicate a connection with the new value of the
+    /// characteristic, and wait for the client to confirm it.
+    ///
+    /// The indication waits for the confirmation of any previous one to the connection, a client
+    /// confirming one at a time. The confirmations come through the processing of the GATT
+    /// requests of the connection, which must go on meanwhile.
+    ///
+    /// Returns whether the client confirmed the indication, `false` if it has not subscribed for
+    /// it, or [`Error::Timeout`] if it did not confirm it within the 30 s of an ATT transaction.
+    ///
+    /// If the characteristic does not support indications, an error is returned.
+    pub async fn indicate_confirmed<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        value: &T,
+    ) -> Result<bool, Error> {
+        let conn = connection.handle();
+        poll_fn(|cx| server.poll_start_indication(conn, cx)).await;
+        let _indication = PendingIndication { server, conn };
+        if !self
+            .send_value(server, connection, value, crate::att::ATT_HANDLE_VALUE_IND)
+            .await?
+        {
+            return Ok(false);
+        }
+        with_timeout(
+            ATT_TRANSACTION_TIMEOUT,
+            poll_fn(|cx| server.poll_confirmation(conn, cx)),
+        )
+        .await
+        .map_err(|_| Error::Timeout)?;
+        Ok(true)
+    }
+
+    async fn send_value<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        value: &T,
+        opcode: u8,
+    ) -> Result<bool, Error> {
         let value = value.as_gatt();
         server.table().set_raw(self.handle, value)?;
 
@@ -642,13 +737,13 @@ impl<T: FromGatt> Characteristic<T> {
 
         if !server.should_notify(connection, cccd_handle) {
             // No reason to fail?
-            return Ok(());
+            return Ok(false);
         }
 
         let mut tx = connection.alloc_tx()?;
         let mut w = WriteCursor::new(tx.as_mut());
         let (mut header, mut data) = w.split(4)?;
-        data.write(crate::att::ATT_HANDLE_VALUE_NTF)?;
+        data.write(opcode)?;
         data.write(self.handle)?;
         data.append(value)?;
 
@@ -658,7 +753,7 @@ impl<T: FromGatt> Characteristic<T> {
 
         let pdu = crate::pdu::Pdu::new(tx, total);
         connection.send(pdu).await;
-        Ok(())
+        Ok(true)
     }
 
     /// Set the value of the characteristic in the provided attribute server.
@@ -725,6 +820,8 @@ impl<'d, T: AsGatt, M: RawMutex, const MAX: usize> CharacteristicBuilder<'_, 'd,
     }
 
     /// Add a characteristic descriptor for this characteristic.
+    ///
+    /// The descriptor has a variable length if its type has, set with [`AttributeTable::set`].
     pub fn add_descriptor<DT: AsGatt, U: Into<Uuid>>(
         &mut self,
         uuid: U,
@@ -739,7 +836,7 @@ impl<'d, T: AsGatt, M: RawMutex, const MAX: usize> CharacteristicBuilder<'_, 'd,
             AttributeData::Data {
                 props,
                 value: data,
-                variable_len: false,
+                variable_len: DT::MAX_SIZE != DT::MIN_SIZE,
                 len,
             },
         )
@@ -866,6 +963,7 @@ impl<M: RawMutex> AttributeValue<'_, M> {}
 
 /// CCCD flag values.
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum CCCDFlag {
     /// Notifications enabled.
     Notify = 0x1,
@@ -874,6 +972,7 @@ pub enum CCCDFlag {
 }
 
 /// CCCD flag.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct CCCD(pub(crate) u16);
 
 impl<const T: usize> From<[CCCDFlag; T]> for CCCD {
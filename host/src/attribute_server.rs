@@ -1,8 +1,10 @@
 use core::cell::RefCell;
+use core::task::{Context, Poll};
 
 use bt_hci::param::ConnHandle;
 use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::waitqueue::MultiWakerRegistration;
 
 use crate::att::{self, AttClient, AttCmd, AttErrorCode, AttReq};
 use crate::attribute::{AttributeData, AttributeTable};
@@ -16,10 +18,18 @@ pub(crate) struct NotificationTable<const ENTRIES: usize> {
     state: [(u16, ConnHandle); ENTRIES],
 }
 
+const MAX_INDICATIONS: usize = 4;
+/// Connections with an indication awaiting its confirmation, a client confirming one at a time.
+pub(crate) struct IndicationTable<const ENTRIES: usize> {
+    pending: [Option<ConnHandle>; ENTRIES],
+    wakers: MultiWakerRegistration<ENTRIES>,
+}
+
 /// A GATT server capable of processing the GATT protocol using the provided table of attributes.
 pub struct AttributeServer<'values, M: RawMutex, const MAX: usize> {
     pub(crate) table: AttributeTable<'values, M, MAX>,
     pub(crate) notification: Mutex<M, RefCell<NotificationTable<MAX_NOTIFICATIONS>>>,
+    pub(crate) indication: Mutex<M, RefCell<IndicationTable<MAX_INDICATIONS>>>,
 }
 
 pub(crate) mod sealed {
@@ -59,9 +69,52 @@ impl<'values, M: RawMutex, const MAX: usize> AttributeServer<'values, M, MAX> {
             notification: Mutex::new(RefCell::new(NotificationTable {
                 state: [(0, ConnHandle::new(0)); 4],
             })),
+            indication: Mutex::new(RefCell::new(IndicationTable {
+                pending: [None; MAX_INDICATIONS],
+                wakers: MultiWakerRegistration::new(),
+            })),
         }
     }
 
+    /// Start an indication to the connection, once the previous one is confirmed.
+    pub(crate) fn poll_start_indication(&self, conn: ConnHandle, cx: &mut Context<'_>) -> Poll<()> {
+        self.indication.lock(|i| {
+            let mut i = i.borrow_mut();
+            if !i.pending.contains(&Some(conn)) {
+                if let Some(entry) = i.pending.iter_mut().find(|entry| entry.is_none()) {
+                    *entry = Some(conn);
+                    return Poll::Ready(());
+                }
+            }
+            i.wakers.register(cx.waker());
+            Poll::Pending
+        })
+    }
+
+    /// Wait for the confirmation of the indication started to the connection.
+    pub(crate) fn poll_confirmation(&self, conn: ConnHandle, cx: &mut Context<'_>) -> Poll<()> {
+        self.indication.lock(|i| {
+            let mut i = i.borrow_mut();
+            if i.pending.contains(&Some(conn)) {
+                i.wakers.register(cx.waker());
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        })
+    }
+
+    /// End the indication to the connection, when confirmed or given up.
+    pub(crate) fn end_indication(&self, conn: ConnHandle) {
+        self.indication.lock(|i| {
+            let mut i = i.borrow_mut();
+            if let Some(entry) = i.pending.iter_mut().find(|entry| **entry == Some(conn)) {
+                *entry = None;
+                i.wakers.wake();
+            }
+        })
+    }
+
     pub(crate) fn should_notify(&self, connection: &Connection, cccd_handle: u16) -> bool {
         self.notification.lock(|n| {
             let n = n.borrow();
@@ -245,7 +298,7 @@ impl<'values, M: RawMutex, const MAX: usize> AttributeServer<'values, M, MAX> {
                             indications,
                         } = att.data
                         {
-                            self.set_notify(connection.handle(), handle, notifications);
+                            self.set_notify(connection.handle(), handle, notifications || indications);
                         }
                     }
                     break;
@@ -492,7 +545,10 @@ impl<'values, M: RawMutex, const MAX: usize> AttributeServer<'values, M, MAX> {
                 self.handle_read_multiple(connection, rx, handles)?
             }
 
-            AttClient::Confirmation(_) => 0,
+            AttClient::Confirmation(_) => {
+                self.end_indication(connection.handle());
+                0
+            }
         };
         if len > 0 { Ok(Some(len)) } else { Ok(None) }
     }
@@ -0,0 +1,202 @@
+//! LE Audio profiles, built on GATT and connected isochronous streams.
+//!
+//! The [`ascs`] and [`pacs`] modules hold the values of the Audio Stream Control Service and the
+//! Published Audio Capabilities Service, which together describe the audio streams of a unicast
+//! server, along with the servers adding them to an attribute table. The [`unicast_client`]
+//! drives the audio stream endpoints of a remote unicast server, while the streams themselves are
+//! created with the [`iso`](crate::iso) API. The [`codec`] module frames the audio of a codec
+//! into the SDUs of a stream.
+//!
+//! The [`bass`] module holds the Broadcast Audio Scan Service of a scan delegator, whose sources
+//! are controlled by a remote broadcast assistant, as implemented by `broadcast_assistant` with the
+//! `central` feature.
+use crate::Error;
+use crate::cursor::{ReadCursor, WriteCursor};
+
+pub mod ascs;
+pub mod bass;
+#[cfg(feature = "central")]
+pub mod broadcast_assistant;
+pub mod codec;
+pub mod pacs;
+#[cfg(feature = "central")]
+pub mod unicast_client;
+
+/// Identifier of a codec, as used in capabilities and configurations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CodecId {
+    /// Coding format, or 0xff for a vendor-specific codec.
+    pub format: u8,
+    /// Company identifier of a vendor-specific codec, or 0.
+    pub company_id: u16,
+    /// Vendor-defined identifier of a vendor-specific codec, or 0.
+    pub vendor_id: u16,
+}
+
+impl CodecId {
+    /// The Low Complexity Communication Codec.
+    pub const LC3: Self = Self {
+        format: 0x06,
+        company_id: 0,
+        vendor_id: 0,
+    };
+
+    /// Encode the identifier, as used by HCI and the audio services.
+    pub fn to_bytes(&self) -> [u8; 5] {
+        let [c0, c1] = self.company_id.to_le_bytes();
+        let [v0, v1] = self.vendor_id.to_le_bytes();
+        [self.format, c0, c1, v0, v1]
+    }
+
+    /// Decode an identifier.
+    pub fn from_bytes(data: [u8; 5]) -> Self {
+        Self {
+            format: data[0],
+            company_id: u16::from_le_bytes([data[1], data[2]]),
+            vendor_id: u16::from_le_bytes([data[3], data[4]]),
+        }
+    }
+}
+
+/// Direction of audio, as seen from the unicast server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AudioDirection {
+    /// Audio received by the server, sent from the central to the peripheral.
+    Sink,
+    /// Audio sent by the server, sent from the peripheral to the central.
+    Source,
+}
+
+/// A length-type-value structure, used by codec capabilities, configurations and metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Ltv<'d> {
+    /// Type of the structure.
+    pub ty: u8,
+    /// Value of the structure.
+    pub value: &'d [u8],
+}
+
+impl<'d> Ltv<'d> {
+    /// Decode a sequence of structures.
+    pub fn decode(data: &'d [u8]) -> LtvIter<'d> {
+        LtvIter {
+            cursor: ReadCursor::new(data),
+        }
+    }
+
+    /// Find the value of the first structure of a type in a sequence.
+    pub fn find(data: &'d [u8], ty: u8) -> Option<&'d [u8]> {
+        Self::decode(data)
+            .filter_map(Result::ok)
+            .find(|ltv| ltv.ty == ty)
+            .map(|ltv| ltv.value)
+    }
+
+    /// Encode the structure into the buffer, returning its length.
+    pub fn encode(&self, dest: &mut [u8]) -> Result<usize, Error> {
+        let len = u8::try_from(self.value.len() + 1).map_err(|_| Error::InvalidValue)?;
+        let mut w = WriteCursor::new(dest);
+        w.write(len)?;
+        w.write(self.ty)?;
+        w.append(self.value)?;
+        Ok(w.len())
+    }
+}
+
+/// Iterator over length-type-value structures.
+pub struct LtvIter<'d> {
+    cursor: ReadCursor<'d>,
+}
+
+impl<'d> LtvIter<'d> {
+    fn read(&mut self) -> Result<Ltv<'d>, Error> {
+        let len = read_u8(&mut self.cursor)?;
+        if len == 0 {
+            return Err(Error::InvalidValue);
+        }
+        let ty = read_u8(&mut self.cursor)?;
+        let value = self.cursor.slice(len as usize - 1)?;
+        Ok(Ltv { ty, value })
+    }
+}
+
+impl<'d> Iterator for LtvIter<'d> {
+    type Item = Result<Ltv<'d>, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.cursor.available() == 0 {
+            return None;
+        }
+        let item = self.read();
+        if item.is_err() {
+            self.cursor = ReadCursor::new(&[]);
+        }
+        Some(item)
+    }
+}
+
+// The readers of the primitive types assume the data is long enough, these check the length first.
+pub(crate) fn read_u8(r: &mut ReadCursor<'_>) -> Result<u8, Error> {
+    Ok(r.slice(1)?[0])
+}
+
+pub(crate) fn read_u16(r: &mut ReadCursor<'_>) -> Result<u16, Error> {
+    let s = r.slice(2)?;
+    Ok(u16::from_le_bytes([s[0], s[1]]))
+}
+
+pub(crate) fn read_u24(r: &mut ReadCursor<'_>) -> Result<u32, Error> {
+    let s = r.slice(3)?;
+    Ok(u32::from_le_bytes([s[0], s[1], s[2], 0]))
+}
+
+pub(crate) fn read_u32(r: &mut ReadCursor<'_>) -> Result<u32, Error> {
+    let s = r.slice(4)?;
+    Ok(u32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+}
+
+pub(crate) fn write_u24(w: &mut WriteCursor<'_>, value: u32) -> Result<(), Error> {
+    if value > 0xff_ffff {
+        return Err(Error::InvalidValue);
+    }
+    w.append(&value.to_le_bytes()[..3])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ltv_roundtrip() {
+        let mut buf = [0; 8];
+        let len = Ltv { ty: 0x02, value: &[1] }.encode(&mut buf).unwrap();
+        let len = len
+            + Ltv {
+                ty: 0x04,
+                value: &[40, 0],
+            }
+            .encode(&mut buf[len..])
+            .unwrap();
+        assert_eq!(&buf[..len], &[2, 0x02, 1, 3, 0x04, 40, 0]);
+        assert_eq!(Ltv::find(&buf[..len], 0x04), Some(&[40, 0][..]));
+        assert_eq!(Ltv::decode(&buf[..len]).count(), 2);
+
+        let mut it = Ltv::decode(&[3, 0x01, 1]);
+        assert!(matches!(it.next(), Some(Err(Error::InsufficientSpace))));
+        assert!(it.next().is_none());
+    }
+
+    #[test]
+    fn codec_id_roundtrip() {
+        let id = CodecId {
+            format: 0xff,
+            company_id: 0x0059,
+            vendor_id: 0x1234,
+        };
+        assert_eq!(id.to_bytes(), [0xff, 0x59, 0x00, 0x34, 0x12]);
+        assert_eq!(CodecId::from_bytes(id.to_bytes()), id);
+    }
+}
@@ -0,0 +1,1288 @@
+//! Values of the Audio Stream Control Service.
+//!
+//! An audio stream endpoint (ASE) of a unicast server is moved through its states by operations
+//! written by the client to the ASE control point. The server notifies the outcome of each
+//! operation on the control point, and each change of state on the characteristic of the ASE.
+//! The [`AscsServer`] holds these state machines on a unicast server, leaving the decisions to an
+//! [`AseHandler`].
+use bt_hci::param::PhyKind;
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::Duration;
+use heapless::Vec;
+
+use super::{AudioDirection, CodecId, read_u8, read_u16, read_u24, write_u24};
+use crate::Error;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::cursor::{ReadCursor, WriteCursor};
+use crate::iso::{CigConfig, CisConfig, phy_mask};
+
+/// Operation code of Config Codec.
+pub const OP_CONFIG_CODEC: u8 = 0x01;
+/// Operation code of Config QoS.
+pub const OP_CONFIG_QOS: u8 = 0x02;
+/// Operation code of Enable.
+pub const OP_ENABLE: u8 = 0x03;
+/// Operation code of Receiver Start Ready.
+pub const OP_RECEIVER_START_READY: u8 = 0x04;
+/// Operation code of Disable.
+pub const OP_DISABLE: u8 = 0x05;
+/// Operation code of Receiver Stop Ready.
+pub const OP_RECEIVER_STOP_READY: u8 = 0x06;
+/// Operation code of Update Metadata.
+pub const OP_UPDATE_METADATA: u8 = 0x07;
+/// Operation code of Release.
+pub const OP_RELEASE: u8 = 0x08;
+
+/// Latency targeted by a codec configuration.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TargetLatency {
+    /// Low latency, such as for gaming.
+    Low = 0x01,
+    /// Balanced latency and reliability.
+    Balanced = 0x02,
+    /// High reliability, such as for media streaming.
+    HighReliability = 0x03,
+}
+
+/// Codec configuration of an ASE, requested by the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CodecConfiguration<'a> {
+    /// Latency the server should target when selecting its preferred QoS.
+    pub target_latency: TargetLatency,
+    /// PHY the server should target when selecting its preferred QoS.
+    pub target_phy: PhyKind,
+    /// Codec to use.
+    pub codec_id: CodecId,
+    /// Codec specific configuration, as length-type-value structures.
+    pub config: &'a [u8],
+}
+
+/// QoS configuration of an ASE, matching the parameters of its connected isochronous stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct QosConfiguration {
+    /// Identifier of the group of the stream.
+    pub cig_id: u8,
+    /// Identifier of the stream in its group.
+    pub cis_id: u8,
+    /// Interval between SDUs.
+    pub sdu_interval: Duration,
+    /// Whether SDUs are framed.
+    pub framed: bool,
+    /// PHY of the stream.
+    pub phy: PhyKind,
+    /// Maximum size of the SDUs.
+    pub max_sdu: u16,
+    /// Number of retransmissions of each packet.
+    pub rtn: u8,
+    /// Maximum time to transport an SDU.
+    pub max_transport_latency: Duration,
+    /// Delay from the reception of an SDU to the rendering of its audio.
+    pub presentation_delay: Duration,
+}
+
+impl QosConfiguration {
+    /// Set the parameters of the CIG and CIS carrying the audio of an ASE in a direction.
+    ///
+    /// A bidirectional CIS carries a sink ASE and a source ASE, each applied to the same
+    /// configurations.
+    pub fn apply(&self, direction: AudioDirection, cig: &mut CigConfig, cis: &mut CisConfig) {
+        cig.id = self.cig_id;
+        cig.framed = self.framed;
+        cis.id = self.cis_id;
+        match direction {
+            AudioDirection::Sink => {
+                cig.sdu_interval_c_to_p = self.sdu_interval;
+                cig.max_latency_c_to_p = self.max_transport_latency;
+                cis.max_sdu_c_to_p = self.max_sdu;
+                cis.phy_c_to_p = self.phy;
+                cis.rtn_c_to_p = self.rtn;
+            }
+            AudioDirection::Source => {
+                cig.sdu_interval_p_to_c = self.sdu_interval;
+                cig.max_latency_p_to_c = self.max_transport_latency;
+                cis.max_sdu_p_to_c = self.max_sdu;
+                cis.phy_p_to_c = self.phy;
+                cis.rtn_p_to_c = self.rtn;
+            }
+        }
+    }
+
+    fn encode(&self, w: &mut WriteCursor<'_>) -> Result<(), Error> {
+        w.write(self.cig_id)?;
+        w.write(self.cis_id)?;
+        write_u24(w, self.sdu_interval.as_micros() as u32)?;
+        w.write(self.framed as u8)?;
+        w.write(phy_mask(self.phy))?;
+        w.write(self.max_sdu)?;
+        w.write(self.rtn)?;
+        w.write(u16::try_from(self.max_transport_latency.as_millis()).map_err(|_| Error::InvalidValue)?)?;
+        write_u24(w, self.presentation_delay.as_micros() as u32)
+    }
+
+    fn decode(r: &mut ReadCursor<'_>) -> Result<Self, Error> {
+        Ok(Self {
+            cig_id: read_u8(r)?,
+            cis_id: read_u8(r)?,
+            sdu_interval: Duration::from_micros(read_u24(r)?.into()),
+            framed: read_u8(r)? != 0,
+            phy: phy_from_mask(read_u8(r)?),
+            max_sdu: read_u16(r)?,
+            rtn: read_u8(r)?,
+            max_transport_latency: Duration::from_millis(read_u16(r)?.into()),
+            presentation_delay: Duration::from_micros(read_u24(r)?.into()),
+        })
+    }
+}
+
+fn phy_from_mask(mask: u8) -> PhyKind {
+    if mask & 0x02 != 0 {
+        PhyKind::Le2M
+    } else if mask & 0x04 != 0 {
+        PhyKind::LeCoded
+    } else {
+        PhyKind::Le1M
+    }
+}
+
+fn target_phy(phy: PhyKind) -> u8 {
+    match phy {
+        PhyKind::Le1M => 0x01,
+        PhyKind::Le2M => 0x02,
+        PhyKind::LeCoded | PhyKind::LeCodedS2 => 0x03,
+    }
+}
+
+/// QoS preferred by the server for a configured codec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct QosPreferences {
+    /// Whether unframed SDUs are supported.
+    pub unframed_supported: bool,
+    /// Preferred PHYs, with bit 0 for LE 1M, bit 1 for LE 2M and bit 2 for LE Coded.
+    pub phys: u8,
+    /// Preferred number of retransmissions of each packet.
+    pub rtn: u8,
+    /// Maximum time to transport an SDU.
+    pub max_transport_latency: Duration,
+    /// Minimum supported presentation delay.
+    pub presentation_delay_min: Duration,
+    /// Maximum supported presentation delay.
+    pub presentation_delay_max: Duration,
+    /// Minimum preferred presentation delay, or zero if there is no preference.
+    pub preferred_presentation_delay_min: Duration,
+    /// Maximum preferred presentation delay, or zero if there is no preference.
+    pub preferred_presentation_delay_max: Duration,
+}
+
+impl QosPreferences {
+    fn encode(&self, w: &mut WriteCursor<'_>) -> Result<(), Error> {
+        w.write(u8::from(!self.unframed_supported))?;
+        w.write(self.phys)?;
+        w.write(self.rtn)?;
+        w.write(u16::try_from(self.max_transport_latency.as_millis()).map_err(|_| Error::InvalidValue)?)?;
+        write_u24(w, self.presentation_delay_min.as_micros() as u32)?;
+        write_u24(w, self.presentation_delay_max.as_micros() as u32)?;
+        write_u24(w, self.preferred_presentation_delay_min.as_micros() as u32)?;
+        write_u24(w, self.preferred_presentation_delay_max.as_micros() as u32)
+    }
+
+    fn decode(r: &mut ReadCursor<'_>) -> Result<Self, Error> {
+        Ok(Self {
+            unframed_supported: read_u8(r)? == 0,
+            phys: read_u8(r)?,
+            rtn: read_u8(r)?,
+            max_transport_latency: Duration::from_millis(read_u16(r)?.into()),
+            presentation_delay_min: Duration::from_micros(read_u24(r)?.into()),
+            presentation_delay_max: Duration::from_micros(read_u24(r)?.into()),
+            preferred_presentation_delay_min: Duration::from_micros(read_u24(r)?.into()),
+            preferred_presentation_delay_max: Duration::from_micros(read_u24(r)?.into()),
+        })
+    }
+}
+
+/// State of an audio stream endpoint.
+///
+/// The codec configuration and metadata, being of variable length, are not retained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AseState {
+    /// No codec is configured.
+    Idle,
+    /// A codec is configured, and the server reported its preferred QoS.
+    CodecConfigured {
+        /// Configured codec.
+        codec_id: CodecId,
+        /// QoS preferred by the server.
+        preferences: QosPreferences,
+    },
+    /// The QoS is configured.
+    QosConfigured(QosConfiguration),
+    /// The stream is being established.
+    Enabling {
+        /// Identifier of the group of the stream.
+        cig_id: u8,
+        /// Identifier of the stream in its group.
+        cis_id: u8,
+    },
+    /// Audio is streaming.
+    Streaming {
+        /// Identifier of the group of the stream.
+        cig_id: u8,
+        /// Identifier of the stream in its group.
+        cis_id: u8,
+    },
+    /// Audio is stopping, only used by source ASEs.
+    Disabling {
+        /// Identifier of the group of the stream.
+        cig_id: u8,
+        /// Identifier of the stream in its group.
+        cis_id: u8,
+    },
+    /// The ASE is being released.
+    Releasing,
+}
+
+impl AseState {
+    /// Decode the value of an ASE characteristic, returning the identifier and state of the ASE.
+    pub fn decode(data: &[u8]) -> Result<(u8, Self), Error> {
+        let mut r = ReadCursor::new(data);
+        let id = read_u8(&mut r)?;
+        let state = match read_u8(&mut r)? {
+            0x00 => Self::Idle,
+            0x01 => {
+                let preferences = QosPreferences::decode(&mut r)?;
+                let codec_id = CodecId::from_bytes(unwrap!(r.slice(5)?.try_into()));
+                Self::CodecConfigured { codec_id, preferences }
+            }
+            0x02 => Self::QosConfigured(QosConfiguration::decode(&mut r)?),
+            state @ 0x03..=0x05 => {
+                let cig_id = read_u8(&mut r)?;
+                let cis_id = read_u8(&mut r)?;
+                match state {
+                    0x03 => Self::Enabling { cig_id, cis_id },
+                    0x04 => Self::Streaming { cig_id, cis_id },
+                    _ => Self::Disabling { cig_id, cis_id },
+                }
+            }
+            0x06 => Self::Releasing,
+            _ => return Err(Error::InvalidValue),
+        };
+        Ok((id, state))
+    }
+
+    /// Encode the value of an ASE characteristic into the buffer, returning its length.
+    ///
+    /// The codec configuration of the codec configured state, or the metadata of the enabling,
+    /// streaming and disabling states, is given as `extra`.
+    pub fn encode(&self, id: u8, extra: &[u8], dest: &mut [u8]) -> Result<usize, Error> {
+        let mut w = WriteCursor::new(dest);
+        w.write(id)?;
+        w.write(self.id())?;
+        match self {
+            Self::Idle | Self::Releasing => {}
+            Self::CodecConfigured { codec_id, preferences } => {
+                preferences.encode(&mut w)?;
+                w.append(&codec_id.to_bytes())?;
+                w.write(u8::try_from(extra.len()).map_err(|_| Error::InvalidValue)?)?;
+                w.append(extra)?;
+            }
+            Self::QosConfigured(qos) => qos.encode(&mut w)?,
+            Self::Enabling { cig_id, cis_id }
+            | Self::Streaming { cig_id, cis_id }
+            | Self::Disabling { cig_id, cis_id } => {
+                w.write(*cig_id)?;
+                w.write(*cis_id)?;
+                w.write(u8::try_from(extra.len()).map_err(|_| Error::InvalidValue)?)?;
+                w.append(extra)?;
+            }
+        }
+        Ok(w.len())
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            Self::Idle => 0x00,
+            Self::CodecConfigured { .. } => 0x01,
+            Self::QosConfigured(_) => 0x02,
+            Self::Enabling { .. } => 0x03,
+            Self::Streaming { .. } => 0x04,
+            Self::Disabling { .. } => 0x05,
+            Self::Releasing => 0x06,
+        }
+    }
+}
+
+/// Response code of an operation on an ASE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ResponseCode(u8);
+
+impl ResponseCode {
+    /// The operation succeeded.
+    pub const SUCCESS: Self = Self(0x00);
+    /// The operation code is not supported.
+    pub const UNSUPPORTED_OPCODE: Self = Self(0x01);
+    /// The length of the operation is invalid.
+    pub const INVALID_LENGTH: Self = Self(0x02);
+    /// The ASE identifier is invalid.
+    pub const INVALID_ASE_ID: Self = Self(0x03);
+    /// The operation is not allowed in the state of the ASE.
+    pub const INVALID_TRANSITION: Self = Self(0x04);
+    /// The operation is not allowed in the direction of the ASE.
+    pub const INVALID_DIRECTION: Self = Self(0x05);
+    /// The codec configuration is not supported.
+    pub const UNSUPPORTED_AUDIO_CAPABILITIES: Self = Self(0x06);
+    /// A configuration parameter is not supported, as given by the reason.
+    pub const UNSUPPORTED_CONFIGURATION: Self = Self(0x07);
+    /// A configuration parameter is rejected, as given by the reason.
+    pub const REJECTED_CONFIGURATION: Self = Self(0x08);
+    /// A configuration parameter is invalid, as given by the reason.
+    pub const INVALID_CONFIGURATION: Self = Self(0x09);
+    /// A metadata type is not supported, as given by the reason.
+    pub const UNSUPPORTED_METADATA: Self = Self(0x0a);
+    /// A metadata type is rejected, as given by the reason.
+    pub const REJECTED_METADATA: Self = Self(0x0b);
+    /// A metadata value is invalid, as given by the reason.
+    pub const INVALID_METADATA: Self = Self(0x0c);
+    /// The server has insufficient resources.
+    pub const INSUFFICIENT_RESOURCES: Self = Self(0x0d);
+    /// Another error occurred.
+    pub const UNSPECIFIED_ERROR: Self = Self(0x0e);
+
+    /// Create a response code from its value.
+    pub const fn new(value: u8) -> Self {
+        Self(value)
+    }
+
+    /// Value of the response code.
+    pub const fn into_inner(self) -> u8 {
+        self.0
+    }
+}
+
+/// Outcome of an operation on an ASE, notified by the server on the ASE control point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AseResponse {
+    /// Identifier of the ASE.
+    pub ase_id: u8,
+    /// Outcome of the operation.
+    pub code: ResponseCode,
+    /// Parameter or metadata type the response code refers to, or 0.
+    pub reason: u8,
+}
+
+/// Responses to an operation, notified by the server on the ASE control point.
+pub struct AseResponses<'d> {
+    /// Operation code of the operation.
+    pub opcode: u8,
+    cursor: ReadCursor<'d>,
+    remaining: u8,
+}
+
+impl<'d> AseResponses<'d> {
+    /// Decode the value of an ASE control point notification.
+    pub fn decode(data: &'d [u8]) -> Result<Self, Error> {
+        let mut cursor = ReadCursor::new(data);
+        let opcode = read_u8(&mut cursor)?;
+        let mut remaining = read_u8(&mut cursor)?;
+        // A single response with an invalid ASE identifier rejects the whole operation.
+        if remaining == 0xff {
+            remaining = 1;
+        }
+        Ok(Self {
+            opcode,
+            cursor,
+            remaining,
+        })
+    }
+
+    fn read(&mut self) -> Result<AseResponse, Error> {
+        Ok(AseResponse {
+            ase_id: read_u8(&mut self.cursor)?,
+            code: ResponseCode(read_u8(&mut self.cursor)?),
+            reason: read_u8(&mut self.cursor)?,
+        })
+    }
+}
+
+impl Iterator for AseResponses<'_> {
+    type Item = Result<AseResponse, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let item = self.read();
+        if item.is_err() {
+            self.remaining = 0;
+        }
+        Some(item)
+    }
+}
+
+/// An operation on one or more ASEs, written by the client to the ASE control point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AseOperation<'a> {
+    /// Configure the codec of each ASE.
+    ConfigCodec(&'a [(u8, CodecConfiguration<'a>)]),
+    /// Configure the QoS of each ASE.
+    ConfigQos(&'a [(u8, QosConfiguration)]),
+    /// Start establishing the stream of each ASE, with its metadata.
+    Enable(&'a [(u8, &'a [u8])]),
+    /// Tell the server that the client is ready to receive audio from each source ASE.
+    ReceiverStartReady(&'a [u8]),
+    /// Stop the audio of each ASE.
+    Disable(&'a [u8]),
+    /// Tell the server that the client stopped receiving audio from each source ASE.
+    ReceiverStopReady(&'a [u8]),
+    /// Update the metadata of each ASE.
+    UpdateMetadata(&'a [(u8, &'a [u8])]),
+    /// Release each ASE, returning it to the idle or codec configured state.
+    Release(&'a [u8]),
+}
+
+impl AseOperation<'_> {
+    /// Operation code of the operation.
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Self::ConfigCodec(_) => OP_CONFIG_CODEC,
+            Self::ConfigQos(_) => OP_CONFIG_QOS,
+            Self::Enable(_) => OP_ENABLE,
+            Self::ReceiverStartReady(_) => OP_RECEIVER_START_READY,
+            Self::Disable(_) => OP_DISABLE,
+            Self::ReceiverStopReady(_) => OP_RECEIVER_STOP_READY,
+            Self::UpdateMetadata(_) => OP_UPDATE_METADATA,
+            Self::Release(_) => OP_RELEASE,
+        }
+    }
+
+    /// Number of ASEs the operation applies to.
+    pub fn num_ases(&self) -> usize {
+        match self {
+            Self::ConfigCodec(c) => c.len(),
+            Self::ConfigQos(c) => c.len(),
+            Self::Enable(m) | Self::UpdateMetadata(m) => m.len(),
+            Self::ReceiverStartReady(ids) | Self::Disable(ids) | Self::ReceiverStopReady(ids) | Self::Release(ids) => {
+                ids.len()
+            }
+        }
+    }
+
+    /// Identifier of the ASE at an index of the operation.
+    pub fn ase_id(&self, index: usize) -> Option<u8> {
+        match self {
+            Self::ConfigCodec(c) => c.get(index).map(|c| c.0),
+            Self::ConfigQos(c) => c.get(index).map(|c| c.0),
+            Self::Enable(m) | Self::UpdateMetadata(m) => m.get(index).map(|m| m.0),
+            Self::ReceiverStartReady(ids) | Self::Disable(ids) | Self::ReceiverStopReady(ids) | Self::Release(ids) => {
+                ids.get(index).copied()
+            }
+        }
+    }
+
+    /// Encode the operation into the buffer, returning its length.
+    pub fn encode(&self, dest: &mut [u8]) -> Result<usize, Error> {
+        let mut w = WriteCursor::new(dest);
+        w.write(self.opcode())?;
+        w.write(u8::try_from(self.num_ases()).map_err(|_| Error::InvalidValue)?)?;
+        match self {
+            Self::ConfigCodec(configs) => {
+                for (id, config) in configs.iter() {
+                    w.write(*id)?;
+                    w.write(config.target_latency as u8)?;
+                    w.write(target_phy(config.target_phy))?;
+                    w.append(&config.codec_id.to_bytes())?;
+                    w.write(u8::try_from(config.config.len()).map_err(|_| Error::InvalidValue)?)?;
+                    w.append(config.config)?;
+                }
+            }
+            Self::ConfigQos(configs) => {
+                for (id, config) in configs.iter() {
+                    w.write(*id)?;
+                    config.encode(&mut w)?;
+                }
+            }
+            Self::Enable(metadata) | Self::UpdateMetadata(metadata) => {
+                for (id, metadata) in metadata.iter() {
+                    w.write(*id)?;
+                    w.write(u8::try_from(metadata.len()).map_err(|_| Error::InvalidValue)?)?;
+                    w.append(metadata)?;
+                }
+            }
+            Self::ReceiverStartReady(ids) | Self::Disable(ids) | Self::ReceiverStopReady(ids) | Self::Release(ids) => {
+                w.append(ids)?;
+            }
+        }
+        Ok(w.len())
+    }
+}
+
+/// Reason of a response, for the Codec_ID configuration parameter.
+pub const REASON_CODEC_ID: u8 = 0x01;
+/// Reason of a response, for the codec specific configuration.
+pub const REASON_CODEC_CONFIGURATION: u8 = 0x02;
+/// Reason of a response, for the SDU interval.
+pub const REASON_SDU_INTERVAL: u8 = 0x03;
+/// Reason of a response, for the framing.
+pub const REASON_FRAMING: u8 = 0x04;
+/// Reason of a response, for the PHY.
+pub const REASON_PHY: u8 = 0x05;
+/// Reason of a response, for the maximum SDU size.
+pub const REASON_MAX_SDU: u8 = 0x06;
+/// Reason of a response, for the retransmission number.
+pub const REASON_RTN: u8 = 0x07;
+/// Reason of a response, for the maximum transport latency.
+pub const REASON_MAX_TRANSPORT_LATENCY: u8 = 0x08;
+/// Reason of a response, for the presentation delay.
+pub const REASON_PRESENTATION_DELAY: u8 = 0x09;
+/// Reason of a response, for a CIS used by ASEs of the same direction.
+pub const REASON_INVALID_CIS_MAPPING: u8 = 0x0a;
+
+/// Rejection of an operation on an ASE by the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AseRejection {
+    /// Response code of the rejection.
+    pub code: ResponseCode,
+    /// Parameter or metadata type the response code refers to, or 0.
+    pub reason: u8,
+}
+
+impl From<ResponseCode> for AseRejection {
+    fn from(code: ResponseCode) -> Self {
+        Self { code, reason: 0 }
+    }
+}
+
+/// Handler of the operations on the ASEs of an [`AscsServer`], implemented by the application.
+///
+/// An operation is only passed to the handler once it is allowed by the state of the ASE. The
+/// codec configuration must be checked by the handler, while the other operations are accepted
+/// unless overridden.
+pub trait AseHandler {
+    /// Configure the codec of an ASE, returning the QoS preferred by the server for it.
+    fn config_codec(
+        &mut self,
+        ase_id: u8,
+        direction: AudioDirection,
+        config: &CodecConfiguration<'_>,
+    ) -> Result<QosPreferences, AseRejection>;
+
+    /// Configure the QoS of an ASE.
+    fn config_qos(
+        &mut self,
+        ase_id: u8,
+        direction: AudioDirection,
+        qos: &QosConfiguration,
+    ) -> Result<(), AseRejection> {
+        Ok(())
+    }
+
+    /// Enable an ASE with its metadata. The CIS of the ASE is then to be accepted.
+    fn enable(&mut self, ase_id: u8, direction: AudioDirection, metadata: &[u8]) -> Result<(), AseRejection> {
+        Ok(())
+    }
+
+    /// Start sending the audio of a source ASE, as the client is ready to receive it.
+    fn receiver_start_ready(&mut self, ase_id: u8) -> Result<(), AseRejection> {
+        Ok(())
+    }
+
+    /// Stop the audio of an ASE.
+    fn disable(&mut self, ase_id: u8, direction: AudioDirection) -> Result<(), AseRejection> {
+        Ok(())
+    }
+
+    /// Acknowledge that the client stopped receiving the audio of a source ASE.
+    fn receiver_stop_ready(&mut self, ase_id: u8) -> Result<(), AseRejection> {
+        Ok(())
+    }
+
+    /// Update the metadata of an enabled ASE.
+    fn update_metadata(&mut self, ase_id: u8, direction: AudioDirection, metadata: &[u8]) -> Result<(), AseRejection> {
+        Ok(())
+    }
+
+    /// Release an ASE. Its release is completed with [`AscsServer::release_complete`] once its CIS is disconnected.
+    fn release(&mut self, ase_id: u8, direction: AudioDirection) -> Result<(), AseRejection> {
+        Ok(())
+    }
+}
+
+/// Number of attributes of the Audio Stream Control Service with a number of ASEs.
+pub const fn ascs_attribute_count(ases: usize) -> usize {
+    // The service, and the declaration, value and CCCD of each ASE and the control point.
+    1 + 3 * (ases + 1)
+}
+
+/// Storage of the values of the characteristics of an [`AscsServer`].
+///
+/// Each value holds up to `LEN` octets, which must fit the codec configurations and metadata of
+/// the ASEs as well as the operations written by the client.
+pub struct AscsStorage<const ASES: usize, const LEN: usize = 64> {
+    ases: [[u8; LEN]; ASES],
+    control_point: [u8; LEN],
+}
+
+impl<const ASES: usize, const LEN: usize> AscsStorage<ASES, LEN> {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            ases: [[0; LEN]; ASES],
+            control_point: [0; LEN],
+        }
+    }
+}
+
+impl<const ASES: usize, const LEN: usize> Default for AscsStorage<ASES, LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Endpoint<const LEN: usize> {
+    characteristic: Characteristic<Vec<u8, LEN>>,
+    id: u8,
+    direction: AudioDirection,
+    state: AseState,
+    qos: Option<QosConfiguration>,
+    value: Vec<u8, LEN>,
+    notify: bool,
+}
+
+impl<const LEN: usize> Endpoint<LEN> {
+    fn set(&mut self, state: AseState, extra: &[u8]) -> Result<(), AseRejection> {
+        let mut buf = [0; LEN];
+        let len = state
+            .encode(self.id, extra, &mut buf)
+            .map_err(|_| ResponseCode::INSUFFICIENT_RESOURCES)?;
+        self.value = unwrap!(Vec::from_slice(&buf[..len]));
+        self.state = state;
+        self.notify = true;
+        Ok(())
+    }
+
+    // Move between the enabling, streaming and disabling states, which share the same value layout.
+    fn set_keeping_metadata(&mut self, state: AseState) {
+        if let Some(id) = self.value.get_mut(1) {
+            *id = state.id();
+        }
+        self.state = state;
+        self.notify = true;
+    }
+
+    fn stream(&self) -> Option<(u8, u8)> {
+        match self.state {
+            AseState::Enabling { cig_id, cis_id }
+            | AseState::Streaming { cig_id, cis_id }
+            | AseState::Disabling { cig_id, cis_id } => Some((cig_id, cis_id)),
+            _ => None,
+        }
+    }
+
+    fn disabled(&mut self) -> Result<(), AseRejection> {
+        let qos = self.qos.ok_or(ResponseCode::UNSPECIFIED_ERROR)?;
+        self.set(AseState::QosConfigured(qos), &[])
+    }
+}
+
+// An entry of an operation written to the control point.
+enum Entry<'d> {
+    Codec {
+        id: u8,
+        target_latency: u8,
+        target_phy: u8,
+        codec_id: CodecId,
+        config: &'d [u8],
+    },
+    Qos(u8, QosConfiguration),
+    Metadata(u8, &'d [u8]),
+    Id(u8),
+}
+
+impl<'d> Entry<'d> {
+    fn read(opcode: u8, r: &mut ReadCursor<'d>) -> Result<Self, Error> {
+        let id = read_u8(r)?;
+        Ok(match opcode {
+            OP_CONFIG_CODEC => {
+                let target_latency = read_u8(r)?;
+                let target_phy = read_u8(r)?;
+                let codec_id = CodecId::from_bytes(unwrap!(r.slice(5)?.try_into()));
+                let len = read_u8(r)?;
+                Self::Codec {
+                    id,
+                    target_latency,
+                    target_phy,
+                    codec_id,
+                    config: r.slice(len as usize)?,
+                }
+            }
+            OP_CONFIG_QOS => Self::Qos(id, QosConfiguration::decode(r)?),
+            OP_ENABLE | OP_UPDATE_METADATA => {
+                let len = read_u8(r)?;
+                Self::Metadata(id, r.slice(len as usize)?)
+            }
+            _ => Self::Id(id),
+        })
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            Self::Codec { id, .. } | Self::Qos(id, _) | Self::Metadata(id, _) | Self::Id(id) => *id,
+        }
+    }
+}
+
+/// Audio Stream Control Service server, holding the state machines of the ASEs of a unicast server.
+///
+/// Writes to the control point are handled with [`AscsServer::process`] before being accepted,
+/// after which the outcome is notified to the client with [`AscsServer::notify`]. The server
+/// holds the ASEs of a single client.
+pub struct AscsServer<const ASES: usize, const LEN: usize = 64> {
+    control_point: Characteristic<Vec<u8, LEN>>,
+    endpoints: Vec<Endpoint<LEN>, ASES>,
+    response: Option<Vec<u8, LEN>>,
+}
+
+impl<const ASES: usize, const LEN: usize> AscsServer<ASES, LEN> {
+    /// Add the service to the attribute table, with `sinks` sink ASEs followed by source ASEs.
+    ///
+    /// The ASEs are identified from 1, in the order of the storage.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut AscsStorage<ASES, LEN>,
+        sinks: usize,
+    ) -> Result<Self, Error> {
+        if sinks > ASES || ASES > u8::MAX as usize {
+            return Err(Error::InvalidValue);
+        }
+        let AscsStorage { ases, control_point } = storage;
+        let mut service = table.add_service(Service::new(service::AUDIO_STREAM_CONTROL));
+        let mut endpoints = Vec::new();
+        for (i, store) in ases.iter_mut().enumerate() {
+            let id = i as u8 + 1;
+            let (uuid, direction) = if i < sinks {
+                (characteristic::SINK_ASE, AudioDirection::Sink)
+            } else {
+                (characteristic::SOURCE_ASE, AudioDirection::Source)
+            };
+            let value: Vec<u8, LEN> =
+                Vec::from_slice(&[id, AseState::Idle.id()]).map_err(|_| Error::InsufficientSpace)?;
+            let characteristic = service
+                .add_characteristic(
+                    uuid,
+                    &[CharacteristicProp::Read, CharacteristicProp::Notify],
+                    value.clone(),
+                    store,
+                )
+                .build();
+            let endpoint = Endpoint {
+                characteristic,
+                id,
+                direction,
+                state: AseState::Idle,
+                qos: None,
+                value,
+                notify: false,
+            };
+            unwrap!(endpoints.push(endpoint).ok());
+        }
+        let control_point = service
+            .add_characteristic(
+                characteristic::ASE_CONTROL_POINT,
+                &[
+                    CharacteristicProp::Write,
+                    CharacteristicProp::WriteWithoutResponse,
+                    CharacteristicProp::Notify,
+                ],
+                Vec::new(),
+                control_point,
+            )
+            .build();
+        service.build();
+        Ok(Self {
+            control_point,
+            endpoints,
+            response: None,
+        })
+    }
+
+    /// The ASE control point, written by the client.
+    pub fn control_point(&self) -> &Characteristic<Vec<u8, LEN>> {
+        &self.control_point
+    }
+
+    /// State of an ASE.
+    pub fn state(&self, ase_id: u8) -> Option<&AseState> {
+        self.endpoints.iter().find(|e| e.id == ase_id).map(|e| &e.state)
+    }
+
+    /// Handle an operation written to the control point, passing each of its ASEs to the handler.
+    pub fn process(&mut self, data: &[u8], handler: &mut impl AseHandler) {
+        let mut response: Vec<u8, LEN> = Vec::new();
+        let opcode = data.first().copied().unwrap_or(0);
+        let mut r = ReadCursor::new(data.get(2..).unwrap_or(&[]));
+        let code = match (opcode, data.get(1)) {
+            (OP_CONFIG_CODEC..=OP_RELEASE, Some(&count)) if count > 0 => {
+                // Check the length of the whole operation before applying any of it.
+                let mut check = r.clone();
+                let valid = (0..count).all(|_| Entry::read(opcode, &mut check).is_ok()) && check.available() == 0;
+                if valid && response.extend_from_slice(&[opcode, count]).is_ok() {
+                    for _ in 0..count {
+                        let entry = unwrap!(Entry::read(opcode, &mut r));
+                        let id = entry.id();
+                        let result = self
+                            .apply(opcode, entry, handler)
+                            .err()
+                            .unwrap_or(ResponseCode::SUCCESS.into());
+                        let _ = response.extend_from_slice(&[id, result.code.0, result.reason]);
+                    }
+                    None
+                } else {
+                    Some(ResponseCode::INVALID_LENGTH)
+                }
+            }
+            (OP_CONFIG_CODEC..=OP_RELEASE, _) => Some(ResponseCode::INVALID_LENGTH),
+            _ => Some(ResponseCode::UNSUPPORTED_OPCODE),
+        };
+        if let Some(code) = code {
+            response.clear();
+            let _ = response.extend_from_slice(&[opcode, 0xff, 0x00, code.0, 0x00]);
+        }
+        self.response = Some(response);
+    }
+
+    fn apply(&mut self, opcode: u8, entry: Entry<'_>, handler: &mut impl AseHandler) -> Result<(), AseRejection> {
+        let id = entry.id();
+        let ep = self
+            .endpoints
+            .iter_mut()
+            .find(|e| e.id == id)
+            .ok_or(ResponseCode::INVALID_ASE_ID)?;
+        let invalid = AseRejection::from(ResponseCode::INVALID_TRANSITION);
+        match entry {
+            Entry::Codec {
+                target_latency,
+                target_phy,
+                codec_id,
+                config,
+                ..
+            } => {
+                if !matches!(
+                    ep.state,
+                    AseState::Idle | AseState::CodecConfigured { .. } | AseState::QosConfigured(_)
+                ) {
+                    return Err(invalid);
+                }
+                let target_latency = match target_latency {
+                    0x01 => TargetLatency::Low,
+                    0x02 => TargetLatency::Balanced,
+                    0x03 => TargetLatency::HighReliability,
+                    _ => return Err(ResponseCode::INVALID_CONFIGURATION.into()),
+                };
+                let target_phy = match target_phy {
+                    0x01 => PhyKind::Le1M,
+                    0x02 => PhyKind::Le2M,
+                    0x03 => PhyKind::LeCoded,
+                    _ => {
+                        return Err(AseRejection {
+                            code: ResponseCode::INVALID_CONFIGURATION,
+                            reason: REASON_PHY,
+                        });
+                    }
+                };
+                let config = CodecConfiguration {
+                    target_latency,
+                    target_phy,
+                    codec_id,
+                    config,
+                };
+                let preferences = handler.config_codec(id, ep.direction, &config)?;
+                ep.qos = None;
+                ep.set(AseState::CodecConfigured { codec_id, preferences }, config.config)
+            }
+            Entry::Qos(_, qos) => {
+                if !matches!(ep.state, AseState::CodecConfigured { .. } | AseState::QosConfigured(_)) {
+                    return Err(invalid);
+                }
+                handler.config_qos(id, ep.direction, &qos)?;
+                ep.qos = Some(qos);
+                ep.set(AseState::QosConfigured(qos), &[])
+            }
+            Entry::Metadata(_, metadata) if opcode == OP_ENABLE => {
+                let AseState::QosConfigured(qos) = ep.state else {
+                    return Err(invalid);
+                };
+                handler.enable(id, ep.direction, metadata)?;
+                let state = AseState::Enabling {
+                    cig_id: qos.cig_id,
+                    cis_id: qos.cis_id,
+                };
+                ep.set(state, metadata)
+            }
+            Entry::Metadata(_, metadata) => {
+                if !matches!(ep.state, AseState::Enabling { .. } | AseState::Streaming { .. }) {
+                    return Err(invalid);
+                }
+                handler.update_metadata(id, ep.direction, metadata)?;
+                ep.set(ep.state, metadata)
+            }
+            Entry::Id(_) => match opcode {
+                OP_RECEIVER_START_READY | OP_RECEIVER_STOP_READY if ep.direction == AudioDirection::Sink => {
+                    Err(ResponseCode::INVALID_DIRECTION.into())
+                }
+                OP_RECEIVER_START_READY => {
+                    let AseState::Enabling { cig_id, cis_id } = ep.state else {
+                        return Err(invalid);
+                    };
+                    handler.receiver_start_ready(id)?;
+                    ep.set_keeping_metadata(AseState::Streaming { cig_id, cis_id });
+                    Ok(())
+                }
+                OP_DISABLE => {
+                    let Some((cig_id, cis_id)) =
+                        ep.stream().filter(|_| !matches!(ep.state, AseState::Disabling { .. }))
+                    else {
+                        return Err(invalid);
+                    };
+                    handler.disable(id, ep.direction)?;
+                    match ep.direction {
+                        AudioDirection::Sink => ep.disabled(),
+                        AudioDirection::Source => {
+                            ep.set_keeping_metadata(AseState::Disabling { cig_id, cis_id });
+                            Ok(())
+                        }
+                    }
+                }
+                OP_RECEIVER_STOP_READY => {
+                    if !matches!(ep.state, AseState::Disabling { .. }) {
+                        return Err(invalid);
+                    }
+                    handler.receiver_stop_ready(id)?;
+                    ep.disabled()
+                }
+                _ => {
+                    if matches!(ep.state, AseState::Idle | AseState::Releasing) {
+                        return Err(invalid);
+                    }
+                    handler.release(id, ep.direction)?;
+                    ep.set(AseState::Releasing, &[])
+                }
+            },
+        }
+    }
+
+    /// Move a sink ASE to the streaming state, once the server is ready to receive its audio.
+    pub fn receiver_ready(&mut self, ase_id: u8) -> Result<(), Error> {
+        let ep = self.endpoint(ase_id)?;
+        match (ep.direction, ep.state) {
+            (AudioDirection::Sink, AseState::Enabling { cig_id, cis_id }) => {
+                ep.set_keeping_metadata(AseState::Streaming { cig_id, cis_id });
+                Ok(())
+            }
+            _ => Err(Error::InvalidState),
+        }
+    }
+
+    /// Move a releasing ASE back to the idle state, once its CIS is disconnected.
+    pub fn release_complete(&mut self, ase_id: u8) -> Result<(), Error> {
+        let ep = self.endpoint(ase_id)?;
+        if ep.state != AseState::Releasing {
+            return Err(Error::InvalidState);
+        }
+        ep.qos = None;
+        unwrap!(ep.set(AseState::Idle, &[]).ok());
+        Ok(())
+    }
+
+    /// Move the ASEs of a CIS back to the QoS configured state, when the CIS is lost.
+    pub fn cis_lost(&mut self, cig_id: u8, cis_id: u8) {
+        for ep in self.endpoints.iter_mut() {
+            if ep.stream() == Some((cig_id, cis_id)) {
+                let _ = ep.disabled();
+            }
+        }
+    }
+
+    /// Move all ASEs to the idle state, such as when the client disconnects.
+    pub fn reset<M: RawMutex, const MAX: usize>(&mut self, server: &AttributeServer<'_, M, MAX>) -> Result<(), Error> {
+        self.response = None;
+        for ep in self.endpoints.iter_mut() {
+            ep.qos = None;
+            unwrap!(ep.set(AseState::Idle, &[]).ok());
+            ep.notify = false;
+            ep.characteristic.set(server, &ep.value)?;
+        }
+        Ok(())
+    }
+
+    /// Notify the client of the response to the last operation and of the changed ASEs.
+    pub async fn notify<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+    ) -> Result<(), Error> {
+        if let Some(response) = self.response.take() {
+            self.control_point.notify(server, connection, &response).await?;
+        }
+        for ep in self.endpoints.iter_mut().filter(|ep| ep.notify) {
+            ep.notify = false;
+            ep.characteristic.notify(server, connection, &ep.value).await?;
+        }
+        Ok(())
+    }
+
+    fn endpoint(&mut self, ase_id: u8) -> Result<&mut Endpoint<LEN>, Error> {
+        self.endpoints
+            .iter_mut()
+            .find(|e| e.id == ase_id)
+            .ok_or(Error::NotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+
+    fn qos() -> QosConfiguration {
+        QosConfiguration {
+            cig_id: 1,
+            cis_id: 2,
+            sdu_interval: Duration::from_micros(10_000),
+            framed: false,
+            phy: PhyKind::Le2M,
+            max_sdu: 40,
+            rtn: 2,
+            max_transport_latency: Duration::from_millis(10),
+            presentation_delay: Duration::from_micros(40_000),
+        }
+    }
+
+    #[test]
+    fn encode_operations() {
+        let mut buf = [0; 32];
+        let config = CodecConfiguration {
+            target_latency: TargetLatency::Balanced,
+            target_phy: PhyKind::Le2M,
+            codec_id: CodecId::LC3,
+            config: &[2, 0x01, 0x08],
+        };
+        let len = AseOperation::ConfigCodec(&[(1, config)]).encode(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            &[0x01, 1, 1, 0x02, 0x02, 0x06, 0, 0, 0, 0, 3, 2, 0x01, 0x08]
+        );
+
+        let len = AseOperation::ConfigQos(&[(1, qos())]).encode(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            &[0x02, 1, 1, 1, 2, 0x10, 0x27, 0, 0, 0x02, 40, 0, 2, 10, 0, 0x40, 0x9c, 0]
+        );
+
+        let len = AseOperation::Release(&[1, 2]).encode(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &[0x08, 2, 1, 2]);
+    }
+
+    #[test]
+    fn decode_states() {
+        let mut data = [0; 20];
+        data[0] = 3;
+        data[1] = 0x02;
+        let mut w = WriteCursor::new(&mut data[2..]);
+        qos().encode(&mut w).unwrap();
+        assert_eq!(
+            AseState::decode(&data[..17]).unwrap(),
+            (3, AseState::QosConfigured(qos()))
+        );
+        assert!(AseState::decode(&data[..16]).is_err());
+
+        let data = [4, 0x04, 1, 2, 0];
+        assert_eq!(
+            AseState::decode(&data).unwrap(),
+            (4, AseState::Streaming { cig_id: 1, cis_id: 2 })
+        );
+        assert!(AseState::decode(&[4, 0x07]).is_err());
+    }
+
+    #[test]
+    fn decode_responses() {
+        let data = [0x01, 2, 1, 0x00, 0x00, 2, 0x07, 0x02];
+        let responses = AseResponses::decode(&data).unwrap();
+        assert_eq!(responses.opcode, OP_CONFIG_CODEC);
+        let responses: heapless::Vec<_, 2> = responses.map(Result::unwrap).collect();
+        assert_eq!(responses[0].code, ResponseCode::SUCCESS);
+        assert_eq!(
+            responses[1],
+            AseResponse {
+                ase_id: 2,
+                code: ResponseCode::UNSUPPORTED_CONFIGURATION,
+                reason: 0x02,
+            }
+        );
+    }
+
+    struct Handler;
+
+    impl AseHandler for Handler {
+        fn config_codec(
+            &mut self,
+            _ase_id: u8,
+            _direction: AudioDirection,
+            config: &CodecConfiguration<'_>,
+        ) -> Result<QosPreferences, AseRejection> {
+            if config.codec_id != CodecId::LC3 {
+                return Err(AseRejection {
+                    code: ResponseCode::UNSUPPORTED_CONFIGURATION,
+                    reason: REASON_CODEC_ID,
+                });
+            }
+            Ok(QosPreferences {
+                unframed_supported: true,
+                phys: 0x02,
+                rtn: 2,
+                max_transport_latency: Duration::from_millis(10),
+                presentation_delay_min: Duration::from_micros(20_000),
+                presentation_delay_max: Duration::from_micros(40_000),
+                preferred_presentation_delay_min: Duration::from_micros(0),
+                preferred_presentation_delay_max: Duration::from_micros(0),
+            })
+        }
+    }
+
+    fn write(server: &mut AscsServer<2, 64>, op: AseOperation<'_>) -> heapless::Vec<AseResponse, 2> {
+        let mut buf = [0; 64];
+        let len = op.encode(&mut buf).unwrap();
+        server.process(&buf[..len], &mut Handler);
+        let response = server.response.take().unwrap();
+        AseResponses::decode(&response).unwrap().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn server_state_machine() {
+        let mut storage = AscsStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, { ascs_attribute_count(2) }> = AttributeTable::new();
+        let mut server: AscsServer<2> = AscsServer::build(&mut table, &mut storage, 1).unwrap();
+        let config = CodecConfiguration {
+            target_latency: TargetLatency::Balanced,
+            target_phy: PhyKind::Le2M,
+            codec_id: CodecId::LC3,
+            config: &[2, 0x01, 0x08],
+        };
+        let vendor = CodecConfiguration {
+            codec_id: CodecId {
+                format: 0xff,
+                company_id: 0x0059,
+                vendor_id: 0,
+            },
+            ..config
+        };
+
+        let responses = write(&mut server, AseOperation::ConfigCodec(&[(1, config), (2, vendor)]));
+        assert_eq!(responses[0].code, ResponseCode::SUCCESS);
+        assert_eq!(responses[1].code, ResponseCode::UNSUPPORTED_CONFIGURATION);
+        assert_eq!(responses[1].reason, REASON_CODEC_ID);
+        let ep = &server.endpoints[0];
+        assert!(ep.notify && !server.endpoints[1].notify);
+        assert_eq!(AseState::decode(&ep.value).unwrap(), (1, ep.state));
+        assert!(ep.value.ends_with(&[3, 2, 0x01, 0x08]));
+
+        let responses = write(&mut server, AseOperation::Enable(&[(1, &[])]));
+        assert_eq!(responses[0].code, ResponseCode::INVALID_TRANSITION);
+        write(&mut server, AseOperation::ConfigQos(&[(1, qos())]));
+        write(&mut server, AseOperation::Enable(&[(1, &[3, 0x02, 0x04, 0x00])]));
+        assert_eq!(server.state(1), Some(&AseState::Enabling { cig_id: 1, cis_id: 2 }));
+        let responses = write(&mut server, AseOperation::ReceiverStartReady(&[1]));
+        assert_eq!(responses[0].code, ResponseCode::INVALID_DIRECTION);
+        server.receiver_ready(1).unwrap();
+        assert_eq!(
+            AseState::decode(&server.endpoints[0].value).unwrap(),
+            (1, AseState::Streaming { cig_id: 1, cis_id: 2 })
+        );
+        assert!(server.endpoints[0].value.ends_with(&[4, 3, 0x02, 0x04, 0x00]));
+
+        write(&mut server, AseOperation::Disable(&[1]));
+        assert_eq!(server.state(1), Some(&AseState::QosConfigured(qos())));
+        write(&mut server, AseOperation::Release(&[1]));
+        assert_eq!(server.state(1), Some(&AseState::Releasing));
+        server.release_complete(1).unwrap();
+        assert_eq!(server.state(1), Some(&AseState::Idle));
+        assert_eq!(server.endpoints[0].value, [1, 0x00]);
+
+        let responses = write(&mut server, AseOperation::Release(&[3]));
+        assert_eq!(responses[0].code, ResponseCode::INVALID_ASE_ID);
+    }
+
+    #[test]
+    fn server_rejects_malformed_operations() {
+        let mut storage = AscsStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, { ascs_attribute_count(2) }> = AttributeTable::new();
+        let mut server: AscsServer<2> = AscsServer::build(&mut table, &mut storage, 1).unwrap();
+
+        server.process(&[0x09, 1, 1], &mut Handler);
+        assert_eq!(server.response.take().unwrap(), [0x09, 0xff, 0, 0x01, 0]);
+        server.process(&[OP_RELEASE, 2, 1], &mut Handler);
+        assert_eq!(server.response.take().unwrap(), [OP_RELEASE, 0xff, 0, 0x02, 0]);
+        server.process(&[OP_RELEASE, 1, 1, 2], &mut Handler);
+        assert_eq!(server.response.take().unwrap(), [OP_RELEASE, 0xff, 0, 0x02, 0]);
+        assert!(server.endpoints.iter().all(|ep| !ep.notify));
+    }
+
+    #[test]
+    fn operations_are_notified_over_att() {
+        let mut storage = AscsStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, { ascs_attribute_count(2) }> = AttributeTable::new();
+        let mut ascs: AscsServer<2> = AscsServer::build(&mut table, &mut storage, 1).unwrap();
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+        let (control_point, control_point_cccd) = (ascs.control_point.handle, ascs.control_point.cccd_handle.unwrap());
+        let (ase, ase_cccd) = {
+            let ase = &ascs.endpoints[0].characteristic;
+            (ase.handle, ase.cccd_handle.unwrap())
+        };
+        client.subscribe(&server, control_point_cccd, 0x0001).unwrap();
+        client.subscribe(&server, ase_cccd, 0x0001).unwrap();
+        assert_eq!(client.read(&server, ase).unwrap(), [1, 0x00]);
+
+        let config = CodecConfiguration {
+            target_latency: TargetLatency::Balanced,
+            target_phy: PhyKind::Le2M,
+            codec_id: CodecId::LC3,
+            config: &[2, 0x01, 0x08],
+        };
+        let mut buf = [0; 32];
+        let len = AseOperation::ConfigCodec(&[(1, config)]).encode(&mut buf).unwrap();
+        client
+            .write_without_response(&server, control_point, &buf[..len], |_, data| {
+                ascs.process(data, &mut Handler);
+                Ok(())
+            })
+            .unwrap();
+        block_on(ascs.notify(&server, client.connection())).unwrap();
+
+        assert_eq!(
+            client.notified().unwrap(),
+            (control_point, Vec::from_slice(&[0x01, 1, 1, 0x00, 0x00]).unwrap())
+        );
+        let notified = client.notified().unwrap();
+        assert_eq!(notified.0, ase);
+        assert!(matches!(
+            AseState::decode(&notified.1).unwrap(),
+            (1, AseState::CodecConfigured { .. })
+        ));
+        assert_eq!(client.read(&server, ase).unwrap(), notified.1);
+        assert!(client.notified().is_none());
+    }
+}
@@ -0,0 +1,916 @@
+//! Values and server of the Broadcast Audio Scan Service.
+//!
+//! A scan delegator, such as a broadcast receiver, exposes a receive state for each broadcast
+//! source it may receive. A broadcast assistant adds, modifies and removes these sources by
+//! writing operations to the control point, and follows the state of each source through the
+//! notifications of its receive state. The [`BassServer`] holds the receive states of a scan
+//! delegator, leaving the decisions to a [`ScanDelegatorHandler`].
+//!
+//! The sync to the periodic advertising of a source can be handed off from the assistant with
+//! PAST, using [`past_service_data`] as the service data of the transfer.
+use bt_hci::param::{AddrKind, BdAddr};
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+
+use super::{read_u8, read_u16, read_u24, read_u32, write_u24};
+use crate::att::AttErrorCode;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::cursor::{ReadCursor, WriteCursor};
+use crate::{Address, Error};
+
+/// Operation code of Remote Scan Stopped.
+pub const OP_REMOTE_SCAN_STOPPED: u8 = 0x00;
+/// Operation code of Remote Scan Started.
+pub const OP_REMOTE_SCAN_STARTED: u8 = 0x01;
+/// Operation code of Add Source.
+pub const OP_ADD_SOURCE: u8 = 0x02;
+/// Operation code of Modify Source.
+pub const OP_MODIFY_SOURCE: u8 = 0x03;
+/// Operation code of Set Broadcast_Code.
+pub const OP_SET_BROADCAST_CODE: u8 = 0x04;
+/// Operation code of Remove Source.
+pub const OP_REMOVE_SOURCE: u8 = 0x05;
+
+/// Error of a write to the control point with an unsupported operation code.
+pub const ERROR_OPCODE_NOT_SUPPORTED: AttErrorCode = AttErrorCode::application(0x80);
+/// Error of a write to the control point with an unknown source identifier.
+pub const ERROR_INVALID_SOURCE_ID: AttErrorCode = AttErrorCode::application(0x81);
+
+/// BIS sync of a subgroup without preference for the BISes to synchronize to.
+pub const BIS_SYNC_NO_PREFERENCE: u32 = 0xffff_ffff;
+/// BIS sync state of a subgroup whose BIG could not be synchronized to.
+pub const BIS_SYNC_FAILED: u32 = 0xffff_ffff;
+
+/// Service data of a periodic advertising sync transfer of a source to a scan delegator.
+///
+/// The address of the transferred sync is that of the source.
+pub const fn past_service_data(source_id: u8) -> u16 {
+    (source_id as u16) << 8
+}
+
+/// Request to synchronize to the periodic advertising of a source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PaSync {
+    /// Do not synchronize.
+    DoNotSync,
+    /// Synchronize, with the sync transferred by the assistant.
+    SyncPastAvailable,
+    /// Synchronize by scanning for the source.
+    SyncPastNotAvailable,
+}
+
+impl PaSync {
+    fn decode(value: u8) -> Result<Self, Error> {
+        Ok(match value {
+            0x00 => Self::DoNotSync,
+            0x01 => Self::SyncPastAvailable,
+            0x02 => Self::SyncPastNotAvailable,
+            _ => return Err(Error::InvalidValue),
+        })
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::DoNotSync => 0x00,
+            Self::SyncPastAvailable => 0x01,
+            Self::SyncPastNotAvailable => 0x02,
+        }
+    }
+}
+
+/// State of the sync of a scan delegator to the periodic advertising of a source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PaSyncState {
+    /// Not synchronized.
+    NotSynchronized,
+    /// Waiting for the assistant to transfer the sync.
+    SyncInfoRequest,
+    /// Synchronized.
+    Synchronized,
+    /// Failed to synchronize.
+    Failed,
+    /// The sync cannot be transferred.
+    NoPast,
+}
+
+impl PaSyncState {
+    fn decode(value: u8) -> Result<Self, Error> {
+        Ok(match value {
+            0x00 => Self::NotSynchronized,
+            0x01 => Self::SyncInfoRequest,
+            0x02 => Self::Synchronized,
+            0x03 => Self::Failed,
+            0x04 => Self::NoPast,
+            _ => return Err(Error::InvalidValue),
+        })
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::NotSynchronized => 0x00,
+            Self::SyncInfoRequest => 0x01,
+            Self::Synchronized => 0x02,
+            Self::Failed => 0x03,
+            Self::NoPast => 0x04,
+        }
+    }
+}
+
+/// Encryption of the BIG of a source, as seen by a scan delegator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BigEncryption {
+    /// The BIG is not encrypted.
+    NotEncrypted,
+    /// The broadcast code of the BIG is needed.
+    BroadcastCodeRequired,
+    /// The BIG is being decrypted.
+    Decrypting,
+    /// The broadcast code given does not decrypt the BIG.
+    BadCode([u8; 16]),
+}
+
+/// A broadcast source, identified by its extended advertising.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BroadcastSource {
+    /// Address of the extended advertising of the source.
+    pub addr: Address,
+    /// Advertising SID of the source.
+    pub adv_sid: u8,
+    /// Broadcast identifier of the source, of 24 bits.
+    pub broadcast_id: u32,
+}
+
+impl BroadcastSource {
+    fn encode(&self, w: &mut WriteCursor<'_>) -> Result<(), Error> {
+        w.write(u8::from(self.addr.kind == AddrKind::RANDOM))?;
+        w.append(self.addr.addr.raw())?;
+        w.write(self.adv_sid)?;
+        write_u24(w, self.broadcast_id)
+    }
+
+    fn decode(r: &mut ReadCursor<'_>) -> Result<Self, Error> {
+        let kind = match read_u8(r)? {
+            0x00 => AddrKind::PUBLIC,
+            0x01 => AddrKind::RANDOM,
+            _ => return Err(Error::InvalidValue),
+        };
+        let addr = BdAddr::new(unwrap!(r.slice(6)?.try_into()));
+        Ok(Self {
+            addr: Address { kind, addr },
+            adv_sid: read_u8(r)?,
+            broadcast_id: read_u24(r)?,
+        })
+    }
+}
+
+/// A subgroup of the BISes of a source, sharing the same metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Subgroup<'d> {
+    /// Bitfield of the BISes to synchronize to, or that are synchronized to in a receive state.
+    pub bis_sync: u32,
+    /// Metadata of the subgroup, as length-type-value structures.
+    pub metadata: &'d [u8],
+}
+
+impl Subgroup<'_> {
+    /// Encode a list of subgroups, as used by the operations and receive states.
+    pub fn encode_list(subgroups: &[Subgroup<'_>], dest: &mut [u8]) -> Result<usize, Error> {
+        let mut w = WriteCursor::new(dest);
+        w.write(u8::try_from(subgroups.len()).map_err(|_| Error::InvalidValue)?)?;
+        for subgroup in subgroups {
+            w.write(subgroup.bis_sync)?;
+            w.write(u8::try_from(subgroup.metadata.len()).map_err(|_| Error::InvalidValue)?)?;
+            w.append(subgroup.metadata)?;
+        }
+        Ok(w.len())
+    }
+}
+
+/// An encoded list of subgroups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Subgroups<'d> {
+    data: &'d [u8],
+}
+
+impl<'d> Subgroups<'d> {
+    // Read a list of subgroups, checking the length of each of them.
+    fn read(r: &mut ReadCursor<'d>) -> Result<Self, Error> {
+        let mut check = r.clone();
+        let count = read_u8(&mut check)?;
+        let mut len = 1;
+        for _ in 0..count {
+            let _bis_sync = read_u32(&mut check)?;
+            let metadata = read_u8(&mut check)?;
+            check.slice(metadata as usize)?;
+            len += 5 + metadata as usize;
+        }
+        Ok(Self { data: r.slice(len)? })
+    }
+
+    /// Number of subgroups.
+    pub fn len(&self) -> usize {
+        self.data[0] as usize
+    }
+
+    /// Whether the list is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over the subgroups.
+    pub fn iter(&self) -> impl Iterator<Item = Subgroup<'d>> + use<'d> {
+        let mut r = ReadCursor::new(&self.data[1..]);
+        (0..self.len()).map(move |_| {
+            let bis_sync = unwrap!(read_u32(&mut r));
+            let len = unwrap!(read_u8(&mut r));
+            let metadata = unwrap!(r.slice(len as usize));
+            Subgroup { bis_sync, metadata }
+        })
+    }
+}
+
+/// Receive state of a source, as notified by a scan delegator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReceiveState<'d> {
+    /// Identifier of the source, chosen by the scan delegator.
+    pub source_id: u8,
+    /// The source.
+    pub source: BroadcastSource,
+    /// State of the sync to the periodic advertising of the source.
+    pub pa_sync_state: PaSyncState,
+    /// Encryption of the BIG of the source.
+    pub big_encryption: BigEncryption,
+    /// The subgroups of the source, with the BISes synchronized to.
+    pub subgroups: Subgroups<'d>,
+}
+
+impl<'d> ReceiveState<'d> {
+    /// Decode the value of a receive state characteristic, which is empty without a source.
+    pub fn decode(data: &'d [u8]) -> Result<Option<Self>, Error> {
+        if data.is_empty() {
+            return Ok(None);
+        }
+        let mut r = ReadCursor::new(data);
+        let source_id = read_u8(&mut r)?;
+        let source = BroadcastSource::decode(&mut r)?;
+        let pa_sync_state = PaSyncState::decode(read_u8(&mut r)?)?;
+        let big_encryption = match read_u8(&mut r)? {
+            0x00 => BigEncryption::NotEncrypted,
+            0x01 => BigEncryption::BroadcastCodeRequired,
+            0x02 => BigEncryption::Decrypting,
+            0x03 => BigEncryption::BadCode(unwrap!(r.slice(16)?.try_into())),
+            _ => return Err(Error::InvalidValue),
+        };
+        Ok(Some(Self {
+            source_id,
+            source,
+            pa_sync_state,
+            big_encryption,
+            subgroups: Subgroups::read(&mut r)?,
+        }))
+    }
+
+    /// Encode the value of a receive state characteristic, returning its length.
+    pub fn encode(&self, dest: &mut [u8]) -> Result<usize, Error> {
+        let mut w = WriteCursor::new(dest);
+        w.write(self.source_id)?;
+        self.source.encode(&mut w)?;
+        w.write(self.pa_sync_state.to_u8())?;
+        match &self.big_encryption {
+            BigEncryption::NotEncrypted => w.write(0x00u8)?,
+            BigEncryption::BroadcastCodeRequired => w.write(0x01u8)?,
+            BigEncryption::Decrypting => w.write(0x02u8)?,
+            BigEncryption::BadCode(code) => {
+                w.write(0x03u8)?;
+                w.append(code)?;
+            }
+        }
+        w.append(self.subgroups.data)?;
+        Ok(w.len())
+    }
+}
+
+/// An operation written by a broadcast assistant to the control point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BassOperation<'a> {
+    /// The assistant stopped scanning for sources on behalf of the delegator.
+    RemoteScanStopped,
+    /// The assistant started scanning for sources on behalf of the delegator.
+    RemoteScanStarted,
+    /// Add a source to the delegator.
+    AddSource {
+        /// The source.
+        source: BroadcastSource,
+        /// Whether to synchronize to the periodic advertising of the source.
+        pa_sync: PaSync,
+        /// Periodic advertising interval of the source in units of 1.25 ms, or 0xffff if unknown.
+        pa_interval: u16,
+        /// The subgroups, with the BISes to synchronize to.
+        subgroups: &'a [Subgroup<'a>],
+    },
+    /// Modify the sync of the delegator to a source.
+    ModifySource {
+        /// Identifier of the source.
+        source_id: u8,
+        /// Whether to synchronize to the periodic advertising of the source.
+        pa_sync: PaSync,
+        /// Periodic advertising interval of the source in units of 1.25 ms, or 0xffff if unknown.
+        pa_interval: u16,
+        /// The subgroups, with the BISes to synchronize to.
+        subgroups: &'a [Subgroup<'a>],
+    },
+    /// Give the broadcast code of the BIG of a source.
+    SetBroadcastCode {
+        /// Identifier of the source.
+        source_id: u8,
+        /// The broadcast code.
+        code: [u8; 16],
+    },
+    /// Remove a source from the delegator.
+    RemoveSource {
+        /// Identifier of the source.
+        source_id: u8,
+    },
+}
+
+impl BassOperation<'_> {
+    /// Encode the operation into the buffer, returning its length.
+    pub fn encode(&self, dest: &mut [u8]) -> Result<usize, Error> {
+        let mut w = WriteCursor::new(dest);
+        match self {
+            Self::RemoteScanStopped => w.write(OP_REMOTE_SCAN_STOPPED)?,
+            Self::RemoteScanStarted => w.write(OP_REMOTE_SCAN_STARTED)?,
+            Self::AddSource {
+                source,
+                pa_sync,
+                pa_interval,
+                subgroups,
+            } => {
+                w.write(OP_ADD_SOURCE)?;
+                source.encode(&mut w)?;
+                w.write(pa_sync.to_u8())?;
+                w.write(*pa_interval)?;
+                let len = Subgroup::encode_list(subgroups, w.write_buf())?;
+                w.commit(len)?;
+            }
+            Self::ModifySource {
+                source_id,
+                pa_sync,
+                pa_interval,
+                subgroups,
+            } => {
+                w.write(OP_MODIFY_SOURCE)?;
+                w.write(*source_id)?;
+                w.write(pa_sync.to_u8())?;
+                w.write(*pa_interval)?;
+                let len = Subgroup::encode_list(subgroups, w.write_buf())?;
+                w.commit(len)?;
+            }
+            Self::SetBroadcastCode { source_id, code } => {
+                w.write(OP_SET_BROADCAST_CODE)?;
+                w.write(*source_id)?;
+                w.append(code)?;
+            }
+            Self::RemoveSource { source_id } => {
+                w.write(OP_REMOVE_SOURCE)?;
+                w.write(*source_id)?;
+            }
+        }
+        Ok(w.len())
+    }
+}
+
+/// Request of a broadcast assistant to synchronize to a source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SourceRequest<'d> {
+    /// Whether to synchronize to the periodic advertising of the source.
+    pub pa_sync: PaSync,
+    /// Periodic advertising interval of the source in units of 1.25 ms, or 0xffff if unknown.
+    pub pa_interval: u16,
+    /// The subgroups, with the BISes to synchronize to.
+    pub subgroups: Subgroups<'d>,
+}
+
+impl<'d> SourceRequest<'d> {
+    fn read(r: &mut ReadCursor<'d>) -> Result<Self, Error> {
+        Ok(Self {
+            pa_sync: PaSync::decode(read_u8(r)?)?,
+            pa_interval: read_u16(r)?,
+            subgroups: Subgroups::read(r)?,
+        })
+    }
+}
+
+/// Handler of the operations of a broadcast assistant on a [`BassServer`], implemented by the application.
+///
+/// The operations are accepted unless overridden. Once accepted, the application synchronizes
+/// to the source as requested, and reports its progress with the setters of the server.
+pub trait ScanDelegatorHandler {
+    /// The assistant started or stopped scanning for sources on behalf of the delegator.
+    fn remote_scan(&mut self, scanning: bool) {}
+
+    /// Add a source, identified from now on by its source identifier.
+    ///
+    /// When the sync is to be transferred by the assistant, the receipt of the sync must be
+    /// prepared and the PA sync state set to [`PaSyncState::SyncInfoRequest`].
+    fn add_source(
+        &mut self,
+        source_id: u8,
+        source: &BroadcastSource,
+        request: &SourceRequest<'_>,
+    ) -> Result<(), AttErrorCode> {
+        Ok(())
+    }
+
+    /// Modify the sync to a source.
+    fn modify_source(&mut self, source_id: u8, request: &SourceRequest<'_>) -> Result<(), AttErrorCode> {
+        Ok(())
+    }
+
+    /// Receive the broadcast code of the BIG of a source.
+    fn broadcast_code(&mut self, source_id: u8, code: &[u8; 16]) {}
+
+    /// Remove a source.
+    fn remove_source(&mut self, source_id: u8) -> Result<(), AttErrorCode> {
+        Ok(())
+    }
+}
+
+/// Number of attributes of the Broadcast Audio Scan Service with a number of receive states.
+pub const fn bass_attribute_count(sources: usize) -> usize {
+    // The service, the declaration, value and CCCD of each receive state, and the declaration
+    // and value of the control point.
+    1 + 3 * sources + 2
+}
+
+/// Storage of the values of the characteristics of a [`BassServer`].
+///
+/// Each value holds up to `LEN` octets, which must fit the receive states with their metadata
+/// as well as the operations written by the assistant.
+pub struct BassStorage<const SOURCES: usize, const LEN: usize = 64> {
+    states: [[u8; LEN]; SOURCES],
+    control_point: [u8; LEN],
+}
+
+impl<const SOURCES: usize, const LEN: usize> BassStorage<SOURCES, LEN> {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            states: [[0; LEN]; SOURCES],
+            control_point: [0; LEN],
+        }
+    }
+}
+
+impl<const SOURCES: usize, const LEN: usize> Default for BassStorage<SOURCES, LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Receiving<const LEN: usize> {
+    source: BroadcastSource,
+    pa_sync_state: PaSyncState,
+    big_encryption: BigEncryption,
+    subgroups: Vec<u8, LEN>,
+}
+
+struct Slot<const LEN: usize> {
+    characteristic: Characteristic<Vec<u8, LEN>>,
+    receiving: Option<Receiving<LEN>>,
+    value: Vec<u8, LEN>,
+    notify: bool,
+}
+
+impl<const LEN: usize> Slot<LEN> {
+    fn update(&mut self, source_id: u8) -> Result<(), Error> {
+        self.value.clear();
+        if let Some(r) = &self.receiving {
+            let state = ReceiveState {
+                source_id,
+                source: r.source,
+                pa_sync_state: r.pa_sync_state,
+                big_encryption: r.big_encryption,
+                subgroups: Subgroups { data: &r.subgroups },
+            };
+            let mut buf = [0; LEN];
+            let len = state.encode(&mut buf)?;
+            self.value = unwrap!(Vec::from_slice(&buf[..len]));
+        }
+        self.notify = true;
+        Ok(())
+    }
+}
+
+/// Broadcast Audio Scan Service server, holding the receive states of a scan delegator.
+///
+/// Writes to the control point are handled with [`BassServer::process`] before being accepted,
+/// after which the changed receive states are notified with [`BassServer::notify`]. The sources
+/// are identified by the index of their receive state.
+pub struct BassServer<const SOURCES: usize, const LEN: usize = 64> {
+    control_point: Characteristic<Vec<u8, LEN>>,
+    slots: Vec<Slot<LEN>, SOURCES>,
+    scanning: bool,
+}
+
+impl<const SOURCES: usize, const LEN: usize> BassServer<SOURCES, LEN> {
+    /// Add the service to the attribute table.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut BassStorage<SOURCES, LEN>,
+    ) -> Result<Self, Error> {
+        if SOURCES > u8::MAX as usize {
+            return Err(Error::InvalidValue);
+        }
+        let BassStorage { states, control_point } = storage;
+        let mut service = table.add_service(Service::new(service::BROADCAST_AUDIO_SCAN));
+        let control_point = service
+            .add_characteristic(
+                characteristic::BROADCAST_AUDIO_SCAN_CONTROL_POINT,
+                &[CharacteristicProp::Write, CharacteristicProp::WriteWithoutResponse],
+                Vec::new(),
+                control_point,
+            )
+            .build();
+        let mut slots = Vec::new();
+        for store in states.iter_mut() {
+            let characteristic = service
+                .add_characteristic(
+                    characteristic::BROADCAST_RECEIVE_STATE,
+                    &[CharacteristicProp::Read, CharacteristicProp::Notify],
+                    Vec::new(),
+                    store,
+                )
+                .build();
+            let slot = Slot {
+                characteristic,
+                receiving: None,
+                value: Vec::new(),
+                notify: false,
+            };
+            unwrap!(slots.push(slot).ok());
+        }
+        service.build();
+        Ok(Self {
+            control_point,
+            slots,
+            scanning: false,
+        })
+    }
+
+    /// The control point, written by the assistant.
+    pub fn control_point(&self) -> &Characteristic<Vec<u8, LEN>> {
+        &self.control_point
+    }
+
+    /// Whether an assistant is scanning for sources on behalf of the delegator.
+    pub fn scanning(&self) -> bool {
+        self.scanning
+    }
+
+    /// Receive state of a source.
+    pub fn receive_state(&self, source_id: u8) -> Option<ReceiveState<'_>> {
+        let slot = self.slots.get(source_id as usize)?;
+        ReceiveState::decode(&slot.value).ok().flatten()
+    }
+
+    /// Handle an operation written to the control point, returning the error to reject the write with.
+    pub fn process(&mut self, data: &[u8], handler: &mut impl ScanDelegatorHandler) -> Result<(), AttErrorCode> {
+        let (&opcode, params) = data.split_first().ok_or(AttErrorCode::WRITE_REQUEST_REJECTED)?;
+        let mut r = ReadCursor::new(params);
+        let malformed = |_| AttErrorCode::WRITE_REQUEST_REJECTED;
+        match opcode {
+            OP_REMOTE_SCAN_STOPPED | OP_REMOTE_SCAN_STARTED => {
+                if !params.is_empty() {
+                    return Err(AttErrorCode::WRITE_REQUEST_REJECTED);
+                }
+                self.scanning = opcode == OP_REMOTE_SCAN_STARTED;
+                handler.remote_scan(self.scanning);
+            }
+            OP_ADD_SOURCE => {
+                let source = BroadcastSource::decode(&mut r).map_err(malformed)?;
+                let request = SourceRequest::read(&mut r).map_err(malformed)?;
+                if r.available() > 0 {
+                    return Err(AttErrorCode::WRITE_REQUEST_REJECTED);
+                }
+                let source_id = self
+                    .slots
+                    .iter()
+                    .position(|s| s.receiving.is_none())
+                    .ok_or(AttErrorCode::INSUFFICIENT_RESOURCES)? as u8;
+                let subgroups = Self::subgroups(&request.subgroups, None)?;
+                handler.add_source(source_id, &source, &request)?;
+                let slot = &mut self.slots[source_id as usize];
+                slot.receiving = Some(Receiving {
+                    source,
+                    pa_sync_state: PaSyncState::NotSynchronized,
+                    big_encryption: BigEncryption::NotEncrypted,
+                    subgroups,
+                });
+                slot.update(source_id)
+                    .map_err(|_| AttErrorCode::INSUFFICIENT_RESOURCES)?;
+            }
+            OP_MODIFY_SOURCE => {
+                let source_id = read_u8(&mut r).map_err(malformed)?;
+                let request = SourceRequest::read(&mut r).map_err(malformed)?;
+                if r.available() > 0 {
+                    return Err(AttErrorCode::WRITE_REQUEST_REJECTED);
+                }
+                let receiving = self.receiving(source_id)?;
+                let subgroups = Self::subgroups(&request.subgroups, Some(&receiving.subgroups))?;
+                handler.modify_source(source_id, &request)?;
+                let slot = &mut self.slots[source_id as usize];
+                unwrap!(slot.receiving.as_mut()).subgroups = subgroups;
+                slot.update(source_id)
+                    .map_err(|_| AttErrorCode::INSUFFICIENT_RESOURCES)?;
+            }
+            OP_SET_BROADCAST_CODE => {
+                let source_id = read_u8(&mut r).map_err(malformed)?;
+                let code: [u8; 16] = r
+                    .slice(16)
+                    .ok()
+                    .filter(|_| params.len() == 17)
+                    .ok_or(AttErrorCode::WRITE_REQUEST_REJECTED)
+                    .map(|c| unwrap!(c.try_into()))?;
+                self.receiving(source_id)?;
+                handler.broadcast_code(source_id, &code);
+            }
+            OP_REMOVE_SOURCE => {
+                let source_id = read_u8(&mut r).map_err(malformed)?;
+                if r.available() > 0 {
+                    return Err(AttErrorCode::WRITE_REQUEST_REJECTED);
+                }
+                self.receiving(source_id)?;
+                handler.remove_source(source_id)?;
+                let slot = &mut self.slots[source_id as usize];
+                slot.receiving = None;
+                unwrap!(slot.update(source_id).ok());
+            }
+            _ => return Err(ERROR_OPCODE_NOT_SUPPORTED),
+        }
+        Ok(())
+    }
+
+    fn receiving(&self, source_id: u8) -> Result<&Receiving<LEN>, AttErrorCode> {
+        self.slots
+            .get(source_id as usize)
+            .and_then(|s| s.receiving.as_ref())
+            .ok_or(ERROR_INVALID_SOURCE_ID)
+    }
+
+    // Turn the subgroups of a request into those of a receive state, keeping the BIS sync state
+    // of the current subgroups.
+    fn subgroups(request: &Subgroups<'_>, current: Option<&Vec<u8, LEN>>) -> Result<Vec<u8, LEN>, AttErrorCode> {
+        let current = current.map(|c| Subgroups { data: c });
+        let mut buf = [0; LEN];
+        let mut w = WriteCursor::new(&mut buf);
+        let mut write = || -> Result<usize, Error> {
+            w.write(request.len() as u8)?;
+            for (i, subgroup) in request.iter().enumerate() {
+                let bis_sync = current.and_then(|c| c.iter().nth(i)).map_or(0, |c| c.bis_sync);
+                w.write(bis_sync)?;
+                w.write(subgroup.metadata.len() as u8)?;
+                w.append(subgroup.metadata)?;
+            }
+            Ok(w.len())
+        };
+        let len = write().map_err(|_| AttErrorCode::INSUFFICIENT_RESOURCES)?;
+        Ok(unwrap!(Vec::from_slice(&buf[..len])))
+    }
+
+    fn slot(&mut self, source_id: u8) -> Result<&mut Slot<LEN>, Error> {
+        self.slots
+            .get_mut(source_id as usize)
+            .filter(|s| s.receiving.is_some())
+            .ok_or(Error::NotFound)
+    }
+
+    /// Update the state of the sync to the periodic advertising of a source.
+    pub fn set_pa_sync_state(&mut self, source_id: u8, state: PaSyncState) -> Result<(), Error> {
+        let slot = self.slot(source_id)?;
+        unwrap!(slot.receiving.as_mut()).pa_sync_state = state;
+        slot.update(source_id)
+    }
+
+    /// Update the encryption of the BIG of a source.
+    pub fn set_big_encryption(&mut self, source_id: u8, encryption: BigEncryption) -> Result<(), Error> {
+        let slot = self.slot(source_id)?;
+        unwrap!(slot.receiving.as_mut()).big_encryption = encryption;
+        slot.update(source_id)
+    }
+
+    /// Update the BISes of a subgroup of a source that are synchronized to.
+    pub fn set_bis_sync_state(&mut self, source_id: u8, subgroup: usize, bis_sync: u32) -> Result<(), Error> {
+        let slot = self.slot(source_id)?;
+        let receiving = unwrap!(slot.receiving.as_mut());
+        let mut offset = 1;
+        for _ in 0..subgroup {
+            let len = *receiving.subgroups.get(offset + 4).ok_or(Error::NotFound)?;
+            offset += 5 + len as usize;
+        }
+        let field = receiving.subgroups.get_mut(offset..offset + 4).ok_or(Error::NotFound)?;
+        field.copy_from_slice(&bis_sync.to_le_bytes());
+        slot.update(source_id)
+    }
+
+    /// Notify the assistant of the changed receive states.
+    pub async fn notify<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+    ) -> Result<(), Error> {
+        for slot in self.slots.iter_mut().filter(|s| s.notify) {
+            slot.notify = false;
+            slot.characteristic.notify(server, connection, &slot.value).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+
+    fn source() -> BroadcastSource {
+        BroadcastSource {
+            addr: Address {
+                kind: AddrKind::RANDOM,
+                addr: BdAddr::new([1, 2, 3, 4, 5, 0xc6]),
+            },
+            adv_sid: 1,
+            broadcast_id: 0x123456,
+        }
+    }
+
+    #[derive(Default)]
+    struct Handler {
+        code: Option<[u8; 16]>,
+        reject_removal: bool,
+    }
+
+    impl ScanDelegatorHandler for Handler {
+        fn broadcast_code(&mut self, _source_id: u8, code: &[u8; 16]) {
+            self.code = Some(*code);
+        }
+
+        fn remove_source(&mut self, _source_id: u8) -> Result<(), AttErrorCode> {
+            if self.reject_removal {
+                return Err(AttErrorCode::WRITE_REQUEST_REJECTED);
+            }
+            Ok(())
+        }
+    }
+
+    fn write(server: &mut BassServer<2>, handler: &mut Handler, op: BassOperation<'_>) -> Result<(), AttErrorCode> {
+        let mut buf = [0; 64];
+        let len = op.encode(&mut buf).unwrap();
+        server.process(&buf[..len], handler)
+    }
+
+    #[test]
+    fn receive_state_roundtrip() {
+        let mut subgroups = [0; 16];
+        let len = Subgroup::encode_list(
+            &[Subgroup {
+                bis_sync: 0b11,
+                metadata: &[3, 0x02, 0x04, 0x00],
+            }],
+            &mut subgroups,
+        )
+        .unwrap();
+        let state = ReceiveState {
+            source_id: 1,
+            source: source(),
+            pa_sync_state: PaSyncState::Synchronized,
+            big_encryption: BigEncryption::BadCode([7; 16]),
+            subgroups: Subgroups {
+                data: &subgroups[..len],
+            },
+        };
+        let mut buf = [0; 64];
+        let len = state.encode(&mut buf).unwrap();
+        assert_eq!(len, 14 + 16 + 10);
+        assert_eq!(&buf[..3], &[1, 1, 1]);
+        assert_eq!(ReceiveState::decode(&buf[..len]).unwrap(), Some(state));
+        assert!(ReceiveState::decode(&buf[..len - 1]).is_err());
+        assert_eq!(ReceiveState::decode(&[]).unwrap(), None);
+    }
+
+    #[test]
+    fn server_manages_sources() {
+        let mut storage = BassStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, { bass_attribute_count(2) }> = AttributeTable::new();
+        let mut server: BassServer<2> = BassServer::build(&mut table, &mut storage).unwrap();
+        let mut handler = Handler::default();
+
+        let subgroups = [Subgroup {
+            bis_sync: BIS_SYNC_NO_PREFERENCE,
+            metadata: &[3, 0x02, 0x04, 0x00],
+        }];
+        let add = BassOperation::AddSource {
+            source: source(),
+            pa_sync: PaSync::SyncPastAvailable,
+            pa_interval: 0xffff,
+            subgroups: &subgroups,
+        };
+        write(&mut server, &mut handler, add).unwrap();
+        write(&mut server, &mut handler, add).unwrap();
+        assert_eq!(
+            write(&mut server, &mut handler, add),
+            Err(AttErrorCode::INSUFFICIENT_RESOURCES)
+        );
+        assert!(server.slots.iter().all(|s| s.notify));
+
+        server.set_pa_sync_state(1, PaSyncState::SyncInfoRequest).unwrap();
+        server.set_bis_sync_state(1, 0, 0b01).unwrap();
+        let state = server.receive_state(1).unwrap();
+        assert_eq!(state.source, source());
+        assert_eq!(state.pa_sync_state, PaSyncState::SyncInfoRequest);
+        let subgroup = state.subgroups.iter().next().unwrap();
+        assert_eq!(subgroup.bis_sync, 0b01);
+        assert_eq!(subgroup.metadata, &[3, 0x02, 0x04, 0x00]);
+        assert!(matches!(server.set_bis_sync_state(1, 1, 0), Err(Error::NotFound)));
+
+        let modify = BassOperation::ModifySource {
+            source_id: 1,
+            pa_sync: PaSync::SyncPastAvailable,
+            pa_interval: 80,
+            subgroups: &[Subgroup {
+                bis_sync: 0b01,
+                metadata: &[],
+            }],
+        };
+        write(&mut server, &mut handler, modify).unwrap();
+        let subgroup = server.receive_state(1).unwrap().subgroups.iter().next().unwrap();
+        assert_eq!(subgroup.bis_sync, 0b01);
+        assert!(subgroup.metadata.is_empty());
+
+        let code = BassOperation::SetBroadcastCode {
+            source_id: 1,
+            code: [9; 16],
+        };
+        write(&mut server, &mut handler, code).unwrap();
+        assert_eq!(handler.code, Some([9; 16]));
+
+        handler.reject_removal = true;
+        let remove = BassOperation::RemoveSource { source_id: 1 };
+        assert_eq!(
+            write(&mut server, &mut handler, remove),
+            Err(AttErrorCode::WRITE_REQUEST_REJECTED)
+        );
+        handler.reject_removal = false;
+        write(&mut server, &mut handler, remove).unwrap();
+        assert!(server.receive_state(1).is_none());
+        assert!(server.slots[1].value.is_empty());
+        assert_eq!(write(&mut server, &mut handler, remove), Err(ERROR_INVALID_SOURCE_ID));
+    }
+
+    #[test]
+    fn server_rejects_malformed_operations() {
+        let mut storage = BassStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, { bass_attribute_count(2) }> = AttributeTable::new();
+        let mut server: BassServer<2> = BassServer::build(&mut table, &mut storage).unwrap();
+        let mut handler = Handler::default();
+
+        assert_eq!(server.process(&[0x06], &mut handler), Err(ERROR_OPCODE_NOT_SUPPORTED));
+        assert_eq!(
+            server.process(&[OP_REMOTE_SCAN_STARTED, 0], &mut handler),
+            Err(AttErrorCode::WRITE_REQUEST_REJECTED)
+        );
+        server.process(&[OP_REMOTE_SCAN_STARTED], &mut handler).unwrap();
+        assert!(server.scanning());
+
+        let mut buf = [0; 64];
+        let add = BassOperation::AddSource {
+            source: source(),
+            pa_sync: PaSync::DoNotSync,
+            pa_interval: 0xffff,
+            subgroups: &[Subgroup {
+                bis_sync: 0,
+                metadata: &[2, 0x01, 0x01],
+            }],
+        };
+        let len = add.encode(&mut buf).unwrap();
+        for len in [len - 1, len + 1] {
+            assert_eq!(
+                server.process(&buf[..len], &mut handler),
+                Err(AttErrorCode::WRITE_REQUEST_REJECTED)
+            );
+        }
+        assert!(server.slots.iter().all(|s| !s.notify));
+    }
+}
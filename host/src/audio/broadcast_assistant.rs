@@ -0,0 +1,223 @@
+//! Broadcast assistant, controlling the broadcast sources received by a remote scan delegator.
+//!
+//! The assistant discovers the Broadcast Audio Scan Service of a scan delegator with
+//! [`BroadcastAssistant::new`], over a [`GattClient`] whose task is running. A source found by
+//! the assistant is then handed to the delegator:
+//!
+//! 1. Add the source with [`BroadcastAssistant::add_source`], asking the delegator to
+//!    synchronize to its periodic advertising and to the BISes of its subgroups.
+//! 2. When the delegator requests the sync info of the source in its receive state, transfer
+//!    the sync with PAST, using [`past_service_data`](super::bass::past_service_data) as the
+//!    service data. For a sync of the assistant this is `PeriodicSync::transfer`, and for a
+//!    source advertised by the assistant itself `Advertiser::transfer_periodic_sync_info`.
+//! 3. When the delegator requires the broadcast code of the BIG, give it with
+//!    [`BroadcastAssistant::set_broadcast_code`].
+//! 4. Change the BISes to synchronize to with [`BroadcastAssistant::modify_source`], and remove
+//!    the source with [`BroadcastAssistant::remove_source`] once it is no longer synchronized to.
+//!
+//! The receive states notified by the delegator are followed with
+//! [`BroadcastAssistant::receive_state_change`].
+use bt_hci::uuid::{characteristic, service};
+use heapless::Vec;
+
+use super::bass::{BassOperation, BroadcastSource, PaSync, ReceiveState, Subgroup};
+use crate::attribute::Characteristic;
+use crate::gatt::{GattClient, NotificationListener};
+use crate::{BleHostError, Controller, Error};
+
+// The values of the audio characteristics have variable lengths, and are encoded by hand.
+type RawCharacteristic = Characteristic<u8>;
+
+/// A broadcast assistant, controlling the sources of a scan delegator.
+pub struct BroadcastAssistant<
+    'c,
+    'd,
+    C: Controller,
+    const MAX_SERVICES: usize,
+    const L2CAP_MTU: usize,
+    const MAX_SOURCES: usize = 4,
+> {
+    gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>,
+    control_point: RawCharacteristic,
+    receive_states: Vec<(RawCharacteristic, Option<(u8, BroadcastSource)>), MAX_SOURCES>,
+    notifications: NotificationListener<'c, L2CAP_MTU>,
+}
+
+impl<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize, const MAX_SOURCES: usize>
+    BroadcastAssistant<'c, 'd, C, MAX_SERVICES, L2CAP_MTU, MAX_SOURCES>
+{
+    /// Discover the scan service of the delegator, and subscribe to the notifications of its receive states.
+    ///
+    /// The GATT client must have room for one more service, and a free notification subscriber.
+    pub async fn new(gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>) -> Result<Self, BleHostError<C::Error>> {
+        let services = gatt.services_by_uuid(&service::BROADCAST_AUDIO_SCAN.into()).await?;
+        let bass = services.first().cloned().ok_or(Error::NotFound)?;
+        let control_point: RawCharacteristic = gatt
+            .characteristic_by_uuid(&bass, &characteristic::BROADCAST_AUDIO_SCAN_CONTROL_POINT.into())
+            .await?;
+        let characteristics: Vec<RawCharacteristic, MAX_SOURCES> = gatt
+            .characteristics_by_uuid(&bass, &characteristic::BROADCAST_RECEIVE_STATE.into())
+            .await?;
+
+        // Listen before subscribing, so that no notification is missed.
+        let notifications = gatt.notifications()?;
+        let mut receive_states = Vec::new();
+        let mut buf = [0; L2CAP_MTU];
+        for c in characteristics {
+            gatt.enable_notifications(&c, false).await?;
+            let len = gatt.read_characteristic(&c, &mut buf).await?;
+            let source = ReceiveState::decode(&buf[..len])?.map(|s| (s.source_id, s.source));
+            unwrap!(receive_states.push((c, source)).ok());
+        }
+
+        Ok(Self {
+            gatt,
+            control_point,
+            receive_states,
+            notifications,
+        })
+    }
+
+    /// Number of receive states of the delegator, which is the number of sources it can receive at once.
+    pub fn num_receive_states(&self) -> usize {
+        self.receive_states.len()
+    }
+
+    /// Read a receive state of the delegator into the buffer.
+    pub async fn read_receive_state<'b>(
+        &mut self,
+        index: usize,
+        buf: &'b mut [u8],
+    ) -> Result<Option<ReceiveState<'b>>, BleHostError<C::Error>> {
+        let (c, source) = self.receive_states.get_mut(index).ok_or(Error::NotFound)?;
+        let len = self.gatt.read_characteristic(c, buf).await?;
+        let state = ReceiveState::decode(&buf[..len])?;
+        *source = state.map(|s| (s.source_id, s.source));
+        Ok(state)
+    }
+
+    /// Tell the delegator the assistant started scanning for sources on its behalf.
+    pub async fn remote_scan_started(&mut self) -> Result<(), BleHostError<C::Error>> {
+        self.write(&BassOperation::RemoteScanStarted).await
+    }
+
+    /// Tell the delegator the assistant stopped scanning for sources on its behalf.
+    pub async fn remote_scan_stopped(&mut self) -> Result<(), BleHostError<C::Error>> {
+        self.write(&BassOperation::RemoteScanStopped).await
+    }
+
+    /// Add a source to the delegator, returning the source identifier chosen by the delegator.
+    ///
+    /// The `pa_interval` is the periodic advertising interval of the source in units of 1.25 ms,
+    /// or 0xffff if unknown.
+    pub async fn add_source(
+        &mut self,
+        source: &BroadcastSource,
+        pa_sync: PaSync,
+        pa_interval: u16,
+        subgroups: &[Subgroup<'_>],
+    ) -> Result<u8, BleHostError<C::Error>> {
+        self.write(&BassOperation::AddSource {
+            source: *source,
+            pa_sync,
+            pa_interval,
+            subgroups,
+        })
+        .await?;
+        loop {
+            if let (_, Some((source_id, added))) = self.next().await? {
+                if added == *source {
+                    return Ok(source_id);
+                }
+            }
+        }
+    }
+
+    /// Modify the sync of the delegator to a source.
+    pub async fn modify_source(
+        &mut self,
+        source_id: u8,
+        pa_sync: PaSync,
+        pa_interval: u16,
+        subgroups: &[Subgroup<'_>],
+    ) -> Result<(), BleHostError<C::Error>> {
+        self.write(&BassOperation::ModifySource {
+            source_id,
+            pa_sync,
+            pa_interval,
+            subgroups,
+        })
+        .await?;
+        while self.next().await?.1.map(|(id, _)| id) != Some(source_id) {}
+        Ok(())
+    }
+
+    /// Give the broadcast code of the BIG of a source to the delegator.
+    pub async fn set_broadcast_code(&mut self, source_id: u8, code: &[u8; 16]) -> Result<(), BleHostError<C::Error>> {
+        self.write(&BassOperation::SetBroadcastCode { source_id, code: *code })
+            .await
+    }
+
+    /// Remove a source from the delegator.
+    pub async fn remove_source(&mut self, source_id: u8) -> Result<(), BleHostError<C::Error>> {
+        let index = self
+            .receive_states
+            .iter()
+            .position(|(_, source)| source.is_some_and(|(id, _)| id == source_id))
+            .ok_or(Error::NotFound)?;
+        self.write(&BassOperation::RemoveSource { source_id }).await?;
+        while self.next().await? != (index, None) {}
+        Ok(())
+    }
+
+    /// Wait for the delegator to notify a receive state, copying it into the buffer.
+    ///
+    /// Returns the index of the receive state along with its new value, which is `None` once its
+    /// source is removed.
+    pub async fn receive_state_change<'b>(
+        &mut self,
+        buf: &'b mut [u8],
+    ) -> Result<(usize, Option<ReceiveState<'b>>), BleHostError<C::Error>> {
+        loop {
+            let n = self.notifications.next().await;
+            if let Some(index) = self.update(n.handle(), n.as_ref()) {
+                let data = buf.get_mut(..n.as_ref().len()).ok_or(Error::InsufficientSpace)?;
+                data.copy_from_slice(n.as_ref());
+                return Ok((index, ReceiveState::decode(data)?));
+            }
+        }
+    }
+
+    async fn write(&self, op: &BassOperation<'_>) -> Result<(), BleHostError<C::Error>> {
+        // The ATT header of a write request takes 3 octets of the ATT MTU.
+        let mut buf = [0; L2CAP_MTU];
+        let len = op.encode(&mut buf[..L2CAP_MTU.saturating_sub(7)])?;
+        self.gatt.write_characteristic(&self.control_point, &buf[..len]).await?;
+        Ok(())
+    }
+
+    // Wait for the next receive state notification, returning its index and source.
+    async fn next(&mut self) -> Result<(usize, Option<(u8, BroadcastSource)>), BleHostError<C::Error>> {
+        loop {
+            let n = self.notifications.next().await;
+            if let Some(index) = self.update(n.handle(), n.as_ref()) {
+                return Ok((index, self.receive_states[index].1));
+            }
+        }
+    }
+
+    // Update the source of the receive state of a characteristic, returning its index.
+    fn update(&mut self, handle: u16, data: &[u8]) -> Option<usize> {
+        let index = self.receive_states.iter().position(|(c, _)| c.handle == handle)?;
+        match ReceiveState::decode(data) {
+            Ok(state) => {
+                self.receive_states[index].1 = state.map(|s| (s.source_id, s.source));
+                Some(index)
+            }
+            Err(e) => {
+                warn!("[bass] invalid receive state {}: {:?}", index, e);
+                None
+            }
+        }
+    }
+}
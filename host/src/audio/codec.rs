@@ -0,0 +1,298 @@
+//! Framing of codec frames into isochronous SDUs.
+//!
+//! The host makes no assumption about the codec carried by an audio stream. Each SDU holds a
+//! number of blocks of codec frames, with one frame per audio channel in each block, as described
+//! by a [`FrameFormat`]. The frames themselves are produced by a [`FrameEncoder`] and consumed by
+//! a [`FrameDecoder`], implemented on top of LC3 or any other codec.
+//!
+//! An adapter to an LC3 implementation could look like this, where `lc3` stands for the codec
+//! library of the application:
+//!
+//! ```ignore
+//! struct Lc3Encoder<'a> {
+//!     encoders: [lc3::Encoder; 2],
+//!     pcm: &'a [[i16; 480]; 2],
+//! }
+//!
+//! impl FrameEncoder for Lc3Encoder<'_> {
+//!     type Error = lc3::Error;
+//!
+//!     fn encode(&mut self, channel: u8, frame: &mut [u8]) -> Result<(), Self::Error> {
+//!         let channel = channel as usize;
+//!         self.encoders[channel].encode(&self.pcm[channel], frame)
+//!     }
+//! }
+//!
+//! let format = FrameFormat::from_config(config.config)?;
+//! let mut sdu = [0; 240];
+//! let len = format.encode_sdu(&mut encoder, &mut sdu)?;
+//! channel.send_sdu::<251>(&sdu[..len], None).await?;
+//! ```
+use embassy_time::Duration;
+
+use super::{Ltv, read_u8, read_u16, read_u32};
+use crate::Error;
+use crate::cursor::{ReadCursor, WriteCursor};
+use crate::iso::{IsoPacketStatus, SduInfo};
+
+/// Type of the sampling frequency in a codec configuration.
+pub const CONFIG_SAMPLING_FREQUENCY: u8 = 0x01;
+/// Type of the frame duration in a codec configuration.
+pub const CONFIG_FRAME_DURATION: u8 = 0x02;
+/// Type of the audio channel allocation in a codec configuration.
+pub const CONFIG_AUDIO_CHANNEL_ALLOCATION: u8 = 0x03;
+/// Type of the octets per codec frame in a codec configuration.
+pub const CONFIG_OCTETS_PER_FRAME: u8 = 0x04;
+/// Type of the codec frame blocks per SDU in a codec configuration.
+pub const CONFIG_FRAME_BLOCKS_PER_SDU: u8 = 0x05;
+
+const SAMPLING_FREQUENCIES: [(u8, u32); 13] = [
+    (0x01, 8_000),
+    (0x02, 11_025),
+    (0x03, 16_000),
+    (0x04, 22_050),
+    (0x05, 24_000),
+    (0x06, 32_000),
+    (0x07, 44_100),
+    (0x08, 48_000),
+    (0x09, 88_200),
+    (0x0a, 96_000),
+    (0x0b, 176_400),
+    (0x0c, 192_000),
+    (0x0d, 384_000),
+];
+
+/// Layout of the codec frames of an audio stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FrameFormat {
+    /// Sampling frequency of the audio, in Hz.
+    pub sampling_frequency: u32,
+    /// Duration of the audio of a codec frame.
+    pub frame_duration: Duration,
+    /// Audio locations of the channels, or 0 for a single channel without location.
+    pub channel_allocation: u32,
+    /// Length of each codec frame.
+    pub octets_per_frame: u16,
+    /// Number of blocks of codec frames in an SDU.
+    pub frame_blocks_per_sdu: u8,
+}
+
+impl FrameFormat {
+    /// Decode the format from the LC3 specific configuration of a codec configuration.
+    ///
+    /// The sampling frequency, frame duration and octets per codec frame must be present, while
+    /// the channel allocation defaults to a single channel and the frame blocks to one per SDU.
+    pub fn from_config(config: &[u8]) -> Result<Self, Error> {
+        let value = |ty| Ltv::find(config, ty).map(ReadCursor::new);
+        let mut r = value(CONFIG_SAMPLING_FREQUENCY).ok_or(Error::InvalidValue)?;
+        let frequency = read_u8(&mut r)?;
+        let sampling_frequency = SAMPLING_FREQUENCIES
+            .iter()
+            .find(|(id, _)| *id == frequency)
+            .map(|(_, hz)| *hz)
+            .ok_or(Error::InvalidValue)?;
+        let mut r = value(CONFIG_FRAME_DURATION).ok_or(Error::InvalidValue)?;
+        let frame_duration = match read_u8(&mut r)? {
+            0x00 => Duration::from_micros(7_500),
+            0x01 => Duration::from_micros(10_000),
+            _ => return Err(Error::InvalidValue),
+        };
+        let mut r = value(CONFIG_OCTETS_PER_FRAME).ok_or(Error::InvalidValue)?;
+        let octets_per_frame = read_u16(&mut r)?;
+        let channel_allocation = match value(CONFIG_AUDIO_CHANNEL_ALLOCATION) {
+            Some(mut r) => read_u32(&mut r)?,
+            None => 0,
+        };
+        let frame_blocks_per_sdu = match value(CONFIG_FRAME_BLOCKS_PER_SDU) {
+            Some(mut r) => read_u8(&mut r)?,
+            None => 1,
+        };
+        Ok(Self {
+            sampling_frequency,
+            frame_duration,
+            channel_allocation,
+            octets_per_frame,
+            frame_blocks_per_sdu,
+        })
+    }
+
+    /// Encode the format as an LC3 specific configuration, returning its length.
+    pub fn to_config(&self, dest: &mut [u8]) -> Result<usize, Error> {
+        let frequency = SAMPLING_FREQUENCIES
+            .iter()
+            .find(|(_, hz)| *hz == self.sampling_frequency)
+            .map(|(id, _)| *id)
+            .ok_or(Error::InvalidValue)?;
+        let duration = match self.frame_duration.as_micros() {
+            7_500 => 0x00,
+            10_000 => 0x01,
+            _ => return Err(Error::InvalidValue),
+        };
+        let mut w = WriteCursor::new(dest);
+        w.append(&[2, CONFIG_SAMPLING_FREQUENCY, frequency])?;
+        w.append(&[2, CONFIG_FRAME_DURATION, duration])?;
+        w.append(&[5, CONFIG_AUDIO_CHANNEL_ALLOCATION])?;
+        w.write(self.channel_allocation)?;
+        w.append(&[3, CONFIG_OCTETS_PER_FRAME])?;
+        w.write(self.octets_per_frame)?;
+        w.append(&[2, CONFIG_FRAME_BLOCKS_PER_SDU, self.frame_blocks_per_sdu])?;
+        Ok(w.len())
+    }
+
+    /// Number of audio channels, each with its own codec frame in every block.
+    pub fn channels(&self) -> u8 {
+        self.channel_allocation.count_ones().max(1) as u8
+    }
+
+    /// Number of audio samples of a codec frame, for each channel.
+    pub fn samples_per_frame(&self) -> usize {
+        (self.sampling_frequency as u64 * self.frame_duration.as_micros() / 1_000_000) as usize
+    }
+
+    /// Length of an SDU.
+    pub fn sdu_len(&self) -> usize {
+        self.octets_per_frame as usize * self.channels() as usize * self.frame_blocks_per_sdu as usize
+    }
+
+    /// Interval between SDUs, to be used as SDU interval of the stream.
+    pub fn sdu_interval(&self) -> Duration {
+        self.frame_duration * self.frame_blocks_per_sdu as u32
+    }
+
+    /// Fill an SDU with codec frames from the encoder, returning its length.
+    ///
+    /// The frames are requested in order, for each channel of each block.
+    pub fn encode_sdu<E: FrameEncoder>(&self, encoder: &mut E, sdu: &mut [u8]) -> Result<usize, CodecError<E::Error>> {
+        let len = self.sdu_len();
+        let sdu = sdu.get_mut(..len).ok_or(CodecError::InvalidLength)?;
+        if self.octets_per_frame > 0 {
+            for frames in sdu.chunks_exact_mut(self.octets_per_frame as usize * self.channels() as usize) {
+                for (channel, frame) in frames.chunks_exact_mut(self.octets_per_frame as usize).enumerate() {
+                    encoder.encode(channel as u8, frame).map_err(CodecError::Codec)?;
+                }
+            }
+        }
+        Ok(len)
+    }
+
+    /// Pass the codec frames of a received SDU to the decoder.
+    ///
+    /// Frames of an SDU that was not received correctly, or that does not have the length of the
+    /// format, are passed as lost so that the decoder can conceal them.
+    pub fn decode_sdu<D: FrameDecoder>(&self, decoder: &mut D, sdu: &[u8], info: &SduInfo) -> Result<(), D::Error> {
+        let valid = info.status == IsoPacketStatus::Correct && sdu.len() == self.sdu_len();
+        let octets = self.octets_per_frame as usize;
+        for block in 0..self.frame_blocks_per_sdu as usize {
+            for channel in 0..self.channels() {
+                let start = (block * self.channels() as usize + channel as usize) * octets;
+                let frame = if valid { Some(&sdu[start..start + octets]) } else { None };
+                decoder.decode(channel, frame)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Producer of codec frames, such as an audio encoder.
+pub trait FrameEncoder {
+    /// Error of the encoder.
+    type Error;
+
+    /// Encode the next codec frame of a channel, filling the frame.
+    fn encode(&mut self, channel: u8, frame: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+/// Consumer of codec frames, such as an audio decoder.
+pub trait FrameDecoder {
+    /// Error of the decoder.
+    type Error;
+
+    /// Decode the next codec frame of a channel, or conceal it if it was lost.
+    fn decode(&mut self, channel: u8, frame: Option<&[u8]>) -> Result<(), Self::Error>;
+}
+
+/// Error framing codec frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CodecError<E> {
+    /// The buffer does not fit an SDU of the format.
+    InvalidLength,
+    /// Error of the codec.
+    Codec(E),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: [u8; 19] = [
+        2, 0x01, 0x08, 2, 0x02, 0x01, 5, 0x03, 0x03, 0, 0, 0, 3, 0x04, 100, 0, 2, 0x05, 1,
+    ];
+
+    struct Counter(u8);
+
+    impl FrameEncoder for Counter {
+        type Error = ();
+
+        fn encode(&mut self, channel: u8, frame: &mut [u8]) -> Result<(), ()> {
+            self.0 += 1;
+            frame.fill(self.0 + channel * 0x10);
+            Ok(())
+        }
+    }
+
+    struct Frames(heapless::Vec<(u8, Option<u8>), 4>);
+
+    impl FrameDecoder for Frames {
+        type Error = ();
+
+        fn decode(&mut self, channel: u8, frame: Option<&[u8]>) -> Result<(), ()> {
+            self.0.push((channel, frame.map(|f| f[0]))).map_err(|_| ())
+        }
+    }
+
+    #[test]
+    fn lc3_config_roundtrip() {
+        let format = FrameFormat::from_config(&CONFIG).unwrap();
+        assert_eq!(format.sampling_frequency, 48_000);
+        assert_eq!(format.channels(), 2);
+        assert_eq!(format.samples_per_frame(), 480);
+        assert_eq!(format.sdu_len(), 200);
+        assert_eq!(format.sdu_interval(), Duration::from_micros(10_000));
+        let mut buf = [0; 32];
+        let len = format.to_config(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &CONFIG);
+        assert!(FrameFormat::from_config(&CONFIG[3..]).is_err());
+    }
+
+    #[test]
+    fn sdu_framing() {
+        let format = FrameFormat {
+            octets_per_frame: 2,
+            ..FrameFormat::from_config(&CONFIG).unwrap()
+        };
+        let mut sdu = [0; 4];
+        assert_eq!(format.encode_sdu(&mut Counter(0), &mut sdu), Ok(4));
+        assert_eq!(sdu, [1, 1, 0x12, 0x12]);
+        assert_eq!(
+            format.encode_sdu(&mut Counter(0), &mut sdu[..3]),
+            Err(CodecError::InvalidLength)
+        );
+
+        let mut info = SduInfo {
+            len: 4,
+            sequence_number: 0,
+            timestamp: None,
+            status: IsoPacketStatus::Correct,
+        };
+        let mut frames = Frames(heapless::Vec::new());
+        format.decode_sdu(&mut frames, &sdu, &info).unwrap();
+        assert_eq!(frames.0, [(0, Some(1)), (1, Some(0x12))]);
+
+        info.status = IsoPacketStatus::PartiallyLost;
+        let mut frames = Frames(heapless::Vec::new());
+        format.decode_sdu(&mut frames, &sdu, &info).unwrap();
+        assert_eq!(frames.0, [(0, None), (1, None)]);
+    }
+}
@@ -0,0 +1,365 @@
+//! Values of the Published Audio Capabilities Service.
+//!
+//! A unicast server publishes the codecs it supports for each direction as PAC records, along
+//! with the audio locations it renders or captures and the contexts it accepts audio for. The
+//! [`PacsServer`] adds the service to the attribute table of a unicast server.
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+
+use super::{CodecId, read_u8, read_u16};
+use crate::Error;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::cursor::{ReadCursor, WriteCursor};
+
+/// A published audio capability, describing a supported codec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PacRecord<'d> {
+    /// The supported codec.
+    pub codec_id: CodecId,
+    /// Codec specific capabilities, as length-type-value structures.
+    pub capabilities: &'d [u8],
+    /// Metadata, as length-type-value structures.
+    pub metadata: &'d [u8],
+}
+
+impl<'d> PacRecord<'d> {
+    /// Decode the value of a PAC characteristic.
+    pub fn decode(data: &'d [u8]) -> Result<PacRecords<'d>, Error> {
+        let mut cursor = ReadCursor::new(data);
+        let remaining = read_u8(&mut cursor)?;
+        Ok(PacRecords { cursor, remaining })
+    }
+
+    /// Encode a list of records as the value of a PAC characteristic, returning its length.
+    pub fn encode_list(records: &[PacRecord<'_>], dest: &mut [u8]) -> Result<usize, Error> {
+        let mut w = WriteCursor::new(dest);
+        w.write(u8::try_from(records.len()).map_err(|_| Error::InvalidValue)?)?;
+        for record in records {
+            w.append(&record.codec_id.to_bytes())?;
+            for ltvs in [record.capabilities, record.metadata] {
+                w.write(u8::try_from(ltvs.len()).map_err(|_| Error::InvalidValue)?)?;
+                w.append(ltvs)?;
+            }
+        }
+        Ok(w.len())
+    }
+}
+
+/// Iterator over the PAC records of a PAC characteristic.
+pub struct PacRecords<'d> {
+    cursor: ReadCursor<'d>,
+    remaining: u8,
+}
+
+impl<'d> PacRecords<'d> {
+    fn read(&mut self) -> Result<PacRecord<'d>, Error> {
+        let codec_id = CodecId::from_bytes(unwrap!(self.cursor.slice(5)?.try_into()));
+        let len = read_u8(&mut self.cursor)?;
+        let capabilities = self.cursor.slice(len as usize)?;
+        let len = read_u8(&mut self.cursor)?;
+        let metadata = self.cursor.slice(len as usize)?;
+        Ok(PacRecord {
+            codec_id,
+            capabilities,
+            metadata,
+        })
+    }
+}
+
+impl<'d> Iterator for PacRecords<'d> {
+    type Item = Result<PacRecord<'d>, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let item = self.read();
+        if item.is_err() {
+            self.remaining = 0;
+        }
+        Some(item)
+    }
+}
+
+/// A set of audio contexts, describing the use of audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AudioContexts(u16);
+
+impl AudioContexts {
+    /// No context.
+    pub const NONE: Self = Self(0x0000);
+    /// Audio of unspecified use.
+    pub const UNSPECIFIED: Self = Self(0x0001);
+    /// Conversation between humans, such as telephony.
+    pub const CONVERSATIONAL: Self = Self(0x0002);
+    /// Media, such as music or film soundtracks.
+    pub const MEDIA: Self = Self(0x0004);
+    /// Audio of a game.
+    pub const GAME: Self = Self(0x0008);
+    /// Instructional audio, such as navigation.
+    pub const INSTRUCTIONAL: Self = Self(0x0010);
+    /// Conversation between a human and a device, such as a voice assistant.
+    pub const VOICE_ASSISTANTS: Self = Self(0x0020);
+    /// Live audio, such as from a microphone in a venue.
+    pub const LIVE: Self = Self(0x0040);
+    /// Sound effects, such as keyboard clicks.
+    pub const SOUND_EFFECTS: Self = Self(0x0080);
+    /// Notifications, such as of incoming messages.
+    pub const NOTIFICATIONS: Self = Self(0x0100);
+    /// Ringtone of an incoming call.
+    pub const RINGTONE: Self = Self(0x0200);
+    /// Alerts, such as from a timer.
+    pub const ALERTS: Self = Self(0x0400);
+    /// Emergency alarm.
+    pub const EMERGENCY_ALARM: Self = Self(0x0800);
+}
+
+bitfield_set!(AudioContexts: u16, "contexts");
+
+/// Contexts of audio a server accepts for each direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AudioContextsPerDirection {
+    /// Contexts of audio received by the server.
+    pub sink: AudioContexts,
+    /// Contexts of audio sent by the server.
+    pub source: AudioContexts,
+}
+
+impl AudioContextsPerDirection {
+    /// Decode the value of an audio contexts characteristic.
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let mut r = ReadCursor::new(data);
+        Ok(Self {
+            sink: AudioContexts(read_u16(&mut r)?),
+            source: AudioContexts(read_u16(&mut r)?),
+        })
+    }
+
+    /// Encode the value of an audio contexts characteristic.
+    pub fn to_bytes(&self) -> [u8; 4] {
+        let [s0, s1] = self.sink.0.to_le_bytes();
+        let [r0, r1] = self.source.0.to_le_bytes();
+        [s0, s1, r0, r1]
+    }
+}
+
+/// A set of audio locations, describing the placement of the audio rendered or captured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AudioLocations(u32);
+
+impl AudioLocations {
+    /// Audio not intended for a specific location.
+    pub const MONO: Self = Self(0x0000_0000);
+    /// Front left.
+    pub const FRONT_LEFT: Self = Self(0x0000_0001);
+    /// Front right.
+    pub const FRONT_RIGHT: Self = Self(0x0000_0002);
+    /// Front center.
+    pub const FRONT_CENTER: Self = Self(0x0000_0004);
+    /// Low frequency effects 1.
+    pub const LOW_FREQUENCY_EFFECTS_1: Self = Self(0x0000_0008);
+    /// Back left.
+    pub const BACK_LEFT: Self = Self(0x0000_0010);
+    /// Back right.
+    pub const BACK_RIGHT: Self = Self(0x0000_0020);
+    /// Front left of center.
+    pub const FRONT_LEFT_OF_CENTER: Self = Self(0x0000_0040);
+    /// Front right of center.
+    pub const FRONT_RIGHT_OF_CENTER: Self = Self(0x0000_0080);
+    /// Back center.
+    pub const BACK_CENTER: Self = Self(0x0000_0100);
+    /// Low frequency effects 2.
+    pub const LOW_FREQUENCY_EFFECTS_2: Self = Self(0x0000_0200);
+    /// Side left.
+    pub const SIDE_LEFT: Self = Self(0x0000_0400);
+    /// Side right.
+    pub const SIDE_RIGHT: Self = Self(0x0000_0800);
+}
+
+bitfield_set!(AudioLocations: u32, "locations");
+
+/// Number of attributes of the Published Audio Capabilities Service, with both directions.
+pub const PACS_MAX_ATTRIBUTE_COUNT: usize = 14;
+
+/// Capabilities of a unicast server in one direction.
+#[derive(Debug, Clone, Copy)]
+pub struct PacsDirection<'d> {
+    /// The PAC records, encoded with [`PacRecord::encode_list`].
+    pub records: &'d [u8],
+    /// Locations of the audio.
+    pub locations: AudioLocations,
+}
+
+/// Capabilities published by a [`PacsServer`].
+#[derive(Debug, Clone, Copy)]
+pub struct PacsConfig<'d> {
+    /// Capabilities of audio received by the server, if any.
+    pub sink: Option<PacsDirection<'d>>,
+    /// Capabilities of audio sent by the server, if any.
+    pub source: Option<PacsDirection<'d>>,
+    /// Contexts the server supports.
+    pub supported_contexts: AudioContextsPerDirection,
+    /// Contexts the server currently accepts audio for.
+    pub available_contexts: AudioContextsPerDirection,
+}
+
+/// Storage of the values of the characteristics of a [`PacsServer`].
+pub struct PacsStorage {
+    sink_locations: [u8; 4],
+    source_locations: [u8; 4],
+    supported_contexts: [u8; 4],
+    available_contexts: [u8; 4],
+}
+
+impl PacsStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            sink_locations: [0; 4],
+            source_locations: [0; 4],
+            supported_contexts: [0; 4],
+            available_contexts: [0; 4],
+        }
+    }
+}
+
+impl Default for PacsStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Published Audio Capabilities Service server.
+///
+/// The records and locations are read-only, while the available contexts are updated and
+/// notified with [`PacsServer::set_available_contexts`].
+pub struct PacsServer {
+    available_contexts: Characteristic<[u8; 4]>,
+}
+
+impl PacsServer {
+    /// Add the service to the attribute table.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut PacsStorage,
+        config: PacsConfig<'d>,
+    ) -> Self {
+        let PacsStorage {
+            sink_locations,
+            source_locations,
+            supported_contexts,
+            available_contexts,
+        } = storage;
+        let mut service = table.add_service(Service::new(service::PUBLISHED_AUDIO_CAPABILITIES));
+        for (direction, pac, location, store) in [
+            (
+                config.sink,
+                characteristic::SINK_PAC,
+                characteristic::SINK_AUDIO_LOCATIONS,
+                sink_locations,
+            ),
+            (
+                config.source,
+                characteristic::SOURCE_PAC,
+                characteristic::SOURCE_AUDIO_LOCATIONS,
+                source_locations,
+            ),
+        ] {
+            if let Some(direction) = direction {
+                *store = direction.locations.0.to_le_bytes();
+                service.add_characteristic_ro_bytes(pac, direction.records).build();
+                service.add_characteristic_ro(location, &*store).build();
+            }
+        }
+        let available_contexts = service
+            .add_characteristic(
+                characteristic::AVAILABLE_AUDIO_CONTEXTS,
+                &[CharacteristicProp::Read, CharacteristicProp::Notify],
+                config.available_contexts.to_bytes(),
+                available_contexts,
+            )
+            .build();
+        *supported_contexts = config.supported_contexts.to_bytes();
+        service
+            .add_characteristic_ro(characteristic::SUPPORTED_AUDIO_CONTEXTS, &*supported_contexts)
+            .build();
+        service.build();
+        Self { available_contexts }
+    }
+
+    /// Update the contexts the server accepts audio for, notifying the client.
+    pub async fn set_available_contexts<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        contexts: AudioContextsPerDirection,
+    ) -> Result<(), Error> {
+        self.available_contexts
+            .notify(server, connection, &contexts.to_bytes())
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_pac_records() {
+        let data = [
+            2, 0x06, 0, 0, 0, 0, 3, 2, 0x02, 0x03, 0, 0xff, 0x59, 0, 1, 0, 0, 2, 1, 0x02,
+        ];
+        let mut records = PacRecord::decode(&data).unwrap();
+        let lc3 = records.next().unwrap().unwrap();
+        assert_eq!(lc3.codec_id, CodecId::LC3);
+        assert_eq!(lc3.capabilities, &[2, 0x02, 0x03]);
+        assert!(lc3.metadata.is_empty());
+        let vendor = records.next().unwrap().unwrap();
+        assert_eq!(vendor.codec_id.company_id, 0x0059);
+        assert_eq!(vendor.metadata, &[1, 0x02]);
+        assert!(records.next().is_none());
+
+        let mut records = PacRecord::decode(&data[..8]).unwrap();
+        assert!(records.next().unwrap().is_err());
+        assert!(records.next().is_none());
+    }
+
+    #[test]
+    fn encode_pac_records() {
+        let records = [
+            PacRecord {
+                codec_id: CodecId::LC3,
+                capabilities: &[2, 0x02, 0x03],
+                metadata: &[],
+            },
+            PacRecord {
+                codec_id: CodecId {
+                    format: 0xff,
+                    company_id: 0x0059,
+                    vendor_id: 0x0001,
+                },
+                capabilities: &[],
+                metadata: &[1, 0x02],
+            },
+        ];
+        let mut buf = [0; 32];
+        let len = PacRecord::encode_list(&records, &mut buf).unwrap();
+        let decoded = PacRecord::decode(&buf[..len]).unwrap();
+        assert!(decoded.map(Result::unwrap).eq(records.iter().copied()));
+        assert!(PacRecord::encode_list(&records, &mut buf[..8]).is_err());
+    }
+
+    #[test]
+    fn audio_contexts() {
+        let contexts = AudioContextsPerDirection::decode(&[0x06, 0x00, 0x02, 0x00]).unwrap();
+        assert!(contexts.sink.contains(AudioContexts::MEDIA));
+        assert!(!contexts.source.contains(AudioContexts::MEDIA));
+        assert_eq!(contexts.to_bytes(), [0x06, 0x00, 0x02, 0x00]);
+    }
+}
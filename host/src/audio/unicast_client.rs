@@ -0,0 +1,310 @@
+//! Basic Audio Profile unicast client, driving the audio stream endpoints of a unicast server.
+//!
+//! The client discovers the Audio Stream Control Service and the Published Audio Capabilities
+//! Service of a server with [`UnicastClient::new`], over a [`GattClient`] whose task is running.
+//! A stream is then set up in the order of the ASE state machine:
+//!
+//! 1. Read the codecs the server supports with [`UnicastClient::read_pac`].
+//! 2. Configure the codec of the ASE with [`UnicastClient::config_codec`], and read the QoS
+//!    preferred by the server from its [`AseState::CodecConfigured`] state.
+//! 3. Configure the QoS of the ASE with [`UnicastClient::config_qos`], and create the CIG of the
+//!    stream with [`Central::create_cig`](crate::central::Central::create_cig), using
+//!    [`QosConfiguration::apply`] to fill in the CIG and CIS configurations.
+//! 4. Enable the ASE with [`UnicastClient::enable`], connect its CIS with
+//!    [`Central::connect_cis`](crate::central::Central::connect_cis) and set up its data path.
+//!    For a source ASE, tell the server audio can be received with
+//!    [`UnicastClient::receiver_start_ready`].
+//! 5. Stop the stream with [`UnicastClient::disable`], and release the ASE with
+//!    [`UnicastClient::release`].
+//!
+//! Each operation completes once the server has notified its outcome on the ASE control point
+//! and the new state of each ASE. The notifications are queued by the GATT client, whose
+//! notification queue must hold the notifications of an operation on all its ASEs.
+use bt_hci::uuid::{characteristic, service};
+use heapless::Vec;
+
+use super::AudioDirection;
+use super::ascs::{AseOperation, AseResponses, AseState, CodecConfiguration, QosConfiguration, ResponseCode};
+use super::pacs::{AudioContextsPerDirection, PacRecord, PacRecords};
+use crate::attribute::Characteristic;
+use crate::gatt::{GattClient, NotificationListener, ServiceHandle};
+use crate::types::uuid::Uuid;
+use crate::{BleHostError, Controller, Error};
+
+/// Maximum number of PAC characteristics of each direction.
+const MAX_PACS: usize = 2;
+
+// The values of the audio characteristics have variable lengths, and are encoded by hand.
+type RawCharacteristic = Characteristic<u8>;
+
+/// An audio stream endpoint of the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Ase {
+    handle: u16,
+    direction: AudioDirection,
+    id: u8,
+    state: AseState,
+}
+
+impl Ase {
+    /// Identifier of the ASE, chosen by the server.
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Direction of the audio of the ASE.
+    pub fn direction(&self) -> AudioDirection {
+        self.direction
+    }
+
+    /// Last state notified by the server.
+    pub fn state(&self) -> &AseState {
+        &self.state
+    }
+}
+
+/// A unicast client, controlling the audio streams of a server.
+pub struct UnicastClient<
+    'c,
+    'd,
+    C: Controller,
+    const MAX_SERVICES: usize,
+    const L2CAP_MTU: usize,
+    const MAX_ASES: usize = 4,
+> {
+    gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>,
+    sink_pacs: Vec<RawCharacteristic, MAX_PACS>,
+    source_pacs: Vec<RawCharacteristic, MAX_PACS>,
+    available_contexts: Option<RawCharacteristic>,
+    control_point: RawCharacteristic,
+    ases: Vec<Ase, MAX_ASES>,
+    notifications: NotificationListener<'c, L2CAP_MTU>,
+}
+
+impl<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize, const MAX_ASES: usize>
+    UnicastClient<'c, 'd, C, MAX_SERVICES, L2CAP_MTU, MAX_ASES>
+{
+    /// Discover the audio services of the server, and subscribe to the notifications of its ASEs.
+    ///
+    /// The GATT client must have room for two more services, and a free notification subscriber.
+    pub async fn new(gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>) -> Result<Self, BleHostError<C::Error>> {
+        let ascs = Self::service(gatt, service::AUDIO_STREAM_CONTROL.into()).await?;
+        let pacs = Self::service(gatt, service::PUBLISHED_AUDIO_CAPABILITIES.into()).await?;
+
+        let control_point: RawCharacteristic = gatt
+            .characteristic_by_uuid(&ascs, &characteristic::ASE_CONTROL_POINT.into())
+            .await?;
+        let sinks: Vec<RawCharacteristic, MAX_ASES> = gatt
+            .characteristics_by_uuid(&ascs, &characteristic::SINK_ASE.into())
+            .await?;
+        let sources: Vec<RawCharacteristic, MAX_ASES> = gatt
+            .characteristics_by_uuid(&ascs, &characteristic::SOURCE_ASE.into())
+            .await?;
+        let sink_pacs = gatt
+            .characteristics_by_uuid(&pacs, &characteristic::SINK_PAC.into())
+            .await?;
+        let source_pacs = gatt
+            .characteristics_by_uuid(&pacs, &characteristic::SOURCE_PAC.into())
+            .await?;
+        let available_contexts = gatt
+            .characteristics_by_uuid::<u8, 1>(&pacs, &characteristic::AVAILABLE_AUDIO_CONTEXTS.into())
+            .await?
+            .pop();
+
+        // Listen before subscribing, so that no notification is missed.
+        let notifications = gatt.notifications()?;
+        gatt.enable_notifications(&control_point, false).await?;
+
+        let mut ases = Vec::new();
+        let mut buf = [0; L2CAP_MTU];
+        let all = sinks
+            .iter()
+            .map(|c| (c, AudioDirection::Sink))
+            .chain(sources.iter().map(|c| (c, AudioDirection::Source)));
+        for (c, direction) in all {
+            gatt.enable_notifications(c, false).await?;
+            let len = gatt.read_characteristic(c, &mut buf).await?;
+            let (id, state) = AseState::decode(&buf[..len])?;
+            ases.push(Ase {
+                handle: c.handle,
+                direction,
+                id,
+                state,
+            })
+            .map_err(|_| Error::InsufficientSpace)?;
+        }
+
+        Ok(Self {
+            gatt,
+            sink_pacs,
+            source_pacs,
+            available_contexts,
+            control_point,
+            ases,
+            notifications,
+        })
+    }
+
+    async fn service(
+        gatt: &GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>,
+        uuid: Uuid,
+    ) -> Result<ServiceHandle, BleHostError<C::Error>> {
+        let services = gatt.services_by_uuid(&uuid).await?;
+        Ok(services.first().cloned().ok_or(Error::NotFound)?)
+    }
+
+    /// The ASEs of the server.
+    pub fn ases(&self) -> &[Ase] {
+        &self.ases
+    }
+
+    /// The ASE with an identifier.
+    pub fn ase(&self, id: u8) -> Option<&Ase> {
+        self.ases.iter().find(|a| a.id == id)
+    }
+
+    /// Number of PAC characteristics of the server for a direction.
+    pub fn num_pacs(&self, direction: AudioDirection) -> usize {
+        self.pacs(direction).len()
+    }
+
+    fn pacs(&self, direction: AudioDirection) -> &[RawCharacteristic] {
+        match direction {
+            AudioDirection::Sink => &self.sink_pacs,
+            AudioDirection::Source => &self.source_pacs,
+        }
+    }
+
+    /// Read the PAC records of a PAC characteristic of the server into the buffer.
+    ///
+    /// Values longer than the ATT MTU are truncated.
+    pub async fn read_pac<'b>(
+        &self,
+        direction: AudioDirection,
+        index: usize,
+        buf: &'b mut [u8],
+    ) -> Result<PacRecords<'b>, BleHostError<C::Error>> {
+        let c = self.pacs(direction).get(index).ok_or(Error::NotFound)?;
+        let len = self.gatt.read_characteristic(c, buf).await?;
+        Ok(PacRecord::decode(&buf[..len])?)
+    }
+
+    /// Read the contexts of audio the server currently accepts.
+    pub async fn read_available_contexts(&self) -> Result<AudioContextsPerDirection, BleHostError<C::Error>> {
+        let c = self.available_contexts.as_ref().ok_or(Error::NotFound)?;
+        let mut buf = [0; 4];
+        let len = self.gatt.read_characteristic(c, &mut buf).await?;
+        Ok(AudioContextsPerDirection::decode(&buf[..len])?)
+    }
+
+    /// Configure the codec of each ASE.
+    pub async fn config_codec(
+        &mut self,
+        configs: &[(u8, CodecConfiguration<'_>)],
+    ) -> Result<(), BleHostError<C::Error>> {
+        self.perform(AseOperation::ConfigCodec(configs)).await
+    }
+
+    /// Configure the QoS of each ASE.
+    pub async fn config_qos(&mut self, configs: &[(u8, QosConfiguration)]) -> Result<(), BleHostError<C::Error>> {
+        self.perform(AseOperation::ConfigQos(configs)).await
+    }
+
+    /// Enable each ASE with its metadata, after which its CIS is to be connected.
+    pub async fn enable(&mut self, metadata: &[(u8, &[u8])]) -> Result<(), BleHostError<C::Error>> {
+        self.perform(AseOperation::Enable(metadata)).await
+    }
+
+    /// Tell the server the client is ready to receive the audio of each source ASE.
+    pub async fn receiver_start_ready(&mut self, ase_ids: &[u8]) -> Result<(), BleHostError<C::Error>> {
+        self.perform(AseOperation::ReceiverStartReady(ase_ids)).await
+    }
+
+    /// Stop the audio of each ASE.
+    pub async fn disable(&mut self, ase_ids: &[u8]) -> Result<(), BleHostError<C::Error>> {
+        self.perform(AseOperation::Disable(ase_ids)).await
+    }
+
+    /// Tell the server the client stopped receiving the audio of each source ASE.
+    pub async fn receiver_stop_ready(&mut self, ase_ids: &[u8]) -> Result<(), BleHostError<C::Error>> {
+        self.perform(AseOperation::ReceiverStopReady(ase_ids)).await
+    }
+
+    /// Update the metadata of each enabled ASE.
+    pub async fn update_metadata(&mut self, metadata: &[(u8, &[u8])]) -> Result<(), BleHostError<C::Error>> {
+        self.perform(AseOperation::UpdateMetadata(metadata)).await
+    }
+
+    /// Release each ASE, after which its CIS is to be disconnected.
+    pub async fn release(&mut self, ase_ids: &[u8]) -> Result<(), BleHostError<C::Error>> {
+        self.perform(AseOperation::Release(ase_ids)).await
+    }
+
+    /// Wait for the server to change the state of an ASE on its own, returning the identifier of the ASE.
+    ///
+    /// Such as a sink ASE starting to stream once its CIS is connected, or any ASE being released
+    /// when its CIS is lost.
+    pub async fn state_change(&mut self) -> u8 {
+        loop {
+            let n = self.notifications.next().await;
+            if let Some(id) = self.update(n.handle(), n.as_ref()) {
+                return id;
+            }
+        }
+    }
+
+    // Write an operation, and wait for its responses and the new states of its ASEs.
+    async fn perform(&mut self, op: AseOperation<'_>) -> Result<(), BleHostError<C::Error>> {
+        let mut pending: Vec<u8, MAX_ASES> = Vec::new();
+        for i in 0..op.num_ases() {
+            let id = unwrap!(op.ase_id(i));
+            if self.ase(id).is_none() || pending.contains(&id) {
+                return Err(Error::InvalidValue.into());
+            }
+            unwrap!(pending.push(id));
+        }
+
+        // The ATT header of a write request takes 3 octets of the ATT MTU.
+        let mut buf = [0; L2CAP_MTU];
+        let len = op.encode(&mut buf[..L2CAP_MTU.saturating_sub(7)])?;
+        self.gatt.write_characteristic(&self.control_point, &buf[..len]).await?;
+
+        let mut responded = false;
+        while !responded || !pending.is_empty() {
+            let n = self.notifications.next().await;
+            if n.handle() == self.control_point.handle {
+                let responses = AseResponses::decode(n.as_ref())?;
+                if responses.opcode != op.opcode() {
+                    continue;
+                }
+                responded = true;
+                for response in responses {
+                    let response = response?;
+                    if response.code != ResponseCode::SUCCESS {
+                        return Err(Error::Ascs(response).into());
+                    }
+                }
+            } else if let Some(id) = self.update(n.handle(), n.as_ref()) {
+                pending.retain(|p| *p != id);
+            }
+        }
+        Ok(())
+    }
+
+    // Update the state of the ASE of a characteristic, returning its identifier.
+    fn update(&mut self, handle: u16, data: &[u8]) -> Option<u8> {
+        let ase = self.ases.iter_mut().find(|a| a.handle == handle)?;
+        match AseState::decode(data) {
+            Ok((id, state)) => {
+                ase.id = id;
+                ase.state = state;
+                Some(id)
+            }
+            Err(e) => {
+                warn!("[bap] invalid state of ASE {}: {:?}", ase.id, e);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+#![macro_use]
+#![allow(unused_macros)]
+
+/// Implement the methods of a set kept as a bitfield, for a tuple struct around an integer.
+///
+/// The members of the set are either sets themselves, named by their constants, or the variants
+/// of a fieldless enum giving the position of their bit.
+macro_rules! bitfield_set {
+    ($set:ident: $bits:ty, $members:literal) => {
+        impl $set {
+            /// Create a set from its bitfield.
+            pub const fn new(bits: $bits) -> Self {
+                Self(bits)
+            }
+
+            /// Bitfield of the set.
+            pub const fn bits(self) -> $bits {
+                self.0
+            }
+
+            #[doc = concat!("Whether the set contains all ", $members, " of another set.")]
+            pub const fn contains(self, other: Self) -> bool {
+                self.0 & other.0 == other.0
+            }
+
+            /// Union of two sets.
+            pub const fn union(self, other: Self) -> Self {
+                Self(self.0 | other.0)
+            }
+        }
+    };
+    ($set:ident: $bits:ty, $member:ty, $member_name:literal) => {
+        impl $set {
+            /// Create a set from its bitfield.
+            pub const fn new(bits: $bits) -> Self {
+                Self(bits)
+            }
+
+            /// Bitfield of the set.
+            pub const fn bits(self) -> $bits {
+                self.0
+            }
+
+            #[doc = concat!("Whether the set contains the ", $member_name, ".")]
+            pub const fn contains(self, member: $member) -> bool {
+                self.0 & (1 << member as u8) != 0
+            }
+
+            /// Union of two sets.
+            pub const fn union(self, other: Self) -> Self {
+                Self(self.0 | other.0)
+            }
+        }
+    };
+}
@@ -0,0 +1,459 @@
+//! Capture of the HCI packets exchanged with the controller, in the btsnoop format read by
+//! Wireshark.
+//!
+//! A [`PacketCapture`] set with [`Stack::set_packet_capture`](crate::Stack::set_packet_capture)
+//! records the packets as the runners exchange them with the controller, whatever its
+//! implementation: the commands sent by the host, the data sent on the links and everything read
+//! by the receive runner. The controller gives the host the packets it reads parsed, so they are
+//! encoded again for the capture. The Command Complete and Command Status events are consumed by
+//! the controller to complete the commands, the host never seeing them, and are missing from the
+//! capture.
+//!
+//! [`BtSnoop`] writes the packets with their direction and the time they were sent or received to
+//! a sink such as an RTT channel, a UART or a file in flash, starting with the header of the
+//! format. The sink is written from the runners, and should not block for long. A record the sink
+//! fails to take is dropped and counted in the following records, the capture going on.
+//!
+//! The timestamps are the time since boot shown as a date from the Unix epoch, the device having
+//! no wall clock.
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+use bt_hci::event::le::{LeEvent, LeEventParams};
+use bt_hci::event::{Event, EventParams};
+use bt_hci::{ControllerToHostPacket, HostToControllerPacket, PacketKind, WriteHci};
+use embassy_time::Instant;
+
+const HEADER: [u8; 16] = *b"btsnoop\0\x00\x00\x00\x01\x00\x00\x03\xea";
+// Microseconds from year 0 to the Unix epoch, the origin of the btsnoop timestamps.
+const EPOCH_OFFSET: u64 = 0x00e0_3ab4_4a67_6000;
+
+// Octets of a record before the packet: lengths, flags, drops, timestamp and packet indicator.
+const RECORD_HEADER_LEN: usize = 25;
+
+const FLAG_RECEIVED: u32 = 0x01;
+const FLAG_COMMAND_OR_EVENT: u32 = 0x02;
+
+// Octets of a packet given to a capture, longer packets being truncated.
+const MAX_PACKET_LEN: usize = 259;
+
+/// Direction of a captured packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    /// Sent to the controller.
+    Sent,
+    /// Received from the controller.
+    Received,
+}
+
+/// Recorder of the HCI packets exchanged with the controller.
+pub trait PacketCapture {
+    /// Record a packet sent to or received from the controller.
+    ///
+    /// `len` is the length of the packet without its packet indicator, and `data` its first octets,
+    /// the whole packet unless it is longer than 259 octets.
+    fn record(&self, kind: PacketKind, direction: Direction, len: usize, data: &[u8]);
+}
+
+struct Capture<S> {
+    sink: S,
+    started: bool,
+    drops: u32,
+}
+
+/// Capture of the HCI packets to a btsnoop sink.
+///
+/// The packets are recorded up to `SNAPLEN` octets, longer ones being truncated in the capture.
+pub struct BtSnoop<S, const SNAPLEN: usize = 259> {
+    capture: RefCell<Capture<S>>,
+}
+
+impl<S: embedded_io::Write, const SNAPLEN: usize> BtSnoop<S, SNAPLEN> {
+    /// Capture the packets to the sink.
+    pub fn new(sink: S) -> Self {
+        Self {
+            capture: RefCell::new(Capture {
+                sink,
+                started: false,
+                drops: 0,
+            }),
+        }
+    }
+
+    /// Number of records the sink failed to take.
+    pub fn drops(&self) -> u32 {
+        self.capture.borrow().drops
+    }
+}
+
+impl<S: embedded_io::Write, const SNAPLEN: usize> PacketCapture for BtSnoop<S, SNAPLEN> {
+    fn record(&self, kind: PacketKind, direction: Direction, len: usize, data: &[u8]) {
+        let data = &data[..data.len().min(SNAPLEN).min(MAX_PACKET_LEN)];
+        let mut flags = match direction {
+            Direction::Sent => 0,
+            Direction::Received => FLAG_RECEIVED,
+        };
+        if matches!(kind, PacketKind::Cmd | PacketKind::Event) {
+            flags |= FLAG_COMMAND_OR_EVENT;
+        }
+        let timestamp = EPOCH_OFFSET + Instant::now().as_micros();
+        let mut capture = self.capture.borrow_mut();
+        let Capture { sink, started, drops } = &mut *capture;
+
+        // The record is written at once, so that a failed write does not leave part of it.
+        let mut buf = [0; HEADER.len() + RECORD_HEADER_LEN + MAX_PACKET_LEN];
+        let start = if *started { HEADER.len() } else { 0 };
+        buf[..HEADER.len()].copy_from_slice(&HEADER);
+        let record = &mut buf[HEADER.len()..];
+        record[..4].copy_from_slice(&(len as u32 + 1).to_be_bytes());
+        record[4..8].copy_from_slice(&(data.len() as u32 + 1).to_be_bytes());
+        record[8..12].copy_from_slice(&flags.to_be_bytes());
+        record[12..16].copy_from_slice(&drops.to_be_bytes());
+        record[16..24].copy_from_slice(&timestamp.to_be_bytes());
+        record[24] = kind as u8;
+        record[RECORD_HEADER_LEN..][..data.len()].copy_from_slice(data);
+
+        let end = HEADER.len() + RECORD_HEADER_LEN + data.len();
+        match sink.write_all(&buf[start..end]) {
+            Ok(()) => *started = true,
+            Err(_) => *drops = drops.wrapping_add(1),
+        }
+    }
+}
+
+/// Record a packet sent to the controller.
+pub(crate) fn record_sent<P: HostToControllerPacket>(capture: Option<&dyn PacketCapture>, packet: &P) {
+    if let Some(capture) = capture {
+        let mut encoder = Encoder::new();
+        encoder.put(packet);
+        capture.record(P::KIND, Direction::Sent, encoder.len, encoder.data());
+    }
+}
+
+/// Record a packet received from the controller, encoding it again.
+pub(crate) fn record_received(capture: Option<&dyn PacketCapture>, packet: &ControllerToHostPacket<'_>) {
+    let Some(capture) = capture else {
+        return;
+    };
+    let mut encoder = Encoder::new();
+    let encoded = match packet {
+        ControllerToHostPacket::Acl(acl) => {
+            encoder.put(acl);
+            true
+        }
+        ControllerToHostPacket::Sync(sync) => {
+            encoder.put(sync);
+            true
+        }
+        ControllerToHostPacket::Iso(iso) => {
+            encoder.put(iso);
+            true
+        }
+        ControllerToHostPacket::Event(event) => encoder.event(event),
+    };
+    if encoded {
+        capture.record(packet.kind(), Direction::Received, encoder.len, encoder.data());
+    } else {
+        warn!("[host] event not known to the packet capture");
+    }
+}
+
+/// Buffer a packet is encoded to, truncating it while counting its length.
+struct Encoder {
+    buf: [u8; MAX_PACKET_LEN],
+    len: usize,
+}
+
+impl embedded_io::ErrorType for Encoder {
+    type Error = Infallible;
+}
+
+impl embedded_io::Write for Encoder {
+    fn write(&mut self, data: &[u8]) -> Result<usize, Self::Error> {
+        let start = self.len.min(MAX_PACKET_LEN);
+        let n = data.len().min(MAX_PACKET_LEN - start);
+        self.buf[start..start + n].copy_from_slice(&data[..n]);
+        self.len += data.len();
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+// Encode the parameters of events, listing the fields of each in the order of the packet.
+macro_rules! encode_events {
+    (
+        $encoder:ident, $event:expr;
+        $($name:ident { $($field:ident),* })+;
+        $($le_name:ident { $($le_field:ident),* })+
+    ) => {
+        match $event {
+            $(
+                Event::$name(e) => {
+                    $encoder.put(&<bt_hci::event::$name as EventParams>::EVENT_CODE);
+                    $encoder.put(&0u8);
+                    $($encoder.put(&e.$field);)*
+                }
+            )+
+            Event::Le(event) => {
+                $encoder.put(&0x3eu8);
+                $encoder.put(&0u8);
+                match event {
+                    $(
+                        LeEvent::$le_name(e) => {
+                            $encoder.put(&<bt_hci::event::le::$le_name as LeEventParams>::SUBEVENT_CODE);
+                            $($encoder.put(&e.$le_field);)*
+                        }
+                    )+
+                    _ => return false,
+                }
+            }
+            Event::Unknown { code, params } => {
+                $encoder.put(code);
+                $encoder.put(&0u8);
+                $encoder.put_bytes(params);
+            }
+            _ => return false,
+        }
+    };
+}
+
+impl Encoder {
+    fn new() -> Self {
+        Self {
+            buf: [0; MAX_PACKET_LEN],
+            len: 0,
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.buf[..self.len.min(MAX_PACKET_LEN)]
+    }
+
+    fn put(&mut self, value: &impl WriteHci) {
+        let Ok(()) = value.write_hci(&mut *self);
+    }
+
+    fn put_bytes(&mut self, bytes: &[u8]) {
+        let Ok(()) = embedded_io::Write::write_all(self, bytes);
+    }
+
+    /// Encode an event, returning false if its fields are not known.
+    fn event(&mut self, event: &Event<'_>) -> bool {
+        encode_events! {
+            self, event;
+            DisconnectionComplete { status, handle, reason }
+            EncryptionChangeV1 { status, handle, enabled }
+            EncryptionChangeV2 { status, handle, encryption_enabled, encryption_key_size }
+            ReadRemoteVersionInformationComplete { status, handle, version, company_id, subversion }
+            CommandComplete { num_hci_cmd_pkts, cmd_opcode, status, return_param_bytes }
+            CommandStatus { status, num_hci_cmd_pkts, cmd_opcode }
+            HardwareError { hardware_code }
+            NumberOfCompletedPackets { completed_packets }
+            DataBufferOverflow { link_type }
+            EncryptionKeyRefreshComplete { status, handle }
+            AuthenticatedPayloadTimeoutExpired { handle }
+            Vendor { params };
+            LeConnectionComplete {
+                status, handle, role, peer_addr_kind, peer_addr, conn_interval, peripheral_latency,
+                supervision_timeout, central_clock_accuracy
+            }
+            LeAdvertisingReport { reports }
+            LeConnectionUpdateComplete { status, handle, conn_interval, peripheral_latency, supervision_timeout }
+            LeReadRemoteFeaturesComplete { status, handle, le_features }
+            LeLongTermKeyRequest { handle, random_number, encrypted_diversifier }
+            LeRemoteConnectionParameterRequest { handle, interval_min, interval_max, max_latency, timeout }
+            LeDataLengthChange { handle, max_tx_octets, max_tx_time, max_rx_octets, max_rx_time }
+            LeReadLocalP256PublicKeyComplete { status, key_x_coordinate, key_y_coordinate }
+            LeGenerateDhkeyComplete { status, dh_key }
+            LeEnhancedConnectionComplete {
+                status, handle, role, peer_addr_kind, peer_addr, local_resolvable_private_addr,
+                peer_resolvable_private_addr, conn_interval, peripheral_latency, supervision_timeout,
+                central_clock_accuracy
+            }
+            LeDirectedAdvertisingReport { reports }
+            LePhyUpdateComplete { status, handle, tx_phy, rx_phy }
+            LeExtendedAdvertisingReport { reports }
+            LePeriodicAdvertisingSyncEstablished {
+                status, sync_handle, adv_sid, adv_addr_kind, adv_addr, adv_phy, periodic_adv_interval,
+                adv_clock_accuracy
+            }
+            LePeriodicAdvertisingReport { sync_handle, tx_power, rssi, cte_kind, data_status, data }
+            LePeriodicAdvertisingSyncLost { sync_handle }
+            LeScanTimeout {}
+            LeAdvertisingSetTerminated { status, adv_handle, handle, num_completed_ext_adv_evts }
+            LeScanRequestReceived { adv_handle, scanner_addr_kind, scanner_addr }
+            LeChannelSelectionAlgorithm { handle, channel_selection_algorithm }
+            LeConnectionlessIqReport {
+                sync_handle, channel_index, rssi, rssi_antenna_id, cte_kind, slot_durations, packet_status,
+                periodic_event_counter, iq_samples
+            }
+            LeConnectionIqReport {
+                handle, rx_phy, data_channel_index, rssi, rssi_antenna_id, cte_kind, slot_durations,
+                packet_status, connection_event_counter, iq_samples
+            }
+            LeCteRequestFailed { status, handle }
+            LePeriodicAdvertisingSyncTransferReceived {
+                status, handle, service_data, sync_handle, adv_sid, adv_addr_kind, adv_addr, adv_phy,
+                periodic_adv_interval, adv_clock_accuracy
+            }
+            LeCisEstablished {
+                status, handle, cig_sync_delay, cis_sync_delay, transport_latency_c_to_p,
+                transport_latency_p_to_c, phy_c_to_p, phy_p_to_c, nse, bn_c_to_p, bn_p_to_c, ft_c_to_p,
+                ft_p_to_c, max_pdu_c_to_p, max_pdu_p_to_c, iso_interval
+            }
+            LeCisRequest { acl_handle, cis_handle, cig_id, cis_id }
+            LeCreateBigComplete {
+                status, big_handle, big_sync_delay, transport_latency_big, phy, nse, bn, pto, irc, max_pdu,
+                iso_interval, bis_handles
+            }
+            LeTerminateBigComplete { big_handle, reason }
+            LeBigSyncEstablished {
+                status, big_handle, transport_latency_big, nse, bn, pto, irc, max_pdu, iso_interval, bis_handles
+            }
+            LeBigSyncLost { big_handle, reason }
+            LeRequestPeerScaComplete { status, handle, peer_clock_accuracy }
+            LePathLossThreshold { handle, current_path_loss, zone_entered }
+            LeTransmitPowerReporting { status, handle, reason, phy, tx_power_level, tx_power_level_flag, delta }
+            LeBiginfoAdvertisingReport {
+                sync_handle, num_bis, nse, iso_interval, bn, pto, irc, max_pdu, sdu_interval, max_sdu, phy,
+                is_framed, is_encrypted
+            }
+            LeSubrateChange {
+                status, handle, subrate_factor, peripheral_latency, continuation_number, supervision_timeout
+            }
+        }
+        // The length of the parameters, following the event code.
+        self.buf[1] = (self.len - 2) as u8;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use bt_hci::cmd::controller_baseband::Reset;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Sink(heapless::Vec<u8, 128>);
+
+    impl embedded_io::ErrorType for Sink {
+        type Error = Infallible;
+    }
+
+    impl embedded_io::Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            let n = buf.len().min(self.0.capacity() - self.0.len());
+            self.0.extend_from_slice(&buf[..n]).unwrap();
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct LastPacket(RefCell<(usize, heapless::Vec<u8, MAX_PACKET_LEN>)>);
+
+    impl PacketCapture for LastPacket {
+        fn record(&self, kind: PacketKind, direction: Direction, len: usize, data: &[u8]) {
+            let mut last = self.0.borrow_mut();
+            last.0 = len;
+            last.1.clear();
+            last.1.extend_from_slice(data).unwrap();
+        }
+    }
+
+    fn received(kind: PacketKind, data: &[u8]) -> heapless::Vec<u8, MAX_PACKET_LEN> {
+        let (packet, _) = ControllerToHostPacket::from_hci_bytes_with_kind(kind, data).unwrap();
+        let capture = LastPacket::default();
+        record_received(Some(&capture), &packet);
+        let (len, data) = capture.0.take();
+        assert_eq!(len, data.len());
+        data
+    }
+
+    #[test]
+    fn records_both_directions() {
+        let snoop: BtSnoop<Sink> = BtSnoop::new(Sink::default());
+        record_sent(Some(&snoop), &Reset::new());
+        let event = [0x05, 0x04, 0x00, 0x40, 0x00, 0x13];
+        let (packet, _) = ControllerToHostPacket::from_hci_bytes_with_kind(PacketKind::Event, &event).unwrap();
+        record_received(Some(&snoop), &packet);
+
+        let capture = snoop.capture.borrow();
+        let bytes = &capture.sink.0;
+        assert_eq!(&bytes[..16], &HEADER);
+        // The command, sent.
+        let record = &bytes[16..];
+        assert_eq!(&record[..12], &[0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 2]);
+        assert_eq!(&record[24..28], &[0x01, 0x03, 0x0c, 0x00]);
+        // The event, received.
+        let record = &record[28..];
+        assert_eq!(&record[..12], &[0, 0, 0, 7, 0, 0, 0, 7, 0, 0, 0, 3]);
+        assert_eq!(&record[24..], &[0x04, 0x05, 0x04, 0x00, 0x40, 0x00, 0x13]);
+        assert_eq!(capture.drops, 0);
+    }
+
+    // A sink taking whole writes only, failing writes which do not fit.
+    #[derive(Default)]
+    struct WholeSink(heapless::Vec<u8, 70>);
+
+    impl embedded_io::ErrorType for WholeSink {
+        type Error = embedded_io::ErrorKind;
+    }
+
+    impl embedded_io::Write for WholeSink {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.0
+                .extend_from_slice(buf)
+                .map_err(|_| embedded_io::ErrorKind::OutOfMemory)?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_records_are_dropped_whole() {
+        let snoop: BtSnoop<WholeSink> = BtSnoop::new(WholeSink::default());
+        record_sent(Some(&snoop), &Reset::new());
+        // The second record only fits without its packet.
+        record_sent(Some(&snoop), &Reset::new());
+
+        let capture = snoop.capture.borrow();
+        assert_eq!(capture.sink.0.len(), HEADER.len() + RECORD_HEADER_LEN + 3);
+        assert_eq!(capture.drops, 1);
+    }
+
+    #[test]
+    fn encodes_received_packets_again() {
+        let packets: [(PacketKind, &[u8]); 4] = [
+            // LE Connection Update Complete.
+            (
+                PacketKind::Event,
+                &[0x3e, 0x0a, 0x03, 0x00, 0x40, 0x00, 0x18, 0x00, 0x00, 0x00, 0xc8, 0x00],
+            ),
+            // Number Of Completed Packets.
+            (PacketKind::Event, &[0x13, 0x05, 0x01, 0x40, 0x00, 0x02, 0x00]),
+            // Vendor event.
+            (PacketKind::Event, &[0xff, 0x03, 0x01, 0x02, 0x03]),
+            (
+                PacketKind::AclData,
+                &[0x40, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x0a],
+            ),
+        ];
+        for (kind, data) in packets {
+            assert_eq!(&received(kind, data)[..], data);
+        }
+    }
+}
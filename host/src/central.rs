@@ -2,10 +2,25 @@
 use bt_hci::cmd::le::{LeAddDeviceToFilterAcceptList, LeClearFilterAcceptList, LeCreateConn, LeExtCreateConn};
 use bt_hci::controller::{Controller, ControllerCmdAsync, ControllerCmdSync};
 use bt_hci::param::{AddrKind, BdAddr, InitiatingPhy, LeConnRole, PhyParams};
-use embassy_futures::select::{Either, select};
+use embassy_futures::select::{Either3, select3};
+use embassy_time::{Duration, Timer};
 
-use crate::connection::{ConnectConfig, Connection, PhySet};
-use crate::{BleHostError, Error, Stack};
+use crate::connection::{ConnectConfig, Connection, PhySet, SubrateParams};
+use crate::hci::LeSetDefaultSubrate;
+use crate::procedure::{Procedure, Span};
+use crate::{Address, BleHostError, Error, Stack};
+
+// Filter accept list to connect with.
+enum AcceptList<'a> {
+    // Keep the list in the controller.
+    Current,
+    // Replace the list.
+    Replace(&'a [(AddrKind, &'a BdAddr)]),
+    // Replace the list with the known peers that are not connected.
+    Known(&'a [Address]),
+    // Replace the list with the peers.
+    Any(&'a [Address]),
+}
 
 /// A type implementing the BLE central role.
 pub struct Central<'stack, C> {
@@ -18,24 +33,159 @@ impl<'stack, C: Controller> Central<'stack, C> {
     }
 
     /// Attempt to create a connection with the provided config.
+    ///
+    /// The attempt fails with `Error::Timeout` if no connection is established within the scan
+    /// timeout of the config, or waits indefinitely if the timeout is zero. Dropping the returned
+    /// future cancels the connection attempt.
+    ///
+    /// Connecting while scanning is supported if the controller allows it, unless the scan filters
+    /// using the filter accept list and the config would replace it, which returns
+    /// `Error::InvalidState`.
     pub async fn connect(&mut self, config: &ConnectConfig<'_>) -> Result<Connection<'stack>, BleHostError<C::Error>>
     where
         C: ControllerCmdSync<LeClearFilterAcceptList>
             + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
             + ControllerCmdAsync<LeCreateConn>,
     {
-        if config.scan_config.filter_accept_list.is_empty() {
+        if config.scan_config.filter_accept_list.is_empty() && !config.scan_config.use_filter_accept_list {
+            return Err(Error::InvalidValue.into());
+        }
+        let accept_list = if config.scan_config.use_filter_accept_list {
+            AcceptList::Current
+        } else {
+            AcceptList::Replace(config.scan_config.filter_accept_list)
+        };
+        self.create_connection(config, accept_list).await
+    }
+
+    /// Connect to the first of the known peers that is seen advertising.
+    ///
+    /// The controller scans in the background and connects as soon as one of `peers` that is not
+    /// already connected appears, so calling this in a loop keeps a collector connected to all of
+    /// its peers. The peers are identity addresses, such as those of the bonds of
+    /// [`Stack::bonds`](crate::Stack::bonds), see [`Central::connect_bonded`]. Peers advertising
+    /// with a resolvable private address are only recognized when the controller resolves it,
+    /// which requires their identity resolving key in the resolving list, see
+    /// `Peripheral::enable_privacy`.
+    ///
+    /// The filter accept list is replaced by the peers that are not connected, and the filter
+    /// accept list of the scan config is ignored. Returns `Error::InvalidState` if all peers are
+    /// already connected.
+    pub async fn connect_known(
+        &mut self,
+        peers: &[Address],
+        config: &ConnectConfig<'_>,
+    ) -> Result<Connection<'stack>, BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeClearFilterAcceptList>
+            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
+            + ControllerCmdAsync<LeCreateConn>,
+    {
+        self.create_connection(config, AcceptList::Known(peers)).await
+    }
+
+    /// Connect to whichever peer of `accept_list` is seen advertising first.
+    ///
+    /// The filter accept list is replaced by `accept_list`, and the controller initiates with the
+    /// filter accept list policy: it scans and connects on its own, without the host matching the
+    /// advertisements. The filter accept list of the scan config is ignored.
+    ///
+    /// Returns `Error::InvalidValue` if `accept_list` is empty.
+    pub async fn connect_any(
+        &mut self,
+        accept_list: &[Address],
+        config: &ConnectConfig<'_>,
+    ) -> Result<Connection<'stack>, BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeClearFilterAcceptList>
+            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
+            + ControllerCmdAsync<LeCreateConn>,
+    {
+        if accept_list.is_empty() {
             return Err(Error::InvalidValue.into());
         }
+        self.create_connection(config, AcceptList::Any(accept_list)).await
+    }
+
+    /// Connect to the first of the bonded peers that is seen advertising.
+    ///
+    /// Like [`Central::connect_known`], with the identity addresses of the bonds of the security
+    /// manager as the known peers. Returns `Error::InvalidState` if there are no bonds, or if all
+    /// bonded peers are already connected.
+    #[cfg(feature = "security")]
+    pub async fn connect_bonded(
+        &mut self,
+        config: &ConnectConfig<'_>,
+    ) -> Result<Connection<'stack>, BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeClearFilterAcceptList>
+            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
+            + ControllerCmdAsync<LeCreateConn>,
+    {
+        let peers: heapless::Vec<Address, { crate::config::BOND_TABLE_SIZE }> = self
+            .stack
+            .host
+            .security
+            .bonds()
+            .iter()
+            .map(|bond| bond.identity)
+            .collect();
+        self.connect_known(&peers, config).await
+    }
 
+    // Sets up the filter accept list and connects, holding the connect command state throughout
+    // so that the list cannot be replaced in between.
+    async fn create_connection(
+        &mut self,
+        config: &ConnectConfig<'_>,
+        accept_list: AcceptList<'_>,
+    ) -> Result<Connection<'stack>, BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeClearFilterAcceptList>
+            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
+            + ControllerCmdAsync<LeCreateConn>,
+    {
+        let _span = Span::begin(Procedure::Connect, None);
         let host = &self.stack.host;
-        let _drop = crate::host::OnDrop::new(|| {
-            host.connect_command_state.cancel(true);
-        });
         host.connect_command_state.request().await;
+        // Until the connection is being created, there is no attempt to cancel on failure.
+        let release = crate::host::OnDrop::new(|| {
+            host.connect_command_state.done();
+        });
 
-        self.set_accept_filter(config.scan_config.filter_accept_list).await?;
+        let mut peers: &[(AddrKind, &BdAddr)] = &[];
+        match accept_list {
+            AcceptList::Current => {}
+            AcceptList::Replace(list) => {
+                self.check_scan_accept_list()?;
+                self.set_accept_filter(list).await?;
+                peers = list;
+            }
+            AcceptList::Known(known) => {
+                self.check_scan_accept_list()?;
+                host.clear_accept_list().await?;
+                let mut pending = 0;
+                for peer in known {
+                    if !host.connections.is_peer_connected(peer.kind, &peer.addr) {
+                        host.add_to_accept_list(peer.kind, peer.addr).await?;
+                        pending += 1;
+                    }
+                }
+                if pending == 0 {
+                    return Err(Error::InvalidState.into());
+                }
+            }
+            AcceptList::Any(any) => {
+                self.check_scan_accept_list()?;
+                host.command(LeClearFilterAcceptList::new()).await?;
+                for peer in any {
+                    host.command(LeAddDeviceToFilterAcceptList::new(peer.kind, peer.addr))
+                        .await?;
+                }
+            }
+        }
 
+        let (min_event_length, max_event_length) = config.connect_params.event_lengths();
         host.async_command(LeCreateConn::new(
             config.scan_config.interval.into(),
             config.scan_config.window.into(),
@@ -47,24 +197,40 @@ impl<'stack, C: Controller> Central<'stack, C> {
             config.connect_params.max_connection_interval.into(),
             config.connect_params.max_latency,
             config.connect_params.supervision_timeout.into(),
-            config.connect_params.event_length.into(),
-            config.connect_params.event_length.into(),
+            min_event_length.into(),
+            max_event_length.into(),
         ))
         .await?;
-        match select(
-            host.connections
-                .accept(LeConnRole::Central, config.scan_config.filter_accept_list),
-            host.connect_command_state.wait_idle(),
-        )
-        .await
-        {
-            Either::First(conn) => {
-                _drop.defuse();
-                host.connect_command_state.done();
-                Ok(conn)
-            }
-            Either::Second(_) => Err(Error::Timeout.into()),
-        }
+        release.defuse();
+        let drop = crate::host::OnDrop::new(|| {
+            host.connect_command_state.cancel(true);
+        });
+        let conn = self.wait_connected(peers, config.scan_config.timeout).await?;
+        drop.defuse();
+        host.connect_command_state.done();
+        Ok(conn)
+    }
+
+    /// Set the subrating accepted for connections in the central role.
+    ///
+    /// Subrate requests of peripherals outside of these parameters are rejected by the controller.
+    /// Requires host support for connection subrating, enabled with
+    /// [`HostFeatures::enable`](crate::prelude::HostFeatures::enable) in the setup of `Runner::run_with_setup`.
+    pub async fn set_default_subrate(&mut self, params: &SubrateParams) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeSetDefaultSubrate>,
+    {
+        params.validate()?;
+        self.stack
+            .host
+            .command(LeSetDefaultSubrate::new(
+                params.subrate_min,
+                params.subrate_max,
+                params.max_latency,
+                params.continuation_number,
+                params.supervision_timeout.into(),
+            ))
+            .await
     }
 
     /// Attempt to create a connection with the provided config.
@@ -77,19 +243,25 @@ impl<'stack, C: Controller> Central<'stack, C> {
             + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
             + ControllerCmdAsync<LeExtCreateConn>,
     {
-        if config.scan_config.filter_accept_list.is_empty() {
+        if config.scan_config.filter_accept_list.is_empty() && !config.scan_config.use_filter_accept_list {
             return Err(Error::InvalidValue.into());
         }
 
+        let _span = Span::begin(Procedure::Connect, None);
         let host = &self.stack.host;
         // Ensure no other connect ongoing.
-        let _drop = crate::host::OnDrop::new(|| {
-            host.connect_command_state.cancel(true);
-        });
         host.connect_command_state.request().await;
+        // Until the connection is being created, there is no attempt to cancel on failure.
+        let release = crate::host::OnDrop::new(|| {
+            host.connect_command_state.done();
+        });
 
-        self.set_accept_filter(config.scan_config.filter_accept_list).await?;
+        if !config.scan_config.use_filter_accept_list {
+            self.check_scan_accept_list()?;
+            self.set_accept_filter(config.scan_config.filter_accept_list).await?;
+        }
 
+        let (min_event_length, max_event_length) = config.connect_params.event_lengths();
         let initiating = InitiatingPhy {
             scan_interval: config.scan_config.interval.into(),
             scan_window: config.scan_config.window.into(),
@@ -97,8 +269,8 @@ impl<'stack, C: Controller> Central<'stack, C> {
             conn_interval_max: config.connect_params.max_connection_interval.into(),
             max_latency: config.connect_params.max_latency,
             supervision_timeout: config.connect_params.supervision_timeout.into(),
-            min_ce_len: config.connect_params.event_length.into(),
-            max_ce_len: config.connect_params.event_length.into(),
+            min_ce_len: min_event_length.into(),
+            max_ce_len: max_event_length.into(),
         };
         let phy_params = create_phy_params(initiating, config.scan_config.phys);
 
@@ -110,21 +282,57 @@ impl<'stack, C: Controller> Central<'stack, C> {
             phy_params,
         ))
         .await?;
+        release.defuse();
+        let drop = crate::host::OnDrop::new(|| {
+            host.connect_command_state.cancel(true);
+        });
 
-        match select(
-            host.connections
-                .accept(LeConnRole::Central, config.scan_config.filter_accept_list),
+        let conn = self
+            .wait_connected(config.scan_config.filter_accept_list, config.scan_config.timeout)
+            .await?;
+        drop.defuse();
+        host.connect_command_state.done();
+        Ok(conn)
+    }
+
+    // Wait for the connection to be established, or for the attempt to time out or be cancelled.
+    // The connection attempt is cancelled by the caller when returning an error.
+    async fn wait_connected(
+        &self,
+        peers: &[(AddrKind, &BdAddr)],
+        timeout: Duration,
+    ) -> Result<Connection<'stack>, Error> {
+        let host = &self.stack.host;
+        let deadline = async {
+            if timeout.as_ticks() == 0 {
+                core::future::pending::<()>().await
+            } else {
+                Timer::after(timeout).await
+            }
+        };
+        match select3(
+            host.connections.accept(LeConnRole::Central, peers),
             host.connect_command_state.wait_idle(),
+            deadline,
         )
         .await
         {
-            Either::First(conn) => {
-                _drop.defuse();
-                host.connect_command_state.done();
-                Ok(conn)
+            Either3::First(conn) => Ok(conn),
+            Either3::Second(_) | Either3::Third(_) => Err(Error::Timeout),
+        }
+    }
+
+    // Connecting may run concurrently with scanning, as long as the filter accept list the scan
+    // is filtering with is left untouched.
+    fn check_scan_accept_list(&self) -> Result<(), Error> {
+        #[cfg(feature = "scan")]
+        {
+            let host = &self.stack.host;
+            if host.scan_filtered.get() && !host.scan_command_state.is_idle() {
+                return Err(Error::InvalidState);
             }
-            Either::Second(_) => Err(Error::Timeout.into()),
         }
+        Ok(())
     }
 
     pub(crate) async fn set_accept_filter(
@@ -135,10 +343,9 @@ impl<'stack, C: Controller> Central<'stack, C> {
         C: ControllerCmdSync<LeClearFilterAcceptList> + ControllerCmdSync<LeAddDeviceToFilterAcceptList>,
     {
         let host = &self.stack.host;
-        host.command(LeClearFilterAcceptList::new()).await?;
+        host.clear_accept_list().await?;
         for entry in filter_accept_list {
-            host.command(LeAddDeviceToFilterAcceptList::new(entry.0, *entry.1))
-                .await?;
+            host.add_to_accept_list(entry.0, *entry.1).await?;
         }
         Ok(())
     }
@@ -22,6 +22,7 @@ use crate::types::l2cap::{
 use crate::{BleHostError, Error, config};
 
 const BASE_ID: u16 = 0x40;
+const CONN_PARAM_UPDATE_ACCEPTED: u16 = 0x0000;
 
 struct State<'d> {
     next_req_id: u8,
@@ -114,7 +115,7 @@ impl<'d> ChannelManager<'d> {
         }
     }
 
-    fn next_request_id(&self) -> u8 {
+    pub(crate) fn next_request_id(&self) -> u8 {
         self.state.borrow_mut().next_request_id()
     }
 
@@ -167,58 +168,87 @@ impl<'d> ChannelManager<'d> {
         initial_credits: Option<u16>,
         ble: &BleHost<'_, T>,
     ) -> Result<L2capChannel<'_>, BleHostError<T::Error>> {
-        // Wait until we find a channel for our connection in the connecting state matching our PSM.
-        let (channel, req_id, mps, mtu, cid, credits) = poll_fn(|cx| {
-            let mut state = self.state.borrow_mut();
-            state.accept_waker.register(cx.waker());
-            for (idx, chan) in state.channels.iter_mut().enumerate() {
-                match chan.state {
-                    ChannelState::PeerConnecting(req_id) if chan.conn == Some(conn) && psm.contains(&chan.psm) => {
-                        chan.mps = chan.mps.min(self.pool.mtu() as u16 - 4);
-                        chan.mtu = chan.mtu.min(mtu);
-                        chan.mtu = mtu;
-                        chan.flow_control = CreditFlowControl::new(
-                            credit_flow,
-                            initial_credits.unwrap_or(self.pool.available() as u16),
-                        );
-                        chan.state = ChannelState::Connected;
-                        let mps = chan.mps;
-                        let mtu = chan.mtu;
-                        let cid = chan.cid;
-                        let available = chan.flow_control.available();
-                        if chan.refcount != 0 {
-                            state.print(true);
-                            panic!("unexpected refcount");
+        loop {
+            // Wait until we find a channel for our connection in the connecting state matching our PSM.
+            let accepted = poll_fn(|cx| {
+                let mut state = self.state.borrow_mut();
+                state.accept_waker.register(cx.waker());
+                for (idx, chan) in state.channels.iter_mut().enumerate() {
+                    match chan.state {
+                        #[cfg(feature = "security")]
+                        ChannelState::PeerConnecting(req_id)
+                            if chan.conn == Some(conn) && psm.contains(&chan.psm) && !ble.link_secure_enough(conn) =>
+                        {
+                            warn!("[l2cap] refusing channel on insufficiently secure conn {:?}", conn);
+                            chan.close();
+                            return Poll::Ready(Err(req_id));
                         }
-                        assert_eq!(chan.refcount, 0);
-                        let index = ChannelIndex(idx as u8);
-
-                        state.inc_ref(index);
-                        return Poll::Ready((L2capChannel::new(index, self), req_id, mps, mtu, cid, available));
+                        ChannelState::PeerConnecting(req_id) if chan.conn == Some(conn) && psm.contains(&chan.psm) => {
+                            chan.mps = chan.mps.min(self.pool.mtu() as u16 - 4);
+                            chan.mtu = chan.mtu.min(mtu);
+                            chan.mtu = mtu;
+                            chan.flow_control = CreditFlowControl::new(
+                                credit_flow,
+                                initial_credits.unwrap_or(self.pool.available() as u16),
+                            );
+                            chan.state = ChannelState::Connected;
+                            let mps = chan.mps;
+                            let mtu = chan.mtu;
+                            let cid = chan.cid;
+                            let available = chan.flow_control.available();
+                            if chan.refcount != 0 {
+                                state.print(true);
+                                panic!("unexpected refcount");
+                            }
+                            assert_eq!(chan.refcount, 0);
+                            let index = ChannelIndex(idx as u8);
+
+                            state.inc_ref(index);
+                            return Poll::Ready(Ok((L2capChannel::new(index, self), req_id, mps, mtu, cid, available)));
+                        }
+                        _ => {}
                     }
-                    _ => {}
+                }
+                Poll::Pending
+            })
+            .await;
+
+            let mut tx = [0; 18];
+            match accepted {
+                Ok((channel, req_id, mps, mtu, cid, credits)) => {
+                    // Respond that we accept the channel.
+                    ble.l2cap_signal(
+                        conn,
+                        req_id,
+                        &LeCreditConnRes {
+                            mps,
+                            dcid: cid,
+                            mtu,
+                            credits,
+                            result: LeCreditConnResultCode::Success,
+                        },
+                        &mut tx[..],
+                    )
+                    .await?;
+                    return Ok(channel);
+                }
+                Err(req_id) => {
+                    ble.l2cap_signal(
+                        conn,
+                        req_id,
+                        &LeCreditConnRes {
+                            mps: 0,
+                            dcid: 0,
+                            mtu: 0,
+                            credits: 0,
+                            result: LeCreditConnResultCode::InsufficientAuthentication,
+                        },
+                        &mut tx[..],
+                    )
+                    .await?;
                 }
             }
-            Poll::Pending
-        })
-        .await;
-
-        let mut tx = [0; 18];
-        // Respond that we accept the channel.
-        ble.l2cap_signal(
-            conn,
-            req_id,
-            &LeCreditConnRes {
-                mps,
-                dcid: cid,
-                mtu,
-                credits,
-                result: LeCreditConnResultCode::Success,
-            },
-            &mut tx[..],
-        )
-        .await?;
-        Ok(channel)
+        }
     }
 
     pub(crate) async fn create<T: Controller>(
@@ -333,7 +363,7 @@ impl<'d> ChannelManager<'d> {
     }
 
     /// Handle incoming L2CAP signal
-    pub(crate) fn signal(&self, conn: ConnHandle, data: &[u8]) -> Result<(), Error> {
+    pub(crate) fn signal(&self, conn: ConnHandle, data: &[u8], manager: &ConnectionManager<'_>) -> Result<(), Error> {
         let (header, data) = L2capSignalHeader::from_hci_bytes(data)?;
         //trace!(
         //    "[l2cap][conn = {:?}] received signal (req {}) code {:?}",
@@ -381,6 +411,10 @@ impl<'d> ChannelManager<'d> {
                     "[l2cap][conn = {:?}] connection param update response: {}",
                     conn, res.result,
                 );
+                // The parameters are only updated once the link layer procedure completes.
+                if res.result != CONN_PARAM_UPDATE_ACCEPTED {
+                    manager.param_update_rejected(conn)?;
+                }
             }
             r => {
                 warn!("[l2cap][conn = {:?}] unsupported signal: {:?}", conn, r);
@@ -431,7 +465,7 @@ impl<'d> ChannelManager<'d> {
                 Err(Error::NotFound)
             }
             other => {
-                warn!("Channel open request failed: {:?}", other);
+                warn!("[l2cap] channel open request failed: {:?}", other);
                 Err(Error::NotSupported)
             }
         }
@@ -550,7 +584,18 @@ impl<'d> ChannelManager<'d> {
         let len = (buf.len() as u16).saturating_add(2);
         let n_packets = len.div_ceil(mps);
 
-        let mut grant = poll_fn(|cx| self.poll_request_to_send(index, n_packets, Some(cx))).await?;
+        #[cfg(feature = "traffic-metrics")]
+        let mut stalled = false;
+        let mut grant = poll_fn(|cx| {
+            let poll = self.poll_request_to_send(index, n_packets, Some(cx));
+            #[cfg(feature = "traffic-metrics")]
+            if poll.is_pending() && !stalled {
+                stalled = true;
+                ble.count(|m| m.l2cap_stalls = m.l2cap_stalls.wrapping_add(1));
+            }
+            poll
+        })
+        .await?;
 
         let mut sender = ble.l2cap(conn, len, n_packets).await?;
 
@@ -592,6 +637,8 @@ impl<'d> ChannelManager<'d> {
         let mut grant = match self.poll_request_to_send(index, n_packets, None) {
             Poll::Ready(res) => res?,
             Poll::Pending => {
+                #[cfg(feature = "traffic-metrics")]
+                ble.count(|m| m.l2cap_stalls = m.l2cap_stalls.wrapping_add(1));
                 return Err(Error::Busy.into());
             }
         };
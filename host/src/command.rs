@@ -68,6 +68,16 @@ impl<CTX: Clone + Copy> CommandState<CTX> {
         .await
     }
 
+    /// Check if no command is active.
+    pub fn is_idle(&self) -> bool {
+        self.with_inner(|inner| matches!(inner.state, State::Idle))
+    }
+
+    /// Check if the command is being canceled.
+    pub fn is_cancelling(&self) -> bool {
+        self.with_inner(|inner| matches!(inner.state, State::Cancel(_)))
+    }
+
     /// Poll if the command should be canceled
     pub fn poll_cancelled(&self, cx: &mut Context<'_>) -> Poll<CTX> {
         self.with_inner(|inner| {
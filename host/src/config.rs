@@ -80,3 +80,73 @@ pub const GATT_CLIENT_NOTIFICATION_MAX_SUBSCRIBERS: usize = raw::GATT_CLIENT_NOT
 ///
 /// Default: 1.
 pub const GATT_CLIENT_NOTIFICATION_QUEUE_SIZE: usize = raw::GATT_CLIENT_NOTIFICATION_QUEUE_SIZE;
+
+/// Host event queue size.
+///
+/// This is the number of host events buffered for every `HostEventListener`. A listener not
+/// keeping up misses the oldest events.
+///
+/// Default: 4.
+pub const HOST_EVENT_QUEUE_SIZE: usize = raw::HOST_EVENT_QUEUE_SIZE;
+
+/// Maximum number of host event listeners.
+///
+/// Default: 1.
+pub const HOST_EVENT_MAX_SUBSCRIBERS: usize = raw::HOST_EVENT_MAX_SUBSCRIBERS;
+
+/// Bond table size.
+///
+/// This is the number of bonded peers the security manager keeps the keys of.
+///
+/// Default: 4.
+pub const BOND_TABLE_SIZE: usize = raw::BOND_TABLE_SIZE;
+
+/// Pairing attempts table size.
+///
+/// This is the number of peers whose failed pairing attempts the security manager tracks, to make
+/// them wait before pairing again. When it is full, the peer allowed to pair again first is
+/// forgotten.
+///
+/// Default: 4.
+pub const PAIRING_ATTEMPTS_TABLE_SIZE: usize = raw::PAIRING_ATTEMPTS_TABLE_SIZE;
+
+/// Filter accept list size.
+///
+/// This is the number of filter accept list entries the host keeps, to restore the list when the
+/// controller is reset after becoming unresponsive. Entries beyond it are not restored.
+///
+/// Default: 8.
+pub const FILTER_ACCEPT_LIST_SIZE: usize = raw::FILTER_ACCEPT_LIST_SIZE;
+
+/// Scan reassembly size.
+///
+/// This is the number of extended advertising payloads split over several reports that the host
+/// reassembles at the same time. When it is full, the oldest partial payload is dropped.
+///
+/// Default: 2.
+pub const SCAN_REASSEMBLY_SIZE: usize = raw::SCAN_REASSEMBLY_SIZE;
+
+/// Scan report data size.
+///
+/// This is the largest advertising payload, and the largest scan response payload, kept by a
+/// reassembled or queued scan report. Larger payloads are dropped. The default fits the largest
+/// extended advertising data; lower it to use less RAM for every reassembled and queued report.
+///
+/// Default: 1650.
+pub const SCAN_REPORT_DATA_SIZE: usize = raw::SCAN_REPORT_DATA_SIZE;
+
+/// Maximum number of isochronous channels.
+///
+/// This is the number of isochronous streams that can be established or pending at the same
+/// time, across all connections and groups.
+///
+/// Default: 2.
+pub const ISO_CHANNELS_MAX: usize = raw::ISO_CHANNELS_MAX;
+
+/// Isochronous RX queue size.
+///
+/// This is the number of received SDUs buffered for every isochronous channel. SDUs received
+/// while the queue is full are dropped.
+///
+/// Default: 2.
+pub const ISO_RX_QUEUE_SIZE: usize = raw::ISO_RX_QUEUE_SIZE;
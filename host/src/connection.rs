@@ -1,16 +1,31 @@
 //! BLE connection.
 
-use bt_hci::cmd::le::LeConnUpdate;
+use core::future::poll_fn;
+
+use bt_hci::cmd::controller_baseband::{ReadAuthenticatedPayloadTimeout, WriteAuthenticatedPayloadTimeout};
+use bt_hci::cmd::le::{
+    LeConnUpdate, LeEnhancedReadTransmitPowerLevel, LeReadChannelMap, LeReadPhy, LeReadRemoteFeatures,
+    LeReadRemoteTransmitPowerLevel, LeSetDataLength, LeSetPhy, LeSetTransmitPowerReportingEnable,
+};
+use bt_hci::cmd::link_control::ReadRemoteVersionInformation;
 use bt_hci::cmd::status::ReadRssi;
 use bt_hci::controller::{ControllerCmdAsync, ControllerCmdSync};
-use bt_hci::param::{AddrKind, BdAddr, ConnHandle, DisconnectReason, LeConnRole, Status};
-use embassy_time::Duration;
+use bt_hci::param::{
+    AddrKind, AllPhys, BdAddr, ConnHandle, CoreSpecificationVersion, LeConnRole, LeFeatureMask, PhyMask, Status,
+};
+pub use bt_hci::param::{ChannelMap, DisconnectReason, LeTxPowerReportingReason, PhyKind, PhyOptions};
+use embassy_time::{Duration, Timer, with_timeout};
 
 use crate::connection_manager::ConnectionManager;
+use crate::hci::LeSubrateRequest;
+use crate::host::{OnDrop, unsupported_as_none};
 use crate::pdu::Pdu;
-use crate::{BleHostError, Error, Stack};
+use crate::types::gatt_traits::AsGatt;
+use crate::types::l2cap::ConnParamUpdateReq;
+use crate::{Address, BleHostError, Error, Stack};
 
 /// Connection configuration.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct ConnectConfig<'d> {
     /// Scan configuration to use while connecting.
     pub scan_config: ScanConfig<'d>,
@@ -19,11 +34,20 @@ pub struct ConnectConfig<'d> {
 }
 
 /// Scan/connect configuration.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct ScanConfig<'d> {
     /// Active scanning.
     pub active: bool,
     /// List of addresses to accept.
+    ///
+    /// The controller filter accept list is replaced by this list when scanning starts, and
+    /// cleared when it is empty, unless a connection is being created.
     pub filter_accept_list: &'d [(AddrKind, &'d BdAddr)],
+    /// Filter using the entries already in the controller filter accept list.
+    ///
+    /// When set, the controller list is left untouched instead of being replaced by
+    /// `filter_accept_list`, and can be managed with `Stack::add_to_filter_accept_list`.
+    pub use_filter_accept_list: bool,
     /// PHYs to scan on.
     pub phys: PhySet,
     /// Scan interval.
@@ -31,6 +55,8 @@ pub struct ScanConfig<'d> {
     /// Scan window.
     pub window: Duration,
     /// Scan timeout.
+    ///
+    /// When connecting, the connection attempt is cancelled after this timeout. Zero means no timeout.
     pub timeout: Duration,
 }
 
@@ -39,6 +65,7 @@ impl Default for ScanConfig<'_> {
         Self {
             active: true,
             filter_accept_list: &[],
+            use_filter_accept_list: false,
             phys: PhySet::M1,
             interval: Duration::from_secs(1),
             window: Duration::from_secs(1),
@@ -68,7 +95,18 @@ pub enum PhySet {
     M1M2Coded = 7,
 }
 
+impl PhySet {
+    fn mask(self) -> PhyMask {
+        let bits = self as u8;
+        PhyMask::new()
+            .set_le_1m_preferred(bits & 1 != 0)
+            .set_le_2m_preferred(bits & 2 != 0)
+            .set_le_coded_preferred(bits & 4 != 0)
+    }
+}
+
 /// Connection parameters.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct ConnectParams {
     /// Minimum connection interval.
     pub min_connection_interval: Duration,
@@ -76,30 +114,278 @@ pub struct ConnectParams {
     pub max_connection_interval: Duration,
     /// Maximum slave latency.
     pub max_latency: u16,
-    /// Event length.
+    /// Length of connection events, used as the minimum length.
+    ///
+    /// The event lengths are hints to the controller, longer connection events allow more packets
+    /// to be exchanged per connection interval.
     pub event_length: Duration,
+    /// Maximum length of connection events.
+    ///
+    /// Raised to `event_length` when shorter, so that leaving it at zero requests connection
+    /// events of `event_length`.
+    pub max_event_length: Duration,
+    /// Supervision timeout.
+    pub supervision_timeout: Duration,
+}
+
+/// Parameters of an established connection.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionParams {
+    /// Connection interval.
+    pub conn_interval: Duration,
+    /// Peripheral latency, in connection events.
+    pub peripheral_latency: u16,
     /// Supervision timeout.
     pub supervision_timeout: Duration,
 }
 
+impl ConnectionParams {
+    pub(crate) const fn new() -> Self {
+        Self {
+            conn_interval: Duration::from_ticks(0),
+            peripheral_latency: 0,
+            supervision_timeout: Duration::from_ticks(0),
+        }
+    }
+
+    pub(crate) fn from_hci(
+        conn_interval: bt_hci::param::Duration<1_250>,
+        peripheral_latency: u16,
+        supervision_timeout: bt_hci::param::Duration<10_000>,
+    ) -> Self {
+        Self {
+            conn_interval: Duration::from_micros(conn_interval.as_micros()),
+            peripheral_latency,
+            supervision_timeout: Duration::from_micros(supervision_timeout.as_micros()),
+        }
+    }
+}
+
+/// Maximum payload size and transmission time of the link layer data packets of a connection.
+///
+/// Times are in microseconds.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataLength {
+    /// Maximum number of payload bytes sent in a single packet.
+    pub max_tx_octets: u16,
+    /// Maximum time used to send a single packet.
+    pub max_tx_time: u16,
+    /// Maximum number of payload bytes received in a single packet.
+    pub max_rx_octets: u16,
+    /// Maximum time used to receive a single packet.
+    pub max_rx_time: u16,
+}
+
+impl DataLength {
+    // Data length of a connection until changed by the data length update procedure.
+    pub(crate) const DEFAULT: Self = Self {
+        max_tx_octets: 27,
+        max_tx_time: 328,
+        max_rx_octets: 27,
+        max_rx_time: 328,
+    };
+}
+
+/// Acceptable subrating of a connection, requested with the LE connection subrating procedure.
+///
+/// Subrating lets a connection skip connection events while idle, and resume using every
+/// connection event as soon as data is sent.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SubrateParams {
+    /// Minimum subrate factor.
+    pub subrate_min: u16,
+    /// Maximum subrate factor.
+    pub subrate_max: u16,
+    /// Maximum peripheral latency, in subrated connection events.
+    pub max_latency: u16,
+    /// Number of connection events to stay active after a packet was received.
+    pub continuation_number: u16,
+    /// Supervision timeout.
+    pub supervision_timeout: Duration,
+}
+
+impl SubrateParams {
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let factors = 1..=500;
+        if !factors.contains(&self.subrate_min)
+            || !factors.contains(&self.subrate_max)
+            || self.subrate_min > self.subrate_max
+            || self.continuation_number >= self.subrate_max
+            || u32::from(self.subrate_max) * (u32::from(self.max_latency) + 1) > 500
+        {
+            return Err(Error::InvalidValue);
+        }
+        Ok(())
+    }
+}
+
+/// Subrating in use by a connection.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subrate {
+    /// Subrate factor, the connection uses one in every `factor` connection events.
+    pub factor: u16,
+    /// Peripheral latency, in subrated connection events.
+    pub peripheral_latency: u16,
+    /// Number of connection events to stay active after a packet was received.
+    pub continuation_number: u16,
+    /// Supervision timeout.
+    pub supervision_timeout: Duration,
+}
+
+/// Transmit power level of either side of a connection, reported by the controller.
+///
+/// Power levels are in dBm.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxPowerReport {
+    /// Why the report was sent, which also tells whose transmitter it is for.
+    pub reason: LeTxPowerReportingReason,
+    /// PHY the power level applies to.
+    pub phy: PhyKind,
+    /// Transmit power level. 126 if the peer is not managing power levels on this PHY, 127 if not available.
+    pub tx_power_level: i8,
+    /// Change of the power level since the previous report, 127 if not available.
+    pub delta: i8,
+}
+
+/// Version information of the controller of the peer.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteVersion {
+    /// Bluetooth version supported by the link layer of the peer.
+    pub version: CoreSpecificationVersion,
+    /// Company identifier of the manufacturer of the controller.
+    pub company_id: u16,
+    /// Manufacturer specific revision of the controller.
+    pub subversion: u16,
+}
+
+/// Security level of a connection, ordered from the least to the most secure.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SecurityLevel {
+    /// The link is not encrypted (security mode 1, level 1).
+    NoEncryption,
+    /// The link is encrypted with an unauthenticated key (security mode 1, level 2).
+    ///
+    /// This is also the level of links encrypted with a key the host did not negotiate itself,
+    /// since it cannot tell how the key was generated.
+    Encrypted,
+    /// The link is encrypted with a key authenticated during pairing (security mode 1, level 3).
+    EncryptedAuthenticated,
+    /// The link is encrypted with a 128-bit key of authenticated LE Secure Connections pairing
+    /// (security mode 1, level 4).
+    SecureConnections,
+}
+
+impl SecurityLevel {
+    /// Check if the link is encrypted.
+    pub fn encrypted(&self) -> bool {
+        !matches!(self, SecurityLevel::NoEncryption)
+    }
+
+    /// Check if the link is encrypted with an authenticated key.
+    pub fn authenticated(&self) -> bool {
+        matches!(
+            self,
+            SecurityLevel::EncryptedAuthenticated | SecurityLevel::SecureConnections
+        )
+    }
+}
+
 #[cfg(not(feature = "gatt"))]
 /// A connection event.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum ConnectionEvent {
     /// Connection disconnected.
     Disconnected {
         /// The reason (status code) for the disconnect.
         reason: Status,
     },
+    /// Encryption was enabled, disabled or the encryption key was refreshed.
+    SecurityChanged {
+        /// The security level of the link after the change.
+        security_level: SecurityLevel,
+    },
+    /// The connection parameters were changed, by either side of the connection.
+    ConnectionParamsUpdated {
+        /// The connection parameters after the change.
+        params: ConnectionParams,
+    },
+    /// The PHYs of the connection were changed, by either side of the connection.
+    PhyChanged {
+        /// The transmitter PHY.
+        tx_phy: PhyKind,
+        /// The receiver PHY.
+        rx_phy: PhyKind,
+    },
+    /// The maximum data length of the connection was changed.
+    DataLengthChanged {
+        /// The data length after the change.
+        data_length: DataLength,
+    },
+    /// The transmit power level of either side of the connection was reported.
+    TxPowerReported {
+        /// The reported transmit power.
+        report: TxPowerReport,
+    },
+    /// The subrating of the connection was changed, by either side of the connection.
+    SubrateChanged {
+        /// The subrating after the change.
+        subrate: Subrate,
+    },
+    /// No packet with a valid MIC was received from the peer within the authenticated payload timeout.
+    AuthenticatedPayloadTimeoutExpired,
 }
 
 /// A connection event.
 #[cfg(feature = "gatt")]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum ConnectionEvent<'stack> {
     /// Connection disconnected.
     Disconnected {
         /// The reason (status code) for the disconnect.
         reason: Status,
     },
+    /// Encryption was enabled, disabled or the encryption key was refreshed.
+    SecurityChanged {
+        /// The security level of the link after the change.
+        security_level: SecurityLevel,
+    },
+    /// The connection parameters were changed, by either side of the connection.
+    ConnectionParamsUpdated {
+        /// The connection parameters after the change.
+        params: ConnectionParams,
+    },
+    /// The PHYs of the connection were changed, by either side of the connection.
+    PhyChanged {
+        /// The transmitter PHY.
+        tx_phy: PhyKind,
+        /// The receiver PHY.
+        rx_phy: PhyKind,
+    },
+    /// The maximum data length of the connection was changed.
+    DataLengthChanged {
+        /// The data length after the change.
+        data_length: DataLength,
+    },
+    /// The transmit power level of either side of the connection was reported.
+    TxPowerReported {
+        /// The reported transmit power.
+        report: TxPowerReport,
+    },
+    /// The subrating of the connection was changed, by either side of the connection.
+    SubrateChanged {
+        /// The subrating after the change.
+        subrate: Subrate,
+    },
+    /// No packet with a valid MIC was received from the peer within the authenticated payload timeout.
+    AuthenticatedPayloadTimeoutExpired,
     /// GATT event.
     Gatt {
         /// The event that was returned
@@ -113,6 +399,40 @@ pub(crate) enum ConnectionEventData {
         /// The reason (status code) for the disconnect.
         reason: Status,
     },
+    /// Security level changed.
+    SecurityChanged {
+        /// The new security level.
+        security_level: SecurityLevel,
+    },
+    /// Connection parameters changed.
+    ConnectionParamsUpdated {
+        /// The new connection parameters.
+        params: ConnectionParams,
+    },
+    /// PHYs changed.
+    PhyChanged {
+        /// The new transmitter PHY.
+        tx_phy: PhyKind,
+        /// The new receiver PHY.
+        rx_phy: PhyKind,
+    },
+    /// Data length changed.
+    DataLengthChanged {
+        /// The new data length.
+        data_length: DataLength,
+    },
+    /// Transmit power reported.
+    TxPowerReported {
+        /// The report.
+        report: TxPowerReport,
+    },
+    /// Subrating changed.
+    SubrateChanged {
+        /// The new subrating.
+        subrate: Subrate,
+    },
+    /// Authenticated payload timeout expired.
+    AuthenticatedPayloadTimeoutExpired,
     /// GATT event.
     Gatt {
         /// The event that was returned
@@ -120,6 +440,98 @@ pub(crate) enum ConnectionEventData {
     },
 }
 
+impl ConnectParams {
+    /// Long connection interval with peripheral latency, for links that exchange little data.
+    pub const fn low_power() -> Self {
+        LOW_POWER
+    }
+
+    /// Moderate connection interval, for links with occasional bursts of data.
+    pub const fn balanced() -> Self {
+        BALANCED
+    }
+
+    /// Short connection interval with long connection events, for bulk transfers.
+    pub const fn high_throughput() -> Self {
+        HIGH_THROUGHPUT
+    }
+
+    /// Shortest connection interval with a high peripheral latency, so that an HID peripheral can
+    /// report input with low latency while skipping connection events when idle.
+    pub const fn low_latency_hid() -> Self {
+        LOW_LATENCY_HID
+    }
+
+    /// Check the parameters against the ranges allowed by the specification.
+    ///
+    /// The supervision timeout must also be long enough to not expire while the peripheral
+    /// skips connection events using its latency.
+    pub const fn is_valid(&self) -> bool {
+        let min_interval = self.min_connection_interval.as_micros();
+        let max_interval = self.max_connection_interval.as_micros();
+        let timeout = self.supervision_timeout.as_micros();
+        min_interval >= 7_500
+            && max_interval <= 4_000_000
+            && min_interval <= max_interval
+            && self.max_latency <= 499
+            && timeout >= 100_000
+            && timeout <= 32_000_000
+            && timeout > (1 + self.max_latency as u64) * max_interval * 2
+    }
+
+    // Minimum and maximum connection event lengths, in the order required by the controller.
+    pub(crate) fn event_lengths(&self) -> (Duration, Duration) {
+        (self.event_length, self.event_length.max(self.max_event_length))
+    }
+
+    const fn checked(self) -> Self {
+        core::assert!(self.is_valid(), "invalid connection parameters");
+        self
+    }
+}
+
+// Evaluated in a const context, so that invalid presets fail to compile.
+const LOW_POWER: ConnectParams = ConnectParams {
+    min_connection_interval: Duration::from_millis(100),
+    max_connection_interval: Duration::from_millis(200),
+    max_latency: 4,
+    event_length: Duration::from_secs(0),
+    max_event_length: Duration::from_secs(0),
+    supervision_timeout: Duration::from_secs(6),
+}
+.checked();
+
+const BALANCED: ConnectParams = ConnectParams {
+    min_connection_interval: Duration::from_millis(30),
+    max_connection_interval: Duration::from_millis(50),
+    max_latency: 0,
+    event_length: Duration::from_secs(0),
+    max_event_length: Duration::from_secs(0),
+    supervision_timeout: Duration::from_secs(4),
+}
+.checked();
+
+const HIGH_THROUGHPUT: ConnectParams = ConnectParams {
+    min_connection_interval: Duration::from_millis(15),
+    max_connection_interval: Duration::from_millis(30),
+    max_latency: 0,
+    // Connection events cannot outlast the shortest interval.
+    event_length: Duration::from_millis(15),
+    max_event_length: Duration::from_millis(15),
+    supervision_timeout: Duration::from_secs(4),
+}
+.checked();
+
+const LOW_LATENCY_HID: ConnectParams = ConnectParams {
+    min_connection_interval: Duration::from_micros(7_500),
+    max_connection_interval: Duration::from_millis(15),
+    max_latency: 30,
+    event_length: Duration::from_secs(0),
+    max_event_length: Duration::from_secs(0),
+    supervision_timeout: Duration::from_secs(2),
+}
+.checked();
+
 impl Default for ConnectParams {
     fn default() -> Self {
         Self {
@@ -127,11 +539,65 @@ impl Default for ConnectParams {
             max_connection_interval: Duration::from_millis(80),
             max_latency: 0,
             event_length: Duration::from_secs(0),
+            max_event_length: Duration::from_secs(0),
             supervision_timeout: Duration::from_secs(8),
         }
     }
 }
 
+/// Connection parameters preferred by a peripheral, as exposed by its Peripheral Preferred
+/// Connection Parameters characteristic.
+///
+/// The event lengths of the parameters are left out.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreferredConnectionParameters([u8; 8]);
+
+impl PreferredConnectionParameters {
+    /// Create the preferences from connection parameters.
+    pub const fn new(params: &ConnectParams) -> Self {
+        let min_interval = (params.min_connection_interval.as_micros() / 1_250) as u16;
+        let max_interval = (params.max_connection_interval.as_micros() / 1_250) as u16;
+        let timeout = (params.supervision_timeout.as_micros() / 10_000) as u16;
+        let [a, b] = min_interval.to_le_bytes();
+        let [c, d] = max_interval.to_le_bytes();
+        let [e, f] = params.max_latency.to_le_bytes();
+        let [g, h] = timeout.to_le_bytes();
+        Self([a, b, c, d, e, f, g, h])
+    }
+
+    /// The connection parameters preferred.
+    pub fn params(&self) -> ConnectParams {
+        let field = |i: usize| u16::from_le_bytes([self.0[i], self.0[i + 1]]) as u64;
+        ConnectParams {
+            min_connection_interval: Duration::from_micros(field(0) * 1_250),
+            max_connection_interval: Duration::from_micros(field(2) * 1_250),
+            max_latency: field(4) as u16,
+            event_length: Duration::from_secs(0),
+            max_event_length: Duration::from_secs(0),
+            supervision_timeout: Duration::from_micros(field(6) * 10_000),
+        }
+    }
+
+    /// Whether the parameters of a connection already match the preferences.
+    pub fn matches(&self, params: &ConnectionParams) -> bool {
+        let preferred = self.params();
+        params.conn_interval >= preferred.min_connection_interval
+            && params.conn_interval <= preferred.max_connection_interval
+            && params.peripheral_latency <= preferred.max_latency
+            && params.supervision_timeout == preferred.supervision_timeout
+    }
+}
+
+impl AsGatt for PreferredConnectionParameters {
+    const MIN_SIZE: usize = 8;
+    const MAX_SIZE: usize = 8;
+
+    fn as_gatt(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 /// Handle to a BLE connection.
 ///
 /// When the last reference to a connection is dropped, the connection is automatically disconnected.
@@ -147,6 +613,13 @@ impl Clone for Connection<'_> {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Connection<'_> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "Connection({})", self.handle());
+    }
+}
+
 impl Drop for Connection<'_> {
     fn drop(&mut self) {
         self.manager.dec_ref(self.index);
@@ -192,19 +665,57 @@ impl<'stack> Connection<'stack> {
     }
 
     /// Wait for next connection event.
+    ///
+    /// Events other than GATT events are not queued: a change that happens again before it was
+    /// returned is reported once, with the latest value. They are returned before queued GATT
+    /// events, and the disconnection after all other events.
     #[cfg(not(feature = "gatt"))]
     pub async fn next(&self) -> ConnectionEvent {
         match self.manager.next(self.index).await {
             ConnectionEventData::Disconnected { reason } => ConnectionEvent::Disconnected { reason },
+            ConnectionEventData::SecurityChanged { security_level } => {
+                ConnectionEvent::SecurityChanged { security_level }
+            }
+            ConnectionEventData::ConnectionParamsUpdated { params } => {
+                ConnectionEvent::ConnectionParamsUpdated { params }
+            }
+            ConnectionEventData::PhyChanged { tx_phy, rx_phy } => ConnectionEvent::PhyChanged { tx_phy, rx_phy },
+            ConnectionEventData::AuthenticatedPayloadTimeoutExpired => {
+                ConnectionEvent::AuthenticatedPayloadTimeoutExpired
+            }
+            ConnectionEventData::SubrateChanged { subrate } => ConnectionEvent::SubrateChanged { subrate },
+            ConnectionEventData::TxPowerReported { report } => ConnectionEvent::TxPowerReported { report },
+            ConnectionEventData::DataLengthChanged { data_length } => {
+                ConnectionEvent::DataLengthChanged { data_length }
+            }
             ConnectionEventData::Gatt { data } => unreachable!(),
         }
     }
 
     /// Wait for next connection event.
+    ///
+    /// Events other than GATT events are not queued: a change that happens again before it was
+    /// returned is reported once, with the latest value. They are returned before queued GATT
+    /// events, and the disconnection after all other events.
     #[cfg(feature = "gatt")]
     pub async fn next(&self) -> ConnectionEvent<'stack> {
         match self.manager.next(self.index).await {
             ConnectionEventData::Disconnected { reason } => ConnectionEvent::Disconnected { reason },
+            ConnectionEventData::SecurityChanged { security_level } => {
+                ConnectionEvent::SecurityChanged { security_level }
+            }
+            ConnectionEventData::ConnectionParamsUpdated { params } => {
+                ConnectionEvent::ConnectionParamsUpdated { params }
+            }
+            ConnectionEventData::PhyChanged { tx_phy, rx_phy } => ConnectionEvent::PhyChanged { tx_phy, rx_phy },
+            ConnectionEventData::AuthenticatedPayloadTimeoutExpired => {
+                ConnectionEvent::AuthenticatedPayloadTimeoutExpired
+            }
+            ConnectionEventData::SubrateChanged { subrate } => ConnectionEvent::SubrateChanged { subrate },
+            ConnectionEventData::TxPowerReported { report } => ConnectionEvent::TxPowerReported { report },
+            ConnectionEventData::DataLengthChanged { data_length } => {
+                ConnectionEvent::DataLengthChanged { data_length }
+            }
             ConnectionEventData::Gatt { data } => ConnectionEvent::Gatt {
                 data: crate::gatt::GattData::new(data, self.clone()),
             },
@@ -227,6 +738,9 @@ impl<'stack> Connection<'stack> {
     }
 
     /// The connection role for this connection.
+    ///
+    /// A host with both the central and peripheral features can hold connections in
+    /// both roles at the same time, for instance while advertising to accept new connections.
     pub fn role(&self) -> LeConnRole {
         self.manager.role(self.index)
     }
@@ -236,50 +750,490 @@ impl<'stack> Connection<'stack> {
         self.manager.peer_address(self.index)
     }
 
+    /// The address and address type of the peer.
+    pub(crate) fn peer(&self) -> Address {
+        self.manager.peer(self.index)
+    }
+
+    /// The current security level of this connection.
+    pub fn security_level(&self) -> SecurityLevel {
+        self.manager.security_level(self.index)
+    }
+
+    /// Secure the link, encrypting it with the key of a bonded peer or pairing with the peer.
+    ///
+    /// A central encrypts the link with the key of a bonded peer, or pairs otherwise. A peripheral
+    /// sends a security request, letting the central decide. The outcome is reported by a
+    /// `ConnectionEvent::SecurityChanged` with the new security level, the link staying at its
+    /// level if pairing failed.
+    #[cfg(feature = "security")]
+    pub fn request_security<T: crate::Controller>(&self, stack: &Stack<'_, T>) -> Result<(), Error> {
+        stack.host.security.request_security(&stack.host, self.handle())
+    }
+
     /// Request connection to be disconnected.
     pub fn disconnect(&self) {
         self.manager
             .request_disconnect(self.index, DisconnectReason::RemoteUserTerminatedConn);
     }
 
-    /// The RSSI value for this connection.
+    /// Disconnect with the given reason, and wait until the controller has completed the disconnection.
+    ///
+    /// Returns immediately if the connection is already disconnected. If a disconnect is already
+    /// in progress, that disconnect is awaited and its reason is kept.
+    pub async fn disconnect_with(&self, reason: DisconnectReason) {
+        self.manager.request_disconnect(self.index, reason);
+        poll_fn(|cx| self.manager.poll_disconnected(self.index, cx)).await
+    }
+
+    /// Read the RSSI of this connection, in dBm.
+    ///
+    /// The value is measured by the controller on packets received from the peer.
+    /// Returns `Error::NotSupported` if the controller has no RSSI available for the connection.
     pub async fn rssi<T>(&self, stack: &Stack<'_, T>) -> Result<i8, BleHostError<T::Error>>
     where
         T: ControllerCmdSync<ReadRssi>,
     {
         let handle = self.handle();
         let ret = stack.host.command(ReadRssi::new(handle)).await?;
+        // The controller reports 127 when the RSSI is not available.
+        if ret.rssi == RSSI_NOT_AVAILABLE {
+            return Err(Error::NotSupported.into());
+        }
         Ok(ret.rssi)
     }
 
-    /// Update connection parameters for this connection.
-    pub async fn update_connection_params<T>(
+    /// The link layer features supported by the peer.
+    ///
+    /// The features are read from the peer the first time, and cached for the
+    /// lifetime of the connection.
+    pub async fn remote_features<T>(&self, stack: &Stack<'_, T>) -> Result<LeFeatureMask, BleHostError<T::Error>>
+    where
+        T: ControllerCmdAsync<LeReadRemoteFeatures>,
+    {
+        loop {
+            if self.manager.request_remote_features(self.index)? {
+                let drop = OnDrop::new(|| self.manager.abort_remote_features(self.index));
+                stack
+                    .host
+                    .async_command(LeReadRemoteFeatures::new(self.handle()))
+                    .await?;
+                drop.defuse();
+            }
+            // A request started by another task may have been cancelled, in which case it is restarted.
+            if let Some(result) = poll_fn(|cx| self.manager.poll_remote_features(self.index, cx)).await {
+                return Ok(result?);
+            }
+        }
+    }
+
+    /// The version information of the controller of the peer.
+    ///
+    /// The version is read from the peer the first time, and cached for the
+    /// lifetime of the connection.
+    pub async fn remote_version<T>(&self, stack: &Stack<'_, T>) -> Result<RemoteVersion, BleHostError<T::Error>>
+    where
+        T: ControllerCmdAsync<ReadRemoteVersionInformation>,
+    {
+        loop {
+            if self.manager.request_remote_version(self.index)? {
+                let drop = OnDrop::new(|| self.manager.abort_remote_version(self.index));
+                stack
+                    .host
+                    .async_command(ReadRemoteVersionInformation::new(self.handle()))
+                    .await?;
+                drop.defuse();
+            }
+            if let Some(result) = poll_fn(|cx| self.manager.poll_remote_version(self.index, cx)).await {
+                return Ok(result?);
+            }
+        }
+    }
+
+    /// Request a change of the PHYs used by this connection.
+    ///
+    /// The controller negotiates the PHYs with the peer, so the preferred PHYs are not
+    /// necessarily selected. The PHYs in use after the procedure are reported with
+    /// `ConnectionEvent::PhyChanged`, if they changed. Fails with `Error::NotSupported` if the
+    /// controller does not support changing PHYs.
+    pub async fn set_phy<T>(
         &self,
         stack: &Stack<'_, T>,
-        params: ConnectParams,
+        tx: PhySet,
+        rx: PhySet,
+        options: PhyOptions,
     ) -> Result<(), BleHostError<T::Error>>
     where
-        T: ControllerCmdAsync<LeConnUpdate>,
+        T: ControllerCmdAsync<LeSetPhy>,
     {
-        let handle = self.handle();
-        match stack
+        let set_phy = stack
             .host
-            .async_command(LeConnUpdate::new(
-                handle,
-                params.min_connection_interval.into(),
-                params.max_connection_interval.into(),
+            .async_command(LeSetPhy::new(
+                self.handle(),
+                AllPhys::new(),
+                tx.mask(),
+                rx.mask(),
+                options,
+            ))
+            .await;
+        unsupported_as_none(set_phy)?.ok_or(Error::NotSupported.into())
+    }
+
+    /// Read the transmitter and receiver PHYs in use by this connection.
+    ///
+    /// Fails with `Error::NotSupported` if the controller does not support reading PHYs.
+    pub async fn phy<T>(&self, stack: &Stack<'_, T>) -> Result<(PhyKind, PhyKind), BleHostError<T::Error>>
+    where
+        T: ControllerCmdSync<LeReadPhy>,
+    {
+        let ret =
+            unsupported_as_none(stack.host.command(LeReadPhy::new(self.handle())).await)?.ok_or(Error::NotSupported)?;
+        Ok((ret.tx_phy, ret.rx_phy))
+    }
+
+    /// Request a change of the subrating of this connection.
+    ///
+    /// The subrating in use after the procedure is reported with `ConnectionEvent::SubrateChanged`.
+    /// Requests of a peripheral are checked against the defaults set with `Central::set_default_subrate`.
+    pub async fn request_subrate<T>(
+        &self,
+        stack: &Stack<'_, T>,
+        params: &SubrateParams,
+    ) -> Result<(), BleHostError<T::Error>>
+    where
+        T: ControllerCmdAsync<LeSubrateRequest>,
+    {
+        params.validate()?;
+        stack
+            .host
+            .async_command(LeSubrateRequest::new(
+                self.handle(),
+                params.subrate_min,
+                params.subrate_max,
                 params.max_latency,
+                params.continuation_number,
                 params.supervision_timeout.into(),
-                params.event_length.into(),
-                params.event_length.into(),
             ))
             .await
+    }
+
+    /// Set the maximum time allowed between packets with a valid MIC from the peer.
+    ///
+    /// The controller pings the peer before the timeout expires, and posts
+    /// `ConnectionEvent::AuthenticatedPayloadTimeoutExpired` if it does. Only used on encrypted links.
+    pub async fn set_authenticated_payload_timeout<T>(
+        &self,
+        stack: &Stack<'_, T>,
+        timeout: Duration,
+    ) -> Result<(), BleHostError<T::Error>>
+    where
+        T: ControllerCmdSync<WriteAuthenticatedPayloadTimeout>,
+    {
+        stack
+            .host
+            .command(WriteAuthenticatedPayloadTimeout::new(self.handle(), timeout.into()))
+            .await?;
+        Ok(())
+    }
+
+    /// Read the authenticated payload timeout of this connection.
+    pub async fn authenticated_payload_timeout<T>(
+        &self,
+        stack: &Stack<'_, T>,
+    ) -> Result<Duration, BleHostError<T::Error>>
+    where
+        T: ControllerCmdSync<ReadAuthenticatedPayloadTimeout>,
+    {
+        let ret = stack
+            .host
+            .command(ReadAuthenticatedPayloadTimeout::new(self.handle()))
+            .await?;
+        let timeout = ret.timeout;
+        Ok(Duration::from_micros(timeout.as_micros()))
+    }
+
+    /// Read the data channels currently used by this connection.
+    ///
+    /// Channels excluded by the channel selection are marked as bad in the returned map.
+    pub async fn channel_map<T>(&self, stack: &Stack<'_, T>) -> Result<ChannelMap, BleHostError<T::Error>>
+    where
+        T: ControllerCmdSync<LeReadChannelMap>,
+    {
+        let ret = stack.host.command(LeReadChannelMap::new(self.handle())).await?;
+        Ok(ret.channel_map)
+    }
+
+    /// Read the current and maximum transmit power levels of the local controller on a PHY, in dBm.
+    pub async fn tx_power<T>(&self, stack: &Stack<'_, T>, phy: PhyKind) -> Result<(i8, i8), BleHostError<T::Error>>
+    where
+        T: ControllerCmdSync<LeEnhancedReadTransmitPowerLevel>,
+    {
+        let ret = stack
+            .host
+            .command(LeEnhancedReadTransmitPowerLevel::new(self.handle(), phy))
+            .await?;
+        Ok((ret.current_tx_power_level, ret.max_tx_power_level))
+    }
+
+    /// Request the transmit power level of the peer on a PHY.
+    ///
+    /// The power level is read using the LE power control procedure, and reported with
+    /// `ConnectionEvent::TxPowerReported`.
+    pub async fn request_remote_tx_power<T>(
+        &self,
+        stack: &Stack<'_, T>,
+        phy: PhyKind,
+    ) -> Result<(), BleHostError<T::Error>>
+    where
+        T: ControllerCmdAsync<LeReadRemoteTransmitPowerLevel>,
+    {
+        stack
+            .host
+            .async_command(LeReadRemoteTransmitPowerLevel::new(self.handle(), phy))
+            .await
+    }
+
+    /// Enable or disable reporting changes of the local and peer transmit power levels.
+    ///
+    /// Changes are reported with `ConnectionEvent::TxPowerReported`.
+    pub async fn set_tx_power_reporting<T>(
+        &self,
+        stack: &Stack<'_, T>,
+        local: bool,
+        remote: bool,
+    ) -> Result<(), BleHostError<T::Error>>
+    where
+        T: ControllerCmdSync<LeSetTransmitPowerReportingEnable>,
+    {
+        stack
+            .host
+            .command(LeSetTransmitPowerReportingEnable::new(self.handle(), local, remote))
+            .await?;
+        Ok(())
+    }
+
+    /// Request the controller to use the given maximum payload size and transmission time.
+    ///
+    /// The values are negotiated with the peer, and the resulting data length is reported
+    /// with `ConnectionEvent::DataLengthChanged` if it changed. The host suggests the largest
+    /// data length supported by the controller for new connections, if the controller supports
+    /// data length extension. Fails with `Error::NotSupported` otherwise.
+    pub async fn update_data_length<T>(
+        &self,
+        stack: &Stack<'_, T>,
+        tx_octets: u16,
+        tx_time: u16,
+    ) -> Result<(), BleHostError<T::Error>>
+    where
+        T: ControllerCmdSync<LeSetDataLength>,
+    {
+        let set_data_length = stack
+            .host
+            .command(LeSetDataLength::new(self.handle(), tx_octets, tx_time))
+            .await;
+        unsupported_as_none(set_data_length)?.ok_or(Error::NotSupported)?;
+        Ok(())
+    }
+
+    /// The current data length of this connection.
+    pub fn data_length(&self) -> DataLength {
+        self.manager.data_length(self.index)
+    }
+
+    /// The current connection parameters of this connection.
+    pub fn params(&self) -> ConnectionParams {
+        self.manager.connection_params(self.index)
+    }
+
+    /// Update connection parameters for this connection.
+    ///
+    /// As central, the parameters are updated through the link layer. As peripheral, the update
+    /// is requested from the central through L2CAP signaling. The L2CAP request has no event
+    /// lengths, so they are only used as central. Returns once the update is requested, and the
+    /// resulting parameters are reported with `ConnectionEvent::ConnectionParamsUpdated`. Use
+    /// [`Connection::update_connection_params_and_wait`] to wait for the outcome instead.
+    pub async fn update_connection_params<T>(
+        &self,
+        stack: &Stack<'_, T>,
+        params: ConnectParams,
+    ) -> Result<(), BleHostError<T::Error>>
+    where
+        T: ControllerCmdAsync<LeConnUpdate>,
+    {
+        self.request_param_update(stack, &params).await
+    }
+
+    /// Update connection parameters for this connection, and wait for the outcome.
+    ///
+    /// The update is requested as with [`Connection::update_connection_params`]. As peripheral,
+    /// the central may reject the request with `UNACCEPTABLE_CONN_PARAMETERS`. Resolves with the
+    /// parameters selected by the controller once the update is complete.
+    pub async fn update_connection_params_and_wait<T>(
+        &self,
+        stack: &Stack<'_, T>,
+        params: ConnectParams,
+    ) -> Result<ConnectionParams, BleHostError<T::Error>>
+    where
+        T: ControllerCmdAsync<LeConnUpdate>,
+    {
+        self.manager.start_param_update(self.index)?;
+        let _drop = OnDrop::new(|| self.manager.abort_param_update(self.index));
+        self.request_param_update(stack, &params).await?;
+
+        // A central might never respond to the request of a peripheral.
+        match with_timeout(
+            PARAM_UPDATE_TIMEOUT,
+            poll_fn(|cx| self.manager.poll_param_update(self.index, cx)),
+        )
+        .await
         {
-            Ok(_) => Ok(()),
-            Err(BleHostError::BleHost(crate::Error::Hci(bt_hci::param::Error::UNKNOWN_CONN_IDENTIFIER))) => {
-                Err(crate::Error::Disconnected.into())
+            Ok(result) => Ok(result?),
+            Err(_) => Err(crate::Error::Timeout.into()),
+        }
+    }
+
+    async fn request_param_update<T>(
+        &self,
+        stack: &Stack<'_, T>,
+        params: &ConnectParams,
+    ) -> Result<(), BleHostError<T::Error>>
+    where
+        T: ControllerCmdAsync<LeConnUpdate>,
+    {
+        let handle = self.handle();
+        match self.role() {
+            LeConnRole::Central => {
+                let (min_event_length, max_event_length) = params.event_lengths();
+                match stack
+                    .host
+                    .async_command(LeConnUpdate::new(
+                        handle,
+                        params.min_connection_interval.into(),
+                        params.max_connection_interval.into(),
+                        params.max_latency,
+                        params.supervision_timeout.into(),
+                        min_event_length.into(),
+                        max_event_length.into(),
+                    ))
+                    .await
+                {
+                    Ok(_) => {}
+                    Err(BleHostError::BleHost(e))
+                        if e.hci_status() == Some(bt_hci::param::Error::UNKNOWN_CONN_IDENTIFIER) =>
+                    {
+                        return Err(crate::Error::Disconnected.into());
+                    }
+                    Err(e) => return Err(e),
+                }
             }
-            Err(e) => Err(e),
+            LeConnRole::Peripheral => {
+                let identifier = stack.host.channels.next_request_id();
+                let req = ConnParamUpdateReq {
+                    interval_min: bt_hci::param::Duration::<1_250>::from(params.min_connection_interval).as_u16(),
+                    interval_max: bt_hci::param::Duration::<1_250>::from(params.max_connection_interval).as_u16(),
+                    latency: params.max_latency,
+                    timeout: bt_hci::param::Duration::<10_000>::from(params.supervision_timeout).as_u16(),
+                };
+                let mut tx = [0; 16];
+                stack.host.l2cap_signal(handle, identifier, &req, &mut tx[..]).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Request the preferred connection parameters of the peripheral, after the pause of the
+    /// peripheral following the establishment of the connection.
+    ///
+    /// The parameters are kept if they already match the preferences. Centrals such as iOS devices
+    /// accept the update when the preferences follow their guidelines, which favor long intervals.
+    pub async fn request_preferred_params<T>(
+        &self,
+        stack: &Stack<'_, T>,
+        preferred: &PreferredConnectionParameters,
+    ) -> Result<ConnectionParams, BleHostError<T::Error>>
+    where
+        T: ControllerCmdAsync<LeConnUpdate>,
+    {
+        Timer::after(CONN_PAUSE_PERIPHERAL).await;
+        let params = self.params();
+        if preferred.matches(&params) {
+            return Ok(params);
         }
+        self.update_connection_params_and_wait(stack, preferred.params()).await
+    }
+}
+
+// Response timeout of L2CAP signaling requests.
+const PARAM_UPDATE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Pause of a peripheral after the connection is established before updating its parameters,
+// TGAP(conn_pause_peripheral).
+const CONN_PAUSE_PERIPHERAL: Duration = Duration::from_secs(5);
+
+// RSSI reported by the controller when no measurement is available.
+const RSSI_NOT_AVAILABLE: i8 = 127;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connect_params_are_validated() {
+        assert!(ConnectParams::default().is_valid());
+        let mut params = ConnectParams::low_latency_hid();
+        // The peripheral latency would outlast the supervision timeout.
+        params.max_latency = 100;
+        assert!(!params.is_valid());
+
+        let mut params = ConnectParams {
+            event_length: Duration::from_millis(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            params.event_lengths(),
+            (Duration::from_millis(10), Duration::from_millis(10))
+        );
+        params.max_event_length = Duration::from_millis(20);
+        assert_eq!(
+            params.event_lengths(),
+            (Duration::from_millis(10), Duration::from_millis(20))
+        );
+        let params = ConnectParams::high_throughput();
+        assert!(params.event_lengths().1 <= params.min_connection_interval);
+    }
+
+    #[test]
+    fn preferred_params_encoding() {
+        let preferred = PreferredConnectionParameters::new(&ConnectParams::low_power());
+        assert_eq!(preferred.as_gatt(), &[80, 0, 160, 0, 4, 0, 0x58, 0x02]);
+        assert_eq!(preferred.params().max_connection_interval, Duration::from_millis(200));
+        let mut params = ConnectionParams {
+            conn_interval: Duration::from_millis(150),
+            peripheral_latency: 2,
+            supervision_timeout: Duration::from_secs(6),
+        };
+        assert!(preferred.matches(&params));
+        params.conn_interval = Duration::from_millis(30);
+        assert!(!preferred.matches(&params));
+    }
+
+    #[test]
+    fn subrate_params_are_validated() {
+        let mut params = SubrateParams {
+            subrate_min: 1,
+            subrate_max: 10,
+            max_latency: 4,
+            continuation_number: 2,
+            supervision_timeout: Duration::from_secs(2),
+        };
+        assert!(params.validate().is_ok());
+
+        params.continuation_number = 10;
+        assert!(params.validate().is_err());
+
+        // The subrated latency would exceed 500 connection events.
+        params.continuation_number = 0;
+        params.max_latency = 50;
+        assert!(params.validate().is_err());
     }
 }
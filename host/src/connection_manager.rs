@@ -2,16 +2,18 @@ use core::cell::RefCell;
 use core::future::poll_fn;
 use core::task::{Context, Poll};
 
-use bt_hci::param::{AddrKind, BdAddr, ConnHandle, DisconnectReason, LeConnRole, Status};
+use bt_hci::param::{AddrKind, BdAddr, ConnHandle, DisconnectReason, LeConnRole, LeFeatureMask, PhyKind, Status};
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::channel::Channel;
-use embassy_sync::waitqueue::WakerRegistration;
+use embassy_sync::waitqueue::{MultiWakerRegistration, WakerRegistration};
 
-use crate::connection::{Connection, ConnectionEventData};
+use crate::connection::{
+    Connection, ConnectionEventData, ConnectionParams, DataLength, RemoteVersion, SecurityLevel, Subrate, TxPowerReport,
+};
 #[cfg(feature = "gatt")]
 use crate::packet_pool::{Packet, Pool};
 use crate::pdu::Pdu;
-use crate::{Error, config};
+use crate::{Address, Error, config};
 
 struct State<'d> {
     connections: &'d mut [ConnectionStorage],
@@ -20,7 +22,9 @@ struct State<'d> {
     disconnect_waker: WakerRegistration,
     #[cfg(feature = "controller-host-flow-control")]
     completed_packets_waker: WakerRegistration,
-    default_link_credits: usize,
+    // Number of ACL buffers in the controller, shared by all connections.
+    total_link_credits: usize,
+    link_credits: usize,
     default_att_mtu: u16,
 }
 
@@ -33,6 +37,19 @@ impl State<'_> {
         }
     }
 
+    // Return the credits of packets no longer held by the controller, and wake connections waiting for them.
+    fn release_credits(&mut self, index: usize, packets: usize) {
+        let storage = &mut self.connections[index];
+        let packets = packets.min(storage.tx_pending);
+        storage.tx_pending -= packets;
+        self.link_credits += packets;
+        for storage in self.connections.iter_mut() {
+            if storage.tx_waiting {
+                storage.link_credit_waker.wake();
+            }
+        }
+    }
+
     fn inc_ref(&mut self, index: u8) {
         let state = &mut self.connections[index as usize];
         state.refcount = unwrap!(
@@ -50,8 +67,8 @@ impl EventChannel {
     #[allow(clippy::declare_interior_mutable_const)]
     pub(crate) const NEW: EventChannel = EventChannel { chan: Channel::new() };
 
-    pub async fn receive(&self) -> ConnectionEventData {
-        self.chan.receive().await
+    pub fn poll_receive(&self, cx: &mut Context<'_>) -> Poll<ConnectionEventData> {
+        self.chan.poll_receive(cx)
     }
 
     pub async fn send(&self, event: ConnectionEventData) {
@@ -90,7 +107,8 @@ impl<'d> ConnectionManager<'d> {
                 disconnect_waker: WakerRegistration::new(),
                 #[cfg(feature = "controller-host-flow-control")]
                 completed_packets_waker: WakerRegistration::new(),
-                default_link_credits: 0,
+                total_link_credits: 0,
+                link_credits: 0,
                 default_att_mtu,
             }),
             events,
@@ -122,13 +140,43 @@ impl<'d> ConnectionManager<'d> {
     }
 
     pub(crate) async fn next(&self, index: u8) -> ConnectionEventData {
-        self.events[index as usize].receive().await
+        poll_fn(|cx| self.poll_next(index, cx)).await
+    }
+
+    /// Poll the next event of a connection.
+    ///
+    /// Pending informational events come first, then the queued GATT PDUs, and the disconnection last.
+    pub(crate) fn poll_next(&self, index: u8, cx: &mut Context<'_>) -> Poll<ConnectionEventData> {
+        let event = self.with_mut(|state| {
+            let storage = &mut state.connections[index as usize];
+            storage.event_waker.register(cx.waker());
+            storage.take_pending_event()
+        });
+        if let Some(event) = event {
+            return Poll::Ready(event);
+        }
+        if let Poll::Ready(event) = self.events[index as usize].poll_receive(cx) {
+            return Poll::Ready(event);
+        }
+        self.with_mut(
+            |state| match state.connections[index as usize].pending_events.disconnected.take() {
+                Some(reason) => Poll::Ready(ConnectionEventData::Disconnected { reason }),
+                None => Poll::Pending,
+            },
+        )
     }
 
     pub(crate) async fn post_event(&self, index: u8, event: ConnectionEventData) {
-        self.events[index as usize].send(event).await
+        match event {
+            ConnectionEventData::Gatt { .. } => self.events[index as usize].send(event).await,
+            event => self.with_mut(|state| state.connections[index as usize].post_pending_event(event)),
+        }
     }
 
+    /// Post an event to a connection.
+    ///
+    /// Only GATT PDUs take a slot of the event queue, and fail if it is full. Other events are
+    /// coalesced with the pending events of the same kind.
     pub(crate) fn post_handle_event(&self, handle: ConnHandle, event: ConnectionEventData) -> Result<(), Error> {
         let index = self.with_mut(|state| {
             for (index, entry) in state.connections.iter().enumerate() {
@@ -138,7 +186,13 @@ impl<'d> ConnectionManager<'d> {
             }
             Err(Error::NotFound)
         })?;
-        self.events[index].try_send(event).map_err(|_| Error::OutOfMemory)
+        match event {
+            ConnectionEventData::Gatt { .. } => self.events[index].try_send(event).map_err(|_| Error::OutOfMemory),
+            event => {
+                self.with_mut(|state| state.connections[index].post_pending_event(event));
+                Ok(())
+            }
+        }
     }
 
     pub(crate) fn peer_address(&self, index: u8) -> BdAddr {
@@ -148,6 +202,289 @@ impl<'d> ConnectionManager<'d> {
         })
     }
 
+    pub(crate) fn peer(&self, index: u8) -> Address {
+        self.with_mut(|state| {
+            let state = &mut state.connections[index as usize];
+            Address {
+                kind: unwrap!(state.peer_addr_kind),
+                addr: unwrap!(state.peer_addr),
+            }
+        })
+    }
+
+    /// Record the resolvable private addresses used on a link, reported as zero when not used.
+    pub(crate) fn set_private_addresses(&self, handle: ConnHandle, local: BdAddr, peer: BdAddr) -> Result<(), Error> {
+        let used = |addr: BdAddr| (addr.raw() != [0; 6]).then_some(addr);
+        self.with_connected_handle(handle, |storage| {
+            storage.local_rpa = used(local);
+            storage.peer_rpa = used(peer);
+            Ok(())
+        })
+    }
+
+    /// Record that a pairing timed out on a link, after which no more pairing may take place on it.
+    pub(crate) fn set_smp_timed_out(&self, handle: ConnHandle) -> Result<(), Error> {
+        self.with_connected_handle(handle, |storage| {
+            storage.smp_timed_out = true;
+            Ok(())
+        })
+    }
+
+    /// The addresses, role and security level of a link.
+    pub(crate) fn link_info(&self, handle: ConnHandle) -> Option<LinkInfo> {
+        let state = self.state.borrow();
+        state
+            .connections
+            .iter()
+            .find(|storage| {
+                storage.handle == Some(handle)
+                    && matches!(storage.state, ConnectionState::Connected | ConnectionState::Connecting)
+            })
+            .map(|storage| LinkInfo {
+                role: unwrap!(storage.role),
+                peer: Address {
+                    kind: unwrap!(storage.peer_addr_kind),
+                    addr: unwrap!(storage.peer_addr),
+                },
+                local_rpa: storage.local_rpa,
+                peer_rpa: storage.peer_rpa,
+                security_level: storage.security_level,
+                smp_timed_out: storage.smp_timed_out,
+            })
+    }
+
+    pub(crate) fn security_level(&self, index: u8) -> SecurityLevel {
+        self.with_mut(|state| state.connections[index as usize].security_level)
+    }
+
+    pub(crate) fn set_security_level(&self, handle: ConnHandle, level: SecurityLevel) -> Result<(), Error> {
+        self.with_connected_handle(handle, |storage| {
+            storage.post_pending_event(ConnectionEventData::SecurityChanged { security_level: level });
+            Ok(())
+        })
+    }
+
+    pub(crate) fn connection_params(&self, index: u8) -> ConnectionParams {
+        self.with_mut(|state| state.connections[index as usize].params)
+    }
+
+    pub(crate) fn set_connection_params(&self, handle: ConnHandle, params: ConnectionParams) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        for storage in state.connections.iter_mut() {
+            if storage.state != ConnectionState::Disconnected && storage.handle == Some(handle) {
+                storage.params = params;
+                return Ok(());
+            }
+        }
+        Err(Error::NotFound)
+    }
+
+    pub(crate) fn data_length(&self, index: u8) -> DataLength {
+        self.with_mut(|state| state.connections[index as usize].data_length)
+    }
+
+    pub(crate) fn data_length_changed(&self, handle: ConnHandle, data_length: DataLength) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        for storage in state.connections.iter_mut() {
+            if storage.state != ConnectionState::Disconnected && storage.handle == Some(handle) {
+                storage.post_pending_event(ConnectionEventData::DataLengthChanged { data_length });
+                return Ok(());
+            }
+        }
+        Err(Error::NotFound)
+    }
+
+    /// Handle the completion of a connection parameter update, initiated by either side.
+    pub(crate) fn connection_params_updated(
+        &self,
+        handle: ConnHandle,
+        result: Result<ConnectionParams, bt_hci::param::Error>,
+    ) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        for storage in state.connections.iter_mut() {
+            if storage.state == ConnectionState::Connected && storage.handle == Some(handle) {
+                if let Ok(params) = result {
+                    storage.post_pending_event(ConnectionEventData::ConnectionParamsUpdated { params });
+                }
+                if let ParamUpdate::Pending = storage.param_update {
+                    storage.param_update = ParamUpdate::Done(result);
+                    storage.param_update_waker.wake();
+                }
+                return Ok(());
+            }
+        }
+        Err(Error::NotFound)
+    }
+
+    /// Handle the rejection of a connection parameter update request by the central.
+    pub(crate) fn param_update_rejected(&self, handle: ConnHandle) -> Result<(), Error> {
+        self.with_connected_handle(handle, |storage| {
+            if let ParamUpdate::Pending = storage.param_update {
+                storage.param_update = ParamUpdate::Done(Err(bt_hci::param::Error::UNACCEPTABLE_CONN_PARAMETERS));
+                storage.param_update_waker.wake();
+            }
+            Ok(())
+        })
+    }
+
+    pub(crate) fn start_param_update(&self, index: u8) -> Result<(), Error> {
+        self.with_mut(|state| {
+            let storage = &mut state.connections[index as usize];
+            match storage.param_update {
+                ParamUpdate::Idle => {
+                    storage.param_update = ParamUpdate::Pending;
+                    Ok(())
+                }
+                _ => Err(Error::Busy),
+            }
+        })
+    }
+
+    pub(crate) fn abort_param_update(&self, index: u8) {
+        self.with_mut(|state| {
+            state.connections[index as usize].param_update = ParamUpdate::Idle;
+        })
+    }
+
+    pub(crate) fn poll_param_update(&self, index: u8, cx: &mut Context<'_>) -> Poll<Result<ConnectionParams, Error>> {
+        self.with_mut(|state| {
+            let storage = &mut state.connections[index as usize];
+            storage.param_update_waker.register(cx.waker());
+            match storage.param_update {
+                ParamUpdate::Done(result) => {
+                    storage.param_update = ParamUpdate::Idle;
+                    Poll::Ready(result.map_err(Error::Hci))
+                }
+                _ if storage.state != ConnectionState::Connected => Poll::Ready(Err(Error::Disconnected)),
+                _ => Poll::Pending,
+            }
+        })
+    }
+
+    /// Returns true if the features must be read from the peer.
+    pub(crate) fn request_remote_features(&self, index: u8) -> Result<bool, Error> {
+        self.request_remote_info(index, |storage| &mut storage.remote_features)
+    }
+
+    pub(crate) fn abort_remote_features(&self, index: u8) {
+        self.abort_remote_info(index, |storage| &mut storage.remote_features)
+    }
+
+    pub(crate) fn remote_features_read(
+        &self,
+        handle: ConnHandle,
+        result: Result<LeFeatureMask, bt_hci::param::Error>,
+    ) -> Result<(), Error> {
+        self.remote_info_read(handle, result, |storage| &mut storage.remote_features)
+    }
+
+    pub(crate) fn poll_remote_features(
+        &self,
+        index: u8,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<LeFeatureMask, Error>>> {
+        self.poll_remote_info(index, cx, |storage| &mut storage.remote_features)
+    }
+
+    /// Returns true if the version must be read from the peer.
+    pub(crate) fn request_remote_version(&self, index: u8) -> Result<bool, Error> {
+        self.request_remote_info(index, |storage| &mut storage.remote_version)
+    }
+
+    pub(crate) fn abort_remote_version(&self, index: u8) {
+        self.abort_remote_info(index, |storage| &mut storage.remote_version)
+    }
+
+    pub(crate) fn remote_version_read(
+        &self,
+        handle: ConnHandle,
+        result: Result<RemoteVersion, bt_hci::param::Error>,
+    ) -> Result<(), Error> {
+        self.remote_info_read(handle, result, |storage| &mut storage.remote_version)
+    }
+
+    pub(crate) fn poll_remote_version(
+        &self,
+        index: u8,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<RemoteVersion, Error>>> {
+        self.poll_remote_info(index, cx, |storage| &mut storage.remote_version)
+    }
+
+    fn request_remote_info<T>(
+        &self,
+        index: u8,
+        info: impl FnOnce(&mut ConnectionStorage) -> &mut RemoteInfo<T>,
+    ) -> Result<bool, Error> {
+        self.with_mut(|state| {
+            let storage = &mut state.connections[index as usize];
+            if storage.state != ConnectionState::Connected {
+                return Err(Error::Disconnected);
+            }
+            let info = info(storage);
+            match info {
+                RemoteInfo::Unknown | RemoteInfo::Failed(_) => {
+                    *info = RemoteInfo::Pending;
+                    Ok(true)
+                }
+                RemoteInfo::Pending | RemoteInfo::Known(_) => Ok(false),
+            }
+        })
+    }
+
+    fn abort_remote_info<T>(&self, index: u8, info: impl FnOnce(&mut ConnectionStorage) -> &mut RemoteInfo<T>) {
+        self.with_mut(|state| {
+            let storage = &mut state.connections[index as usize];
+            let info = info(storage);
+            if let RemoteInfo::Pending = info {
+                *info = RemoteInfo::Unknown;
+            } else {
+                return;
+            }
+            // Wake tasks waiting for the aborted request, so that one of them can restart it.
+            storage.remote_info_waker.wake();
+        })
+    }
+
+    fn remote_info_read<T>(
+        &self,
+        handle: ConnHandle,
+        result: Result<T, bt_hci::param::Error>,
+        info: impl FnOnce(&mut ConnectionStorage) -> &mut RemoteInfo<T>,
+    ) -> Result<(), Error> {
+        self.with_connected_handle(handle, |storage| {
+            *info(storage) = match result {
+                Ok(value) => RemoteInfo::Known(value),
+                Err(e) => RemoteInfo::Failed(e),
+            };
+            storage.remote_info_waker.wake();
+            Ok(())
+        })
+    }
+
+    /// Returns `None` if the pending request was aborted before completing.
+    fn poll_remote_info<T: Copy>(
+        &self,
+        index: u8,
+        cx: &mut Context<'_>,
+        info: impl FnOnce(&mut ConnectionStorage) -> &mut RemoteInfo<T>,
+    ) -> Poll<Option<Result<T, Error>>> {
+        self.with_mut(|state| {
+            let storage = &mut state.connections[index as usize];
+            let disconnected = storage.state != ConnectionState::Connected;
+            match *info(storage) {
+                RemoteInfo::Known(value) => Poll::Ready(Some(Ok(value))),
+                RemoteInfo::Failed(e) => Poll::Ready(Some(Err(Error::Hci(e)))),
+                RemoteInfo::Unknown => Poll::Ready(None),
+                RemoteInfo::Pending if disconnected => Poll::Ready(Some(Err(Error::Disconnected))),
+                RemoteInfo::Pending => {
+                    storage.remote_info_waker.register(cx.waker());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+
     pub(crate) fn set_att_mtu(&self, index: u8, mtu: u16) {
         self.with_mut(|state| {
             state.connections[index as usize].att_mtu = mtu;
@@ -164,6 +501,18 @@ impl<'d> ConnectionManager<'d> {
         })
     }
 
+    pub(crate) fn poll_disconnected(&self, index: u8, cx: &mut Context<'_>) -> Poll<()> {
+        self.with_mut(|state| {
+            let storage = &mut state.connections[index as usize];
+            if storage.state == ConnectionState::Disconnected {
+                Poll::Ready(())
+            } else {
+                storage.disconnected_waker.register(cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+
     pub(crate) fn completed_packets(&self, _handle: ConnHandle, _amount: u16) {
         #[cfg(feature = "controller-host-flow-control")]
         self.with_mut(|state| {
@@ -182,7 +531,9 @@ impl<'d> ConnectionManager<'d> {
     pub(crate) fn request_handle_disconnect(&self, handle: ConnHandle, reason: DisconnectReason) {
         self.with_mut(|state| {
             for entry in state.connections.iter_mut() {
-                if entry.state == ConnectionState::Connected && Some(handle) == entry.handle {
+                if matches!(entry.state, ConnectionState::Connecting | ConnectionState::Connected)
+                    && Some(handle) == entry.handle
+                {
                     entry.state = ConnectionState::DisconnectRequest(reason);
                     state.disconnect_waker.wake();
                     break;
@@ -247,6 +598,19 @@ impl<'d> ConnectionManager<'d> {
         Poll::Pending
     }
 
+    pub(crate) fn connections(&'d self) -> impl Iterator<Item = Connection<'d>> + 'd {
+        let len = self.state.borrow().connections.len();
+        (0..len).filter_map(move |index| {
+            let mut state = self.state.borrow_mut();
+            if state.connections[index].state == ConnectionState::Connected {
+                state.inc_ref(index as u8);
+                Some(Connection::new(index as u8, self))
+            } else {
+                None
+            }
+        })
+    }
+
     pub(crate) fn get_connected_handle(&'d self, h: ConnHandle) -> Option<Connection<'d>> {
         let mut state = self.state.borrow_mut();
         for (index, storage) in state.connections.iter().enumerate() {
@@ -289,18 +653,55 @@ impl<'d> ConnectionManager<'d> {
         })
     }
 
+    pub(crate) fn is_peer_connected(&self, kind: AddrKind, addr: &BdAddr) -> bool {
+        // Identity addresses resolved by the controller are reported with the address kinds of
+        // resolvable private addresses.
+        let identity_kind = |kind: AddrKind| {
+            if kind == AddrKind::RESOLVABLE_PRIVATE_OR_PUBLIC {
+                AddrKind::PUBLIC
+            } else if kind == AddrKind::RESOLVABLE_PRIVATE_OR_RANDOM {
+                AddrKind::RANDOM
+            } else {
+                kind
+            }
+        };
+        let state = self.state.borrow();
+        state.connections.iter().any(|storage| {
+            matches!(storage.state, ConnectionState::Connecting | ConnectionState::Connected)
+                && storage.peer_addr_kind.map(identity_kind) == Some(identity_kind(kind))
+                && storage.peer_addr.as_ref() == Some(addr)
+        })
+    }
+
     pub(crate) fn is_handle_connected(&self, h: ConnHandle) -> bool {
         self.with_connected_handle(h, |_storage| Ok(())).is_ok()
     }
 
+    /// Handle of a link that is not disconnected, if any.
+    pub(crate) fn active_handle(&self) -> Option<ConnHandle> {
+        let state = self.state.borrow();
+        state
+            .connections
+            .iter()
+            .find(|storage| storage.state != ConnectionState::Disconnected)
+            .and_then(|storage| storage.handle)
+    }
+
     pub(crate) fn disconnected(&self, h: ConnHandle, reason: Status) -> Result<(), Error> {
         let mut state = self.state.borrow_mut();
         for (idx, storage) in state.connections.iter_mut().enumerate() {
             if Some(h) == storage.handle && storage.state != ConnectionState::Disconnected {
                 storage.state = ConnectionState::Disconnected;
-                let _ = self.events[idx].try_send(ConnectionEventData::Disconnected { reason });
+                storage.param_update_waker.wake();
+                storage.remote_info_waker.wake();
+                storage.disconnected_waker.wake();
+                storage.tx_waiting = false;
+                storage.post_pending_event(ConnectionEventData::Disconnected { reason });
                 #[cfg(feature = "connection-metrics")]
                 storage.metrics.reset();
+                // The controller flushes the packets of a disconnected link.
+                let pending = storage.tx_pending;
+                state.release_credits(idx, pending);
                 return Ok(());
             }
         }
@@ -316,21 +717,30 @@ impl<'d> ConnectionManager<'d> {
         role: LeConnRole,
     ) -> Result<(), Error> {
         let mut state = self.state.borrow_mut();
-        let default_credits = state.default_link_credits;
         let default_att_mtu = state.default_att_mtu;
         for (idx, storage) in state.connections.iter_mut().enumerate() {
             if ConnectionState::Disconnected == storage.state && storage.refcount == 0 {
                 self.events[idx].clear();
                 storage.state = ConnectionState::Connecting;
-                storage.link_credits = default_credits;
+                storage.tx_pending = 0;
+                storage.tx_waiting = false;
                 #[cfg(feature = "controller-host-flow-control")]
                 {
                     storage.completed_packets = 0;
                 }
                 storage.att_mtu = default_att_mtu;
+                storage.security_level = SecurityLevel::NoEncryption;
+                storage.pending_events = PendingEvents::NONE;
+                storage.param_update = ParamUpdate::Idle;
+                storage.data_length = DataLength::DEFAULT;
+                storage.remote_features = RemoteInfo::Unknown;
+                storage.remote_version = RemoteInfo::Unknown;
                 storage.handle.replace(handle);
                 storage.peer_addr_kind.replace(peer_addr_kind);
                 storage.peer_addr.replace(peer_addr);
+                storage.local_rpa = None;
+                storage.peer_rpa = None;
+                storage.smp_timed_out = false;
                 storage.role.replace(role);
                 match role {
                     LeConnRole::Central => {
@@ -435,10 +845,8 @@ impl<'d> ConnectionManager<'d> {
 
     pub(crate) fn set_link_credits(&self, credits: usize) {
         let mut state = self.state.borrow_mut();
-        state.default_link_credits = credits;
-        for storage in state.connections.iter_mut() {
-            storage.link_credits = credits;
-        }
+        state.total_link_credits = credits;
+        state.link_credits = credits;
     }
 
     pub(crate) fn set_default_att_mtu(&self, att_mtu: u16) {
@@ -448,17 +856,13 @@ impl<'d> ConnectionManager<'d> {
 
     pub(crate) fn confirm_sent(&self, handle: ConnHandle, packets: usize) -> Result<(), Error> {
         let mut state = self.state.borrow_mut();
-        for storage in state.connections.iter_mut() {
-            match storage.state {
-                ConnectionState::Connected if handle == storage.handle.unwrap() => {
-                    storage.link_credits += packets;
-                    storage.link_credit_waker.wake();
-                    return Ok(());
-                }
-                _ => {}
-            }
-        }
-        Err(Error::NotFound)
+        let index = state
+            .connections
+            .iter()
+            .position(|storage| storage.state != ConnectionState::Disconnected && storage.handle == Some(handle))
+            .ok_or(Error::NotFound)?;
+        state.release_credits(index, packets);
+        Ok(())
     }
 
     pub(crate) fn poll_request_to_send(
@@ -468,31 +872,49 @@ impl<'d> ConnectionManager<'d> {
         cx: Option<&mut Context<'_>>,
     ) -> Poll<Result<PacketGrant<'_, 'd>, Error>> {
         let mut state = self.state.borrow_mut();
-        for storage in state.connections.iter_mut() {
-            match storage.state {
-                ConnectionState::Connected if storage.handle.unwrap() == handle => {
-                    if packets <= storage.link_credits {
-                        storage.link_credits -= packets;
+        let Some(index) = state
+            .connections
+            .iter()
+            .position(|storage| storage.state == ConnectionState::Connected && storage.handle == Some(handle))
+        else {
+            trace!("[link][pool_request_to_send] connection {:?} not found", handle);
+            return Poll::Ready(Err(Error::NotFound));
+        };
 
-                        return Poll::Ready(Ok(PacketGrant::new(&self.state, handle, packets)));
-                    } else {
-                        if let Some(cx) = cx {
-                            storage.link_credit_waker.register(cx.waker());
-                        }
-                        debug!(
-                            "[link][poll_request_to_send][conn = {}] requested {} available {}",
-                            handle.raw(),
-                            packets,
-                            storage.link_credits
-                        );
-                        return Poll::Pending;
-                    }
-                }
-                _ => {}
+        // While other connections are waiting for credits, each connection is limited to an
+        // equal share of the controller buffers so that a busy connection can't starve the others.
+        let waiting = state
+            .connections
+            .iter()
+            .enumerate()
+            .filter(|(i, storage)| *i != index && storage.tx_waiting)
+            .count();
+        let share = match waiting {
+            0 => usize::MAX,
+            n => (state.total_link_credits / (n + 1)).max(1),
+        };
+
+        let available = state.link_credits;
+        let storage = &mut state.connections[index];
+        if packets <= available && (storage.tx_pending == 0 || storage.tx_pending + packets <= share) {
+            storage.tx_pending += packets;
+            storage.tx_waiting = false;
+            state.link_credits -= packets;
+            Poll::Ready(Ok(PacketGrant::new(&self.state, handle, packets)))
+        } else {
+            if let Some(cx) = cx {
+                storage.tx_waiting = true;
+                storage.link_credit_waker.register(cx.waker());
             }
+            debug!(
+                "[link][poll_request_to_send][conn = {}] requested {} available {} pending {}",
+                handle.raw(),
+                packets,
+                available,
+                storage.tx_pending
+            );
+            Poll::Pending
         }
-        trace!("[link][pool_request_to_send] connection {:?} not found", handle);
-        Poll::Ready(Err(Error::NotFound))
     }
 
     pub(crate) fn get_att_mtu(&self, index: u8) -> u16 {
@@ -600,15 +1022,98 @@ impl CompletedPackets<'_, '_> {
     }
 }
 
-#[derive(Debug)]
+/// The addresses, role and security level of a link.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LinkInfo {
+    pub(crate) role: LeConnRole,
+    pub(crate) peer: Address,
+    pub(crate) local_rpa: Option<BdAddr>,
+    pub(crate) peer_rpa: Option<BdAddr>,
+    pub(crate) security_level: SecurityLevel,
+    pub(crate) smp_timed_out: bool,
+}
+
+/// Informational events of a connection not yet returned by `Connection::next`.
+///
+/// They are coalesced to the latest value rather than queued, so that a burst of them can neither
+/// fill the event queue, which only holds GATT PDUs, nor push out the disconnection. The values of
+/// the security level, connection parameters and data length are those of the connection storage.
+#[derive(Debug, Clone, Copy)]
+pub struct PendingEvents {
+    pub security_changed: bool,
+    pub params_updated: bool,
+    pub phy_changed: Option<(PhyKind, PhyKind)>,
+    pub data_length_changed: bool,
+    pub tx_power_reported: Option<TxPowerReport>,
+    pub subrate_changed: Option<Subrate>,
+    pub authenticated_payload_timeout_expired: bool,
+    pub disconnected: Option<Status>,
+}
+
+impl PendingEvents {
+    pub(crate) const NONE: PendingEvents = PendingEvents {
+        security_changed: false,
+        params_updated: false,
+        phy_changed: None,
+        data_length_changed: false,
+        tx_power_reported: None,
+        subrate_changed: None,
+        authenticated_payload_timeout_expired: false,
+        disconnected: None,
+    };
+}
+
+/// Information about the peer, read from the peer on request.
+#[derive(Debug, Clone, Copy)]
+pub enum RemoteInfo<T> {
+    /// Not read yet, or the read was abandoned.
+    Unknown,
+    /// Read requested, waiting for the controller to report it.
+    Pending,
+    /// Read from the peer.
+    Known(T),
+    /// The read failed with the given status, and is retried on the next request.
+    Failed(bt_hci::param::Error),
+}
+
+/// State of a connection parameter update requested by the host.
+#[derive(Debug, Clone, Copy)]
+pub enum ParamUpdate {
+    /// No update is waited for.
+    Idle,
+    /// An update was requested, and is waiting for its completion or rejection.
+    Pending,
+    /// The update completed with the given parameters, or failed, and is waiting to be taken.
+    Done(Result<ConnectionParams, bt_hci::param::Error>),
+}
+
+// Number of tasks that can wait for the disconnection of the same connection before they are all
+// woken to register again.
+const DISCONNECT_WAITERS: usize = 4;
+
 pub struct ConnectionStorage {
     pub state: ConnectionState,
     pub handle: Option<ConnHandle>,
     pub role: Option<LeConnRole>,
     pub peer_addr_kind: Option<AddrKind>,
     pub peer_addr: Option<BdAddr>,
+    pub local_rpa: Option<BdAddr>,
+    pub peer_rpa: Option<BdAddr>,
+    pub smp_timed_out: bool,
     pub att_mtu: u16,
-    pub link_credits: usize,
+    pub security_level: SecurityLevel,
+    pub pending_events: PendingEvents,
+    pub event_waker: WakerRegistration,
+    pub params: ConnectionParams,
+    pub data_length: DataLength,
+    pub param_update: ParamUpdate,
+    pub param_update_waker: WakerRegistration,
+    pub remote_features: RemoteInfo<LeFeatureMask>,
+    pub remote_version: RemoteInfo<RemoteVersion>,
+    pub remote_info_waker: WakerRegistration,
+    pub disconnected_waker: MultiWakerRegistration<DISCONNECT_WAITERS>,
+    pub tx_pending: usize,
+    pub tx_waiting: bool,
     pub link_credit_waker: WakerRegistration,
     #[cfg(feature = "controller-host-flow-control")]
     pub completed_packets: u16,
@@ -673,8 +1178,23 @@ impl ConnectionStorage {
         role: None,
         peer_addr_kind: None,
         peer_addr: None,
+        local_rpa: None,
+        peer_rpa: None,
+        smp_timed_out: false,
         att_mtu: 23,
-        link_credits: 0,
+        security_level: SecurityLevel::NoEncryption,
+        pending_events: PendingEvents::NONE,
+        event_waker: WakerRegistration::new(),
+        params: ConnectionParams::new(),
+        data_length: DataLength::DEFAULT,
+        param_update: ParamUpdate::Idle,
+        param_update_waker: WakerRegistration::new(),
+        remote_features: RemoteInfo::Unknown,
+        remote_version: RemoteInfo::Unknown,
+        remote_info_waker: WakerRegistration::new(),
+        disconnected_waker: MultiWakerRegistration::new(),
+        tx_pending: 0,
+        tx_waiting: false,
         #[cfg(feature = "controller-host-flow-control")]
         completed_packets: 0,
         link_credit_waker: WakerRegistration::new(),
@@ -682,6 +1202,78 @@ impl ConnectionStorage {
         #[cfg(feature = "connection-metrics")]
         metrics: Metrics::new(),
     };
+
+    fn post_pending_event(&mut self, event: ConnectionEventData) {
+        let pending = &mut self.pending_events;
+        match event {
+            ConnectionEventData::SecurityChanged { security_level } => {
+                self.security_level = security_level;
+                pending.security_changed = true;
+            }
+            ConnectionEventData::ConnectionParamsUpdated { params } => {
+                self.params = params;
+                pending.params_updated = true;
+            }
+            ConnectionEventData::PhyChanged { tx_phy, rx_phy } => pending.phy_changed = Some((tx_phy, rx_phy)),
+            ConnectionEventData::DataLengthChanged { data_length } => {
+                self.data_length = data_length;
+                pending.data_length_changed = true;
+            }
+            ConnectionEventData::TxPowerReported { report } => pending.tx_power_reported = Some(report),
+            ConnectionEventData::SubrateChanged { subrate } => pending.subrate_changed = Some(subrate),
+            ConnectionEventData::AuthenticatedPayloadTimeoutExpired => {
+                pending.authenticated_payload_timeout_expired = true;
+            }
+            ConnectionEventData::Disconnected { reason } => pending.disconnected = Some(reason),
+            ConnectionEventData::Gatt { .. } => unreachable!(),
+        }
+        self.event_waker.wake();
+    }
+
+    /// Take the first pending informational event, excluding the disconnection.
+    fn take_pending_event(&mut self) -> Option<ConnectionEventData> {
+        let pending = &mut self.pending_events;
+        if core::mem::take(&mut pending.security_changed) {
+            Some(ConnectionEventData::SecurityChanged {
+                security_level: self.security_level,
+            })
+        } else if core::mem::take(&mut pending.params_updated) {
+            Some(ConnectionEventData::ConnectionParamsUpdated { params: self.params })
+        } else if let Some((tx_phy, rx_phy)) = pending.phy_changed.take() {
+            Some(ConnectionEventData::PhyChanged { tx_phy, rx_phy })
+        } else if core::mem::take(&mut pending.data_length_changed) {
+            Some(ConnectionEventData::DataLengthChanged {
+                data_length: self.data_length,
+            })
+        } else if let Some(report) = pending.tx_power_reported.take() {
+            Some(ConnectionEventData::TxPowerReported { report })
+        } else if let Some(subrate) = pending.subrate_changed.take() {
+            Some(ConnectionEventData::SubrateChanged { subrate })
+        } else if core::mem::take(&mut pending.authenticated_payload_timeout_expired) {
+            Some(ConnectionEventData::AuthenticatedPayloadTimeoutExpired)
+        } else {
+            None
+        }
+    }
+}
+
+// Written by hand, as the waker registrations do not implement `Debug`.
+impl core::fmt::Debug for ConnectionStorage {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut s = f.debug_struct("ConnectionStorage");
+        s.field("state", &self.state)
+            .field("handle", &self.handle)
+            .field("tx_pending", &self.tx_pending);
+        #[cfg(feature = "controller-host-flow-control")]
+        s.field("completed_packets", &self.completed_packets);
+        s.field("role", &self.role)
+            .field("peer_addr", &self.peer_addr)
+            .field("security_level", &self.security_level)
+            .field("refcount", &self.refcount);
+        #[cfg(feature = "connection-metrics")]
+        s.field("metrics", &self.metrics);
+        s.finish_non_exhaustive()
+    }
 }
 
 #[cfg(feature = "defmt")]
@@ -692,21 +1284,22 @@ impl defmt::Format for ConnectionStorage {
             "state = {}, conn = {}, flow = {}",
             self.state,
             self.handle,
-            self.link_credits,
+            self.tx_pending,
         );
 
         #[cfg(feature = "controller-host-flow-control")]
         defmt::write!(f, ", completed = {}", self.completed_packets);
         defmt::write!(
             f,
-            ", role = {}, peer = {:02x}, ref = {}",
+            ", role = {}, peer = {:02x}, security = {}, ref = {}",
             self.role,
             self.peer_addr,
+            self.security_level,
             self.refcount
         );
 
         #[cfg(feature = "connection-metrics")]
-        defmt::write!(", {}", self.metrics);
+        defmt::write!(f, ", {}", self.metrics);
     }
 }
 
@@ -753,18 +1346,15 @@ impl Drop for PacketGrant<'_, '_> {
     fn drop(&mut self) {
         if self.packets > 0 {
             let mut state = self.state.borrow_mut();
-            for storage in state.connections.iter_mut() {
-                match storage.state {
-                    ConnectionState::Connected if self.handle == storage.handle.unwrap() => {
-                        storage.link_credits += self.packets;
-                        storage.link_credit_waker.wake();
-                        return;
-                    }
-                    _ => {}
-                }
+            let index = state
+                .connections
+                .iter()
+                .position(|storage| storage.state == ConnectionState::Connected && storage.handle == Some(self.handle));
+            match index {
+                Some(index) => state.release_credits(index, self.packets),
+                // The credits were returned on disconnect.
+                None => warn!("[link] connection {:?} not found", self.handle),
             }
-            // make it an assert?
-            warn!("[link] connection {:?} not found", self.handle);
         }
     }
 }
@@ -830,6 +1420,31 @@ mod tests {
         assert_eq!(handle.peer_address(), BdAddr::new(ADDR_2));
     }
 
+    #[test]
+    fn all_links_lost_on_controller_reset() {
+        let mgr = setup();
+        unwrap!(mgr.connect(
+            ConnHandle::new(1),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_1),
+            LeConnRole::Central
+        ));
+        unwrap!(mgr.connect(
+            ConnHandle::new(2),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_2),
+            LeConnRole::Peripheral
+        ));
+
+        let mut lost = 0;
+        while let Some(handle) = mgr.active_handle() {
+            unwrap!(mgr.disconnected(handle, Status::HARDWARE_FAILURE));
+            lost += 1;
+        }
+        assert_eq!(lost, 2);
+        assert!(mgr.active_handle().is_none());
+    }
+
     #[test]
     fn controller_disconnects_before_host() {
         let mgr = setup();
@@ -941,6 +1556,21 @@ mod tests {
         assert!(mgr.poll_disconnecting(None).is_pending());
     }
 
+    #[test]
+    fn resolved_peers_are_connected_by_identity() {
+        let mgr = setup();
+
+        unwrap!(mgr.connect(
+            ConnHandle::new(0),
+            AddrKind::RESOLVABLE_PRIVATE_OR_RANDOM,
+            BdAddr::new(ADDR_2),
+            LeConnRole::Central
+        ));
+        assert!(mgr.is_peer_connected(AddrKind::RANDOM, &BdAddr::new(ADDR_2)));
+        assert!(!mgr.is_peer_connected(AddrKind::PUBLIC, &BdAddr::new(ADDR_2)));
+        assert!(!mgr.is_peer_connected(AddrKind::RANDOM, &BdAddr::new(ADDR_1)));
+    }
+
     #[test]
     fn referenced_handle_not_reused() {
         let mgr = setup();
@@ -1074,4 +1704,257 @@ mod tests {
 
         assert!(!mgr.is_handle_connected(ConnHandle::new(3)));
     }
+
+    #[test]
+    fn security_level_reset_on_new_connection() {
+        let mgr = setup();
+
+        unwrap!(mgr.connect(
+            ConnHandle::new(3),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_1),
+            LeConnRole::Peripheral
+        ));
+
+        let Poll::Ready(handle) = mgr.poll_accept(LeConnRole::Peripheral, &[], None) else {
+            panic!("expected connection to be accepted");
+        };
+        assert_eq!(handle.security_level(), SecurityLevel::NoEncryption);
+
+        unwrap!(mgr.set_security_level(ConnHandle::new(3), SecurityLevel::Encrypted));
+        assert_eq!(handle.security_level(), SecurityLevel::Encrypted);
+        let ConnectionEventData::SecurityChanged { security_level } = block_on(mgr.next(0)) else {
+            panic!("expected security changed event");
+        };
+        assert_eq!(security_level, SecurityLevel::Encrypted);
+
+        unwrap!(mgr.disconnected(ConnHandle::new(3), Status::UNSPECIFIED));
+        drop(handle);
+
+        unwrap!(mgr.connect(
+            ConnHandle::new(4),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_2),
+            LeConnRole::Peripheral
+        ));
+        let Poll::Ready(handle) = mgr.poll_accept(LeConnRole::Peripheral, &[], None) else {
+            panic!("expected connection to be accepted");
+        };
+        assert_eq!(handle.security_level(), SecurityLevel::NoEncryption);
+    }
+
+    #[test]
+    fn informational_events_never_drop_gatt_or_disconnection() {
+        let mgr = setup();
+        unwrap!(mgr.connect(
+            ConnHandle::new(3),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_1),
+            LeConnRole::Peripheral
+        ));
+        let Poll::Ready(_handle) = mgr.poll_accept(LeConnRole::Peripheral, &[], None) else {
+            panic!("expected connection to be accepted");
+        };
+
+        let pool = PacketPool::<27, 64>::new();
+        for _ in 0..config::CONNECTION_EVENT_QUEUE_SIZE {
+            let data = Pdu::new(unwrap!(pool.alloc()), 0);
+            unwrap!(mgr.post_handle_event(ConnHandle::new(3), ConnectionEventData::Gatt { data }));
+        }
+        let data = Pdu::new(unwrap!(pool.alloc()), 0);
+        assert!(matches!(
+            mgr.post_handle_event(ConnHandle::new(3), ConnectionEventData::Gatt { data }),
+            Err(Error::OutOfMemory)
+        ));
+
+        unwrap!(mgr.set_security_level(ConnHandle::new(3), SecurityLevel::Encrypted));
+        unwrap!(mgr.set_security_level(ConnHandle::new(3), SecurityLevel::EncryptedAuthenticated));
+        for (tx_phy, rx_phy) in [(PhyKind::Le2M, PhyKind::Le1M), (PhyKind::Le2M, PhyKind::Le2M)] {
+            unwrap!(mgr.post_handle_event(ConnHandle::new(3), ConnectionEventData::PhyChanged { tx_phy, rx_phy }));
+        }
+        unwrap!(mgr.post_handle_event(
+            ConnHandle::new(3),
+            ConnectionEventData::AuthenticatedPayloadTimeoutExpired
+        ));
+        unwrap!(mgr.disconnected(ConnHandle::new(3), Status::UNSPECIFIED));
+
+        let ConnectionEventData::SecurityChanged { security_level } = block_on(mgr.next(0)) else {
+            panic!("expected security changed event");
+        };
+        assert_eq!(security_level, SecurityLevel::EncryptedAuthenticated);
+        let ConnectionEventData::PhyChanged { tx_phy, rx_phy } = block_on(mgr.next(0)) else {
+            panic!("expected phy changed event");
+        };
+        assert_eq!((tx_phy, rx_phy), (PhyKind::Le2M, PhyKind::Le2M));
+        assert!(matches!(
+            block_on(mgr.next(0)),
+            ConnectionEventData::AuthenticatedPayloadTimeoutExpired
+        ));
+        for _ in 0..config::CONNECTION_EVENT_QUEUE_SIZE {
+            assert!(matches!(block_on(mgr.next(0)), ConnectionEventData::Gatt { .. }));
+        }
+        assert!(matches!(
+            block_on(mgr.next(0)),
+            ConnectionEventData::Disconnected {
+                reason: Status::UNSPECIFIED
+            }
+        ));
+        let mut cx = Context::from_waker(core::task::Waker::noop());
+        assert!(mgr.poll_next(0, &mut cx).is_pending());
+    }
+
+    #[test]
+    fn param_update_resolves_on_completion() {
+        let mgr = setup();
+        unwrap!(mgr.connect(
+            ConnHandle::new(3),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_1),
+            LeConnRole::Peripheral
+        ));
+        let Poll::Ready(handle) = mgr.poll_accept(LeConnRole::Peripheral, &[], None) else {
+            panic!("expected connection to be accepted");
+        };
+        let mut cx = Context::from_waker(core::task::Waker::noop());
+        let params = ConnectionParams {
+            conn_interval: embassy_time::Duration::from_micros(7_500),
+            peripheral_latency: 0,
+            supervision_timeout: embassy_time::Duration::from_secs(4),
+        };
+
+        unwrap!(mgr.start_param_update(0));
+        assert!(matches!(mgr.start_param_update(0), Err(Error::Busy)));
+        unwrap!(mgr.param_update_rejected(ConnHandle::new(3)));
+        assert!(matches!(
+            mgr.poll_param_update(0, &mut cx),
+            Poll::Ready(Err(Error::Hci(bt_hci::param::Error::UNACCEPTABLE_CONN_PARAMETERS)))
+        ));
+
+        unwrap!(mgr.start_param_update(0));
+        assert!(mgr.poll_param_update(0, &mut cx).is_pending());
+        unwrap!(mgr.connection_params_updated(ConnHandle::new(3), Ok(params)));
+        assert!(matches!(mgr.poll_param_update(0, &mut cx), Poll::Ready(Ok(p)) if p == params));
+        assert_eq!(handle.params(), params);
+        let ConnectionEventData::ConnectionParamsUpdated { params: event } = block_on(mgr.next(0)) else {
+            panic!("expected connection params updated event");
+        };
+        assert_eq!(event, params);
+    }
+
+    #[test]
+    fn link_credits_are_shared_fairly() {
+        let mgr = setup();
+        mgr.set_link_credits(4);
+        unwrap!(mgr.connect(
+            ConnHandle::new(1),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_1),
+            LeConnRole::Central
+        ));
+        unwrap!(mgr.connect(
+            ConnHandle::new(2),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_2),
+            LeConnRole::Central
+        ));
+        let Poll::Ready(_a) = mgr.poll_accept(LeConnRole::Central, &[], None) else {
+            panic!("expected connection to be accepted");
+        };
+        let Poll::Ready(_b) = mgr.poll_accept(LeConnRole::Central, &[], None) else {
+            panic!("expected connection to be accepted");
+        };
+        assert_eq!(mgr.connections().count(), 2);
+        let mut cx = Context::from_waker(core::task::Waker::noop());
+        let (a, b) = (ConnHandle::new(1), ConnHandle::new(2));
+
+        // Without contention, a connection may use all credits.
+        let Poll::Ready(Ok(mut grant)) = mgr.poll_request_to_send(a, 3, Some(&mut cx)) else {
+            panic!("expected credits to be granted");
+        };
+        grant.confirm(3);
+        drop(grant);
+
+        // Credits are shared by the connections.
+        assert!(mgr.poll_request_to_send(b, 2, Some(&mut cx)).is_pending());
+        // The waiting connection limits the share of the busy connection.
+        assert!(mgr.poll_request_to_send(a, 1, Some(&mut cx)).is_pending());
+
+        unwrap!(mgr.confirm_sent(a, 3));
+        let Poll::Ready(Ok(mut grant)) = mgr.poll_request_to_send(b, 2, Some(&mut cx)) else {
+            panic!("expected credits to be granted");
+        };
+        grant.confirm(2);
+        drop(grant);
+        assert!(matches!(
+            mgr.poll_request_to_send(a, 2, Some(&mut cx)),
+            Poll::Ready(Ok(_))
+        ));
+
+        // Unused credits are returned when the grant is dropped.
+        assert!(matches!(
+            mgr.poll_request_to_send(a, 2, Some(&mut cx)),
+            Poll::Ready(Ok(_))
+        ));
+
+        // Credits of packets pending on a disconnected link are returned.
+        unwrap!(mgr.disconnected(b, Status::UNSPECIFIED));
+        assert!(matches!(
+            mgr.poll_request_to_send(a, 4, Some(&mut cx)),
+            Poll::Ready(Ok(_))
+        ));
+    }
+
+    #[test]
+    fn disconnect_completes_on_event() {
+        let mgr = setup();
+        unwrap!(mgr.connect(
+            ConnHandle::new(3),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_1),
+            LeConnRole::Peripheral
+        ));
+        let Poll::Ready(_handle) = mgr.poll_accept(LeConnRole::Peripheral, &[], None) else {
+            panic!("expected connection to be accepted");
+        };
+        let mut cx = Context::from_waker(core::task::Waker::noop());
+
+        mgr.request_disconnect(0, DisconnectReason::RemoteDeviceTerminatedConnPowerOff);
+        let Poll::Ready(request) = mgr.poll_disconnecting(None) else {
+            panic!("expected disconnect request");
+        };
+        assert_eq!(request.reason(), DisconnectReason::RemoteDeviceTerminatedConnPowerOff);
+        request.confirm();
+        assert!(mgr.poll_disconnected(0, &mut cx).is_pending());
+
+        unwrap!(mgr.disconnected(ConnHandle::new(3), Status::UNSPECIFIED));
+        assert!(mgr.poll_disconnected(0, &mut cx).is_ready());
+    }
+
+    #[test]
+    fn remote_features_are_cached() {
+        let mgr = setup();
+        unwrap!(mgr.connect(
+            ConnHandle::new(3),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_1),
+            LeConnRole::Central
+        ));
+        let Poll::Ready(_handle) = mgr.poll_accept(LeConnRole::Central, &[], None) else {
+            panic!("expected connection to be accepted");
+        };
+        let mut cx = Context::from_waker(core::task::Waker::noop());
+        let features = LeFeatureMask::new().set_le_2m_phy(true);
+
+        assert!(unwrap!(mgr.request_remote_features(0)));
+        // A request that is already pending is shared.
+        assert!(!unwrap!(mgr.request_remote_features(0)));
+        mgr.abort_remote_features(0);
+        assert!(matches!(mgr.poll_remote_features(0, &mut cx), Poll::Ready(None)));
+
+        assert!(unwrap!(mgr.request_remote_features(0)));
+        assert!(mgr.poll_remote_features(0, &mut cx).is_pending());
+        unwrap!(mgr.remote_features_read(ConnHandle::new(3), Ok(features)));
+        assert!(matches!(mgr.poll_remote_features(0, &mut cx), Poll::Ready(Some(Ok(f))) if f == features));
+        assert!(!unwrap!(mgr.request_remote_features(0)));
+    }
 }
@@ -0,0 +1,193 @@
+//! Events of the host as a whole.
+//!
+//! Each connection reports its own events through `Connection::next`. The host also publishes
+//! the changes of all connections, along with the end of advertising sets, to the listeners
+//! returned by [`Stack::events`](crate::Stack::events), so that a supervisory task can follow the
+//! state of the host without holding the connections.
+//!
+//! With the `security` feature, the progress of pairing is published as well, from
+//! [`HostEvent::PairingStarted`] to [`HostEvent::PairingComplete`] or [`HostEvent::PairingFailed`],
+//! the encryption of the link with the keys of the pairing being reported by
+//! [`HostEvent::SecurityChanged`].
+//!
+//! The events are buffered up to `HOST_EVENT_QUEUE_SIZE` for each listener, a listener not
+//! keeping up missing the oldest ones as reported by [`HostEvent::Lagged`].
+use bt_hci::param::{AdvHandle, ConnHandle, LeConnRole, PhyKind, Status};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::pubsub::{DynSubscriber, PubSubChannel, WaitResult};
+
+use crate::connection::{ConnectionParams, SecurityLevel};
+use crate::{Address, config};
+
+pub(crate) type HostEventChannel = PubSubChannel<
+    NoopRawMutex,
+    HostEvent,
+    { config::HOST_EVENT_QUEUE_SIZE },
+    { config::HOST_EVENT_MAX_SUBSCRIBERS },
+    1,
+>;
+
+/// An event of the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum HostEvent {
+    /// A connection was established.
+    Connected {
+        /// The handle of the connection.
+        handle: ConnHandle,
+        /// The role of the local device.
+        role: LeConnRole,
+        /// The address of the peer.
+        peer: Address,
+        /// The parameters of the connection.
+        params: ConnectionParams,
+    },
+    /// A connection was disconnected.
+    Disconnected {
+        /// The handle of the connection.
+        handle: ConnHandle,
+        /// The reason (status code) for the disconnect.
+        reason: Status,
+    },
+    /// Encryption was enabled, disabled or the encryption key was refreshed.
+    SecurityChanged {
+        /// The handle of the connection.
+        handle: ConnHandle,
+        /// The security level of the link after the change.
+        security_level: SecurityLevel,
+    },
+    /// The connection parameters were changed, by either side of the connection.
+    ConnectionParamsUpdated {
+        /// The handle of the connection.
+        handle: ConnHandle,
+        /// The connection parameters after the change.
+        params: ConnectionParams,
+    },
+    /// The PHYs of the connection were changed, by either side of the connection.
+    PhyChanged {
+        /// The handle of the connection.
+        handle: ConnHandle,
+        /// The transmitter PHY.
+        tx_phy: PhyKind,
+        /// The receiver PHY.
+        rx_phy: PhyKind,
+    },
+    /// An extended advertising set stopped advertising.
+    AdvertisingTerminated {
+        /// The handle of the advertising set.
+        adv_handle: AdvHandle,
+        /// Why the set stopped, success meaning a connection was established.
+        status: Status,
+        /// The connection established, if any.
+        connection: Option<ConnHandle>,
+    },
+    /// The security manager started pairing with the peer, on request of either side.
+    #[cfg(feature = "security")]
+    PairingStarted {
+        /// The handle of the connection.
+        handle: ConnHandle,
+    },
+    /// The passkey to display to the user, who enters it on the peer to authenticate the pairing.
+    #[cfg(feature = "security")]
+    PasskeyDisplay {
+        /// The handle of the connection.
+        handle: ConnHandle,
+        /// The six digit passkey.
+        passkey: u32,
+    },
+    /// The devices exchanged the keys of a bonding pairing. The link is encrypted, and its
+    /// security level reported by [`HostEvent::SecurityChanged`].
+    #[cfg(feature = "security")]
+    KeysDistributed {
+        /// The handle of the connection.
+        handle: ConnHandle,
+        /// The identity address of the peer, as distributed with its keys.
+        identity: Address,
+    },
+    /// The keys of the peer were stored in the bond table, listed by `Stack::bonds`.
+    #[cfg(feature = "security")]
+    Bonded {
+        /// The handle of the connection.
+        handle: ConnHandle,
+        /// The identity address of the peer.
+        identity: Address,
+    },
+    /// A pairing completed.
+    #[cfg(feature = "security")]
+    PairingComplete {
+        /// The handle of the connection.
+        handle: ConnHandle,
+        /// The security level of the keys of the pairing.
+        security_level: SecurityLevel,
+    },
+    /// A pairing failed, aborted by either side.
+    #[cfg(feature = "security")]
+    PairingFailed {
+        /// The handle of the connection.
+        handle: ConnHandle,
+        /// The reason of the failure.
+        reason: crate::security_manager::Reason,
+    },
+    /// A pairing failed as the peer did not answer within 30 seconds. No more pairing can take
+    /// place on the connection, which must be reconnected to pair.
+    #[cfg(feature = "security")]
+    PairingTimeout {
+        /// The handle of the connection.
+        handle: ConnHandle,
+    },
+    /// The listener missed events, not keeping up with the host.
+    Lagged {
+        /// The number of events missed.
+        missed: u64,
+    },
+}
+
+/// Listener of the events of the host, returned by [`Stack::events`](crate::Stack::events).
+pub struct HostEventListener<'d> {
+    subscriber: DynSubscriber<'d, HostEvent>,
+}
+
+impl<'d> HostEventListener<'d> {
+    pub(crate) fn new(subscriber: DynSubscriber<'d, HostEvent>) -> Self {
+        Self { subscriber }
+    }
+
+    /// Wait for the next event of the host.
+    pub async fn next(&mut self) -> HostEvent {
+        match self.subscriber.next_message().await {
+            WaitResult::Message(event) => event,
+            WaitResult::Lagged(missed) => HostEvent::Lagged { missed },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    #[test]
+    fn listener_reports_missed_events() {
+        let channel = HostEventChannel::new();
+        let mut listener = HostEventListener::new(channel.dyn_subscriber().unwrap());
+        let publisher = channel.immediate_publisher();
+        let handle = ConnHandle::new(1);
+        for _ in 0..config::HOST_EVENT_QUEUE_SIZE + 2 {
+            publisher.publish_immediate(HostEvent::Disconnected {
+                handle,
+                reason: Status::UNSPECIFIED,
+            });
+        }
+
+        assert_eq!(block_on(listener.next()), HostEvent::Lagged { missed: 2 });
+        assert_eq!(
+            block_on(listener.next()),
+            HostEvent::Disconnected {
+                handle,
+                reason: Status::UNSPECIFIED
+            }
+        );
+    }
+}
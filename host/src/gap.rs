@@ -9,12 +9,16 @@
 
 use embassy_sync::blocking_mutex::raw::RawMutex;
 use heapless::String;
-use static_cell::StaticCell;
 
 use crate::prelude::*;
 
+/// Standard values of the Appearance characteristic, from the assigned numbers.
+///
+/// Example: `appearance::sensor::GENERIC_SENSOR`.
+pub use bt_hci::uuid::appearance;
+
 /// Advertising packet is limited to 31 bytes. 9 of these are used by other GAP data, leaving 22 bytes for the Device Name characteristic
-const DEVICE_NAME_MAX_LENGTH: usize = 22;
+pub const DEVICE_NAME_MAX_LENGTH: usize = 22;
 
 /// The number of attributes added by the GAP and GATT services
 /// GAP_SERVICE:       1
@@ -23,8 +27,16 @@ const DEVICE_NAME_MAX_LENGTH: usize = 22;
 /// GATT_SERVICE:    + 1
 ///                  ---
 ///                  = 6
+///
+/// See [`GapServiceConfig::attribute_count`] for the count of a configuration.
 pub const GAP_SERVICE_ATTRIBUTE_COUNT: usize = 6;
 
+/// The number of attributes added to the GAP service by the Peripheral Preferred Connection
+/// Parameters characteristic, when configured.
+pub const GAP_PPCP_ATTRIBUTE_COUNT: usize = 2;
+
+const CENTRAL_PPCP_ERROR: &str = "Preferred connection parameters are those of a peripheral";
+
 /// Configuration for the GAP Service.
 pub enum GapConfig<'a> {
     /// Peripheral device configuration.
@@ -42,7 +54,13 @@ pub struct PeripheralConfig<'a> {
     /// Example: `&appearance::sensor::GENERIC_SENSOR.`
     pub appearance: &'a BluetoothUuid16,
     // TODO: Add more GAP parameters
-    // pub preferred_connection_parameters: Option<ConnectionParameters>,
+}
+
+impl<'a> PeripheralConfig<'a> {
+    /// Create a configuration with a name and an appearance.
+    pub const fn new(name: &'a str, appearance: &'a BluetoothUuid16) -> Self {
+        Self { name, appearance }
+    }
 }
 
 /// Configuration for a central device GAP Service.
@@ -56,41 +74,288 @@ pub struct CentralConfig<'a> {
     // TODO: Add more GAP parameters
 }
 
+impl<'a> CentralConfig<'a> {
+    /// Create a configuration with a name and an appearance.
+    pub const fn new(name: &'a str, appearance: &'a BluetoothUuid16) -> Self {
+        Self { name, appearance }
+    }
+}
+
 impl<'a> GapConfig<'a> {
     /// Create a default peripheral configuration.
     ///
     /// This configuration will use the `UNKNOWN` appearance.
     pub fn default(name: &'a str) -> Self {
-        GapConfig::Peripheral(PeripheralConfig {
-            name,
-            appearance: &appearance::UNKNOWN,
-        })
+        GapConfig::Peripheral(PeripheralConfig::new(name, &appearance::UNKNOWN))
+    }
+
+    /// Let the peer write the name of the device.
+    ///
+    /// Requires building the service with [`GapServiceConfig::build_with_storage`].
+    pub const fn name_writable(self, writable: bool) -> GapServiceConfig<'a> {
+        GapServiceConfig::new(self).name_writable(writable)
+    }
+
+    /// Expose the connection parameters preferred by the peripheral to the central.
+    ///
+    /// See [`GapServiceConfig::preferred_connection_parameters`].
+    pub const fn preferred_connection_parameters(
+        self,
+        params: &'a PreferredConnectionParameters,
+    ) -> GapServiceConfig<'a> {
+        GapServiceConfig::new(self).preferred_connection_parameters(params)
     }
 
     /// Add the GAP config to the attribute table
+    ///
+    /// The name and the appearance are fixed, see [`GapConfig::build_with_storage`] to update them
+    /// at runtime.
     pub fn build<M: RawMutex, const MAX: usize>(
         self,
         table: &mut AttributeTable<'a, M, MAX>,
     ) -> Result<(), &'static str> {
-        match self {
-            GapConfig::Peripheral(config) => config.build(table),
+        GapServiceConfig::new(self).build(table)
+    }
+
+    /// Add the GAP config to the attribute table, with the name and the appearance held in the
+    /// storage to be updated at runtime through the returned [`GapService`].
+    pub fn build_with_storage<M: RawMutex, const MAX: usize>(
+        self,
+        table: &mut AttributeTable<'a, M, MAX>,
+        storage: &'a mut GapStorage,
+    ) -> Result<GapService, &'static str> {
+        GapServiceConfig::new(self).build_with_storage(table, storage)
+    }
+}
+
+/// A [`GapConfig`] with the options of the GAP service, created by the option methods of the
+/// configuration, such as [`GapConfig::name_writable`].
+pub struct GapServiceConfig<'a> {
+    config: GapConfig<'a>,
+    name_writable: bool,
+    preferred_connection_parameters: Option<&'a PreferredConnectionParameters>,
+}
+
+impl<'a> From<GapConfig<'a>> for GapServiceConfig<'a> {
+    fn from(config: GapConfig<'a>) -> Self {
+        Self::new(config)
+    }
+}
+
+impl<'a> GapServiceConfig<'a> {
+    const fn new(config: GapConfig<'a>) -> Self {
+        Self {
+            config,
+            name_writable: false,
+            preferred_connection_parameters: None,
+        }
+    }
+
+    /// Let the peer write the name of the device.
+    ///
+    /// Requires building the service with [`GapServiceConfig::build_with_storage`].
+    pub const fn name_writable(mut self, writable: bool) -> Self {
+        self.name_writable = writable;
+        self
+    }
+
+    /// Expose the connection parameters preferred by the peripheral to the central.
+    ///
+    /// The characteristic adds [`GAP_PPCP_ATTRIBUTE_COUNT`] attributes to the table, so servers
+    /// of the `gatt_server` macro make room for them with `attribute_table_size`. See
+    /// [`Connection::request_preferred_params`] to also request them after connecting. Building
+    /// the service of a central with them fails.
+    pub const fn preferred_connection_parameters(mut self, params: &'a PreferredConnectionParameters) -> Self {
+        self.preferred_connection_parameters = Some(params);
+        self
+    }
+
+    /// The number of attributes added by the GAP and GATT services with this configuration.
+    pub const fn attribute_count(&self) -> usize {
+        match self.preferred_connection_parameters {
+            Some(_) => GAP_SERVICE_ATTRIBUTE_COUNT + GAP_PPCP_ATTRIBUTE_COUNT,
+            None => GAP_SERVICE_ATTRIBUTE_COUNT,
+        }
+    }
+
+    /// Add the GAP config to the attribute table
+    ///
+    /// The name and the appearance are fixed, see [`GapServiceConfig::build_with_storage`] to
+    /// update them at runtime.
+    pub fn build<M: RawMutex, const MAX: usize>(
+        self,
+        table: &mut AttributeTable<'a, M, MAX>,
+    ) -> Result<(), &'static str> {
+        if self.name_writable {
+            return Err("A writable device name requires a GapStorage");
+        }
+        match self.config {
+            GapConfig::Peripheral(config) => config.build(table, self.preferred_connection_parameters),
+            GapConfig::Central(_) if self.preferred_connection_parameters.is_some() => Err(CENTRAL_PPCP_ERROR),
             GapConfig::Central(config) => config.build(table),
         }
     }
+
+    /// Add the GAP config to the attribute table, with the name and the appearance held in the
+    /// storage to be updated at runtime through the returned [`GapService`].
+    pub fn build_with_storage<M: RawMutex, const MAX: usize>(
+        self,
+        table: &mut AttributeTable<'a, M, MAX>,
+        storage: &'a mut GapStorage,
+    ) -> Result<GapService, &'static str> {
+        let (name_writable, preferred) = (self.name_writable, self.preferred_connection_parameters);
+        let (name, appearance) = match self.config {
+            GapConfig::Peripheral(config) => (config.name, *config.appearance),
+            GapConfig::Central(_) if preferred.is_some() => return Err(CENTRAL_PPCP_ERROR),
+            GapConfig::Central(config) => (config.name, *config.appearance),
+        };
+        let name: String<DEVICE_NAME_MAX_LENGTH> =
+            String::try_from(name).map_err(|_| "Device name is too long. Max length is 22 bytes")?;
+
+        let mut gap_builder = table.add_service(Service::new(service::GAP));
+        let props: &[CharacteristicProp] = if name_writable {
+            &[CharacteristicProp::Read, CharacteristicProp::Write]
+        } else {
+            &[CharacteristicProp::Read]
+        };
+        let device_name = gap_builder
+            .add_characteristic(characteristic::DEVICE_NAME, props, name, &mut storage.name)
+            .build();
+        let appearance = gap_builder
+            .add_characteristic(
+                characteristic::APPEARANCE,
+                &[CharacteristicProp::Read],
+                appearance,
+                &mut storage.appearance,
+            )
+            .build();
+        if let Some(preferred) = preferred {
+            gap_builder.add_characteristic_ro(characteristic::PERIPHERAL_PREFERRED_CONNECTION_PARAMETERS, preferred);
+        }
+        gap_builder.build();
+
+        table.add_service(Service::new(service::GATT));
+
+        Ok(GapService {
+            device_name,
+            appearance,
+            name_writable,
+        })
+    }
+}
+
+/// Storage of the name and the appearance of a [`GapService`].
+pub struct GapStorage {
+    name: [u8; DEVICE_NAME_MAX_LENGTH],
+    appearance: [u8; 2],
+}
+
+impl GapStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            name: [0; DEVICE_NAME_MAX_LENGTH],
+            appearance: [0; 2],
+        }
+    }
+}
+
+impl Default for GapStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A write of the peer, handled by [`GapService::process`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum GapEvent {
+    /// The peer changed the name of the device.
+    NameChanged(String<DEVICE_NAME_MAX_LENGTH>),
+}
+
+/// The GAP service built with [`GapConfig::build_with_storage`], whose name and appearance are
+/// updated at runtime.
+///
+/// The writes of the peer to the name, if writable, are handled with [`GapService::process`]
+/// before being accepted.
+pub struct GapService {
+    device_name: Characteristic<String<DEVICE_NAME_MAX_LENGTH>>,
+    appearance: Characteristic<BluetoothUuid16>,
+    name_writable: bool,
+}
+
+impl GapService {
+    /// The Device Name characteristic.
+    pub fn device_name(&self) -> &Characteristic<String<DEVICE_NAME_MAX_LENGTH>> {
+        &self.device_name
+    }
+
+    /// The Appearance characteristic.
+    pub fn appearance(&self) -> &Characteristic<BluetoothUuid16> {
+        &self.appearance
+    }
+
+    /// The name of the device.
+    pub fn name<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+    ) -> Result<String<DEVICE_NAME_MAX_LENGTH>, Error> {
+        self.device_name.get(server)
+    }
+
+    /// Change the name of the device.
+    ///
+    /// Returns [`Error::InvalidValue`] if the name is longer than [`DEVICE_NAME_MAX_LENGTH`]. The
+    /// name advertised is to be changed by the application.
+    pub fn set_name<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        name: &str,
+    ) -> Result<(), Error> {
+        let name = String::try_from(name).map_err(|_| Error::InvalidValue)?;
+        self.device_name.set(server, &name)
+    }
+
+    /// Change the appearance of the device.
+    pub fn set_appearance<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        appearance: BluetoothUuid16,
+    ) -> Result<(), Error> {
+        self.appearance.set(server, &appearance)
+    }
+
+    /// Handle a write of the peer to an attribute, returning `None` if it is not one of the service.
+    ///
+    /// The write is accepted when the event is returned, and rejected with the error otherwise.
+    pub fn process(&self, handle: u16, data: &[u8]) -> Result<Option<GapEvent>, AttErrorCode> {
+        if !self.name_writable || handle != self.device_name.handle {
+            return Ok(None);
+        }
+        let name = core::str::from_utf8(data).map_err(|_| AttErrorCode::VALUE_NOT_ALLOWED)?;
+        let name = String::try_from(name).map_err(|_| AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH)?;
+        Ok(Some(GapEvent::NameChanged(name)))
+    }
 }
 
 impl<'a> PeripheralConfig<'a> {
     /// Add the peripheral GAP config to the attribute table
-    fn build<M: RawMutex, const MAX: usize>(self, table: &mut AttributeTable<'a, M, MAX>) -> Result<(), &'static str> {
-        static PERIPHERAL_NAME: StaticCell<String<DEVICE_NAME_MAX_LENGTH>> = StaticCell::new();
-        let peripheral_name = PERIPHERAL_NAME.init(String::new());
-        peripheral_name
-            .push_str(self.name)
-            .map_err(|_| "Device name is too long. Max length is 22 bytes")?;
+    fn build<M: RawMutex, const MAX: usize>(
+        self,
+        table: &mut AttributeTable<'a, M, MAX>,
+        preferred: Option<&'a PreferredConnectionParameters>,
+    ) -> Result<(), &'static str> {
+        if self.name.len() > DEVICE_NAME_MAX_LENGTH {
+            return Err("Device name is too long. Max length is 22 bytes");
+        }
 
         let mut gap_builder = table.add_service(Service::new(service::GAP));
-        gap_builder.add_characteristic_ro(characteristic::DEVICE_NAME, peripheral_name);
+        gap_builder.add_characteristic_ro_bytes(characteristic::DEVICE_NAME, self.name.as_bytes());
         gap_builder.add_characteristic_ro(characteristic::APPEARANCE, self.appearance);
+        if let Some(preferred) = preferred {
+            gap_builder.add_characteristic_ro(characteristic::PERIPHERAL_PREFERRED_CONNECTION_PARAMETERS, preferred);
+        }
         gap_builder.build();
 
         table.add_service(Service::new(service::GATT));
@@ -102,14 +367,12 @@ impl<'a> PeripheralConfig<'a> {
 impl<'a> CentralConfig<'a> {
     /// Add the peripheral GAP config to the attribute table
     fn build<M: RawMutex, const MAX: usize>(self, table: &mut AttributeTable<'a, M, MAX>) -> Result<(), &'static str> {
-        static CENTRAL_NAME: StaticCell<String<DEVICE_NAME_MAX_LENGTH>> = StaticCell::new();
-        let central_name = CENTRAL_NAME.init(String::new());
-        central_name
-            .push_str(self.name)
-            .map_err(|_| "Device name is too long. Max length is 22 bytes")?;
+        if self.name.len() > DEVICE_NAME_MAX_LENGTH {
+            return Err("Device name is too long. Max length is 22 bytes");
+        }
 
         let mut gap_builder = table.add_service(Service::new(service::GAP));
-        gap_builder.add_characteristic_ro(characteristic::DEVICE_NAME, central_name);
+        gap_builder.add_characteristic_ro_bytes(characteristic::DEVICE_NAME, self.name.as_bytes());
         gap_builder.add_characteristic_ro(characteristic::APPEARANCE, self.appearance);
         gap_builder.build();
 
@@ -118,3 +381,112 @@ impl<'a> CentralConfig<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_time::Duration;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+
+    #[test]
+    fn gap_service_per_table() {
+        let mut first: AttributeTable<'_, NoopRawMutex, GAP_SERVICE_ATTRIBUTE_COUNT> = AttributeTable::new();
+        let mut second: AttributeTable<'_, NoopRawMutex, GAP_SERVICE_ATTRIBUTE_COUNT> = AttributeTable::new();
+        assert!(GapConfig::default("first").build(&mut first).is_ok());
+        assert!(GapConfig::default("second").build(&mut second).is_ok());
+        assert!(
+            GapConfig::default("a name that is too long for the advertisement")
+                .build(&mut AttributeTable::<NoopRawMutex, GAP_SERVICE_ATTRIBUTE_COUNT>::new())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn configuration_literals_build() {
+        let config = PeripheralConfig {
+            name: "device",
+            appearance: &appearance::sensor::GENERIC_SENSOR,
+        };
+        let mut table: AttributeTable<'_, NoopRawMutex, GAP_SERVICE_ATTRIBUTE_COUNT> = AttributeTable::new();
+        assert!(GapConfig::Peripheral(config).build(&mut table).is_ok());
+
+        let config = CentralConfig {
+            name: "device",
+            appearance: &appearance::UNKNOWN,
+        };
+        let mut table: AttributeTable<'_, NoopRawMutex, GAP_SERVICE_ATTRIBUTE_COUNT> = AttributeTable::new();
+        assert!(GapConfig::Central(config).build(&mut table).is_ok());
+
+        // The name written by the peer is held in the storage.
+        let mut table: AttributeTable<'_, NoopRawMutex, GAP_SERVICE_ATTRIBUTE_COUNT> = AttributeTable::new();
+        assert!(
+            GapConfig::default("device")
+                .name_writable(true)
+                .build(&mut table)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn preferred_connection_parameters_are_sized_by_configuration() {
+        let params = PreferredConnectionParameters::new(&ConnectParams {
+            min_connection_interval: Duration::from_millis(30),
+            max_connection_interval: Duration::from_millis(50),
+            supervision_timeout: Duration::from_secs(4),
+            ..Default::default()
+        });
+        let config = GapServiceConfig::from(GapConfig::default("device"));
+        assert_eq!(config.attribute_count(), GAP_SERVICE_ATTRIBUTE_COUNT);
+        let central = GapConfig::Central(CentralConfig::new("device", &appearance::UNKNOWN));
+        let mut table: AttributeTable<'_, NoopRawMutex, 8> = AttributeTable::new();
+        assert!(
+            central
+                .preferred_connection_parameters(&params)
+                .build(&mut table)
+                .is_err()
+        );
+        let config = GapConfig::default("device").preferred_connection_parameters(&params);
+        assert_eq!(config.attribute_count(), 8);
+
+        let mut table: AttributeTable<'_, NoopRawMutex, 8> = AttributeTable::new();
+        assert!(config.build(&mut table).is_ok());
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+        let (_, value) = client
+            .read_by_type(&server, characteristic::PERIPHERAL_PREFERRED_CONNECTION_PARAMETERS)
+            .unwrap();
+        assert_eq!(&value[..], &[24, 0, 40, 0, 0, 0, 0x90, 0x01]);
+    }
+
+    #[test]
+    fn gap_service_updates_name() {
+        let mut storage = GapStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, GAP_SERVICE_ATTRIBUTE_COUNT> = AttributeTable::new();
+        let gap = GapConfig::Central(CentralConfig::new("central", &appearance::computer::LAPTOP))
+            .name_writable(true)
+            .build_with_storage(&mut table, &mut storage)
+            .unwrap();
+        let server = AttributeServer::new(table);
+        assert_eq!(gap.name(&server).unwrap(), "central");
+        assert_eq!(gap.appearance().get(&server).unwrap(), appearance::computer::LAPTOP);
+
+        gap.set_name(&server, "a longer central name").unwrap();
+        assert_eq!(gap.name(&server).unwrap(), "a longer central name");
+        gap.set_name(&server, "short").unwrap();
+        assert_eq!(gap.name(&server).unwrap(), "short");
+        assert!(
+            gap.set_name(&server, "a name that is too long for the advertisement")
+                .is_err()
+        );
+
+        let handle = gap.device_name().handle;
+        assert_eq!(
+            gap.process(handle, b"renamed"),
+            Ok(Some(GapEvent::NameChanged(String::try_from("renamed").unwrap())))
+        );
+        assert_eq!(gap.process(handle, &[0xff]), Err(AttErrorCode::VALUE_NOT_ALLOWED));
+        assert_eq!(gap.process(gap.appearance().handle, &[0, 0]), Ok(None));
+    }
+}
@@ -20,6 +20,7 @@ use crate::connection::Connection;
 use crate::connection_manager::ConnectionManager;
 use crate::cursor::{ReadCursor, WriteCursor};
 use crate::pdu::Pdu;
+use crate::procedure::{self, Procedure, Span};
 use crate::types::gatt_traits::{AsGatt, FromGatt, FromGattError};
 use crate::types::l2cap::L2capHeader;
 use crate::{BleHostError, Error, Stack, config};
@@ -30,6 +31,13 @@ pub struct GattData<'stack> {
     connection: Connection<'stack>,
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for GattData<'_> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "GattData {{ connection: {} }}", self.connection);
+    }
+}
+
 impl<'stack> Drop for GattData<'stack> {
     fn drop(&mut self) {
         if let Some(pdu) = self.pdu.take() {
@@ -122,6 +130,7 @@ impl<'stack> GattData<'stack> {
 }
 
 /// An event returned while processing GATT requests.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum GattEvent<'stack, 'server> {
     /// A characteristic was read.
     Read(ReadEvent<'stack, 'server>),
@@ -176,6 +185,18 @@ impl<'stack> ReadEvent<'stack, '_> {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for ReadEvent<'_, '_> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(
+            f,
+            "ReadEvent {{ handle: {}, connection: {} }}",
+            self.value_handle,
+            self.connection
+        );
+    }
+}
+
 impl Drop for ReadEvent<'_, '_> {
     fn drop(&mut self) {
         let handle = self.handle();
@@ -224,6 +245,18 @@ impl<'stack> WriteEvent<'stack, '_> {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for WriteEvent<'_, '_> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(
+            f,
+            "WriteEvent {{ handle: {}, connection: {} }}",
+            self.value_handle,
+            self.connection
+        );
+    }
+}
+
 impl Drop for WriteEvent<'_, '_> {
     fn drop(&mut self) {
         let handle = self.handle();
@@ -350,7 +383,7 @@ impl Drop for Reply<'_> {
 
 /// Notification listener for GATT client.
 pub struct NotificationListener<'lst, const MTU: usize> {
-    handle: u16,
+    handle: Option<u16>,
     listener: pubsub::DynSubscriber<'lst, Notification<MTU>>,
 }
 
@@ -360,7 +393,7 @@ impl<'lst, const MTU: usize> NotificationListener<'lst, MTU> {
     pub async fn next(&mut self) -> Notification<MTU> {
         loop {
             if let WaitResult::Message(m) = self.listener.next_message().await {
-                if m.handle == self.handle {
+                if self.handle.is_none_or(|h| h == m.handle) {
                     return m;
                 }
             }
@@ -368,6 +401,40 @@ impl<'lst, const MTU: usize> NotificationListener<'lst, MTU> {
     }
 }
 
+/// A procedure of the GATT client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum GattProcedure {
+    /// Discovering the services of the server.
+    DiscoverServices,
+    /// Discovering the characteristics of a service.
+    DiscoverCharacteristics,
+    /// Discovering the descriptors of a characteristic.
+    DiscoverDescriptors,
+    /// Reading a characteristic.
+    Read,
+    /// Writing a characteristic.
+    Write,
+    /// Subscribing to notifications or indications.
+    Subscribe,
+    /// Unsubscribing from notifications or indications.
+    Unsubscribe,
+}
+
+/// A GATT procedure failed with an error response of the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GattError {
+    /// The procedure that failed.
+    pub procedure: GattProcedure,
+    /// The connection to the server.
+    pub connection: ConnHandle,
+    /// The attribute handle reported by the server, the one in error or the first one requested.
+    pub handle: u16,
+    /// The error code of the server.
+    pub code: AttErrorCode,
+}
+
 const MAX_NOTIF: usize = config::GATT_CLIENT_NOTIFICATION_MAX_SUBSCRIBERS;
 const NOTIF_QSIZE: usize = config::GATT_CLIENT_NOTIFICATION_QUEUE_SIZE;
 
@@ -391,6 +458,13 @@ pub struct Notification<const MTU: usize> {
     len: usize,
 }
 
+impl<const MTU: usize> Notification<MTU> {
+    /// Handle of the characteristic the notification is for.
+    pub fn handle(&self) -> u16 {
+        self.handle
+    }
+}
+
 impl<const MTU: usize> AsRef<[u8]> for Notification<MTU> {
     fn as_ref(&self) -> &[u8] {
         &self.data[..self.len]
@@ -495,6 +569,7 @@ impl<'reference, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usiz
 
         let mut grant = stack.host.l2cap(connection.handle(), w.len() as u16, 1).await?;
         grant.send(w.finish()).await?;
+        procedure::begin(Procedure::MtuExchange, connection.handle());
 
         Ok(Self {
             known_services: RefCell::new(heapless::Vec::new()),
@@ -508,11 +583,26 @@ impl<'reference, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usiz
         })
     }
 
+    /// The connection of the client.
+    pub(crate) fn connection(&self) -> &Connection<'reference> {
+        &self.connection
+    }
+
+    fn gatt_error(&self, procedure: GattProcedure, handle: u16, code: AttErrorCode) -> Error {
+        Error::Gatt(GattError {
+            procedure,
+            connection: self.connection.handle(),
+            handle,
+            code,
+        })
+    }
+
     /// Discover primary services associated with a UUID.
     pub async fn services_by_uuid(
         &self,
         uuid: &Uuid,
     ) -> Result<Vec<ServiceHandle, MAX_SERVICES>, BleHostError<C::Error>> {
+        let _span = Span::begin(Procedure::Discovery, Some(self.connection.handle()));
         let mut start: u16 = 0x0001;
         let mut result = Vec::new();
 
@@ -531,7 +621,7 @@ impl<'reference, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usiz
                     if code == att::AttErrorCode::ATTRIBUTE_NOT_FOUND {
                         break;
                     }
-                    return Err(Error::Att(code).into());
+                    return Err(self.gatt_error(GattProcedure::DiscoverServices, handle, code).into());
                 }
                 AttRsp::FindByTypeValue { mut it } => {
                     let mut end: u16 = 0;
@@ -570,6 +660,7 @@ impl<'reference, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usiz
         service: &ServiceHandle,
         uuid: &Uuid,
     ) -> Result<Characteristic<T>, BleHostError<C::Error>> {
+        let _span = Span::begin(Procedure::Discovery, Some(self.connection.handle()));
         let mut start: u16 = service.start;
         loop {
             let data = att::AttReq::ReadByType {
@@ -616,12 +707,82 @@ impl<'reference, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usiz
                         }
                     }
                 }
-                AttRsp::Error { request, handle, code } => return Err(Error::Att(code).into()),
+                AttRsp::Error { request, handle, code } => {
+                    return Err(self
+                        .gatt_error(GattProcedure::DiscoverCharacteristics, handle, code)
+                        .into());
+                }
+                _ => {
+                    return Err(Error::InvalidValue.into());
+                }
+            }
+        }
+    }
+
+    /// Discover all characteristics in a given service using a UUID, such as the instances of a characteristic.
+    pub async fn characteristics_by_uuid<T: AsGatt, const N: usize>(
+        &self,
+        service: &ServiceHandle,
+        uuid: &Uuid,
+    ) -> Result<Vec<Characteristic<T>, N>, BleHostError<C::Error>> {
+        let _span = Span::begin(Procedure::Discovery, Some(self.connection.handle()));
+        let mut result = Vec::new();
+        let mut start: u16 = service.start;
+        while start <= service.end {
+            let data = att::AttReq::ReadByType {
+                start,
+                end: service.end,
+                attribute_type: CHARACTERISTIC.into(),
+            };
+            let response = self.request(data).await?;
+
+            match Self::response(response.pdu.as_ref())? {
+                AttRsp::ReadByType { mut it } => {
+                    let mut last = None;
+                    while let Some(Ok((decl_handle, item))) = it.next() {
+                        last = Some(decl_handle);
+                        if let AttributeData::Declaration {
+                            props,
+                            handle,
+                            uuid: decl_uuid,
+                        } = AttributeData::decode_declaration(item)?
+                        {
+                            if *uuid == decl_uuid {
+                                let cccd_handle =
+                                    if props.any(&[CharacteristicProp::Indicate, CharacteristicProp::Notify]) {
+                                        Some(self.get_characteristic_cccd(handle).await?.0)
+                                    } else {
+                                        None
+                                    };
+                                result
+                                    .push(Characteristic {
+                                        handle,
+                                        cccd_handle,
+                                        phantom: PhantomData,
+                                    })
+                                    .map_err(|_| Error::InsufficientSpace)?;
+                            }
+                        } else {
+                            return Err(Error::InvalidValue.into());
+                        }
+                    }
+                    match last {
+                        Some(handle) if handle < 0xffff => start = handle + 1,
+                        _ => break,
+                    }
+                }
+                AttRsp::Error { code, .. } if code == att::AttErrorCode::ATTRIBUTE_NOT_FOUND => break,
+                AttRsp::Error { request, handle, code } => {
+                    return Err(self
+                        .gatt_error(GattProcedure::DiscoverCharacteristics, handle, code)
+                        .into());
+                }
                 _ => {
                     return Err(Error::InvalidValue.into());
                 }
             }
         }
+        Ok(result)
     }
 
     async fn get_characteristic_cccd(&self, char_handle: u16) -> Result<(u16, CCCD), BleHostError<C::Error>> {
@@ -644,7 +805,9 @@ impl<'reference, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usiz
                     Err(Error::NotFound.into())
                 }
             }
-            AttRsp::Error { request, handle, code } => Err(Error::Att(code).into()),
+            AttRsp::Error { request, handle, code } => {
+                Err(self.gatt_error(GattProcedure::DiscoverDescriptors, handle, code).into())
+            }
             _ => Err(Error::InvalidValue.into()),
         }
     }
@@ -669,7 +832,7 @@ impl<'reference, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usiz
                 dest[..to_copy].copy_from_slice(&data[..to_copy]);
                 Ok(to_copy)
             }
-            AttRsp::Error { request, handle, code } => Err(Error::Att(code).into()),
+            AttRsp::Error { request, handle, code } => Err(self.gatt_error(GattProcedure::Read, handle, code).into()),
             _ => Err(Error::InvalidValue.into()),
         }
     }
@@ -701,7 +864,7 @@ impl<'reference, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usiz
                 }
                 Ok(to_copy)
             }
-            AttRsp::Error { request, handle, code } => Err(Error::Att(code).into()),
+            AttRsp::Error { request, handle, code } => Err(self.gatt_error(GattProcedure::Read, handle, code).into()),
             _ => Err(Error::InvalidValue.into()),
         }
     }
@@ -720,7 +883,7 @@ impl<'reference, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usiz
         let response = self.request(data).await?;
         match Self::response(response.pdu.as_ref())? {
             AttRsp::Write => Ok(()),
-            AttRsp::Error { request, handle, code } => Err(Error::Att(code).into()),
+            AttRsp::Error { request, handle, code } => Err(self.gatt_error(GattProcedure::Write, handle, code).into()),
             _ => Err(Error::InvalidValue.into()),
         }
     }
@@ -741,6 +904,48 @@ impl<'reference, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usiz
         Ok(())
     }
 
+    /// Write without waiting for a response to a characteristic described by a handle, signing the
+    /// write with the signing key of the bond instead of encrypting the link.
+    ///
+    /// The peer must be bonded with a signing key. On an encrypted link the write is sent as a
+    /// write command, as signed writes are only used on unencrypted links.
+    #[cfg(feature = "security")]
+    pub async fn write_characteristic_signed<T: FromGatt>(
+        &self,
+        handle: &Characteristic<T>,
+        buf: &[u8],
+    ) -> Result<(), BleHostError<C::Error>> {
+        let host = &self.stack.host;
+        let encrypted = host
+            .connections
+            .link_info(self.connection.handle())
+            .is_some_and(|link| link.security_level.encrypted());
+        if encrypted {
+            return self.write_characteristic_without_response(handle, buf).await;
+        }
+        let len = 3 + buf.len() + crate::security_manager::SIGNATURE_LEN;
+        if len > self.connection.att_mtu() as usize {
+            return Err(Error::InsufficientSpace.into());
+        }
+        let mut pdu = [0; L2CAP_MTU];
+        let mut w = WriteCursor::new(&mut pdu);
+        w.write_hci(&L2capHeader {
+            channel: crate::types::l2cap::L2CAP_CID_ATT,
+            length: len as u16,
+        })?;
+        w.write(att::ATT_SIGNED_WRITE_CMD)?;
+        w.write(handle.handle)?;
+        w.append(buf)?;
+        w.append(&[0; crate::security_manager::SIGNATURE_LEN])?;
+        let end = w.len();
+        host.security
+            .sign_write(host, self.connection.handle(), &mut pdu[4..end])?;
+
+        let mut grant = host.l2cap(self.connection.handle(), end as u16, 1).await?;
+        grant.send(&pdu[..end]).await?;
+        Ok(())
+    }
+
     /// Subscribe to indication/notification of a given Characteristic
     ///
     /// A listener is returned, which has a `next()` method
@@ -749,6 +954,32 @@ impl<'reference, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usiz
         characteristic: &Characteristic<T>,
         indication: bool,
     ) -> Result<NotificationListener<'_, L2CAP_MTU>, BleHostError<C::Error>> {
+        self.enable_notifications(characteristic, indication).await?;
+        let listener = self
+            .notifications
+            .dyn_subscriber()
+            .map_err(|_| Error::InsufficientSpace)?;
+        Ok(NotificationListener {
+            listener,
+            handle: Some(characteristic.handle),
+        })
+    }
+
+    /// Listen to the notifications and indications of all subscribed characteristics.
+    pub fn notifications(&self) -> Result<NotificationListener<'_, L2CAP_MTU>, Error> {
+        let listener = self
+            .notifications
+            .dyn_subscriber()
+            .map_err(|_| Error::InsufficientSpace)?;
+        Ok(NotificationListener { listener, handle: None })
+    }
+
+    /// Set the CCCD of a characteristic, without listening to its notifications.
+    pub(crate) async fn enable_notifications<T: AsGatt>(
+        &self,
+        characteristic: &Characteristic<T>,
+        indication: bool,
+    ) -> Result<(), BleHostError<C::Error>> {
         let properties = u16::to_le_bytes(if indication { 0x02 } else { 0x01 });
 
         let data = att::AttReq::Write {
@@ -760,17 +991,10 @@ impl<'reference, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usiz
         let response = self.request(data).await?;
 
         match Self::response(response.pdu.as_ref())? {
-            AttRsp::Write => {
-                let listener = self
-                    .notifications
-                    .dyn_subscriber()
-                    .map_err(|_| Error::InsufficientSpace)?;
-                Ok(NotificationListener {
-                    listener,
-                    handle: characteristic.handle,
-                })
+            AttRsp::Write => Ok(()),
+            AttRsp::Error { request, handle, code } => {
+                Err(self.gatt_error(GattProcedure::Subscribe, handle, code).into())
             }
-            AttRsp::Error { request, handle, code } => Err(Error::Att(code).into()),
             _ => Err(Error::InvalidValue.into()),
         }
     }
@@ -791,7 +1015,9 @@ impl<'reference, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usiz
 
         match Self::response(response.pdu.as_ref())? {
             AttRsp::Write => Ok(()),
-            AttRsp::Error { request, handle, code } => Err(Error::Att(code).into()),
+            AttRsp::Error { request, handle, code } => {
+                Err(self.gatt_error(GattProcedure::Unsubscribe, handle, code).into())
+            }
             _ => Err(Error::InvalidValue.into()),
         }
     }
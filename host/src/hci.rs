@@ -0,0 +1,685 @@
+//! HCI commands used by the host that are not provided by `bt-hci`.
+use bt_hci::cmd::{Cmd, CmdReturnBuf, Opcode, OpcodeGroup, SyncCmd};
+use bt_hci::param::{
+    AddrKind, AdvChannelMap, AdvEventProps, AdvFilterPolicy, AdvHandle, BdAddr, ConnHandle, DisconnectReason, Duration,
+    ExtDuration, PeriodicAdvProps, PhyKind, Status, SyncHandle,
+};
+use bt_hci::{FixedSizeValue, FromHciBytes, FromHciBytesError, WriteHci, cmd};
+
+cmd! {
+    /// LE Set Default Subrate command.
+    LeSetDefaultSubrate(LE, 0x007d) {
+        LeSetDefaultSubrateParams {
+            subrate_min: u16,
+            subrate_max: u16,
+            max_latency: u16,
+            continuation_number: u16,
+            supervision_timeout: Duration<10_000>,
+        }
+        Return = ();
+    }
+}
+
+cmd! {
+    /// LE Set Host Feature command.
+    LeSetHostFeature(LE, 0x0074) {
+        LeSetHostFeatureParams {
+            bit_number: u8,
+            bit_value: u8,
+        }
+        Return = ();
+    }
+}
+
+cmd! {
+    /// LE Subrate Request command.
+    LeSubrateRequest(LE, 0x007e) {
+        LeSubrateRequestParams {
+            handle: ConnHandle,
+            subrate_min: u16,
+            subrate_max: u16,
+            max_latency: u16,
+            continuation_number: u16,
+            supervision_timeout: Duration<10_000>,
+        }
+    }
+}
+
+cmd! {
+    /// LE Receiver Test command.
+    LeReceiverTestV1(LE, 0x001d) {
+        LeReceiverTestV1Params {
+            rx_channel: u8,
+        }
+        Return = ();
+    }
+}
+
+cmd! {
+    /// LE Transmitter Test command.
+    LeTransmitterTestV1(LE, 0x001e) {
+        LeTransmitterTestV1Params {
+            tx_channel: u8,
+            test_data_length: u8,
+            packet_payload: u8,
+        }
+        Return = ();
+    }
+}
+
+cmd! {
+    /// LE Receiver Test command, version 2.
+    LeReceiverTestV2(LE, 0x0033) {
+        LeReceiverTestV2Params {
+            rx_channel: u8,
+            phy: u8,
+            modulation_index: u8,
+        }
+        Return = ();
+    }
+}
+
+cmd! {
+    /// LE Transmitter Test command, version 2.
+    LeTransmitterTestV2(LE, 0x0034) {
+        LeTransmitterTestV2Params {
+            tx_channel: u8,
+            test_data_length: u8,
+            packet_payload: u8,
+            phy: u8,
+        }
+        Return = ();
+    }
+}
+
+cmd! {
+    /// LE Transmitter Test command, version 4.
+    ///
+    /// Constant tone extensions are not supported, so the switching pattern is always empty.
+    LeTransmitterTestV4(LE, 0x007b) {
+        LeTransmitterTestV4Params {
+            tx_channel: u8,
+            test_data_length: u8,
+            packet_payload: u8,
+            phy: u8,
+            cte_length: u8,
+            cte_type: u8,
+            switching_pattern_length: u8,
+            tx_power_level: i8,
+        }
+        Return = ();
+    }
+}
+
+bt_hci::param! {
+    /// Parameters of a connected isochronous group, for [`LeSetCigParams`].
+    struct CigParams {
+        cig_id: u8,
+        sdu_interval_c_to_p: ExtDuration,
+        sdu_interval_p_to_c: ExtDuration,
+        worst_case_sca: u8,
+        packing: u8,
+        framing: u8,
+        max_transport_latency_c_to_p: u16,
+        max_transport_latency_p_to_c: u16,
+    }
+}
+
+bt_hci::param! {
+    /// Parameters of a connected isochronous stream, for [`LeSetCigParams`].
+    struct CisParams {
+        cis_id: u8,
+        max_sdu_c_to_p: u16,
+        max_sdu_p_to_c: u16,
+        phy_c_to_p: u8,
+        phy_p_to_c: u8,
+        rtn_c_to_p: u8,
+        rtn_p_to_c: u8,
+    }
+}
+
+/// Parameters of a [`LeSetCigParams`] command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeSetCigParamsParams<'a, const N: usize> {
+    /// Parameters of the group.
+    pub cig: CigParams,
+    /// Parameters of the streams in the group.
+    pub cis: &'a [CisParams; N],
+}
+
+impl<const N: usize> WriteHci for LeSetCigParamsParams<'_, N> {
+    fn size(&self) -> usize {
+        self.cig.size() + 1 + N * core::mem::size_of::<CisParams>()
+    }
+
+    fn write_hci<W: embedded_io::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+        self.cig.write_hci(&mut writer)?;
+        writer.write_all(&[N as u8])?;
+        for cis in self.cis {
+            cis.write_hci(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    async fn write_hci_async<W: embedded_io_async::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+        self.cig.write_hci_async(&mut writer).await?;
+        writer.write_all(&[N as u8]).await?;
+        for cis in self.cis {
+            cis.write_hci_async(&mut writer).await?;
+        }
+        Ok(())
+    }
+}
+
+/// LE Set CIG Parameters command, configuring a group of `N` connected isochronous streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeSetCigParams<'a, const N: usize>(LeSetCigParamsParams<'a, N>);
+
+impl<'a, const N: usize> LeSetCigParams<'a, N> {
+    const VALID: () = core::assert!(N > 0 && N <= 0x1f, "a CIG holds from 1 to 31 streams");
+
+    /// Create a new instance of the command.
+    pub fn new(cig: CigParams, cis: &'a [CisParams; N]) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::VALID;
+        Self(LeSetCigParamsParams { cig, cis })
+    }
+}
+
+impl<'a, const N: usize> Cmd for LeSetCigParams<'a, N> {
+    const OPCODE: Opcode = Opcode::new(OpcodeGroup::LE, 0x0062);
+    type Params = LeSetCigParamsParams<'a, N>;
+
+    fn params(&self) -> &LeSetCigParamsParams<'a, N> {
+        &self.0
+    }
+}
+
+impl<const N: usize> WriteHci for LeSetCigParams<'_, N> {
+    fn size(&self) -> usize {
+        self.0.size() + 3
+    }
+
+    fn write_hci<W: embedded_io::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(&self.header())?;
+        self.0.write_hci(writer)
+    }
+
+    async fn write_hci_async<W: embedded_io_async::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(&self.header()).await?;
+        self.0.write_hci_async(writer).await
+    }
+}
+
+/// Return parameters of a [`LeSetCigParams`] command.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeSetCigParamsReturn<const N: usize> {
+    /// Identifier of the group.
+    pub cig_id: u8,
+    /// Number of streams in the group.
+    pub cis_count: u8,
+    /// Connection handles assigned to the streams, in the order of their parameters.
+    pub cis_handles: [ConnHandle; N],
+}
+
+// Safety: the struct is packed, and all bit patterns are valid for its fields.
+unsafe impl<const N: usize> FixedSizeValue for LeSetCigParamsReturn<N> {
+    fn is_valid(_data: &[u8]) -> bool {
+        true
+    }
+}
+
+/// Return buffer of a [`LeSetCigParams`] command, sized for `N` streams.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LeSetCigParamsReturnBuf<const N: usize>([u8; 2], [[u8; 2]; N]);
+
+impl<const N: usize> AsRef<[u8]> for LeSetCigParamsReturnBuf<N> {
+    fn as_ref(&self) -> &[u8] {
+        // Safety: the struct only holds byte arrays, so it has no padding.
+        unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, Self::LEN) }
+    }
+}
+
+impl<const N: usize> AsMut<[u8]> for LeSetCigParamsReturnBuf<N> {
+    fn as_mut(&mut self) -> &mut [u8] {
+        // Safety: the struct only holds byte arrays, so it has no padding.
+        unsafe { core::slice::from_raw_parts_mut(self as *mut Self as *mut u8, Self::LEN) }
+    }
+}
+
+impl<const N: usize> CmdReturnBuf for LeSetCigParamsReturnBuf<N> {
+    const LEN: usize = 2 + 2 * N;
+
+    fn new() -> Self {
+        Self([0; 2], [[0; 2]; N])
+    }
+}
+
+impl<const N: usize> SyncCmd for LeSetCigParams<'_, N> {
+    type Return = LeSetCigParamsReturn<N>;
+    type Handle = ();
+    type ReturnBuf = LeSetCigParamsReturnBuf<N>;
+
+    fn param_handle(&self) {}
+
+    fn return_handle(_data: &[u8]) -> Result<Self::Handle, FromHciBytesError> {
+        Ok(())
+    }
+}
+
+cmd! {
+    /// LE Create CIS command.
+    ///
+    /// Only a single stream is created per command, so `cis_count` is always 1.
+    LeCreateCis(LE, 0x0064) {
+        LeCreateCisParams {
+            cis_count: u8,
+            cis_handle: ConnHandle,
+            acl_handle: ConnHandle,
+        }
+    }
+}
+
+cmd! {
+    /// LE Remove CIG command.
+    LeRemoveCig(LE, 0x0065) {
+        LeRemoveCigParams {
+            cig_id: u8,
+        }
+        Return = u8;
+    }
+}
+
+cmd! {
+    /// LE Accept CIS Request command.
+    LeAcceptCisRequest(LE, 0x0066) {
+        LeAcceptCisRequestParams {
+            handle: ConnHandle,
+        }
+    }
+}
+
+cmd! {
+    /// LE Reject CIS Request command.
+    LeRejectCisRequest(LE, 0x0067) {
+        LeRejectCisRequestParams {
+            reason: Status,
+        }
+        Return = ConnHandle;
+        Handle = handle: ConnHandle;
+    }
+}
+
+cmd! {
+    /// LE Setup ISO Data Path command.
+    LeSetupIsoDataPath(LE, 0x006e) {
+        LeSetupIsoDataPathParams<'a> {
+            data_path_direction: u8,
+            data_path_id: u8,
+            codec_id: [u8; 5],
+            controller_delay: ExtDuration,
+            codec_configuration: &'a [u8],
+        }
+        Return = ConnHandle;
+        Handle = handle: ConnHandle;
+    }
+}
+
+cmd! {
+    /// LE Remove ISO Data Path command.
+    LeRemoveIsoDataPath(LE, 0x006f) {
+        LeRemoveIsoDataPathParams {
+            data_path_direction: u8,
+        }
+        Return = ConnHandle;
+        Handle = handle: ConnHandle;
+    }
+}
+
+cmd! {
+    /// LE Read Buffer Size command, version 2.
+    LeReadBufferSizeV2(LE, 0x0060) {
+        Params = ();
+        LeReadBufferSizeV2Return {
+            le_acl_data_packet_length: u16,
+            total_num_le_acl_data_packets: u8,
+            iso_data_packet_length: u16,
+            total_num_iso_data_packets: u8,
+        }
+    }
+}
+
+cmd! {
+    /// LE Create BIG command.
+    LeCreateBig(LE, 0x0068) {
+        LeCreateBigParams {
+            big_handle: u8,
+            adv_handle: AdvHandle,
+            num_bis: u8,
+            sdu_interval: ExtDuration,
+            max_sdu: u16,
+            max_transport_latency: u16,
+            rtn: u8,
+            phy: u8,
+            packing: u8,
+            framing: u8,
+            encryption: bool,
+            broadcast_code: [u8; 16],
+        }
+    }
+}
+
+cmd! {
+    /// LE Terminate BIG command.
+    LeTerminateBig(LE, 0x006a) {
+        LeTerminateBigParams {
+            big_handle: u8,
+            reason: DisconnectReason,
+        }
+    }
+}
+
+cmd! {
+    /// LE BIG Create Sync command.
+    LeBigCreateSync(LE, 0x006b) {
+        LeBigCreateSyncParams<'a> {
+            big_handle: u8,
+            sync_handle: SyncHandle,
+            encryption: bool,
+            broadcast_code: [u8; 16],
+            mse: u8,
+            big_sync_timeout: Duration<10_000>,
+            bis: LengthPrefixed<'a>,
+        }
+    }
+}
+
+cmd! {
+    /// LE BIG Terminate Sync command.
+    LeBigTerminateSync(LE, 0x006c) {
+        LeBigTerminateSyncParams {
+            big_handle: u8,
+        }
+        Return = u8;
+    }
+}
+
+/// Raw parameters of a [`VendorCommand`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct VendorParams<'a>(pub &'a [u8]);
+
+impl WriteHci for VendorParams<'_> {
+    fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    fn write_hci<W: embedded_io::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(self.0)
+    }
+
+    async fn write_hci_async<W: embedded_io_async::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(self.0).await
+    }
+}
+
+impl<'a> FromHciBytes<'a> for LengthPrefixed<'a> {
+    fn from_hci_bytes(data: &'a [u8]) -> Result<(Self, &'a [u8]), FromHciBytesError> {
+        let (len, data) = data.split_first().ok_or(FromHciBytesError::InvalidSize)?;
+        if data.len() < *len as usize {
+            return Err(FromHciBytesError::InvalidSize);
+        }
+        let (value, rest) = data.split_at(*len as usize);
+        Ok((Self(value), rest))
+    }
+}
+
+/// A vendor-specific HCI command with opcode command field `OCF`.
+///
+/// The parameters are sent as-is, and the controller is expected to respond with a command complete event
+/// carrying `N` bytes of return parameters after the status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct VendorCommand<'a, const OCF: u16, const N: usize = 0>(VendorParams<'a>);
+
+impl<'a, const OCF: u16, const N: usize> VendorCommand<'a, OCF, N> {
+    /// Maximum length of the command parameters.
+    pub const MAX_PARAMS_LEN: usize = 255;
+
+    /// Create a vendor command with the given raw parameters.
+    ///
+    /// Returns `None` if the parameters do not fit in a single command packet.
+    pub fn new(params: &'a [u8]) -> Option<Self> {
+        (params.len() <= Self::MAX_PARAMS_LEN).then_some(Self(VendorParams(params)))
+    }
+}
+
+impl<'a, const OCF: u16, const N: usize> Cmd for VendorCommand<'a, OCF, N> {
+    const OPCODE: Opcode = Opcode::new(OpcodeGroup::VENDOR_SPECIFIC, OCF);
+    type Params = VendorParams<'a>;
+
+    fn params(&self) -> &VendorParams<'a> {
+        &self.0
+    }
+}
+
+impl<const OCF: u16, const N: usize> WriteHci for VendorCommand<'_, OCF, N> {
+    fn size(&self) -> usize {
+        self.0.size() + 3
+    }
+
+    fn write_hci<W: embedded_io::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(&self.header())?;
+        self.0.write_hci(writer)
+    }
+
+    async fn write_hci_async<W: embedded_io_async::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(&self.header()).await?;
+        self.0.write_hci_async(writer).await
+    }
+}
+
+impl<const OCF: u16, const N: usize> SyncCmd for VendorCommand<'_, OCF, N> {
+    type Return = [u8; N];
+    type Handle = ();
+    type ReturnBuf = [u8; N];
+
+    fn param_handle(&self) {}
+
+    fn return_handle(_data: &[u8]) -> Result<Self::Handle, FromHciBytesError> {
+        Ok(())
+    }
+}
+
+/// A variable length parameter, preceded by its length.
+///
+/// The size of a `&[u8]` parameter in `bt-hci` leaves out its length octet.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LengthPrefixed<'a>(pub &'a [u8]);
+
+impl WriteHci for LengthPrefixed<'_> {
+    fn size(&self) -> usize {
+        1 + self.0.len()
+    }
+
+    fn write_hci<W: embedded_io::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(&[self.0.len() as u8])?;
+        writer.write_all(self.0)
+    }
+
+    async fn write_hci_async<W: embedded_io_async::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(&[self.0.len() as u8]).await?;
+        writer.write_all(self.0).await
+    }
+}
+
+cmd! {
+    /// LE Set Extended Advertising Parameters command, version 2.
+    ///
+    /// Version 2 adds the choice of the coding of the coded PHY, which is 0 for no preference,
+    /// 1 to prefer S=2 and 2 to prefer S=8.
+    LeSetExtAdvParamsV2(LE, 0x007f) {
+        LeSetExtAdvParamsV2Params {
+            adv_handle: AdvHandle,
+            adv_event_props: AdvEventProps,
+            primary_adv_interval_min: ExtDuration<625>,
+            primary_adv_interval_max: ExtDuration<625>,
+            primary_adv_channel_map: AdvChannelMap,
+            own_addr_kind: AddrKind,
+            peer_addr_kind: AddrKind,
+            peer_addr: BdAddr,
+            adv_filter_policy: AdvFilterPolicy,
+            adv_tx_power: i8,
+            primary_adv_phy: PhyKind,
+            secondary_adv_max_skip: u8,
+            secondary_adv_phy: PhyKind,
+            adv_sid: u8,
+            scan_request_notification_enable: bool,
+            primary_adv_phy_options: u8,
+            secondary_adv_phy_options: u8,
+        }
+        Return = i8;
+    }
+}
+
+cmd! {
+    /// LE Set Periodic Advertising Parameters command, version 2.
+    ///
+    /// The command provided by `bt-hci` uses the opcode of version 1.
+    LeSetPeriodicAdvParamsV2(LE, 0x0086) {
+        LeSetPeriodicAdvParamsV2Params {
+            adv_handle: AdvHandle,
+            periodic_adv_interval_min: Duration<1_250>,
+            periodic_adv_interval_max: Duration<1_250>,
+            periodic_adv_props: PeriodicAdvProps,
+            num_subevents: u8,
+            subevent_interval: u8,
+            response_slot_delay: u8,
+            response_slot_spacing: u8,
+            num_response_slots: u8,
+        }
+        Return = AdvHandle;
+    }
+}
+
+cmd! {
+    /// LE Set Periodic Advertising Subevent Data command.
+    ///
+    /// Only the data of a single subevent is set per command, so `num_subevents` is always 1.
+    LeSetPeriodicAdvSubeventData(LE, 0x0082) {
+        LeSetPeriodicAdvSubeventDataParams<'a> {
+            adv_handle: AdvHandle,
+            num_subevents: u8,
+            subevent: u8,
+            response_slot_start: u8,
+            response_slot_count: u8,
+            subevent_data: LengthPrefixed<'a>,
+        }
+        Return = AdvHandle;
+    }
+}
+
+cmd! {
+    /// LE Set Periodic Advertising Response Data command.
+    ///
+    /// The command provided by `bt-hci` uses the opcode of another command.
+    LeSetPeriodicAdvResponseData(LE, 0x0083) {
+        LeSetPeriodicAdvResponseDataParams<'a> {
+            sync_handle: SyncHandle,
+            request_event: u16,
+            request_subevent: u8,
+            response_subevent: u8,
+            response_slot: u8,
+            response_data: LengthPrefixed<'a>,
+        }
+        Return = SyncHandle;
+    }
+}
+
+cmd! {
+    /// LE Set Periodic Sync Subevent command.
+    ///
+    /// The command provided by `bt-hci` uses the opcode of another command.
+    LeSetPeriodicSyncSubevent(LE, 0x0084) {
+        LeSetPeriodicSyncSubeventParams<'a> {
+            sync_handle: SyncHandle,
+            periodic_adv_props: PeriodicAdvProps,
+            subevents: LengthPrefixed<'a>,
+        }
+        Return = SyncHandle;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vendor_command_writes_raw_params() {
+        let cmd = VendorCommand::<0x0001>::new(&[0x01, 0x02, 0x03]).unwrap();
+        let mut buf = [0; 6];
+        cmd.write_hci(&mut buf[..]).unwrap();
+        assert_eq!(buf, [0x01, 0xfc, 0x03, 0x01, 0x02, 0x03]);
+        assert_eq!(cmd.size(), 6);
+        assert!(VendorCommand::<0x0001>::new(&[0; 256]).is_none());
+    }
+
+    #[test]
+    fn set_cig_params_writes_stream_count() {
+        let cig = CigParams {
+            cig_id: 1,
+            sdu_interval_c_to_p: ExtDuration::from_u32(10_000),
+            sdu_interval_p_to_c: ExtDuration::from_u32(10_000),
+            worst_case_sca: 0,
+            packing: 0,
+            framing: 0,
+            max_transport_latency_c_to_p: 20,
+            max_transport_latency_p_to_c: 20,
+        };
+        let cis = [CisParams {
+            cis_id: 2,
+            max_sdu_c_to_p: 40,
+            max_sdu_p_to_c: 0,
+            phy_c_to_p: 0x02,
+            phy_p_to_c: 0x02,
+            rtn_c_to_p: 2,
+            rtn_p_to_c: 2,
+        }];
+        let cmd = LeSetCigParams::new(cig, &cis);
+        let mut buf = [0; 27];
+        cmd.write_hci(&mut buf[..]).unwrap();
+        assert_eq!(cmd.size(), buf.len());
+        assert_eq!(&buf[..4], &[0x62, 0x20, 24, 1]);
+        assert_eq!(&buf[17..], &[1, 2, 40, 0, 0, 0, 0x02, 0x02, 2, 2]);
+    }
+
+    #[test]
+    fn set_cig_params_returns_stream_handles() {
+        let mut buf = LeSetCigParamsReturnBuf::<2>::new();
+        buf.as_mut().copy_from_slice(&[1, 2, 0x60, 0x00, 0x61, 0x00]);
+        let (ret, rest) = <LeSetCigParamsReturn<2> as bt_hci::FromHciBytes>::from_hci_bytes(buf.as_ref()).unwrap();
+        assert!(rest.is_empty());
+        let handles = ret.cis_handles;
+        assert_eq!(handles, [ConnHandle::new(0x60), ConnHandle::new(0x61)]);
+    }
+
+    #[test]
+    fn transmitter_test_v4_has_empty_switching_pattern() {
+        let cmd = LeTransmitterTestV4::new(19, 37, 0x00, 0x02, 0, 0, 0, -4);
+        let mut buf = [0; 11];
+        cmd.write_hci(&mut buf[..]).unwrap();
+        assert_eq!(buf, [0x7b, 0x20, 0x08, 19, 37, 0x00, 0x02, 0, 0, 0, 0xfc]);
+    }
+
+    #[test]
+    fn periodic_sync_subevent_lists_subevents() {
+        let handle = SyncHandle::from_hci_bytes(&[1, 0]).unwrap().0;
+        let cmd = LeSetPeriodicSyncSubevent::new(handle, PeriodicAdvProps::new(), LengthPrefixed(&[0, 3]));
+        let mut buf = [0; 10];
+        cmd.write_hci(&mut buf[..]).unwrap();
+        assert_eq!(buf, [0x84, 0x20, 7, 1, 0, 0, 0, 2, 0, 3]);
+    }
+}
@@ -1,50 +1,71 @@
 //! BleHost
 //!
 //! The host module contains the main entry point for the TrouBLE host.
-use core::cell::RefCell;
-use core::future::poll_fn;
+use core::cell::{Cell, RefCell};
+use core::future::{Future, poll_fn};
 use core::mem::MaybeUninit;
-use core::task::Poll;
+use core::task::{Context, Poll};
 
+#[cfg(feature = "security")]
+use bt_hci::cmd::controller_baseband::SetEventMaskPage2;
 use bt_hci::cmd::controller_baseband::{
     HostBufferSize, HostNumberOfCompletedPackets, Reset, SetControllerToHostFlowControl, SetEventMask,
 };
+#[cfg(feature = "scan")]
+use bt_hci::cmd::le::LePeriodicAdvTerminateSync;
 use bt_hci::cmd::le::{
-    LeConnUpdate, LeCreateConnCancel, LeReadBufferSize, LeReadFilterAcceptListSize, LeSetAdvEnable, LeSetEventMask,
-    LeSetExtAdvEnable, LeSetExtScanEnable, LeSetRandomAddr, LeSetScanEnable,
+    LeAddDeviceToFilterAcceptList, LeClearFilterAcceptList, LeConnUpdate, LeCreateConnCancel, LeReadBufferSize,
+    LeReadFilterAcceptListSize, LeReadLocalSupportedFeatures, LeReadMaxDataLength, LeRemoveDeviceFromFilterAcceptList,
+    LeSetAdvEnable, LeSetEventMask, LeSetExtAdvEnable, LeSetExtScanEnable, LeSetRandomAddr, LeSetScanEnable,
+    LeWriteSuggestedDefaultDataLength,
 };
+#[cfg(feature = "peripheral")]
+use bt_hci::cmd::le::{
+    LeAddDeviceToResolvingList, LeClearResolvingList, LeSetAddrResolutionEnable, LeSetResolvablePrivateAddrTimeout,
+};
+#[cfg(feature = "security")]
+use bt_hci::cmd::le::{LeEnableEncryption, LeLongTermKeyRequestNegativeReply, LeLongTermKeyRequestReply};
 use bt_hci::cmd::link_control::Disconnect;
-use bt_hci::cmd::{AsyncCmd, SyncCmd};
+use bt_hci::cmd::{AsyncCmd, Cmd, Opcode, SyncCmd};
 use bt_hci::controller::{Controller, ControllerCmdAsync, ControllerCmdSync, blocking};
 use bt_hci::data::{AclBroadcastFlag, AclPacket, AclPacketBoundary};
 use bt_hci::event::le::LeEvent;
 use bt_hci::event::{Event, Vendor};
 use bt_hci::param::{
     AddrKind, AdvHandle, AdvSet, BdAddr, ConnHandle, DisconnectReason, EventMask, FilterDuplicates, LeConnRole,
-    LeEventMask, Status,
+    LeEventMask, LeFeatureMask, Status,
 };
 #[cfg(feature = "controller-host-flow-control")]
 use bt_hci::param::{ConnHandleCompletedPackets, ControllerToHostFlowControl};
 use bt_hci::{ControllerToHostPacket, FromHciBytes, WriteHci};
-use embassy_futures::select::{Either3, Either4, select3, select4};
+use embassy_futures::select::{Either, Either3, Either4, select, select3, select4};
 use embassy_sync::once_lock::OnceLock;
-use embassy_sync::waitqueue::WakerRegistration;
+use embassy_sync::waitqueue::{MultiWakerRegistration, WakerRegistration};
 #[cfg(feature = "gatt")]
 use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_time::with_timeout;
 use futures::pin_mut;
 
 use crate::att::{AttClient, AttServer};
+use crate::capture::{self, PacketCapture};
 use crate::channel_manager::{ChannelManager, ChannelStorage, PacketChannel};
 use crate::command::CommandState;
-#[cfg(feature = "gatt")]
-use crate::connection::ConnectionEventData;
+#[cfg(not(feature = "security"))]
+use crate::connection::SecurityLevel;
+use crate::connection::{ConnectionEventData, ConnectionParams, DataLength, RemoteVersion, Subrate, TxPowerReport};
 use crate::connection_manager::{ConnectionManager, ConnectionStorage, EventChannel, PacketGrant};
 use crate::cursor::WriteCursor;
+use crate::event::{HostEvent, HostEventChannel};
+use crate::hci::LeSetHostFeature;
 use crate::l2cap::sar::{PacketReassembly, SarType};
 use crate::packet_pool::Pool;
+#[cfg(feature = "traffic-metrics")]
+use crate::packet_pool::PoolMetrics;
 use crate::pdu::Pdu;
+use crate::procedure::{self, Procedure};
 use crate::types::l2cap::{
-    L2CAP_CID_ATT, L2CAP_CID_DYN_START, L2CAP_CID_LE_U_SIGNAL, L2capHeader, L2capSignal, L2capSignalHeader,
+    L2CAP_CID_ATT, L2CAP_CID_DYN_START, L2CAP_CID_LE_U_SECURITY_MANAGER, L2CAP_CID_LE_U_SIGNAL, L2capHeader,
+    L2capSignal, L2capSignalHeader,
 };
 use crate::{Address, BleHostError, Error, Stack, att, config};
 
@@ -56,10 +77,29 @@ use crate::{Address, BleHostError, Error, Stack, att, config};
 /// The host performs connection management, l2cap channel management, and
 /// multiplexes events and data across connections and l2cap channels.
 pub(crate) struct BleHost<'d, T> {
-    initialized: OnceLock<InitialState>,
+    // Replaced when the controller is reset after becoming unresponsive.
+    initialized: OnceLock<Cell<InitialState>>,
+    recovery_requested: Cell<bool>,
+    recovery_waker: RefCell<WakerRegistration>,
+    pub(crate) command_timeout: embassy_time::Duration,
+    commands: RefCell<CommandSequence>,
+    // Number of commands of the host that timed out in a row.
+    command_timeouts: Cell<u8>,
+    // Incremented for every packet received from the controller and every completed command.
+    controller_activity: Cell<u32>,
     metrics: RefCell<HostMetrics>,
+    #[cfg(feature = "command-metrics")]
+    command_metrics: RefCell<CommandMetrics>,
+    #[cfg(feature = "command-metrics")]
+    pub(crate) command_credits: Option<&'d crate::transport::credits::CommandCredits>,
+    #[cfg(feature = "traffic-metrics")]
+    traffic_metrics: RefCell<TrafficMetrics>,
     pub(crate) address: Option<Address>,
+    #[cfg(feature = "peripheral")]
+    pub(crate) privacy: Cell<Option<Privacy>>,
+    pub(crate) accept_list: FilterAcceptList,
     pub(crate) controller: T,
+    pub(crate) capture: Option<&'d dyn PacketCapture>,
     pub(crate) connections: ConnectionManager<'d>,
     pub(crate) reassembly: PacketReassembly<'d>,
     pub(crate) channels: ChannelManager<'d>,
@@ -73,23 +113,262 @@ pub(crate) struct BleHost<'d, T> {
     pub(crate) advertise_command_state: CommandState<bool>,
     pub(crate) connect_command_state: CommandState<bool>,
     pub(crate) scan_command_state: CommandState<bool>,
+    #[cfg(feature = "security")]
+    pub(crate) security: crate::security_manager::SecurityManager,
+    pub(crate) events: HostEventChannel,
+    #[cfg(feature = "scan")]
+    pub(crate) periodic_sync: crate::scan::PeriodicSyncState,
+    #[cfg(feature = "scan")]
+    pub(crate) periodic_reports: crate::scan::PeriodicReportQueue<'d>,
+    #[cfg(feature = "scan")]
+    pub(crate) scan_cache: crate::scan::DuplicateFilter<'d>,
+    #[cfg(feature = "scan")]
+    pub(crate) scan_reports: crate::scan::ScanReportQueue<'d>,
+    /// Advertisements held until their scan response arrives.
+    #[cfg(feature = "scan")]
+    pub(crate) scan_responses: RefCell<crate::scan::Merger<&'d mut [Option<crate::scan::PendingReport>]>>,
+    /// Extended advertising payloads split over several reports.
+    #[cfg(feature = "scan")]
+    pub(crate) scan_fragments: RefCell<crate::scan::HostReassembler>,
+    /// Filter of the reports queued for scan sessions.
+    #[cfg(feature = "scan")]
+    pub(crate) scan_filter: RefCell<crate::scan::ScanFilter<'d>>,
+    /// Whether the active scan, if any, filters using the filter accept list.
+    #[cfg(feature = "scan")]
+    pub(crate) scan_filtered: Cell<bool>,
+    #[cfg(feature = "iso")]
+    pub(crate) iso: crate::iso::IsoState,
+}
+
+/// Default time to wait for the controller to respond to a command before it times out.
+#[cfg(not(test))]
+pub(crate) const COMMAND_TIMEOUT: embassy_time::Duration = embassy_time::Duration::from_secs(2);
+// Shortened so that the tests of unresponsive controllers do not last.
+#[cfg(test)]
+pub(crate) const COMMAND_TIMEOUT: embassy_time::Duration = embassy_time::Duration::from_millis(100);
+
+/// Number of commands of the host timing out in a row after which a controller that still sends
+/// events is considered unresponsive.
+const RECOVERY_TIMEOUTS: u8 = 3;
+
+/// Commands in flight, so that a command only starts timing out once the commands issued before it
+/// are done, as they may hold the command slots of the controller.
+struct CommandSequence {
+    outstanding: u32,
+    completed: u32,
+    waker: MultiWakerRegistration<4>,
+}
+
+#[cfg(not(feature = "security"))]
+const SMP_PAIRING_REQUEST: u8 = 0x01;
+#[cfg(not(feature = "security"))]
+const SMP_PAIRING_FAILED: u8 = 0x05;
+#[cfg(not(feature = "security"))]
+const SMP_SECURITY_REQUEST: u8 = 0x0b;
+#[cfg(not(feature = "security"))]
+const SMP_REASON_PAIRING_NOT_SUPPORTED: u8 = 0x05;
+
+#[cfg(feature = "security")]
+type SecurityAction = crate::security_manager::Action;
+#[cfg(not(feature = "security"))]
+type SecurityAction = core::convert::Infallible;
+
+/// The opcode and handle of an ATT request needing a secure link, service discovery being allowed on any link.
+#[cfg(all(feature = "security", feature = "gatt"))]
+fn restricted(client: &AttClient<'_>) -> Option<(u8, u16)> {
+    use bt_hci::uuid::declarations::{CHARACTERISTIC, INCLUDE};
+    let AttClient::Request(req) = client else {
+        return None;
+    };
+    let discovery = |uuid: &crate::types::uuid::Uuid| *uuid == INCLUDE.into() || *uuid == CHARACTERISTIC.into();
+    match req {
+        att::AttReq::ExchangeMtu { .. }
+        | att::AttReq::FindInformation { .. }
+        | att::AttReq::FindByTypeValue { .. }
+        | att::AttReq::ReadByGroupType { .. } => None,
+        att::AttReq::ReadByType {
+            start, attribute_type, ..
+        } => (!discovery(attribute_type)).then_some((att::ATT_READ_BY_TYPE_REQ, *start)),
+        att::AttReq::Read { handle } => Some((att::ATT_READ_REQ, *handle)),
+        att::AttReq::Write { handle, .. } => Some((att::ATT_WRITE_REQ, *handle)),
+        att::AttReq::PrepareWrite { handle, .. } => Some((att::ATT_PREPARE_WRITE_REQ, *handle)),
+        att::AttReq::ExecuteWrite { .. } => Some((att::ATT_EXECUTE_WRITE_REQ, 0)),
+        att::AttReq::ReadMultiple { handles } => Some((
+            att::ATT_READ_MULTIPLE_REQ,
+            u16::from_le_bytes([
+                handles.first().copied().unwrap_or(0),
+                handles.get(1).copied().unwrap_or(0),
+            ]),
+        )),
+        att::AttReq::ReadBlob { handle, .. } => Some((att::ATT_READ_BLOB_REQ, *handle)),
+    }
+}
+
+/// Ignore commands that timed out, as an unresponsive controller is reset instead.
+fn recoverable<E>(result: Result<(), BleHostError<E>>) -> Result<(), BleHostError<E>> {
+    match result {
+        Err(BleHostError::BleHost(Error::Timeout)) => Ok(()),
+        result => result,
+    }
+}
+
+/// Treat an optional command rejected by the controller as unknown as unsupported, instead of failing.
+pub(crate) fn unsupported_as_none<R, E>(result: Result<R, BleHostError<E>>) -> Result<Option<R>, BleHostError<E>> {
+    match result {
+        Ok(r) => Ok(Some(r)),
+        Err(BleHostError::BleHost(Error::Command { status, .. })) if status == bt_hci::param::Error::UNKNOWN_CMD => {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The error of a command, with its opcode if the controller rejected it.
+fn command_error<E>(opcode: Opcode, error: bt_hci::cmd::Error<E>) -> BleHostError<E> {
+    match error {
+        bt_hci::cmd::Error::Hci(status) => Error::Command { opcode, status }.into(),
+        bt_hci::cmd::Error::Io(e) => BleHostError::Controller(e),
+    }
 }
 
 #[derive(Clone, Copy)]
 pub(crate) struct InitialState {
     acl_max: usize,
+    capabilities: Capabilities,
+}
+
+/// Privacy configuration, kept to program the resolving list again after a reset.
+#[cfg(feature = "peripheral")]
+#[derive(Clone, Copy)]
+pub(crate) struct Privacy {
+    pub(crate) local_irk: [u8; 16],
+    pub(crate) rpa_timeout: embassy_time::Duration,
+}
+
+/// Entries of the controller filter accept list, kept to restore the list after a reset.
+pub(crate) struct FilterAcceptList {
+    entries: RefCell<heapless::Vec<(AddrKind, BdAddr), { config::FILTER_ACCEPT_LIST_SIZE }>>,
+}
+
+impl FilterAcceptList {
+    const fn new() -> Self {
+        Self {
+            entries: RefCell::new(heapless::Vec::new()),
+        }
+    }
+
+    fn added(&self, kind: AddrKind, addr: BdAddr) {
+        let mut entries = self.entries.borrow_mut();
+        if !entries.contains(&(kind, addr)) && entries.push((kind, addr)).is_err() {
+            warn!("[host] filter accept list entry not kept, it is not restored after a reset");
+        }
+    }
+
+    fn removed(&self, kind: AddrKind, addr: BdAddr) {
+        self.entries.borrow_mut().retain(|e| *e != (kind, addr));
+    }
+
+    fn cleared(&self) {
+        self.entries.borrow_mut().clear();
+    }
+
+    fn entries(&self) -> heapless::Vec<(AddrKind, BdAddr), { config::FILTER_ACCEPT_LIST_SIZE }> {
+        self.entries.borrow().clone()
+    }
+}
+
+/// LE features supported by the host.
+///
+/// Several features require host support to be enabled before the controller makes use of them.
+/// They are enabled with [`HostFeatures::enable`] from the setup of
+/// [`ControlRunner::run_with_setup`], so that they are enabled again when the host resets the
+/// controller.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HostFeatures(u64);
+
+impl HostFeatures {
+    const ISOCHRONOUS_CHANNELS: u8 = 32;
+    const CONNECTION_SUBRATING: u8 = 38;
+    const CHANNEL_CLASSIFICATION: u8 = 39;
+
+    /// No host features.
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    /// Enable host support for the LE feature with the given bit number.
+    pub const fn with_bit(self, bit_number: u8) -> Self {
+        core::assert!(bit_number < 64);
+        Self(self.0 | (1 << bit_number))
+    }
+
+    /// Enable host support for isochronous channels.
+    pub const fn isochronous_channels(self) -> Self {
+        self.with_bit(Self::ISOCHRONOUS_CHANNELS)
+    }
+
+    /// Enable host support for connection subrating.
+    pub const fn connection_subrating(self) -> Self {
+        self.with_bit(Self::CONNECTION_SUBRATING)
+    }
+
+    /// Enable host support for channel classification.
+    pub const fn channel_classification(self) -> Self {
+        self.with_bit(Self::CHANNEL_CLASSIFICATION)
+    }
+
+    /// Enable the features in the controller.
+    ///
+    /// The controller must support the LE Set Host Feature command, which the host does not
+    /// require otherwise.
+    pub async fn enable<C>(self, controller: &C) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeSetHostFeature>,
+    {
+        for bit_number in self.bits() {
+            info!("[host] enabling host feature {}", bit_number);
+            LeSetHostFeature::new(bit_number, 1)
+                .exec(controller)
+                .await
+                .map_err(|e| command_error(LeSetHostFeature::OPCODE, e))?;
+        }
+        Ok(())
+    }
+
+    /// Bit numbers of the enabled features.
+    fn bits(self) -> impl Iterator<Item = u8> {
+        (0..64).filter(move |bit| self.0 & (1 << bit) != 0)
+    }
+}
+
+/// Capabilities of the controller, read when the host is initialized.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Capabilities {
+    /// LE features supported by the controller.
+    pub le_features: LeFeatureMask,
+    /// Maximum length of the payload of an ACL data packet sent to the controller.
+    pub acl_data_packet_length: u16,
+    /// Number of ACL data packets the controller can buffer.
+    pub total_acl_data_packets: u8,
+    /// Number of entries in the filter accept list.
+    pub filter_accept_list_size: u8,
 }
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(Clone, Copy, Debug)]
 pub(crate) enum AdvHandleState {
     None,
-    Advertising(AdvHandle),
+    /// Advertising, with the transmit power selected by the controller if known.
+    Advertising(AdvHandle, Option<i8>),
     Terminated(AdvHandle),
 }
 
 pub(crate) struct AdvInnerState<'d> {
     handles: &'d mut [AdvHandleState],
+    // Set when advertising with the legacy commands, registered as handle 0.
+    legacy: bool,
     waker: WakerRegistration,
 }
 
@@ -102,6 +381,7 @@ impl<'d> AdvState<'d> {
         Self {
             state: RefCell::new(AdvInnerState {
                 handles,
+                legacy: false,
                 waker: WakerRegistration::new(),
             }),
         }
@@ -120,7 +400,7 @@ impl<'d> AdvState<'d> {
         let mut state = self.state.borrow_mut();
         for entry in state.handles.iter_mut() {
             match entry {
-                AdvHandleState::Advertising(h) if *h == handle => {
+                AdvHandleState::Advertising(h, _) if *h == handle => {
                     *entry = AdvHandleState::Terminated(handle);
                 }
                 _ => {}
@@ -134,16 +414,64 @@ impl<'d> AdvState<'d> {
         state.handles.len()
     }
 
-    pub(crate) fn start(&self, sets: &[AdvSet]) {
+    // Terminate the legacy advertising, which stops once a connection is established.
+    pub(crate) fn terminate_legacy(&self) {
+        if self.state.borrow().legacy {
+            self.terminate(AdvHandle::new(0));
+        }
+    }
+
+    pub(crate) fn start(&self, sets: &[AdvSet], legacy: bool) {
         let mut state = self.state.borrow_mut();
         assert!(sets.len() <= state.handles.len());
+        state.legacy = legacy;
         for handle in state.handles.iter_mut() {
             *handle = AdvHandleState::None;
         }
 
         for (idx, entry) in sets.iter().enumerate() {
-            state.handles[idx] = AdvHandleState::Advertising(entry.adv_handle);
+            state.handles[idx] = AdvHandleState::Advertising(entry.adv_handle, None);
+        }
+    }
+
+    pub(crate) fn set_tx_power(&self, handle: AdvHandle, tx_power: i8) {
+        let mut state = self.state.borrow_mut();
+        for entry in state.handles.iter_mut() {
+            match entry {
+                AdvHandleState::Advertising(h, power) if *h == handle => {
+                    power.replace(tx_power);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub(crate) fn tx_power(&self, handle: AdvHandle) -> Option<i8> {
+        let state = self.state.borrow();
+        state.handles.iter().find_map(|entry| match entry {
+            AdvHandleState::Advertising(h, power) if *h == handle => *power,
+            _ => None,
+        })
+    }
+
+    // Take the next terminated handle, if any.
+    pub(crate) fn poll_terminated(&self, cx: &mut Context<'_>) -> Poll<Option<AdvHandle>> {
+        let mut state = self.state.borrow_mut();
+        state.waker.register(cx.waker());
+        let mut advertising = false;
+        for entry in state.handles.iter_mut() {
+            match *entry {
+                AdvHandleState::Terminated(handle) => {
+                    *entry = AdvHandleState::None;
+                    return Poll::Ready(Some(handle));
+                }
+                AdvHandleState::Advertising(..) => {
+                    advertising = true;
+                }
+                AdvHandleState::None => {}
+            }
         }
+        if advertising { Poll::Pending } else { Poll::Ready(None) }
     }
 
     pub async fn wait(&self) {
@@ -175,6 +503,7 @@ impl<'d> AdvState<'d> {
 
 /// Host metrics
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct HostMetrics {
     /// How many connect events have been received.
     pub connect_events: u32,
@@ -182,6 +511,128 @@ pub struct HostMetrics {
     pub disconnect_events: u32,
     /// How many errors processing received data.
     pub rx_errors: u32,
+    /// How many times the controller was reset after becoming unresponsive.
+    pub controller_resets: u32,
+}
+
+/// Traffic metrics of the host, counting the data exchanged with the peers and the resources it
+/// ran short of.
+#[cfg(feature = "traffic-metrics")]
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TrafficMetrics {
+    /// How many ACL packets were sent to the controller.
+    pub acl_tx: u32,
+    /// How many ACL packets were received from the controller.
+    pub acl_rx: u32,
+    /// How many received ACL packets were dropped without being delivered.
+    pub dropped: u32,
+    /// How many times sending on an L2CAP channel waited for the peer to grant credits.
+    pub l2cap_stalls: u32,
+    /// How many ATT error responses were sent or received.
+    ///
+    /// Attribute Not Found errors, which end the discovery procedures, are not counted.
+    pub gatt_errors: u32,
+    /// Usage of the pool of received packets.
+    pub rx_pool: PoolMetrics,
+    /// Usage of the pool of packets sent by the GATT server.
+    #[cfg(feature = "gatt")]
+    pub tx_pool: PoolMetrics,
+}
+
+/// Whether an ATT PDU is an error response counted by the traffic metrics.
+#[cfg(feature = "traffic-metrics")]
+fn is_gatt_error(att: &att::Att<'_>) -> bool {
+    matches!(
+        att,
+        att::Att::Server(AttServer::Response(att::AttRsp::Error { code, .. }))
+            if *code != att::AttErrorCode::ATTRIBUTE_NOT_FOUND
+    )
+}
+
+/// Number of opcodes for which command latencies are recorded.
+#[cfg(feature = "command-metrics")]
+pub const COMMAND_METRICS_OPCODES: usize = 16;
+
+/// Latency of the HCI commands with an opcode.
+#[cfg(feature = "command-metrics")]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CommandLatency {
+    /// Opcode of the commands.
+    pub opcode: u16,
+    /// How many commands completed.
+    pub completed: u32,
+    /// How many commands timed out.
+    pub timeouts: u32,
+    /// Total time taken by the completed commands.
+    pub total: embassy_time::Duration,
+    /// Longest time taken by a completed command.
+    pub max: embassy_time::Duration,
+}
+
+#[cfg(feature = "command-metrics")]
+impl CommandLatency {
+    /// Average time taken by the completed commands.
+    pub fn average(&self) -> embassy_time::Duration {
+        self.total / self.completed.max(1)
+    }
+}
+
+/// HCI command metrics, measured from the host issuing a command until it completes.
+///
+/// Commands run while the host initializes the controller are not included.
+#[cfg(feature = "command-metrics")]
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CommandMetrics {
+    /// How many commands are waiting for completion.
+    pub outstanding: u8,
+    /// Highest number of commands waiting for completion at once.
+    pub max_outstanding: u8,
+    /// Command credits granted by the controller in its last Command Complete or Command Status
+    /// event, known once tracked with [`Stack::set_command_credits`](crate::Stack::set_command_credits).
+    pub credits: Option<u8>,
+    /// Lowest number of command credits granted by the controller.
+    pub min_credits: Option<u8>,
+    /// Latencies of the first `COMMAND_METRICS_OPCODES` opcodes run.
+    pub latencies: heapless::Vec<CommandLatency, COMMAND_METRICS_OPCODES>,
+}
+
+#[cfg(feature = "command-metrics")]
+impl CommandMetrics {
+    fn started(&mut self) {
+        self.outstanding += 1;
+        self.max_outstanding = self.max_outstanding.max(self.outstanding);
+    }
+
+    fn completed(&mut self, opcode: Opcode, latency: Option<embassy_time::Duration>) {
+        let opcode = opcode.to_raw();
+        let entry = match self.latencies.iter().position(|l| l.opcode == opcode) {
+            Some(index) => &mut self.latencies[index],
+            None => {
+                let entry = CommandLatency {
+                    opcode,
+                    completed: 0,
+                    timeouts: 0,
+                    total: embassy_time::Duration::from_ticks(0),
+                    max: embassy_time::Duration::from_ticks(0),
+                };
+                if self.latencies.push(entry).is_err() {
+                    return;
+                }
+                unwrap!(self.latencies.last_mut())
+            }
+        };
+        match latency {
+            Some(latency) => {
+                entry.completed = entry.completed.wrapping_add(1);
+                entry.total += latency;
+                entry.max = entry.max.max(latency);
+            }
+            None => entry.timeouts = entry.timeouts.wrapping_add(1),
+        }
+    }
 }
 
 impl<'d, T> BleHost<'d, T>
@@ -203,12 +654,36 @@ where
         channels_rx: &'d mut [PacketChannel<{ config::L2CAP_RX_QUEUE_SIZE }>],
         sar: &'d mut [SarType],
         advertise_handles: &'d mut [AdvHandleState],
+        #[cfg(feature = "scan")] scan_cache: &'d mut [Option<crate::scan::ScanCacheEntry>],
+        #[cfg(feature = "scan")] scan_reports: &'d mut [Option<crate::scan::OwnedScanReport>],
+        #[cfg(feature = "scan")] periodic_reports: &'d mut [Option<crate::scan::QueuedPeriodicReport>],
+        #[cfg(feature = "scan")] scan_responses: &'d mut [Option<crate::scan::PendingReport>],
     ) -> Self {
         Self {
             address: None,
+            #[cfg(feature = "peripheral")]
+            privacy: Cell::new(None),
+            accept_list: FilterAcceptList::new(),
             initialized: OnceLock::new(),
+            recovery_requested: Cell::new(false),
+            recovery_waker: RefCell::new(WakerRegistration::new()),
+            command_timeout: COMMAND_TIMEOUT,
+            commands: RefCell::new(CommandSequence {
+                outstanding: 0,
+                completed: 0,
+                waker: MultiWakerRegistration::new(),
+            }),
+            command_timeouts: Cell::new(0),
+            controller_activity: Cell::new(0),
             metrics: RefCell::new(HostMetrics::default()),
+            #[cfg(feature = "command-metrics")]
+            command_metrics: RefCell::new(CommandMetrics::default()),
+            #[cfg(feature = "command-metrics")]
+            command_credits: None,
+            #[cfg(feature = "traffic-metrics")]
+            traffic_metrics: RefCell::new(TrafficMetrics::default()),
             controller,
+            capture: None,
             #[cfg(feature = "gatt")]
             connections: ConnectionManager::new(connections, events, rx_pool.mtu() as u16 - 4, tx_pool),
             #[cfg(not(feature = "gatt"))]
@@ -224,9 +699,76 @@ where
             advertise_command_state: CommandState::new(),
             scan_command_state: CommandState::new(),
             connect_command_state: CommandState::new(),
+            #[cfg(feature = "security")]
+            security: crate::security_manager::SecurityManager::new(),
+            events: HostEventChannel::new(),
+            #[cfg(feature = "scan")]
+            periodic_sync: crate::scan::PeriodicSyncState::new(),
+            #[cfg(feature = "scan")]
+            periodic_reports: crate::scan::PeriodicReportQueue::new(periodic_reports),
+            #[cfg(feature = "scan")]
+            scan_cache: crate::scan::DuplicateFilter::new(scan_cache),
+            #[cfg(feature = "scan")]
+            scan_reports: crate::scan::ScanReportQueue::new(scan_reports),
+            #[cfg(feature = "scan")]
+            scan_responses: RefCell::new(crate::scan::Merger::new(
+                scan_responses,
+                crate::scan::SCAN_RESPONSE_TIMEOUT,
+            )),
+            #[cfg(feature = "scan")]
+            scan_fragments: RefCell::new(crate::scan::Reassembler::new(
+                [const { None }; config::SCAN_REASSEMBLY_SIZE],
+            )),
+            #[cfg(feature = "scan")]
+            scan_filter: RefCell::new(crate::scan::ScanFilter::default()),
+            #[cfg(feature = "scan")]
+            scan_filtered: Cell::new(false),
+            #[cfg(feature = "iso")]
+            iso: crate::iso::IsoState::new(),
+        }
+    }
+
+    /// Publish an event to the listeners of the host events.
+    pub(crate) fn publish(&self, event: HostEvent) {
+        self.events.immediate_publisher().publish_immediate(event);
+    }
+
+    /// Pass a scan report through the duplicate filter to the event handler, and queue it for
+    /// the scan session if it matches the scan filter.
+    #[cfg(feature = "scan")]
+    fn deliver_scan_report<E: EventHandler>(&self, report: &crate::scan::ScanReport<'_>, event_handler: &E) {
+        if !self.scan_cache.is_duplicate(report) {
+            event_handler.on_scan_report(report);
+            if self.scan_filter.borrow().matches(report) {
+                self.scan_reports.push(report);
+            }
         }
     }
 
+    /// Own address type to use when advertising.
+    ///
+    /// With privacy enabled the controller generates a resolvable private address, falling back
+    /// to the configured identity address if it has no matching resolving list entry.
+    #[cfg(feature = "peripheral")]
+    pub(crate) fn own_adv_addr_kind(&self) -> AddrKind {
+        match (self.privacy.get().is_some(), self.address) {
+            (true, Some(_)) => AddrKind::RESOLVABLE_PRIVATE_OR_RANDOM,
+            (true, None) => AddrKind::RESOLVABLE_PRIVATE_OR_PUBLIC,
+            (false, address) => address.map(|a| a.kind).unwrap_or(AddrKind::PUBLIC),
+        }
+    }
+
+    /// LE features supported by the controller, available once the host is initialized.
+    #[cfg(feature = "peripheral")]
+    pub(crate) async fn le_features(&self) -> LeFeatureMask {
+        self.initialized.get().await.get().capabilities.le_features
+    }
+
+    /// Capabilities of the controller, available once the host is initialized.
+    pub(crate) async fn capabilities(&self) -> Capabilities {
+        self.initialized.get().await.get().capabilities
+    }
+
     /// Run a HCI command and return the response.
     pub(crate) async fn command<C>(&self, cmd: C) -> Result<C::Return, BleHostError<T::Error>>
     where
@@ -234,8 +776,8 @@ where
         T: ControllerCmdSync<C>,
     {
         let _ = self.initialized.get().await;
-        let ret = cmd.exec(&self.controller).await?;
-        Ok(ret)
+        capture::record_sent(self.capture, &cmd);
+        self.exec(C::OPCODE, cmd.exec(&self.controller), true).await
     }
 
     /// Run an async HCI command where the response will generate an event later.
@@ -245,10 +787,225 @@ where
         T: ControllerCmdAsync<C>,
     {
         let _ = self.initialized.get().await;
-        cmd.exec(&self.controller).await?;
+        capture::record_sent(self.capture, &cmd);
+        self.exec(C::OPCODE, cmd.exec(&self.controller), true).await
+    }
+
+    /// Run a HCI command on behalf of the application.
+    ///
+    /// The command may be vendor specific or leave the controller busy for longer, so the controller
+    /// is not reset when it times out.
+    pub(crate) async fn user_command<C>(&self, cmd: C) -> Result<C::Return, BleHostError<T::Error>>
+    where
+        C: SyncCmd,
+        T: ControllerCmdSync<C>,
+    {
+        let _ = self.initialized.get().await;
+        capture::record_sent(self.capture, &cmd);
+        self.exec(C::OPCODE, cmd.exec(&self.controller), false).await
+    }
+
+    /// Run an async HCI command on behalf of the application, see [`BleHost::user_command`].
+    pub(crate) async fn user_async_command<C>(&self, cmd: C) -> Result<(), BleHostError<T::Error>>
+    where
+        C: AsyncCmd,
+        T: ControllerCmdAsync<C>,
+    {
+        let _ = self.initialized.get().await;
+        capture::record_sent(self.capture, &cmd);
+        self.exec(C::OPCODE, cmd.exec(&self.controller), false).await
+    }
+
+    /// Run a HCI command right away, without a timeout.
+    #[cfg(feature = "controller-host-flow-control")]
+    async fn raw_command<C>(&self, cmd: C) -> Result<C::Return, BleHostError<T::Error>>
+    where
+        C: SyncCmd,
+        T: ControllerCmdSync<C>,
+    {
+        capture::record_sent(self.capture, &cmd);
+        cmd.exec(&self.controller)
+            .await
+            .map_err(|e| command_error(C::OPCODE, e))
+    }
+
+    /// Run a HCI command initializing the host, without waiting for the host to be initialized.
+    ///
+    /// The command times out like any other, but without resetting the controller, as the
+    /// initialization is what resets it: the runner fails with `Error::Timeout` instead.
+    async fn init_command<C>(&self, cmd: C) -> Result<C::Return, BleHostError<T::Error>>
+    where
+        C: SyncCmd,
+        T: ControllerCmdSync<C>,
+    {
+        capture::record_sent(self.capture, &cmd);
+        self.exec(C::OPCODE, cmd.exec(&self.controller), false).await
+    }
+
+    /// Wait for a command to complete, considering the controller unresponsive if it times out and
+    /// `recover` is set.
+    ///
+    /// The timeout starts once as many commands completed as were in flight when the command was
+    /// issued, so that waiting for a command slot of the controller does not count. The controller
+    /// is only reset when it sent nothing while the command timed out, or when several commands
+    /// timed out in a row.
+    async fn exec<R>(
+        &self,
+        opcode: Opcode,
+        exec: impl Future<Output = Result<R, bt_hci::cmd::Error<T::Error>>>,
+        recover: bool,
+    ) -> Result<R, BleHostError<T::Error>> {
+        #[cfg(feature = "command-metrics")]
+        let _outstanding = {
+            self.command_metrics.borrow_mut().started();
+            OnDrop::new(|| self.command_metrics.borrow_mut().outstanding -= 1)
+        };
+        #[cfg(feature = "command-metrics")]
+        let start = embassy_time::Instant::now();
+        let (ahead, issued) = {
+            let mut commands = self.commands.borrow_mut();
+            commands.outstanding += 1;
+            (commands.outstanding - 1, commands.completed)
+        };
+        let _done = OnDrop::new(|| {
+            let mut commands = self.commands.borrow_mut();
+            commands.outstanding -= 1;
+            commands.completed = commands.completed.wrapping_add(1);
+            commands.waker.wake();
+        });
+        let sent = poll_fn(|cx| {
+            let mut commands = self.commands.borrow_mut();
+            if commands.completed.wrapping_sub(issued) >= ahead {
+                Poll::Ready(())
+            } else {
+                commands.waker.register(cx.waker());
+                Poll::Pending
+            }
+        });
+
+        pin_mut!(exec);
+        let result = match select(exec.as_mut(), sent).await {
+            Either::First(ret) => Ok(ret),
+            Either::Second(_) => {
+                let activity = self.controller_activity.get();
+                with_timeout(self.command_timeout, exec).await.map_err(|_| activity)
+            }
+        };
+        #[cfg(feature = "command-metrics")]
+        self.command_metrics
+            .borrow_mut()
+            .completed(opcode, result.as_ref().ok().map(|_| start.elapsed()));
+        match result {
+            Ok(ret) => {
+                self.controller_activity
+                    .set(self.controller_activity.get().wrapping_add(1));
+                if recover {
+                    self.command_timeouts.set(0);
+                }
+                ret.map_err(|e| command_error(opcode, e))
+            }
+            Err(activity) => {
+                warn!("[host] command {:04x} timed out", opcode.to_raw());
+                if recover {
+                    let timeouts = self.command_timeouts.get().saturating_add(1);
+                    self.command_timeouts.set(timeouts);
+                    if self.controller_activity.get() == activity || timeouts >= RECOVERY_TIMEOUTS {
+                        self.request_recovery();
+                    }
+                }
+                Err(Error::Timeout.into())
+            }
+        }
+    }
+
+    /// Add a device to the controller filter accept list.
+    pub(crate) async fn add_to_accept_list(&self, kind: AddrKind, addr: BdAddr) -> Result<(), BleHostError<T::Error>>
+    where
+        T: ControllerCmdSync<LeAddDeviceToFilterAcceptList>,
+    {
+        self.command(LeAddDeviceToFilterAcceptList::new(kind, addr)).await?;
+        self.accept_list.added(kind, addr);
+        Ok(())
+    }
+
+    /// Remove a device from the controller filter accept list.
+    pub(crate) async fn remove_from_accept_list(
+        &self,
+        kind: AddrKind,
+        addr: BdAddr,
+    ) -> Result<(), BleHostError<T::Error>>
+    where
+        T: ControllerCmdSync<LeRemoveDeviceFromFilterAcceptList>,
+    {
+        self.command(LeRemoveDeviceFromFilterAcceptList::new(kind, addr))
+            .await?;
+        self.accept_list.removed(kind, addr);
+        Ok(())
+    }
+
+    /// Remove all devices from the controller filter accept list.
+    pub(crate) async fn clear_accept_list(&self) -> Result<(), BleHostError<T::Error>>
+    where
+        T: ControllerCmdSync<LeClearFilterAcceptList>,
+    {
+        self.command(LeClearFilterAcceptList::new()).await?;
+        self.accept_list.cleared();
+        Ok(())
+    }
+
+    /// Enable address resolution with the local IRK and the IRKs of the bonded peers.
+    #[cfg(feature = "peripheral")]
+    pub(crate) async fn enable_resolving_list(&self, privacy: Privacy) -> Result<(), BleHostError<T::Error>>
+    where
+        T: ControllerCmdSync<LeSetAddrResolutionEnable>
+            + ControllerCmdSync<LeClearResolvingList>
+            + ControllerCmdSync<LeAddDeviceToResolvingList>
+            + ControllerCmdSync<LeSetResolvablePrivateAddrTimeout>,
+    {
+        self.command(LeSetAddrResolutionEnable::new(false)).await?;
+        self.command(LeClearResolvingList::new()).await?;
+        // The controller picks the local IRK of undirected advertising from the entry matching
+        // the peer address of the advertising parameters, which is the zero public address when
+        // there is no peer. The zero peer IRK stands for a peer using its identity address.
+        self.command(LeAddDeviceToResolvingList::new(
+            AddrKind::PUBLIC,
+            BdAddr::default(),
+            [0; 16],
+            privacy.local_irk,
+        ))
+        .await?;
+        #[cfg(feature = "security")]
+        for bond in self.security.bonds() {
+            self.command(LeAddDeviceToResolvingList::new(
+                bond.identity.kind,
+                bond.identity.addr,
+                bond.irk.map(|irk| irk.to_le_bytes()).unwrap_or([0; 16]),
+                privacy.local_irk,
+            ))
+            .await?;
+        }
+        self.command(LeSetResolvablePrivateAddrTimeout::new(privacy.rpa_timeout.into()))
+            .await?;
+        self.command(LeSetAddrResolutionEnable::new(true)).await?;
+        self.privacy.set(Some(privacy));
         Ok(())
     }
 
+    /// Request a reset of the controller, as it is considered unresponsive.
+    pub(crate) fn request_recovery(&self) {
+        self.recovery_requested.set(true);
+        self.recovery_waker.borrow_mut().wake();
+    }
+
+    fn poll_recovery(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.recovery_waker.borrow_mut().register(cx.waker());
+        if self.recovery_requested.replace(false) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
     fn handle_connection(
         &self,
         status: Status,
@@ -256,11 +1013,16 @@ where
         peer_addr_kind: AddrKind,
         peer_addr: BdAddr,
         role: LeConnRole,
+        params: ConnectionParams,
     ) -> bool {
         match status.to_result() {
             Ok(_) => {
-                if let Err(err) = self.connections.connect(handle, peer_addr_kind, peer_addr, role) {
-                    warn!("Error establishing connection: {:?}", err);
+                if let Err(err) = self
+                    .connections
+                    .connect(handle, peer_addr_kind, peer_addr, role)
+                    .and_then(|_| self.connections.set_connection_params(handle, params))
+                {
+                    warn!("[host] error establishing connection: {:?}", err);
                     return false;
                 } else {
                     #[cfg(feature = "defmt")]
@@ -276,6 +1038,26 @@ where
                     );
                     let mut m = self.metrics.borrow_mut();
                     m.connect_events = m.connect_events.wrapping_add(1);
+                    if role == LeConnRole::Peripheral {
+                        self.advertise_state.terminate_legacy();
+                    }
+                    self.publish(HostEvent::Connected {
+                        handle,
+                        role,
+                        peer: Address {
+                            kind: peer_addr_kind,
+                            addr: peer_addr,
+                        },
+                        params,
+                    });
+
+                    // Nobody is waiting for a connection that completes while the attempt is being cancelled.
+                    if role == LeConnRole::Central && self.connect_command_state.is_cancelling() {
+                        warn!("[host] connection established after connect was cancelled, disconnecting");
+                        self.connections
+                            .request_handle_disconnect(handle, DisconnectReason::RemoteUserTerminatedConn);
+                        self.connect_command_state.canceled();
+                    }
                 }
             }
             Err(bt_hci::param::Error::ADV_TIMEOUT) => {
@@ -285,8 +1067,14 @@ where
                 warn!("[host] connect cancelled");
                 self.connect_command_state.canceled();
             }
+            // The central and peripheral roles may be in use at the same time, so only the
+            // role of the failed connection is reset.
+            Err(e) if role == LeConnRole::Peripheral || self.connect_command_state.is_idle() => {
+                warn!("[host] error in connection complete event for peripheral: {:?}", e);
+                self.advertise_state.reset();
+            }
             Err(e) => {
-                warn!("Error connection complete event: {:?}", e);
+                warn!("[host] error in connection complete event: {:?}", e);
                 self.connect_command_state.canceled();
             }
         }
@@ -294,6 +1082,8 @@ where
     }
 
     fn handle_acl(&self, acl: AclPacket<'_>) -> Result<(), Error> {
+        #[cfg(feature = "traffic-metrics")]
+        self.count(|m| m.acl_rx = m.acl_rx.wrapping_add(1));
         self.connections.received(acl.handle())?;
         let handle = acl.handle();
         let on_drop = OnDrop::new(|| {
@@ -305,7 +1095,8 @@ where
 
                 // Ignore channels we don't support
                 if header.channel < L2CAP_CID_DYN_START
-                    && !(&[L2CAP_CID_LE_U_SIGNAL, L2CAP_CID_ATT].contains(&header.channel))
+                    && !(&[L2CAP_CID_LE_U_SIGNAL, L2CAP_CID_ATT, L2CAP_CID_LE_U_SECURITY_MANAGER]
+                        .contains(&header.channel))
                 {
                     warn!("[host] unsupported l2cap channel id {}", header.channel);
                     return Err(Error::NotSupported);
@@ -314,12 +1105,12 @@ where
                 // Avoids using the packet buffer for signalling packets
                 if header.channel == L2CAP_CID_LE_U_SIGNAL {
                     assert!(data.len() == header.length as usize);
-                    self.channels.signal(acl.handle(), data)?;
+                    self.channels.signal(acl.handle(), data, &self.connections)?;
                     return Ok(());
                 }
 
                 let Some(mut p) = self.rx_pool.alloc() else {
-                    info!("No memory for packets on channel {}", header.channel);
+                    warn!("[host] no memory for packets on channel {}", header.channel);
                     return Err(Error::OutOfMemory);
                 };
                 p.as_mut()[..data.len()].copy_from_slice(data);
@@ -341,16 +1132,32 @@ where
                 }
             }
             other => {
-                warn!("Unexpected boundary flag: {:?}!", other);
+                warn!("[host] unexpected boundary flag: {:?}", other);
                 return Err(Error::NotSupported);
             }
         };
 
         match header.channel {
             L2CAP_CID_ATT => {
+                // Verify signed writes here, passing them on as write commands.
+                #[cfg(all(feature = "security", feature = "gatt"))]
+                let mut header = header;
+                #[cfg(all(feature = "security", feature = "gatt"))]
+                if packet.as_ref().first() == Some(&att::ATT_SIGNED_WRITE_CMD) {
+                    let pdu = &packet.as_ref()[..header.length as usize];
+                    if !self.security.verify_signed_write(self, acl.handle(), pdu) {
+                        return Ok(());
+                    }
+                    packet.as_mut()[0] = att::ATT_WRITE_CMD;
+                    header.length -= crate::security_manager::SIGNATURE_LEN as u16;
+                }
                 // Handle ATT MTU exchange here since it doesn't strictly require
                 // gatt to be enabled.
                 let a = att::Att::decode(&packet.as_ref()[..header.length as usize]);
+                #[cfg(feature = "traffic-metrics")]
+                if a.as_ref().is_ok_and(is_gatt_error) {
+                    self.count(|m| m.gatt_errors = m.gatt_errors.wrapping_add(1));
+                }
                 if let Ok(att::Att::Client(AttClient::Request(att::AttReq::ExchangeMtu { mtu }))) = a {
                     let mtu = self.connections.exchange_att_mtu(acl.handle(), mtu);
 
@@ -370,10 +1177,42 @@ where
                     on_drop.defuse();
                 } else if let Ok(att::Att::Server(AttServer::Response(att::AttRsp::ExchangeMtu { mtu }))) = a {
                     info!("[host] remote agreed att MTU of {}", mtu);
+                    procedure::end(Procedure::MtuExchange, acl.handle());
                     self.connections.exchange_att_mtu(acl.handle(), mtu);
                 } else {
                     #[cfg(feature = "gatt")]
                     match a {
+                        #[cfg(feature = "security")]
+                        Ok(att::Att::Client(ref client)) if !self.link_secure_enough(acl.handle()) => {
+                            if let Some((request, handle)) = restricted(client) {
+                                // Reply in the packet of the request.
+                                let rsp = att::Att::Server(AttServer::Response(att::AttRsp::Error {
+                                    request,
+                                    handle,
+                                    code: att::AttErrorCode::INSUFFICIENT_AUTHENTICATION,
+                                }));
+                                let mut w = WriteCursor::new(packet.as_mut());
+                                w.write_hci(&L2capHeader {
+                                    channel: L2CAP_CID_ATT,
+                                    length: rsp.size() as u16,
+                                })?;
+                                w.write(rsp)?;
+                                let len = w.len();
+                                self.connections.try_outbound(acl.handle(), Pdu::new(packet, len))?;
+                                on_drop.defuse();
+                            } else if !matches!(client, AttClient::Command(_)) {
+                                let event = ConnectionEventData::Gatt {
+                                    data: Pdu::new(packet, header.length as usize),
+                                };
+                                self.connections.post_handle_event(acl.handle(), event)?;
+                                on_drop.defuse();
+                            } else {
+                                warn!(
+                                    "[host] dropping command from insufficiently secure conn {:?}",
+                                    acl.handle()
+                                );
+                            }
+                        }
                         Ok(att::Att::Client(_)) => {
                             let event = ConnectionEventData::Gatt {
                                 data: Pdu::new(packet, header.length as usize),
@@ -391,7 +1230,7 @@ where
                             on_drop.defuse();
                         }
                         Err(e) => {
-                            warn!("Error decoding attribute payload: {:?}", e);
+                            warn!("[host] error decoding attribute payload: {:?}", e);
                         }
                     }
                     #[cfg(not(feature = "gatt"))]
@@ -401,12 +1240,46 @@ where
             L2CAP_CID_LE_U_SIGNAL => {
                 panic!("le signalling channel was fragmented, impossible!");
             }
+            #[cfg(feature = "security")]
+            L2CAP_CID_LE_U_SECURITY_MANAGER => {
+                // Commands are at most a public key of 65 octets, and replies use packets of their own.
+                let mut pdu = [0; crate::security_manager::MAX_PDU_LEN];
+                let len = header.length as usize;
+                if len > pdu.len() {
+                    drop(packet);
+                    self.security.reject_oversized(self, acl.handle());
+                } else {
+                    pdu[..len].copy_from_slice(&packet.as_ref()[..len]);
+                    drop(packet);
+                    self.security.handle(self, acl.handle(), &pdu[..len]);
+                }
+            }
+            #[cfg(not(feature = "security"))]
+            L2CAP_CID_LE_U_SECURITY_MANAGER => {
+                // Without the security manager, pairing is never possible and links stay
+                // unencrypted. Tell the peer right away instead of letting it wait for the SMP
+                // procedure timeout.
+                let code = packet.as_ref()[..header.length as usize].first().copied();
+                if matches!(code, Some(SMP_PAIRING_REQUEST | SMP_SECURITY_REQUEST)) {
+                    warn!("[host] rejecting pairing attempt from conn {:?}", acl.handle());
+                    let l2cap = L2capHeader {
+                        channel: L2CAP_CID_LE_U_SECURITY_MANAGER,
+                        length: 2,
+                    };
+                    let mut w = WriteCursor::new(packet.as_mut());
+                    w.write_hci(&l2cap)?;
+                    w.append(&[SMP_PAIRING_FAILED, SMP_REASON_PAIRING_NOT_SUPPORTED])?;
+                    let len = w.len();
+                    self.connections.try_outbound(acl.handle(), Pdu::new(packet, len))?;
+                    on_drop.defuse();
+                }
+            }
             other if other >= L2CAP_CID_DYN_START => match self.channels.dispatch(header, packet) {
                 Ok(_) => {
                     on_drop.defuse();
                 }
                 Err(e) => {
-                    warn!("Error dispatching l2cap packet to channel: {:?}", e);
+                    warn!("[host] error dispatching l2cap packet to channel: {:?}", e);
                     return Err(e);
                 }
             },
@@ -422,16 +1295,71 @@ where
         Ok(())
     }
 
-    // Send l2cap signal payload
-    pub(crate) async fn l2cap_signal<D: L2capSignal>(
-        &self,
-        conn: ConnHandle,
-        identifier: u8,
-        signal: &D,
-        p_buf: &mut [u8],
-    ) -> Result<(), BleHostError<T::Error>> {
-        //trace!(
-        //    "[l2cap] sending control signal (req = {}) signal: {:?}",
+    /// Wait for a command of the security manager to run in the control runner.
+    async fn next_security_action(&self) -> SecurityAction {
+        #[cfg(feature = "security")]
+        return self.security.next_action(self).await;
+        #[cfg(not(feature = "security"))]
+        core::future::pending().await
+    }
+
+    // Wait for a dropped periodic sync to terminate.
+    fn poll_sync_terminate(&self, cx: &mut Context<'_>) -> Poll<bt_hci::param::SyncHandle> {
+        #[cfg(feature = "scan")]
+        return self.periodic_sync.poll_terminate(cx);
+        #[cfg(not(feature = "scan"))]
+        Poll::Pending
+    }
+
+    async fn run_security_action(&self, action: SecurityAction) -> Result<(), BleHostError<T::Error>>
+    where
+        T: crate::SecurityController,
+    {
+        #[cfg(feature = "security")]
+        {
+            use crate::security_manager::Action;
+            match action {
+                Action::LtkReply { handle, ltk } => {
+                    self.command(LeLongTermKeyRequestReply::new(handle, ltk.to_le_bytes()))
+                        .await?;
+                }
+                Action::LtkNegativeReply { handle } => {
+                    self.command(LeLongTermKeyRequestNegativeReply::new(handle)).await?;
+                }
+                Action::EnableEncryption {
+                    handle,
+                    ltk,
+                    ediv,
+                    rand,
+                } => {
+                    self.async_command(LeEnableEncryption::new(handle, rand, ediv, ltk.to_le_bytes()))
+                        .await?;
+                }
+            }
+            Ok(())
+        }
+        #[cfg(not(feature = "security"))]
+        match action {}
+    }
+
+    /// Check if a link reached the security level the security manager requires for services.
+    #[cfg(feature = "security")]
+    pub(crate) fn link_secure_enough(&self, handle: ConnHandle) -> bool {
+        self.connections
+            .link_info(handle)
+            .is_some_and(|info| info.security_level >= self.security.required_level())
+    }
+
+    // Send l2cap signal payload
+    pub(crate) async fn l2cap_signal<D: L2capSignal>(
+        &self,
+        conn: ConnHandle,
+        identifier: u8,
+        signal: &D,
+        p_buf: &mut [u8],
+    ) -> Result<(), BleHostError<T::Error>> {
+        //trace!(
+        //    "[l2cap] sending control signal (req = {}) signal: {:?}",
         //    identifier,
         //    signal
         //);
@@ -467,15 +1395,18 @@ where
         n_packets: u16,
     ) -> Result<L2capSender<'_, 'd, T>, BleHostError<T::Error>> {
         // Take into account l2cap header.
-        let acl_max = self.initialized.get().await.acl_max as u16;
+        let acl_max = self.initialized.get().await.get().acl_max as u16;
         let len = len + (4 * n_packets);
         let n_acl = len.div_ceil(acl_max);
         let grant = poll_fn(|cx| self.connections.poll_request_to_send(handle, n_acl as usize, Some(cx))).await?;
         Ok(L2capSender {
             controller: &self.controller,
+            capture: self.capture,
             handle,
             grant,
             fragment_size: acl_max,
+            #[cfg(feature = "traffic-metrics")]
+            metrics: &self.traffic_metrics,
         })
     }
 
@@ -489,7 +1420,7 @@ where
         len: u16,
         n_packets: u16,
     ) -> Result<L2capSender<'_, 'd, T>, BleHostError<T::Error>> {
-        let acl_max = self.initialized.try_get().map(|i| i.acl_max).unwrap_or(27) as u16;
+        let acl_max = self.initialized.try_get().map(|i| i.get().acl_max).unwrap_or(27) as u16;
         let len = len + (4 * n_packets);
         let n_acl = len.div_ceil(acl_max);
         let grant = match self.connections.poll_request_to_send(handle, n_acl as usize, None) {
@@ -500,9 +1431,12 @@ where
         };
         Ok(L2capSender {
             controller: &self.controller,
+            capture: self.capture,
             handle,
             grant,
             fragment_size: acl_max,
+            #[cfg(feature = "traffic-metrics")]
+            metrics: &self.traffic_metrics,
         })
     }
 
@@ -512,12 +1446,41 @@ where
         m
     }
 
+    /// Read current command metrics
+    #[cfg(feature = "command-metrics")]
+    pub(crate) fn command_metrics(&self) -> CommandMetrics {
+        let mut m = self.command_metrics.borrow().clone();
+        if let Some(credits) = self.command_credits {
+            m.credits = credits.current();
+            m.min_credits = credits.min();
+        }
+        m
+    }
+
+    /// Read current traffic metrics
+    #[cfg(feature = "traffic-metrics")]
+    pub(crate) fn traffic_metrics(&self) -> TrafficMetrics {
+        let mut m = self.traffic_metrics.borrow().clone();
+        m.rx_pool = self.rx_pool.metrics();
+        #[cfg(feature = "gatt")]
+        {
+            m.tx_pool = self.tx_pool.metrics();
+        }
+        m
+    }
+
+    #[cfg(feature = "traffic-metrics")]
+    pub(crate) fn count(&self, f: impl FnOnce(&mut TrafficMetrics)) {
+        f(&mut self.traffic_metrics.borrow_mut());
+    }
+
     /// Log status information of the host
     pub(crate) fn log_status(&self, verbose: bool) {
         let m = self.metrics.borrow();
         debug!("[host] connect events: {}", m.connect_events);
         debug!("[host] disconnect events: {}", m.disconnect_events);
         debug!("[host] rx errors: {}", m.rx_errors);
+        debug!("[host] controller resets: {}", m.controller_resets);
         self.connections.log_status(verbose);
         self.channels.log_status(verbose);
     }
@@ -547,14 +1510,45 @@ pub struct TxRunner<'d, C> {
 
 /// Event handler.
 pub trait EventHandler {
-    /// Handle vendor events
+    /// Handle vendor-specific events, such as those triggered by [`Stack::vendor_command`](crate::Stack::vendor_command).
     fn on_vendor(&self, vendor: &Vendor) {}
     /// Handle advertising reports
     #[cfg(feature = "scan")]
     fn on_adv_reports(&self, reports: bt_hci::param::LeAdvReportsIter) {}
     /// Handle extended advertising reports
+    ///
+    /// `bt-hci` misplaces the data of these reports, which [`crate::scan::ExtAdvReport`] decodes
+    /// correctly, and so does [`EventHandler::on_scan_report`].
     #[cfg(feature = "scan")]
     fn on_ext_adv_reports(&self, reports: bt_hci::param::LeExtAdvReportsIter) {}
+    /// Handle a single advertising or extended advertising report.
+    ///
+    /// Called for every report after `on_adv_reports` or `on_ext_adv_reports`, except for
+    /// reports already seen during the current scan when the host duplicate filter is enabled
+    /// through the `SCAN_CACHE` size of `HostResources`. Scan responses are reported along with
+    /// their advertisement when the `SCAN_RESPONSES` size of `HostResources` is not zero, which
+    /// may delay the report until the next one is received. Extended advertising data split over
+    /// several reports is reported once reassembled, up to `config::SCAN_REPORT_DATA_SIZE` bytes.
+    #[cfg(feature = "scan")]
+    fn on_scan_report(&self, report: &crate::scan::ScanReport<'_>) {}
+    /// Handle periodic advertising reports
+    #[cfg(feature = "scan")]
+    fn on_periodic_adv_report(&self, report: &bt_hci::event::le::LePeriodicAdvertisingReport) {}
+    /// Handle the loss of a periodic advertising sync
+    #[cfg(feature = "scan")]
+    fn on_periodic_sync_lost(&self, handle: bt_hci::param::SyncHandle) {}
+    /// Handle BIGInfo reports, describing the broadcast isochronous group of a synced periodic advertiser
+    #[cfg(all(feature = "scan", feature = "iso"))]
+    fn on_biginfo_report(&self, info: &crate::iso::BigInfo) {}
+    /// Handle scan requests received by an advertisement set with scan request notifications enabled
+    #[cfg(feature = "peripheral")]
+    fn on_scan_request(&self, handle: AdvHandle, scanner: Address) {}
+    /// Handle a request of the controller for the data of subevents of periodic advertising with responses
+    #[cfg(feature = "peripheral")]
+    fn on_subevent_data_request(&self, request: &crate::advertise::PawrSubeventDataRequest) {}
+    /// Handle the responses received in a subevent of periodic advertising with responses
+    #[cfg(feature = "peripheral")]
+    fn on_response_report(&self, report: &crate::advertise::PawrResponseReport<'_>) {}
 }
 
 struct DummyHandler;
@@ -579,17 +1573,24 @@ impl<'d, C: Controller> Runner<'d, C> {
     where
         C: ControllerCmdSync<Disconnect>
             + ControllerCmdSync<SetEventMask>
+            + crate::SecurityController
+            + crate::ScanController
+            + crate::PrivacyController
+            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
             + ControllerCmdSync<LeSetEventMask>
             + ControllerCmdSync<LeSetRandomAddr>
             + ControllerCmdSync<HostBufferSize>
             + ControllerCmdAsync<LeConnUpdate>
             + ControllerCmdSync<LeReadFilterAcceptListSize>
+            + ControllerCmdSync<LeReadLocalSupportedFeatures>
+            + ControllerCmdSync<LeReadMaxDataLength>
+            + ControllerCmdSync<LeWriteSuggestedDefaultDataLength>
             + ControllerCmdSync<SetControllerToHostFlowControl>
             + ControllerCmdSync<Reset>
             + ControllerCmdSync<LeCreateConnCancel>
             + ControllerCmdSync<LeSetScanEnable>
             + ControllerCmdSync<LeSetExtScanEnable>
-            + for<'t> ControllerCmdSync<LeSetAdvEnable>
+            + ControllerCmdSync<LeSetAdvEnable>
             + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
             + for<'t> ControllerCmdSync<HostNumberOfCompletedPackets<'t>>
             + ControllerCmdSync<LeReadBufferSize>,
@@ -603,13 +1604,20 @@ impl<'d, C: Controller> Runner<'d, C> {
     where
         C: ControllerCmdSync<Disconnect>
             + ControllerCmdSync<SetEventMask>
+            + crate::SecurityController
+            + crate::ScanController
+            + crate::PrivacyController
+            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
             + ControllerCmdSync<LeSetEventMask>
             + ControllerCmdSync<LeSetRandomAddr>
             + ControllerCmdSync<LeReadFilterAcceptListSize>
+            + ControllerCmdSync<LeReadLocalSupportedFeatures>
+            + ControllerCmdSync<LeReadMaxDataLength>
+            + ControllerCmdSync<LeWriteSuggestedDefaultDataLength>
             + ControllerCmdSync<HostBufferSize>
             + ControllerCmdAsync<LeConnUpdate>
             + ControllerCmdSync<SetControllerToHostFlowControl>
-            + for<'t> ControllerCmdSync<LeSetAdvEnable>
+            + ControllerCmdSync<LeSetAdvEnable>
             + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
             + for<'t> ControllerCmdSync<HostNumberOfCompletedPackets<'t>>
             + ControllerCmdSync<LeSetScanEnable>
@@ -618,7 +1626,44 @@ impl<'d, C: Controller> Runner<'d, C> {
             + ControllerCmdSync<LeCreateConnCancel>
             + ControllerCmdSync<LeReadBufferSize>,
     {
-        let control_fut = self.control.run();
+        self.run_with_setup(event_handler, async |_: &C| Ok(())).await
+    }
+
+    /// Run the host with a vendor event handler, running `setup` with the controller before it is initialized.
+    ///
+    /// See [`ControlRunner::run_with_setup`].
+    pub async fn run_with_setup<E: EventHandler, F>(
+        &mut self,
+        event_handler: &E,
+        setup: F,
+    ) -> Result<(), BleHostError<C::Error>>
+    where
+        F: AsyncFn(&C) -> Result<(), BleHostError<C::Error>>,
+        C: ControllerCmdSync<Disconnect>
+            + ControllerCmdSync<SetEventMask>
+            + crate::SecurityController
+            + crate::ScanController
+            + crate::PrivacyController
+            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
+            + ControllerCmdSync<LeSetEventMask>
+            + ControllerCmdSync<LeSetRandomAddr>
+            + ControllerCmdSync<LeReadFilterAcceptListSize>
+            + ControllerCmdSync<LeReadLocalSupportedFeatures>
+            + ControllerCmdSync<LeReadMaxDataLength>
+            + ControllerCmdSync<LeWriteSuggestedDefaultDataLength>
+            + ControllerCmdSync<HostBufferSize>
+            + ControllerCmdAsync<LeConnUpdate>
+            + ControllerCmdSync<SetControllerToHostFlowControl>
+            + ControllerCmdSync<LeSetAdvEnable>
+            + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
+            + for<'t> ControllerCmdSync<HostNumberOfCompletedPackets<'t>>
+            + ControllerCmdSync<LeSetScanEnable>
+            + ControllerCmdSync<LeSetExtScanEnable>
+            + ControllerCmdSync<Reset>
+            + ControllerCmdSync<LeCreateConnCancel>
+            + ControllerCmdSync<LeReadBufferSize>,
+    {
+        let control_fut = self.control.run_with_setup(setup);
         let rx_fut = self.rx.run_with_handler(event_handler);
         let tx_fut = self.tx.run();
         pin_mut!(control_fut, rx_fut, tx_fut);
@@ -668,12 +1713,19 @@ impl<'d, C: Controller> RxRunner<'d, C> {
             //     trace!("[host] time since last poll was {} us", elapsed);
             // }
             let result = host.controller.read(&mut rx).await;
+            if let Ok(packet) = &result {
+                capture::record_received(host.capture, packet);
+                host.controller_activity
+                    .set(host.controller_activity.get().wrapping_add(1));
+            }
             // last = Instant::now();
             //        trace!("[host] polling took {} ms", (polled - started).as_millis());
             match result {
                 Ok(ControllerToHostPacket::Acl(acl)) => match host.handle_acl(acl) {
                     Ok(_) => {}
                     Err(e) => {
+                        #[cfg(feature = "traffic-metrics")]
+                        host.count(|m| m.dropped = m.dropped.wrapping_add(1));
                         warn!(
                             "[host] encountered error processing ACL data for {:?}: {:?}",
                             acl.handle(),
@@ -699,7 +1751,19 @@ impl<'d, C: Controller> RxRunner<'d, C> {
                     match event {
                         Event::Le(event) => match event {
                             LeEvent::LeConnectionComplete(e) => {
-                                if !host.handle_connection(e.status, e.handle, e.peer_addr_kind, e.peer_addr, e.role) {
+                                let params = ConnectionParams::from_hci(
+                                    e.conn_interval,
+                                    e.peripheral_latency,
+                                    e.supervision_timeout,
+                                );
+                                if !host.handle_connection(
+                                    e.status,
+                                    e.handle,
+                                    e.peer_addr_kind,
+                                    e.peer_addr,
+                                    e.role,
+                                    params,
+                                ) {
                                     let _ = host
                                         .command(Disconnect::new(
                                             e.handle,
@@ -710,7 +1774,19 @@ impl<'d, C: Controller> RxRunner<'d, C> {
                                 }
                             }
                             LeEvent::LeEnhancedConnectionComplete(e) => {
-                                if !host.handle_connection(e.status, e.handle, e.peer_addr_kind, e.peer_addr, e.role) {
+                                let params = ConnectionParams::from_hci(
+                                    e.conn_interval,
+                                    e.peripheral_latency,
+                                    e.supervision_timeout,
+                                );
+                                if !host.handle_connection(
+                                    e.status,
+                                    e.handle,
+                                    e.peer_addr_kind,
+                                    e.peer_addr,
+                                    e.role,
+                                    params,
+                                ) {
                                     let _ = host
                                         .command(Disconnect::new(
                                             e.handle,
@@ -718,26 +1794,217 @@ impl<'d, C: Controller> RxRunner<'d, C> {
                                         ))
                                         .await;
                                     host.connect_command_state.canceled();
+                                } else if e.status.to_result().is_ok() {
+                                    let _ = host.connections.set_private_addresses(
+                                        e.handle,
+                                        e.local_resolvable_private_addr,
+                                        e.peer_resolvable_private_addr,
+                                    );
+                                }
+                            }
+                            LeEvent::LeConnectionUpdateComplete(e) => {
+                                let result = e.status.to_result().map(|_| {
+                                    ConnectionParams::from_hci(
+                                        e.conn_interval,
+                                        e.peripheral_latency,
+                                        e.supervision_timeout,
+                                    )
+                                });
+                                if let Ok(params) = result {
+                                    host.publish(HostEvent::ConnectionParamsUpdated {
+                                        handle: e.handle,
+                                        params,
+                                    });
+                                }
+                                if let Err(e) = host.connections.connection_params_updated(e.handle, result) {
+                                    warn!("[host] error updating connection parameters: {:?}", e);
+                                }
+                            }
+                            LeEvent::LePhyUpdateComplete(e) => {
+                                if let Err(e) = e.status.to_result() {
+                                    warn!("[host] phy update failed: {:?}", e);
+                                } else {
+                                    host.publish(HostEvent::PhyChanged {
+                                        handle: e.handle,
+                                        tx_phy: e.tx_phy,
+                                        rx_phy: e.rx_phy,
+                                    });
+                                    if let Err(e) = host.connections.post_handle_event(
+                                        e.handle,
+                                        ConnectionEventData::PhyChanged {
+                                            tx_phy: e.tx_phy,
+                                            rx_phy: e.rx_phy,
+                                        },
+                                    ) {
+                                        warn!("[host] error posting phy update: {:?}", e);
+                                    }
+                                }
+                            }
+                            LeEvent::LeSubrateChange(e) => {
+                                if let Err(e) = e.status.to_result() {
+                                    warn!("[host] subrate change failed: {:?}", e);
+                                } else {
+                                    let subrate = Subrate {
+                                        factor: e.subrate_factor,
+                                        peripheral_latency: e.peripheral_latency,
+                                        continuation_number: e.continuation_number,
+                                        supervision_timeout: embassy_time::Duration::from_micros(
+                                            e.supervision_timeout.as_micros(),
+                                        ),
+                                    };
+                                    if let Err(e) = host
+                                        .connections
+                                        .post_handle_event(e.handle, ConnectionEventData::SubrateChanged { subrate })
+                                    {
+                                        warn!("[host] error posting subrate change: {:?}", e);
+                                    }
+                                }
+                            }
+                            LeEvent::LeTransmitPowerReporting(e) => {
+                                if let Err(e) = e.status.to_result() {
+                                    warn!("[host] tx power reporting failed: {:?}", e);
+                                } else {
+                                    let report = TxPowerReport {
+                                        reason: e.reason,
+                                        phy: e.phy,
+                                        tx_power_level: e.tx_power_level,
+                                        delta: e.delta,
+                                    };
+                                    if let Err(e) = host
+                                        .connections
+                                        .post_handle_event(e.handle, ConnectionEventData::TxPowerReported { report })
+                                    {
+                                        warn!("[host] error posting tx power report: {:?}", e);
+                                    }
+                                }
+                            }
+                            LeEvent::LeReadRemoteFeaturesComplete(e) => {
+                                let result = e.status.to_result().map(|_| e.le_features);
+                                if let Err(e) = host.connections.remote_features_read(e.handle, result) {
+                                    warn!("[host] error updating remote features: {:?}", e);
+                                }
+                            }
+                            LeEvent::LeDataLengthChange(e) => {
+                                let data_length = DataLength {
+                                    max_tx_octets: e.max_tx_octets,
+                                    max_tx_time: e.max_tx_time,
+                                    max_rx_octets: e.max_rx_octets,
+                                    max_rx_time: e.max_rx_time,
+                                };
+                                if let Err(e) = host.connections.data_length_changed(e.handle, data_length) {
+                                    warn!("[host] error updating data length: {:?}", e);
                                 }
                             }
                             LeEvent::LeScanTimeout(_) => {}
                             LeEvent::LeAdvertisingSetTerminated(set) => {
                                 host.advertise_state.terminate(set.adv_handle);
+                                host.publish(HostEvent::AdvertisingTerminated {
+                                    adv_handle: set.adv_handle,
+                                    status: set.status,
+                                    connection: set.status.to_result().is_ok().then_some(set.handle),
+                                });
+                            }
+                            LeEvent::LeScanRequestReceived(req) => {
+                                #[cfg(feature = "peripheral")]
+                                {
+                                    event_handler.on_scan_request(
+                                        req.adv_handle,
+                                        Address {
+                                            kind: req.scanner_addr_kind,
+                                            addr: req.scanner_addr,
+                                        },
+                                    );
+                                }
                             }
                             LeEvent::LeExtendedAdvertisingReport(data) => {
                                 #[cfg(feature = "scan")]
                                 {
                                     event_handler.on_ext_adv_reports(data.reports.iter());
+                                    let mut merger = host.scan_responses.borrow_mut();
+                                    let mut fragments = host.scan_fragments.borrow_mut();
+                                    for report in crate::scan::ExtAdvReport::iter(&data.reports).flatten() {
+                                        // Only legacy advertisements carry data along with a scan response,
+                                        // and only extended ones are fragmented
+                                        if report.event_kind.legacy() {
+                                            merger
+                                                .process_ext(&report, |r| host.deliver_scan_report(&r, event_handler));
+                                        } else {
+                                            fragments.process(&report, |r| host.deliver_scan_report(&r, event_handler));
+                                        }
+                                    }
                                 }
                             }
                             LeEvent::LeAdvertisingReport(data) => {
                                 #[cfg(feature = "scan")]
                                 {
                                     event_handler.on_adv_reports(data.reports.iter());
+                                    let mut merger = host.scan_responses.borrow_mut();
+                                    for report in data.reports.iter().flatten() {
+                                        merger.process(&report, |r| host.deliver_scan_report(&r, event_handler));
+                                    }
+                                }
+                            }
+                            #[cfg(feature = "security")]
+                            LeEvent::LeLongTermKeyRequest(e) => {
+                                host.security.long_term_key_request(
+                                    host,
+                                    e.handle,
+                                    e.random_number,
+                                    e.encrypted_diversifier,
+                                );
+                            }
+                            #[cfg(feature = "scan")]
+                            LeEvent::LePeriodicAdvertisingSyncEstablished(e) => {
+                                host.periodic_sync.established(crate::scan::SyncEstablished {
+                                    connection: None,
+                                    status: e.status,
+                                    handle: e.sync_handle,
+                                    addr: Address {
+                                        kind: e.adv_addr_kind,
+                                        addr: e.adv_addr,
+                                    },
+                                    sid: e.adv_sid,
+                                });
+                            }
+                            #[cfg(feature = "scan")]
+                            LeEvent::LePeriodicAdvertisingSyncTransferReceived(e) => {
+                                host.periodic_sync.established(crate::scan::SyncEstablished {
+                                    connection: Some(e.handle),
+                                    status: e.status,
+                                    handle: e.sync_handle,
+                                    addr: Address {
+                                        kind: e.adv_addr_kind,
+                                        addr: e.adv_addr,
+                                    },
+                                    sid: e.adv_sid,
+                                });
+                            }
+                            #[cfg(feature = "scan")]
+                            LeEvent::LePeriodicAdvertisingReport(report) => {
+                                event_handler.on_periodic_adv_report(&report);
+                                host.periodic_reports.push(&report);
+                            }
+                            #[cfg(feature = "scan")]
+                            LeEvent::LePeriodicAdvertisingSyncLost(e) => {
+                                event_handler.on_periodic_sync_lost(e.sync_handle);
+                                host.periodic_reports.lost(e.sync_handle);
+                            }
+                            #[cfg(all(feature = "scan", feature = "iso"))]
+                            LeEvent::LeBiginfoAdvertisingReport(e) => {
+                                event_handler.on_biginfo_report(&crate::iso::BigInfo::from(&e));
+                            }
+                            #[cfg(feature = "iso")]
+                            LeEvent::LeCisEstablished(e) => {
+                                host.iso.cis_established(&e);
+                            }
+                            #[cfg(feature = "iso")]
+                            LeEvent::LeCisRequest(e) => {
+                                if !host.iso.cis_requested(&e) {
+                                    warn!("[host] dropping CIS request on handle {}", e.cis_handle.raw());
                                 }
                             }
                             _ => {
-                                warn!("Unknown LE event!");
+                                warn!("[host] unknown LE event");
                             }
                         },
                         Event::DisconnectionComplete(e) => {
@@ -757,7 +2024,12 @@ impl<'d, C: Controller> RxRunner<'d, C> {
                                 None
                             }
                             .unwrap_or(Status::UNSPECIFIED);
+                            #[cfg(feature = "security")]
+                            host.security.disconnected(handle);
+                            #[cfg(feature = "iso")]
+                            host.iso.disconnected(handle, reason);
                             let _ = host.connections.disconnected(handle, reason);
+                            host.publish(HostEvent::Disconnected { handle, reason });
                             let _ = host.channels.disconnected(handle);
                             host.reassembly.disconnected(handle);
                             let mut m = host.metrics.borrow_mut();
@@ -768,6 +2040,10 @@ impl<'d, C: Controller> RxRunner<'d, C> {
                             for entry in c.completed_packets.iter() {
                                 match (entry.handle(), entry.num_completed_packets()) {
                                     (Ok(handle), Ok(completed)) => {
+                                        #[cfg(feature = "iso")]
+                                        if host.iso.confirm_sent(handle, completed as usize) {
+                                            continue;
+                                        }
                                         let _ = host.connections.confirm_sent(handle, completed as usize);
                                     }
                                     (Ok(handle), Err(e)) => {
@@ -777,13 +2053,107 @@ impl<'d, C: Controller> RxRunner<'d, C> {
                                 }
                             }
                         }
+                        Event::ReadRemoteVersionInformationComplete(e) => {
+                            let result = e.status.to_result().map(|_| RemoteVersion {
+                                version: e.version,
+                                company_id: e.company_id,
+                                subversion: e.subversion,
+                            });
+                            if let Err(e) = host.connections.remote_version_read(e.handle, result) {
+                                warn!("[host] error updating remote version: {:?}", e);
+                            }
+                        }
+                        Event::AuthenticatedPayloadTimeoutExpired(e) => {
+                            if let Err(e) = host
+                                .connections
+                                .post_handle_event(e.handle, ConnectionEventData::AuthenticatedPayloadTimeoutExpired)
+                            {
+                                warn!("[host] error posting authenticated payload timeout: {:?}", e);
+                            }
+                        }
+                        Event::EncryptionChangeV1(e) => {
+                            if let Err(err) = e.status.to_result() {
+                                warn!(
+                                    "[host] encryption change failed on handle {}: {:?}",
+                                    e.handle.raw(),
+                                    err
+                                );
+                                #[cfg(feature = "security")]
+                                host.security.encryption_failed(host, e.handle);
+                            } else {
+                                #[cfg(feature = "security")]
+                                let level = host.security.encryption_changed(host, e.handle, e.enabled);
+                                #[cfg(not(feature = "security"))]
+                                let level = if e.enabled {
+                                    SecurityLevel::Encrypted
+                                } else {
+                                    SecurityLevel::NoEncryption
+                                };
+                                info!("[host] security level of handle {} is {:?}", e.handle.raw(), level);
+                                let _ = host.connections.set_security_level(e.handle, level);
+                                host.publish(HostEvent::SecurityChanged {
+                                    handle: e.handle,
+                                    security_level: level,
+                                });
+                            }
+                        }
+                        Event::EncryptionKeyRefreshComplete(e) => {
+                            if let Err(err) = e.status.to_result() {
+                                warn!(
+                                    "[host] encryption key refresh failed on handle {}: {:?}",
+                                    e.handle.raw(),
+                                    err
+                                );
+                            } else {
+                                #[cfg(feature = "security")]
+                                let level = host.security.encryption_changed(host, e.handle, true);
+                                #[cfg(not(feature = "security"))]
+                                let level = SecurityLevel::Encrypted;
+                                let _ = host.connections.set_security_level(e.handle, level);
+                                host.publish(HostEvent::SecurityChanged {
+                                    handle: e.handle,
+                                    security_level: level,
+                                });
+                            }
+                        }
+                        Event::HardwareError(e) => {
+                            warn!("[host] controller hardware error {}", e.hardware_code);
+                            host.request_recovery();
+                        }
                         Event::Vendor(vendor) => {
                             event_handler.on_vendor(&vendor);
                         }
+                        Event::Unknown { code: 0x3e, params } => {
+                            #[cfg(feature = "iso")]
+                            match crate::iso::BigEvent::from_le_meta(params) {
+                                Ok(Some(e)) => host.iso.big_event(&e),
+                                Ok(None) => {}
+                                Err(e) => warn!("[host] error parsing BIG event: {:?}", e),
+                            }
+                            #[cfg(feature = "peripheral")]
+                            match crate::advertise::PawrEvent::from_le_meta(params) {
+                                Ok(Some(crate::advertise::PawrEvent::SubeventDataRequest(request))) => {
+                                    event_handler.on_subevent_data_request(&request)
+                                }
+                                Ok(Some(crate::advertise::PawrEvent::ResponseReport(report))) => {
+                                    event_handler.on_response_report(&report)
+                                }
+                                Ok(None) => {}
+                                Err(e) => warn!("[host] error parsing PAwR event: {:?}", e),
+                            }
+                        }
                         // Ignore
                         _ => {}
                     }
                 }
+                #[cfg(feature = "iso")]
+                Ok(ControllerToHostPacket::Iso(iso)) => {
+                    if let Err(e) = host.iso.received(&iso, host.rx_pool) {
+                        warn!("[host] error processing ISO data for {:?}: {:?}", iso.handle(), e);
+                        let mut m = host.metrics.borrow_mut();
+                        m.rx_errors = m.rx_errors.wrapping_add(1);
+                    }
+                }
                 // Ignore
                 Ok(_) => {}
                 Err(e) => {
@@ -800,15 +2170,59 @@ impl<'d, C: Controller> ControlRunner<'d, C> {
     where
         C: ControllerCmdSync<Disconnect>
             + ControllerCmdSync<SetEventMask>
+            + crate::SecurityController
+            + crate::ScanController
+            + crate::PrivacyController
+            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
             + ControllerCmdSync<LeSetEventMask>
             + ControllerCmdSync<LeSetRandomAddr>
             + ControllerCmdSync<HostBufferSize>
             + ControllerCmdAsync<LeConnUpdate>
             + ControllerCmdSync<LeReadFilterAcceptListSize>
+            + ControllerCmdSync<LeReadLocalSupportedFeatures>
+            + ControllerCmdSync<LeReadMaxDataLength>
+            + ControllerCmdSync<LeWriteSuggestedDefaultDataLength>
             + ControllerCmdSync<SetControllerToHostFlowControl>
             + ControllerCmdSync<Reset>
             + ControllerCmdSync<LeCreateConnCancel>
-            + for<'t> ControllerCmdSync<LeSetAdvEnable>
+            + ControllerCmdSync<LeSetAdvEnable>
+            + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
+            + ControllerCmdSync<LeSetScanEnable>
+            + ControllerCmdSync<LeSetExtScanEnable>
+            + for<'t> ControllerCmdSync<HostNumberOfCompletedPackets<'t>>
+            + ControllerCmdSync<LeReadBufferSize>,
+    {
+        self.run_with_setup(async |_: &C| Ok(())).await
+    }
+
+    /// Run the control loop for the host, running `setup` with the controller before it is initialized.
+    ///
+    /// The setup runs right after the controller is reset by the host, before the host configures it,
+    /// and may be used for vendor specific bring-up such as downloading firmware patches, or to enable
+    /// host features with [`HostFeatures::enable`]. Commands must be run directly on the controller, as
+    /// the host only accepts commands once initialized, while the receive loop runs to complete them.
+    /// It runs again when an unresponsive controller is reset.
+    pub async fn run_with_setup<F>(&mut self, setup: F) -> Result<(), BleHostError<C::Error>>
+    where
+        F: AsyncFn(&C) -> Result<(), BleHostError<C::Error>>,
+        C: ControllerCmdSync<Disconnect>
+            + ControllerCmdSync<SetEventMask>
+            + crate::SecurityController
+            + crate::ScanController
+            + crate::PrivacyController
+            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
+            + ControllerCmdSync<LeSetEventMask>
+            + ControllerCmdSync<LeSetRandomAddr>
+            + ControllerCmdSync<HostBufferSize>
+            + ControllerCmdAsync<LeConnUpdate>
+            + ControllerCmdSync<LeReadFilterAcceptListSize>
+            + ControllerCmdSync<LeReadLocalSupportedFeatures>
+            + ControllerCmdSync<LeReadMaxDataLength>
+            + ControllerCmdSync<LeWriteSuggestedDefaultDataLength>
+            + ControllerCmdSync<SetControllerToHostFlowControl>
+            + ControllerCmdSync<Reset>
+            + ControllerCmdSync<LeCreateConnCancel>
+            + ControllerCmdSync<LeSetAdvEnable>
             + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
             + ControllerCmdSync<LeSetScanEnable>
             + ControllerCmdSync<LeSetExtScanEnable>
@@ -816,39 +2230,242 @@ impl<'d, C: Controller> ControlRunner<'d, C> {
             + ControllerCmdSync<LeReadBufferSize>,
     {
         let host = &self.stack.host;
-        Reset::new().exec(&host.controller).await?;
+        let initial = self.initialize(&setup).await?;
+        let _ = host.initialized.init(Cell::new(initial));
+        info!("[host] initialized");
+
+        #[allow(unused_mut)]
+        let mut completed_packets_cursor = 0;
+        loop {
+            match select3(
+                poll_fn(|cx| host.poll_recovery(cx)),
+                host.next_security_action(),
+                select4(
+                    poll_fn(|cx| host.connections.poll_disconnecting(Some(cx))),
+                    poll_fn(|cx| host.channels.poll_disconnecting(Some(cx))),
+                    poll_fn(|cx| {
+                        host.connections
+                            .poll_completed_packets(completed_packets_cursor, Some(cx))
+                    }),
+                    select4(
+                        poll_fn(|cx| host.connect_command_state.poll_cancelled(cx)),
+                        poll_fn(|cx| host.advertise_command_state.poll_cancelled(cx)),
+                        poll_fn(|cx| host.scan_command_state.poll_cancelled(cx)),
+                        poll_fn(|cx| host.poll_sync_terminate(cx)),
+                    ),
+                ),
+            )
+            .await
+            {
+                Either3::First(_) => self.recover(&setup).await?,
+                Either3::Second(action) => recoverable(host.run_security_action(action).await)?,
+                Either3::Third(event) => match event {
+                    Either4::First(request) => {
+                        trace!("[host] poll disconnecting links");
+                        recoverable(host.command(Disconnect::new(request.handle(), request.reason())).await)?;
+                        request.confirm();
+                    }
+                    Either4::Second(request) => {
+                        trace!("[host] poll disconnecting channels");
+                        recoverable(request.send(host).await)?;
+                        request.confirm();
+                    }
+                    Either4::Third(completed) => {
+                        #[cfg(feature = "controller-host-flow-control")]
+                        {
+                            if let Err(e) = host
+                                .raw_command(HostNumberOfCompletedPackets::new(&[ConnHandleCompletedPackets::new(
+                                    completed.handle(),
+                                    completed.amount(),
+                                )]))
+                                .await
+                            {
+                                warn!("[host] error performing flow control");
+                            }
+                            completed_packets_cursor = completed.confirm();
+                        }
+                    }
+                    Either4::Fourth(states) => match states {
+                        Either4::First(_) => {
+                            trace!("[host] cancel connection create");
+                            // trace!("[host] cancelling create connection");
+                            if host.command(LeCreateConnCancel::new()).await.is_err() {
+                                // Signal to ensure no one is stuck
+                                host.connect_command_state.canceled();
+                            }
+                        }
+                        Either4::Second(ext) => {
+                            trace!("[host] disabling advertising");
+                            if ext {
+                                recoverable(host.command(LeSetExtAdvEnable::new(false, &[])).await)?
+                            } else {
+                                recoverable(host.command(LeSetAdvEnable::new(false)).await)?
+                            }
+                            host.advertise_command_state.canceled();
+                        }
+                        Either4::Third(ext) => {
+                            trace!("[host] disabling scanning");
+                            if ext {
+                                // TODO: A bit opinionated but not more than before
+                                recoverable(
+                                    host.command(LeSetExtScanEnable::new(
+                                        false,
+                                        FilterDuplicates::Disabled,
+                                        bt_hci::param::Duration::from_secs(0),
+                                        bt_hci::param::Duration::from_secs(0),
+                                    ))
+                                    .await,
+                                )?;
+                            } else {
+                                recoverable(host.command(LeSetScanEnable::new(false, false)).await)?;
+                            }
+                            host.scan_command_state.canceled();
+                        }
+                        Either4::Fourth(handle) => {
+                            #[cfg(feature = "scan")]
+                            {
+                                trace!("[host] terminating periodic sync {:?}", handle);
+                                // The sync may have been lost meanwhile, which the controller rejects.
+                                if host.command(LePeriodicAdvTerminateSync::new(handle)).await.is_err() {
+                                    warn!("[host] error terminating periodic sync");
+                                }
+                                host.periodic_sync.terminated(handle);
+                            }
+                        }
+                    },
+                },
+            }
+        }
+    }
+
+    /// Reset the controller, run the setup and configure the controller for use by the host.
+    async fn initialize<F>(&self, setup: &F) -> Result<InitialState, BleHostError<C::Error>>
+    where
+        F: AsyncFn(&C) -> Result<(), BleHostError<C::Error>>,
+        C: ControllerCmdSync<Disconnect>
+            + ControllerCmdSync<SetEventMask>
+            + crate::SecurityController
+            + crate::ScanController
+            + crate::PrivacyController
+            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
+            + ControllerCmdSync<LeSetEventMask>
+            + ControllerCmdSync<LeSetRandomAddr>
+            + ControllerCmdSync<HostBufferSize>
+            + ControllerCmdAsync<LeConnUpdate>
+            + ControllerCmdSync<LeReadFilterAcceptListSize>
+            + ControllerCmdSync<LeReadLocalSupportedFeatures>
+            + ControllerCmdSync<LeReadMaxDataLength>
+            + ControllerCmdSync<LeWriteSuggestedDefaultDataLength>
+            + ControllerCmdSync<SetControllerToHostFlowControl>
+            + ControllerCmdSync<Reset>
+            + ControllerCmdSync<LeCreateConnCancel>
+            + ControllerCmdSync<LeSetAdvEnable>
+            + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
+            + ControllerCmdSync<LeSetScanEnable>
+            + ControllerCmdSync<LeSetExtScanEnable>
+            + for<'t> ControllerCmdSync<HostNumberOfCompletedPackets<'t>>
+            + ControllerCmdSync<LeReadBufferSize>,
+    {
+        let host = &self.stack.host;
+        host.init_command(Reset::new()).await?;
+        setup(&host.controller).await?;
 
         if let Some(addr) = host.address {
-            LeSetRandomAddr::new(addr.addr).exec(&host.controller).await?;
+            host.init_command(LeSetRandomAddr::new(addr.addr)).await?;
         }
 
-        SetEventMask::new(
+        host.init_command(SetEventMask::new(
             EventMask::new()
                 .enable_le_meta(true)
                 .enable_conn_request(true)
                 .enable_conn_complete(true)
                 .enable_hardware_error(true)
-                .enable_disconnection_complete(true),
-        )
-        .exec(&host.controller)
+                .enable_disconnection_complete(true)
+                .enable_read_remote_version_information_complete(true)
+                .enable_encryption_change_v1(true)
+                .enable_encryption_key_refresh_complete(true),
+        ))
         .await?;
 
-        LeSetEventMask::new(
+        // Authenticated payload timeouts only apply to encrypted links. Only supported by
+        // controllers with the LE ping feature.
+        #[cfg(feature = "security")]
+        if host
+            .init_command(SetEventMaskPage2::new(
+                bt_hci::param::EventMaskPage2::new().enable_authenticated_payload_timeout_expired(true),
+            ))
+            .await
+            .is_err()
+        {
+            warn!("[host] authenticated payload timeout events not supported");
+        }
+
+        host.init_command(LeSetEventMask::new(
             LeEventMask::new()
                 .enable_le_conn_complete(true)
                 .enable_le_enhanced_conn_complete(true)
+                .enable_le_conn_update_complete(true)
+                .enable_le_read_remote_features_complete(true)
+                .enable_le_phy_update_complete(true)
+                .enable_le_data_length_change(true)
+                .enable_le_transmit_power_reporting(true)
+                .enable_le_subrate_change(true)
                 .enable_le_adv_set_terminated(true)
+                .enable_le_scan_request_received(true)
+                .enable_le_periodic_adv_sync_established(true)
+                .enable_le_periodic_adv_report(true)
+                .enable_le_periodic_adv_sync_lost(true)
+                .enable_le_periodic_adv_sync_transfer_received(true)
                 .enable_le_adv_report(true)
                 .enable_le_scan_timeout(true)
-                .enable_le_ext_adv_report(true),
-        )
-        .exec(&host.controller)
+                .enable_le_ext_adv_report(true)
+                .enable_le_long_term_key_request(cfg!(feature = "security"))
+                .enable_le_cis_established(cfg!(feature = "iso"))
+                .enable_le_cis_request(cfg!(feature = "iso"))
+                // The BIG events are parsed by the host, as bt-hci parses their BIG handle as two octets.
+                .enable_le_create_big_complete(cfg!(all(feature = "peripheral", feature = "iso")))
+                .enable_le_terminate_big_complete(cfg!(all(feature = "peripheral", feature = "iso")))
+                .enable_le_big_sync_established(cfg!(all(feature = "scan", feature = "iso")))
+                .enable_le_big_sync_lost(cfg!(all(feature = "scan", feature = "iso")))
+                .enable_le_biginfo_adv_report(cfg!(all(feature = "scan", feature = "iso")))
+                // The advertiser events of periodic advertising with responses are parsed by the host, as
+                // bt-hci does not know them.
+                .enable_le_periodic_adv_subevent_data_request(cfg!(feature = "peripheral"))
+                .enable_le_periodic_adv_response_report(cfg!(feature = "peripheral")),
+        ))
         .await?;
 
-        let ret = LeReadFilterAcceptListSize::new().exec(&host.controller).await?;
-        info!("[host] filter accept list size: {}", ret);
+        #[cfg(feature = "security")]
+        host.security
+            .set_public_address(host.init_command(bt_hci::cmd::info::ReadBdAddr::new()).await?);
+
+        let filter_accept_list_size = host.init_command(LeReadFilterAcceptListSize::new()).await?;
+        info!("[host] filter accept list size: {}", filter_accept_list_size);
+
+        let le_features = host.init_command(LeReadLocalSupportedFeatures::new()).await?;
+        info!(
+            "[host] extended advertising supported: {}",
+            le_features.supports_le_ext_adv()
+        );
+
+        // Use the largest supported data length for new connections, the controller defaults
+        // to the 27 byte payloads of Bluetooth 4.1 otherwise.
+        if le_features.supports_le_data_packet_length_extension() {
+            if let Some(max) = unsupported_as_none(host.init_command(LeReadMaxDataLength::new()).await)? {
+                let (tx_octets, tx_time) = (max.supported_max_tx_octets, max.supported_max_tx_time);
+                info!("[host] suggesting data length of {} bytes ({} us)", tx_octets, tx_time);
+                if unsupported_as_none(
+                    host.init_command(LeWriteSuggestedDefaultDataLength::new(tx_octets, tx_time))
+                        .await,
+                )?
+                .is_none()
+                {
+                    warn!("[host] suggested default data length not supported");
+                }
+            }
+        }
 
-        let ret = LeReadBufferSize::new().exec(&host.controller).await?;
+        let ret = host.init_command(LeReadBufferSize::new()).await?;
         info!(
             "[host] setting txq to {}, fragmenting at {}",
             ret.total_num_le_acl_data_packets as usize, ret.le_acl_data_packet_length as usize
@@ -861,109 +2478,120 @@ impl<'d, C: Controller> ControlRunner<'d, C> {
             config::L2CAP_RX_PACKET_POOL_SIZE,
             host.rx_pool.mtu()
         );
-        HostBufferSize::new(
-            host.rx_pool.mtu() as u16,
-            0,
-            config::L2CAP_RX_PACKET_POOL_SIZE as u16,
-            0,
-        )
-        .exec(&host.controller)
-        .await?;
+        let host_buffer_size = host
+            .init_command(HostBufferSize::new(
+                host.rx_pool.mtu() as u16,
+                0,
+                config::L2CAP_RX_PACKET_POOL_SIZE as u16,
+                0,
+            ))
+            .await;
+        // The host buffers bound what the controller may send when flow control is enabled,
+        // otherwise they are only informative.
+        #[cfg(feature = "controller-host-flow-control")]
+        host_buffer_size?;
+        #[cfg(not(feature = "controller-host-flow-control"))]
+        if unsupported_as_none(host_buffer_size)?.is_none() {
+            warn!("[host] host buffer size not supported");
+        }
 
         #[cfg(feature = "controller-host-flow-control")]
         {
             info!("[host] enabling flow control");
-            SetControllerToHostFlowControl::new(ControllerToHostFlowControl::AclOnSyncOff)
-                .exec(&host.controller)
-                .await?;
+            host.init_command(SetControllerToHostFlowControl::new(
+                ControllerToHostFlowControl::AclOnSyncOff,
+            ))
+            .await?;
         }
 
-        let _ = host.initialized.init(InitialState {
+        Ok(InitialState {
             acl_max: ret.le_acl_data_packet_length as usize,
-        });
-        info!("[host] initialized");
+            capabilities: Capabilities {
+                le_features,
+                acl_data_packet_length: ret.le_acl_data_packet_length,
+                total_acl_data_packets: ret.total_num_le_acl_data_packets,
+                filter_accept_list_size,
+            },
+        })
+    }
 
-        #[allow(unused_mut)]
-        let mut completed_packets_cursor = 0;
-        loop {
-            match select4(
-                poll_fn(|cx| host.connections.poll_disconnecting(Some(cx))),
-                poll_fn(|cx| host.channels.poll_disconnecting(Some(cx))),
-                poll_fn(|cx| {
-                    host.connections
-                        .poll_completed_packets(completed_packets_cursor, Some(cx))
-                }),
-                select3(
-                    poll_fn(|cx| host.connect_command_state.poll_cancelled(cx)),
-                    poll_fn(|cx| host.advertise_command_state.poll_cancelled(cx)),
-                    poll_fn(|cx| host.scan_command_state.poll_cancelled(cx)),
-                ),
-            )
-            .await
-            {
-                Either4::First(request) => {
-                    trace!("[host] poll disconnecting links");
-                    host.command(Disconnect::new(request.handle(), request.reason()))
-                        .await?;
-                    request.confirm();
-                }
-                Either4::Second(request) => {
-                    trace!("[host] poll disconnecting channels");
-                    request.send(host).await?;
-                    request.confirm();
-                }
-                Either4::Third(completed) => {
-                    #[cfg(feature = "controller-host-flow-control")]
-                    {
-                        if let Err(e) = HostNumberOfCompletedPackets::new(&[ConnHandleCompletedPackets::new(
-                            completed.handle(),
-                            completed.amount(),
-                        )])
-                        .exec(&host.controller)
-                        .await
-                        {
-                            warn!("[host] error performing flow control");
-                        }
-                        completed_packets_cursor = completed.confirm();
-                    }
-                }
-                Either4::Fourth(states) => match states {
-                    Either3::First(_) => {
-                        trace!("[host] cancel connection create");
-                        // trace!("[host] cancelling create connection");
-                        if host.command(LeCreateConnCancel::new()).await.is_err() {
-                            // Signal to ensure no one is stuck
-                            host.connect_command_state.canceled();
-                        }
-                    }
-                    Either3::Second(ext) => {
-                        trace!("[host] disabling advertising");
-                        if ext {
-                            host.command(LeSetExtAdvEnable::new(false, &[])).await?
-                        } else {
-                            host.command(LeSetAdvEnable::new(false)).await?
-                        }
-                        host.advertise_command_state.canceled();
-                    }
-                    Either3::Third(ext) => {
-                        trace!("[host] disabling scanning");
-                        if ext {
-                            // TODO: A bit opinionated but not more than before
-                            host.command(LeSetExtScanEnable::new(
-                                false,
-                                FilterDuplicates::Disabled,
-                                bt_hci::param::Duration::from_secs(0),
-                                bt_hci::param::Duration::from_secs(0),
-                            ))
-                            .await?;
-                        } else {
-                            host.command(LeSetScanEnable::new(false, false)).await?;
-                        }
-                        host.scan_command_state.canceled();
-                    }
-                },
-            }
+    /// Reset an unresponsive controller and restore the host configuration.
+    ///
+    /// Links are lost by the reset, and are reported as disconnected with `Status::HARDWARE_FAILURE`.
+    /// The resolving list and the filter accept list are restored. Advertising and scanning stop,
+    /// ending the advertisers and scan sessions, as the host does not keep their data.
+    async fn recover<F>(&self, setup: &F) -> Result<(), BleHostError<C::Error>>
+    where
+        F: AsyncFn(&C) -> Result<(), BleHostError<C::Error>>,
+        C: ControllerCmdSync<Disconnect>
+            + ControllerCmdSync<SetEventMask>
+            + crate::SecurityController
+            + crate::ScanController
+            + crate::PrivacyController
+            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
+            + ControllerCmdSync<LeSetEventMask>
+            + ControllerCmdSync<LeSetRandomAddr>
+            + ControllerCmdSync<HostBufferSize>
+            + ControllerCmdAsync<LeConnUpdate>
+            + ControllerCmdSync<LeReadFilterAcceptListSize>
+            + ControllerCmdSync<LeReadLocalSupportedFeatures>
+            + ControllerCmdSync<LeReadMaxDataLength>
+            + ControllerCmdSync<LeWriteSuggestedDefaultDataLength>
+            + ControllerCmdSync<SetControllerToHostFlowControl>
+            + ControllerCmdSync<Reset>
+            + ControllerCmdSync<LeCreateConnCancel>
+            + ControllerCmdSync<LeSetAdvEnable>
+            + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
+            + ControllerCmdSync<LeSetScanEnable>
+            + ControllerCmdSync<LeSetExtScanEnable>
+            + for<'t> ControllerCmdSync<HostNumberOfCompletedPackets<'t>>
+            + ControllerCmdSync<LeReadBufferSize>,
+    {
+        let host = &self.stack.host;
+        warn!("[host] controller unresponsive, resetting");
+        host.command_timeouts.set(0);
+        {
+            let mut m = host.metrics.borrow_mut();
+            m.controller_resets = m.controller_resets.wrapping_add(1);
+        }
+        while let Some(handle) = host.connections.active_handle() {
+            let _ = host.connections.disconnected(handle, Status::HARDWARE_FAILURE);
+            #[cfg(feature = "security")]
+            host.security.disconnected(handle);
+            let _ = host.channels.disconnected(handle);
+            host.reassembly.disconnected(handle);
+        }
+        #[cfg(feature = "iso")]
+        host.iso.reset();
+        host.advertise_state.reset();
+        if !host.connect_command_state.is_idle() {
+            host.connect_command_state.canceled();
+        }
+        if !host.scan_command_state.is_idle() {
+            host.scan_command_state.canceled();
+        }
+        #[cfg(feature = "scan")]
+        host.scan_responses.borrow_mut().clear();
+        #[cfg(feature = "scan")]
+        host.scan_fragments.borrow_mut().clear();
+        #[cfg(feature = "scan")]
+        host.scan_reports.close();
+
+        let initial = self.initialize(setup).await?;
+        if let Some(state) = host.initialized.try_get() {
+            state.set(initial);
+        }
+
+        // The reset emptied the resolving list and the filter accept list.
+        #[cfg(feature = "peripheral")]
+        if let Some(privacy) = host.privacy.get() {
+            host.enable_resolving_list(privacy).await?;
+        }
+        for (kind, addr) in host.accept_list.entries() {
+            host.command(LeAddDeviceToFilterAcceptList::new(kind, addr)).await?;
         }
+        info!("[host] controller reset");
+        Ok(())
     }
 }
 
@@ -971,9 +2599,15 @@ impl<'d, C: Controller> TxRunner<'d, C> {
     /// Run the transmit loop for the host.
     pub async fn run(&mut self) -> Result<(), BleHostError<C::Error>> {
         let host = &self.stack.host;
-        let params = host.initialized.get().await;
+        let _ = host.initialized.get().await;
         loop {
             let (conn, pdu) = host.connections.outbound().await;
+            #[cfg(feature = "traffic-metrics")]
+            if let Ok((header, data)) = L2capHeader::from_hci_bytes(pdu.as_ref()) {
+                if header.channel == L2CAP_CID_ATT && att::Att::decode(data).as_ref().is_ok_and(is_gatt_error) {
+                    host.count(|m| m.gatt_errors = m.gatt_errors.wrapping_add(1));
+                }
+            }
             match host.l2cap(conn, pdu.len as u16, 1).await {
                 Ok(mut sender) => {
                     if let Err(e) = sender.send(pdu.as_ref()).await {
@@ -998,9 +2632,12 @@ impl<'d, C: Controller> TxRunner<'d, C> {
 
 pub struct L2capSender<'a, 'd, T: Controller> {
     pub(crate) controller: &'a T,
+    pub(crate) capture: Option<&'a dyn PacketCapture>,
     pub(crate) handle: ConnHandle,
     pub(crate) grant: PacketGrant<'a, 'd>,
     pub(crate) fragment_size: u16,
+    #[cfg(feature = "traffic-metrics")]
+    pub(crate) metrics: &'a RefCell<TrafficMetrics>,
 }
 
 impl<'a, 'd, T: Controller> L2capSender<'a, 'd, T> {
@@ -1014,10 +2651,16 @@ impl<'a, 'd, T: Controller> L2capSender<'a, 'd, T> {
             // info!("Sent ACL {:?}", acl);
             match self.controller.try_write_acl_data(&acl) {
                 Ok(result) => {
+                    capture::record_sent(self.capture, &acl);
                     self.grant.confirm(1);
+                    #[cfg(feature = "traffic-metrics")]
+                    {
+                        let mut m = self.metrics.borrow_mut();
+                        m.acl_tx = m.acl_tx.wrapping_add(1);
+                    }
                 }
                 Err(blocking::TryError::Busy) => {
-                    warn!("hci: acl data send busy");
+                    warn!("[host] acl data send busy");
                     return Err(Error::Busy.into());
                 }
                 Err(blocking::TryError::Error(e)) => return Err(BleHostError::Controller(e)),
@@ -1036,7 +2679,13 @@ impl<'a, 'd, T: Controller> L2capSender<'a, 'd, T> {
                 .write_acl_data(&acl)
                 .await
                 .map_err(BleHostError::Controller)?;
+            capture::record_sent(self.capture, &acl);
             self.grant.confirm(1);
+            #[cfg(feature = "traffic-metrics")]
+            {
+                let mut m = self.metrics.borrow_mut();
+                m.acl_tx = m.acl_tx.wrapping_add(1);
+            }
             pbf = AclPacketBoundary::Continuing;
         }
         Ok(())
@@ -1066,3 +2715,266 @@ impl<F: FnOnce()> Drop for OnDrop<F> {
         unsafe { self.f.as_ptr().read()() }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use embassy_time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn legacy_advertising_terminates_on_connection() {
+        let mut handles = [AdvHandleState::None; 2];
+        let state = AdvState::new(&mut handles);
+        let set = AdvSet {
+            adv_handle: AdvHandle::new(0),
+            duration: bt_hci::param::Duration::from_secs(0),
+            max_ext_adv_events: 0,
+        };
+        let terminated = |state: &AdvState<'_>| embassy_futures::poll_once(poll_fn(|cx| state.poll_terminated(cx)));
+
+        state.start(&[set], false);
+        state.terminate_legacy();
+        assert_eq!(terminated(&state), Poll::Pending);
+
+        state.start(&[set], true);
+        assert_eq!(terminated(&state), Poll::Pending);
+        state.terminate_legacy();
+        assert_eq!(terminated(&state), Poll::Ready(Some(AdvHandle::new(0))));
+        assert_eq!(terminated(&state), Poll::Ready(None));
+    }
+
+    #[cfg(all(feature = "security", feature = "gatt"))]
+    #[test]
+    fn insufficient_security_allows_service_discovery_only() {
+        use bt_hci::uuid::declarations::CHARACTERISTIC;
+
+        use crate::types::uuid::Uuid;
+
+        let request = |req| AttClient::Request(req);
+        let characteristics = request(att::AttReq::ReadByType {
+            start: 1,
+            end: 0xffff,
+            attribute_type: CHARACTERISTIC.into(),
+        });
+        assert_eq!(restricted(&characteristics), None);
+        let services = request(att::AttReq::ReadByGroupType {
+            start: 1,
+            end: 0xffff,
+            group_type: Uuid::new_short(0x2800),
+        });
+        assert_eq!(restricted(&services), None);
+
+        let value_by_type = request(att::AttReq::ReadByType {
+            start: 5,
+            end: 0xffff,
+            attribute_type: Uuid::new_short(0x2a19),
+        });
+        assert_eq!(restricted(&value_by_type), Some((att::ATT_READ_BY_TYPE_REQ, 5)));
+        let read = request(att::AttReq::Read { handle: 3 });
+        assert_eq!(restricted(&read), Some((att::ATT_READ_REQ, 3)));
+        let write = request(att::AttReq::Write { handle: 7, data: &[1] });
+        assert_eq!(restricted(&write), Some((att::ATT_WRITE_REQ, 7)));
+        assert_eq!(
+            restricted(&AttClient::Command(att::AttCmd::Write { handle: 7, data: &[1] })),
+            None
+        );
+    }
+
+    #[test]
+    fn unresponsive_controller_is_reset_and_restored() {
+        use core::convert::Infallible;
+
+        use bt_hci::cmd::Cmd;
+        use embassy_futures::block_on;
+        use embassy_futures::select::select;
+        use embassy_time::Timer;
+
+        use crate::mock_controller::ZeroController;
+        use crate::{Address, Host, HostResources};
+
+        let mut resources: HostResources<1, 1, 27> = HostResources::new();
+        let stack = crate::new(ZeroController::new(), &mut resources);
+        let Host { runner, .. } = stack.build();
+        let (_rx, mut control, _tx) = runner.split();
+        let controller = &stack.host.controller;
+        let timed_out =
+            |result: Result<(), BleHostError<Infallible>>| matches!(result, Err(BleHostError::BleHost(Error::Timeout)));
+
+        block_on(select(control.run(), async {
+            stack.capabilities().await;
+            stack
+                .add_to_filter_accept_list(&Address::random([1, 2, 3, 4, 5, 6]))
+                .await
+                .unwrap();
+            controller.hang(Some(LeClearFilterAcceptList::OPCODE));
+
+            // Commands of the application time out without resetting the controller.
+            assert!(timed_out(stack.command(LeClearFilterAcceptList::new()).await));
+            Timer::after(Duration::from_millis(10)).await;
+            assert_eq!(stack.metrics().controller_resets, 0);
+
+            controller.count(LeAddDeviceToFilterAcceptList::OPCODE);
+            assert!(timed_out(stack.clear_filter_accept_list().await));
+            controller.hang(None);
+            while stack.metrics().controller_resets == 0 {
+                Timer::after(Duration::from_millis(1)).await;
+            }
+            // The accept list is programmed again once the controller is initialized.
+            while controller.counted() == 0 {
+                Timer::after(Duration::from_millis(1)).await;
+            }
+            assert_eq!(stack.host.accept_list.entries().len(), 1);
+            stack.clear_filter_accept_list().await.unwrap();
+        }));
+    }
+
+    #[test]
+    fn controller_sending_events_is_reset_after_repeated_timeouts() {
+        use core::convert::Infallible;
+
+        use bt_hci::cmd::Cmd;
+        use embassy_futures::block_on;
+        use embassy_futures::select::select;
+        use embassy_time::Timer;
+
+        use crate::mock_controller::ZeroController;
+        use crate::{Host, HostResources};
+
+        let mut resources: HostResources<1, 1, 27> = HostResources::new();
+        let stack = crate::new(ZeroController::new(), &mut resources).set_command_timeout(Duration::from_millis(50));
+        let Host { runner, .. } = stack.build();
+        let (_rx, mut control, _tx) = runner.split();
+        let controller = &stack.host.controller;
+        let timed_out =
+            |result: Result<(), BleHostError<Infallible>>| matches!(result, Err(BleHostError::BleHost(Error::Timeout)));
+
+        block_on(select(control.run(), async {
+            stack.capabilities().await;
+            controller.hang(Some(LeClearFilterAcceptList::OPCODE));
+            // Stands for the events received from the controller meanwhile.
+            let events = async {
+                loop {
+                    Timer::after(Duration::from_millis(5)).await;
+                    let activity = &stack.host.controller_activity;
+                    activity.set(activity.get().wrapping_add(1));
+                }
+            };
+            let commands = async {
+                for _ in 1..RECOVERY_TIMEOUTS {
+                    assert!(timed_out(stack.clear_filter_accept_list().await));
+                    Timer::after(Duration::from_millis(10)).await;
+                    assert_eq!(stack.metrics().controller_resets, 0);
+                }
+                assert!(timed_out(stack.clear_filter_accept_list().await));
+                controller.hang(None);
+                while stack.metrics().controller_resets == 0 {
+                    Timer::after(Duration::from_millis(1)).await;
+                }
+            };
+            select(events, commands).await;
+        }));
+    }
+
+    #[test]
+    fn unresponsive_controller_fails_initialization() {
+        use bt_hci::cmd::Cmd;
+        use embassy_futures::block_on;
+
+        use crate::mock_controller::ZeroController;
+        use crate::{Host, HostResources};
+
+        let mut resources: HostResources<1, 1, 27> = HostResources::new();
+        let stack = crate::new(ZeroController::new(), &mut resources).set_command_timeout(Duration::from_millis(50));
+        let Host { runner, .. } = stack.build();
+        let (_rx, mut control, _tx) = runner.split();
+        stack.host.controller.hang(Some(HostBufferSize::OPCODE));
+
+        assert!(matches!(
+            block_on(control.run()),
+            Err(BleHostError::BleHost(Error::Timeout))
+        ));
+    }
+
+    #[cfg(feature = "central")]
+    #[test]
+    fn failed_connect_setup_leaves_nothing_to_cancel() {
+        use bt_hci::cmd::Cmd;
+        use embassy_futures::block_on;
+        use embassy_futures::select::select;
+
+        use crate::mock_controller::ZeroController;
+        use crate::prelude::{ConnectConfig, ScanConfig};
+        use crate::{Address, Host, HostResources};
+
+        let mut resources: HostResources<1, 1, 27> = HostResources::new();
+        let stack = crate::new(ZeroController::new(), &mut resources).set_command_timeout(Duration::from_millis(50));
+        let Host {
+            mut central, runner, ..
+        } = stack.build();
+        let (_rx, mut control, _tx) = runner.split();
+        let peer = Address::random([1, 2, 3, 4, 5, 6]);
+        let config = ConnectConfig {
+            scan_config: ScanConfig {
+                filter_accept_list: &[(peer.kind, &peer.addr)],
+                ..Default::default()
+            },
+            connect_params: Default::default(),
+        };
+
+        block_on(select(control.run(), async {
+            stack.capabilities().await;
+            stack.host.controller.hang(Some(LeClearFilterAcceptList::OPCODE));
+            assert!(matches!(
+                central.connect(&config).await,
+                Err(BleHostError::BleHost(Error::Timeout))
+            ));
+            // No connection is being created, so the connect state is released rather than cancelled.
+            assert!(stack.host.connect_command_state.is_idle());
+        }));
+    }
+
+    #[cfg(feature = "command-metrics")]
+    #[test]
+    fn command_latencies_are_recorded_per_opcode() {
+        let mut metrics = CommandMetrics::default();
+        let reset = Opcode::new(bt_hci::cmd::OpcodeGroup::CONTROL_BASEBAND, 0x0003);
+        let rssi = Opcode::new(bt_hci::cmd::OpcodeGroup::STATUS_PARAMS, 0x0005);
+
+        metrics.started();
+        metrics.started();
+        metrics.completed(reset, Some(Duration::from_millis(2)));
+        metrics.completed(reset, Some(Duration::from_millis(4)));
+        metrics.completed(rssi, None);
+
+        assert_eq!(metrics.max_outstanding, 2);
+        assert_eq!(metrics.latencies.len(), 2);
+        let latency = metrics.latencies[0];
+        assert_eq!(latency.opcode, reset.to_raw());
+        assert_eq!(latency.completed, 2);
+        assert_eq!(latency.max, Duration::from_millis(4));
+        assert_eq!(latency.average(), Duration::from_millis(3));
+        assert_eq!(metrics.latencies[1].timeouts, 1);
+    }
+
+    #[test]
+    fn rejected_commands_fail_with_their_opcode() {
+        let reset = Opcode::new(bt_hci::cmd::OpcodeGroup::CONTROL_BASEBAND, 0x0003);
+        let unknown = bt_hci::param::Error::UNKNOWN_CMD;
+
+        let error = command_error::<()>(reset, bt_hci::cmd::Error::Hci(unknown));
+        assert!(matches!(
+            error,
+            BleHostError::BleHost(Error::Command { opcode, status }) if opcode == reset && status == unknown
+        ));
+        assert!(matches!(unsupported_as_none::<(), ()>(Err(error)), Ok(None)));
+        assert!(matches!(
+            command_error(reset, bt_hci::cmd::Error::Io(())),
+            BleHostError::Controller(())
+        ));
+
+        // Without the command, a conversion only keeps the status.
+        let error: BleHostError<()> = bt_hci::cmd::Error::Hci(unknown).into();
+        assert!(matches!(error, BleHostError::BleHost(ref e) if e.hci_status() == Some(unknown)));
+    }
+}
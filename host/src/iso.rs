@@ -0,0 +1,1892 @@
+//! Isochronous channels, for streams of time-bounded data such as LE Audio.
+//!
+//! A connected isochronous stream (CIS) is created by the central on an existing connection. The
+//! central first configures a connected isochronous group (CIG) holding the parameters of its
+//! streams with [`Central::create_cig`], and then establishes each stream with
+//! [`Central::connect_cis`]. The peripheral receives the request with [`Peripheral::cis_request`],
+//! and accepts or rejects it. The data path of an established [`IsoChannel`] is then configured
+//! with [`IsoChannel::setup_data_path`], and SDUs exchanged over HCI with [`IsoChannel::send_sdu`]
+//! and [`IsoChannel::recv_sdu`].
+//!
+//! A broadcast isochronous group (BIG) is sent alongside the periodic advertising of an
+//! advertisement set. The broadcaster creates it with [`Peripheral::create_big`], and it is
+//! described to the scanners synced to the periodic advertising by BIGInfo reports, delivered to
+//! `EventHandler::on_biginfo_report`. A receiver synchronizes to its streams with
+//! [`PeriodicSync::sync_big`]. The streams of a [`Big`] are [`BisChannel`]s, which exchange SDUs
+//! like connected streams.
+//!
+//! `bt-hci` parses the BIG handle of the BIG events as two octets, so they are parsed by the
+//! host instead, which requires using the transports of the [`transport`](crate::transport)
+//! module rather than the `SerialTransport` of `bt-hci`.
+//!
+//! Isochronous channels require the isochronous channels host feature, see
+//! [`HostFeatures::isochronous_channels`](crate::prelude::HostFeatures::isochronous_channels). It is enabled
+//! with [`HostFeatures::enable`](crate::prelude::HostFeatures::enable) in the setup of `Runner::run_with_setup`.
+use core::cell::RefCell;
+use core::future::{Future, poll_fn};
+use core::task::{Context, Poll};
+
+use bt_hci::cmd::link_control::Disconnect;
+use bt_hci::controller::{ControllerCmdAsync, ControllerCmdSync};
+pub use bt_hci::data::IsoPacketStatus;
+use bt_hci::data::{IsoDataLoadHeader, IsoPacket, IsoPacketBoundary};
+use bt_hci::event::le::{LeBiginfoAdvertisingReport, LeCisEstablished, LeCisRequest};
+#[cfg(feature = "peripheral")]
+use bt_hci::param::AdvHandle;
+use bt_hci::param::{ConnHandle, DisconnectReason, ExtDuration, PhyKind, Status, SyncHandle};
+use bt_hci::{FromHciBytes, FromHciBytesError, WriteHci};
+use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::Duration;
+use heapless::{Deque, Vec};
+
+#[cfg(feature = "central")]
+use crate::central::Central;
+#[cfg(feature = "central")]
+use crate::connection::Connection;
+use crate::hci::{
+    CigParams, CisParams, LeBigTerminateSync, LeReadBufferSizeV2, LeRemoveIsoDataPath, LeSetupIsoDataPath,
+    LeTerminateBig,
+};
+#[cfg(feature = "peripheral")]
+use crate::hci::{LeAcceptCisRequest, LeCreateBig, LeRejectCisRequest};
+#[cfg(feature = "scan")]
+use crate::hci::{LeBigCreateSync, LengthPrefixed};
+#[cfg(feature = "central")]
+use crate::hci::{LeCreateCis, LeRemoveCig, LeSetCigParams};
+use crate::host::OnDrop;
+use crate::packet_pool::{Packet, Pool};
+#[cfg(feature = "peripheral")]
+use crate::peripheral::Peripheral;
+#[cfg(feature = "scan")]
+use crate::scan::PeriodicSync;
+use crate::{BleHostError, Controller, Error, Stack, config};
+
+/// Maximum length of an SDU.
+const MAX_SDU_LEN: usize = 0x0fff;
+/// Range of SDU intervals of a group, in microseconds.
+const SDU_INTERVAL_US: core::ops::RangeInclusive<u64> = 0xff..=0xf_ffff;
+/// Range of maximum transport latencies of a group, in milliseconds.
+const TRANSPORT_LATENCY_MS: core::ops::RangeInclusive<u64> = 0x5..=0xfa0;
+/// Maximum number of streams in a broadcast isochronous group.
+const MAX_BIS: usize = 0x1f;
+
+/// LE Create BIG Complete subevent code.
+const LE_CREATE_BIG_COMPLETE: u8 = 0x1b;
+/// LE Terminate BIG Complete subevent code.
+const LE_TERMINATE_BIG_COMPLETE: u8 = 0x1c;
+/// LE BIG Sync Established subevent code.
+const LE_BIG_SYNC_ESTABLISHED: u8 = 0x1d;
+/// LE BIG Sync Lost subevent code.
+const LE_BIG_SYNC_LOST: u8 = 0x1e;
+
+/// How the streams of a group are scheduled.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Packing {
+    /// The subevents of each stream are scheduled one stream after the other.
+    #[default]
+    Sequential = 0x00,
+    /// The subevents of the streams are interleaved.
+    Interleaved = 0x01,
+}
+
+/// Configuration of a connected isochronous group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CigConfig {
+    /// Identifier of the group, chosen by the host.
+    pub id: u8,
+    /// Interval between SDUs sent from the central to the peripheral.
+    pub sdu_interval_c_to_p: Duration,
+    /// Interval between SDUs sent from the peripheral to the central.
+    pub sdu_interval_p_to_c: Duration,
+    /// Worst case sleep clock accuracy of the peripherals, as defined by the specification.
+    pub worst_case_sca: u8,
+    /// How the streams of the group are scheduled.
+    pub packing: Packing,
+    /// Whether SDUs are framed, allowing SDU intervals that are not a multiple of the ISO interval.
+    pub framed: bool,
+    /// Maximum time to transport an SDU from the central to the peripheral.
+    pub max_latency_c_to_p: Duration,
+    /// Maximum time to transport an SDU from the peripheral to the central.
+    pub max_latency_p_to_c: Duration,
+}
+
+impl Default for CigConfig {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            sdu_interval_c_to_p: Duration::from_millis(10),
+            sdu_interval_p_to_c: Duration::from_millis(10),
+            worst_case_sca: 0,
+            packing: Packing::Sequential,
+            framed: false,
+            max_latency_c_to_p: Duration::from_millis(10),
+            max_latency_p_to_c: Duration::from_millis(10),
+        }
+    }
+}
+
+impl CigConfig {
+    fn params(&self) -> Result<CigParams, Error> {
+        Ok(CigParams {
+            cig_id: self.id,
+            sdu_interval_c_to_p: sdu_interval(self.sdu_interval_c_to_p)?,
+            sdu_interval_p_to_c: sdu_interval(self.sdu_interval_p_to_c)?,
+            worst_case_sca: self.worst_case_sca,
+            packing: self.packing as u8,
+            framing: self.framed as u8,
+            max_transport_latency_c_to_p: transport_latency(self.max_latency_c_to_p)?,
+            max_transport_latency_p_to_c: transport_latency(self.max_latency_p_to_c)?,
+        })
+    }
+}
+
+fn sdu_interval(interval: Duration) -> Result<ExtDuration, Error> {
+    let us = interval.as_micros();
+    if !SDU_INTERVAL_US.contains(&us) {
+        return Err(Error::InvalidValue);
+    }
+    Ok(ExtDuration::from_micros(us))
+}
+
+fn transport_latency(latency: Duration) -> Result<u16, Error> {
+    let ms = latency.as_millis();
+    if !TRANSPORT_LATENCY_MS.contains(&ms) {
+        return Err(Error::InvalidValue);
+    }
+    Ok(ms as u16)
+}
+
+/// Configuration of a connected isochronous stream in a group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CisConfig {
+    /// Identifier of the stream in the group, chosen by the host.
+    pub id: u8,
+    /// Maximum size of the SDUs sent from the central to the peripheral, or 0 if none are sent.
+    pub max_sdu_c_to_p: u16,
+    /// Maximum size of the SDUs sent from the peripheral to the central, or 0 if none are sent.
+    pub max_sdu_p_to_c: u16,
+    /// Preferred PHY from the central to the peripheral.
+    pub phy_c_to_p: PhyKind,
+    /// Preferred PHY from the peripheral to the central.
+    pub phy_p_to_c: PhyKind,
+    /// Number of retransmissions of each packet from the central to the peripheral.
+    pub rtn_c_to_p: u8,
+    /// Number of retransmissions of each packet from the peripheral to the central.
+    pub rtn_p_to_c: u8,
+}
+
+impl Default for CisConfig {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            max_sdu_c_to_p: 0,
+            max_sdu_p_to_c: 0,
+            phy_c_to_p: PhyKind::Le2M,
+            phy_p_to_c: PhyKind::Le2M,
+            rtn_c_to_p: 2,
+            rtn_p_to_c: 2,
+        }
+    }
+}
+
+impl CisConfig {
+    fn params(&self) -> CisParams {
+        CisParams {
+            cis_id: self.id,
+            max_sdu_c_to_p: self.max_sdu_c_to_p,
+            max_sdu_p_to_c: self.max_sdu_p_to_c,
+            phy_c_to_p: phy_mask(self.phy_c_to_p),
+            phy_p_to_c: phy_mask(self.phy_p_to_c),
+            rtn_c_to_p: self.rtn_c_to_p,
+            rtn_p_to_c: self.rtn_p_to_c,
+        }
+    }
+}
+
+pub(crate) fn phy_mask(phy: PhyKind) -> u8 {
+    match phy {
+        PhyKind::Le1M => 0x01,
+        PhyKind::Le2M => 0x02,
+        PhyKind::LeCoded | PhyKind::LeCodedS2 => 0x04,
+    }
+}
+
+/// A connected isochronous group configured in the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Cig<const N: usize> {
+    id: u8,
+    handles: [ConnHandle; N],
+}
+
+impl<const N: usize> Cig<N> {
+    /// Identifier of the group.
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Handles of the streams of the group, in the order of their configuration.
+    pub fn cis_handles(&self) -> &[ConnHandle; N] {
+        &self.handles
+    }
+}
+
+/// A request from the central to establish a connected isochronous stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CisRequest {
+    /// Handle of the connection the stream is requested on.
+    pub acl_handle: ConnHandle,
+    /// Handle of the requested stream.
+    pub cis_handle: ConnHandle,
+    /// Identifier of the group of the stream.
+    pub cig_id: u8,
+    /// Identifier of the stream in its group.
+    pub cis_id: u8,
+}
+
+impl From<&LeCisRequest> for CisRequest {
+    fn from(e: &LeCisRequest) -> Self {
+        Self {
+            acl_handle: e.acl_handle,
+            cis_handle: e.cis_handle,
+            cig_id: e.cig_id,
+            cis_id: e.cis_id,
+        }
+    }
+}
+
+/// Parameters of an established connected isochronous stream, as selected by the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CisInfo {
+    /// Maximum time for transmitting the packets of all streams of the group in an ISO interval.
+    pub cig_sync_delay: Duration,
+    /// Maximum time for transmitting the packets of this stream in an ISO interval.
+    pub cis_sync_delay: Duration,
+    /// Actual transport latency from the central to the peripheral.
+    pub transport_latency_c_to_p: Duration,
+    /// Actual transport latency from the peripheral to the central.
+    pub transport_latency_p_to_c: Duration,
+    /// PHY used from the central to the peripheral.
+    pub phy_c_to_p: PhyKind,
+    /// PHY used from the peripheral to the central.
+    pub phy_p_to_c: PhyKind,
+    /// Maximum number of subevents in each ISO interval.
+    pub nse: u8,
+    /// Burst number from the central to the peripheral.
+    pub bn_c_to_p: u8,
+    /// Burst number from the peripheral to the central.
+    pub bn_p_to_c: u8,
+    /// Flush timeout from the central to the peripheral, in ISO intervals.
+    pub ft_c_to_p: u8,
+    /// Flush timeout from the peripheral to the central, in ISO intervals.
+    pub ft_p_to_c: u8,
+    /// Maximum payload size of the packets from the central to the peripheral.
+    pub max_pdu_c_to_p: u16,
+    /// Maximum payload size of the packets from the peripheral to the central.
+    pub max_pdu_p_to_c: u16,
+    /// Time between consecutive anchor points of the stream.
+    pub iso_interval: Duration,
+}
+
+impl From<&LeCisEstablished> for CisInfo {
+    fn from(e: &LeCisEstablished) -> Self {
+        Self {
+            cig_sync_delay: Duration::from_micros(e.cig_sync_delay.as_micros()),
+            cis_sync_delay: Duration::from_micros(e.cis_sync_delay.as_micros()),
+            transport_latency_c_to_p: Duration::from_micros(e.transport_latency_c_to_p.as_micros()),
+            transport_latency_p_to_c: Duration::from_micros(e.transport_latency_p_to_c.as_micros()),
+            phy_c_to_p: e.phy_c_to_p,
+            phy_p_to_c: e.phy_p_to_c,
+            nse: e.nse,
+            bn_c_to_p: e.bn_c_to_p,
+            bn_p_to_c: e.bn_p_to_c,
+            ft_c_to_p: e.ft_c_to_p,
+            ft_p_to_c: e.ft_p_to_c,
+            max_pdu_c_to_p: e.max_pdu_c_to_p,
+            max_pdu_p_to_c: e.max_pdu_p_to_c,
+            iso_interval: Duration::from_micros(e.iso_interval.as_micros()),
+        }
+    }
+}
+
+/// Parameters of a broadcast isochronous group, reported by a synced periodic advertiser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BigInfo {
+    /// Handle of the periodic advertising sync the group was reported on.
+    pub sync_handle: SyncHandle,
+    /// Number of streams in the group.
+    pub num_bis: u8,
+    /// Number of subevents of each stream in each BIG event.
+    pub nse: u8,
+    /// Time between consecutive BIG anchor points.
+    pub iso_interval: Duration,
+    /// Burst number.
+    pub bn: u8,
+    /// Pre-transmission offset.
+    pub pto: u8,
+    /// Number of times each payload is transmitted.
+    pub irc: u8,
+    /// Maximum payload size of the packets.
+    pub max_pdu: u16,
+    /// Interval between SDUs.
+    pub sdu_interval: Duration,
+    /// Maximum size of the SDUs.
+    pub max_sdu: u16,
+    /// PHY the group is transmitted on.
+    pub phy: PhyKind,
+    /// Whether SDUs are framed.
+    pub framed: bool,
+    /// Whether the group is encrypted, requiring the broadcast code to synchronize.
+    pub encrypted: bool,
+}
+
+impl From<&LeBiginfoAdvertisingReport> for BigInfo {
+    fn from(e: &LeBiginfoAdvertisingReport) -> Self {
+        Self {
+            sync_handle: e.sync_handle,
+            num_bis: e.num_bis,
+            nse: e.nse,
+            iso_interval: Duration::from_micros(u64::from(e.iso_interval) * 1250),
+            bn: e.bn,
+            pto: e.pto,
+            irc: e.irc,
+            max_pdu: e.max_pdu,
+            sdu_interval: Duration::from_micros(e.sdu_interval.as_micros()),
+            max_sdu: e.max_sdu,
+            phy: e.phy,
+            framed: e.is_framed,
+            encrypted: e.is_encrypted,
+        }
+    }
+}
+
+/// Configuration of a broadcast isochronous group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BigConfig {
+    /// Interval between SDUs.
+    pub sdu_interval: Duration,
+    /// Maximum size of the SDUs.
+    pub max_sdu: u16,
+    /// Maximum time to transport an SDU.
+    pub max_latency: Duration,
+    /// Number of retransmissions of each packet.
+    pub rtn: u8,
+    /// Preferred PHY of the group.
+    pub phy: PhyKind,
+    /// How the streams of the group are scheduled.
+    pub packing: Packing,
+    /// Whether SDUs are framed, allowing SDU intervals that are not a multiple of the ISO interval.
+    pub framed: bool,
+    /// Code encrypting the group, if any.
+    pub broadcast_code: Option<[u8; 16]>,
+}
+
+impl Default for BigConfig {
+    fn default() -> Self {
+        Self {
+            sdu_interval: Duration::from_millis(10),
+            max_sdu: 40,
+            max_latency: Duration::from_millis(10),
+            rtn: 2,
+            phy: PhyKind::Le2M,
+            packing: Packing::Sequential,
+            framed: false,
+            broadcast_code: None,
+        }
+    }
+}
+
+/// Configuration of the synchronization to a broadcast isochronous group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BigSyncConfig {
+    /// Code the group is encrypted with, if it is encrypted.
+    pub broadcast_code: Option<[u8; 16]>,
+    /// Maximum number of subevents of each stream to receive in each BIG event, or 0 to let the controller decide.
+    pub mse: u8,
+    /// The sync is lost when no packets of the group are received within this duration.
+    pub timeout: Duration,
+}
+
+impl Default for BigSyncConfig {
+    fn default() -> Self {
+        Self {
+            broadcast_code: None,
+            mse: 0,
+            timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Parameters of a broadcast isochronous group, as selected by the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BigParams {
+    /// Maximum time for transmitting the packets of all streams of the group in a BIG event,
+    /// only reported to the broadcaster.
+    pub sync_delay: Duration,
+    /// Actual transport latency of the group.
+    pub transport_latency: Duration,
+    /// PHY the group is transmitted on, only reported to the broadcaster.
+    pub phy: Option<PhyKind>,
+    /// Number of subevents of each stream in each BIG event.
+    pub nse: u8,
+    /// Burst number.
+    pub bn: u8,
+    /// Pre-transmission offset.
+    pub pto: u8,
+    /// Number of times each payload is transmitted.
+    pub irc: u8,
+    /// Maximum payload size of the packets.
+    pub max_pdu: u16,
+    /// Time between consecutive BIG anchor points.
+    pub iso_interval: Duration,
+}
+
+/// A BIG event, parsed by the host from the parameters of an LE meta event.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum BigEvent<'a> {
+    /// The group was created or synchronized to, with the handles of its streams.
+    Established {
+        big_handle: u8,
+        result: Result<BigParams, Status>,
+        bis_handles: &'a [u8],
+    },
+    /// The group was terminated or its sync was lost.
+    Closed { big_handle: u8, reason: Status },
+}
+
+impl<'a> BigEvent<'a> {
+    /// Parse the parameters of an LE meta event, returning `None` if it is not a BIG event.
+    pub(crate) fn from_le_meta(params: &'a [u8]) -> Result<Option<Self>, FromHciBytesError> {
+        let (subevent, data) = u8::from_hci_bytes(params)?;
+        match subevent {
+            LE_CREATE_BIG_COMPLETE | LE_BIG_SYNC_ESTABLISHED => {
+                let created = subevent == LE_CREATE_BIG_COMPLETE;
+                let (status, data) = Status::from_hci_bytes(data)?;
+                let (big_handle, data) = u8::from_hci_bytes(data)?;
+                if status.to_result().is_err() {
+                    return Ok(Some(Self::Established {
+                        big_handle,
+                        result: Err(status),
+                        bis_handles: &[],
+                    }));
+                }
+                let (sync_delay, data) = match created {
+                    true => ExtDuration::<1>::from_hci_bytes(data).map(|(d, data)| (d.as_micros(), data))?,
+                    false => (0, data),
+                };
+                let (transport_latency, data) = ExtDuration::<1>::from_hci_bytes(data)?;
+                let (phy, data) = match created {
+                    true => PhyKind::from_hci_bytes(data).map(|(phy, data)| (Some(phy), data))?,
+                    false => (None, data),
+                };
+                let (nse, data) = u8::from_hci_bytes(data)?;
+                let (bn, data) = u8::from_hci_bytes(data)?;
+                let (pto, data) = u8::from_hci_bytes(data)?;
+                let (irc, data) = u8::from_hci_bytes(data)?;
+                let (max_pdu, data) = u16::from_hci_bytes(data)?;
+                let (iso_interval, data) = u16::from_hci_bytes(data)?;
+                let (num_bis, data) = u8::from_hci_bytes(data)?;
+                let bis_handles = data
+                    .get(..2 * usize::from(num_bis))
+                    .ok_or(FromHciBytesError::InvalidSize)?;
+                Ok(Some(Self::Established {
+                    big_handle,
+                    result: Ok(BigParams {
+                        sync_delay: Duration::from_micros(sync_delay),
+                        transport_latency: Duration::from_micros(transport_latency.as_micros()),
+                        phy,
+                        nse,
+                        bn,
+                        pto,
+                        irc,
+                        max_pdu,
+                        iso_interval: Duration::from_micros(u64::from(iso_interval) * 1250),
+                    }),
+                    bis_handles,
+                }))
+            }
+            LE_TERMINATE_BIG_COMPLETE | LE_BIG_SYNC_LOST => {
+                let (big_handle, data) = u8::from_hci_bytes(data)?;
+                let (reason, _) = Status::from_hci_bytes(data)?;
+                Ok(Some(Self::Closed { big_handle, reason }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn big_handle(&self) -> u8 {
+        match self {
+            Self::Established { big_handle, .. } | Self::Closed { big_handle, .. } => *big_handle,
+        }
+    }
+}
+
+/// Metadata of an SDU received on an isochronous channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SduInfo {
+    /// Length of the SDU.
+    pub len: usize,
+    /// Sequence number of the SDU, incremented every SDU interval.
+    pub sequence_number: u16,
+    /// Time in microseconds at which the SDU was received, as measured by the controller.
+    pub timestamp: Option<u32>,
+    /// Whether the SDU was received correctly.
+    pub status: IsoPacketStatus,
+}
+
+/// Direction of an isochronous data path.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DataPathDirection {
+    /// Data sent by the host to the controller.
+    Input = 0x00,
+    /// Data received by the host from the controller.
+    Output = 0x01,
+}
+
+/// Configuration of an isochronous data path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DataPath<'a> {
+    /// Identifier of the data path, 0 for HCI or a vendor-specific path.
+    pub id: u8,
+    /// Coding format, company identifier and vendor codec identifier of the codec used by the controller.
+    pub codec_id: [u8; 5],
+    /// Delay added by the controller to process the data.
+    pub controller_delay: Duration,
+    /// Vendor-specific configuration of the codec.
+    pub codec_config: &'a [u8],
+}
+
+impl DataPath<'static> {
+    /// Data exchanged over HCI, which the controller passes through without encoding.
+    pub const HCI: Self = Self {
+        id: 0x00,
+        codec_id: [0x03, 0x00, 0x00, 0x00, 0x00],
+        controller_delay: Duration::from_ticks(0),
+        codec_config: &[],
+    };
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LinkState {
+    Free,
+    Pending,
+    Established(Result<CisInfo, Status>),
+    Open,
+    Closed(Status),
+    /// Released by the host, waiting for the controller to complete the packets sent.
+    Draining,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BigState {
+    Free,
+    Pending,
+    Established(Result<BigParams, Status>),
+    Open,
+    Closed(Status),
+}
+
+/// A broadcast isochronous group, identified by its index as the BIG handle.
+struct IsoBig {
+    state: BigState,
+    waker: WakerRegistration,
+    streams: Vec<ConnHandle, MAX_BIS>,
+}
+
+/// An SDU received on an isochronous channel.
+struct RxSdu {
+    packet: Packet,
+    info: SduInfo,
+}
+
+struct IsoLink {
+    handle: ConnHandle,
+    state: LinkState,
+    waker: WakerRegistration,
+    tx_waker: WakerRegistration,
+    /// Packets sent to the controller that are not completed yet.
+    outstanding: usize,
+    /// SDU being reassembled from fragments.
+    partial: Option<RxSdu>,
+    rx: Deque<RxSdu, { config::ISO_RX_QUEUE_SIZE }>,
+}
+
+/// ISO data buffers of the controller, shared by all isochronous channels.
+#[derive(Clone, Copy)]
+struct IsoBuffers {
+    packet_len: usize,
+    available: usize,
+}
+
+struct IsoInner {
+    links: [IsoLink; config::ISO_CHANNELS_MAX],
+    bigs: [IsoBig; config::ISO_CHANNELS_MAX],
+    requests: Deque<CisRequest, { config::ISO_CHANNELS_MAX }>,
+    request_waker: WakerRegistration,
+    buffers: Option<IsoBuffers>,
+}
+
+/// Tracks the isochronous channels, their establishment and their data.
+pub(crate) struct IsoState {
+    inner: RefCell<IsoInner>,
+}
+
+impl IsoState {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: RefCell::new(IsoInner {
+                links: core::array::from_fn(|_| IsoLink {
+                    handle: ConnHandle::new(0),
+                    state: LinkState::Free,
+                    waker: WakerRegistration::new(),
+                    tx_waker: WakerRegistration::new(),
+                    outstanding: 0,
+                    partial: None,
+                    rx: Deque::new(),
+                }),
+                bigs: core::array::from_fn(|_| IsoBig {
+                    state: BigState::Free,
+                    waker: WakerRegistration::new(),
+                    streams: Vec::new(),
+                }),
+                requests: Deque::new(),
+                request_waker: WakerRegistration::new(),
+                buffers: None,
+            }),
+        }
+    }
+
+    // Start establishing a stream.
+    fn open(&self, handle: ConnHandle) -> Result<(), Error> {
+        let mut inner = self.inner.borrow_mut();
+        if inner.find(handle).is_some() {
+            return Err(Error::InvalidState);
+        }
+        let link = inner
+            .links
+            .iter_mut()
+            .find(|l| matches!(l.state, LinkState::Free))
+            .ok_or(Error::NoChannelAvailable)?;
+        link.handle = handle;
+        link.state = LinkState::Pending;
+        Ok(())
+    }
+
+    // Release a stream, either established or pending.
+    fn close(&self, handle: ConnHandle) {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(link) = inner.find(handle) {
+            link.partial = None;
+            link.rx.clear();
+            link.state = if link.outstanding > 0 {
+                LinkState::Draining
+            } else {
+                LinkState::Free
+            };
+        }
+    }
+
+    // Open a stream of a broadcast isochronous group, which is established with its group.
+    fn open_bis(&self, handle: ConnHandle) -> Result<(), Error> {
+        self.open(handle)?;
+        if let Some(link) = self.inner.borrow_mut().find(handle) {
+            link.state = LinkState::Open;
+        }
+        Ok(())
+    }
+
+    // Start creating or synchronizing to a group, returning its BIG handle.
+    fn open_big(&self) -> Result<u8, Error> {
+        let mut inner = self.inner.borrow_mut();
+        let (index, big) = inner
+            .bigs
+            .iter_mut()
+            .enumerate()
+            .find(|(_, b)| matches!(b.state, BigState::Free))
+            .ok_or(Error::NoChannelAvailable)?;
+        big.state = BigState::Pending;
+        big.streams.clear();
+        Ok(index as u8)
+    }
+
+    // Release a group, either established or pending.
+    fn close_big(&self, big_handle: u8) {
+        if let Some(big) = self.inner.borrow_mut().bigs.get_mut(usize::from(big_handle)) {
+            big.state = BigState::Free;
+        }
+    }
+
+    fn has_buffers(&self) -> bool {
+        self.inner.borrow().buffers.is_some()
+    }
+
+    fn set_buffers(&self, packet_len: u16, packets: u8) {
+        info!("[host] iso buffers: len: {}, count: {}", packet_len, packets);
+        self.inner.borrow_mut().buffers.replace(IsoBuffers {
+            packet_len: packet_len as usize,
+            available: packets as usize,
+        });
+    }
+
+    pub(crate) fn cis_established(&self, e: &LeCisEstablished) {
+        let mut inner = self.inner.borrow_mut();
+        match inner.find(e.handle) {
+            Some(link) if matches!(link.state, LinkState::Pending) => {
+                let result = match e.status.to_result() {
+                    Ok(()) => Ok(CisInfo::from(e)),
+                    Err(_) => Err(e.status),
+                };
+                link.state = LinkState::Established(result);
+                link.waker.wake();
+            }
+            _ => warn!("[host] CIS established on unknown handle {}", e.handle.raw()),
+        }
+    }
+
+    pub(crate) fn big_event(&self, e: &BigEvent<'_>) {
+        let mut inner = self.inner.borrow_mut();
+        let Some(big) = inner.bigs.get_mut(usize::from(e.big_handle())) else {
+            warn!("[host] event on unknown BIG {}", e.big_handle());
+            return;
+        };
+        let closed = match (*e, big.state) {
+            (
+                BigEvent::Established {
+                    result, bis_handles, ..
+                },
+                BigState::Pending,
+            ) => {
+                big.streams.clear();
+                for handle in bis_handles.chunks_exact(2) {
+                    let _ = big
+                        .streams
+                        .push(ConnHandle::new(u16::from_le_bytes([handle[0], handle[1]])));
+                }
+                big.state = BigState::Established(result);
+                None
+            }
+            (BigEvent::Closed { reason, .. }, BigState::Pending) => {
+                big.state = BigState::Established(Err(reason));
+                None
+            }
+            (BigEvent::Closed { reason, .. }, BigState::Open) => {
+                big.state = BigState::Closed(reason);
+                Some((big.streams.clone(), reason))
+            }
+            _ => {
+                warn!("[host] unexpected event on BIG {}", e.big_handle());
+                return;
+            }
+        };
+        big.waker.wake();
+        drop(inner);
+        if let Some((streams, reason)) = closed {
+            for handle in streams {
+                self.disconnected(handle, reason);
+            }
+        }
+    }
+
+    // Queue a request from the central, returning false if the queue is full.
+    pub(crate) fn cis_requested(&self, e: &LeCisRequest) -> bool {
+        let mut inner = self.inner.borrow_mut();
+        if inner.requests.push_back(CisRequest::from(e)).is_err() {
+            return false;
+        }
+        inner.request_waker.wake();
+        true
+    }
+
+    pub(crate) fn disconnected(&self, handle: ConnHandle, reason: Status) {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(link) = inner.find(handle) {
+            link.state = match link.state {
+                LinkState::Pending => LinkState::Established(Err(reason)),
+                LinkState::Open => LinkState::Closed(reason),
+                LinkState::Draining => LinkState::Free,
+                state => state,
+            };
+            link.waker.wake();
+            link.tx_waker.wake();
+            // The controller flushes the packets of a disconnected stream without completing them.
+            let flushed = core::mem::take(&mut link.outstanding);
+            if let Some(buffers) = inner.buffers.as_mut() {
+                buffers.available += flushed;
+            }
+        }
+    }
+
+    /// Fail all streams, as the controller was reset.
+    pub(crate) fn reset(&self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.requests.clear();
+        inner.buffers = None;
+        for link in inner.links.iter_mut() {
+            link.state = match link.state {
+                LinkState::Pending => LinkState::Established(Err(Status::HARDWARE_FAILURE)),
+                LinkState::Open | LinkState::Established(Ok(_)) => LinkState::Closed(Status::HARDWARE_FAILURE),
+                LinkState::Draining => LinkState::Free,
+                state => state,
+            };
+            link.outstanding = 0;
+            link.waker.wake();
+            link.tx_waker.wake();
+        }
+        for big in inner.bigs.iter_mut() {
+            big.state = match big.state {
+                BigState::Pending => BigState::Established(Err(Status::HARDWARE_FAILURE)),
+                BigState::Open | BigState::Established(Ok(_)) => BigState::Closed(Status::HARDWARE_FAILURE),
+                state => state,
+            };
+            big.waker.wake();
+        }
+    }
+
+    /// Return the buffers of completed packets, returning false if the handle is not an isochronous channel.
+    pub(crate) fn confirm_sent(&self, handle: ConnHandle, packets: usize) -> bool {
+        let mut inner = self.inner.borrow_mut();
+        let Some(link) = inner.find(handle) else {
+            return false;
+        };
+        let packets = packets.min(link.outstanding);
+        link.outstanding -= packets;
+        if link.outstanding == 0 && matches!(link.state, LinkState::Draining) {
+            link.state = LinkState::Free;
+        }
+        if let Some(buffers) = inner.buffers.as_mut() {
+            buffers.available += packets;
+        }
+        for link in inner.links.iter_mut() {
+            link.tx_waker.wake();
+        }
+        true
+    }
+
+    /// Reassemble a received ISO data packet into an SDU.
+    pub(crate) fn received(&self, packet: &IsoPacket<'_>, pool: &dyn Pool) -> Result<(), Error> {
+        let mut inner = self.inner.borrow_mut();
+        let link = match inner.find(packet.handle()) {
+            Some(link) if matches!(link.state, LinkState::Open) => link,
+            _ => return Err(Error::NotFound),
+        };
+        let data = packet.data();
+        let first = match (packet.boundary_flag(), packet.data_load_header()) {
+            (IsoPacketBoundary::FirstFragment | IsoPacketBoundary::Complete, Some(header)) => Some(header),
+            (IsoPacketBoundary::ContinuationFragment | IsoPacketBoundary::LastFragment, None) => None,
+            _ => return Err(Error::InvalidValue),
+        };
+        if let Some(header) = first {
+            if link.partial.take().is_some() {
+                warn!("[host] dropping incomplete SDU on handle {}", link.handle.raw());
+            }
+            let packet = pool.alloc().ok_or(Error::OutOfMemory)?;
+            link.partial.replace(RxSdu {
+                packet,
+                info: SduInfo {
+                    len: 0,
+                    sequence_number: header.sequence_num,
+                    timestamp: header.timestamp,
+                    status: match header.iso_sdu_len >> 14 {
+                        0 => IsoPacketStatus::Correct,
+                        1 => IsoPacketStatus::PossiblyInvalid,
+                        _ => IsoPacketStatus::PartiallyLost,
+                    },
+                },
+            });
+        }
+
+        let Some(sdu) = link.partial.as_mut() else {
+            return Err(Error::InvalidState);
+        };
+        let start = sdu.info.len;
+        let Some(dest) = sdu.packet.as_mut().get_mut(start..start + data.len()) else {
+            link.partial = None;
+            return Err(Error::InsufficientSpace);
+        };
+        dest.copy_from_slice(data);
+        sdu.info.len += data.len();
+
+        if matches!(
+            packet.boundary_flag(),
+            IsoPacketBoundary::Complete | IsoPacketBoundary::LastFragment
+        ) {
+            let sdu = unwrap!(link.partial.take());
+            if link.rx.push_back(sdu).is_err() {
+                return Err(Error::OutOfMemory);
+            }
+            link.waker.wake();
+        }
+        Ok(())
+    }
+
+    fn poll_established(&self, cx: &mut Context<'_>, handle: ConnHandle) -> Poll<Result<CisInfo, Error>> {
+        let mut inner = self.inner.borrow_mut();
+        let Some(link) = inner.find(handle) else {
+            return Poll::Ready(Err(Error::Disconnected));
+        };
+        match link.state {
+            LinkState::Established(Ok(info)) => {
+                link.state = LinkState::Open;
+                Poll::Ready(Ok(info))
+            }
+            LinkState::Established(Err(status)) => {
+                link.state = LinkState::Free;
+                Poll::Ready(Err(Error::Hci(status.to_result().unwrap_err())))
+            }
+            _ => {
+                link.waker.register(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn poll_big_established(
+        &self,
+        cx: &mut Context<'_>,
+        big_handle: u8,
+    ) -> Poll<Result<(BigParams, Vec<ConnHandle, MAX_BIS>), Error>> {
+        let mut inner = self.inner.borrow_mut();
+        let Some(big) = inner.bigs.get_mut(usize::from(big_handle)) else {
+            return Poll::Ready(Err(Error::NotFound));
+        };
+        match big.state {
+            BigState::Established(Ok(params)) => {
+                big.state = BigState::Open;
+                Poll::Ready(Ok((params, big.streams.clone())))
+            }
+            BigState::Established(Err(status)) => {
+                big.state = BigState::Free;
+                Poll::Ready(Err(Error::Hci(status.to_result().unwrap_err())))
+            }
+            _ => {
+                big.waker.register(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+
+    // Wait for a group to be terminated.
+    fn poll_big_closed(&self, cx: &mut Context<'_>, big_handle: u8) -> Poll<()> {
+        let mut inner = self.inner.borrow_mut();
+        match inner.bigs.get_mut(usize::from(big_handle)) {
+            Some(big) if matches!(big.state, BigState::Open) => {
+                big.waker.register(cx.waker());
+                Poll::Pending
+            }
+            _ => Poll::Ready(()),
+        }
+    }
+
+    fn is_big_open(&self, big_handle: u8) -> bool {
+        let inner = self.inner.borrow();
+        matches!(
+            inner.bigs.get(usize::from(big_handle)).map(|b| b.state),
+            Some(BigState::Open)
+        )
+    }
+
+    fn poll_request(&self, cx: &mut Context<'_>) -> Poll<CisRequest> {
+        let mut inner = self.inner.borrow_mut();
+        match inner.requests.pop_front() {
+            Some(request) => Poll::Ready(request),
+            None => {
+                inner.request_waker.register(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+
+    // Reserve a buffer of the controller to send a packet, returning the maximum packet length.
+    fn poll_reserve(&self, cx: &mut Context<'_>, handle: ConnHandle) -> Poll<Result<usize, Error>> {
+        let mut inner = self.inner.borrow_mut();
+        let Some(buffers) = inner.buffers else {
+            return Poll::Ready(Err(Error::NotSupported));
+        };
+        let Some(link) = inner.find(handle) else {
+            return Poll::Ready(Err(Error::Disconnected));
+        };
+        if !matches!(link.state, LinkState::Open) {
+            return Poll::Ready(Err(Error::Disconnected));
+        }
+        if buffers.available == 0 {
+            link.tx_waker.register(cx.waker());
+            return Poll::Pending;
+        }
+        link.outstanding += 1;
+        if let Some(buffers) = inner.buffers.as_mut() {
+            buffers.available -= 1;
+        }
+        Poll::Ready(Ok(buffers.packet_len))
+    }
+
+    // Return a reserved buffer that was not used.
+    fn unreserve(&self, handle: ConnHandle) {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(link) = inner.find(handle) {
+            if link.outstanding > 0 {
+                link.outstanding -= 1;
+                if let Some(buffers) = inner.buffers.as_mut() {
+                    buffers.available += 1;
+                }
+            }
+        }
+    }
+
+    fn poll_receive(&self, cx: &mut Context<'_>, handle: ConnHandle, buf: &mut [u8]) -> Poll<Result<SduInfo, Error>> {
+        let mut inner = self.inner.borrow_mut();
+        let Some(link) = inner.find(handle) else {
+            return Poll::Ready(Err(Error::Disconnected));
+        };
+        if let Some(sdu) = link.rx.pop_front() {
+            let Some(dest) = buf.get_mut(..sdu.info.len) else {
+                return Poll::Ready(Err(Error::InsufficientSpace));
+            };
+            dest.copy_from_slice(&sdu.packet.as_ref()[..sdu.info.len]);
+            return Poll::Ready(Ok(sdu.info));
+        }
+        match link.state {
+            LinkState::Open => {
+                link.waker.register(cx.waker());
+                Poll::Pending
+            }
+            _ => Poll::Ready(Err(Error::Disconnected)),
+        }
+    }
+
+    fn is_open(&self, handle: ConnHandle) -> bool {
+        let mut inner = self.inner.borrow_mut();
+        matches!(inner.find(handle).map(|l| l.state), Some(LinkState::Open))
+    }
+}
+
+impl IsoInner {
+    fn find(&mut self, handle: ConnHandle) -> Option<&mut IsoLink> {
+        self.links
+            .iter_mut()
+            .find(|l| !matches!(l.state, LinkState::Free) && l.handle == handle)
+    }
+}
+
+/// Read the ISO data buffers of the controller, if not read yet.
+async fn read_buffers<C>(stack: &Stack<'_, C>) -> Result<(), BleHostError<C::Error>>
+where
+    C: Controller + ControllerCmdSync<LeReadBufferSizeV2>,
+{
+    let host = &stack.host;
+    if !host.iso.has_buffers() {
+        let ret = host.command(LeReadBufferSizeV2::new()).await?;
+        host.iso
+            .set_buffers(ret.iso_data_packet_length, ret.total_num_iso_data_packets);
+    }
+    Ok(())
+}
+
+/// Wait for a stream to be established, releasing it if waiting is cancelled or fails.
+async fn establish<'d, C>(
+    stack: &'d Stack<'d, C>,
+    handle: ConnHandle,
+    start: impl Future<Output = Result<(), BleHostError<C::Error>>>,
+) -> Result<IsoChannel<'d, C>, BleHostError<C::Error>>
+where
+    C: Controller + ControllerCmdSync<LeReadBufferSizeV2>,
+{
+    let host = &stack.host;
+    read_buffers(stack).await?;
+    host.iso.open(handle)?;
+    let guard = OnDrop::new(|| host.iso.close(handle));
+    start.await?;
+    let info = poll_fn(|cx| host.iso.poll_established(cx, handle)).await?;
+    guard.defuse();
+    Ok(IsoChannel {
+        stream: IsoStream::new(stack, handle),
+        info,
+    })
+}
+
+/// Wait for a group to be created or synchronized to, releasing it if waiting is cancelled or fails.
+async fn establish_big<'d, C, F, const N: usize>(
+    stack: &'d Stack<'d, C>,
+    broadcaster: bool,
+    start: impl FnOnce(u8) -> F,
+) -> Result<Big<'d, C, N>, BleHostError<C::Error>>
+where
+    C: Controller + ControllerCmdSync<LeReadBufferSizeV2>,
+    F: Future<Output = Result<(), BleHostError<C::Error>>>,
+{
+    if N == 0 || N > MAX_BIS {
+        return Err(Error::InvalidValue.into());
+    }
+    let host = &stack.host;
+    read_buffers(stack).await?;
+    let handle = host.iso.open_big()?;
+    let guard = OnDrop::new(|| host.iso.close_big(handle));
+    start(handle).await?;
+    let (params, handles) = poll_fn(|cx| host.iso.poll_big_established(cx, handle)).await?;
+    let mut streams: Vec<BisChannel<'d, C>, N> = Vec::new();
+    for handle in handles {
+        host.iso.open_bis(handle)?;
+        let stream = BisChannel {
+            stream: IsoStream::new(stack, handle),
+        };
+        if streams.push(stream).is_err() {
+            return Err(Error::InvalidValue.into());
+        }
+    }
+    let Ok(streams) = streams.into_array() else {
+        return Err(Error::InvalidValue.into());
+    };
+    guard.defuse();
+    Ok(Big {
+        stack,
+        handle,
+        broadcaster,
+        params,
+        streams,
+    })
+}
+
+#[cfg(feature = "central")]
+impl<'d, C: Controller> Central<'d, C> {
+    /// Configure a connected isochronous group with `N` streams, or update a group not yet in use.
+    ///
+    /// Returns the group with the handles the controller assigned to its streams, which are
+    /// then established with [`Central::connect_cis`].
+    pub async fn create_cig<const N: usize>(
+        &mut self,
+        config: &CigConfig,
+        streams: &[CisConfig; N],
+    ) -> Result<Cig<N>, BleHostError<C::Error>>
+    where
+        C: for<'t> ControllerCmdSync<LeSetCigParams<'t, N>>,
+    {
+        let cis = streams.map(|s| s.params());
+        let ret = self
+            .stack
+            .host
+            .command(LeSetCigParams::new(config.params()?, &cis))
+            .await?;
+        Ok(Cig {
+            id: ret.cig_id,
+            handles: ret.cis_handles,
+        })
+    }
+
+    /// Remove a connected isochronous group, once none of its streams are established.
+    pub async fn remove_cig(&mut self, id: u8) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeRemoveCig>,
+    {
+        self.stack.host.command(LeRemoveCig::new(id)).await?;
+        Ok(())
+    }
+
+    /// Establish a connected isochronous stream of a configured group with a connected peripheral.
+    ///
+    /// Fails if the peripheral rejects the stream. Streams are established one at a time.
+    pub async fn connect_cis(
+        &mut self,
+        cis_handle: ConnHandle,
+        connection: &Connection<'_>,
+    ) -> Result<IsoChannel<'d, C>, BleHostError<C::Error>>
+    where
+        C: ControllerCmdAsync<LeCreateCis> + ControllerCmdSync<LeReadBufferSizeV2>,
+    {
+        let host = &self.stack.host;
+        establish(
+            self.stack,
+            cis_handle,
+            host.async_command(LeCreateCis::new(1, cis_handle, connection.handle())),
+        )
+        .await
+    }
+}
+
+#[cfg(feature = "peripheral")]
+impl<'d, C: Controller> Peripheral<'d, C> {
+    /// Wait for a central to request a connected isochronous stream.
+    ///
+    /// The request must be accepted with [`Peripheral::accept_cis`] or rejected with
+    /// [`Peripheral::reject_cis`] before the controller times out. Requests are queued until
+    /// received, and rejected by the controller when the queue is full.
+    pub async fn cis_request(&mut self) -> CisRequest {
+        poll_fn(|cx| self.stack.host.iso.poll_request(cx)).await
+    }
+
+    /// Accept a request for a connected isochronous stream, waiting for it to be established.
+    pub async fn accept_cis(&mut self, request: &CisRequest) -> Result<IsoChannel<'d, C>, BleHostError<C::Error>>
+    where
+        C: ControllerCmdAsync<LeAcceptCisRequest> + ControllerCmdSync<LeReadBufferSizeV2>,
+    {
+        let host = &self.stack.host;
+        establish(
+            self.stack,
+            request.cis_handle,
+            host.async_command(LeAcceptCisRequest::new(request.cis_handle)),
+        )
+        .await
+    }
+
+    /// Reject a request for a connected isochronous stream with the given reason.
+    pub async fn reject_cis(&mut self, request: &CisRequest, reason: Status) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeRejectCisRequest>,
+    {
+        self.stack
+            .host
+            .command(LeRejectCisRequest::new(request.cis_handle, reason))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "peripheral")]
+impl<'d, C: Controller> Peripheral<'d, C> {
+    /// Create a broadcast isochronous group of `N` streams, sent alongside the periodic
+    /// advertising of an advertisement set.
+    ///
+    /// Periodic advertising must be configured on the set, and the group is described to the
+    /// scanners synced to it by BIGInfo reports.
+    pub async fn create_big<const N: usize>(
+        &mut self,
+        adv_handle: AdvHandle,
+        config: &BigConfig,
+    ) -> Result<Big<'d, C, N>, BleHostError<C::Error>>
+    where
+        C: ControllerCmdAsync<LeCreateBig> + ControllerCmdSync<LeReadBufferSizeV2>,
+    {
+        if usize::from(config.max_sdu) > MAX_SDU_LEN || config.rtn > 0x1e {
+            return Err(Error::InvalidValue.into());
+        }
+        let sdu_interval = sdu_interval(config.sdu_interval)?;
+        let max_latency = transport_latency(config.max_latency)?;
+        let host = &self.stack.host;
+        establish_big(self.stack, true, |big_handle| {
+            host.async_command(LeCreateBig::new(
+                big_handle,
+                adv_handle,
+                N as u8,
+                sdu_interval,
+                config.max_sdu,
+                max_latency,
+                config.rtn,
+                phy_mask(config.phy),
+                config.packing as u8,
+                config.framed as u8,
+                config.broadcast_code.is_some(),
+                config.broadcast_code.unwrap_or_default(),
+            ))
+        })
+        .await
+    }
+}
+
+#[cfg(feature = "scan")]
+impl<'d, C: Controller> PeriodicSync<'d, C> {
+    /// Synchronize to `N` streams of the broadcast isochronous group sent alongside the periodic
+    /// advertising, given their indices in the group starting at 1.
+    ///
+    /// The group is described by the BIGInfo reports received while synced.
+    pub async fn sync_big<const N: usize>(
+        &self,
+        config: &BigSyncConfig,
+        bis: &[u8; N],
+    ) -> Result<Big<'d, C, N>, BleHostError<C::Error>>
+    where
+        C: for<'t> ControllerCmdAsync<LeBigCreateSync<'t>> + ControllerCmdSync<LeReadBufferSizeV2>,
+    {
+        if usize::from(config.mse) > MAX_BIS || bis.iter().any(|&i| i == 0 || usize::from(i) > MAX_BIS) {
+            return Err(Error::InvalidValue.into());
+        }
+        let host = &self.stack.host;
+        establish_big(self.stack, false, |big_handle| {
+            host.async_command(LeBigCreateSync::new(
+                big_handle,
+                self.handle(),
+                config.broadcast_code.is_some(),
+                config.broadcast_code.unwrap_or_default(),
+                config.mse,
+                config.timeout.into(),
+                LengthPrefixed(bis),
+            ))
+        })
+        .await
+    }
+}
+
+/// An established isochronous channel.
+///
+/// The channel must be disconnected with [`IsoChannel::disconnect`] to terminate the stream,
+/// dropping it only releases the resources of the host.
+pub struct IsoChannel<'d, C> {
+    stream: IsoStream<'d, C>,
+    info: CisInfo,
+}
+
+impl<'d, C: Controller> IsoChannel<'d, C> {
+    /// Connection handle of the stream.
+    pub fn handle(&self) -> ConnHandle {
+        self.stream.handle
+    }
+
+    /// Parameters of the stream, as selected by the controller when it was established.
+    pub fn info(&self) -> &CisInfo {
+        &self.info
+    }
+
+    /// Whether the stream is still established.
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_connected()
+    }
+
+    /// Configure the data path of the stream in one direction.
+    ///
+    /// Use [`DataPath::HCI`] to exchange SDUs with the host.
+    pub async fn setup_data_path(
+        &self,
+        direction: DataPathDirection,
+        path: &DataPath<'_>,
+    ) -> Result<(), BleHostError<C::Error>>
+    where
+        C: for<'t> ControllerCmdSync<LeSetupIsoDataPath<'t>>,
+    {
+        self.stream.setup_data_path(direction, path).await
+    }
+
+    /// Remove the data path of the stream in one direction.
+    pub async fn remove_data_path(&self, direction: DataPathDirection) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeRemoveIsoDataPath>,
+    {
+        self.stream.remove_data_path(direction).await
+    }
+
+    /// Send an SDU on the stream, fragmented into ISO data packets fitting the buffers of the controller.
+    ///
+    /// SDUs are numbered in the order they are sent, and should be sent once every SDU interval.
+    /// The timestamp, if any, is the time in microseconds at which the SDU is to be sent, as
+    /// measured by the controller. The data path of the input direction must be set up over HCI.
+    ///
+    /// The `TX_MTU` is the size of the buffer used for a single ISO data packet, including its
+    /// 4 byte header. Waits for the controller to have buffers available, which are shared by
+    /// all isochronous channels and separate from the buffers of ACL data.
+    pub async fn send_sdu<const TX_MTU: usize>(
+        &mut self,
+        sdu: &[u8],
+        timestamp: Option<u32>,
+    ) -> Result<(), BleHostError<C::Error>> {
+        self.stream.send_sdu::<TX_MTU>(sdu, timestamp).await
+    }
+
+    /// Receive an SDU from the stream and copy it into the buffer.
+    ///
+    /// The data path of the output direction must be set up over HCI. SDUs are queued until
+    /// received, up to the configured ISO RX queue size, and the buffer must fit the SDU. Fails
+    /// with `Error::Disconnected` once the stream is terminated and the queued SDUs are received.
+    pub async fn recv_sdu(&mut self, buf: &mut [u8]) -> Result<SduInfo, BleHostError<C::Error>> {
+        self.stream.recv_sdu(buf).await
+    }
+
+    /// Terminate the stream.
+    pub async fn disconnect(self) -> Result<(), BleHostError<C::Error>> {
+        self.stream
+            .stack
+            .host
+            .command(Disconnect::new(
+                self.stream.handle,
+                DisconnectReason::RemoteUserTerminatedConn,
+            ))
+            .await
+    }
+}
+
+/// A broadcast isochronous group, created as a broadcaster or synchronized to as a receiver.
+///
+/// The group must be terminated with [`Big::terminate`], dropping it only releases the resources
+/// of the host.
+pub struct Big<'d, C, const N: usize> {
+    stack: &'d Stack<'d, C>,
+    handle: u8,
+    broadcaster: bool,
+    params: BigParams,
+    streams: [BisChannel<'d, C>; N],
+}
+
+impl<'d, C: Controller, const N: usize> Big<'d, C, N> {
+    /// BIG handle of the group, chosen by the host.
+    pub fn handle(&self) -> u8 {
+        self.handle
+    }
+
+    /// Parameters of the group, as selected by the controller when it was created or synchronized to.
+    pub fn params(&self) -> &BigParams {
+        &self.params
+    }
+
+    /// Whether the group is still created or synchronized to.
+    pub fn is_open(&self) -> bool {
+        self.stack.host.iso.is_big_open(self.handle)
+    }
+
+    /// Streams of the group, in the order of their indices in the group.
+    pub fn streams(&mut self) -> &mut [BisChannel<'d, C>; N] {
+        &mut self.streams
+    }
+
+    /// Terminate the group as a broadcaster, or stop receiving it as a receiver.
+    pub async fn terminate(self) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdAsync<LeTerminateBig> + ControllerCmdSync<LeBigTerminateSync>,
+    {
+        let host = &self.stack.host;
+        if !host.iso.is_big_open(self.handle) {
+            return Ok(());
+        }
+        if self.broadcaster {
+            host.async_command(LeTerminateBig::new(
+                self.handle,
+                DisconnectReason::RemoteUserTerminatedConn,
+            ))
+            .await?;
+            poll_fn(|cx| host.iso.poll_big_closed(cx, self.handle)).await;
+        } else {
+            host.command(LeBigTerminateSync::new(self.handle)).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<C, const N: usize> Drop for Big<'_, C, N> {
+    fn drop(&mut self) {
+        self.stack.host.iso.close_big(self.handle);
+    }
+}
+
+/// A stream of a broadcast isochronous group.
+///
+/// A broadcaster sends SDUs on its streams, and a receiver receives them. The stream is
+/// terminated with its group.
+pub struct BisChannel<'d, C> {
+    stream: IsoStream<'d, C>,
+}
+
+impl<'d, C: Controller> BisChannel<'d, C> {
+    /// Connection handle of the stream.
+    pub fn handle(&self) -> ConnHandle {
+        self.stream.handle
+    }
+
+    /// Whether the group of the stream is still created or synchronized to.
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_connected()
+    }
+
+    /// Configure the data path of the stream in one direction.
+    ///
+    /// Use [`DataPath::HCI`] to exchange SDUs with the host.
+    pub async fn setup_data_path(
+        &self,
+        direction: DataPathDirection,
+        path: &DataPath<'_>,
+    ) -> Result<(), BleHostError<C::Error>>
+    where
+        C: for<'t> ControllerCmdSync<LeSetupIsoDataPath<'t>>,
+    {
+        self.stream.setup_data_path(direction, path).await
+    }
+
+    /// Remove the data path of the stream in one direction.
+    pub async fn remove_data_path(&self, direction: DataPathDirection) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeRemoveIsoDataPath>,
+    {
+        self.stream.remove_data_path(direction).await
+    }
+
+    /// Send an SDU on the stream of a group created as a broadcaster.
+    ///
+    /// See [`IsoChannel::send_sdu`] for how SDUs are sent.
+    pub async fn send_sdu<const TX_MTU: usize>(
+        &mut self,
+        sdu: &[u8],
+        timestamp: Option<u32>,
+    ) -> Result<(), BleHostError<C::Error>> {
+        self.stream.send_sdu::<TX_MTU>(sdu, timestamp).await
+    }
+
+    /// Receive an SDU from the stream of a group synchronized to, and copy it into the buffer.
+    ///
+    /// See [`IsoChannel::recv_sdu`] for how SDUs are received.
+    pub async fn recv_sdu(&mut self, buf: &mut [u8]) -> Result<SduInfo, BleHostError<C::Error>> {
+        self.stream.recv_sdu(buf).await
+    }
+}
+
+/// An isochronous stream open in the host, connected or broadcast.
+struct IsoStream<'d, C> {
+    stack: &'d Stack<'d, C>,
+    handle: ConnHandle,
+    sequence_number: u16,
+}
+
+impl<'d, C: Controller> IsoStream<'d, C> {
+    fn new(stack: &'d Stack<'d, C>, handle: ConnHandle) -> Self {
+        Self {
+            stack,
+            handle,
+            sequence_number: 0,
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.stack.host.iso.is_open(self.handle)
+    }
+
+    async fn setup_data_path(
+        &self,
+        direction: DataPathDirection,
+        path: &DataPath<'_>,
+    ) -> Result<(), BleHostError<C::Error>>
+    where
+        C: for<'t> ControllerCmdSync<LeSetupIsoDataPath<'t>>,
+    {
+        let delay = path.controller_delay.as_micros();
+        if delay >= 1 << 24 {
+            return Err(Error::InvalidValue.into());
+        }
+        self.stack
+            .host
+            .command(LeSetupIsoDataPath::new(
+                self.handle,
+                direction as u8,
+                path.id,
+                path.codec_id,
+                ExtDuration::from_micros(delay),
+                path.codec_config,
+            ))
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_data_path(&self, direction: DataPathDirection) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeRemoveIsoDataPath>,
+    {
+        self.stack
+            .host
+            .command(LeRemoveIsoDataPath::new(self.handle, 1 << direction as u8))
+            .await?;
+        Ok(())
+    }
+
+    async fn send_sdu<const TX_MTU: usize>(
+        &mut self,
+        sdu: &[u8],
+        timestamp: Option<u32>,
+    ) -> Result<(), BleHostError<C::Error>> {
+        if sdu.len() > MAX_SDU_LEN {
+            return Err(Error::InsufficientSpace.into());
+        }
+        let host = &self.stack.host;
+        let handle = self.handle;
+        let header = IsoDataLoadHeader {
+            timestamp,
+            sequence_num: self.sequence_number,
+            iso_sdu_len: sdu.len() as u16,
+        };
+        let mut p_buf = [0u8; TX_MTU];
+        let mut remaining = sdu;
+        let mut first = true;
+        loop {
+            let packet_len = poll_fn(|cx| host.iso.poll_reserve(cx, handle)).await?;
+            let reserved = OnDrop::new(|| host.iso.unreserve(handle));
+            let header_len = if first { header.size() } else { 0 };
+            let max = packet_len.min(TX_MTU.saturating_sub(4));
+            if max <= header_len {
+                return Err(Error::InsufficientSpace.into());
+            }
+            let (fragment, rest) = remaining.split_at(remaining.len().min(max - header_len));
+            let boundary = match (first, rest.is_empty()) {
+                (true, false) => 0b00,
+                (false, false) => 0b01,
+                (true, true) => 0b10,
+                (false, true) => 0b11,
+            };
+            let flags = handle.raw() | (boundary << 12) | (u16::from(first && timestamp.is_some()) << 14);
+            let load_len = header_len + fragment.len();
+            p_buf[..2].copy_from_slice(&flags.to_le_bytes());
+            p_buf[2..4].copy_from_slice(&(load_len as u16).to_le_bytes());
+            if first {
+                header
+                    .write_hci(&mut p_buf[4..4 + header_len])
+                    .map_err(|_| Error::InsufficientSpace)?;
+            }
+            p_buf[4 + header_len..4 + load_len].copy_from_slice(fragment);
+            let (packet, _) = IsoPacket::from_hci_bytes(&p_buf[..4 + load_len]).map_err(|_| Error::InvalidValue)?;
+            host.controller
+                .write_iso_data(&packet)
+                .await
+                .map_err(BleHostError::Controller)?;
+            crate::capture::record_sent(host.capture, &packet);
+            reserved.defuse();
+
+            first = false;
+            remaining = rest;
+            if remaining.is_empty() {
+                break;
+            }
+        }
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        Ok(())
+    }
+
+    async fn recv_sdu(&mut self, buf: &mut [u8]) -> Result<SduInfo, BleHostError<C::Error>> {
+        let host = &self.stack.host;
+        Ok(poll_fn(|cx| host.iso.poll_receive(cx, self.handle, buf)).await?)
+    }
+}
+
+impl<C> Drop for IsoStream<'_, C> {
+    fn drop(&mut self) {
+        self.stack.host.iso.close(self.handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use static_cell::StaticCell;
+
+    use super::*;
+    use crate::packet_pool::PacketPool;
+
+    fn established(handle: u16, status: Status) -> [u8; 29] {
+        let mut data = [0; 29];
+        data[0] = status.into_inner();
+        data[1..3].copy_from_slice(&handle.to_le_bytes());
+        data[15] = PhyKind::Le2M as u8;
+        data[16] = PhyKind::Le2M as u8;
+        data
+    }
+
+    #[test]
+    fn cis_established_completes_pending_stream() {
+        let state = IsoState::new();
+        let handle = ConnHandle::new(0x60);
+        state.open(handle).unwrap();
+        assert!(matches!(state.open(handle), Err(Error::InvalidState)));
+
+        let data = established(0x60, Status::SUCCESS);
+        let (e, _) = <LeCisEstablished as bt_hci::FromHciBytes>::from_hci_bytes(&data).unwrap();
+        state.cis_established(&e);
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(matches!(state.poll_established(&mut cx, handle), Poll::Ready(Ok(_))));
+        assert!(state.is_open(handle));
+
+        state.disconnected(handle, Status::REMOTE_USER_TERMINATED_CONN);
+        assert!(!state.is_open(handle));
+        state.close(handle);
+        state.open(handle).unwrap();
+    }
+
+    #[test]
+    fn failed_stream_is_released() {
+        let state = IsoState::new();
+        let handle = ConnHandle::new(0x61);
+        state.open(handle).unwrap();
+        state.disconnected(handle, Status::CONN_FAILED_SYNCHRONIZATION_TIMEOUT);
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(matches!(
+            state.poll_established(&mut cx, handle),
+            Poll::Ready(Err(Error::Hci(_)))
+        ));
+        for _ in 0..config::ISO_CHANNELS_MAX {
+            state.open(handle).unwrap();
+            state.close(handle);
+        }
+    }
+
+    #[test]
+    fn biginfo_report_is_converted() {
+        let data = [
+            0x01, 0x00, 2, 4, 8, 0, 1, 0, 2, 40, 0, 0x10, 0x27, 0x00, 40, 0, 0x02, 0x00, 0x01,
+        ];
+        let (e, _) = <LeBiginfoAdvertisingReport as bt_hci::FromHciBytes>::from_hci_bytes(&data).unwrap();
+        let info = BigInfo::from(&e);
+        assert_eq!(info.num_bis, 2);
+        assert_eq!(info.iso_interval, Duration::from_millis(10));
+        assert_eq!(info.sdu_interval, Duration::from_millis(10));
+        assert_eq!(info.max_sdu, 40);
+        assert_eq!(info.phy, PhyKind::Le2M);
+        assert!(!info.framed);
+        assert!(info.encrypted);
+    }
+
+    #[test]
+    fn cis_requests_are_queued() {
+        let state = IsoState::new();
+        let data = [0x40, 0x00, 0x60, 0x00, 1, 2];
+        let (e, _) = <LeCisRequest as bt_hci::FromHciBytes>::from_hci_bytes(&data).unwrap();
+        for _ in 0..config::ISO_CHANNELS_MAX {
+            assert!(state.cis_requested(&e));
+        }
+        assert!(!state.cis_requested(&e));
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let Poll::Ready(request) = state.poll_request(&mut cx) else {
+            panic!("expected a request");
+        };
+        assert_eq!(request.cis_handle, ConnHandle::new(0x60));
+        assert_eq!(request.cis_id, 2);
+    }
+
+    #[test]
+    fn created_big_opens_its_streams() {
+        let state = IsoState::new();
+        let big = state.open_big().unwrap();
+        #[rustfmt::skip]
+        let data = [
+            LE_CREATE_BIG_COMPLETE, 0x00, big,
+            // BIG sync delay, transport latency and PHY.
+            0x10, 0x27, 0x00, 0x20, 0x4e, 0x00, 0x02,
+            // NSE, BN, PTO, IRC, max PDU and ISO interval.
+            4, 1, 0, 2, 40, 0, 8, 0,
+            // Handles of the streams.
+            2, 0x70, 0x00, 0x71, 0x00,
+        ];
+        let e = BigEvent::from_le_meta(&data).unwrap().unwrap();
+        state.big_event(&e);
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let Poll::Ready(Ok((params, handles))) = state.poll_big_established(&mut cx, big) else {
+            panic!("expected the group to be created");
+        };
+        assert_eq!(params.sync_delay, Duration::from_millis(10));
+        assert_eq!(params.transport_latency, Duration::from_millis(20));
+        assert_eq!(params.phy, Some(PhyKind::Le2M));
+        assert_eq!(params.iso_interval, Duration::from_millis(10));
+        assert_eq!(handles, [ConnHandle::new(0x70), ConnHandle::new(0x71)]);
+        for &handle in handles.iter() {
+            state.open_bis(handle).unwrap();
+            assert!(state.is_open(handle));
+        }
+        assert!(state.is_big_open(big));
+        assert!(state.poll_big_closed(&mut cx, big).is_pending());
+
+        let data = [LE_TERMINATE_BIG_COMPLETE, big, 0x16];
+        state.big_event(&BigEvent::from_le_meta(&data).unwrap().unwrap());
+        assert!(state.poll_big_closed(&mut cx, big).is_ready());
+        assert!(!state.is_big_open(big));
+        assert!(handles.iter().all(|&handle| !state.is_open(handle)));
+        state.close_big(big);
+        assert_eq!(state.open_big().unwrap(), big);
+    }
+
+    #[test]
+    fn failed_big_sync_is_released() {
+        let state = IsoState::new();
+        let big = state.open_big().unwrap();
+        let data = [LE_BIG_SYNC_ESTABLISHED, 0x3e, big];
+        state.big_event(&BigEvent::from_le_meta(&data).unwrap().unwrap());
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(matches!(
+            state.poll_big_established(&mut cx, big),
+            Poll::Ready(Err(Error::Hci(_)))
+        ));
+        assert_eq!(state.open_big().unwrap(), big);
+        assert!(BigEvent::from_le_meta(&[0x01, 0x00]).unwrap().is_none());
+    }
+
+    fn open_stream(state: &IsoState, handle: u16) -> ConnHandle {
+        let data = established(handle, Status::SUCCESS);
+        let (e, _) = <LeCisEstablished as bt_hci::FromHciBytes>::from_hci_bytes(&data).unwrap();
+        let handle = ConnHandle::new(handle);
+        state.open(handle).unwrap();
+        state.cis_established(&e);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(matches!(state.poll_established(&mut cx, handle), Poll::Ready(Ok(_))));
+        handle
+    }
+
+    #[test]
+    fn fragments_are_reassembled() {
+        static POOL: StaticCell<PacketPool<64, 2>> = StaticCell::new();
+        let pool = POOL.init(PacketPool::new());
+        let state = IsoState::new();
+        let handle = open_stream(&state, 0x62);
+
+        // First fragment with timestamp, sequence number 7 and an SDU length of 5.
+        let first = [0x62, 0x40, 10, 0, 0x10, 0x27, 0, 0, 7, 0, 5, 0, 1, 2];
+        let last = [0x62, 0x30, 3, 0, 3, 4, 5];
+        for data in [&first[..], &last[..]] {
+            let (packet, _) = IsoPacket::from_hci_bytes(data).unwrap();
+            state.received(&packet, pool).unwrap();
+        }
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut buf = [0; 8];
+        let Poll::Ready(Ok(info)) = state.poll_receive(&mut cx, handle, &mut buf) else {
+            panic!("expected an SDU");
+        };
+        assert_eq!(info.len, 5);
+        assert_eq!(info.sequence_number, 7);
+        assert_eq!(info.timestamp, Some(10_000));
+        assert_eq!(info.status, IsoPacketStatus::Correct);
+        assert_eq!(&buf[..5], &[1, 2, 3, 4, 5]);
+        assert!(state.poll_receive(&mut cx, handle, &mut buf).is_pending());
+
+        // A continuation without a first fragment is rejected.
+        let (packet, _) = IsoPacket::from_hci_bytes(&last).unwrap();
+        assert!(matches!(state.received(&packet, pool), Err(Error::InvalidState)));
+    }
+
+    #[test]
+    fn buffers_are_returned() {
+        let state = IsoState::new();
+        let handle = open_stream(&state, 0x63);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(matches!(
+            state.poll_reserve(&mut cx, handle),
+            Poll::Ready(Err(Error::NotSupported))
+        ));
+
+        state.set_buffers(100, 2);
+        assert!(matches!(state.poll_reserve(&mut cx, handle), Poll::Ready(Ok(100))));
+        assert!(matches!(state.poll_reserve(&mut cx, handle), Poll::Ready(Ok(100))));
+        assert!(state.poll_reserve(&mut cx, handle).is_pending());
+
+        assert!(state.confirm_sent(handle, 1));
+        assert!(!state.confirm_sent(ConnHandle::new(0x01), 1));
+        assert!(matches!(state.poll_reserve(&mut cx, handle), Poll::Ready(Ok(100))));
+
+        // Packets flushed by a disconnect are returned, and the released stream is freed.
+        state.close(handle);
+        state.disconnected(handle, Status::REMOTE_USER_TERMINATED_CONN);
+        let handle = open_stream(&state, 0x64);
+        assert!(matches!(state.poll_reserve(&mut cx, handle), Poll::Ready(Ok(100))));
+        assert!(matches!(state.poll_reserve(&mut cx, handle), Poll::Ready(Ok(100))));
+    }
+}
@@ -4,6 +4,7 @@ use bt_hci::controller::{Controller, blocking};
 pub use crate::channel_manager::CreditFlowPolicy;
 use crate::channel_manager::{ChannelIndex, DynamicChannelManager};
 use crate::connection::Connection;
+use crate::procedure::{Procedure, Span};
 use crate::{BleHostError, Stack};
 
 pub(crate) mod sar;
@@ -69,6 +70,7 @@ impl Drop for L2capChannelReader<'_> {
 }
 
 /// Configuration for an L2CAP channel.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct L2capChannelConfig {
     /// Size of Service Data Unit
     pub mtu: u16,
@@ -154,6 +156,7 @@ impl<'d> L2capChannel<'d> {
         config: &L2capChannelConfig,
     ) -> Result<Self, BleHostError<T::Error>> {
         let handle = connection.handle();
+        let _span = Span::begin(Procedure::ChannelSetup, Some(handle));
         stack
             .host
             .channels
@@ -176,6 +179,7 @@ impl<'d> L2capChannel<'d> {
         config: &L2capChannelConfig,
     ) -> Result<Self, BleHostError<T::Error>>
 where {
+        let _span = Span::begin(Procedure::ChannelSetup, Some(connection.handle()));
         stack
             .host
             .channels
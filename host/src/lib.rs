@@ -4,32 +4,53 @@
 //!
 //! Trouble can run on embedded devices (`no_std`) and be configured to consume
 //! as little resources are needed depending on your required configuration.
+//!
+//! All state of a host is kept in its [`HostResources`] and [`Stack`], so several hosts
+//! can run in one application, each with its own controller, resources and runner. The
+//! servers defined with the `gatt_server` macro keep the values of their services in a
+//! storage, allocated statically by their constructors, so that a server type can only be
+//! constructed once, or passed by the application to the constructors suffixed with `_in`,
+//! so that each host may have its own instance of the same server type.
+//!
+//! With the `defmt` feature, the public events, errors, addresses, UUIDs and parameters of the
+//! host implement `defmt::Format`, and the host logs through `defmt`, or through `log` with the
+//! `log` feature. Messages are prefixed by the module logging them, such as `[host]`, `[l2cap]`
+//! or `[link]`. Lost data and failed procedures are logged as warnings, changes of the state of
+//! the host and its links as info, dumps of the state as debug, and packets as trace.
 #![no_std]
 #![allow(dead_code)]
 #![allow(unused_variables)]
 #![allow(clippy::needless_lifetimes)]
 #![warn(missing_docs)]
 
+#[cfg(feature = "std")]
+extern crate std;
+
 use core::mem::MaybeUninit;
 
 use advertise::AdvertisementDataError;
 use bt_hci::FromHciBytesError;
 use bt_hci::cmd::status::ReadRssi;
 use bt_hci::cmd::{AsyncCmd, SyncCmd};
-use bt_hci::param::{AddrKind, BdAddr};
+use bt_hci::param::{AddrKind, BdAddr, ChannelMap};
 
 use crate::att::AttErrorCode;
 use crate::channel_manager::{ChannelStorage, PacketChannel};
+use crate::connection::Connection;
 use crate::connection_manager::{ConnectionStorage, EventChannel};
 use crate::l2cap::sar::SarType;
 use crate::packet_pool::PacketPool;
 
+mod bitfield;
 mod fmt;
 
 #[cfg(not(any(feature = "central", feature = "peripheral")))]
 compile_error!("Must enable at least one of the `central` or `peripheral` features");
 
 pub mod att;
+#[cfg(feature = "audio")]
+pub mod audio;
+pub mod capture;
 #[cfg(feature = "central")]
 pub mod central;
 mod channel_manager;
@@ -38,10 +59,16 @@ mod command;
 pub mod config;
 mod connection_manager;
 mod cursor;
+pub mod hci;
+#[cfg(feature = "iso")]
+pub mod iso;
 pub mod packet_pool;
 mod pdu;
 #[cfg(feature = "peripheral")]
 pub mod peripheral;
+mod procedure;
+#[cfg(feature = "security")]
+pub mod security_manager;
 pub mod types;
 
 #[cfg(feature = "central")]
@@ -51,17 +78,26 @@ use peripheral::*;
 
 pub mod advertise;
 pub mod connection;
+pub mod event;
 #[cfg(feature = "gatt")]
 pub mod gap;
 pub mod l2cap;
 #[cfg(feature = "scan")]
 pub mod scan;
+pub mod test_mode;
+pub mod transport;
 
+#[cfg(all(test, feature = "gatt"))]
+pub(crate) mod mock_client;
 #[cfg(test)]
 pub(crate) mod mock_controller;
 
 pub(crate) mod host;
-use host::{AdvHandleState, BleHost, HostMetrics, Runner};
+#[cfg(feature = "command-metrics")]
+use host::CommandMetrics;
+#[cfg(feature = "traffic-metrics")]
+use host::TrafficMetrics;
+use host::{AdvHandleState, BleHost, Capabilities, HostMetrics, Runner};
 
 #[allow(missing_docs)]
 pub mod prelude {
@@ -81,20 +117,34 @@ pub mod prelude {
     pub use crate::attribute::*;
     #[cfg(feature = "gatt")]
     pub use crate::attribute_server::*;
+    #[cfg(feature = "audio")]
+    pub use crate::audio::*;
     #[cfg(feature = "central")]
     pub use crate::central::*;
     pub use crate::connection::*;
+    pub use crate::event::*;
     #[cfg(feature = "gatt")]
     pub use crate::gap::*;
     #[cfg(feature = "gatt")]
     pub use crate::gatt::*;
-    pub use crate::host::{ControlRunner, EventHandler, HostMetrics, Runner, RxRunner, TxRunner};
+    #[cfg(feature = "traffic-metrics")]
+    pub use crate::host::TrafficMetrics;
+    #[cfg(feature = "command-metrics")]
+    pub use crate::host::{COMMAND_METRICS_OPCODES, CommandLatency, CommandMetrics};
+    pub use crate::host::{
+        Capabilities, ControlRunner, EventHandler, HostFeatures, HostMetrics, Runner, RxRunner, TxRunner,
+    };
+    #[cfg(feature = "iso")]
+    pub use crate::iso::*;
     pub use crate::l2cap::*;
     pub use crate::packet_pool::PacketPool;
+    #[cfg(feature = "traffic-metrics")]
+    pub use crate::packet_pool::PoolMetrics;
     #[cfg(feature = "peripheral")]
     pub use crate::peripheral::*;
     #[cfg(feature = "scan")]
     pub use crate::scan::*;
+    pub use crate::test_mode::*;
     #[cfg(feature = "gatt")]
     pub use crate::types::gatt_traits::{AsGatt, FixedGattValue, FromGatt};
 }
@@ -105,9 +155,11 @@ pub mod attribute;
 mod attribute_server;
 #[cfg(feature = "gatt")]
 pub mod gatt;
+#[cfg(feature = "gatt")]
+pub mod services;
 
 /// A BLE address.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Address {
     /// Address type.
@@ -140,12 +192,33 @@ pub enum BleHostError<E> {
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
-    /// Error encoding parameters for HCI commands.
+    /// Error status reported by the controller in an event.
+    ///
+    /// HCI commands rejected by the controller fail with [`Error::Command`] instead.
     Hci(bt_hci::param::Error),
     /// Error decoding responses from HCI commands.
     HciDecode(FromHciBytesError),
     /// Error from the Attribute Protocol.
+    ///
+    /// The error responses received by the GATT client are reported with [`Error::Gatt`] instead.
     Att(AttErrorCode),
+    /// A GATT procedure failed with an error response of the server.
+    ///
+    /// All the error responses received by the GATT client are reported this way, with the
+    /// procedure and the attribute in error.
+    #[cfg(feature = "gatt")]
+    Gatt(gatt::GattError),
+    /// An HCI command failed with an error status of the controller.
+    ///
+    /// All the commands rejected by the controller fail this way, those run by the host and by
+    /// [`Stack::command`] alike. A `bt_hci::cmd::Error` converted with `From`, which does not
+    /// know the command, gives [`Error::Hci`] instead.
+    Command {
+        /// Opcode of the command.
+        opcode: bt_hci::cmd::Opcode,
+        /// Status returned by the controller.
+        status: bt_hci::param::Error,
+    },
     /// Insufficient space in the buffer.
     InsufficientSpace,
     /// Invalid value.
@@ -174,10 +247,57 @@ pub enum Error {
     NoPermits,
     /// Connection is disconnected.
     Disconnected,
+    /// An operation on an audio stream endpoint was rejected by the server.
+    #[cfg(feature = "audio")]
+    Ascs(audio::ascs::AseResponse),
     /// Other error.
     Other,
 }
 
+impl Error {
+    /// The error code of the attribute server, if a GATT procedure failed.
+    pub fn att_code(&self) -> Option<AttErrorCode> {
+        match self {
+            Self::Att(code) => Some(*code),
+            #[cfg(feature = "gatt")]
+            Self::Gatt(e) => Some(e.code),
+            _ => None,
+        }
+    }
+
+    /// The status of the controller, if it reported the error in an event or for a command.
+    pub fn hci_status(&self) -> Option<bt_hci::param::Error> {
+        match self {
+            Self::Hci(status) | Self::Command { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+impl embedded_io::Error for Error {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Self::InsufficientSpace | Self::OutOfMemory => embedded_io::ErrorKind::OutOfMemory,
+            Self::InvalidValue => embedded_io::ErrorKind::InvalidData,
+            Self::NotFound => embedded_io::ErrorKind::NotFound,
+            Self::NotSupported => embedded_io::ErrorKind::Unsupported,
+            Self::Timeout => embedded_io::ErrorKind::TimedOut,
+            Self::ChannelClosed => embedded_io::ErrorKind::BrokenPipe,
+            Self::Disconnected => embedded_io::ErrorKind::NotConnected,
+            _ => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+impl<E: embedded_io::Error> embedded_io::Error for BleHostError<E> {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Self::Controller(e) => e.kind(),
+            Self::BleHost(e) => e.kind(),
+        }
+    }
+}
+
 impl<E> From<Error> for BleHostError<E> {
     fn from(value: Error) -> Self {
         Self::BleHost(value)
@@ -205,6 +325,18 @@ impl<E> From<bt_hci::cmd::Error<E>> for BleHostError<E> {
     }
 }
 
+impl From<AdvertisementDataError> for Error {
+    fn from(error: AdvertisementDataError) -> Self {
+        Self::Advertisement(error)
+    }
+}
+
+impl<E> From<AdvertisementDataError> for BleHostError<E> {
+    fn from(error: AdvertisementDataError) -> Self {
+        Self::BleHost(Error::Advertisement(error))
+    }
+}
+
 impl<E> From<bt_hci::param::Error> for BleHostError<E> {
     fn from(error: bt_hci::param::Error) -> Self {
         Self::BleHost(Error::Hci(error))
@@ -234,12 +366,87 @@ use bt_hci::cmd::le::*;
 use bt_hci::cmd::link_control::*;
 use bt_hci::controller::{ControllerCmdAsync, ControllerCmdSync};
 
+/// Commands the controller must support for the security manager, enabled by the `security` feature.
+#[cfg(feature = "security")]
+pub trait SecurityController:
+    ControllerCmdSync<LeLongTermKeyRequestReply>
+    + ControllerCmdSync<LeLongTermKeyRequestNegativeReply>
+    + ControllerCmdAsync<LeEnableEncryption>
+    + ControllerCmdSync<bt_hci::cmd::info::ReadBdAddr>
+    + ControllerCmdSync<bt_hci::cmd::controller_baseband::SetEventMaskPage2>
+{
+}
+
+#[cfg(feature = "security")]
+impl<
+    C: ControllerCmdSync<LeLongTermKeyRequestReply>
+        + ControllerCmdSync<LeLongTermKeyRequestNegativeReply>
+        + ControllerCmdAsync<LeEnableEncryption>
+        + ControllerCmdSync<bt_hci::cmd::info::ReadBdAddr>
+        + ControllerCmdSync<bt_hci::cmd::controller_baseband::SetEventMaskPage2>,
+> SecurityController for C
+{
+}
+
+/// Commands the controller must support for the security manager, enabled by the `security` feature.
+#[cfg(not(feature = "security"))]
+pub trait SecurityController {}
+
+#[cfg(not(feature = "security"))]
+impl<C> SecurityController for C {}
+
+/// Commands the controller must support for scanning, enabled by the `scan` feature.
+#[cfg(feature = "scan")]
+pub trait ScanController: ControllerCmdSync<bt_hci::cmd::le::LePeriodicAdvTerminateSync> {}
+
+#[cfg(feature = "scan")]
+impl<C: ControllerCmdSync<bt_hci::cmd::le::LePeriodicAdvTerminateSync>> ScanController for C {}
+
+/// Commands the controller must support for scanning, enabled by the `scan` feature.
+#[cfg(not(feature = "scan"))]
+pub trait ScanController {}
+
+#[cfg(not(feature = "scan"))]
+impl<C> ScanController for C {}
+
+/// Commands the controller must support for privacy, enabled by the `peripheral` feature.
+///
+/// They are used to program the resolving list again when an unresponsive controller is reset.
+#[cfg(feature = "peripheral")]
+pub trait PrivacyController:
+    ControllerCmdSync<LeSetAddrResolutionEnable>
+    + ControllerCmdSync<LeClearResolvingList>
+    + ControllerCmdSync<LeAddDeviceToResolvingList>
+    + ControllerCmdSync<LeSetResolvablePrivateAddrTimeout>
+{
+}
+
+#[cfg(feature = "peripheral")]
+impl<
+    C: ControllerCmdSync<LeSetAddrResolutionEnable>
+        + ControllerCmdSync<LeClearResolvingList>
+        + ControllerCmdSync<LeAddDeviceToResolvingList>
+        + ControllerCmdSync<LeSetResolvablePrivateAddrTimeout>,
+> PrivacyController for C
+{
+}
+
+/// Commands the controller must support for privacy, enabled by the `peripheral` feature.
+#[cfg(not(feature = "peripheral"))]
+pub trait PrivacyController {}
+
+#[cfg(not(feature = "peripheral"))]
+impl<C> PrivacyController for C {}
+
 /// Trait that defines the controller implementation required by the host.
 ///
 /// The controller must implement the required commands and events to be able to be used with Trouble.
 pub trait Controller:
     bt_hci::controller::Controller
     + embedded_io::ErrorType
+    + SecurityController
+    + ScanController
+    + PrivacyController
     + ControllerCmdSync<LeReadBufferSize>
     + ControllerCmdSync<Disconnect>
     + ControllerCmdSync<SetEventMask>
@@ -248,6 +455,9 @@ pub trait Controller:
     + ControllerCmdSync<HostBufferSize>
     + ControllerCmdAsync<LeConnUpdate>
     + ControllerCmdSync<LeReadFilterAcceptListSize>
+    + ControllerCmdSync<LeReadLocalSupportedFeatures>
+    + ControllerCmdSync<LeReadMaxDataLength>
+    + ControllerCmdSync<LeWriteSuggestedDefaultDataLength>
     + ControllerCmdSync<SetControllerToHostFlowControl>
     + ControllerCmdSync<Reset>
     + ControllerCmdSync<ReadRssi>
@@ -257,20 +467,22 @@ pub trait Controller:
     + ControllerCmdAsync<LeCreateConn>
     + ControllerCmdSync<LeClearFilterAcceptList>
     + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
-    + for<'t> ControllerCmdSync<LeSetAdvEnable>
+    + ControllerCmdSync<LeSetAdvEnable>
     + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
     + for<'t> ControllerCmdSync<HostNumberOfCompletedPackets<'t>>
     + ControllerCmdSync<LeReadBufferSize>
-    + for<'t> ControllerCmdSync<LeSetAdvData>
+    + ControllerCmdSync<LeSetAdvData>
     + ControllerCmdSync<LeSetAdvParams>
-    + for<'t> ControllerCmdSync<LeSetAdvEnable>
-    + for<'t> ControllerCmdSync<LeSetScanResponseData>
+    + ControllerCmdSync<LeSetScanResponseData>
 {
 }
 
 impl<
     C: bt_hci::controller::Controller
         + embedded_io::ErrorType
+        + SecurityController
+        + ScanController
+        + PrivacyController
         + ControllerCmdSync<LeReadBufferSize>
         + ControllerCmdSync<Disconnect>
         + ControllerCmdSync<SetEventMask>
@@ -279,6 +491,9 @@ impl<
         + ControllerCmdSync<HostBufferSize>
         + ControllerCmdAsync<LeConnUpdate>
         + ControllerCmdSync<LeReadFilterAcceptListSize>
+        + ControllerCmdSync<LeReadLocalSupportedFeatures>
+        + ControllerCmdSync<LeReadMaxDataLength>
+        + ControllerCmdSync<LeWriteSuggestedDefaultDataLength>
         + ControllerCmdSync<LeClearFilterAcceptList>
         + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
         + ControllerCmdSync<SetControllerToHostFlowControl>
@@ -288,14 +503,13 @@ impl<
         + ControllerCmdSync<LeSetExtScanEnable>
         + ControllerCmdSync<LeCreateConnCancel>
         + ControllerCmdAsync<LeCreateConn>
-        + for<'t> ControllerCmdSync<LeSetAdvEnable>
+        + ControllerCmdSync<LeSetAdvEnable>
         + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
         + for<'t> ControllerCmdSync<HostNumberOfCompletedPackets<'t>>
         + ControllerCmdSync<LeReadBufferSize>
-        + for<'t> ControllerCmdSync<LeSetAdvData>
+        + ControllerCmdSync<LeSetAdvData>
         + ControllerCmdSync<LeSetAdvParams>
-        + for<'t> ControllerCmdSync<LeSetAdvEnable>
-        + for<'t> ControllerCmdSync<LeSetScanResponseData>,
+        + ControllerCmdSync<LeSetScanResponseData>,
 > Controller for C
 {
 }
@@ -304,7 +518,29 @@ impl<
 ///
 /// The l2cap packet pool is used by the host to handle inbound data, by allocating space for
 /// incoming packets and dispatching to the appropriate connection and channel.
-pub struct HostResources<const CONNS: usize, const CHANNELS: usize, const L2CAP_MTU: usize, const ADV_SETS: usize = 1> {
+///
+/// `SCAN_CACHE` is the number of advertisers remembered by the host duplicate filter for scan
+/// reports, which is disabled when zero.
+///
+/// `SCAN_REPORTS` is the number of scan reports queued for a [`ScanSession`](scan::ScanSession),
+/// which only receives reports when it is not zero.
+///
+/// `PERIODIC_REPORTS` is the number of periodic advertising reports queued for the
+/// [`PeriodicSync`](scan::PeriodicSync)s, which only receive reports when it is not zero.
+///
+/// `SCAN_RESPONSES` is the number of advertisers whose advertisement is held until their scan
+/// response arrives, so that both are reported together. Scan responses are reported on their
+/// own when it is zero.
+pub struct HostResources<
+    const CONNS: usize,
+    const CHANNELS: usize,
+    const L2CAP_MTU: usize,
+    const ADV_SETS: usize = 1,
+    const SCAN_CACHE: usize = 0,
+    const SCAN_REPORTS: usize = 0,
+    const PERIODIC_REPORTS: usize = 0,
+    const SCAN_RESPONSES: usize = 0,
+> {
     rx_pool: MaybeUninit<PacketPool<L2CAP_MTU, { config::L2CAP_RX_PACKET_POOL_SIZE }>>,
     #[cfg(feature = "gatt")]
     tx_pool: MaybeUninit<PacketPool<L2CAP_MTU, { config::L2CAP_TX_PACKET_POOL_SIZE }>>,
@@ -314,18 +550,43 @@ pub struct HostResources<const CONNS: usize, const CHANNELS: usize, const L2CAP_
     channels_rx: MaybeUninit<[PacketChannel<{ config::L2CAP_RX_QUEUE_SIZE }>; CHANNELS]>,
     sar: MaybeUninit<[SarType; CONNS]>,
     advertise_handles: MaybeUninit<[AdvHandleState; ADV_SETS]>,
+    #[cfg(feature = "scan")]
+    scan_cache: MaybeUninit<[Option<scan::ScanCacheEntry>; SCAN_CACHE]>,
+    #[cfg(feature = "scan")]
+    scan_reports: MaybeUninit<[Option<scan::OwnedScanReport>; SCAN_REPORTS]>,
+    #[cfg(feature = "scan")]
+    periodic_reports: MaybeUninit<[Option<scan::QueuedPeriodicReport>; PERIODIC_REPORTS]>,
+    #[cfg(feature = "scan")]
+    scan_responses: MaybeUninit<[Option<scan::PendingReport>; SCAN_RESPONSES]>,
 }
 
-impl<const CONNS: usize, const CHANNELS: usize, const L2CAP_MTU: usize, const ADV_SETS: usize> Default
-    for HostResources<CONNS, CHANNELS, L2CAP_MTU, ADV_SETS>
+impl<
+    const CONNS: usize,
+    const CHANNELS: usize,
+    const L2CAP_MTU: usize,
+    const ADV_SETS: usize,
+    const SCAN_CACHE: usize,
+    const SCAN_REPORTS: usize,
+    const PERIODIC_REPORTS: usize,
+    const SCAN_RESPONSES: usize,
+> Default
+    for HostResources<CONNS, CHANNELS, L2CAP_MTU, ADV_SETS, SCAN_CACHE, SCAN_REPORTS, PERIODIC_REPORTS, SCAN_RESPONSES>
 {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<const CONNS: usize, const CHANNELS: usize, const L2CAP_MTU: usize, const ADV_SETS: usize>
-    HostResources<CONNS, CHANNELS, L2CAP_MTU, ADV_SETS>
+impl<
+    const CONNS: usize,
+    const CHANNELS: usize,
+    const L2CAP_MTU: usize,
+    const ADV_SETS: usize,
+    const SCAN_CACHE: usize,
+    const SCAN_REPORTS: usize,
+    const PERIODIC_REPORTS: usize,
+    const SCAN_RESPONSES: usize,
+> HostResources<CONNS, CHANNELS, L2CAP_MTU, ADV_SETS, SCAN_CACHE, SCAN_REPORTS, PERIODIC_REPORTS, SCAN_RESPONSES>
 {
     /// Create a new instance of host resources.
     pub const fn new() -> Self {
@@ -339,6 +600,14 @@ impl<const CONNS: usize, const CHANNELS: usize, const L2CAP_MTU: usize, const AD
             channels: MaybeUninit::uninit(),
             channels_rx: MaybeUninit::uninit(),
             advertise_handles: MaybeUninit::uninit(),
+            #[cfg(feature = "scan")]
+            scan_cache: MaybeUninit::uninit(),
+            #[cfg(feature = "scan")]
+            scan_reports: MaybeUninit::uninit(),
+            #[cfg(feature = "scan")]
+            periodic_reports: MaybeUninit::uninit(),
+            #[cfg(feature = "scan")]
+            scan_responses: MaybeUninit::uninit(),
         }
     }
 }
@@ -352,9 +621,22 @@ pub fn new<
     const CHANNELS: usize,
     const L2CAP_MTU: usize,
     const ADV_SETS: usize,
+    const SCAN_CACHE: usize,
+    const SCAN_REPORTS: usize,
+    const PERIODIC_REPORTS: usize,
+    const SCAN_RESPONSES: usize,
 >(
     controller: C,
-    resources: &'resources mut HostResources<CONNS, CHANNELS, L2CAP_MTU, ADV_SETS>,
+    resources: &'resources mut HostResources<
+        CONNS,
+        CHANNELS,
+        L2CAP_MTU,
+        ADV_SETS,
+        SCAN_CACHE,
+        SCAN_REPORTS,
+        PERIODIC_REPORTS,
+        SCAN_RESPONSES,
+    >,
 ) -> Stack<'resources, C> {
     unsafe fn transmute_slice<T>(x: &mut [T]) -> &'static mut [T] {
         unsafe { core::mem::transmute(x) }
@@ -396,6 +678,23 @@ pub fn new<
     let sar: &'static mut [Option<(ConnHandle, L2capHeader, AssembledPacket)>] = unsafe { transmute_slice(sar) };
     let advertise_handles = &mut *resources.advertise_handles.write([AdvHandleState::None; ADV_SETS]);
     let advertise_handles: &'static mut [AdvHandleState] = unsafe { transmute_slice(advertise_handles) };
+    #[cfg(feature = "scan")]
+    let scan_cache = &mut *resources.scan_cache.write([None; SCAN_CACHE]);
+    #[cfg(feature = "scan")]
+    let scan_cache: &'static mut [Option<scan::ScanCacheEntry>] = unsafe { transmute_slice(scan_cache) };
+    #[cfg(feature = "scan")]
+    let scan_reports = &mut *resources.scan_reports.write([const { None }; SCAN_REPORTS]);
+    #[cfg(feature = "scan")]
+    let scan_reports: &'static mut [Option<scan::OwnedScanReport>] = unsafe { transmute_slice(scan_reports) };
+    #[cfg(feature = "scan")]
+    let periodic_reports = &mut *resources.periodic_reports.write([const { None }; PERIODIC_REPORTS]);
+    #[cfg(feature = "scan")]
+    let periodic_reports: &'static mut [Option<scan::QueuedPeriodicReport>] =
+        unsafe { transmute_slice(periodic_reports) };
+    #[cfg(feature = "scan")]
+    let scan_responses = &mut *resources.scan_responses.write([const { None }; SCAN_RESPONSES]);
+    #[cfg(feature = "scan")]
+    let scan_responses: &'static mut [Option<scan::PendingReport>] = unsafe { transmute_slice(scan_responses) };
     let host: BleHost<'_, C> = BleHost::new(
         controller,
         rx_pool,
@@ -407,6 +706,14 @@ pub fn new<
         channels_rx,
         sar,
         advertise_handles,
+        #[cfg(feature = "scan")]
+        scan_cache,
+        #[cfg(feature = "scan")]
+        scan_reports,
+        #[cfg(feature = "scan")]
+        periodic_reports,
+        #[cfg(feature = "scan")]
+        scan_responses,
     );
 
     Stack { host }
@@ -437,6 +744,102 @@ impl<'stack, C: Controller> Stack<'stack, C> {
         self
     }
 
+    /// Report the command credits tracked by the transport of the controller in the command metrics.
+    #[cfg(feature = "command-metrics")]
+    pub fn set_command_credits(mut self, credits: &'stack transport::credits::CommandCredits) -> Self {
+        self.host.command_credits.replace(credits);
+        self
+    }
+
+    /// Set how long the host waits for the controller to respond to a command, 2 seconds by default.
+    ///
+    /// A command only starts timing out once the commands issued before it completed. The
+    /// controller is reset when it sent nothing while a command of the host timed out, or when
+    /// several commands of the host timed out in a row.
+    pub fn set_command_timeout(mut self, timeout: embassy_time::Duration) -> Self {
+        self.host.command_timeout = timeout;
+        self
+    }
+
+    /// Capture the HCI packets exchanged with the controller, see [`capture`].
+    pub fn set_packet_capture(mut self, capture: &'stack dyn capture::PacketCapture) -> Self {
+        self.host.capture.replace(capture);
+        self
+    }
+
+    /// Seed the random generator of the security manager, which pairing needs.
+    ///
+    /// The seed must come from a cryptographically secure source, such as the random number
+    /// generator of the chip, as it decides the keys of the pairings.
+    #[cfg(feature = "security")]
+    pub fn set_random_generator_seed<R: rand_core::RngCore + rand_core::CryptoRng>(mut self, rng: &mut R) -> Self {
+        let mut seed = [0; 32];
+        rng.fill_bytes(&mut seed);
+        self.host.security.set_random_generator_seed(seed);
+        self
+    }
+
+    /// Set the IO capabilities of the device, deciding if pairing can be authenticated.
+    #[cfg(feature = "security")]
+    pub fn set_io_capabilities(mut self, io_capabilities: security_manager::IoCapabilities) -> Self {
+        self.host.security.set_io_capabilities(io_capabilities);
+        self
+    }
+
+    /// Only pair with LE Secure Connections and a 128-bit key, as in security mode 1 level 4.
+    ///
+    /// Legacy pairing is rejected, and peers may only discover services, with attribute requests
+    /// and L2CAP channels refused until the link reaches `SecurityLevel::SecureConnections`.
+    #[cfg(feature = "security")]
+    pub fn set_secure_connections_only(mut self, enabled: bool) -> Self {
+        self.host.security.set_secure_connections_only(enabled);
+        self
+    }
+
+    /// Pair with the debug key of LE Secure Connections, so that sniffers can decrypt the links.
+    ///
+    /// **Not for production**: anyone can decrypt links paired with the debug key, whose private
+    /// key is published in the specification. Peers in secure connections only mode refuse it.
+    #[cfg(feature = "security-debug-keys")]
+    pub fn use_security_debug_keys(mut self) -> Self {
+        self.host.security.use_debug_keys();
+        self
+    }
+
+    /// Set what to do when pairing with a new peer while the bond table is full.
+    #[cfg(feature = "security")]
+    pub fn set_bond_eviction_policy(mut self, policy: security_manager::BondEvictionPolicy) -> Self {
+        self.host.security.set_eviction_policy(policy);
+        self
+    }
+
+    /// The bonded peers, from the least to the most recently used.
+    #[cfg(feature = "security")]
+    pub fn bonds(&self) -> heapless::Vec<security_manager::BondInformation, { config::BOND_TABLE_SIZE }> {
+        self.host.security.bonds()
+    }
+
+    /// Add the bond of a peer, such as one restored from persistent storage, replacing any
+    /// previous bond with the same identity.
+    #[cfg(feature = "security")]
+    pub fn add_bond_information(&self, bond: security_manager::BondInformation) -> Result<(), Error> {
+        self.host.security.add_bond(bond)
+    }
+
+    /// Forget the bond of a peer, returning it.
+    ///
+    /// Links already encrypted with its key stay encrypted until they disconnect.
+    #[cfg(feature = "security")]
+    pub fn remove_bond_information(&self, identity: &Address) -> Result<security_manager::BondInformation, Error> {
+        self.host.security.remove_bond(identity)
+    }
+
+    /// Forget the bonds of all peers.
+    #[cfg(feature = "security")]
+    pub fn clear_bonds(&self) {
+        self.host.security.clear_bonds();
+    }
+
     /// Build the stack.
     pub fn build(&'stack self) -> Host<'stack, C> {
         Host {
@@ -448,22 +851,137 @@ impl<'stack, C: Controller> Stack<'stack, C> {
         }
     }
 
+    /// Add a device to the controller filter accept list.
+    ///
+    /// The list is used by scanning and connecting with `ScanConfig::use_filter_accept_list`,
+    /// and by advertising with a filter policy other than `AdvFilterPolicy::Unfiltered`. It
+    /// cannot be modified while it is in use by the controller.
+    pub async fn add_to_filter_accept_list(&self, address: &Address) -> Result<(), BleHostError<C::Error>> {
+        self.host.add_to_accept_list(address.kind, address.addr).await
+    }
+
+    /// Remove a device from the controller filter accept list.
+    pub async fn remove_from_filter_accept_list(&self, address: &Address) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeRemoveDeviceFromFilterAcceptList>,
+    {
+        self.host.remove_from_accept_list(address.kind, address.addr).await
+    }
+
+    /// Remove all devices from the controller filter accept list.
+    pub async fn clear_filter_accept_list(&self) -> Result<(), BleHostError<C::Error>> {
+        self.host.clear_accept_list().await
+    }
+
+    /// Number of entries the controller filter accept list can hold.
+    pub async fn filter_accept_list_size(&self) -> Result<u8, BleHostError<C::Error>> {
+        self.host.command(LeReadFilterAcceptListSize::new()).await
+    }
+
+    /// Tell the controller which data channels are known to be bad, for instance because of a
+    /// colocated radio, so that they are excluded from the channel maps of connections.
+    ///
+    /// At least two of the 37 data channels must be left usable.
+    pub async fn set_host_channel_classification(&self, map: ChannelMap) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeSetHostChannelClassification>,
+    {
+        // Number of LE data channels.
+        const DATA_CHANNELS: u8 = 37;
+
+        let usable = (0..DATA_CHANNELS).filter(|c| !map.is_channel_bad(*c)).count();
+        if usable < 2 {
+            return Err(Error::InvalidValue.into());
+        }
+        self.host.command(LeSetHostChannelClassification::new(map)).await
+    }
+
     /// Run a HCI command and return the response.
+    ///
+    /// A command timing out fails with `Error::Timeout`. Unlike the commands run by the host itself,
+    /// it does not make the host reset the controller, which is left to the application.
     pub async fn command<T>(&self, cmd: T) -> Result<T::Return, BleHostError<C::Error>>
     where
         T: SyncCmd,
         C: ControllerCmdSync<T>,
     {
-        self.host.command(cmd).await
+        self.host.user_command(cmd).await
     }
 
     /// Run an async HCI command where the response will generate an event later.
+    ///
+    /// Timeouts are handled as with [`Stack::command`].
     pub async fn async_command<T>(&self, cmd: T) -> Result<(), BleHostError<C::Error>>
     where
         T: AsyncCmd,
         C: ControllerCmdAsync<T>,
     {
-        self.host.async_command(cmd).await
+        self.host.user_async_command(cmd).await
+    }
+
+    /// Run a vendor-specific HCI command with opcode command field `OCF` and the given raw parameters.
+    ///
+    /// The command must complete with `N` bytes of return parameters, which are returned as-is. Vendor-specific
+    /// events are delivered to [`EventHandler::on_vendor`](prelude::EventHandler::on_vendor) when running the host with
+    /// [`Runner::run_with_handler`](prelude::Runner::run_with_handler).
+    pub async fn vendor_command<const OCF: u16, const N: usize>(
+        &self,
+        params: &[u8],
+    ) -> Result<[u8; N], BleHostError<C::Error>>
+    where
+        C: for<'a> ControllerCmdSync<hci::VendorCommand<'a, OCF, N>>,
+    {
+        let cmd = hci::VendorCommand::<OCF, N>::new(params).ok_or(Error::InvalidValue)?;
+        self.host.user_command(cmd).await
+    }
+
+    /// Iterate over the active connections, in both the central and peripheral role.
+    pub fn connections(&'stack self) -> impl Iterator<Item = Connection<'stack>> + 'stack {
+        self.host.connections.connections()
+    }
+
+    /// Listen to the events of the host.
+    ///
+    /// Returns `Error::InsufficientSpace` if there are already `HOST_EVENT_MAX_SUBSCRIBERS`
+    /// listeners.
+    pub fn events(&self) -> Result<event::HostEventListener<'_>, Error> {
+        let subscriber = self
+            .host
+            .events
+            .dyn_subscriber()
+            .map_err(|_| Error::InsufficientSpace)?;
+        Ok(event::HostEventListener::new(subscriber))
+    }
+
+    /// Capabilities of the controller.
+    ///
+    /// These are read when the host is initialized, so this waits for the runner to complete initialization.
+    pub async fn capabilities(&self) -> Capabilities {
+        self.host.capabilities().await
+    }
+
+    /// Read the HCI commands supported by the controller.
+    ///
+    /// Returns `None` if the controller does not support reading them.
+    pub async fn supported_commands(&self) -> Result<Option<bt_hci::param::CmdMask>, BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<bt_hci::cmd::info::ReadLocalSupportedCmds>,
+    {
+        host::unsupported_as_none(
+            self.host
+                .command(bt_hci::cmd::info::ReadLocalSupportedCmds::new())
+                .await,
+        )
+    }
+
+    /// Read the LE states and state combinations supported by the controller.
+    ///
+    /// Returns `None` if the controller does not support reading them.
+    pub async fn supported_states(&self) -> Result<Option<[u8; 8]>, BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeReadSupportedStates>,
+    {
+        host::unsupported_as_none(self.host.command(LeReadSupportedStates::new()).await)
     }
 
     /// Read current host metrics
@@ -471,6 +989,18 @@ impl<'stack, C: Controller> Stack<'stack, C> {
         self.host.metrics()
     }
 
+    /// Read current HCI command metrics
+    #[cfg(feature = "command-metrics")]
+    pub fn command_metrics(&self) -> CommandMetrics {
+        self.host.command_metrics()
+    }
+
+    /// Read current traffic metrics
+    #[cfg(feature = "traffic-metrics")]
+    pub fn traffic_metrics(&self) -> TrafficMetrics {
+        self.host.traffic_metrics()
+    }
+
     /// Log status information of the host
     pub fn log_status(&self, verbose: bool) {
         self.host.log_status(verbose);
@@ -0,0 +1,200 @@
+//! A GATT client connected to an attribute server, exchanging ATT PDUs with it like a peer.
+extern crate std;
+
+use core::task::Poll;
+use std::boxed::Box;
+
+use bt_hci::param::{AddrKind, BdAddr, ConnHandle, LeConnRole};
+use embassy_futures::{poll_once, yield_now};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+
+use crate::att::{Att, AttCfm, AttClient, AttCmd, AttErrorCode, AttReq, AttRsp, AttServer, AttUns};
+use crate::attribute_server::AttributeServer;
+use crate::connection::{Connection, SecurityLevel};
+use crate::connection_manager::{ConnectionManager, ConnectionStorage, EventChannel};
+use crate::packet_pool::PacketPool;
+use crate::types::uuid::Uuid;
+
+/// Largest value read by the client.
+const MAX_VALUE_LEN: usize = 128;
+
+/// A peer connected to an attribute server, only processing PDUs when its methods are called.
+pub struct MockClient {
+    manager: &'static ConnectionManager<'static>,
+    connection: Connection<'static>,
+}
+
+impl MockClient {
+    /// Connect a client, as the central of a connection to the server.
+    pub fn new() -> Self {
+        let storage = Box::leak(Box::new([ConnectionStorage::DISCONNECTED; 1]));
+        let events = Box::leak(Box::new([EventChannel::NEW; 1]));
+        let pool = Box::leak(Box::new(PacketPool::<251, 4>::new()));
+        let manager = Box::leak(Box::new(ConnectionManager::new(
+            &mut storage[..],
+            &mut events[..],
+            23,
+            pool,
+        )));
+        unwrap!(manager.connect(
+            ConnHandle::new(0x40),
+            AddrKind::RANDOM,
+            BdAddr::new([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]),
+            LeConnRole::Peripheral,
+        ));
+        let Poll::Ready(connection) = manager.poll_accept(LeConnRole::Peripheral, &[], None) else {
+            panic!("expected the connection to be accepted");
+        };
+        Self { manager, connection }
+    }
+
+    /// The connection of the server to the client.
+    pub fn connection(&self) -> &Connection<'static> {
+        &self.connection
+    }
+
+    /// Encrypt the connection, as when the client paired or encrypted it with its bond.
+    pub fn encrypt(&self, level: SecurityLevel) {
+        unwrap!(self.manager.set_security_level(self.connection.handle(), level));
+    }
+
+    /// Read the value of an attribute.
+    pub fn read<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        handle: u16,
+    ) -> Result<Vec<u8, MAX_VALUE_LEN>, AttErrorCode> {
+        let mut rx = [0; 251];
+        match self.request(server, &AttClient::Request(AttReq::Read { handle }), &mut rx)? {
+            Some(AttRsp::Read { data }) => Ok(unwrap!(Vec::from_slice(data))),
+            rsp => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    /// Read the first attribute of a type, returning its handle and value.
+    pub fn read_by_type<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        attribute_type: impl Into<Uuid>,
+    ) -> Result<(u16, Vec<u8, MAX_VALUE_LEN>), AttErrorCode> {
+        let mut rx = [0; 251];
+        let req = AttReq::ReadByType {
+            start: 1,
+            end: 0xffff,
+            attribute_type: attribute_type.into(),
+        };
+        match self.request(server, &AttClient::Request(req), &mut rx)? {
+            Some(AttRsp::ReadByType { mut it }) => {
+                let (handle, value) = unwrap!(unwrap!(it.next()));
+                Ok((handle, unwrap!(Vec::from_slice(value))))
+            }
+            rsp => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    /// Write the value of an attribute, accepted or rejected by the application with `on_write`
+    /// like a write event.
+    pub fn write<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        handle: u16,
+        data: &[u8],
+        on_write: impl FnOnce(u16, &[u8]) -> Result<(), AttErrorCode>,
+    ) -> Result<(), AttErrorCode> {
+        on_write(handle, data)?;
+        let mut rx = [0; 251];
+        match self.request(server, &AttClient::Request(AttReq::Write { handle, data }), &mut rx)? {
+            Some(AttRsp::Write) => Ok(()),
+            rsp => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    /// Write the value of an attribute without response, accepted or rejected by the application
+    /// with `on_write`, the server dropping a command rejected.
+    pub fn write_without_response<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        handle: u16,
+        data: &[u8],
+        on_write: impl FnOnce(u16, &[u8]) -> Result<(), AttErrorCode>,
+    ) -> Result<(), AttErrorCode> {
+        on_write(handle, data)?;
+        let mut rx = [0; 251];
+        let cmd = AttClient::Command(AttCmd::Write { handle, data });
+        assert!(matches!(self.request(server, &cmd, &mut rx), Ok(None)));
+        Ok(())
+    }
+
+    /// Enable or disable the notifications or indications of a characteristic through its CCCD.
+    pub fn subscribe<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        cccd_handle: u16,
+        value: u16,
+    ) -> Result<(), AttErrorCode> {
+        self.write(server, cccd_handle, &value.to_le_bytes(), |_, _| Ok(()))
+    }
+
+    /// Whether the server notifies the client of a characteristic, given its CCCD.
+    pub fn is_subscribed<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        cccd_handle: u16,
+    ) -> bool {
+        server.should_notify(&self.connection, cccd_handle)
+    }
+
+    /// Take the next notification or indication sent by the server, returning its handle and value.
+    pub fn notified(&self) -> Option<(u16, Vec<u8, MAX_VALUE_LEN>)> {
+        let Poll::Ready((_, pdu)) = poll_once(self.manager.outbound()) else {
+            return None;
+        };
+        // The PDU starts with the L2CAP header.
+        match unwrap!(Att::decode(&pdu.as_ref()[4..])) {
+            Att::Server(AttServer::Unsolicited(
+                AttUns::Notify { handle, data } | AttUns::Indicate { handle, data },
+            )) => Some((handle, unwrap!(Vec::from_slice(data)))),
+            att => panic!("unexpected PDU {:?}", att),
+        }
+    }
+
+    /// Wait for the next indication sent by the server and confirm it, returning its handle and
+    /// value.
+    pub async fn confirm_next<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+    ) -> (u16, Vec<u8, MAX_VALUE_LEN>) {
+        loop {
+            if let Some(indicated) = self.notified() {
+                self.confirm(server);
+                return indicated;
+            }
+            yield_now().await;
+        }
+    }
+
+    /// Confirm the indication sent by the server.
+    pub fn confirm<M: RawMutex, const MAX: usize>(&self, server: &AttributeServer<'_, M, MAX>) {
+        let mut rx = [0; 251];
+        let cfm = AttClient::Confirmation(AttCfm::ConfirmIndication);
+        assert!(matches!(self.request(server, &cfm, &mut rx), Ok(None)));
+    }
+
+    // Process a PDU and decode the response of the server, returning its error code if it failed.
+    fn request<'a, M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        pdu: &AttClient<'_>,
+        rx: &'a mut [u8],
+    ) -> Result<Option<AttRsp<'a>>, AttErrorCode> {
+        let Some(len) = unwrap!(server.process(&self.connection, pdu, rx)) else {
+            return Ok(None);
+        };
+        match unwrap!(Att::decode(&rx[..len])) {
+            Att::Server(AttServer::Response(AttRsp::Error { code, .. })) => Err(code),
+            Att::Server(AttServer::Response(rsp)) => Ok(Some(rsp)),
+            att => panic!("unexpected PDU {:?}", att),
+        }
+    }
+}
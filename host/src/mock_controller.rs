@@ -1,7 +1,8 @@
+use core::cell::Cell;
 use core::convert::Infallible;
-use core::future::Future;
 
-use bt_hci::cmd::{self, AsyncCmd, SyncCmd};
+use bt_hci::FromHciBytes;
+use bt_hci::cmd::{self, AsyncCmd, Opcode, SyncCmd};
 use bt_hci::controller::{ControllerCmdAsync, ControllerCmdSync};
 
 pub struct MockController {}
@@ -63,34 +64,105 @@ impl bt_hci::controller::blocking::Controller for MockController {
 }
 
 impl bt_hci::controller::Controller for MockController {
-    fn write_acl_data(&self, packet: &bt_hci::data::AclPacket) -> impl Future<Output = Result<(), Self::Error>> {
-        async { todo!() }
+    async fn write_acl_data(&self, packet: &bt_hci::data::AclPacket<'_>) -> Result<(), Self::Error> {
+        todo!()
     }
 
-    fn write_sync_data(&self, packet: &bt_hci::data::SyncPacket) -> impl Future<Output = Result<(), Self::Error>> {
-        async { todo!() }
+    async fn write_sync_data(&self, packet: &bt_hci::data::SyncPacket<'_>) -> Result<(), Self::Error> {
+        todo!()
     }
 
-    fn write_iso_data(&self, packet: &bt_hci::data::IsoPacket) -> impl Future<Output = Result<(), Self::Error>> {
-        async { todo!() }
+    async fn write_iso_data(&self, packet: &bt_hci::data::IsoPacket<'_>) -> Result<(), Self::Error> {
+        todo!()
     }
 
-    fn read<'a>(
-        &self,
-        buf: &'a mut [u8],
-    ) -> impl Future<Output = Result<bt_hci::ControllerToHostPacket<'a>, Self::Error>> {
-        async { todo!() }
+    async fn read<'a>(&self, buf: &'a mut [u8]) -> Result<bt_hci::ControllerToHostPacket<'a>, Self::Error> {
+        todo!()
     }
 }
 
 impl<C: SyncCmd> ControllerCmdSync<C> for MockController {
-    fn exec(&self, cmd: &C) -> impl Future<Output = Result<C::Return, cmd::Error<Self::Error>>> {
-        async { todo!() }
+    async fn exec(&self, cmd: &C) -> Result<C::Return, cmd::Error<Self::Error>> {
+        todo!()
     }
 }
 
 impl<C: AsyncCmd> ControllerCmdAsync<C> for MockController {
-    fn exec(&self, cmd: &C) -> impl Future<Output = Result<(), cmd::Error<Self::Error>>> {
-        async { todo!() }
+    async fn exec(&self, cmd: &C) -> Result<(), cmd::Error<Self::Error>> {
+        todo!()
+    }
+}
+
+/// A controller completing every command with zeroed return parameters, except for one command
+/// that never completes, to mimic an unresponsive controller.
+pub struct ZeroController {
+    hang: Cell<Option<Opcode>>,
+    counted: Cell<Option<Opcode>>,
+    count: Cell<u32>,
+}
+
+impl ZeroController {
+    pub fn new() -> Self {
+        Self {
+            hang: Cell::new(None),
+            counted: Cell::new(None),
+            count: Cell::new(0),
+        }
+    }
+
+    /// Stop completing the command with `opcode`, or complete every command again with `None`.
+    pub fn hang(&self, opcode: Option<Opcode>) {
+        self.hang.set(opcode);
+    }
+
+    /// Start counting the completions of the command with `opcode`.
+    pub fn count(&self, opcode: Opcode) {
+        self.counted.set(Some(opcode));
+        self.count.set(0);
+    }
+
+    /// The number of completions of the counted command.
+    pub fn counted(&self) -> u32 {
+        self.count.get()
+    }
+}
+
+impl embedded_io::ErrorType for ZeroController {
+    type Error = Infallible;
+}
+
+impl bt_hci::controller::Controller for ZeroController {
+    async fn write_acl_data(&self, packet: &bt_hci::data::AclPacket<'_>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn write_sync_data(&self, packet: &bt_hci::data::SyncPacket<'_>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn write_iso_data(&self, packet: &bt_hci::data::IsoPacket<'_>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn read<'a>(&self, buf: &'a mut [u8]) -> Result<bt_hci::ControllerToHostPacket<'a>, Self::Error> {
+        core::future::pending().await
+    }
+}
+
+impl<C: SyncCmd> ControllerCmdSync<C> for ZeroController {
+    async fn exec(&self, cmd: &C) -> Result<C::Return, cmd::Error<Self::Error>> {
+        if self.hang.get() == Some(C::OPCODE) {
+            core::future::pending::<()>().await;
+        }
+        if self.counted.get() == Some(C::OPCODE) {
+            self.count.set(self.count.get() + 1);
+        }
+        Ok(C::Return::from_hci_bytes(&[0; 255]).unwrap().0)
+    }
+}
+
+impl<C: AsyncCmd> ControllerCmdAsync<C> for ZeroController {
+    async fn exec(&self, cmd: &C) -> Result<(), cmd::Error<Self::Error>> {
+        Ok(())
     }
 }
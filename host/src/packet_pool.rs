@@ -20,30 +20,56 @@ impl<const MTU: usize> PacketBuf<MTU> {
     }
 }
 
+/// Usage of a packet pool.
+#[cfg(feature = "traffic-metrics")]
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PoolMetrics {
+    /// Number of packets allocated.
+    pub in_use: usize,
+    /// Highest number of packets allocated at once.
+    pub high_water_mark: usize,
+    /// How many allocations failed with all packets allocated.
+    pub exhausted: u32,
+}
+
 struct State<const MTU: usize, const N: usize> {
     packets: [PacketBuf<MTU>; N],
+    #[cfg(feature = "traffic-metrics")]
+    high_water_mark: usize,
+    #[cfg(feature = "traffic-metrics")]
+    exhausted: u32,
 }
 
 impl<const MTU: usize, const N: usize> State<MTU, N> {
     pub(crate) const fn new() -> Self {
         Self {
             packets: [PacketBuf::NEW; N],
+            #[cfg(feature = "traffic-metrics")]
+            high_water_mark: 0,
+            #[cfg(feature = "traffic-metrics")]
+            exhausted: 0,
         }
     }
 
     fn alloc(&mut self) -> Option<PacketRef> {
-        for (idx, packet) in self.packets.iter_mut().enumerate() {
-            if packet.free {
-                // info!("[{}] alloc {}", id.0, idx);
-                packet.free = false;
-                packet.buf.iter_mut().for_each(|b| *b = 0);
-                return Some(PacketRef {
-                    idx,
-                    buf: &mut packet.buf[..],
-                });
-            }
+        let idx = self.packets.iter().position(|p| p.free);
+        #[cfg(feature = "traffic-metrics")]
+        if idx.is_none() {
+            self.exhausted = self.exhausted.wrapping_add(1);
+        }
+        let idx = idx?;
+        // info!("[{}] alloc {}", id.0, idx);
+        let packet = &mut self.packets[idx];
+        packet.free = false;
+        packet.buf.iter_mut().for_each(|b| *b = 0);
+        let buf: *mut [u8] = &mut packet.buf[..];
+        #[cfg(feature = "traffic-metrics")]
+        {
+            let in_use = N - self.available();
+            self.high_water_mark = self.high_water_mark.max(in_use);
         }
-        None
+        Some(PacketRef { idx, buf })
     }
 
     fn free(&mut self, p_ref: PacketRef) {
@@ -54,6 +80,15 @@ impl<const MTU: usize, const N: usize> State<MTU, N> {
     fn available(&mut self) -> usize {
         self.packets.iter().filter(|p| p.free).count()
     }
+
+    #[cfg(feature = "traffic-metrics")]
+    fn metrics(&mut self) -> PoolMetrics {
+        PoolMetrics {
+            in_use: N - self.available(),
+            high_water_mark: self.high_water_mark,
+            exhausted: self.exhausted,
+        }
+    }
 }
 
 /// A packet pool holds a pool of packet buffers that can be dynamically allocated
@@ -99,6 +134,11 @@ impl<const MTU: usize, const N: usize> PacketPool<MTU, N> {
             state.available()
         })
     }
+
+    #[cfg(feature = "traffic-metrics")]
+    fn metrics(&self) -> PoolMetrics {
+        self.state.lock(|state| state.borrow_mut().metrics())
+    }
 }
 
 /// Type erased packet pool
@@ -113,6 +153,9 @@ pub(crate) trait Pool {
     fn available(&self) -> usize;
     /// Check packet size.
     fn mtu(&self) -> usize;
+    /// Check the usage of the pool.
+    #[cfg(feature = "traffic-metrics")]
+    fn metrics(&self) -> PoolMetrics;
 }
 
 impl<const MTU: usize, const N: usize> Pool for PacketPool<MTU, N> {
@@ -131,6 +174,11 @@ impl<const MTU: usize, const N: usize> Pool for PacketPool<MTU, N> {
     fn mtu(&self) -> usize {
         MTU
     }
+
+    #[cfg(feature = "traffic-metrics")]
+    fn metrics(&self) -> PoolMetrics {
+        PacketPool::metrics(self)
+    }
 }
 
 #[repr(C)]
@@ -210,4 +258,23 @@ mod tests {
         let b2 = pool.alloc();
         assert!(b2.is_none());
     }
+
+    #[cfg(feature = "traffic-metrics")]
+    #[test]
+    fn pool_metrics() {
+        static POOL: StaticCell<PacketPool<1, 2>> = StaticCell::new();
+        let pool = POOL.init(PacketPool::new());
+
+        let a1 = pool.alloc();
+        let a2 = pool.alloc();
+        assert!(pool.alloc().is_none());
+        drop(a1);
+        drop(a2);
+        let a3 = pool.alloc();
+
+        let metrics = pool.metrics();
+        assert_eq!(metrics.in_use, 1);
+        assert_eq!(metrics.high_water_mark, 2);
+        assert_eq!(metrics.exhausted, 1);
+    }
 }
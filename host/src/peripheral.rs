@@ -1,28 +1,53 @@
 //! Functionality for the BLE peripheral role.
+use core::future::{Future, pending, poll_fn};
+
 use bt_hci::cmd::le::{
-    LeClearAdvSets, LeReadNumberOfSupportedAdvSets, LeSetAdvData, LeSetAdvEnable, LeSetAdvParams,
+    LeAddDeviceToResolvingList, LeClearAdvSets, LeClearResolvingList, LeConnUpdate, LePeriodicAdvSetInfoTransfer,
+    LeReadNumberOfSupportedAdvSets, LeSetAddrResolutionEnable, LeSetAdvData, LeSetAdvEnable, LeSetAdvParams,
     LeSetAdvSetRandomAddr, LeSetExtAdvData, LeSetExtAdvEnable, LeSetExtAdvParams, LeSetExtScanResponseData,
+    LeSetPeriodicAdvData, LeSetPeriodicAdvEnable, LeSetPeriodicAdvParams, LeSetResolvablePrivateAddrTimeout,
     LeSetScanResponseData,
 };
-use bt_hci::controller::{Controller, ControllerCmdSync};
-use bt_hci::param::{AddrKind, AdvChannelMap, AdvHandle, AdvKind, AdvSet, BdAddr, LeConnRole, Operation};
-use embassy_futures::select::{Either, select};
+use bt_hci::controller::{Controller, ControllerCmdAsync, ControllerCmdSync};
+use bt_hci::param::{
+    AddrKind, AdvChannelMap, AdvHandle, AdvKind, AdvSet, BdAddr, LeConnRole, Operation, PeriodicAdvProps, PhyOptions,
+};
+use embassy_futures::select::{Either3, select, select3};
+use embassy_time::{Instant, Timer};
 
-use crate::advertise::{Advertisement, AdvertisementParameters, AdvertisementSet, RawAdvertisement};
-use crate::connection::Connection;
+use crate::advertise::{
+    Advertisement, AdvertisementDataError, AdvertisementParameters, AdvertisementSet, PawrParameters, PawrSubevent,
+    PeriodicAdvertisementParameters, RawAdvertisement,
+};
+use crate::connection::{Connection, PreferredConnectionParameters};
+use crate::hci::{LeSetExtAdvParamsV2, LeSetPeriodicAdvParamsV2, LeSetPeriodicAdvSubeventData, LengthPrefixed};
 use crate::{Address, BleHostError, Error, Stack};
 
 /// Type which implements the BLE peripheral role.
 pub struct Peripheral<'d, C> {
-    stack: &'d Stack<'d, C>,
+    pub(crate) stack: &'d Stack<'d, C>,
+    preferred_params: Option<PreferredConnectionParameters>,
 }
 
 impl<'d, C: Controller> Peripheral<'d, C> {
     pub(crate) fn new(stack: &'d Stack<'d, C>) -> Self {
-        Self { stack }
+        Self {
+            stack,
+            preferred_params: None,
+        }
+    }
+
+    /// Set the connection parameters to request automatically after each connection served by
+    /// [`Peripheral::advertise_and_serve`], usually those of the Peripheral Preferred Connection
+    /// Parameters characteristic.
+    pub fn set_preferred_connection_params(&mut self, preferred: Option<PreferredConnectionParameters>) {
+        self.preferred_params = preferred;
     }
 
     /// Start advertising with the provided parameters and return a handle to accept connections.
+    ///
+    /// Legacy advertising has no limit on the number of advertising events, so
+    /// [`Error::InvalidValue`] is returned when `params.max_events` is set.
     pub async fn advertise<'k>(
         &mut self,
         params: &AdvertisementParameters,
@@ -34,6 +59,9 @@ impl<'d, C: Controller> Peripheral<'d, C> {
             + for<'t> ControllerCmdSync<LeSetAdvEnable>
             + for<'t> ControllerCmdSync<LeSetScanResponseData>,
     {
+        if params.max_events.is_some() {
+            return Err(Error::InvalidValue.into());
+        }
         let host = &self.stack.host;
 
         // Ensure no other advertise ongoing.
@@ -52,6 +80,7 @@ impl<'d, C: Controller> Peripheral<'d, C> {
 
         let kind = match (data.props.connectable_adv(), data.props.scannable_adv()) {
             (true, true) => AdvKind::AdvInd,
+            (true, false) if data.props.high_duty_cycle_directed_connectable_adv() => AdvKind::AdvDirectIndHigh,
             (true, false) => AdvKind::AdvDirectIndLow,
             (false, true) => AdvKind::AdvScanInd,
             (false, false) => AdvKind::AdvNonconnInd,
@@ -65,7 +94,7 @@ impl<'d, C: Controller> Peripheral<'d, C> {
             params.interval_min.into(),
             params.interval_max.into(),
             kind,
-            host.address.map(|a| a.kind).unwrap_or(AddrKind::PUBLIC),
+            host.own_adv_addr_kind(),
             peer.kind,
             peer.addr,
             params.channel_map.unwrap_or(AdvChannelMap::ALL),
@@ -94,21 +123,107 @@ impl<'d, C: Controller> Peripheral<'d, C> {
         }];
 
         trace!("[host] enabling advertising");
-        host.advertise_state.start(&advset[..]);
+        host.advertise_state.start(&advset[..], true);
+        // Forget the set again if advertising could not be enabled.
+        let started = crate::host::OnDrop::new(|| host.advertise_state.reset());
         host.command(LeSetAdvEnable::new(true)).await?;
+        started.defuse();
         drop.defuse();
         Ok(Advertiser {
             stack: self.stack,
             extended: false,
+            deadline: params.timeout.map(|t| Instant::now() + t),
             done: false,
         })
     }
 
+    /// Advertise and hand each connection to `handler`, resuming advertising with the same
+    /// parameters whenever the handler returns.
+    ///
+    /// The handler is expected to run until the connection is closed, which is usually done
+    /// by processing connection events until `ConnectionEvent::Disconnected` is received. The
+    /// connection is disconnected when the handler returns, if it is still alive. The preferred
+    /// connection parameters set are requested meanwhile.
+    ///
+    /// Returns an error if advertising could not be started or stopped without a connection,
+    /// for instance because the advertising timeout was reached.
+    pub async fn advertise_and_serve<'k, F, Fut>(
+        &mut self,
+        params: &AdvertisementParameters,
+        data: Advertisement<'k>,
+        mut handler: F,
+    ) -> Result<(), BleHostError<C::Error>>
+    where
+        F: FnMut(Connection<'d>) -> Fut,
+        Fut: Future<Output = ()>,
+        C: for<'t> ControllerCmdSync<LeSetAdvData>
+            + ControllerCmdSync<LeSetAdvParams>
+            + for<'t> ControllerCmdSync<LeSetAdvEnable>
+            + for<'t> ControllerCmdSync<LeSetScanResponseData>
+            + ControllerCmdAsync<LeConnUpdate>,
+    {
+        loop {
+            let advertiser = self.advertise(params, data).await?;
+            let conn = advertiser.accept().await?;
+            trace!("[host] serving connection {:?}", conn.handle());
+            match self.preferred_params {
+                Some(preferred) => {
+                    let request = async {
+                        if conn.request_preferred_params(self.stack, &preferred).await.is_err() {
+                            warn!("[host] preferred connection parameters not applied");
+                        }
+                        pending::<()>().await
+                    };
+                    select(handler(conn.clone()), request).await;
+                }
+                None => handler(conn).await,
+            }
+            trace!("[host] connection served, restarting advertising");
+        }
+    }
+
+    /// Start advertising using extended advertising commands if the controller supports them,
+    /// falling back to legacy advertising otherwise.
+    ///
+    /// This allows the same application to run against both older and newer controllers. Note
+    /// that extended advertisement kinds and `params.max_events` can only be used when the
+    /// controller supports extended advertising, and [`Error::InvalidValue`] is returned otherwise.
+    pub async fn advertise_auto<'k>(
+        &mut self,
+        params: &AdvertisementParameters,
+        data: Advertisement<'k>,
+    ) -> Result<Advertiser<'d, C>, BleHostError<C::Error>>
+    where
+        C: for<'t> ControllerCmdSync<LeSetAdvData>
+            + ControllerCmdSync<LeSetAdvParams>
+            + for<'t> ControllerCmdSync<LeSetAdvEnable>
+            + for<'t> ControllerCmdSync<LeSetScanResponseData>
+            + for<'t> ControllerCmdSync<LeSetExtAdvData<'t>>
+            + ControllerCmdSync<LeClearAdvSets>
+            + ControllerCmdSync<LeSetExtAdvParams>
+            + ControllerCmdSync<LeSetExtAdvParamsV2>
+            + ControllerCmdSync<LeSetAdvSetRandomAddr>
+            + ControllerCmdSync<LeReadNumberOfSupportedAdvSets>
+            + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
+            + for<'t> ControllerCmdSync<LeSetExtScanResponseData<'t>>,
+    {
+        if self.stack.host.le_features().await.supports_le_ext_adv() {
+            let sets = [AdvertisementSet { params: *params, data }];
+            let mut handles = AdvertisementSet::handles(&sets);
+            self.advertise_ext(&sets, &mut handles).await
+        } else {
+            self.advertise(params, data).await
+        }
+    }
+
     /// Starts sending BLE advertisements according to the provided config.
     ///
     /// The handles are required to provide the storage while advertising, and
     /// can be created by calling AdvertisementSet::handles(sets).
     ///
+    /// Each set uses its index as advertising SID, so at most 16 sets can be advertised, and
+    /// [`Error::InvalidValue`] is returned otherwise.
+    ///
     /// Advertisements are stopped when a connection is made against this host,
     /// in which case a handle for the connection is returned.
     ///
@@ -122,6 +237,7 @@ impl<'d, C: Controller> Peripheral<'d, C> {
         C: for<'t> ControllerCmdSync<LeSetExtAdvData<'t>>
             + ControllerCmdSync<LeClearAdvSets>
             + ControllerCmdSync<LeSetExtAdvParams>
+            + ControllerCmdSync<LeSetExtAdvParamsV2>
             + ControllerCmdSync<LeSetAdvSetRandomAddr>
             + ControllerCmdSync<LeReadNumberOfSupportedAdvSets>
             + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
@@ -137,6 +253,16 @@ impl<'d, C: Controller> Peripheral<'d, C> {
             }
         }
 
+        // Each set is identified by its index as its 4-bit advertising SID.
+        if sets.len() > MAX_ADV_SID as usize + 1 {
+            return Err(Error::InvalidValue.into());
+        }
+        for set in sets.iter() {
+            let data: RawAdvertisement<'k> = set.data.into();
+            data.validate()?;
+            set.params.validate_phys(data.props)?;
+        }
+
         // Ensure no other advertise ongoing.
         let drop = crate::host::OnDrop::new(|| {
             host.advertise_command_state.cancel(true);
@@ -146,6 +272,19 @@ impl<'d, C: Controller> Peripheral<'d, C> {
         // Clear current advertising terminations
         host.advertise_state.reset();
 
+        for (i, set) in sets.iter().enumerate() {
+            handles[i].adv_handle = AdvHandle::new(i as u8);
+            handles[i].duration = set
+                .params
+                .timeout
+                .unwrap_or(embassy_time::Duration::from_micros(0))
+                .into();
+            handles[i].max_ext_adv_events = set.params.max_events.unwrap_or(0);
+        }
+        host.advertise_state.start(handles, false);
+        // Forget the sets again if any of them could not be configured and enabled.
+        let started = crate::host::OnDrop::new(|| host.advertise_state.reset());
+
         for (i, set) in sets.iter().enumerate() {
             let handle = AdvHandle::new(i as u8);
             let data: RawAdvertisement<'k> = set.data.into();
@@ -154,24 +293,50 @@ impl<'d, C: Controller> Peripheral<'d, C> {
                 kind: AddrKind::PUBLIC,
                 addr: BdAddr::default(),
             });
-            host.command(LeSetExtAdvParams::new(
-                handle,
-                data.props,
-                params.interval_min.into(),
-                params.interval_max.into(),
-                params.channel_map.unwrap_or(AdvChannelMap::ALL),
-                host.address.map(|a| a.kind).unwrap_or(AddrKind::PUBLIC),
-                peer.kind,
-                peer.addr,
-                params.filter_policy,
-                params.tx_power as i8,
-                params.primary_phy,
-                0,
-                params.secondary_phy,
-                0,
-                false,
-            ))
-            .await?;
+            let tx_power = if params.coded_phy_options == PhyOptions::NoPreferredCoding {
+                host.command(LeSetExtAdvParams::new(
+                    handle,
+                    data.props,
+                    params.interval_min.into(),
+                    params.interval_max.into(),
+                    params.channel_map.unwrap_or(AdvChannelMap::ALL),
+                    host.own_adv_addr_kind(),
+                    peer.kind,
+                    peer.addr,
+                    params.filter_policy,
+                    params.tx_power as i8,
+                    params.primary_phy,
+                    0,
+                    params.secondary_phy,
+                    i as u8,
+                    params.scan_request_notification,
+                ))
+                .await?
+            } else {
+                // Only the PHYs that are coded use the options, so they can be sent for both.
+                let options = params.coded_phy_options as u16 as u8;
+                host.command(LeSetExtAdvParamsV2::new(
+                    handle,
+                    data.props,
+                    params.interval_min.into(),
+                    params.interval_max.into(),
+                    params.channel_map.unwrap_or(AdvChannelMap::ALL),
+                    host.own_adv_addr_kind(),
+                    peer.kind,
+                    peer.addr,
+                    params.filter_policy,
+                    params.tx_power as i8,
+                    params.primary_phy,
+                    0,
+                    params.secondary_phy,
+                    i as u8,
+                    params.scan_request_notification,
+                    options,
+                    options,
+                ))
+                .await?
+            };
+            host.advertise_state.set_tx_power(handle, tx_power);
 
             if let Some(address) = host.address.as_ref() {
                 host.command(LeSetAdvSetRandomAddr::new(handle, address.addr)).await?;
@@ -196,50 +361,409 @@ impl<'d, C: Controller> Peripheral<'d, C> {
                 ))
                 .await?;
             }
-            handles[i].adv_handle = handle;
-            handles[i].duration = set
-                .params
-                .timeout
-                .unwrap_or(embassy_time::Duration::from_micros(0))
-                .into();
-            handles[i].max_ext_adv_events = set.params.max_events.unwrap_or(0);
         }
 
         trace!("[host] enabling extended advertising");
-        host.advertise_state.start(handles);
         host.command(LeSetExtAdvEnable::new(true, handles)).await?;
+        started.defuse();
         drop.defuse();
         Ok(Advertiser {
             stack: self.stack,
             extended: true,
+            deadline: None,
             done: false,
         })
     }
+
+    /// Enable controller based privacy for advertising.
+    ///
+    /// The local identity resolving key is installed in the controller resolving list, and
+    /// subsequent undirected advertisements use a resolvable private address generated from it.
+    /// The controller rotates the address every `rpa_timeout`, which it does without stopping
+    /// running advertisement sets. Directed advertising keeps using the identity address.
+    ///
+    /// Bonded peers are added to the resolving list too, so that their resolvable private
+    /// addresses are resolved. Peers bonded afterwards are added by calling this again. The
+    /// resolving list is programmed again when the host resets an unresponsive controller.
+    ///
+    /// Must be called while not advertising, scanning or connecting.
+    pub async fn enable_privacy(
+        &mut self,
+        local_irk: [u8; 16],
+        rpa_timeout: embassy_time::Duration,
+    ) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeSetAddrResolutionEnable>
+            + ControllerCmdSync<LeClearResolvingList>
+            + ControllerCmdSync<LeAddDeviceToResolvingList>
+            + ControllerCmdSync<LeSetResolvablePrivateAddrTimeout>,
+    {
+        if !(1..=RPA_TIMEOUT_MAX_SECS).contains(&rpa_timeout.as_secs()) {
+            return Err(Error::InvalidValue.into());
+        }
+        let host = &self.stack.host;
+        host.advertise_command_state.request().await;
+        let _drop = crate::host::OnDrop::new(|| {
+            host.advertise_command_state.done();
+        });
+
+        host.enable_resolving_list(crate::host::Privacy { local_irk, rpa_timeout })
+            .await?;
+        // Distributed to peers when pairing, so that they resolve the private addresses.
+        #[cfg(feature = "security")]
+        host.security
+            .set_local_irk(Some(crate::security_manager::Key::from_le_bytes(local_irk)));
+        Ok(())
+    }
+
+    /// Disable privacy, advertising with the identity address again.
+    ///
+    /// Must be called while not advertising, scanning or connecting.
+    pub async fn disable_privacy(&mut self) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeSetAddrResolutionEnable> + ControllerCmdSync<LeClearResolvingList>,
+    {
+        let host = &self.stack.host;
+        host.advertise_command_state.request().await;
+        let _drop = crate::host::OnDrop::new(|| {
+            host.advertise_command_state.done();
+        });
+
+        host.privacy.set(None);
+        #[cfg(feature = "security")]
+        host.security.set_local_irk(None);
+        host.command(LeSetAddrResolutionEnable::new(false)).await?;
+        host.command(LeClearResolvingList::new()).await?;
+        Ok(())
+    }
 }
 
+/// Largest resolvable private address timeout accepted by the controller, in seconds.
+const RPA_TIMEOUT_MAX_SECS: u64 = 0xa1b8;
+
+/// Largest advertising SID, identifying an extended advertising set to scanners.
+const MAX_ADV_SID: u8 = 0x0f;
+
 /// Handle to an active advertiser which can accept connections.
 pub struct Advertiser<'d, C: Controller> {
     stack: &'d Stack<'d, C>,
     extended: bool,
+    deadline: Option<Instant>,
     done: bool,
 }
 
 impl<'d, C: Controller> Advertiser<'d, C> {
     /// Accept the next peripheral connection for this advertiser.
     ///
-    /// Returns Error::Timeout if advertiser stopped.
+    /// Returns Error::Timeout if advertiser stopped, either because the advertising timeout or
+    /// maximum number of advertising events was reached, or because the controller stopped it.
     pub async fn accept(mut self) -> Result<Connection<'d>, Error> {
-        let result = match select(
+        let deadline = async {
+            match self.deadline {
+                Some(deadline) => Timer::at(deadline).await,
+                None => pending().await,
+            }
+        };
+        match select3(
             self.stack.host.connections.accept(LeConnRole::Peripheral, &[]),
             self.stack.host.advertise_state.wait(),
+            deadline,
         )
         .await
         {
-            Either::First(conn) => Ok(conn),
-            Either::Second(_) => Err(Error::Timeout),
+            Either3::First(conn) => {
+                self.done = true;
+                Ok(conn)
+            }
+            Either3::Second(_) => {
+                self.done = true;
+                Err(Error::Timeout)
+            }
+            // Legacy advertising has no controller timeout, leave it to drop to stop advertising.
+            Either3::Third(_) => Err(Error::Timeout),
+        }
+    }
+
+    /// Wait for the next advertising set to stop advertising.
+    ///
+    /// A set stops when its timeout or maximum number of advertising events is reached, or
+    /// when it results in a connection, which can then be retrieved using `accept`. The
+    /// remaining sets keep advertising.
+    ///
+    /// Returns the handle of the terminated set, or `None` if no sets are advertising. Legacy
+    /// advertising started with `advertise` is reported as set 0, terminated when a connection
+    /// is established, as it has no controller timeout nor event limit.
+    pub async fn terminated(&mut self) -> Option<AdvHandle> {
+        poll_fn(|cx| self.stack.host.advertise_state.poll_terminated(cx)).await
+    }
+
+    /// Transmit power selected by the controller for an extended advertisement set, in dBm.
+    ///
+    /// The controller picks a power level at or below the one requested in the advertisement
+    /// parameters, which can be embedded in the TX Power Level AD structure using `set_ext_data`.
+    /// Returns `None` if the set is not advertising.
+    pub fn tx_power(&self, handle: AdvHandle) -> Option<i8> {
+        self.stack.host.advertise_state.tx_power(handle)
+    }
+
+    /// Update the advertising data while advertising, without stopping the advertiser.
+    ///
+    /// Only valid for advertisers started with `advertise`.
+    pub async fn set_data(&mut self, data: &[u8]) -> Result<(), BleHostError<C::Error>>
+    where
+        C: for<'t> ControllerCmdSync<LeSetAdvData>,
+    {
+        let buf = self.legacy_data(data)?;
+        self.stack
+            .host
+            .command(LeSetAdvData::new(data.len() as u8, buf))
+            .await?;
+        Ok(())
+    }
+
+    /// Update the scan response data while advertising, without stopping the advertiser.
+    ///
+    /// Only valid for advertisers started with `advertise`.
+    pub async fn set_scan_response(&mut self, data: &[u8]) -> Result<(), BleHostError<C::Error>>
+    where
+        C: for<'t> ControllerCmdSync<LeSetScanResponseData>,
+    {
+        let buf = self.legacy_data(data)?;
+        self.stack
+            .host
+            .command(LeSetScanResponseData::new(data.len() as u8, buf))
+            .await?;
+        Ok(())
+    }
+
+    fn legacy_data(&self, data: &[u8]) -> Result<[u8; 31], Error> {
+        if self.extended {
+            return Err(Error::InvalidState);
+        }
+        let mut buf = [0; 31];
+        if data.len() > buf.len() {
+            return Err(AdvertisementDataError::TooLong.into());
+        }
+        buf[..data.len()].copy_from_slice(data);
+        Ok(buf)
+    }
+
+    /// Update the advertising data of an extended advertisement set while advertising.
+    ///
+    /// The data must fit in a single advertising PDU, as the controller does not accept
+    /// fragmented updates on an enabled set.
+    pub async fn set_ext_data(&mut self, handle: AdvHandle, data: &[u8]) -> Result<(), BleHostError<C::Error>>
+    where
+        C: for<'t> ControllerCmdSync<LeSetExtAdvData<'t>>,
+    {
+        self.check_ext_data(data)?;
+        self.stack
+            .host
+            .command(LeSetExtAdvData::new(handle, Operation::Complete, false, data))
+            .await?;
+        Ok(())
+    }
+
+    /// Update the scan response data of an extended advertisement set while advertising.
+    ///
+    /// The data must fit in a single advertising PDU, as the controller does not accept
+    /// fragmented updates on an enabled set.
+    pub async fn set_ext_scan_response(&mut self, handle: AdvHandle, data: &[u8]) -> Result<(), BleHostError<C::Error>>
+    where
+        C: for<'t> ControllerCmdSync<LeSetExtScanResponseData<'t>>,
+    {
+        self.check_ext_data(data)?;
+        self.stack
+            .host
+            .command(LeSetExtScanResponseData::new(handle, Operation::Complete, false, data))
+            .await?;
+        Ok(())
+    }
+
+    fn check_ext_data(&self, data: &[u8]) -> Result<(), Error> {
+        // Maximum data length of a single HCI command
+        const MAX_UPDATE_LEN: usize = 251;
+        if !self.extended {
+            Err(Error::InvalidState)
+        } else if data.len() > MAX_UPDATE_LEN {
+            Err(AdvertisementDataError::TooLong.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Start periodic advertising on an extended advertisement set.
+    ///
+    /// The set must be non-connectable and non-scannable. Periodic advertising is not
+    /// stopped by the controller when the extended advertising stops, `stop_periodic` must
+    /// be called explicitly.
+    pub async fn start_periodic(
+        &mut self,
+        handle: AdvHandle,
+        params: &PeriodicAdvertisementParameters,
+        data: &[u8],
+    ) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeSetPeriodicAdvParams>
+            + for<'t> ControllerCmdSync<LeSetPeriodicAdvData<'t>>
+            + ControllerCmdSync<LeSetPeriodicAdvEnable>,
+    {
+        if !self.extended {
+            return Err(Error::InvalidState.into());
+        }
+        let host = &self.stack.host;
+        host.command(LeSetPeriodicAdvParams::new(
+            handle,
+            params.interval_min.into(),
+            params.interval_max.into(),
+            PeriodicAdvProps::new().include_tx_power(params.include_tx_power),
+        ))
+        .await?;
+        self.set_periodic_data(handle, data).await?;
+        host.command(LeSetPeriodicAdvEnable::new(true, handle)).await?;
+        Ok(())
+    }
+
+    /// Update the periodic advertising data of an advertisement set.
+    ///
+    /// Data longer than a single HCI command is split into fragments.
+    pub async fn set_periodic_data(&mut self, handle: AdvHandle, data: &[u8]) -> Result<(), BleHostError<C::Error>>
+    where
+        C: for<'t> ControllerCmdSync<LeSetPeriodicAdvData<'t>>,
+    {
+        const MAX_FRAGMENT_LEN: usize = 252;
+        let host = &self.stack.host;
+        if data.len() <= MAX_FRAGMENT_LEN {
+            host.command(LeSetPeriodicAdvData::new(handle, Operation::Complete, data))
+                .await?;
+            return Ok(());
+        }
+        let mut fragments = data.chunks(MAX_FRAGMENT_LEN).peekable();
+        let mut operation = Operation::FirstFragment;
+        while let Some(fragment) = fragments.next() {
+            if fragments.peek().is_none() {
+                operation = Operation::LastFragment;
+            }
+            host.command(LeSetPeriodicAdvData::new(handle, operation, fragment))
+                .await?;
+            operation = Operation::IntermediateFragment;
+        }
+        Ok(())
+    }
+
+    /// Start periodic advertising with responses on an extended advertisement set.
+    ///
+    /// The data of the subevents is set with `set_subevent_data`, and only sent once set. The
+    /// controller requests it ahead of the subevents through
+    /// [`EventHandler::on_subevent_data_request`](crate::prelude::EventHandler::on_subevent_data_request),
+    /// and reports the responses through
+    /// [`EventHandler::on_response_report`](crate::prelude::EventHandler::on_response_report). As
+    /// with `start_periodic`, `stop_periodic` must be called explicitly.
+    ///
+    /// Parameters outside of the ranges documented on [`PawrParameters`] are rejected with
+    /// [`Error::InvalidValue`].
+    pub async fn start_periodic_with_responses(
+        &mut self,
+        handle: AdvHandle,
+        params: &PawrParameters,
+    ) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeSetPeriodicAdvParamsV2> + ControllerCmdSync<LeSetPeriodicAdvEnable>,
+    {
+        if !self.extended {
+            return Err(Error::InvalidState.into());
+        }
+        if !(1..=128).contains(&params.num_subevents) || params.num_response_slots == 0 {
+            return Err(Error::InvalidValue.into());
+        }
+        let units = |duration: embassy_time::Duration, unit: u64, min: u64| {
+            let units = duration.as_micros() / unit;
+            match u8::try_from(units) {
+                Ok(units) if units as u64 >= min => Ok(units),
+                _ => Err(Error::InvalidValue),
+            }
         };
-        self.done = true;
-        result
+        let host = &self.stack.host;
+        host.command(LeSetPeriodicAdvParamsV2::new(
+            handle,
+            params.interval_min.into(),
+            params.interval_max.into(),
+            PeriodicAdvProps::new().include_tx_power(params.include_tx_power),
+            params.num_subevents,
+            units(params.subevent_interval, 1_250, 6)?,
+            units(params.response_slot_delay, 1_250, 1)?,
+            units(params.response_slot_spacing, 125, 2)?,
+            params.num_response_slots,
+        ))
+        .await?;
+        host.command(LeSetPeriodicAdvEnable::new(true, handle)).await?;
+        Ok(())
+    }
+
+    /// Set the data of subevents of periodic advertising with responses.
+    ///
+    /// The data is sent in the next occurrence of each subevent.
+    pub async fn set_subevent_data(
+        &mut self,
+        handle: AdvHandle,
+        subevents: &[PawrSubevent<'_>],
+    ) -> Result<(), BleHostError<C::Error>>
+    where
+        C: for<'t> ControllerCmdSync<LeSetPeriodicAdvSubeventData<'t>>,
+    {
+        const MAX_SUBEVENT_DATA_LEN: usize = 251;
+        if subevents.iter().any(|s| s.data.len() > MAX_SUBEVENT_DATA_LEN) {
+            return Err(AdvertisementDataError::TooLong.into());
+        }
+        let host = &self.stack.host;
+        for subevent in subevents {
+            host.command(LeSetPeriodicAdvSubeventData::new(
+                handle,
+                1,
+                subevent.subevent,
+                subevent.response_slot_start,
+                subevent.response_slot_count,
+                LengthPrefixed(subevent.data),
+            ))
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Stop periodic advertising on an advertisement set.
+    pub async fn stop_periodic(&mut self, handle: AdvHandle) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeSetPeriodicAdvEnable>,
+    {
+        self.stack
+            .host
+            .command(LeSetPeriodicAdvEnable::new(false, handle))
+            .await?;
+        Ok(())
+    }
+
+    /// Transfer the sync information of a periodic advertisement set to a connected peer.
+    ///
+    /// This allows the peer to synchronize to the periodic advertising without scanning
+    /// for it. The `service_data` value is passed on to the peer application.
+    pub async fn transfer_periodic_sync_info(
+        &self,
+        connection: &Connection<'_>,
+        handle: AdvHandle,
+        service_data: u16,
+    ) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LePeriodicAdvSetInfoTransfer>,
+    {
+        self.stack
+            .host
+            .command(LePeriodicAdvSetInfoTransfer::new(
+                connection.handle(),
+                service_data,
+                handle,
+            ))
+            .await?;
+        Ok(())
     }
 }
 
@@ -0,0 +1,96 @@
+//! Begin and end markers of the procedures run by the host.
+//!
+//! With the `procedure-tracing` feature, the host logs a marker at the trace level when a
+//! procedure begins and when it ends, with the connection it runs on and the time elapsed, so
+//! that the latency of a procedure can be measured from the logs. The markers are timestamped
+//! by defmt on embedded targets, and carry the time since boot with `log`.
+use bt_hci::param::ConnHandle;
+#[cfg(feature = "procedure-tracing")]
+use embassy_time::Instant;
+
+/// A procedure of the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Procedure {
+    /// Establishing a connection as central.
+    Connect,
+    /// Exchanging the ATT MTU, from the request to the response of the peer.
+    MtuExchange,
+    /// Discovering services or characteristics of the peer.
+    Discovery,
+    /// Setting up an L2CAP connection oriented channel.
+    ChannelSetup,
+    /// Pairing with the peer, from the pairing request to the distribution of the keys.
+    Pairing,
+}
+
+/// Log the beginning of a procedure that ends elsewhere, with [`end`].
+#[inline]
+pub(crate) fn begin(procedure: Procedure, conn: ConnHandle) {
+    #[cfg(feature = "procedure-tracing")]
+    trace!(
+        "[procedure] {:?} begin on conn {} at {} us",
+        procedure,
+        conn.raw(),
+        Instant::now().as_micros()
+    );
+}
+
+/// Log the end of a procedure started with [`begin`].
+#[inline]
+pub(crate) fn end(procedure: Procedure, conn: ConnHandle) {
+    #[cfg(feature = "procedure-tracing")]
+    trace!(
+        "[procedure] {:?} end on conn {} at {} us",
+        procedure,
+        conn.raw(),
+        Instant::now().as_micros()
+    );
+}
+
+/// A procedure running until the span is dropped, logging its beginning and end.
+pub(crate) struct Span {
+    #[cfg(feature = "procedure-tracing")]
+    procedure: Procedure,
+    #[cfg(feature = "procedure-tracing")]
+    conn: Option<ConnHandle>,
+    #[cfg(feature = "procedure-tracing")]
+    start: Instant,
+}
+
+impl Span {
+    /// Begin a procedure, on a connection if it is already established.
+    #[inline]
+    pub(crate) fn begin(procedure: Procedure, conn: Option<ConnHandle>) -> Self {
+        #[cfg(feature = "procedure-tracing")]
+        {
+            let start = Instant::now();
+            trace!(
+                "[procedure] {:?} begin on conn {:?} at {} us",
+                procedure,
+                conn.map(|c| c.raw()),
+                start.as_micros()
+            );
+            Self { procedure, conn, start }
+        }
+        #[cfg(not(feature = "procedure-tracing"))]
+        Self {}
+    }
+}
+
+impl Drop for Span {
+    #[inline]
+    fn drop(&mut self) {
+        #[cfg(feature = "procedure-tracing")]
+        {
+            let now = Instant::now();
+            trace!(
+                "[procedure] {:?} end on conn {:?} at {} us, after {} us",
+                self.procedure,
+                self.conn.map(|c| c.raw()),
+                now.as_micros(),
+                (now - self.start).as_micros()
+            );
+        }
+    }
+}
@@ -1,22 +1,48 @@
 //! Scan config.
+use core::cell::{Cell, RefCell};
+use core::future::{Future, poll_fn};
+use core::pin::Pin;
+use core::task::{Context, Poll, ready};
+
 use bt_hci::cmd::le::{
-    LeAddDeviceToFilterAcceptList, LeClearFilterAcceptList, LeSetExtScanEnable, LeSetExtScanParams, LeSetScanEnable,
-    LeSetScanParams,
+    LeAddDeviceToFilterAcceptList, LeClearFilterAcceptList, LePeriodicAdvCreateSync, LePeriodicAdvCreateSyncCancel,
+    LePeriodicAdvSyncTransfer, LePeriodicAdvTerminateSync, LeSetExtScanEnable, LeSetExtScanParams,
+    LeSetPeriodicAdvSyncTransferParams, LeSetScanEnable, LeSetScanParams,
+};
+use bt_hci::controller::{Controller, ControllerCmdAsync, ControllerCmdSync};
+pub use bt_hci::event::le::LePeriodicAdvertisingReport;
+use bt_hci::param::{
+    AddrKind, BdAddr, ConnHandle, CteMask, DataStatus, FilterDuplicates, LeAdvEventKind, LeExtAdvDataStatus,
+    LePeriodicAdvCreateSyncOptions, LePeriodicAdvSyncTransferMode, PeriodicAdvProps, ScanningPhy, Status,
 };
-use bt_hci::controller::{Controller, ControllerCmdSync};
-use bt_hci::param::{AddrKind, FilterDuplicates, ScanningPhy};
-pub use bt_hci::param::{LeAdvReportsIter, LeExtAdvReportsIter};
-use embassy_time::Instant;
+pub use bt_hci::param::{
+    LeAdvReport, LeAdvReportsIter, LeExtAdvEventKind, LeExtAdvReport, LeExtAdvReports, LeExtAdvReportsIter, SyncHandle,
+};
+use bt_hci::{FromHciBytes, FromHciBytesError};
+use embassy_futures::poll_once;
+use embassy_futures::select::{Either, select};
+use embassy_sync::waitqueue::{MultiWakerRegistration, WakerRegistration};
+use embassy_time::{Duration, Instant, Timer};
+use futures::Stream;
+use heapless::Vec;
 
+use crate::advertise::{AdStructure, MAX_LEGACY_ADV_DATA_LEN};
 use crate::command::CommandState;
-use crate::connection::ScanConfig;
-use crate::{BleHostError, Central};
+use crate::connection::{Connection, ScanConfig};
+use crate::hci::{LeSetPeriodicAdvResponseData, LeSetPeriodicSyncSubevent, LengthPrefixed};
+use crate::types::uuid::Uuid;
+use crate::{Address, BleHostError, Central, Error, Stack};
 
 /// A scanner that wraps a central to provide additional functionality
 /// around BLE scanning.
 ///
 /// The buffer size can be tuned if in a noisy environment that
 /// returns a lot of results.
+///
+/// Scanning can continue while connections exist or are being created, if the controller
+/// supports it. A scan that filters with a new filter accept list cannot be started while a
+/// connection is being created, as the connection attempt already filters with the list. For
+/// the same reason, a scan started while connecting leaves the list as is instead of clearing it.
 pub struct Scanner<'d, C: Controller> {
     central: Central<'d, C>,
 }
@@ -32,9 +58,41 @@ impl<'d, C: Controller> Scanner<'d, C> {
         self.central
     }
 
+    /// Set the filter of the reports queued for [`ScanSession`]s, replacing the previous one.
+    ///
+    /// Reports that do not match are dropped before they are queued, so they neither reach the
+    /// session nor count as dropped. The filter applies to the running scan too, and is kept for the
+    /// next scans. Criteria on the payload match either the advertising or the scan response
+    /// data, which are only filtered together when the host merges scan responses, see
+    /// [`ScanResponseMerger`].
+    pub fn set_filter(&mut self, filter: ScanFilter<'d>) {
+        self.central.stack.host.scan_filter.replace(filter);
+    }
+
+    // Replace the controller filter accept list with the one of the config, returning whether
+    // the scan filters with it.
+    //
+    // A connection attempt in progress filters using the list too. An unfiltered scan then
+    // leaves the list to the connection attempt instead of clearing it, and a scan with its
+    // own list cannot start.
+    async fn prepare_accept_list(&mut self, config: &ScanConfig<'_>) -> Result<bool, BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeClearFilterAcceptList> + ControllerCmdSync<LeAddDeviceToFilterAcceptList>,
+    {
+        if config.use_filter_accept_list {
+            return Ok(true);
+        }
+        if self.central.stack.host.connect_command_state.is_idle() {
+            self.central.set_accept_filter(config.filter_accept_list).await?;
+        } else if !config.filter_accept_list.is_empty() {
+            return Err(Error::InvalidState.into());
+        }
+        Ok(!config.filter_accept_list.is_empty())
+    }
+
     /// Performs an extended BLE scan, return a report for discovering peripherals.
     ///
-    /// Scan is stopped when a report is received. Call this method repeatedly to continue scanning.
+    /// Reports are read from the returned session, and scanning stops when it is dropped.
     pub async fn scan_ext(&mut self, config: &ScanConfig<'_>) -> Result<ScanSession<'_, true>, BleHostError<C::Error>>
     where
         C: ControllerCmdSync<LeSetExtScanEnable>
@@ -47,7 +105,12 @@ impl<'d, C: Controller> Scanner<'d, C> {
             host.scan_command_state.cancel(false);
         });
         host.scan_command_state.request().await;
-        self.central.set_accept_filter(config.filter_accept_list).await?;
+        host.scan_cache.clear();
+        host.scan_responses.borrow_mut().clear();
+        host.scan_fragments.borrow_mut().clear();
+        host.scan_reports.clear();
+        let filtered = self.prepare_accept_list(config).await?;
+        host.scan_filtered.set(filtered);
 
         let scanning = ScanningPhy {
             active_scan: config.active,
@@ -58,10 +121,10 @@ impl<'d, C: Controller> Scanner<'d, C> {
         let host = &self.central.stack.host;
         host.command(LeSetExtScanParams::new(
             host.address.map(|s| s.kind).unwrap_or(AddrKind::PUBLIC),
-            if config.filter_accept_list.is_empty() {
-                bt_hci::param::ScanningFilterPolicy::BasicUnfiltered
-            } else {
+            if filtered {
                 bt_hci::param::ScanningFilterPolicy::BasicFiltered
+            } else {
+                bt_hci::param::ScanningFilterPolicy::BasicUnfiltered
             },
             phy_params,
         ))
@@ -77,10 +140,11 @@ impl<'d, C: Controller> Scanner<'d, C> {
         drop.defuse();
         Ok(ScanSession {
             command_state: &self.central.stack.host.scan_command_state,
+            reports: &self.central.stack.host.scan_reports,
             deadline: if config.timeout.as_ticks() == 0 {
                 None
             } else {
-                Some(Instant::now() + config.timeout.into())
+                Some(Timer::after(config.timeout))
             },
             done: false,
         })
@@ -88,7 +152,7 @@ impl<'d, C: Controller> Scanner<'d, C> {
 
     /// Performs a BLE scan, return a report for discovering peripherals.
     ///
-    /// Scan is stopped when a report is received. Call this method repeatedly to continue scanning.
+    /// Reports are read from the returned session, and scanning stops when it is dropped.
     pub async fn scan(&mut self, config: &ScanConfig<'_>) -> Result<ScanSession<'_, false>, BleHostError<C::Error>>
     where
         C: ControllerCmdSync<LeSetScanParams>
@@ -101,8 +165,13 @@ impl<'d, C: Controller> Scanner<'d, C> {
             host.scan_command_state.cancel(false);
         });
         host.scan_command_state.request().await;
+        host.scan_cache.clear();
+        host.scan_responses.borrow_mut().clear();
+        host.scan_fragments.borrow_mut().clear();
+        host.scan_reports.clear();
 
-        self.central.set_accept_filter(config.filter_accept_list).await?;
+        let filtered = self.prepare_accept_list(config).await?;
+        host.scan_filtered.set(filtered);
 
         let params = LeSetScanParams::new(
             if config.active {
@@ -113,10 +182,10 @@ impl<'d, C: Controller> Scanner<'d, C> {
             config.interval.into(),
             config.interval.into(),
             host.address.map(|a| a.kind).unwrap_or(AddrKind::PUBLIC),
-            if config.filter_accept_list.is_empty() {
-                bt_hci::param::ScanningFilterPolicy::BasicUnfiltered
-            } else {
+            if filtered {
                 bt_hci::param::ScanningFilterPolicy::BasicFiltered
+            } else {
+                bt_hci::param::ScanningFilterPolicy::BasicUnfiltered
             },
         );
         host.command(params).await?;
@@ -125,25 +194,1801 @@ impl<'d, C: Controller> Scanner<'d, C> {
         drop.defuse();
         Ok(ScanSession {
             command_state: &self.central.stack.host.scan_command_state,
+            reports: &self.central.stack.host.scan_reports,
             deadline: if config.timeout.as_ticks() == 0 {
                 None
             } else {
-                Some(Instant::now() + config.timeout.into())
+                Some(Timer::after(config.timeout))
             },
             done: false,
         })
     }
+    /// Synchronize to the periodic advertising of an extended advertiser.
+    ///
+    /// The advertiser is identified by the address and advertising SID of its extended
+    /// advertisements. The controller only establishes the sync while extended scanning, so a
+    /// scan must be running until this returns. Periodic advertising reports are read from the
+    /// returned sync, and are delivered to `EventHandler::on_periodic_adv_report` too.
+    pub async fn sync_periodic(
+        &mut self,
+        addr: Address,
+        sid: u8,
+        config: &PeriodicSyncConfig,
+    ) -> Result<PeriodicSync<'d, C>, BleHostError<C::Error>>
+    where
+        C: ControllerCmdAsync<LePeriodicAdvCreateSync> + ControllerCmdSync<LePeriodicAdvCreateSyncCancel>,
+    {
+        let host = &self.central.stack.host;
+        // A previous attempt may have been dropped before the sync was established.
+        if host.periodic_sync.start() {
+            let _ = host.command(LePeriodicAdvCreateSyncCancel::new()).await;
+        }
+
+        let options = LePeriodicAdvCreateSyncOptions::new().enable_duplicate_filtering(config.filter_duplicates);
+        host.async_command(LePeriodicAdvCreateSync::new(
+            options,
+            sid,
+            addr.kind,
+            addr.addr,
+            config.skip,
+            config.sync_timeout.into(),
+            CteMask::new(),
+        ))
+        .await?;
+
+        let established = match select(
+            poll_fn(|cx| host.periodic_sync.poll_established(cx, None)),
+            Timer::after(config.timeout),
+        )
+        .await
+        {
+            Either::First(established) => established,
+            Either::Second(_) => {
+                // The controller reports the cancellation, or the sync if it was established meanwhile.
+                // A sync established before the cancel command is reported before the command is
+                // rejected, as there is nothing left to cancel.
+                let established = match host.command(LePeriodicAdvCreateSyncCancel::new()).await {
+                    Ok(()) => poll_fn(|cx| host.periodic_sync.poll_established(cx, None)).await,
+                    Err(e) => match poll_once(poll_fn(|cx| host.periodic_sync.poll_established(cx, None))) {
+                        Poll::Ready(established) => established,
+                        Poll::Pending => return Err(e),
+                    },
+                };
+                if established.status != Status::SUCCESS {
+                    return Err(Error::Timeout.into());
+                }
+                established
+            }
+        };
+        established.status.to_result().map_err(Error::Hci)?;
+        Ok(PeriodicSync::new(self.central.stack, established))
+    }
+
+    /// Accept the periodic advertising sync information transferred by a connected peer.
+    ///
+    /// Waits for the peer to transfer a sync, which then receives reports like a sync created
+    /// with `sync_periodic`.
+    pub async fn receive_periodic_sync(
+        &mut self,
+        connection: &Connection<'_>,
+        config: &PeriodicSyncConfig,
+    ) -> Result<PeriodicSync<'d, C>, BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeSetPeriodicAdvSyncTransferParams>,
+    {
+        let host = &self.central.stack.host;
+        let handle = connection.handle();
+        let mode = if config.filter_duplicates {
+            LePeriodicAdvSyncTransferMode::SyncRxReportFilterDuplicates
+        } else {
+            LePeriodicAdvSyncTransferMode::SyncRxReport
+        };
+        host.command(LeSetPeriodicAdvSyncTransferParams::new(
+            handle,
+            mode,
+            config.skip,
+            config.sync_timeout.into(),
+            CteMask::new(),
+        ))
+        .await?;
+
+        let result = select(
+            poll_fn(|cx| host.periodic_sync.poll_established(cx, Some(handle))),
+            Timer::after(config.timeout),
+        )
+        .await;
+        // Only accept transfers while waiting for them.
+        host.command(LeSetPeriodicAdvSyncTransferParams::new(
+            handle,
+            LePeriodicAdvSyncTransferMode::NoSync,
+            config.skip,
+            config.sync_timeout.into(),
+            CteMask::new(),
+        ))
+        .await?;
+        match result {
+            Either::First(established) => {
+                established.status.to_result().map_err(Error::Hci)?;
+                Ok(PeriodicSync::new(self.central.stack, established))
+            }
+            Either::Second(_) => Err(Error::Timeout.into()),
+        }
+    }
+}
+
+/// Configuration for synchronizing to a periodic advertiser.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PeriodicSyncConfig {
+    /// Number of periodic advertising events that may be skipped after a successful receive.
+    pub skip: u16,
+    /// The sync is lost when no periodic advertising is received within this duration.
+    pub sync_timeout: Duration,
+    /// Give up when the sync is not established within this duration.
+    pub timeout: Duration,
+    /// Filter duplicate periodic advertising reports in the controller.
+    pub filter_duplicates: bool,
+}
+
+impl Default for PeriodicSyncConfig {
+    fn default() -> Self {
+        Self {
+            skip: 0,
+            sync_timeout: Duration::from_secs(2),
+            timeout: Duration::from_secs(5),
+            filter_duplicates: false,
+        }
+    }
+}
+
+/// A sync to a periodic advertiser.
+///
+/// The reports of the sync are read with [`PeriodicSync::next`] or through the [`Stream`]
+/// implementation, which end when the sync is lost. Reports are queued in the host, up to the
+/// `PERIODIC_REPORTS` size of `HostResources`, and are delivered to
+/// `EventHandler::on_periodic_adv_report` too. The sync is terminated when dropped.
+pub struct PeriodicSync<'d, C: Controller> {
+    pub(crate) stack: &'d Stack<'d, C>,
+    handle: SyncHandle,
+    addr: Address,
+    sid: u8,
+    done: bool,
+}
+
+impl<'d, C: Controller> PeriodicSync<'d, C> {
+    fn new(stack: &'d Stack<'d, C>, established: SyncEstablished) -> Self {
+        Self {
+            stack,
+            handle: established.handle,
+            addr: established.addr,
+            sid: established.sid,
+            done: false,
+        }
+    }
+
+    /// Wait for the next periodic advertising report, or `None` once the sync is lost.
+    pub async fn next(&mut self) -> Option<OwnedPeriodicReport> {
+        poll_fn(|cx| self.poll_report(cx)).await
+    }
+
+    fn poll_report(&mut self, cx: &mut Context<'_>) -> Poll<Option<OwnedPeriodicReport>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        let report = ready!(self.stack.host.periodic_reports.poll_take(cx, self.handle));
+        // The sync is gone once lost, and must not be terminated
+        self.done = report.is_none();
+        Poll::Ready(report)
+    }
+
+    /// The sync handle, which identifies the reports of this sync.
+    pub fn handle(&self) -> SyncHandle {
+        self.handle
+    }
+
+    /// Address of the periodic advertiser.
+    pub fn address(&self) -> Address {
+        self.addr
+    }
+
+    /// Advertising SID of the periodic advertiser.
+    pub fn sid(&self) -> u8 {
+        self.sid
+    }
+
+    /// Transfer the sync to a connected peer.
+    ///
+    /// This allows the peer to synchronize to the periodic advertising without scanning for it.
+    /// The `service_data` value is passed on to the peer application.
+    pub async fn transfer(&self, connection: &Connection<'_>, service_data: u16) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LePeriodicAdvSyncTransfer>,
+    {
+        self.stack
+            .host
+            .command(LePeriodicAdvSyncTransfer::new(
+                connection.handle(),
+                service_data,
+                self.handle,
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Select the subevents of periodic advertising with responses to receive.
+    pub async fn set_subevents(&self, subevents: &[u8], include_tx_power: bool) -> Result<(), BleHostError<C::Error>>
+    where
+        C: for<'t> ControllerCmdSync<LeSetPeriodicSyncSubevent<'t>>,
+    {
+        if subevents.is_empty() || subevents.len() > 128 {
+            return Err(Error::InvalidValue.into());
+        }
+        self.stack
+            .host
+            .command(LeSetPeriodicSyncSubevent::new(
+                self.handle,
+                PeriodicAdvProps::new().include_tx_power(include_tx_power),
+                LengthPrefixed(subevents),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Respond to a subevent of periodic advertising with responses.
+    pub async fn set_response_data(&self, response: &PawrResponse<'_>) -> Result<(), BleHostError<C::Error>>
+    where
+        C: for<'t> ControllerCmdSync<LeSetPeriodicAdvResponseData<'t>>,
+    {
+        self.stack
+            .host
+            .command(LeSetPeriodicAdvResponseData::new(
+                self.handle,
+                response.request_event,
+                response.request_subevent,
+                response.response_subevent,
+                response.response_slot,
+                LengthPrefixed(response.data),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Stop receiving periodic advertising reports.
+    pub async fn terminate(mut self) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LePeriodicAdvTerminateSync>,
+    {
+        let host = &self.stack.host;
+        if !self.done {
+            host.command(LePeriodicAdvTerminateSync::new(self.handle)).await?;
+            self.done = true;
+            host.periodic_reports.purge(self.handle);
+        }
+        Ok(())
+    }
+}
+
+impl<C: Controller> Stream for PeriodicSync<'_, C> {
+    type Item = OwnedPeriodicReport;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_report(cx)
+    }
+}
+
+impl<C: Controller> Drop for PeriodicSync<'_, C> {
+    fn drop(&mut self) {
+        let host = &self.stack.host;
+        host.periodic_reports.purge(self.handle);
+        if !self.done {
+            host.periodic_sync.terminate(self.handle);
+        }
+    }
+}
+
+// Largest data carried by a single periodic advertising report.
+const MAX_PERIODIC_REPORT_DATA_LEN: usize = 247;
+
+/// A periodic advertising report received by a [`PeriodicSync`].
+#[derive(Debug, Clone)]
+pub struct OwnedPeriodicReport {
+    /// TX power of the advertiser in dBm, or 127 if not available.
+    pub tx_power: i8,
+    /// Signal strength of the received packet, in dBm.
+    pub rssi: i8,
+    /// Whether the data continues in the next report.
+    pub incomplete: bool,
+    /// Periodic advertising data.
+    pub data: Vec<u8, MAX_PERIODIC_REPORT_DATA_LEN>,
+}
+
+/// Entry of the periodic advertising report queue.
+pub(crate) struct QueuedPeriodicReport {
+    handle: SyncHandle,
+    // None once the sync is lost.
+    report: Option<OwnedPeriodicReport>,
+}
+
+struct PeriodicReportQueueInner<'d> {
+    entries: &'d mut [Option<QueuedPeriodicReport>],
+    len: usize,
+    waker: MultiWakerRegistration<4>,
+}
+
+/// Periodic advertising reports of all syncs, in the order they were received.
+pub(crate) struct PeriodicReportQueue<'d> {
+    inner: RefCell<PeriodicReportQueueInner<'d>>,
+}
+
+impl<'d> PeriodicReportQueue<'d> {
+    pub(crate) fn new(entries: &'d mut [Option<QueuedPeriodicReport>]) -> Self {
+        Self {
+            inner: RefCell::new(PeriodicReportQueueInner {
+                entries,
+                len: 0,
+                waker: MultiWakerRegistration::new(),
+            }),
+        }
+    }
+
+    pub(crate) fn push(&self, report: &LePeriodicAdvertisingReport<'_>) {
+        let Ok(data) = Vec::from_slice(report.data) else {
+            return;
+        };
+        let mut inner = self.inner.borrow_mut();
+        let len = inner.len;
+        if len == inner.entries.len() {
+            trace!("[scan] periodic report queue full, dropping report");
+            return;
+        }
+        inner.entries[len] = Some(QueuedPeriodicReport {
+            handle: report.sync_handle,
+            report: Some(OwnedPeriodicReport {
+                tx_power: report.tx_power,
+                rssi: report.rssi,
+                incomplete: report.data_status == DataStatus::Incomplete,
+                data,
+            }),
+        });
+        inner.len += 1;
+        inner.waker.wake();
+    }
+
+    /// Replace the queued reports of a lost sync with the end of its reports.
+    pub(crate) fn lost(&self, handle: SyncHandle) {
+        self.purge(handle);
+        let mut inner = self.inner.borrow_mut();
+        if inner.entries.is_empty() {
+            return;
+        }
+        if inner.len == inner.entries.len() {
+            // Ending the sync matters more than the oldest report of another sync
+            inner.remove(0);
+        }
+        let len = inner.len;
+        inner.entries[len] = Some(QueuedPeriodicReport { handle, report: None });
+        inner.len += 1;
+        inner.waker.wake();
+    }
+
+    pub(crate) fn purge(&self, handle: SyncHandle) {
+        let mut inner = self.inner.borrow_mut();
+        let mut idx = 0;
+        while idx < inner.len {
+            if matches!(&inner.entries[idx], Some(e) if e.handle == handle) {
+                inner.remove(idx);
+            } else {
+                idx += 1;
+            }
+        }
+    }
+
+    fn poll_take(&self, cx: &mut Context<'_>, handle: SyncHandle) -> Poll<Option<OwnedPeriodicReport>> {
+        let mut inner = self.inner.borrow_mut();
+        let len = inner.len;
+        match inner.entries[..len]
+            .iter()
+            .position(|e| matches!(e, Some(e) if e.handle == handle))
+        {
+            Some(idx) => Poll::Ready(unwrap!(inner.remove(idx)).report),
+            None => {
+                inner.waker.register(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl PeriodicReportQueueInner<'_> {
+    fn remove(&mut self, idx: usize) -> Option<QueuedPeriodicReport> {
+        let entry = self.entries[idx].take();
+        self.entries[idx..self.len].rotate_left(1);
+        self.len -= 1;
+        entry
+    }
+}
+
+/// A response to a subevent of periodic advertising with responses.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PawrResponse<'d> {
+    /// Periodic event counter of the subevent being responded to.
+    pub request_event: u16,
+    /// Number of the subevent being responded to.
+    pub request_subevent: u8,
+    /// Number of the subevent in which to respond.
+    pub response_subevent: u8,
+    /// Response slot in which to respond.
+    pub response_slot: u8,
+    /// Data of the response.
+    pub data: &'d [u8],
 }
 
-/// Handle to an active advertiser which can accept connections.
+#[derive(Clone, Copy)]
+pub(crate) struct SyncEstablished {
+    pub(crate) connection: Option<ConnHandle>,
+    pub(crate) status: Status,
+    pub(crate) handle: SyncHandle,
+    pub(crate) addr: Address,
+    pub(crate) sid: u8,
+}
+
+// Number of dropped syncs waiting to be terminated by the control runner.
+const PENDING_TERMINATIONS: usize = 4;
+
+struct PeriodicSyncInner {
+    pending: bool,
+    established: Option<SyncEstablished>,
+    waker: WakerRegistration,
+    terminate: Vec<SyncHandle, PENDING_TERMINATIONS>,
+    terminate_waker: WakerRegistration,
+}
+
+/// Tracks periodic advertising sync establishment.
+pub(crate) struct PeriodicSyncState {
+    inner: RefCell<PeriodicSyncInner>,
+}
+
+impl PeriodicSyncState {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: RefCell::new(PeriodicSyncInner {
+                pending: false,
+                established: None,
+                waker: WakerRegistration::new(),
+                terminate: Vec::new(),
+                terminate_waker: WakerRegistration::new(),
+            }),
+        }
+    }
+
+    // Start creating a sync, returning true if a previous attempt is still pending.
+    fn start(&self) -> bool {
+        let mut inner = self.inner.borrow_mut();
+        inner.established = None;
+        core::mem::replace(&mut inner.pending, true)
+    }
+
+    pub(crate) fn established(&self, established: SyncEstablished) {
+        let mut inner = self.inner.borrow_mut();
+        if established.connection.is_none() {
+            inner.pending = false;
+        }
+        inner.established.replace(established);
+        inner.waker.wake();
+    }
+
+    // Request the control runner to terminate a dropped sync.
+    fn terminate(&self, handle: SyncHandle) {
+        let mut inner = self.inner.borrow_mut();
+        if inner.terminate.push(handle).is_err() {
+            warn!("[scan] too many periodic syncs to terminate, {:?} is kept", handle);
+        }
+        inner.terminate_waker.wake();
+    }
+
+    pub(crate) fn poll_terminate(&self, cx: &mut Context<'_>) -> Poll<SyncHandle> {
+        let mut inner = self.inner.borrow_mut();
+        match inner.terminate.first() {
+            Some(handle) => Poll::Ready(*handle),
+            None => {
+                inner.terminate_waker.register(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+
+    pub(crate) fn terminated(&self, handle: SyncHandle) {
+        self.inner.borrow_mut().terminate.retain(|h| *h != handle);
+    }
+
+    fn poll_established(&self, cx: &mut Context<'_>, connection: Option<ConnHandle>) -> Poll<SyncEstablished> {
+        let mut inner = self.inner.borrow_mut();
+        inner.waker.register(cx.waker());
+        match inner.established {
+            Some(established) if established.connection == connection => {
+                inner.established = None;
+                Poll::Ready(established)
+            }
+            _ => Poll::Pending,
+        }
+    }
+}
+
+/// Handle to an active scan.
+///
+/// The reports received while scanning are queued and can be read with [`ScanSession::next`],
+/// or through the [`Stream`] implementation. Reports are only queued when the `SCAN_REPORTS`
+/// size of [`HostResources`](crate::HostResources) is not zero. The session ends when the scan timeout of the
+/// config expires, and scanning is stopped when the session is dropped.
 pub struct ScanSession<'d, const EXTENDED: bool> {
     command_state: &'d CommandState<bool>,
-    deadline: Option<Instant>,
+    reports: &'d ScanReportQueue<'d>,
+    deadline: Option<Timer>,
     done: bool,
 }
 
+impl<const EXTENDED: bool> ScanSession<'_, EXTENDED> {
+    /// Wait for the next scan report, or `None` once the scan timeout has expired or the host has
+    /// reset the controller.
+    pub async fn next(&mut self) -> Option<OwnedScanReport> {
+        poll_fn(|cx| self.poll_report(cx)).await
+    }
+
+    /// Number of reports dropped because the queue was full since the scan started.
+    pub fn dropped(&self) -> u32 {
+        self.reports.dropped()
+    }
+
+    fn poll_report(&mut self, cx: &mut Context<'_>) -> Poll<Option<OwnedScanReport>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        if let Poll::Ready(report) = self.reports.poll_receive(cx) {
+            self.done = report.is_none();
+            return Poll::Ready(report);
+        }
+        if let Some(timer) = self.deadline.as_mut() {
+            if Pin::new(timer).poll(cx).is_ready() {
+                self.done = true;
+                return Poll::Ready(None);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<const EXTENDED: bool> Stream for ScanSession<'_, EXTENDED> {
+    type Item = OwnedScanReport;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_report(cx)
+    }
+}
+
+/// A scan report queued by a [`ScanSession`].
+///
+/// Scan responses are queued along with the advertisement they belong to when the host tracks
+/// advertisers for them, see [`ScanResponseMerger`]. Extended advertising data split over several
+/// reports is reassembled before it is queued, see [`ExtAdvReassembler`]. Payloads larger than
+/// [`config::SCAN_REPORT_DATA_SIZE`](crate::config::SCAN_REPORT_DATA_SIZE) are dropped.
+#[derive(Debug, Clone)]
+pub struct OwnedScanReport {
+    /// Address of the advertiser.
+    pub addr: Address,
+    /// Signal strength of the last received packet, in dBm.
+    pub rssi: i8,
+    /// Whether the advertiser accepts connections.
+    pub connectable: bool,
+    /// Advertising data.
+    pub adv_data: Vec<u8, { crate::config::SCAN_REPORT_DATA_SIZE }>,
+    /// Scan response data, empty if none was received.
+    pub scan_data: Vec<u8, { crate::config::SCAN_REPORT_DATA_SIZE }>,
+}
+
+impl OwnedScanReport {
+    /// Borrow the queued report as a [`ScanReport`].
+    pub fn report(&self) -> ScanReport<'_> {
+        ScanReport {
+            addr: self.addr,
+            rssi: self.rssi,
+            connectable: self.connectable,
+            adv_data: &self.adv_data,
+            scan_data: &self.scan_data,
+        }
+    }
+}
+
+struct ScanReportQueueInner {
+    head: usize,
+    len: usize,
+    dropped: u32,
+    // Set when the controller is reset, ending the session.
+    closed: bool,
+    waker: WakerRegistration,
+}
+
+// The entries are cells rather than part of the inner state, so that sessions can borrow the
+// queue for shorter than the host lifetime.
+pub(crate) struct ScanReportQueue<'d> {
+    entries: &'d [Cell<Option<OwnedScanReport>>],
+    inner: RefCell<ScanReportQueueInner>,
+}
+
+impl<'d> ScanReportQueue<'d> {
+    pub(crate) fn new(entries: &'d mut [Option<OwnedScanReport>]) -> Self {
+        Self {
+            entries: Cell::from_mut(entries).as_slice_of_cells(),
+            inner: RefCell::new(ScanReportQueueInner {
+                head: 0,
+                len: 0,
+                dropped: 0,
+                closed: false,
+                waker: WakerRegistration::new(),
+            }),
+        }
+    }
+
+    pub(crate) fn close(&self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.closed = true;
+        inner.waker.wake();
+    }
+
+    fn clear(&self) {
+        let mut inner = self.inner.borrow_mut();
+        self.entries.iter().for_each(|e| e.set(None));
+        inner.head = 0;
+        inner.len = 0;
+        inner.dropped = 0;
+        inner.closed = false;
+    }
+
+    fn dropped(&self) -> u32 {
+        self.inner.borrow().dropped
+    }
+
+    // Take the oldest report, or `None` once the queue is closed and empty.
+    fn poll_receive(&self, cx: &mut Context<'_>) -> Poll<Option<OwnedScanReport>> {
+        let mut inner = self.inner.borrow_mut();
+        if inner.len == 0 {
+            if inner.closed {
+                return Poll::Ready(None);
+            }
+            inner.waker.register(cx.waker());
+            return Poll::Pending;
+        }
+        let head = inner.head;
+        let report = unwrap!(self.entries[head].take());
+        inner.head = (head + 1) % self.entries.len();
+        inner.len -= 1;
+        Poll::Ready(Some(report))
+    }
+
+    pub(crate) fn push(&self, report: &ScanReport<'_>) {
+        let queued = match (Vec::from_slice(report.adv_data), Vec::from_slice(report.scan_data)) {
+            (Ok(adv_data), Ok(scan_data)) => Some(OwnedScanReport {
+                addr: report.addr,
+                rssi: report.rssi,
+                connectable: report.connectable,
+                adv_data,
+                scan_data,
+            }),
+            _ => None,
+        };
+        let mut inner = self.inner.borrow_mut();
+        let capacity = self.entries.len();
+        match queued {
+            Some(queued) if inner.len < capacity => {
+                let tail = (inner.head + inner.len) % capacity;
+                self.entries[tail].set(Some(queued));
+                inner.len += 1;
+                inner.waker.wake();
+            }
+            _ => inner.dropped = inner.dropped.wrapping_add(1),
+        }
+    }
+}
+
 impl<const EXTENDED: bool> Drop for ScanSession<'_, EXTENDED> {
     fn drop(&mut self) {
         self.command_state.cancel(EXTENDED);
     }
 }
+
+/// An advertising report combined with the scan response of the same advertiser.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ScanReport<'d> {
+    /// Address of the advertiser.
+    pub addr: Address,
+    /// Signal strength of the last received packet, in dBm.
+    pub rssi: i8,
+    /// Whether the advertiser accepts connections.
+    pub connectable: bool,
+    /// Advertising data.
+    pub adv_data: &'d [u8],
+    /// Scan response data, empty if none was received.
+    pub scan_data: &'d [u8],
+}
+
+impl<'d> ScanReport<'d> {
+    /// Decode the AD structures of the advertising data, followed by the scan response data.
+    pub fn structures(&self) -> impl Iterator<Item = Result<AdStructure<'d>, crate::codec::Error>> + use<'d> {
+        AdStructure::decode(self.adv_data).chain(AdStructure::decode(self.scan_data))
+    }
+
+    /// The local name of the advertiser, preferring the complete name over the shortened one.
+    pub fn name(&self) -> Option<&'d [u8]> {
+        let mut shortened = None;
+        for s in self.structures().flatten() {
+            match s {
+                AdStructure::CompleteLocalName(name) => return Some(name),
+                AdStructure::ShortenedLocalName(name) => shortened = shortened.or(Some(name)),
+                _ => {}
+            }
+        }
+        shortened
+    }
+
+    /// The manufacturer specific data of the given company, if advertised.
+    pub fn manufacturer_data(&self, company_identifier: u16) -> Option<&'d [u8]> {
+        self.structures().flatten().find_map(|s| match s {
+            AdStructure::ManufacturerSpecificData {
+                company_identifier: id,
+                payload,
+            } if id == company_identifier => Some(payload),
+            _ => None,
+        })
+    }
+}
+
+/// Report of an LE Extended Advertising Report event.
+///
+/// `bt-hci` decodes the advertising data of [`LeExtAdvReport`] right after the address, while
+/// controllers send the PHYs, advertising SID, TX power, RSSI, periodic advertising interval and
+/// direct address first. Decode the reports of an event with [`ExtAdvReport::iter`] instead.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ExtAdvReport<'d> {
+    /// Kind of advertisement and status of its data.
+    pub event_kind: LeExtAdvEventKind,
+    /// Address kind of the advertiser.
+    pub addr_kind: AddrKind,
+    /// Address of the advertiser.
+    pub addr: BdAddr,
+    /// Advertising SID of the set, or 0xff if the advertisement carries none.
+    pub adv_sid: u8,
+    /// TX power of the advertiser in dBm, or 127 if not available.
+    pub tx_power: i8,
+    /// Signal strength of the received packet, in dBm.
+    pub rssi: i8,
+    /// Advertising or scan response data.
+    pub data: &'d [u8],
+}
+
+impl<'d> ExtAdvReport<'d> {
+    /// Iterate over the reports of an LE Extended Advertising Report event.
+    pub fn iter(reports: &'d LeExtAdvReports<'_>) -> ExtAdvReportsIter<'d> {
+        ExtAdvReportsIter {
+            len: reports.len(),
+            bytes: &reports.bytes,
+        }
+    }
+}
+
+impl<'de> FromHciBytes<'de> for ExtAdvReport<'de> {
+    fn from_hci_bytes(data: &'de [u8]) -> Result<(Self, &'de [u8]), FromHciBytesError> {
+        let (event_kind, rest) = LeExtAdvEventKind::from_hci_bytes(data)?;
+        let (addr_kind, rest) = AddrKind::from_hci_bytes(rest)?;
+        let (addr, rest) = BdAddr::from_hci_bytes(rest)?;
+        // Primary and secondary PHYs
+        let (_, rest) = <[u8; 2]>::from_hci_bytes(rest)?;
+        let (adv_sid, rest) = u8::from_hci_bytes(rest)?;
+        let (tx_power, rest) = i8::from_hci_bytes(rest)?;
+        let (rssi, rest) = i8::from_hci_bytes(rest)?;
+        // Periodic advertising interval and direct address
+        let (_, rest) = <[u8; 9]>::from_hci_bytes(rest)?;
+        let (data, rest) = <&'de [u8]>::from_hci_bytes(rest)?;
+        Ok((
+            Self {
+                event_kind,
+                addr_kind,
+                addr,
+                adv_sid,
+                tx_power,
+                rssi,
+                data,
+            },
+            rest,
+        ))
+    }
+}
+
+/// Iterator over the reports of an LE Extended Advertising Report event.
+pub struct ExtAdvReportsIter<'d> {
+    len: usize,
+    bytes: &'d [u8],
+}
+
+impl<'d> Iterator for ExtAdvReportsIter<'d> {
+    type Item = Result<ExtAdvReport<'d>, FromHciBytesError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        match ExtAdvReport::from_hci_bytes(self.bytes) {
+            Ok((report, rest)) => {
+                self.bytes = rest;
+                self.len -= 1;
+                Some(Ok(report))
+            }
+            Err(err) => {
+                self.len = 0;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Entry of the host duplicate filter cache.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ScanCacheEntry {
+    addr: Address,
+    hash: u32,
+}
+
+/// Least recently used cache of the advertisers and payloads seen during a scan.
+///
+/// Entries are kept ordered from most to least recently seen, so that the least recently seen
+/// advertiser is evicted first when the cache is full.
+pub(crate) struct DuplicateFilter<'d> {
+    entries: RefCell<&'d mut [Option<ScanCacheEntry>]>,
+}
+
+impl<'d> DuplicateFilter<'d> {
+    pub(crate) fn new(entries: &'d mut [Option<ScanCacheEntry>]) -> Self {
+        Self {
+            entries: RefCell::new(entries),
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        self.entries.borrow_mut().fill(None);
+    }
+
+    /// Check if the report was seen before, and record it as the most recently seen.
+    ///
+    /// A zero sized cache reports no duplicates.
+    pub(crate) fn is_duplicate(&self, report: &ScanReport<'_>) -> bool {
+        let mut entries = self.entries.borrow_mut();
+        if entries.is_empty() {
+            return false;
+        }
+        let entry = ScanCacheEntry {
+            addr: report.addr,
+            hash: payload_hash(report),
+        };
+        match entries
+            .iter()
+            .position(|e| matches!(e, Some(e) if e.addr == entry.addr && e.hash == entry.hash))
+        {
+            Some(idx) => {
+                entries[..=idx].rotate_right(1);
+                true
+            }
+            None => {
+                entries.rotate_right(1);
+                entries[0] = Some(entry);
+                false
+            }
+        }
+    }
+}
+
+// FNV-1a hash of the advertising and scan response data.
+fn payload_hash(report: &ScanReport<'_>) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    let separator = [report.adv_data.len() as u8];
+    for b in report
+        .adv_data
+        .iter()
+        .chain(separator.iter())
+        .chain(report.scan_data.iter())
+    {
+        hash ^= *b as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+pub(crate) struct PendingReport {
+    addr: Address,
+    received: Instant,
+    rssi: i8,
+    connectable: bool,
+    data: Vec<u8, MAX_LEGACY_ADV_DATA_LEN>,
+}
+
+/// Correlates scan responses with the advertising reports they belong to.
+///
+/// Feed the reports received by an [`EventHandler`](crate::prelude::EventHandler) to the merger,
+/// which calls back with a single [`ScanReport`] per advertisement. Reports of scannable
+/// advertisements are held until the scan response arrives, until the same advertiser is seen
+/// again, or until they are older than the timeout of the merger. Up to `N` advertisers are
+/// tracked, and the oldest one is reported without scan response data when the merger is full.
+///
+/// Expired reports are evicted whenever a report is processed. When reports may stop arriving,
+/// call [`ScanResponseMerger::flush_expired`] periodically so that held reports are not delayed.
+///
+/// The host runner merges the reports queued for [`ScanSession`]s the same way, tracking as many
+/// advertisers as the `SCAN_RESPONSES` size of [`HostResources`](crate::HostResources).
+pub struct ScanResponseMerger<const N: usize> {
+    inner: Merger<[Option<PendingReport>; N]>,
+}
+
+/// Default time a [`ScanResponseMerger`] waits for the scan response of an advertisement.
+pub const SCAN_RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+impl<const N: usize> Default for ScanResponseMerger<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ScanResponseMerger<N> {
+    /// Create a new merger waiting up to [`SCAN_RESPONSE_TIMEOUT`] for scan responses.
+    pub fn new() -> Self {
+        Self::with_timeout(SCAN_RESPONSE_TIMEOUT)
+    }
+
+    /// Create a new merger waiting up to `timeout` for scan responses.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            inner: Merger::new([const { None }; N], timeout),
+        }
+    }
+
+    /// Process a legacy advertising report.
+    pub fn process(&mut self, report: &LeAdvReport<'_>, f: impl FnMut(ScanReport<'_>)) {
+        self.inner.process(report, f);
+    }
+
+    /// Process an extended advertising report.
+    ///
+    /// Extended scannable advertisements carry no advertising data, so only reports of legacy
+    /// advertisements received while extended scanning are held for their scan response.
+    pub fn process_ext(&mut self, report: &ExtAdvReport<'_>, f: impl FnMut(ScanReport<'_>)) {
+        self.inner.process_ext(report, f);
+    }
+
+    /// Report all held advertisements without scan response data.
+    pub fn flush(&mut self, f: impl FnMut(ScanReport<'_>)) {
+        self.inner.flush(f);
+    }
+
+    /// Report the held advertisements that waited longer than the timeout without scan response.
+    pub fn flush_expired(&mut self, f: impl FnMut(ScanReport<'_>)) {
+        self.inner.expire(Instant::now(), f);
+    }
+}
+
+// Merger state over any storage, so that the host can keep its entries in the host resources.
+// Held reports are kept in the order they were received, followed by the free entries.
+pub(crate) struct Merger<S> {
+    pending: S,
+    timeout: Duration,
+}
+
+impl<S: AsMut<[Option<PendingReport>]>> Merger<S> {
+    pub(crate) fn new(pending: S, timeout: Duration) -> Self {
+        Self { pending, timeout }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.pending.as_mut().fill_with(|| None);
+    }
+
+    pub(crate) fn process(&mut self, report: &LeAdvReport<'_>, f: impl FnMut(ScanReport<'_>)) {
+        let addr = Address {
+            kind: report.addr_kind,
+            addr: report.addr,
+        };
+        let (scannable, scan_response) = match report.event_kind {
+            LeAdvEventKind::AdvInd | LeAdvEventKind::AdvScanInd => (true, false),
+            LeAdvEventKind::ScanRsp => (false, true),
+            _ => (false, false),
+        };
+        let connectable = matches!(report.event_kind, LeAdvEventKind::AdvInd | LeAdvEventKind::AdvDirectInd);
+        self.handle(
+            Instant::now(),
+            addr,
+            report.rssi,
+            connectable,
+            scannable,
+            scan_response,
+            report.data,
+            f,
+        );
+    }
+
+    pub(crate) fn process_ext(&mut self, report: &ExtAdvReport<'_>, f: impl FnMut(ScanReport<'_>)) {
+        let addr = Address {
+            kind: report.addr_kind,
+            addr: report.addr,
+        };
+        let kind = report.event_kind;
+        let scannable = kind.legacy() && kind.scannable() && !kind.scan_response();
+        self.handle(
+            Instant::now(),
+            addr,
+            report.rssi,
+            kind.connectable(),
+            scannable,
+            kind.scan_response(),
+            report.data,
+            f,
+        );
+    }
+
+    fn flush(&mut self, mut f: impl FnMut(ScanReport<'_>)) {
+        for pending in self.pending.as_mut().iter_mut().filter_map(Option::take) {
+            f(pending.report(pending.rssi, &[]));
+        }
+    }
+
+    fn len(&mut self) -> usize {
+        let pending = self.pending.as_mut();
+        pending.iter().position(Option::is_none).unwrap_or(pending.len())
+    }
+
+    fn remove(&mut self, idx: usize) -> PendingReport {
+        let len = self.len();
+        let pending = self.pending.as_mut();
+        let removed = unwrap!(pending[idx].take());
+        pending[idx..len].rotate_left(1);
+        removed
+    }
+
+    fn expire(&mut self, now: Instant, mut f: impl FnMut(ScanReport<'_>)) {
+        while let Some(oldest) = self.pending.as_mut().first().and_then(Option::as_ref) {
+            if now.saturating_duration_since(oldest.received) < self.timeout {
+                break;
+            }
+            let oldest = self.remove(0);
+            f(oldest.report(oldest.rssi, &[]));
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle(
+        &mut self,
+        now: Instant,
+        addr: Address,
+        rssi: i8,
+        connectable: bool,
+        scannable: bool,
+        scan_response: bool,
+        data: &[u8],
+        mut f: impl FnMut(ScanReport<'_>),
+    ) {
+        self.expire(now, &mut f);
+        let existing = self
+            .pending
+            .as_mut()
+            .iter()
+            .position(|p| p.as_ref().is_some_and(|p| p.addr == addr));
+        if scan_response {
+            match existing {
+                Some(idx) => {
+                    let pending = self.remove(idx);
+                    f(pending.report(rssi, data));
+                }
+                None => f(ScanReport {
+                    addr,
+                    rssi,
+                    connectable: false,
+                    adv_data: &[],
+                    scan_data: data,
+                }),
+            }
+            return;
+        }
+
+        // A new advertisement replaces the previous one from the same advertiser
+        if let Some(idx) = existing {
+            let pending = self.remove(idx);
+            f(pending.report(pending.rssi, &[]));
+        }
+
+        // Without any entries, advertisements are reported right away
+        let capacity = self.pending.as_mut().len();
+        if scannable && capacity > 0 && data.len() <= MAX_LEGACY_ADV_DATA_LEN {
+            let mut len = self.len();
+            if len == capacity {
+                let oldest = self.remove(0);
+                f(oldest.report(oldest.rssi, &[]));
+                len -= 1;
+            }
+            self.pending.as_mut()[len] = Some(PendingReport {
+                addr,
+                received: now,
+                rssi,
+                connectable,
+                data: unwrap!(Vec::from_slice(data)),
+            });
+        } else {
+            f(ScanReport {
+                addr,
+                rssi,
+                connectable,
+                adv_data: data,
+                scan_data: &[],
+            });
+        }
+    }
+}
+
+impl PendingReport {
+    fn report<'d>(&'d self, rssi: i8, scan_data: &'d [u8]) -> ScanReport<'d> {
+        ScanReport {
+            addr: self.addr,
+            rssi,
+            connectable: self.connectable,
+            adv_data: &self.data,
+            scan_data,
+        }
+    }
+}
+
+pub(crate) struct PartialReport<const L: usize> {
+    addr: Address,
+    sid: u8,
+    scan_response: bool,
+    overflow: bool,
+    data: Vec<u8, L>,
+}
+
+/// Reassembles extended advertising reports split over several HCI events.
+///
+/// Controllers report advertising data larger than a single HCI event in fragments, and the
+/// reassembler calls back with a single [`ScanReport`] once the last fragment is received.
+/// Payloads from up to `N` advertising sets are reassembled at the same time, each up to `L`
+/// bytes. Sets are told apart by the address and advertising SID of the advertiser.
+/// Payloads that do not fit are dropped, and so are the oldest partial payloads when a new
+/// advertiser needs to be tracked. Payloads truncated by the controller are reported as received.
+///
+/// The host runner reassembles the reports queued for [`ScanSession`]s the same way, as sized by
+/// [`config::SCAN_REASSEMBLY_SIZE`](crate::config::SCAN_REASSEMBLY_SIZE) and
+/// [`config::SCAN_REPORT_DATA_SIZE`](crate::config::SCAN_REPORT_DATA_SIZE).
+pub struct ExtAdvReassembler<const N: usize, const L: usize> {
+    inner: Reassembler<[Option<PartialReport<L>>; N], L>,
+}
+
+impl<const N: usize, const L: usize> Default for ExtAdvReassembler<N, L> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const L: usize> ExtAdvReassembler<N, L> {
+    /// Create a new reassembler.
+    pub fn new() -> Self {
+        Self {
+            inner: Reassembler::new([const { None }; N]),
+        }
+    }
+
+    /// Process an extended advertising report.
+    pub fn process(&mut self, report: &ExtAdvReport<'_>, f: impl FnOnce(ScanReport<'_>)) {
+        self.inner.process(report, f);
+    }
+}
+
+// Reassembler of the host runner.
+pub(crate) type HostReassembler = Reassembler<
+    [Option<PartialReport<{ crate::config::SCAN_REPORT_DATA_SIZE }>>; crate::config::SCAN_REASSEMBLY_SIZE],
+    { crate::config::SCAN_REPORT_DATA_SIZE },
+>;
+
+// Reassembler state over any storage, so that the host can keep its own. Partial payloads are
+// kept in the order they were started, followed by the free entries.
+pub(crate) struct Reassembler<S, const L: usize> {
+    partial: S,
+}
+
+impl<S: AsMut<[Option<PartialReport<L>>]>, const L: usize> Reassembler<S, L> {
+    pub(crate) fn new(partial: S) -> Self {
+        Self { partial }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.partial.as_mut().fill_with(|| None);
+    }
+
+    pub(crate) fn process(&mut self, report: &ExtAdvReport<'_>, f: impl FnOnce(ScanReport<'_>)) {
+        let addr = Address {
+            kind: report.addr_kind,
+            addr: report.addr,
+        };
+        let kind = report.event_kind;
+        let complete = !matches!(kind.data_status(), LeExtAdvDataStatus::IncompleteMoreExpected);
+        self.handle(
+            addr,
+            report.adv_sid,
+            report.rssi,
+            kind.connectable(),
+            kind.scan_response(),
+            complete,
+            report.data,
+            f,
+        );
+    }
+
+    fn len(&mut self) -> usize {
+        let partial = self.partial.as_mut();
+        partial.iter().position(Option::is_none).unwrap_or(partial.len())
+    }
+
+    fn remove(&mut self, idx: usize) -> PartialReport<L> {
+        let len = self.len();
+        let partial = self.partial.as_mut();
+        let removed = unwrap!(partial[idx].take());
+        partial[idx..len].rotate_left(1);
+        removed
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle(
+        &mut self,
+        addr: Address,
+        sid: u8,
+        rssi: i8,
+        connectable: bool,
+        scan_response: bool,
+        complete: bool,
+        data: &[u8],
+        f: impl FnOnce(ScanReport<'_>),
+    ) {
+        let existing = self.partial.as_mut().iter().position(|p| {
+            p.as_ref()
+                .is_some_and(|p| p.addr == addr && p.sid == sid && p.scan_response == scan_response)
+        });
+
+        let idx = match existing {
+            Some(idx) => idx,
+            None if complete => {
+                f(report(addr, rssi, connectable, scan_response, data));
+                return;
+            }
+            None => {
+                let capacity = self.partial.as_mut().len();
+                if capacity == 0 {
+                    // Only possible when tracking no advertisers at all
+                    return;
+                }
+                let mut len = self.len();
+                if len == capacity {
+                    self.remove(0);
+                    len -= 1;
+                }
+                self.partial.as_mut()[len] = Some(PartialReport {
+                    addr,
+                    sid,
+                    scan_response,
+                    overflow: false,
+                    data: Vec::new(),
+                });
+                len
+            }
+        };
+
+        let partial = unwrap!(self.partial.as_mut()[idx].as_mut());
+        if partial.data.extend_from_slice(data).is_err() {
+            partial.overflow = true;
+        }
+        if complete {
+            let partial = self.remove(idx);
+            if partial.overflow {
+                warn!("[scan] dropping advertising data larger than {} bytes", L);
+            } else {
+                f(report(addr, rssi, connectable, scan_response, &partial.data));
+            }
+        }
+    }
+}
+
+fn report(addr: Address, rssi: i8, connectable: bool, scan_response: bool, data: &[u8]) -> ScanReport<'_> {
+    let (adv_data, scan_data): (&[u8], &[u8]) = if scan_response { (&[], data) } else { (data, &[]) };
+    ScanReport {
+        addr,
+        rssi,
+        connectable,
+        adv_data,
+        scan_data,
+    }
+}
+
+/// Host side filter for scan reports, installed with [`Scanner::set_filter`].
+///
+/// Every criteria that is set must match for a report to pass the filter, and the default
+/// filter accepts all reports. Criteria on the advertisement payload are evaluated on both the
+/// advertising data and the scan response data.
+#[derive(Debug, Default, Clone)]
+pub struct ScanFilter<'d> {
+    /// Minimum signal strength, in dBm.
+    pub min_rssi: Option<i8>,
+    /// Advertisers to accept. An empty list accepts any advertiser.
+    pub addresses: &'d [Address],
+    /// Service UUID that must be listed, or have service data, in the payload.
+    pub service_uuid: Option<Uuid>,
+    /// Prefix of the complete or shortened local name.
+    pub name_prefix: Option<&'d [u8]>,
+    /// Company identifier of the manufacturer specific data.
+    pub manufacturer_id: Option<u16>,
+}
+
+impl ScanFilter<'_> {
+    /// Check if a report passes the filter.
+    pub fn matches(&self, report: &ScanReport<'_>) -> bool {
+        if self.min_rssi.is_some_and(|min| report.rssi < min) {
+            return false;
+        }
+        if !self.addresses.is_empty() && !self.addresses.contains(&report.addr) {
+            return false;
+        }
+        if let Some(uuid) = &self.service_uuid {
+            if !self.any_structure(report, |s| has_service(&s, uuid)) {
+                return false;
+            }
+        }
+        if let Some(prefix) = self.name_prefix {
+            let name = |s: AdStructure<'_>| match s {
+                AdStructure::CompleteLocalName(name) | AdStructure::ShortenedLocalName(name) => {
+                    name.starts_with(prefix)
+                }
+                _ => false,
+            };
+            if !self.any_structure(report, name) {
+                return false;
+            }
+        }
+        if let Some(id) = self.manufacturer_id {
+            let manufacturer = |s: AdStructure<'_>| matches!(s, AdStructure::ManufacturerSpecificData { company_identifier, .. } if company_identifier == id);
+            if !self.any_structure(report, manufacturer) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn any_structure(&self, report: &ScanReport<'_>, mut f: impl FnMut(AdStructure<'_>) -> bool) -> bool {
+        report.structures().any(|s| s.is_ok_and(&mut f))
+    }
+}
+
+fn has_service(s: &AdStructure<'_>, uuid: &Uuid) -> bool {
+    match (s, uuid) {
+        (AdStructure::ServiceUuids16(uuids), Uuid::Uuid16(u)) => uuids.contains(u),
+        (AdStructure::ServiceUuids128(uuids), Uuid::Uuid128(u)) => uuids.contains(u),
+        (AdStructure::ServiceData16 { uuid, .. }, Uuid::Uuid16(u)) => uuid == u,
+        (AdStructure::ServiceData128 { uuid, .. }, Uuid::Uuid128(u)) => uuid == u,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bt_hci::param::BdAddr;
+
+    use super::*;
+
+    fn addr(last: u8) -> Address {
+        Address {
+            kind: AddrKind::RANDOM,
+            addr: BdAddr::new([1, 2, 3, 4, 5, last]),
+        }
+    }
+
+    #[test]
+    fn scan_response_is_merged() {
+        let mut merger: ScanResponseMerger<2> = ScanResponseMerger::new();
+        let now = Instant::from_secs(1);
+        let mut reports = 0;
+        merger
+            .inner
+            .handle(now, addr(1), -40, true, true, false, &[1, 2], |_| reports += 1);
+        merger.inner.handle(now, addr(2), -50, false, false, false, &[3], |r| {
+            assert_eq!(r.addr, addr(2));
+            assert!(r.scan_data.is_empty());
+            reports += 1;
+        });
+        assert_eq!(reports, 1);
+        merger
+            .inner
+            .handle(now, addr(1), -41, false, false, true, &[4, 5], |r| {
+                assert_eq!(r.addr, addr(1));
+                assert!(r.connectable);
+                assert_eq!(r.adv_data, &[1, 2]);
+                assert_eq!(r.scan_data, &[4, 5]);
+                reports += 1;
+            });
+        assert_eq!(reports, 2);
+        merger.inner.flush(|_| reports += 1);
+        assert_eq!(reports, 2);
+    }
+
+    #[test]
+    fn scan_filter_criteria() {
+        let adv_data = [
+            0x02, 0x01, 0x06, 0x03, 0x03, 0x0f, 0x18, 0x05, 0x09, b'a', b'b', b'c', b'd',
+        ];
+        let scan_data = [0x05, 0xff, 0x59, 0x00, 0x01, 0x02];
+        let report = ScanReport {
+            addr: addr(1),
+            rssi: -60,
+            connectable: true,
+            adv_data: &adv_data,
+            scan_data: &scan_data,
+        };
+        assert!(ScanFilter::default().matches(&report));
+        assert_eq!(report.name(), Some(&b"abcd"[..]));
+        assert_eq!(report.manufacturer_data(0x0059), Some(&[0x01, 0x02][..]));
+        assert_eq!(report.manufacturer_data(0x004c), None);
+
+        let filter = ScanFilter {
+            min_rssi: Some(-70),
+            addresses: &[addr(2), addr(1)],
+            service_uuid: Some(Uuid::new_short(0x180f)),
+            name_prefix: Some(b"ab"),
+            manufacturer_id: Some(0x0059),
+        };
+        assert!(filter.matches(&report));
+
+        assert!(
+            !ScanFilter {
+                min_rssi: Some(-50),
+                ..filter.clone()
+            }
+            .matches(&report)
+        );
+        assert!(
+            !ScanFilter {
+                addresses: &[addr(2)],
+                ..filter.clone()
+            }
+            .matches(&report)
+        );
+        assert!(
+            !ScanFilter {
+                service_uuid: Some(Uuid::new_short(0x180d)),
+                ..filter.clone()
+            }
+            .matches(&report)
+        );
+        assert!(
+            !ScanFilter {
+                name_prefix: Some(b"b"),
+                ..filter.clone()
+            }
+            .matches(&report)
+        );
+        assert!(
+            !ScanFilter {
+                manufacturer_id: Some(0x004c),
+                ..filter
+            }
+            .matches(&report)
+        );
+    }
+
+    #[test]
+    fn ext_adv_fragments_are_reassembled() {
+        let mut reassembler: ExtAdvReassembler<2, 8> = ExtAdvReassembler::new();
+        let mut payload = Vec::<u8, 8>::new();
+        reassembler
+            .inner
+            .handle(addr(1), 0, -40, false, false, false, &[1, 2, 3], |_| panic!());
+        reassembler
+            .inner
+            .handle(addr(2), 0, -40, false, false, true, &[9], |r| {
+                assert_eq!(r.adv_data, &[9]);
+            });
+        reassembler
+            .inner
+            .handle(addr(1), 0, -40, false, false, false, &[4, 5], |_| panic!());
+        reassembler
+            .inner
+            .handle(addr(1), 0, -41, false, false, true, &[6], |r| {
+                assert_eq!(r.addr, addr(1));
+                payload.extend_from_slice(r.adv_data).unwrap();
+            });
+        assert_eq!(&payload[..], &[1, 2, 3, 4, 5, 6]);
+
+        // Sets of the same advertiser are reassembled separately
+        payload.clear();
+        reassembler
+            .inner
+            .handle(addr(1), 1, -40, false, false, false, &[1], |_| panic!());
+        reassembler
+            .inner
+            .handle(addr(1), 2, -40, false, false, false, &[2], |_| panic!());
+        reassembler
+            .inner
+            .handle(addr(1), 1, -40, false, false, true, &[3], |r| {
+                payload.extend_from_slice(r.adv_data).unwrap();
+            });
+        assert_eq!(&payload[..], &[1, 3]);
+        reassembler
+            .inner
+            .handle(addr(1), 2, -40, false, false, true, &[4], |r| {
+                assert_eq!(r.adv_data, &[2, 4]);
+            });
+
+        // Payloads that are too large are dropped
+        reassembler
+            .inner
+            .handle(addr(1), 0, -40, false, false, false, &[0; 6], |_| panic!());
+        reassembler
+            .inner
+            .handle(addr(1), 0, -40, false, false, true, &[0; 6], |_| panic!());
+    }
+
+    #[test]
+    fn ext_adv_reports_are_decoded() {
+        #[rustfmt::skip]
+        let bytes = [
+            2,
+            // Connectable legacy advertisement, public address
+            0x13, 0x00, 0x00, 1, 2, 3, 4, 5, 6,
+            // 1M PHYs, no SID, 127 dBm TX power, -60 dBm RSSI, no interval, no direct address
+            0x01, 0x00, 0xff, 0x7f, 0xc4, 0x00, 0x00, 0x00, 0, 0, 0, 0, 0, 0,
+            0x03, 0x02, 0x01, 0x06,
+            // Extended advertisement of SID 3 with more data expected
+            0x20, 0x00, 0x01, 6, 5, 4, 3, 2, 1,
+            0x01, 0x02, 0x03, 0x04, 0xb0, 0x00, 0x00, 0x00, 0, 0, 0, 0, 0, 0,
+            0x01, 0xaa,
+        ];
+        let reports = LeExtAdvReports::from_hci_bytes_complete(&bytes).unwrap();
+        let mut iter = ExtAdvReport::iter(&reports);
+
+        let first = iter.next().unwrap().unwrap();
+        assert!(first.event_kind.legacy() && first.event_kind.connectable());
+        assert_eq!(first.addr, BdAddr::new([1, 2, 3, 4, 5, 6]));
+        assert_eq!(first.adv_sid, 0xff);
+        assert_eq!(first.tx_power, 127);
+        assert_eq!(first.rssi, -60);
+        assert_eq!(first.data, &[0x02, 0x01, 0x06]);
+
+        let second = iter.next().unwrap().unwrap();
+        assert!(matches!(
+            second.event_kind.data_status(),
+            LeExtAdvDataStatus::IncompleteMoreExpected
+        ));
+        assert_eq!(second.addr_kind, AddrKind::RANDOM);
+        assert_eq!(second.adv_sid, 3);
+        assert_eq!(second.tx_power, 4);
+        assert_eq!(second.rssi, -80);
+        assert_eq!(second.data, &[0xaa]);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn periodic_reports_are_taken_per_sync() {
+        fn sync(handle: u8) -> SyncHandle {
+            SyncHandle::from_hci_bytes(&[handle, 0]).unwrap().0
+        }
+        fn report(handle: u8, data: &[u8]) -> LePeriodicAdvertisingReport<'_> {
+            LePeriodicAdvertisingReport {
+                sync_handle: sync(handle),
+                tx_power: 0,
+                rssi: -50,
+                cte_kind: Default::default(),
+                data_status: DataStatus::Complete,
+                data,
+            }
+        }
+        let take = |queue: &PeriodicReportQueue<'_>, handle: u8| {
+            let waker = futures::task::noop_waker();
+            let mut cx = Context::from_waker(&waker);
+            queue.poll_take(&mut cx, sync(handle))
+        };
+
+        let mut entries = [const { None }; 3];
+        let queue = PeriodicReportQueue::new(&mut entries);
+        queue.push(&report(1, &[1]));
+        queue.push(&report(2, &[2]));
+        queue.push(&report(1, &[3]));
+        // Dropped while the queue is full
+        queue.push(&report(2, &[4]));
+
+        assert!(matches!(take(&queue, 1), Poll::Ready(Some(r)) if r.data == [1]));
+        assert!(matches!(take(&queue, 1), Poll::Ready(Some(r)) if r.data == [3]));
+        assert!(take(&queue, 1).is_pending());
+
+        queue.push(&report(1, &[5]));
+        queue.push(&report(2, &[6]));
+        // The reports of a lost sync are replaced by its end
+        queue.lost(sync(1));
+        assert!(matches!(take(&queue, 1), Poll::Ready(None)));
+        assert!(matches!(take(&queue, 2), Poll::Ready(Some(r)) if r.data == [2]));
+        assert!(matches!(take(&queue, 2), Poll::Ready(Some(r)) if r.data == [6]));
+
+        queue.push(&report(2, &[7]));
+        queue.purge(sync(2));
+        assert!(take(&queue, 2).is_pending());
+    }
+
+    #[test]
+    fn oldest_pending_is_evicted() {
+        let mut merger: ScanResponseMerger<1> = ScanResponseMerger::new();
+        let now = Instant::from_secs(1);
+        let mut seen = Vec::<Address, 4>::new();
+        merger.inner.handle(now, addr(1), -40, true, true, false, &[1], |r| {
+            seen.push(r.addr).unwrap()
+        });
+        merger.inner.handle(now, addr(2), -40, true, true, false, &[2], |r| {
+            seen.push(r.addr).unwrap()
+        });
+        assert_eq!(&seen[..], &[addr(1)]);
+        merger.inner.flush(|r| seen.push(r.addr).unwrap());
+        assert_eq!(&seen[..], &[addr(1), addr(2)]);
+    }
+
+    #[test]
+    fn pending_without_scan_response_expires() {
+        let mut merger: ScanResponseMerger<2> = ScanResponseMerger::with_timeout(Duration::from_millis(100));
+        let start = Instant::from_secs(1);
+        let mut seen = Vec::<Address, 4>::new();
+        merger.inner.handle(start, addr(1), -40, true, true, false, &[1], |r| {
+            seen.push(r.addr).unwrap()
+        });
+        let later = start + Duration::from_millis(50);
+        merger.inner.handle(later, addr(2), -40, true, true, false, &[2], |r| {
+            seen.push(r.addr).unwrap()
+        });
+        merger
+            .inner
+            .expire(start + Duration::from_millis(99), |r| seen.push(r.addr).unwrap());
+        assert!(seen.is_empty());
+
+        // A late scan response is reported on its own
+        let expired = start + Duration::from_millis(120);
+        merger
+            .inner
+            .handle(expired, addr(1), -40, false, false, true, &[3], |r| {
+                assert!(r.adv_data.is_empty() || r.scan_data.is_empty());
+                seen.push(r.addr).unwrap()
+            });
+        assert_eq!(&seen[..], &[addr(1), addr(1)]);
+        merger
+            .inner
+            .expire(later + Duration::from_millis(100), |r| seen.push(r.addr).unwrap());
+        assert_eq!(&seen[..], &[addr(1), addr(1), addr(2)]);
+    }
+
+    #[test]
+    fn merged_reports_are_queued_with_scan_response() {
+        let mut pending = [const { None }; 1];
+        let mut merger = Merger::new(&mut pending[..], SCAN_RESPONSE_TIMEOUT);
+        let mut entries = [const { None }; 2];
+        let queue = ScanReportQueue::new(&mut entries);
+        let now = Instant::from_secs(1);
+        merger.handle(now, addr(1), -40, true, true, false, &[1, 2], |r| queue.push(&r));
+        merger.handle(now, addr(1), -42, false, false, true, &[3], |r| queue.push(&r));
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let Poll::Ready(Some(queued)) = queue.poll_receive(&mut cx) else {
+            panic!("expected a queued report");
+        };
+        assert_eq!(queued.rssi, -42);
+        assert!(queued.connectable);
+        assert_eq!(&queued.adv_data[..], &[1, 2]);
+        assert_eq!(&queued.scan_data[..], &[3]);
+        assert!(queue.poll_receive(&mut cx).is_pending());
+
+        // Without entries, advertisements and scan responses are queued separately
+        let mut merger = Merger::new(&mut [][..], SCAN_RESPONSE_TIMEOUT);
+        merger.handle(now, addr(2), -40, true, true, false, &[4], |r| queue.push(&r));
+        merger.handle(now, addr(2), -40, false, false, true, &[5], |r| queue.push(&r));
+        let Poll::Ready(Some(adv)) = queue.poll_receive(&mut cx) else {
+            panic!("expected a queued report");
+        };
+        assert!(adv.scan_data.is_empty());
+        let Poll::Ready(Some(rsp)) = queue.poll_receive(&mut cx) else {
+            panic!("expected a queued report");
+        };
+        assert!(rsp.adv_data.is_empty());
+        assert_eq!(&rsp.scan_data[..], &[5]);
+    }
+
+    #[test]
+    fn reassembled_payloads_are_queued_whole() {
+        let mut fragments: HostReassembler = Reassembler::new([const { None }; crate::config::SCAN_REASSEMBLY_SIZE]);
+        let mut entries = [const { None }; 1];
+        let queue = ScanReportQueue::new(&mut entries);
+        for chunk in [[1; 229], [2; 229]] {
+            fragments.handle(addr(1), 3, -40, false, false, false, &chunk, |r| queue.push(&r));
+        }
+        fragments.handle(addr(1), 3, -40, false, false, true, &[3; 100], |r| queue.push(&r));
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let Poll::Ready(Some(queued)) = queue.poll_receive(&mut cx) else {
+            panic!("expected a queued report");
+        };
+        assert_eq!(queued.adv_data.len(), 558);
+        assert!(queued.adv_data[..229].iter().all(|b| *b == 1));
+        assert_eq!(queued.adv_data[557], 3);
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[test]
+    fn duplicate_filter_evicts_least_recently_seen() {
+        let mut entries = [None; 2];
+        let filter = DuplicateFilter::new(&mut entries);
+        let a = report(addr(1), -40, true, false, &[1]);
+        let b = report(addr(2), -40, true, false, &[2]);
+        let c = report(addr(3), -40, true, false, &[3]);
+
+        assert!(!filter.is_duplicate(&a));
+        assert!(!filter.is_duplicate(&b));
+        assert!(filter.is_duplicate(&a));
+        // Changed payloads are not duplicates
+        assert!(!filter.is_duplicate(&report(addr(1), -40, true, false, &[4])));
+        assert!(!filter.is_duplicate(&c));
+        assert!(!filter.is_duplicate(&a));
+
+        filter.clear();
+        assert!(!filter.is_duplicate(&c));
+        assert!(!DuplicateFilter::new(&mut []).is_duplicate(&c));
+    }
+
+    #[test]
+    fn full_report_queue_counts_drops() {
+        let mut entries = [const { None }; 4];
+        let queue = ScanReportQueue::new(&mut entries);
+        for i in 0..4 {
+            queue.push(&report(addr(i as u8), -40, true, i % 2 == 1, &[i as u8]));
+        }
+        queue.push(&report(addr(0xff), -40, true, false, &[]));
+        queue.push(&report(
+            addr(0xff),
+            -40,
+            true,
+            false,
+            &[0; crate::config::SCAN_REPORT_DATA_SIZE + 1],
+        ));
+        assert_eq!(queue.dropped(), 2);
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let Poll::Ready(Some(queued)) = queue.poll_receive(&mut cx) else {
+            panic!("expected a queued report");
+        };
+        assert_eq!(queued.addr, addr(0));
+        assert_eq!(queued.report().adv_data, &[0]);
+        // The freed slot takes the next report after the oldest ones
+        queue.push(&report(addr(4), -40, true, false, &[4]));
+        for i in 1..5 {
+            let Poll::Ready(Some(queued)) = queue.poll_receive(&mut cx) else {
+                panic!("expected a queued report");
+            };
+            assert_eq!(queued.addr, addr(i));
+        }
+
+        // Closing ends the session once the queued reports are taken
+        queue.push(&report(addr(5), -40, true, false, &[5]));
+        queue.close();
+        assert!(matches!(queue.poll_receive(&mut cx), Poll::Ready(Some(_))));
+        assert!(matches!(queue.poll_receive(&mut cx), Poll::Ready(None)));
+
+        queue.push(&report(addr(5), -40, true, false, &[5]));
+        queue.clear();
+        assert_eq!(queue.dropped(), 0);
+        assert!(queue.poll_receive(&mut cx).is_pending());
+        assert_eq!(ScanReportQueue::new(&mut []).dropped(), 0);
+    }
+}
@@ -0,0 +1,2317 @@
+//! Security manager, pairing with peers and encrypting the links of bonded peers.
+//!
+//! With the `security` feature, the host pairs using LE Secure Connections, or LE legacy pairing
+//! with peers that do not support it unless
+//! [`Stack::set_secure_connections_only`](crate::Stack::set_secure_connections_only) is used.
+//! The pairing is authenticated with Passkey Entry when the IO capabilities of both devices allow
+//! it, the local device displaying the passkey reported by [`HostEvent::PasskeyDisplay`], and
+//! uses Just Works otherwise. The devices then distribute their keys and bond, and the links of
+//! bonded peers are encrypted again with the long term key on reconnection.
+//!
+//! Pairing needs a random generator seeded with
+//! [`Stack::set_random_generator_seed`](crate::Stack::set_random_generator_seed), and one
+//! pairing may be in progress at a time. LE Secure Connections exchanges public keys of 65 octets,
+//! which needs packets of at least [`SECURE_CONNECTIONS_MIN_MTU`] octets: hosts with smaller
+//! packets do not ask for LE Secure Connections, and pair with legacy pairing. After a failed
+//! pairing, a peer is refused with `RepeatedAttempts` for 2 seconds, doubled after each further
+//! failure up to 128 seconds, against brute forcing of the passkey. This is best effort: bonded peers are tracked by their
+//! identity, but other peers by the address they connect with, so a peer changing its address
+//! pairs again right away, as does a peer forgotten when more than
+//! `PAIRING_ATTEMPTS_TABLE_SIZE` peers failed to pair.
+//!
+//! For debugging with a sniffer, the `security-debug-keys` feature adds
+//! `Stack::use_security_debug_keys`, pairing with the published debug key of LE Secure Connections
+//! so that the sniffer can decrypt the links. It must never be enabled in production.
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::{Context, Poll};
+
+use bt_hci::controller::Controller;
+use bt_hci::param::{AddrKind, BdAddr, ConnHandle, LeConnRole};
+use embassy_futures::select::{Either, select};
+use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::{Duration, Instant, Timer};
+use rand_chacha::ChaCha12Rng;
+use rand_core::{RngCore, SeedableRng};
+
+use crate::connection::SecurityLevel;
+use crate::cursor::WriteCursor;
+use crate::event::HostEvent;
+use crate::host::BleHost;
+use crate::pdu::Pdu;
+use crate::procedure::{self, Procedure};
+use crate::types::l2cap::{L2CAP_CID_LE_U_SECURITY_MANAGER, L2capHeader};
+use crate::{Address, Error, config};
+
+pub(crate) mod crypto;
+pub(crate) mod ecdh;
+
+use ecdh::{PublicKey, SecretKey};
+
+/// Smallest packet size of the host resources allowing LE Secure Connections pairing.
+pub const SECURE_CONNECTIONS_MIN_MTU: usize = 4 + MAX_PDU_LEN;
+
+/// The length of the longest command of the security manager, the public key of LE Secure Connections.
+pub(crate) const MAX_PDU_LEN: usize = 65;
+
+const PAIRING_REQUEST: u8 = 0x01;
+const PAIRING_RESPONSE: u8 = 0x02;
+const PAIRING_CONFIRM: u8 = 0x03;
+const PAIRING_RANDOM: u8 = 0x04;
+const PAIRING_FAILED: u8 = 0x05;
+const ENCRYPTION_INFORMATION: u8 = 0x06;
+const CENTRAL_IDENTIFICATION: u8 = 0x07;
+const IDENTITY_INFORMATION: u8 = 0x08;
+const IDENTITY_ADDRESS_INFORMATION: u8 = 0x09;
+const SIGNING_INFORMATION: u8 = 0x0a;
+const SECURITY_REQUEST: u8 = 0x0b;
+const PAIRING_PUBLIC_KEY: u8 = 0x0c;
+const PAIRING_DHKEY_CHECK: u8 = 0x0d;
+const PAIRING_KEYPRESS_NOTIFICATION: u8 = 0x0e;
+
+const AUTH_BONDING: u8 = 0x01;
+const AUTH_MITM: u8 = 0x04;
+const AUTH_SECURE_CONNECTIONS: u8 = 0x08;
+
+const KEY_ENC: u8 = 0x01;
+const KEY_ID: u8 = 0x02;
+const KEY_SIGN: u8 = 0x04;
+
+const MIN_KEY_SIZE: u8 = 7;
+const MAX_KEY_SIZE: u8 = 16;
+
+// Rounds of Passkey Entry, one for each bit of the passkey.
+const PASSKEY_ROUNDS: u8 = 20;
+
+// Waiting interval after the first failed pairing of a peer, doubled after each of the next
+// ones up to 2^6 times, before it may pair again.
+const REPEATED_ATTEMPTS_INTERVAL: Duration = Duration::from_secs(2);
+const REPEATED_ATTEMPTS_MAX_DOUBLINGS: u32 = 6;
+
+/// Length of the signature ending a signed write command, its sign counter and MAC.
+pub(crate) const SIGNATURE_LEN: usize = 12;
+
+// Time allowed between two commands of a pairing before it fails.
+const PAIRING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// IO capabilities of the local device, which decide whether pairing can be authenticated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum IoCapabilities {
+    /// The device can display a six digit passkey, reported by [`HostEvent::PasskeyDisplay`].
+    DisplayOnly,
+    /// The device has no input or output, pairing with Just Works.
+    #[default]
+    NoInputNoOutput,
+}
+
+impl IoCapabilities {
+    fn to_raw(self) -> u8 {
+        match self {
+            Self::DisplayOnly => 0x00,
+            Self::NoInputNoOutput => 0x03,
+        }
+    }
+}
+
+// IO capabilities of a peer.
+const IO_DISPLAY_ONLY: u8 = 0x00;
+const IO_DISPLAY_YES_NO: u8 = 0x01;
+const IO_KEYBOARD_ONLY: u8 = 0x02;
+const IO_NO_INPUT_NO_OUTPUT: u8 = 0x03;
+const IO_KEYBOARD_DISPLAY: u8 = 0x04;
+
+/// Reason of a pairing failure, sent by the device aborting the pairing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Reason {
+    /// The user input of the passkey failed.
+    PasskeyEntryFailed,
+    /// The OOB data is not available.
+    OobNotAvailable,
+    /// The authentication requirements cannot be met with the IO capabilities of the devices.
+    AuthenticationRequirements,
+    /// The confirm value does not match the calculated value.
+    ConfirmValueFailed,
+    /// Pairing is not supported by the device.
+    PairingNotSupported,
+    /// The resulting key size is not long enough for the security requirements of the device.
+    EncryptionKeySize,
+    /// The SMP command received is not supported.
+    CommandNotSupported,
+    /// Pairing failed for a reason not listed here.
+    UnspecifiedReason,
+    /// Pairing was attempted again too soon after a failure.
+    RepeatedAttempts,
+    /// An SMP command had invalid parameters.
+    InvalidParameters,
+    /// The DHKey check value does not match the calculated value.
+    DhKeyCheckFailed,
+    /// The confirm values of numeric comparison do not match.
+    NumericComparisonFailed,
+    /// Pairing over BR/EDR is in progress.
+    BrEdrPairingInProgress,
+    /// The BR/EDR link key or the LE LTK cannot be derived from the other.
+    CrossTransportKeyDerivationNotAllowed,
+    /// The device rejected the keys distributed.
+    KeyRejected,
+}
+
+impl Reason {
+    fn to_raw(self) -> u8 {
+        match self {
+            Self::PasskeyEntryFailed => 0x01,
+            Self::OobNotAvailable => 0x02,
+            Self::AuthenticationRequirements => 0x03,
+            Self::ConfirmValueFailed => 0x04,
+            Self::PairingNotSupported => 0x05,
+            Self::EncryptionKeySize => 0x06,
+            Self::CommandNotSupported => 0x07,
+            Self::UnspecifiedReason => 0x08,
+            Self::RepeatedAttempts => 0x09,
+            Self::InvalidParameters => 0x0a,
+            Self::DhKeyCheckFailed => 0x0b,
+            Self::NumericComparisonFailed => 0x0c,
+            Self::BrEdrPairingInProgress => 0x0d,
+            Self::CrossTransportKeyDerivationNotAllowed => 0x0e,
+            Self::KeyRejected => 0x0f,
+        }
+    }
+
+    fn from_raw(raw: u8) -> Self {
+        match raw {
+            0x01 => Self::PasskeyEntryFailed,
+            0x02 => Self::OobNotAvailable,
+            0x03 => Self::AuthenticationRequirements,
+            0x04 => Self::ConfirmValueFailed,
+            0x05 => Self::PairingNotSupported,
+            0x06 => Self::EncryptionKeySize,
+            0x07 => Self::CommandNotSupported,
+            0x09 => Self::RepeatedAttempts,
+            0x0a => Self::InvalidParameters,
+            0x0b => Self::DhKeyCheckFailed,
+            0x0c => Self::NumericComparisonFailed,
+            0x0d => Self::BrEdrPairingInProgress,
+            0x0e => Self::CrossTransportKeyDerivationNotAllowed,
+            0x0f => Self::KeyRejected,
+            _ => Self::UnspecifiedReason,
+        }
+    }
+}
+
+/// A 128-bit key of the security manager.
+///
+/// The value of the key is not shown by `Debug` and `defmt::Format`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Key(u128);
+
+impl Key {
+    /// Create a key from its value.
+    pub const fn new(value: u128) -> Self {
+        Self(value)
+    }
+
+    /// Create a key from its octets, least significant first as sent to peers and to the controller.
+    pub const fn from_le_bytes(bytes: [u8; 16]) -> Self {
+        Self(u128::from_le_bytes(bytes))
+    }
+
+    /// The value of the key.
+    pub const fn value(&self) -> u128 {
+        self.0
+    }
+
+    /// The octets of the key, least significant first as sent to peers and to the controller.
+    pub const fn to_le_bytes(&self) -> [u8; 16] {
+        self.0.to_le_bytes()
+    }
+}
+
+impl core::fmt::Debug for Key {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Key {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "Key(..)")
+    }
+}
+
+/// What to do when pairing with a new peer while the bond table is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum BondEvictionPolicy {
+    /// Pair without bonding, keeping the existing bonds.
+    #[default]
+    RejectNew,
+    /// Forget the bond least recently used to encrypt a link.
+    EvictLeastRecentlyUsed,
+}
+
+/// The keys of a bonded peer.
+///
+/// The host keeps the bonds in memory only, up to `BOND_TABLE_SIZE` of them. Applications persist
+/// them by reading them with `Stack::bonds` and restoring them with `Stack::add_bond_information`.
+/// The sign counters change with every signed write, so bonds with signing keys must be persisted
+/// again after signed writes for replayed writes to be rejected after a reboot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct BondInformation {
+    /// Identity address of the peer, or the address it paired with if it distributed none.
+    pub identity: Address,
+    /// Identity resolving key of the peer, resolving its private addresses.
+    pub irk: Option<Key>,
+    /// Long term key encrypting the links with the peer.
+    pub ltk: Key,
+    /// Encrypted diversifier identifying a long term key of legacy pairing, zero otherwise.
+    pub ediv: u16,
+    /// Random number identifying a long term key of legacy pairing, zero otherwise.
+    pub rand: [u8; 8],
+    /// Security level of the links encrypted with the long term key.
+    pub security_level: SecurityLevel,
+    /// Signing key of the local device, signing its writes to the peer.
+    pub local_csrk: Option<Key>,
+    /// Sign counter of the next write signed with `local_csrk`.
+    pub local_sign_counter: u32,
+    /// Signing key of the peer, verifying its signed writes.
+    pub peer_csrk: Option<Key>,
+    /// Lowest sign counter accepted in the next signed write of the peer, rejecting replayed writes.
+    pub peer_sign_counter: u32,
+}
+
+impl BondInformation {
+    /// Create the bond of a peer with a long term key of LE Secure Connections.
+    pub fn new(identity: Address, ltk: Key, security_level: SecurityLevel) -> Self {
+        Self {
+            identity,
+            irk: None,
+            ltk,
+            ediv: 0,
+            rand: [0; 8],
+            security_level,
+            local_csrk: None,
+            local_sign_counter: 0,
+            peer_csrk: None,
+            peer_sign_counter: 0,
+        }
+    }
+
+    // Check if a peer address is the identity of the bond, or a private address resolved by its IRK.
+    pub(crate) fn matches(&self, peer: &Address) -> bool {
+        (identity_kind(peer.kind) == identity_kind(self.identity.kind) && peer.addr == self.identity.addr)
+            || self.irk.is_some_and(|irk| resolves(irk, &peer.addr))
+    }
+}
+
+// The type of an identity address, of a peer resolved by the controller or as exchanged in SMP.
+fn identity_kind(kind: AddrKind) -> AddrKind {
+    match kind {
+        AddrKind::RESOLVABLE_PRIVATE_OR_PUBLIC => AddrKind::PUBLIC,
+        AddrKind::RESOLVABLE_PRIVATE_OR_RANDOM => AddrKind::RANDOM,
+        kind => kind,
+    }
+}
+
+// Check if a resolvable private address was generated from an IRK.
+fn resolves(irk: Key, addr: &BdAddr) -> bool {
+    let raw = addr.raw();
+    // The two most significant bits of a resolvable private address are 0b01.
+    if raw[5] >> 6 != 0b01 {
+        return false;
+    }
+    let hash = u32::from_le_bytes([raw[0], raw[1], raw[2], 0]);
+    let prand = u32::from_le_bytes([raw[3], raw[4], raw[5], 0]);
+    crypto::ah(irk.0, prand) == hash
+}
+
+// An address as used in SMP: the address type, 0 for public and 1 for random, and the address.
+#[derive(Clone, Copy)]
+struct SmpAddress {
+    kind: u8,
+    addr: [u8; 6],
+}
+
+impl SmpAddress {
+    fn new(kind: AddrKind, addr: &BdAddr) -> Self {
+        let mut raw = [0; 6];
+        raw.copy_from_slice(addr.raw());
+        Self {
+            kind: identity_kind(kind).into_inner() & 1,
+            addr: raw,
+        }
+    }
+
+    // The octets of the f5 and f6 functions, the type and the address most significant octet first.
+    fn octets(&self) -> [u8; 7] {
+        let a = self.addr;
+        [self.kind, a[5], a[4], a[3], a[2], a[1], a[0]]
+    }
+}
+
+/// A command of the security manager run by the control runner of the host.
+#[derive(Clone, Copy)]
+pub(crate) enum Action {
+    /// Reply to the long term key request of the controller of a peripheral.
+    LtkReply { handle: ConnHandle, ltk: Key },
+    /// Tell the controller of a peripheral that no key is known for the link.
+    LtkNegativeReply { handle: ConnHandle },
+    /// Start the encryption of the link of a central.
+    EnableEncryption {
+        handle: ConnHandle,
+        ltk: Key,
+        ediv: u16,
+        rand: [u8; 8],
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum Step {
+    PairingResponse,
+    PublicKey,
+    Confirm,
+    Random,
+    DhKeyCheck,
+    Encryption,
+    Keys,
+}
+
+struct Pairing {
+    handle: ConnHandle,
+    initiator: bool,
+    step: Step,
+    deadline: Instant,
+    preq: [u8; 7],
+    pres: [u8; 7],
+    initiator_address: SmpAddress,
+    responder_address: SmpAddress,
+    peer: Address,
+    secure_connections: bool,
+    passkey: Option<u32>,
+    bonding: bool,
+    key_size: u8,
+    // Keys distributed by the local device and by the peer.
+    local_keys: u8,
+    peer_keys: u8,
+    secret_key: Option<SecretKey>,
+    local_public_key: Option<PublicKey>,
+    peer_public_key: Option<PublicKey>,
+    dh_key: [u8; 32],
+    local_nonce: u128,
+    peer_nonce: u128,
+    peer_confirm: u128,
+    round: u8,
+    // The STK or LTK encrypting the link.
+    key: u128,
+    ltk_distributed: bool,
+    security_level: SecurityLevel,
+    bond: BondInformation,
+}
+
+impl Pairing {
+    fn local_public_key(&self) -> &PublicKey {
+        unwrap!(self.local_public_key.as_ref())
+    }
+
+    fn peer_public_key(&self) -> &PublicKey {
+        unwrap!(self.peer_public_key.as_ref())
+    }
+
+    // The value of r of the confirm values of Passkey Entry for the current round.
+    fn passkey_bit(&self) -> u8 {
+        match self.passkey {
+            Some(passkey) => 0x80 | ((passkey >> self.round) & 1) as u8,
+            None => 0,
+        }
+    }
+
+    // The confirm value of the local device in LE Secure Connections.
+    fn local_sc_confirm(&self) -> u128 {
+        crypto::f4(
+            &self.local_public_key().x,
+            &self.peer_public_key().x,
+            self.local_nonce,
+            self.passkey_bit(),
+        )
+    }
+
+    fn peer_sc_confirm(&self) -> u128 {
+        crypto::f4(
+            &self.peer_public_key().x,
+            &self.local_public_key().x,
+            self.peer_nonce,
+            self.passkey_bit(),
+        )
+    }
+
+    fn legacy_confirm(&self, random: u128) -> u128 {
+        let (ia, ra) = (self.initiator_address, self.responder_address);
+        crypto::c1(
+            self.passkey.unwrap_or(0) as u128,
+            random,
+            self.preq,
+            self.pres,
+            ia.kind,
+            ia.addr,
+            ra.kind,
+            ra.addr,
+        )
+    }
+
+    // The nonces of the initiator and of the responder.
+    fn nonces(&self) -> (u128, u128) {
+        if self.initiator {
+            (self.local_nonce, self.peer_nonce)
+        } else {
+            (self.peer_nonce, self.local_nonce)
+        }
+    }
+
+    // The DHKey check values of the initiator and of the responder, computing the LTK.
+    fn dh_key_checks(&mut self) -> (u128, u128) {
+        let (na, nb) = self.nonces();
+        let (a, b) = (self.initiator_address.octets(), self.responder_address.octets());
+        let (mac_key, ltk) = crypto::f5(&self.dh_key, na, nb, &a, &b);
+        self.key = mask_key(ltk, self.key_size);
+        let r = self.passkey.unwrap_or(0) as u128;
+        let io_cap = |cmd: &[u8; 7]| [cmd[3], cmd[2], cmd[1]];
+        let ea = crypto::f6(mac_key, na, nb, r, io_cap(&self.preq), &a, &b);
+        let eb = crypto::f6(mac_key, nb, na, r, io_cap(&self.pres), &b, &a);
+        (ea, eb)
+    }
+}
+
+// Keep the octets of a key within the negotiated key size, setting the most significant ones to zero.
+fn mask_key(key: u128, key_size: u8) -> u128 {
+    if key_size >= MAX_KEY_SIZE {
+        key
+    } else {
+        key & ((1 << (key_size as u32 * 8)) - 1)
+    }
+}
+
+// Passkey Entry is used if the local device displays and the peer has a keyboard.
+fn uses_passkey(local: IoCapabilities, peer_io: u8, mitm: bool) -> bool {
+    mitm && local == IoCapabilities::DisplayOnly && matches!(peer_io, IO_KEYBOARD_ONLY | IO_KEYBOARD_DISPLAY)
+}
+
+struct State {
+    rng: Option<ChaCha12Rng>,
+    io_capabilities: IoCapabilities,
+    secure_connections_only: bool,
+    eviction_policy: BondEvictionPolicy,
+    #[cfg(feature = "security-debug-keys")]
+    debug_keys: bool,
+    local_irk: Option<Key>,
+    public_address: Option<BdAddr>,
+    bonds: heapless::Vec<BondInformation, { config::BOND_TABLE_SIZE }>,
+    pairing: Option<Pairing>,
+    attempts: heapless::Vec<Attempts, { config::PAIRING_ATTEMPTS_TABLE_SIZE }>,
+    actions: heapless::Deque<Action, 4>,
+    action_waker: WakerRegistration,
+    // Set when the deadline of the pairing changed, for the timer to be restarted.
+    deadline_changed: bool,
+}
+
+// The failed pairing attempts of a peer, which may not pair again before the deadline. The peer
+// is the identity of its bond when its address resolves to one.
+struct Attempts {
+    peer: Address,
+    failures: u32,
+    until: Instant,
+}
+
+/// State of the security manager of a host.
+pub(crate) struct SecurityManager {
+    state: RefCell<State>,
+}
+
+impl SecurityManager {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: RefCell::new(State {
+                rng: None,
+                io_capabilities: IoCapabilities::NoInputNoOutput,
+                secure_connections_only: false,
+                eviction_policy: BondEvictionPolicy::RejectNew,
+                #[cfg(feature = "security-debug-keys")]
+                debug_keys: false,
+                local_irk: None,
+                public_address: None,
+                bonds: heapless::Vec::new(),
+                pairing: None,
+                attempts: heapless::Vec::new(),
+                actions: heapless::Deque::new(),
+                action_waker: WakerRegistration::new(),
+                deadline_changed: false,
+            }),
+        }
+    }
+
+    pub(crate) fn set_random_generator_seed(&mut self, seed: [u8; 32]) {
+        self.state.get_mut().rng = Some(ChaCha12Rng::from_seed(seed));
+    }
+
+    pub(crate) fn set_io_capabilities(&mut self, io_capabilities: IoCapabilities) {
+        self.state.get_mut().io_capabilities = io_capabilities;
+    }
+
+    pub(crate) fn set_secure_connections_only(&mut self, enabled: bool) {
+        self.state.get_mut().secure_connections_only = enabled;
+    }
+
+    #[cfg(feature = "security-debug-keys")]
+    pub(crate) fn use_debug_keys(&mut self) {
+        warn!("[security] pairing with the debug keys, links can be decrypted by anyone");
+        self.state.get_mut().debug_keys = true;
+    }
+
+    pub(crate) fn set_eviction_policy(&mut self, policy: BondEvictionPolicy) {
+        self.state.get_mut().eviction_policy = policy;
+    }
+
+    /// Security level links must reach before services are accessed.
+    pub(crate) fn required_level(&self) -> SecurityLevel {
+        if self.state.borrow().secure_connections_only {
+            SecurityLevel::SecureConnections
+        } else {
+            SecurityLevel::NoEncryption
+        }
+    }
+
+    pub(crate) fn set_public_address(&self, address: BdAddr) {
+        self.state.borrow_mut().public_address = Some(address);
+    }
+
+    pub(crate) fn set_local_irk(&self, irk: Option<Key>) {
+        self.state.borrow_mut().local_irk = irk;
+    }
+
+    pub(crate) fn add_bond(&self, bond: BondInformation) -> Result<(), Error> {
+        self.state.borrow_mut().store_bond(bond)
+    }
+
+    /// The bonds, from the least to the most recently used.
+    pub(crate) fn bonds(&self) -> heapless::Vec<BondInformation, { config::BOND_TABLE_SIZE }> {
+        self.state.borrow().bonds.clone()
+    }
+
+    pub(crate) fn remove_bond(&self, identity: &Address) -> Result<BondInformation, Error> {
+        let mut state = self.state.borrow_mut();
+        let index = state
+            .bonds
+            .iter()
+            .position(|b| b.identity == *identity)
+            .ok_or(Error::NotFound)?;
+        Ok(state.bonds.remove(index))
+    }
+
+    pub(crate) fn clear_bonds(&self) {
+        self.state.borrow_mut().bonds.clear();
+    }
+
+    /// Handle a PDU received on the security manager channel of a link.
+    pub(crate) fn handle<T: Controller>(&self, host: &BleHost<'_, T>, handle: ConnHandle, pdu: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        let Some((&code, payload)) = pdu.split_first() else {
+            return;
+        };
+        // No security manager commands may be exchanged on a link after a pairing timed out.
+        if smp_timed_out(host, handle) {
+            warn!(
+                "[security] ignoring command {} after pairing timed out on conn {:?}",
+                code, handle
+            );
+            return;
+        }
+        let result = match code {
+            PAIRING_REQUEST => state.on_pairing_request(host, handle, pdu),
+            SECURITY_REQUEST => state.on_security_request(host, handle, payload),
+            PAIRING_FAILED => {
+                let reason = Reason::from_raw(payload.first().copied().unwrap_or(0));
+                warn!("[security] peer aborted pairing on conn {:?}: {:?}", handle, reason);
+                state.pairing_failed(host, handle, reason);
+                Ok(())
+            }
+            PAIRING_KEYPRESS_NOTIFICATION => Ok(()),
+            code => match state.pairing.as_mut() {
+                Some(pairing) if pairing.handle == handle => state.on_pairing_pdu(host, code, payload),
+                _ => {
+                    warn!(
+                        "[security] ignoring command {} outside of pairing on conn {:?}",
+                        code, handle
+                    );
+                    Ok(())
+                }
+            },
+        };
+        if let Err(reason) = result {
+            state.fail(host, handle, reason);
+        }
+        state.restart_timer(handle);
+    }
+
+    /// Reject a command of the peer of a link longer than [`MAX_PDU_LEN`], which no command of the
+    /// security manager may be, failing the pairing in progress on the link.
+    pub(crate) fn reject_oversized<T: Controller>(&self, host: &BleHost<'_, T>, handle: ConnHandle) {
+        if smp_timed_out(host, handle) {
+            return;
+        }
+        let mut state = self.state.borrow_mut();
+        state.fail(host, handle, Reason::InvalidParameters);
+        state.restart_timer(handle);
+    }
+
+    /// Verify the signature of a signed write command of the peer of a link, advancing the sign
+    /// counter of its bond for the write not to be accepted again.
+    pub(crate) fn verify_signed_write<T: Controller>(
+        &self,
+        host: &BleHost<'_, T>,
+        handle: ConnHandle,
+        pdu: &[u8],
+    ) -> bool {
+        let Some(info) = host.connections.link_info(handle) else {
+            return false;
+        };
+        // The opcode, the attribute handle and the signature.
+        if pdu.len() < 3 + SIGNATURE_LEN {
+            return false;
+        }
+        let (pdu, signature) = pdu.split_at(pdu.len() - SIGNATURE_LEN);
+        let counter = u32::from_le_bytes(unwrap!(signature[..4].try_into().ok()));
+        let mut state = self.state.borrow_mut();
+        let Some(bond) = state.bonds.iter_mut().find(|b| b.matches(&info.peer)) else {
+            warn!("[security] signed write from unbonded conn {:?}", handle);
+            return false;
+        };
+        let Some(csrk) = bond.peer_csrk else {
+            warn!("[security] signed write from conn {:?} without a signing key", handle);
+            return false;
+        };
+        if counter < bond.peer_sign_counter {
+            warn!(
+                "[security] replayed signed write with counter {} on conn {:?}",
+                counter, handle
+            );
+            return false;
+        }
+        if crypto::sign(csrk.0, pdu, counter) != signature[4..] {
+            warn!("[security] signed write with an invalid signature on conn {:?}", handle);
+            return false;
+        }
+        bond.peer_sign_counter = counter.saturating_add(1);
+        true
+    }
+
+    /// Sign a write command to the peer of a link, the signature filling the last octets of the PDU.
+    pub(crate) fn sign_write<T: Controller>(
+        &self,
+        host: &BleHost<'_, T>,
+        handle: ConnHandle,
+        pdu: &mut [u8],
+    ) -> Result<(), Error> {
+        let info = host.connections.link_info(handle).ok_or(Error::Disconnected)?;
+        let mut state = self.state.borrow_mut();
+        let bond = state
+            .bonds
+            .iter_mut()
+            .find(|b| b.matches(&info.peer))
+            .ok_or(Error::NotFound)?;
+        let csrk = bond.local_csrk.ok_or(Error::NotSupported)?;
+        let counter = bond.local_sign_counter;
+        bond.local_sign_counter = counter.checked_add(1).ok_or(Error::InvalidState)?;
+        let split = pdu.len().checked_sub(SIGNATURE_LEN).ok_or(Error::InvalidValue)?;
+        let (message, signature) = pdu.split_at_mut(split);
+        signature[..4].copy_from_slice(&counter.to_le_bytes());
+        signature[4..].copy_from_slice(&crypto::sign(csrk.0, message, counter));
+        Ok(())
+    }
+
+    /// Start securing a link: encrypt it with the key of a bonded peer, or pair.
+    pub(crate) fn request_security<T: Controller>(
+        &self,
+        host: &BleHost<'_, T>,
+        handle: ConnHandle,
+    ) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        let info = host.connections.link_info(handle).ok_or(Error::Disconnected)?;
+        if info.smp_timed_out {
+            return Err(Error::Timeout);
+        }
+        if state.pairing.as_ref().is_some_and(|p| p.handle == handle) {
+            return Err(Error::Busy);
+        }
+        let result = match info.role {
+            LeConnRole::Central => {
+                if let Some(bond) = state.use_bond(|b| b.matches(&info.peer)) {
+                    let action = Action::EnableEncryption {
+                        handle,
+                        ltk: bond.ltk,
+                        ediv: bond.ediv,
+                        rand: bond.rand,
+                    };
+                    state.push_action(action);
+                    Ok(())
+                } else {
+                    state.start_pairing(host, handle).map_err(|_| Error::InvalidState)
+                }
+            }
+            LeConnRole::Peripheral => {
+                let auth = state.auth_req(host);
+                send(host, handle, &[SECURITY_REQUEST, auth])
+            }
+        };
+        state.restart_timer(handle);
+        result
+    }
+
+    /// Handle the long term key request of the controller for a link of a peripheral.
+    pub(crate) fn long_term_key_request<T: Controller>(
+        &self,
+        host: &BleHost<'_, T>,
+        handle: ConnHandle,
+        rand: [u8; 8],
+        ediv: u16,
+    ) {
+        let mut state = self.state.borrow_mut();
+        let key = match state.pairing.as_ref() {
+            Some(p) if p.handle == handle && p.step == Step::Encryption && !p.initiator => {
+                (ediv == 0 && rand == [0; 8]).then_some(Key(p.key))
+            }
+            _ => host.connections.link_info(handle).and_then(|info| {
+                state
+                    .use_bond(|b| b.matches(&info.peer) && b.ediv == ediv && b.rand == rand)
+                    .map(|b| b.ltk)
+            }),
+        };
+        let action = match key {
+            Some(ltk) => Action::LtkReply { handle, ltk },
+            None => {
+                info!("[security] no long term key for conn {:?}", handle);
+                Action::LtkNegativeReply { handle }
+            }
+        };
+        state.push_action(action);
+    }
+
+    /// Handle the encryption of a link, returning its security level.
+    pub(crate) fn encryption_changed<T: Controller>(
+        &self,
+        host: &BleHost<'_, T>,
+        handle: ConnHandle,
+        enabled: bool,
+    ) -> SecurityLevel {
+        let mut state = self.state.borrow_mut();
+        if !enabled {
+            return SecurityLevel::NoEncryption;
+        }
+        match state.pairing.as_ref() {
+            Some(p) if p.handle == handle && p.step == Step::Encryption => {
+                let level = p.security_level;
+                if let Err(reason) = state.distribute_keys(host) {
+                    state.fail(host, handle, reason);
+                }
+                state.restart_timer(handle);
+                level
+            }
+            _ => host
+                .connections
+                .link_info(handle)
+                .and_then(|info| state.bonds.iter().find(|b| b.matches(&info.peer)))
+                .map(|b| b.security_level)
+                .unwrap_or(SecurityLevel::Encrypted),
+        }
+    }
+
+    /// Handle the failure of the encryption of a link.
+    pub(crate) fn encryption_failed<T: Controller>(&self, host: &BleHost<'_, T>, handle: ConnHandle) {
+        let mut state = self.state.borrow_mut();
+        if state
+            .pairing
+            .as_ref()
+            .is_some_and(|p| p.handle == handle && p.step == Step::Encryption)
+        {
+            warn!("[security] encryption with the paired key failed on conn {:?}", handle);
+            state.pairing_failed(host, handle, Reason::UnspecifiedReason);
+        }
+    }
+
+    pub(crate) fn disconnected(&self, handle: ConnHandle) {
+        self.state.borrow_mut().end_pairing(handle);
+    }
+
+    /// Wait for the next command to run for the security manager, timing out stalled pairing.
+    pub(crate) async fn next_action<T: Controller>(&self, host: &BleHost<'_, T>) -> Action {
+        loop {
+            let deadline = {
+                let mut state = self.state.borrow_mut();
+                state.deadline_changed = false;
+                state.pairing.as_ref().map(|p| p.deadline)
+            };
+            let timeout = async {
+                match deadline {
+                    Some(deadline) => Timer::at(deadline).await,
+                    None => core::future::pending().await,
+                }
+            };
+            match select(poll_fn(|cx| self.poll_action(cx)), timeout).await {
+                Either::First(Some(action)) => return action,
+                Either::First(None) => {}
+                Either::Second(()) => self.expire(host, Instant::now()),
+            }
+        }
+    }
+
+    // Ready with no command when the deadline of the pairing changed.
+    fn poll_action(&self, cx: &mut Context<'_>) -> Poll<Option<Action>> {
+        let mut state = self.state.borrow_mut();
+        state.action_waker.register(cx.waker());
+        if let Some(action) = state.actions.pop_front() {
+            Poll::Ready(Some(action))
+        } else if state.deadline_changed {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// End the pairing if its deadline passed, after which the link takes no more commands.
+    fn expire<T: Controller>(&self, host: &BleHost<'_, T>, now: Instant) {
+        let mut state = self.state.borrow_mut();
+        let Some(handle) = state.pairing.as_ref().filter(|p| p.deadline <= now).map(|p| p.handle) else {
+            return;
+        };
+        warn!("[security] pairing timed out on conn {:?}", handle);
+        state.end_pairing(handle);
+        let _ = host.connections.set_smp_timed_out(handle);
+        host.publish(HostEvent::PairingTimeout { handle });
+    }
+}
+
+impl State {
+    fn random(&mut self) -> Result<&mut ChaCha12Rng, Reason> {
+        self.rng.as_mut().ok_or_else(|| {
+            warn!("[security] pairing needs a random generator seed");
+            Reason::PairingNotSupported
+        })
+    }
+
+    fn random_u128(&mut self) -> Result<u128, Reason> {
+        let mut bytes = [0; 16];
+        self.random()?.fill_bytes(&mut bytes);
+        Ok(u128::from_le_bytes(bytes))
+    }
+
+    // Give the peer another 30 seconds to answer, as a command of the pairing was exchanged.
+    fn restart_timer(&mut self, handle: ConnHandle) {
+        if let Some(pairing) = self.pairing.as_mut().filter(|p| p.handle == handle) {
+            pairing.deadline = Instant::now() + PAIRING_TIMEOUT;
+            self.deadline_changed = true;
+            self.action_waker.wake();
+        }
+    }
+
+    fn push_action(&mut self, action: Action) {
+        if self.actions.push_back(action).is_err() {
+            warn!("[security] too many pending security commands, dropping one");
+        }
+        self.action_waker.wake();
+    }
+
+    // The authentication requirements sent to the peer, only asking for LE Secure Connections when
+    // the public keys fit in the packets of the host.
+    fn auth_req<T: Controller>(&self, host: &BleHost<'_, T>) -> u8 {
+        let mitm = if self.io_capabilities == IoCapabilities::NoInputNoOutput {
+            0
+        } else {
+            AUTH_MITM
+        };
+        let sc = if secure_connections_supported(host) {
+            AUTH_SECURE_CONNECTIONS
+        } else {
+            0
+        };
+        AUTH_BONDING | mitm | sc
+    }
+
+    // The keys the local device can distribute.
+    fn local_key_distribution(&self) -> u8 {
+        KEY_SIGN | if self.local_irk.is_some() { KEY_ID } else { 0 }
+    }
+
+    // The address of the local device on a link, and its identity address.
+    fn local_addresses<T: Controller>(
+        &self,
+        host: &BleHost<'_, T>,
+        local_rpa: Option<BdAddr>,
+    ) -> Result<(SmpAddress, Address), Reason> {
+        let identity = match (host.address, self.public_address) {
+            (Some(address), _) => address,
+            (None, Some(addr)) => Address {
+                kind: AddrKind::PUBLIC,
+                addr,
+            },
+            (None, None) => {
+                warn!("[security] local address unknown");
+                return Err(Reason::UnspecifiedReason);
+            }
+        };
+        let local = match local_rpa {
+            Some(rpa) => SmpAddress::new(AddrKind::RANDOM, &rpa),
+            None => SmpAddress::new(identity.kind, &identity.addr),
+        };
+        Ok((local, identity))
+    }
+
+    fn new_pairing<T: Controller>(
+        &mut self,
+        host: &BleHost<'_, T>,
+        handle: ConnHandle,
+        initiator: bool,
+    ) -> Result<Pairing, Reason> {
+        let info = host.connections.link_info(handle).ok_or(Reason::UnspecifiedReason)?;
+        if self.pairing.is_some() {
+            warn!("[security] pairing already in progress, rejecting conn {:?}", handle);
+            return Err(Reason::UnspecifiedReason);
+        }
+        let now = Instant::now();
+        let identity = self.identity_of(info.peer);
+        if self.attempts.iter().any(|a| a.peer == identity && now < a.until) {
+            warn!("[security] pairing attempted again too soon on conn {:?}", handle);
+            return Err(Reason::RepeatedAttempts);
+        }
+        self.random()?;
+        let (local, _) = self.local_addresses(host, info.local_rpa)?;
+        let peer = match info.peer_rpa {
+            Some(rpa) => SmpAddress::new(AddrKind::RANDOM, &rpa),
+            None => SmpAddress::new(info.peer.kind, &info.peer.addr),
+        };
+        let (initiator_address, responder_address) = if initiator { (local, peer) } else { (peer, local) };
+        Ok(Pairing {
+            handle,
+            initiator,
+            step: Step::PairingResponse,
+            deadline: Instant::now() + PAIRING_TIMEOUT,
+            preq: [0; 7],
+            pres: [0; 7],
+            initiator_address,
+            responder_address,
+            peer: info.peer,
+            secure_connections: false,
+            passkey: None,
+            bonding: false,
+            key_size: MAX_KEY_SIZE,
+            local_keys: 0,
+            peer_keys: 0,
+            secret_key: None,
+            local_public_key: None,
+            peer_public_key: None,
+            dh_key: [0; 32],
+            local_nonce: 0,
+            peer_nonce: 0,
+            peer_confirm: 0,
+            round: 0,
+            key: 0,
+            ltk_distributed: false,
+            security_level: SecurityLevel::Encrypted,
+            bond: BondInformation::new(info.peer, Key(0), SecurityLevel::Encrypted),
+        })
+    }
+
+    fn start_pairing<T: Controller>(&mut self, host: &BleHost<'_, T>, handle: ConnHandle) -> Result<(), Reason> {
+        let mut pairing = self.new_pairing(host, handle, true)?;
+        let local_keys = self.local_key_distribution();
+        pairing.preq = [
+            PAIRING_REQUEST,
+            self.io_capabilities.to_raw(),
+            0,
+            self.auth_req(host),
+            MAX_KEY_SIZE,
+            local_keys,
+            KEY_ENC | KEY_ID | KEY_SIGN,
+        ];
+        send(host, handle, &pairing.preq).map_err(|_| Reason::UnspecifiedReason)?;
+        info!("[security] pairing started on conn {:?}", handle);
+        procedure::begin(Procedure::Pairing, handle);
+        host.publish(HostEvent::PairingStarted { handle });
+        self.pairing = Some(pairing);
+        Ok(())
+    }
+
+    fn on_security_request<T: Controller>(
+        &mut self,
+        host: &BleHost<'_, T>,
+        handle: ConnHandle,
+        payload: &[u8],
+    ) -> Result<(), Reason> {
+        let info = host.connections.link_info(handle).ok_or(Reason::UnspecifiedReason)?;
+        if info.role != LeConnRole::Central {
+            return Err(Reason::CommandNotSupported);
+        }
+        if self.pairing.as_ref().is_some_and(|p| p.handle == handle) {
+            return Ok(());
+        }
+        let mitm = payload.first().is_some_and(|auth| auth & AUTH_MITM != 0);
+        match self.bonds.iter().find(|b| b.matches(&info.peer)) {
+            // Pair again if the peer asks for a stronger key than the one of the bond.
+            Some(bond) if !mitm || bond.security_level.authenticated() => {
+                let action = Action::EnableEncryption {
+                    handle,
+                    ltk: bond.ltk,
+                    ediv: bond.ediv,
+                    rand: bond.rand,
+                };
+                self.push_action(action);
+                Ok(())
+            }
+            _ => self.start_pairing(host, handle),
+        }
+    }
+
+    fn on_pairing_request<T: Controller>(
+        &mut self,
+        host: &BleHost<'_, T>,
+        handle: ConnHandle,
+        pdu: &[u8],
+    ) -> Result<(), Reason> {
+        let preq: [u8; 7] = pdu.try_into().map_err(|_| Reason::InvalidParameters)?;
+        let info = host.connections.link_info(handle).ok_or(Reason::UnspecifiedReason)?;
+        if info.role != LeConnRole::Peripheral {
+            return Err(Reason::CommandNotSupported);
+        }
+        let mut pairing = self.new_pairing(host, handle, false)?;
+        pairing.preq = preq;
+        let (peer_io, peer_auth, max_key_size) = (preq[1], preq[3], preq[4]);
+        self.negotiate(host, &mut pairing, peer_io, peer_auth, max_key_size)?;
+        let local_keys = if pairing.secure_connections {
+            preq[6] & self.local_key_distribution()
+        } else {
+            preq[6] & (self.local_key_distribution() | KEY_ENC)
+        };
+        let peer_keys = preq[5] & (KEY_ID | KEY_SIGN);
+        let (local_keys, peer_keys) = if pairing.bonding {
+            (local_keys, peer_keys)
+        } else {
+            (0, 0)
+        };
+        pairing.local_keys = local_keys;
+        pairing.peer_keys = peer_keys;
+        pairing.pres = [
+            PAIRING_RESPONSE,
+            self.io_capabilities.to_raw(),
+            0,
+            self.auth_req(host),
+            MAX_KEY_SIZE,
+            peer_keys,
+            local_keys,
+        ];
+        send(host, handle, &pairing.pres).map_err(|_| Reason::UnspecifiedReason)?;
+        info!("[security] pairing started on conn {:?}", handle);
+        procedure::begin(Procedure::Pairing, handle);
+        host.publish(HostEvent::PairingStarted { handle });
+        pairing.step = if pairing.secure_connections {
+            Step::PublicKey
+        } else {
+            Step::Confirm
+        };
+        self.pairing = Some(pairing);
+        self.display_passkey(host);
+        Ok(())
+    }
+
+    // Choose the pairing method from the pairing command of the peer.
+    fn negotiate<T: Controller>(
+        &mut self,
+        host: &BleHost<'_, T>,
+        pairing: &mut Pairing,
+        peer_io: u8,
+        peer_auth: u8,
+        max_key_size: u8,
+    ) -> Result<(), Reason> {
+        if peer_io > IO_KEYBOARD_DISPLAY || !(MIN_KEY_SIZE..=MAX_KEY_SIZE).contains(&max_key_size) {
+            return Err(Reason::InvalidParameters);
+        }
+        let peer_sc = peer_auth & AUTH_SECURE_CONNECTIONS != 0;
+        let sc_supported = secure_connections_supported(host);
+        if self.secure_connections_only {
+            if !peer_sc || !sc_supported {
+                warn!("[security] rejecting legacy pairing in secure connections only mode");
+                return Err(Reason::AuthenticationRequirements);
+            }
+            if max_key_size < MAX_KEY_SIZE {
+                return Err(Reason::EncryptionKeySize);
+            }
+        }
+        pairing.secure_connections = peer_sc && sc_supported;
+        pairing.key_size = max_key_size;
+        pairing.bonding = peer_auth & AUTH_BONDING != 0;
+        let mitm = (peer_auth | self.auth_req(host)) & AUTH_MITM != 0;
+        if uses_passkey(self.io_capabilities, peer_io, mitm) {
+            pairing.passkey = Some(self.random()?.next_u32() % 1_000_000);
+            pairing.security_level = if pairing.secure_connections && pairing.key_size == MAX_KEY_SIZE {
+                SecurityLevel::SecureConnections
+            } else {
+                SecurityLevel::EncryptedAuthenticated
+            };
+        }
+        Ok(())
+    }
+
+    fn display_passkey<T: Controller>(&self, host: &BleHost<'_, T>) {
+        if let Some(pairing) = self.pairing.as_ref() {
+            if let Some(passkey) = pairing.passkey {
+                host.publish(HostEvent::PasskeyDisplay {
+                    handle: pairing.handle,
+                    passkey,
+                });
+            }
+        }
+    }
+
+    fn on_pairing_pdu<T: Controller>(&mut self, host: &BleHost<'_, T>, code: u8, payload: &[u8]) -> Result<(), Reason> {
+        let mut pairing = unwrap!(self.pairing.take());
+        match self.step(host, &mut pairing, code, payload) {
+            Ok(true) => {
+                self.complete(host, pairing);
+                Ok(())
+            }
+            result => {
+                self.pairing = Some(pairing);
+                result.map(|_| ())
+            }
+        }
+    }
+
+    fn step<T: Controller>(
+        &mut self,
+        host: &BleHost<'_, T>,
+        pairing: &mut Pairing,
+        code: u8,
+        payload: &[u8],
+    ) -> Result<bool, Reason> {
+        let handle = pairing.handle;
+        match (pairing.step, code) {
+            (Step::PairingResponse, PAIRING_RESPONSE) => {
+                if payload.len() != 6 {
+                    return Err(Reason::InvalidParameters);
+                }
+                let mut pres = [code; 7];
+                pres[1..].copy_from_slice(payload);
+                pairing.pres = pres;
+                let (peer_io, peer_auth, max_key_size) = (pres[1], pres[3], pres[4]);
+                self.negotiate(host, pairing, peer_io, peer_auth, max_key_size)?;
+                // The responder may only remove keys from those requested.
+                pairing.local_keys = pres[5] & pairing.preq[5];
+                pairing.peer_keys = pres[6] & pairing.preq[6];
+                if pairing.secure_connections {
+                    pairing.peer_keys &= !KEY_ENC;
+                }
+                if let Some(passkey) = pairing.passkey {
+                    host.publish(HostEvent::PasskeyDisplay { handle, passkey });
+                }
+                if pairing.secure_connections {
+                    self.send_public_key(host, pairing)?;
+                    pairing.step = Step::PublicKey;
+                } else {
+                    pairing.local_nonce = self.random_u128()?;
+                    let confirm = pairing.legacy_confirm(pairing.local_nonce);
+                    send_value(host, handle, PAIRING_CONFIRM, confirm)?;
+                    pairing.step = Step::Confirm;
+                }
+            }
+            (Step::PublicKey, PAIRING_PUBLIC_KEY) if pairing.secure_connections => {
+                let (x, y) = payload.split_at_checked(32).ok_or(Reason::InvalidParameters)?;
+                let (mut x, mut y): ([u8; 32], [u8; 32]) = (
+                    x.try_into().map_err(|_| Reason::InvalidParameters)?,
+                    y.try_into().map_err(|_| Reason::InvalidParameters)?,
+                );
+                x.reverse();
+                y.reverse();
+                let peer_key = PublicKey::new(x, y).ok_or(Reason::DhKeyCheckFailed)?;
+                // Links paired with the debug key can be decrypted by anyone.
+                let debug_key = (x, y) == (ecdh::DEBUG_PUBLIC_KEY_X, ecdh::DEBUG_PUBLIC_KEY_Y);
+                if debug_key {
+                    warn!("[security] peer on conn {:?} pairs with the debug key", handle);
+                    if self.secure_connections_only {
+                        return Err(Reason::AuthenticationRequirements);
+                    }
+                }
+                if !pairing.initiator {
+                    self.send_public_key(host, pairing)?;
+                }
+                // Reflecting our key is only expected of a peer also using the debug key.
+                if pairing.local_public_key.as_ref() == Some(&peer_key) && !debug_key {
+                    return Err(Reason::DhKeyCheckFailed);
+                }
+                let secret_key = unwrap!(pairing.secret_key.as_ref());
+                pairing.dh_key = secret_key.dh_key(&peer_key).ok_or(Reason::DhKeyCheckFailed)?;
+                pairing.peer_public_key = Some(peer_key);
+                match (pairing.initiator, pairing.passkey) {
+                    (false, None) => {
+                        pairing.local_nonce = self.random_u128()?;
+                        send_value(host, handle, PAIRING_CONFIRM, pairing.local_sc_confirm())?;
+                        pairing.step = Step::Random;
+                    }
+                    (true, Some(_)) => {
+                        pairing.local_nonce = self.random_u128()?;
+                        send_value(host, handle, PAIRING_CONFIRM, pairing.local_sc_confirm())?;
+                        pairing.step = Step::Confirm;
+                    }
+                    _ => pairing.step = Step::Confirm,
+                }
+            }
+            (Step::Confirm, PAIRING_CONFIRM) => {
+                pairing.peer_confirm = value(payload)?;
+                match (pairing.secure_connections, pairing.initiator) {
+                    (true, true) => {
+                        if pairing.passkey.is_none() {
+                            pairing.local_nonce = self.random_u128()?;
+                        }
+                        send_value(host, handle, PAIRING_RANDOM, pairing.local_nonce)?;
+                    }
+                    (true, false) => {
+                        pairing.local_nonce = self.random_u128()?;
+                        send_value(host, handle, PAIRING_CONFIRM, pairing.local_sc_confirm())?;
+                    }
+                    (false, true) => send_value(host, handle, PAIRING_RANDOM, pairing.local_nonce)?,
+                    (false, false) => {
+                        pairing.local_nonce = self.random_u128()?;
+                        let confirm = pairing.legacy_confirm(pairing.local_nonce);
+                        send_value(host, handle, PAIRING_CONFIRM, confirm)?;
+                    }
+                }
+                pairing.step = Step::Random;
+            }
+            (Step::Random, PAIRING_RANDOM) => {
+                pairing.peer_nonce = value(payload)?;
+                if pairing.secure_connections {
+                    self.secure_connections_random(host, pairing)?;
+                } else {
+                    if pairing.legacy_confirm(pairing.peer_nonce) != pairing.peer_confirm {
+                        return Err(Reason::ConfirmValueFailed);
+                    }
+                    let (mrand, srand) = pairing.nonces();
+                    let stk = crypto::s1(pairing.passkey.unwrap_or(0) as u128, srand, mrand);
+                    pairing.key = mask_key(stk, pairing.key_size);
+                    if pairing.initiator {
+                        self.push_action(Action::EnableEncryption {
+                            handle,
+                            ltk: Key(pairing.key),
+                            ediv: 0,
+                            rand: [0; 8],
+                        });
+                    } else {
+                        send_value(host, handle, PAIRING_RANDOM, pairing.local_nonce)?;
+                    }
+                    pairing.step = Step::Encryption;
+                }
+            }
+            (Step::DhKeyCheck, PAIRING_DHKEY_CHECK) => {
+                let check = value(payload)?;
+                let (ea, eb) = pairing.dh_key_checks();
+                if pairing.initiator {
+                    if check != eb {
+                        return Err(Reason::DhKeyCheckFailed);
+                    }
+                    self.push_action(Action::EnableEncryption {
+                        handle,
+                        ltk: Key(pairing.key),
+                        ediv: 0,
+                        rand: [0; 8],
+                    });
+                } else {
+                    if check != ea {
+                        return Err(Reason::DhKeyCheckFailed);
+                    }
+                    send_value(host, handle, PAIRING_DHKEY_CHECK, eb)?;
+                }
+                pairing.bond.ltk = Key(pairing.key);
+                pairing.step = Step::Encryption;
+            }
+            (Step::Keys, code) => return self.receive_key(host, pairing, code, payload),
+            (step, code) => {
+                warn!(
+                    "[security] unexpected command {} in step {:?} on conn {:?}",
+                    code, step, handle
+                );
+                return Err(Reason::UnspecifiedReason);
+            }
+        }
+        Ok(false)
+    }
+
+    fn send_public_key<T: Controller>(&mut self, host: &BleHost<'_, T>, pairing: &mut Pairing) -> Result<(), Reason> {
+        #[cfg(feature = "security-debug-keys")]
+        let debug_key = self
+            .debug_keys
+            .then(|| unwrap!(SecretKey::new(&ecdh::DEBUG_SECRET_KEY)));
+        #[cfg(not(feature = "security-debug-keys"))]
+        let debug_key = None;
+        let secret_key = debug_key.map_or_else(
+            || loop {
+                let mut scalar = [0; 32];
+                self.random()?.fill_bytes(&mut scalar);
+                if let Some(key) = SecretKey::new(&scalar) {
+                    break Ok(key);
+                }
+            },
+            Ok,
+        )?;
+        let public_key = secret_key.public_key();
+        let mut pdu = [0; 65];
+        pdu[0] = PAIRING_PUBLIC_KEY;
+        pdu[1..33].copy_from_slice(&public_key.x);
+        pdu[33..].copy_from_slice(&public_key.y);
+        pdu[1..33].reverse();
+        pdu[33..].reverse();
+        send(host, pairing.handle, &pdu).map_err(|_| Reason::UnspecifiedReason)?;
+        pairing.secret_key = Some(secret_key);
+        pairing.local_public_key = Some(public_key);
+        Ok(())
+    }
+
+    fn secure_connections_random<T: Controller>(
+        &mut self,
+        host: &BleHost<'_, T>,
+        pairing: &mut Pairing,
+    ) -> Result<(), Reason> {
+        let handle = pairing.handle;
+        // The initiator checks the confirm value of the responder, which checks it on receiving
+        // the random value in Passkey Entry only, Just Works relying on the DHKey check.
+        if (pairing.initiator || pairing.passkey.is_some()) && pairing.peer_sc_confirm() != pairing.peer_confirm {
+            return Err(Reason::ConfirmValueFailed);
+        }
+        if !pairing.initiator {
+            send_value(host, handle, PAIRING_RANDOM, pairing.local_nonce)?;
+        }
+        if pairing.passkey.is_some() {
+            pairing.round += 1;
+            if pairing.round < PASSKEY_ROUNDS {
+                if pairing.initiator {
+                    pairing.local_nonce = self.random_u128()?;
+                    send_value(host, handle, PAIRING_CONFIRM, pairing.local_sc_confirm())?;
+                }
+                pairing.step = Step::Confirm;
+                return Ok(());
+            }
+        }
+        if pairing.initiator {
+            let (ea, _) = pairing.dh_key_checks();
+            send_value(host, handle, PAIRING_DHKEY_CHECK, ea)?;
+        }
+        pairing.step = Step::DhKeyCheck;
+        Ok(())
+    }
+
+    // Distribute the keys once the link is encrypted, the responder first.
+    fn distribute_keys<T: Controller>(&mut self, host: &BleHost<'_, T>) -> Result<(), Reason> {
+        let mut pairing = unwrap!(self.pairing.take());
+        pairing.step = Step::Keys;
+        let result = if pairing.initiator && pairing.peer_keys != 0 {
+            Ok(())
+        } else {
+            self.send_keys(host, &mut pairing)
+        };
+        if result.is_ok() && pairing.peer_keys == 0 {
+            self.complete(host, pairing);
+        } else {
+            self.pairing = Some(pairing);
+        }
+        result
+    }
+
+    fn send_keys<T: Controller>(&mut self, host: &BleHost<'_, T>, pairing: &mut Pairing) -> Result<(), Reason> {
+        let handle = pairing.handle;
+        if pairing.local_keys & KEY_ENC != 0 {
+            let ltk = mask_key(self.random_u128()?, pairing.key_size);
+            let mut rand = [0; 8];
+            self.random()?.fill_bytes(&mut rand);
+            let ediv = self.random()?.next_u32() as u16;
+            send_value(host, handle, ENCRYPTION_INFORMATION, ltk)?;
+            let mut pdu = [0; 11];
+            pdu[0] = CENTRAL_IDENTIFICATION;
+            pdu[1..3].copy_from_slice(&ediv.to_le_bytes());
+            pdu[3..].copy_from_slice(&rand);
+            send(host, handle, &pdu).map_err(|_| Reason::UnspecifiedReason)?;
+            pairing.bond.ltk = Key(ltk);
+            pairing.bond.ediv = ediv;
+            pairing.bond.rand = rand;
+            pairing.ltk_distributed = true;
+        }
+        if pairing.local_keys & KEY_ID != 0 {
+            let info = host.connections.link_info(handle).ok_or(Reason::UnspecifiedReason)?;
+            let (_, identity) = self.local_addresses(host, info.local_rpa)?;
+            let irk = unwrap!(self.local_irk);
+            send_value(host, handle, IDENTITY_INFORMATION, irk.0)?;
+            let mut pdu = [0; 8];
+            pdu[0] = IDENTITY_ADDRESS_INFORMATION;
+            pdu[1] = identity_kind(identity.kind).into_inner() & 1;
+            pdu[2..].copy_from_slice(identity.addr.raw());
+            send(host, handle, &pdu).map_err(|_| Reason::UnspecifiedReason)?;
+        }
+        if pairing.local_keys & KEY_SIGN != 0 {
+            let csrk = self.random_u128()?;
+            send_value(host, handle, SIGNING_INFORMATION, csrk)?;
+            pairing.bond.local_csrk = Some(Key(csrk));
+        }
+        Ok(())
+    }
+
+    fn receive_key<T: Controller>(
+        &mut self,
+        host: &BleHost<'_, T>,
+        pairing: &mut Pairing,
+        code: u8,
+        payload: &[u8],
+    ) -> Result<bool, Reason> {
+        match code {
+            ENCRYPTION_INFORMATION if pairing.peer_keys & KEY_ENC != 0 => {
+                pairing.bond.ltk = Key(value(payload)?);
+            }
+            CENTRAL_IDENTIFICATION if pairing.peer_keys & KEY_ENC != 0 => {
+                let [e0, e1, rand @ ..]: [u8; 10] = payload.try_into().map_err(|_| Reason::InvalidParameters)?;
+                pairing.bond.ediv = u16::from_le_bytes([e0, e1]);
+                pairing.bond.rand = rand;
+                pairing.ltk_distributed = true;
+                pairing.peer_keys &= !KEY_ENC;
+            }
+            IDENTITY_INFORMATION if pairing.peer_keys & KEY_ID != 0 => {
+                pairing.bond.irk = Some(Key(value(payload)?));
+            }
+            IDENTITY_ADDRESS_INFORMATION if pairing.peer_keys & KEY_ID != 0 => {
+                let [kind, addr @ ..]: [u8; 7] = payload.try_into().map_err(|_| Reason::InvalidParameters)?;
+                pairing.bond.identity = Address {
+                    kind: if kind == 0 { AddrKind::PUBLIC } else { AddrKind::RANDOM },
+                    addr: BdAddr::new(addr),
+                };
+                pairing.peer_keys &= !KEY_ID;
+            }
+            SIGNING_INFORMATION if pairing.peer_keys & KEY_SIGN != 0 => {
+                pairing.bond.peer_csrk = Some(Key(value(payload)?));
+                pairing.peer_keys &= !KEY_SIGN;
+            }
+            code => {
+                warn!("[security] unexpected key {} on conn {:?}", code, pairing.handle);
+                return Err(Reason::UnspecifiedReason);
+            }
+        }
+        if pairing.peer_keys != 0 {
+            return Ok(false);
+        }
+        if pairing.initiator {
+            self.send_keys(host, pairing)?;
+        }
+        Ok(true)
+    }
+
+    fn complete<T: Controller>(&mut self, host: &BleHost<'_, T>, pairing: Pairing) {
+        let handle = pairing.handle;
+        procedure::end(Procedure::Pairing, handle);
+        info!(
+            "[security] pairing complete on conn {:?} with level {:?}",
+            handle, pairing.security_level
+        );
+        let security_level = pairing.security_level;
+        host.publish(HostEvent::PairingComplete { handle, security_level });
+        self.attempts.retain(|a| a.peer != pairing.peer);
+        if !pairing.bonding {
+            return;
+        }
+        let identity = pairing.bond.identity;
+        host.publish(HostEvent::KeysDistributed { handle, identity });
+        // Legacy pairing bonds with the long term key distributed by the peripheral only.
+        if pairing.secure_connections || pairing.ltk_distributed {
+            let mut bond = pairing.bond;
+            bond.security_level = security_level;
+            match self.store_bond(bond) {
+                Ok(()) => host.publish(HostEvent::Bonded { handle, identity }),
+                Err(_) => warn!("[security] bond table full, not bonding with conn {:?}", handle),
+            }
+        }
+    }
+
+    // Store a bond as the most recently used, replacing any previous bond of the peer.
+    fn store_bond(&mut self, bond: BondInformation) -> Result<(), Error> {
+        if let Some(index) = self.bonds.iter().position(|b| b.identity == bond.identity) {
+            self.bonds.remove(index);
+        } else if self.bonds.is_full() {
+            match self.eviction_policy {
+                BondEvictionPolicy::RejectNew => return Err(Error::OutOfMemory),
+                BondEvictionPolicy::EvictLeastRecentlyUsed => {
+                    let evicted = self.bonds.remove(0);
+                    info!("[security] evicting the bond of {:?}", evicted.identity);
+                }
+            }
+        }
+        self.bonds.push(bond).map_err(|_| Error::OutOfMemory)
+    }
+
+    // Find a bond to encrypt a link with, making it the most recently used.
+    fn use_bond(&mut self, f: impl Fn(&BondInformation) -> bool) -> Option<&BondInformation> {
+        let index = self.bonds.iter().position(f)?;
+        let bond = self.bonds.remove(index);
+        unwrap!(self.bonds.push(bond).ok());
+        self.bonds.last()
+    }
+
+    // The identity of a peer, resolving its private address with the bonds. Unbonded peers are
+    // only known by the address they connect with.
+    fn identity_of(&self, peer: Address) -> Address {
+        self.bonds
+            .iter()
+            .find(|b| b.matches(&peer))
+            .map(|b| b.identity)
+            .unwrap_or(peer)
+    }
+
+    fn end_pairing(&mut self, handle: ConnHandle) {
+        if self.pairing.as_ref().is_some_and(|p| p.handle == handle) {
+            procedure::end(Procedure::Pairing, handle);
+            self.pairing = None;
+        }
+    }
+
+    // Abort the pairing of a link, telling the peer why.
+    fn fail<T: Controller>(&mut self, host: &BleHost<'_, T>, handle: ConnHandle, reason: Reason) {
+        warn!("[security] pairing failed on conn {:?}: {:?}", handle, reason);
+        let _ = send(host, handle, &[PAIRING_FAILED, reason.to_raw()]);
+        self.pairing_failed(host, handle, reason);
+    }
+
+    // End a failed pairing, making the peer wait longer before each new attempt.
+    fn pairing_failed<T: Controller>(&mut self, host: &BleHost<'_, T>, handle: ConnHandle, reason: Reason) {
+        let Some(peer) = self.pairing.as_ref().filter(|p| p.handle == handle).map(|p| p.peer) else {
+            return;
+        };
+        self.end_pairing(handle);
+        host.publish(HostEvent::PairingFailed { handle, reason });
+        let peer = self.identity_of(peer);
+        let index = match self.attempts.iter().position(|a| a.peer == peer) {
+            Some(index) => index,
+            None => {
+                if self.attempts.is_full() {
+                    let oldest = self.attempts.iter().enumerate().min_by_key(|(_, a)| a.until);
+                    let index = unwrap!(oldest.map(|(i, _)| i));
+                    self.attempts.swap_remove(index);
+                }
+                let attempts = Attempts {
+                    peer,
+                    failures: 0,
+                    until: Instant::now(),
+                };
+                unwrap!(self.attempts.push(attempts).ok());
+                self.attempts.len() - 1
+            }
+        };
+        let attempts = &mut self.attempts[index];
+        let interval = REPEATED_ATTEMPTS_INTERVAL * (1 << attempts.failures.min(REPEATED_ATTEMPTS_MAX_DOUBLINGS));
+        attempts.failures = attempts.failures.saturating_add(1);
+        attempts.until = Instant::now() + interval;
+    }
+}
+
+// Whether a pairing timed out on a link, after which no more commands may be exchanged.
+fn smp_timed_out<T: Controller>(host: &BleHost<'_, T>, handle: ConnHandle) -> bool {
+    host.connections
+        .link_info(handle)
+        .is_some_and(|info| info.smp_timed_out)
+}
+
+// Whether the packets of the host can carry the public keys of LE Secure Connections.
+fn secure_connections_supported<T: Controller>(host: &BleHost<'_, T>) -> bool {
+    host.rx_pool.mtu() >= SECURE_CONNECTIONS_MIN_MTU
+}
+
+/// Send a command of the security manager.
+fn send<T: Controller>(host: &BleHost<'_, T>, handle: ConnHandle, pdu: &[u8]) -> Result<(), Error> {
+    let mut packet = host.rx_pool.alloc().ok_or(Error::OutOfMemory)?;
+    let mut w = WriteCursor::new(packet.as_mut());
+    w.write_hci(&L2capHeader {
+        channel: L2CAP_CID_LE_U_SECURITY_MANAGER,
+        length: pdu.len() as u16,
+    })?;
+    w.append(pdu)?;
+    let len = w.len();
+    host.connections.try_outbound(handle, Pdu::new(packet, len))
+}
+
+// Send a command carrying a single 128-bit value.
+fn send_value<T: Controller>(host: &BleHost<'_, T>, handle: ConnHandle, code: u8, value: u128) -> Result<(), Reason> {
+    let mut pdu = [0; 17];
+    pdu[0] = code;
+    pdu[1..].copy_from_slice(&value.to_le_bytes());
+    send(host, handle, &pdu).map_err(|_| Reason::UnspecifiedReason)
+}
+
+// The 128-bit value of a command.
+fn value(payload: &[u8]) -> Result<u128, Reason> {
+    let bytes: [u8; 16] = payload.try_into().map_err(|_| Reason::InvalidParameters)?;
+    Ok(u128::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::task::Poll;
+    use std::vec::Vec;
+
+    use bt_hci::param::Status;
+    use embassy_futures::poll_once;
+
+    use super::*;
+    use crate::HostResources;
+    use crate::mock_controller::MockController;
+
+    const HANDLE: u16 = 1;
+    const CENTRAL: [u8; 6] = [0xc0, 0x01, 0x02, 0x03, 0x04, 0xc5];
+    const PERIPHERAL: [u8; 6] = [0xa0, 0x01, 0x02, 0x03, 0x04, 0xc5];
+
+    fn handle() -> ConnHandle {
+        ConnHandle::new(HANDLE)
+    }
+
+    fn host<const MTU: usize>(
+        resources: &mut HostResources<1, 1, MTU>,
+        address: Address,
+        seed: u8,
+    ) -> BleHost<'_, MockController> {
+        let mut host = crate::new(MockController::new(), resources)
+            .set_random_address(address)
+            .host;
+        host.security.set_random_generator_seed([seed; 32]);
+        host
+    }
+
+    fn connect(host: &BleHost<'_, MockController>, handle: ConnHandle, peer: Address, role: LeConnRole) {
+        host.connections.connect(handle, peer.kind, peer.addr, role).unwrap();
+    }
+
+    // The security manager PDUs sent by a host.
+    fn sent(host: &BleHost<'_, MockController>) -> Vec<Vec<u8>> {
+        let mut pdus = Vec::new();
+        while let Poll::Ready((_, pdu)) = poll_once(host.connections.outbound()) {
+            assert_eq!(&pdu.as_ref()[2..4], &L2CAP_CID_LE_U_SECURITY_MANAGER.to_le_bytes());
+            pdus.push(pdu.as_ref()[4..].to_vec());
+        }
+        pdus
+    }
+
+    fn action(host: &BleHost<'_, MockController>) -> Option<Action> {
+        match poll_once(host.security.next_action(host)) {
+            Poll::Ready(action) => Some(action),
+            Poll::Pending => None,
+        }
+    }
+
+    // Exchange the PDUs of the hosts and run their commands, as the controllers would, returning
+    // the security levels of the links once encrypted.
+    fn run(
+        central: &BleHost<'_, MockController>,
+        peripheral: &BleHost<'_, MockController>,
+        handle: ConnHandle,
+    ) -> Option<(SecurityLevel, SecurityLevel)> {
+        let mut encryption = None;
+        let mut levels = None;
+        loop {
+            let mut progress = false;
+            for pdu in sent(central) {
+                peripheral.security.handle(peripheral, handle, &pdu);
+                progress = true;
+            }
+            for pdu in sent(peripheral) {
+                central.security.handle(central, handle, &pdu);
+                progress = true;
+            }
+            while let Some(action) = action(central) {
+                let Action::EnableEncryption { ltk, ediv, rand, .. } = action else {
+                    panic!("unexpected command of the central");
+                };
+                peripheral
+                    .security
+                    .long_term_key_request(peripheral, handle, rand, ediv);
+                encryption = Some(ltk);
+                progress = true;
+            }
+            while let Some(action) = action(peripheral) {
+                match action {
+                    Action::LtkReply { ltk, .. } => {
+                        assert_eq!(Some(ltk), encryption.take());
+                        // The controller of the central reports the encryption first.
+                        let central_level = central.security.encryption_changed(central, handle, true);
+                        let peripheral_level = peripheral.security.encryption_changed(peripheral, handle, true);
+                        levels = Some((central_level, peripheral_level));
+                    }
+                    Action::LtkNegativeReply { .. } => {
+                        central.security.encryption_failed(central, handle);
+                        return None;
+                    }
+                    Action::EnableEncryption { .. } => panic!("unexpected command of the peripheral"),
+                }
+                progress = true;
+            }
+            if !progress {
+                return levels;
+            }
+        }
+    }
+
+    fn bonds(host: &BleHost<'_, MockController>) -> Vec<BondInformation> {
+        host.security.state.borrow().bonds.iter().cloned().collect()
+    }
+
+    #[test]
+    fn secure_connections_pairing_bonds_and_encrypts_reconnections() {
+        let mut central_resources: HostResources<1, 1, 80> = HostResources::new();
+        let mut peripheral_resources: HostResources<1, 1, 80> = HostResources::new();
+        let central = host(&mut central_resources, Address::random(CENTRAL), 1);
+        let peripheral = host(&mut peripheral_resources, Address::random(PERIPHERAL), 2);
+        connect(&central, handle(), Address::random(PERIPHERAL), LeConnRole::Central);
+        connect(&peripheral, handle(), Address::random(CENTRAL), LeConnRole::Peripheral);
+
+        central.security.request_security(&central, handle()).unwrap();
+        let levels = run(&central, &peripheral, handle());
+        assert_eq!(levels, Some((SecurityLevel::Encrypted, SecurityLevel::Encrypted)));
+        assert!(central.security.state.borrow().pairing.is_none());
+        assert!(peripheral.security.state.borrow().pairing.is_none());
+
+        let (central_bonds, peripheral_bonds) = (bonds(&central), bonds(&peripheral));
+        assert_eq!(central_bonds.len(), 1);
+        assert_eq!(peripheral_bonds.len(), 1);
+        assert_eq!(central_bonds[0].identity, Address::random(PERIPHERAL));
+        assert_eq!(peripheral_bonds[0].identity, Address::random(CENTRAL));
+        assert_eq!(central_bonds[0].ltk, peripheral_bonds[0].ltk);
+        // Long term keys of LE Secure Connections have no EDIV and Rand.
+        assert_eq!((central_bonds[0].ediv, central_bonds[0].rand), (0, [0; 8]));
+
+        // Reconnecting encrypts the link with the long term key, without pairing again.
+        central.connections.disconnected(handle(), Status::UNSPECIFIED).unwrap();
+        peripheral
+            .connections
+            .disconnected(handle(), Status::UNSPECIFIED)
+            .unwrap();
+        let handle = ConnHandle::new(2);
+        connect(&central, handle, Address::random(PERIPHERAL), LeConnRole::Central);
+        connect(&peripheral, handle, Address::random(CENTRAL), LeConnRole::Peripheral);
+        central.security.request_security(&central, handle).unwrap();
+        assert!(central.security.state.borrow().pairing.is_none());
+        let levels = run(&central, &peripheral, handle);
+        assert_eq!(levels, Some((SecurityLevel::Encrypted, SecurityLevel::Encrypted)));
+    }
+
+    #[test]
+    fn pairing_progress_is_published() {
+        let mut central_resources: HostResources<1, 1, 80> = HostResources::new();
+        let mut peripheral_resources: HostResources<1, 1, 80> = HostResources::new();
+        let central = host(&mut central_resources, Address::random(CENTRAL), 1);
+        let peripheral = host(&mut peripheral_resources, Address::random(PERIPHERAL), 2);
+        connect(&central, handle(), Address::random(PERIPHERAL), LeConnRole::Central);
+        connect(&peripheral, handle(), Address::random(CENTRAL), LeConnRole::Peripheral);
+        let mut events = peripheral.events.dyn_subscriber().unwrap();
+
+        central.security.request_security(&central, handle()).unwrap();
+        run(&central, &peripheral, handle()).unwrap();
+        let identity = Address::random(CENTRAL);
+        let expected = [
+            HostEvent::PairingStarted { handle: handle() },
+            HostEvent::PairingComplete {
+                handle: handle(),
+                security_level: SecurityLevel::Encrypted,
+            },
+            HostEvent::KeysDistributed {
+                handle: handle(),
+                identity,
+            },
+            HostEvent::Bonded {
+                handle: handle(),
+                identity,
+            },
+        ];
+        for event in expected {
+            assert_eq!(events.try_next_message_pure(), Some(event));
+        }
+
+        // A peer aborting the pairing is reported with its reason.
+        peripheral
+            .connections
+            .disconnected(handle(), Status::UNSPECIFIED)
+            .unwrap();
+        let handle = ConnHandle::new(2);
+        connect(&peripheral, handle, Address::random(CENTRAL), LeConnRole::Peripheral);
+        peripheral
+            .security
+            .handle(&peripheral, handle, &[PAIRING_REQUEST, 3, 0, 1, 16, 0, 0]);
+        assert_eq!(
+            events.try_next_message_pure(),
+            Some(HostEvent::PairingStarted { handle })
+        );
+        peripheral.security.handle(&peripheral, handle, &[PAIRING_FAILED, 0x08]);
+        assert_eq!(
+            events.try_next_message_pure(),
+            Some(HostEvent::PairingFailed {
+                handle,
+                reason: Reason::UnspecifiedReason
+            })
+        );
+    }
+
+    #[test]
+    fn stalled_pairing_times_out() {
+        let mut central_resources: HostResources<1, 1, 80> = HostResources::new();
+        let mut peripheral_resources: HostResources<1, 1, 80> = HostResources::new();
+        let central = host(&mut central_resources, Address::random(CENTRAL), 1);
+        let peripheral = host(&mut peripheral_resources, Address::random(PERIPHERAL), 2);
+        connect(&central, handle(), Address::random(PERIPHERAL), LeConnRole::Central);
+        connect(&peripheral, handle(), Address::random(CENTRAL), LeConnRole::Peripheral);
+        let mut events = central.events.dyn_subscriber().unwrap();
+
+        central.security.request_security(&central, handle()).unwrap();
+        let request = sent(&central);
+        assert_eq!(request.len(), 1);
+        assert_eq!(
+            events.try_next_message_pure(),
+            Some(HostEvent::PairingStarted { handle: handle() })
+        );
+        // The deadline is not reached while the peer keeps answering.
+        central.security.expire(&central, Instant::now());
+        assert!(central.security.state.borrow().pairing.is_some());
+
+        central.security.expire(&central, Instant::now() + PAIRING_TIMEOUT);
+        assert!(central.security.state.borrow().pairing.is_none());
+        assert!(matches!(
+            events.try_next_message_pure(),
+            Some(HostEvent::PairingTimeout { handle: h }) if h == handle()
+        ));
+
+        // The late response of the peer is ignored, and no more pairing takes place on the link.
+        peripheral.security.handle(&peripheral, handle(), &request[0]);
+        for pdu in sent(&peripheral) {
+            central.security.handle(&central, handle(), &pdu);
+        }
+        assert!(sent(&central).is_empty());
+        assert!(central.security.state.borrow().pairing.is_none());
+        assert!(matches!(
+            central.security.request_security(&central, handle()),
+            Err(Error::Timeout)
+        ));
+    }
+
+    #[test]
+    fn peripheral_security_request_starts_pairing() {
+        let mut central_resources: HostResources<1, 1, 80> = HostResources::new();
+        let mut peripheral_resources: HostResources<1, 1, 80> = HostResources::new();
+        let central = host(&mut central_resources, Address::random(CENTRAL), 3);
+        let peripheral = host(&mut peripheral_resources, Address::random(PERIPHERAL), 4);
+        connect(&central, handle(), Address::random(PERIPHERAL), LeConnRole::Central);
+        connect(&peripheral, handle(), Address::random(CENTRAL), LeConnRole::Peripheral);
+
+        peripheral.security.request_security(&peripheral, handle()).unwrap();
+        assert_eq!(
+            run(&central, &peripheral, handle()),
+            Some((SecurityLevel::Encrypted, SecurityLevel::Encrypted))
+        );
+        assert_eq!(bonds(&peripheral).len(), 1);
+    }
+
+    #[test]
+    fn small_packets_fall_back_to_legacy_pairing() {
+        let mut central_resources: HostResources<1, 1, 27> = HostResources::new();
+        let mut peripheral_resources: HostResources<1, 1, 27> = HostResources::new();
+        let central = host(&mut central_resources, Address::random(CENTRAL), 5);
+        let peripheral = host(&mut peripheral_resources, Address::random(PERIPHERAL), 6);
+        connect(&central, handle(), Address::random(PERIPHERAL), LeConnRole::Central);
+        connect(&peripheral, handle(), Address::random(CENTRAL), LeConnRole::Peripheral);
+
+        central.security.request_security(&central, handle()).unwrap();
+        assert_eq!(
+            run(&central, &peripheral, handle()),
+            Some((SecurityLevel::Encrypted, SecurityLevel::Encrypted))
+        );
+        // The peripheral distributed the long term key of the bond.
+        let (central_bonds, peripheral_bonds) = (bonds(&central), bonds(&peripheral));
+        assert_eq!(central_bonds.len(), 1);
+        assert_eq!(central_bonds[0].ltk, peripheral_bonds[0].ltk);
+        assert_eq!(central_bonds[0].ediv, peripheral_bonds[0].ediv);
+        assert_eq!(central_bonds[0].rand, peripheral_bonds[0].rand);
+        assert_ne!(central_bonds[0].rand, [0; 8]);
+    }
+
+    #[test]
+    fn peer_with_small_packets_pairs_with_legacy_pairing() {
+        for central_small in [true, false] {
+            let mut small_resources: HostResources<1, 1, 27> = HostResources::new();
+            let mut large_resources: HostResources<1, 1, 80> = HostResources::new();
+            let (central, peripheral) = if central_small {
+                (
+                    host(&mut small_resources, Address::random(CENTRAL), 11),
+                    host(&mut large_resources, Address::random(PERIPHERAL), 12),
+                )
+            } else {
+                (
+                    host(&mut large_resources, Address::random(CENTRAL), 11),
+                    host(&mut small_resources, Address::random(PERIPHERAL), 12),
+                )
+            };
+            connect(&central, handle(), Address::random(PERIPHERAL), LeConnRole::Central);
+            connect(&peripheral, handle(), Address::random(CENTRAL), LeConnRole::Peripheral);
+
+            central.security.request_security(&central, handle()).unwrap();
+            assert_eq!(
+                run(&central, &peripheral, handle()),
+                Some((SecurityLevel::Encrypted, SecurityLevel::Encrypted))
+            );
+            // Long term keys of legacy pairing are distributed with their EDIV and Rand.
+            let (central_bonds, peripheral_bonds) = (bonds(&central), bonds(&peripheral));
+            assert_eq!(central_bonds[0].ltk, peripheral_bonds[0].ltk);
+            assert_ne!(central_bonds[0].rand, [0; 8]);
+        }
+    }
+
+    #[test]
+    fn oversized_command_fails_pairing() {
+        let mut resources: HostResources<1, 1, 80> = HostResources::new();
+        let peripheral = host(&mut resources, Address::random(PERIPHERAL), 13);
+        connect(&peripheral, handle(), Address::random(CENTRAL), LeConnRole::Peripheral);
+
+        peripheral
+            .security
+            .handle(&peripheral, handle(), &[PAIRING_REQUEST, 3, 0, 9, 16, 0, 0]);
+        assert_eq!(sent(&peripheral).len(), 1);
+        // A public key with trailing octets is not cut to the expected length.
+        peripheral.security.reject_oversized(&peripheral, handle());
+        assert_eq!(
+            sent(&peripheral),
+            [[PAIRING_FAILED, Reason::InvalidParameters.to_raw()]]
+        );
+        assert!(peripheral.security.state.borrow().pairing.is_none());
+    }
+
+    #[test]
+    fn secure_connections_only_rejects_legacy_pairing() {
+        let mut central_resources: HostResources<1, 1, 27> = HostResources::new();
+        let mut peripheral_resources: HostResources<1, 1, 80> = HostResources::new();
+        let central = host(&mut central_resources, Address::random(CENTRAL), 7);
+        let mut peripheral = host(&mut peripheral_resources, Address::random(PERIPHERAL), 8);
+        peripheral.security.set_secure_connections_only(true);
+        connect(&central, handle(), Address::random(PERIPHERAL), LeConnRole::Central);
+        connect(&peripheral, handle(), Address::random(CENTRAL), LeConnRole::Peripheral);
+
+        central.security.request_security(&central, handle()).unwrap();
+        let request = sent(&central);
+        // The central does not support LE Secure Connections with its small packets.
+        assert_eq!(request[0][3] & AUTH_SECURE_CONNECTIONS, 0);
+        peripheral.security.handle(&peripheral, handle(), &request[0]);
+        assert_eq!(
+            sent(&peripheral),
+            [[PAIRING_FAILED, Reason::AuthenticationRequirements.to_raw()]]
+        );
+        assert!(peripheral.security.state.borrow().pairing.is_none());
+        assert_eq!(peripheral.security.required_level(), SecurityLevel::SecureConnections);
+    }
+
+    // A central entering the passkey displayed by the peripheral, following the specification.
+    #[test]
+    fn passkey_entry_reaches_secure_connections_level() {
+        let mut resources: HostResources<1, 1, 80> = HostResources::new();
+        let mut peripheral = host(&mut resources, Address::random(PERIPHERAL), 10);
+        peripheral.security.set_io_capabilities(IoCapabilities::DisplayOnly);
+        peripheral.security.set_secure_connections_only(true);
+        connect(&peripheral, handle(), Address::random(CENTRAL), LeConnRole::Peripheral);
+        let exchange = |pdu: &[u8]| {
+            peripheral.security.handle(&peripheral, handle(), pdu);
+            sent(&peripheral)
+        };
+
+        let auth = AUTH_BONDING | AUTH_MITM | AUTH_SECURE_CONNECTIONS;
+        let preq = [PAIRING_REQUEST, IO_KEYBOARD_ONLY, 0, auth, MAX_KEY_SIZE, 0, 0];
+        let pres = exchange(&preq).remove(0);
+        assert_eq!(pres, [PAIRING_RESPONSE, 0x00, 0, auth, MAX_KEY_SIZE, 0, 0]);
+        let passkey = unwrap!(peripheral.security.state.borrow().pairing.as_ref().unwrap().passkey);
+
+        let secret_key = SecretKey::new(&[0x42; 32]).unwrap();
+        let public_key = secret_key.public_key();
+        let mut pdu = [PAIRING_PUBLIC_KEY; 65];
+        pdu[1..33].copy_from_slice(&public_key.x);
+        pdu[33..].copy_from_slice(&public_key.y);
+        pdu[1..33].reverse();
+        pdu[33..].reverse();
+        let reply = exchange(&pdu).remove(0);
+        let (mut x, mut y): ([u8; 32], [u8; 32]) = (reply[1..33].try_into().unwrap(), reply[33..].try_into().unwrap());
+        x.reverse();
+        y.reverse();
+        let peer_key = PublicKey::new(x, y).unwrap();
+        let dh_key = secret_key.dh_key(&peer_key).unwrap();
+
+        let (mut na, mut nb) = (0, 0);
+        for round in 0..PASSKEY_ROUNDS {
+            let r = 0x80 | ((passkey >> round) & 1) as u8;
+            na = 0x1234_5678 + round as u128;
+            let cb = value(
+                &exchange(
+                    &[
+                        &[PAIRING_CONFIRM][..],
+                        &crypto::f4(&public_key.x, &peer_key.x, na, r).to_le_bytes(),
+                    ]
+                    .concat(),
+                )[0][1..],
+            )
+            .unwrap();
+            nb = value(&exchange(&[&[PAIRING_RANDOM][..], &na.to_le_bytes()].concat())[0][1..]).unwrap();
+            assert_eq!(cb, crypto::f4(&peer_key.x, &public_key.x, nb, r));
+        }
+
+        let a = SmpAddress::new(AddrKind::RANDOM, &BdAddr::new(CENTRAL)).octets();
+        let b = SmpAddress::new(AddrKind::RANDOM, &BdAddr::new(PERIPHERAL)).octets();
+        let (mac_key, ltk) = crypto::f5(&dh_key, na, nb, &a, &b);
+        let ea = crypto::f6(mac_key, na, nb, passkey as u128, [auth, 0, IO_KEYBOARD_ONLY], &a, &b);
+        let eb = crypto::f6(mac_key, nb, na, passkey as u128, [auth, 0, 0x00], &b, &a);
+        let check = exchange(&[&[PAIRING_DHKEY_CHECK][..], &ea.to_le_bytes()].concat()).remove(0);
+        assert_eq!(value(&check[1..]).unwrap(), eb);
+
+        peripheral
+            .security
+            .long_term_key_request(&peripheral, handle(), [0; 8], 0);
+        assert!(matches!(action(&peripheral), Some(Action::LtkReply { ltk: key, .. }) if key == Key::new(ltk)));
+        let level = peripheral.security.encryption_changed(&peripheral, handle(), true);
+        assert_eq!(level, SecurityLevel::SecureConnections);
+        assert_eq!(bonds(&peripheral)[0].security_level, SecurityLevel::SecureConnections);
+    }
+
+    #[test]
+    fn unknown_peer_gets_negative_key_reply() {
+        let mut resources: HostResources<1, 1, 80> = HostResources::new();
+        let peripheral = host(&mut resources, Address::random(PERIPHERAL), 9);
+        connect(&peripheral, handle(), Address::random(CENTRAL), LeConnRole::Peripheral);
+        peripheral
+            .security
+            .long_term_key_request(&peripheral, handle(), [1; 8], 2);
+        assert!(matches!(action(&peripheral), Some(Action::LtkNegativeReply { .. })));
+    }
+
+    #[test]
+    fn resolvable_private_address_matches_bond() {
+        let irk = Key::new(0xec02_34a3_57c8_ad05_3410_10a6_0a39_7d9b);
+        // Sample data of the random address hash function ah.
+        let rpa = Address::random([0xaa, 0xfb, 0x0d, 0x94, 0x81, 0x70]);
+        let mut bond = BondInformation::new(Address::random(CENTRAL), Key::new(1), SecurityLevel::Encrypted);
+        assert!(!bond.matches(&rpa));
+        bond.irk = Some(irk);
+        assert!(bond.matches(&rpa));
+        assert!(!bond.matches(&Address::random(PERIPHERAL)));
+    }
+
+    #[test]
+    fn full_bond_table_evicts_least_recently_used() {
+        let mut resources: HostResources<1, 1, 80> = HostResources::new();
+        let mut host = host(&mut resources, Address::random(CENTRAL), 1);
+        let peer = |i: u8| Address::random([i, 0, 0, 0, 0, 0xc0]);
+        let bond = |i: u8| BondInformation::new(peer(i), Key::new(i.into()), SecurityLevel::Encrypted);
+        for i in 0..config::BOND_TABLE_SIZE as u8 {
+            host.security.add_bond(bond(i)).unwrap();
+        }
+        let full = config::BOND_TABLE_SIZE as u8;
+        assert!(matches!(host.security.add_bond(bond(full)), Err(Error::OutOfMemory)));
+
+        // Encrypting a link with the first bond makes the second one the least recently used.
+        connect(&host, handle(), peer(0), LeConnRole::Central);
+        host.security.request_security(&host, handle()).unwrap();
+        assert!(matches!(action(&host), Some(Action::EnableEncryption { .. })));
+        host.security
+            .set_eviction_policy(BondEvictionPolicy::EvictLeastRecentlyUsed);
+        host.security.add_bond(bond(full)).unwrap();
+        let identities: Vec<_> = bonds(&host).iter().map(|b| b.identity).collect();
+        assert!(!identities.contains(&peer(1)));
+        assert_eq!(identities[identities.len() - 2..], [peer(0), peer(full)]);
+
+        assert_eq!(host.security.remove_bond(&peer(0)).unwrap().ltk, Key::new(0));
+        assert!(matches!(host.security.remove_bond(&peer(0)), Err(Error::NotFound)));
+        host.security.clear_bonds();
+        assert!(bonds(&host).is_empty());
+    }
+
+    #[test]
+    fn signed_writes_reject_stale_counters() {
+        let mut central_resources: HostResources<1, 1, 80> = HostResources::new();
+        let mut peripheral_resources: HostResources<1, 1, 80> = HostResources::new();
+        let central = host(&mut central_resources, Address::random(CENTRAL), 1);
+        let peripheral = host(&mut peripheral_resources, Address::random(PERIPHERAL), 2);
+        connect(&central, handle(), Address::random(PERIPHERAL), LeConnRole::Central);
+        connect(&peripheral, handle(), Address::random(CENTRAL), LeConnRole::Peripheral);
+        central.security.request_security(&central, handle()).unwrap();
+        run(&central, &peripheral, handle()).unwrap();
+        let (central_bond, peripheral_bond) = (&bonds(&central)[0], &bonds(&peripheral)[0]);
+        assert!(central_bond.local_csrk.is_some());
+        assert_eq!(central_bond.local_csrk, peripheral_bond.peer_csrk);
+        assert_eq!(central_bond.peer_csrk, peripheral_bond.local_csrk);
+
+        let mut write = [0xd2, 0x03, 0x00, 0x01, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        central.security.sign_write(&central, handle(), &mut write).unwrap();
+        assert_eq!(write[5..9], 0u32.to_le_bytes());
+        assert_eq!(bonds(&central)[0].local_sign_counter, 1);
+        let mut tampered = write;
+        tampered[3] ^= 1;
+        assert!(
+            !peripheral
+                .security
+                .verify_signed_write(&peripheral, handle(), &tampered)
+        );
+        assert!(peripheral.security.verify_signed_write(&peripheral, handle(), &write));
+        assert_eq!(bonds(&peripheral)[0].peer_sign_counter, 1);
+        // A replayed write is rejected, while the next one is accepted.
+        assert!(!peripheral.security.verify_signed_write(&peripheral, handle(), &write));
+        central.security.sign_write(&central, handle(), &mut write).unwrap();
+        assert!(peripheral.security.verify_signed_write(&peripheral, handle(), &write));
+
+        // The counters are restored with the bond, as after a reboot.
+        let bond = peripheral.security.remove_bond(&Address::random(CENTRAL)).unwrap();
+        let mut restored = BondInformation::new(bond.identity, bond.ltk, bond.security_level);
+        restored.peer_csrk = bond.peer_csrk;
+        restored.peer_sign_counter = bond.peer_sign_counter;
+        peripheral.security.add_bond(restored).unwrap();
+        assert!(!peripheral.security.verify_signed_write(&peripheral, handle(), &write));
+    }
+
+    #[cfg(feature = "security-debug-keys")]
+    #[test]
+    fn debug_keys_pair_with_the_published_key() {
+        let mut central_resources: HostResources<1, 1, 80> = HostResources::new();
+        let mut peripheral_resources: HostResources<1, 1, 80> = HostResources::new();
+        let mut central = host(&mut central_resources, Address::random(CENTRAL), 1);
+        let mut peripheral = host(&mut peripheral_resources, Address::random(PERIPHERAL), 2);
+        central.security.use_debug_keys();
+        connect(&central, handle(), Address::random(PERIPHERAL), LeConnRole::Central);
+        connect(&peripheral, handle(), Address::random(CENTRAL), LeConnRole::Peripheral);
+
+        central.security.request_security(&central, handle()).unwrap();
+        for pdu in sent(&central) {
+            peripheral.security.handle(&peripheral, handle(), &pdu);
+        }
+        let public_key = sent(&peripheral)
+            .into_iter()
+            .find_map(|pdu| (pdu[0] == PAIRING_RESPONSE).then_some(pdu))
+            .map(|pdu| {
+                central.security.handle(&central, handle(), &pdu);
+                sent(&central).remove(0)
+            })
+            .unwrap();
+        assert_eq!(public_key[0], PAIRING_PUBLIC_KEY);
+        let (mut x, mut y) = (public_key[1..33].to_vec(), public_key[33..].to_vec());
+        x.reverse();
+        y.reverse();
+        assert_eq!(
+            (&x[..], &y[..]),
+            (&ecdh::DEBUG_PUBLIC_KEY_X[..], &ecdh::DEBUG_PUBLIC_KEY_Y[..])
+        );
+
+        // A peer in secure connections only mode refuses the debug key.
+        peripheral.security.disconnected(handle());
+        peripheral.security.set_secure_connections_only(true);
+        central.security.disconnected(handle());
+        central.security.request_security(&central, handle()).unwrap();
+        for pdu in sent(&central) {
+            peripheral.security.handle(&peripheral, handle(), &pdu);
+        }
+        for pdu in sent(&peripheral) {
+            central.security.handle(&central, handle(), &pdu);
+        }
+        for pdu in sent(&central) {
+            peripheral.security.handle(&peripheral, handle(), &pdu);
+        }
+        assert_eq!(
+            sent(&peripheral).last().unwrap()[..],
+            [PAIRING_FAILED, Reason::AuthenticationRequirements.to_raw()]
+        );
+    }
+
+    #[test]
+    fn repeated_pairing_failures_back_off() {
+        let mut resources: HostResources<1, 1, 80> = HostResources::new();
+        let peripheral = host(&mut resources, Address::random(PERIPHERAL), 2);
+        connect(&peripheral, handle(), Address::random(CENTRAL), LeConnRole::Peripheral);
+        let request = [PAIRING_REQUEST, 3, 0, 1, 16, 0, 0];
+        let waiting = |peripheral: &BleHost<'_, MockController>| {
+            let state = peripheral.security.state.borrow();
+            state.attempts[0].until.saturating_duration_since(Instant::now())
+        };
+
+        peripheral.security.handle(&peripheral, handle(), &request);
+        assert_eq!(sent(&peripheral)[0][0], PAIRING_RESPONSE);
+        peripheral
+            .security
+            .handle(&peripheral, handle(), &[PAIRING_FAILED, 0x04]);
+        assert!(waiting(&peripheral) > Duration::from_secs(1));
+        assert!(waiting(&peripheral) <= REPEATED_ATTEMPTS_INTERVAL);
+
+        // Attempts before the end of the interval are refused, without extending it.
+        peripheral.security.handle(&peripheral, handle(), &request);
+        assert_eq!(
+            sent(&peripheral)[0][..],
+            [PAIRING_FAILED, Reason::RepeatedAttempts.to_raw()]
+        );
+        assert!(waiting(&peripheral) <= REPEATED_ATTEMPTS_INTERVAL);
+
+        // The next failure doubles the interval, and a successful pairing resets it.
+        peripheral.security.state.borrow_mut().attempts[0].until = Instant::now();
+        peripheral.security.handle(&peripheral, handle(), &request);
+        assert_eq!(sent(&peripheral)[0][0], PAIRING_RESPONSE);
+        peripheral
+            .security
+            .handle(&peripheral, handle(), &[PAIRING_FAILED, 0x04]);
+        assert!(waiting(&peripheral) > REPEATED_ATTEMPTS_INTERVAL);
+        assert!(waiting(&peripheral) <= REPEATED_ATTEMPTS_INTERVAL * 2);
+        peripheral.security.state.borrow_mut().attempts[0].until = Instant::now();
+
+        let mut central_resources: HostResources<1, 1, 80> = HostResources::new();
+        let central = host(&mut central_resources, Address::random(CENTRAL), 1);
+        connect(&central, handle(), Address::random(PERIPHERAL), LeConnRole::Central);
+        central.security.request_security(&central, handle()).unwrap();
+        run(&central, &peripheral, handle()).unwrap();
+        assert!(peripheral.security.state.borrow().attempts.is_empty());
+    }
+
+    #[test]
+    fn bonded_peers_back_off_across_private_addresses() {
+        let irk = Key::new(0xec02_34a3_57c8_ad05_3410_10a6_0a39_7d9b);
+        let rpa = |prand: u32| {
+            let hash = crypto::ah(irk.0, prand).to_le_bytes();
+            let prand = prand.to_le_bytes();
+            Address::random([hash[0], hash[1], hash[2], prand[0], prand[1], prand[2]])
+        };
+        let mut resources: HostResources<1, 1, 80> = HostResources::new();
+        let peripheral = host(&mut resources, Address::random(PERIPHERAL), 2);
+        let mut bond = BondInformation::new(Address::random(CENTRAL), Key::new(1), SecurityLevel::Encrypted);
+        bond.irk = Some(irk);
+        peripheral.security.state.borrow_mut().bonds.push(bond).unwrap();
+        let request = [PAIRING_REQUEST, 3, 0, 1, 16, 0, 0];
+
+        connect(&peripheral, handle(), rpa(0x708194), LeConnRole::Peripheral);
+        peripheral.security.handle(&peripheral, handle(), &request);
+        assert_eq!(sent(&peripheral)[0][0], PAIRING_RESPONSE);
+        peripheral
+            .security
+            .handle(&peripheral, handle(), &[PAIRING_FAILED, 0x04]);
+        assert_eq!(
+            peripheral.security.state.borrow().attempts[0].peer,
+            Address::random(CENTRAL)
+        );
+
+        // The peer reconnecting with another private address is still refused.
+        peripheral
+            .connections
+            .disconnected(handle(), Status::REMOTE_USER_TERMINATED_CONN)
+            .unwrap();
+        connect(&peripheral, handle(), rpa(0x5a0b1c), LeConnRole::Peripheral);
+        peripheral.security.handle(&peripheral, handle(), &request);
+        assert_eq!(
+            sent(&peripheral)[0][..],
+            [PAIRING_FAILED, Reason::RepeatedAttempts.to_raw()]
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! Cryptographic functions of the security manager.
+//!
+//! The functions follow the notation of the specification (Vol 3, Part H, 2.2), their 128-bit
+//! inputs and outputs being numbers whose most significant octet is the first octet of the AES
+//! block. Keys and values are exchanged with the peer least significant octet first, the
+//! `u128::from_le_bytes` of the octets received. AES-128 and AES-CMAC are those of the `aes`
+//! and `cmac` crates, and SHA-256, only used by the services deriving their keys from ECDH, is
+//! implemented here.
+
+use aes::Aes128;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use cmac::Mac;
+
+/// Security function e, encrypting a block with AES-128.
+pub(crate) fn e(key: u128, plaintext: u128) -> u128 {
+    let mut block = plaintext.to_be_bytes().into();
+    Aes128::new(&key.to_be_bytes().into()).encrypt_block(&mut block);
+    u128::from_be_bytes(block.into())
+}
+
+/// Inverse of the security function e, decrypting a block with AES-128.
+pub(crate) fn d(key: u128, ciphertext: u128) -> u128 {
+    let mut block = ciphertext.to_be_bytes().into();
+    Aes128::new(&key.to_be_bytes().into()).decrypt_block(&mut block);
+    u128::from_be_bytes(block.into())
+}
+
+/// AES-CMAC of RFC 4493, computed over the octets given to [`Cmac::update`].
+pub(crate) struct Cmac(cmac::Cmac<Aes128>);
+
+impl Cmac {
+    pub(crate) fn new(key: u128) -> Self {
+        Self(<cmac::Cmac<Aes128> as KeyInit>::new(&key.to_be_bytes().into()))
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) -> &mut Self {
+        self.0.update(data);
+        self
+    }
+
+    pub(crate) fn finalize(&mut self) -> u128 {
+        u128::from_be_bytes(self.0.finalize_reset().into_bytes().into())
+    }
+}
+
+/// SHA-256 of FIPS 180-4, computed over the octets given to [`Sha256::update`].
+pub(crate) struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    len: u64,
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5, 0xd807aa98,
+    0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+    0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8,
+    0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+    0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819,
+    0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+    0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+    0xc67178f2,
+];
+
+impl Sha256 {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+            ],
+            block: [0; 64],
+            len: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) -> &mut Self {
+        for &octet in data {
+            self.block[(self.len % 64) as usize] = octet;
+            self.len += 1;
+            if self.len % 64 == 0 {
+                self.compress();
+            }
+        }
+        self
+    }
+
+    pub(crate) fn finalize(mut self) -> [u8; 32] {
+        let bits = self.len * 8;
+        // The message is padded with a one bit, then zeros up to its length in the last 8 octets.
+        self.update(&[0x80]);
+        while self.len % 64 != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut hash = [0; 32];
+        for (octets, word) in hash.chunks_exact_mut(4).zip(self.state) {
+            octets.copy_from_slice(&word.to_be_bytes());
+        }
+        hash
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (word, octets) in w.iter_mut().zip(self.block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([octets[0], octets[1], octets[2], octets[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for (k, w) in SHA256_K.iter().zip(w) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(*k).wrapping_add(w);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+/// Random address hash function ah, resolving private addresses with an IRK.
+pub(crate) fn ah(k: u128, r: u32) -> u32 {
+    (e(k, (r & 0xff_ffff) as u128) & 0xff_ffff) as u32
+}
+
+/// Confirm value generation function c1 of legacy pairing.
+///
+/// `preq` and `pres` are the pairing request and response commands, and the addresses the
+/// types and addresses of the initiator and responder.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn c1(k: u128, r: u128, preq: [u8; 7], pres: [u8; 7], iat: u8, ia: [u8; 6], rat: u8, ra: [u8; 6]) -> u128 {
+    let p1 = (le_number(&pres) << 72) | (le_number(&preq) << 16) | ((rat as u128) << 8) | iat as u128;
+    let p2 = (le_number(&ia) << 48) | le_number(&ra);
+    e(k, e(k, r ^ p1) ^ p2)
+}
+
+/// Key generation function s1 of legacy pairing, generating the STK.
+pub(crate) fn s1(k: u128, r1: u128, r2: u128) -> u128 {
+    e(k, (r1 << 64) | (r2 & u64::MAX as u128))
+}
+
+/// Confirm value generation function f4 of LE Secure Connections.
+pub(crate) fn f4(u: &[u8; 32], v: &[u8; 32], x: u128, z: u8) -> u128 {
+    Cmac::new(x).update(u).update(v).update(&[z]).finalize()
+}
+
+/// Key generation function f5 of LE Secure Connections, generating the MacKey and the LTK.
+///
+/// The addresses are the type of the address followed by the address, most significant octet first.
+pub(crate) fn f5(w: &[u8; 32], n1: u128, n2: u128, a1: &[u8; 7], a2: &[u8; 7]) -> (u128, u128) {
+    const SALT: u128 = 0x6c88_8391_aaf5_a538_6037_0bdb_5a60_83be;
+    const KEY_ID: [u8; 4] = *b"btle";
+    let t = Cmac::new(SALT).update(w).finalize();
+    let key = |counter: u8| {
+        Cmac::new(t)
+            .update(&[counter])
+            .update(&KEY_ID)
+            .update(&n1.to_be_bytes())
+            .update(&n2.to_be_bytes())
+            .update(a1)
+            .update(a2)
+            .update(&256u16.to_be_bytes())
+            .finalize()
+    };
+    (key(0), key(1))
+}
+
+/// Check value generation function f6 of LE Secure Connections.
+///
+/// The IO capabilities are the authentication requirements, the OOB data flag and the IO
+/// capability of the pairing command of the device.
+pub(crate) fn f6(w: u128, n1: u128, n2: u128, r: u128, io_cap: [u8; 3], a1: &[u8; 7], a2: &[u8; 7]) -> u128 {
+    Cmac::new(w)
+        .update(&n1.to_be_bytes())
+        .update(&n2.to_be_bytes())
+        .update(&r.to_be_bytes())
+        .update(&io_cap)
+        .update(a1)
+        .update(a2)
+        .finalize()
+}
+
+/// Signature of a signed write command, its MAC over the PDU followed by the sign counter.
+///
+/// The PDU is the octets of the command before the signature. The MAC is the 64 most significant
+/// bits of the CMAC, in the order sent to the peer.
+pub(crate) fn sign(csrk: u128, pdu: &[u8], counter: u32) -> [u8; 8] {
+    let mut cmac = Cmac::new(csrk);
+    // The message is a number sent least significant octet first.
+    for b in counter.to_be_bytes().iter().chain(pdu.iter().rev()) {
+        cmac.update(&[*b]);
+    }
+    let mut mac = [0; 8];
+    mac.copy_from_slice(&cmac.finalize().to_be_bytes()[..8]);
+    mac.reverse();
+    mac
+}
+
+// A number sent least significant octet first.
+fn le_number(octets: &[u8]) -> u128 {
+    octets.iter().rev().fold(0, |n, b| (n << 8) | *b as u128)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn be(hex: &str) -> u128 {
+        u128::from_str_radix(hex, 16).unwrap()
+    }
+
+    fn be32(hex: &str) -> [u8; 32] {
+        let mut out = [0; 32];
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn aes_encrypts_fips_197_example() {
+        assert_eq!(
+            e(
+                be("000102030405060708090a0b0c0d0e0f"),
+                be("00112233445566778899aabbccddeeff")
+            ),
+            be("69c4e0d86a7b0430d8cdb78070b4c55a")
+        );
+        assert_eq!(
+            d(
+                be("000102030405060708090a0b0c0d0e0f"),
+                be("69c4e0d86a7b0430d8cdb78070b4c55a")
+            ),
+            be("00112233445566778899aabbccddeeff")
+        );
+    }
+
+    #[test]
+    fn sha256_matches_fips_180_examples() {
+        let mut sha = Sha256::new();
+        sha.update(b"abc");
+        assert_eq!(
+            sha.finalize(),
+            be32("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        // Over two blocks, given in parts.
+        let mut sha = Sha256::new();
+        sha.update(b"abcdbcdecdefdefgefghfghighijhijkijkljklm")
+            .update(b"klmnlmnomnopnopq");
+        assert_eq!(
+            sha.finalize(),
+            be32("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1")
+        );
+    }
+
+    #[test]
+    fn cmac_matches_rfc_4493_examples() {
+        let key = be("2b7e151628aed2a6abf7158809cf4f3c");
+        let message = [
+            0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a, 0xae, 0x2d,
+            0x8a, 0x57, 0x1e, 0x03, 0xac, 0x9c, 0x9e, 0xb7, 0x6f, 0xac, 0x45, 0xaf, 0x8e, 0x51, 0x30, 0xc8, 0x1c, 0x46,
+            0xa3, 0x5c, 0xe4, 0x11,
+        ];
+        assert_eq!(Cmac::new(key).finalize(), be("bb1d6929e95937287fa37d129b756746"));
+        assert_eq!(
+            Cmac::new(key).update(&message[..16]).finalize(),
+            be("070a16b46b4d4144f79bdd9dd04a287c")
+        );
+        assert_eq!(
+            Cmac::new(key).update(&message[..7]).update(&message[7..]).finalize(),
+            be("dfa66747de9ae63030ca32611497c827")
+        );
+    }
+
+    #[test]
+    fn legacy_functions_match_specification_examples() {
+        let preq = 0x07071000000101u64.to_le_bytes()[..7].try_into().unwrap();
+        let pres = 0x05000800000302u64.to_le_bytes()[..7].try_into().unwrap();
+        let ia = [0xa6, 0xa5, 0xa4, 0xa3, 0xa2, 0xa1];
+        let ra = [0xb6, 0xb5, 0xb4, 0xb3, 0xb2, 0xb1];
+        let r = be("5783d52156ad6f0e6388274ec6702ee0");
+        assert_eq!(
+            c1(0, r, preq, pres, 1, ia, 0, ra),
+            be("1e1e3fef878988ead2a74dc5bef13b86")
+        );
+
+        let r1 = be("000f0e0d0c0b0a091122334455667788");
+        let r2 = be("010203040506070899aabbccddeeff00");
+        assert_eq!(s1(0, r1, r2), be("9a1fe1f0e8b0f49b5b4216ae796da062"));
+
+        assert_eq!(ah(be("ec0234a357c8ad05341010a60a397d9b"), 0x708194), 0x0dfbaa);
+    }
+
+    #[test]
+    fn secure_connections_functions_match_specification_examples() {
+        let u = be32("20b003d2f297be2c5e2c83a7e9f9a5b9eff49111acf4fddbcc0301480e359de6");
+        let v = be32("55188b3d32f6bb9a900afcfbeed4e72a59cb9ac2f19d7cfb6b4fdd49f47fc5fd");
+        let n1 = be("d5cb8454d177733effffb2ec712baeab");
+        let n2 = be("a6e8e7cc25a75f6e216583f7ff3dc4cf");
+        assert_eq!(f4(&u, &v, n1, 0), be("f2c916f107a9bd1cf1eda1bea974872d"));
+
+        let w = be32("ec0234a357c8ad05341010a60a397d9b99796b13b4f866f1868d34f373bfa698");
+        let a1 = [0x00, 0x56, 0x12, 0x37, 0x37, 0xbf, 0xce];
+        let a2 = [0x00, 0xa7, 0x13, 0x70, 0x2d, 0xcf, 0xc1];
+        let (mac_key, ltk) = f5(&w, n1, n2, &a1, &a2);
+        assert_eq!(mac_key, be("2965f176a1084a02fd3f6a20ce636e20"));
+        assert_eq!(ltk, be("6986791169d7cd23980522b594750a38"));
+
+        let r = be("12a3343bb453bb5408da42d20c2d0fc8");
+        assert_eq!(
+            f6(mac_key, n1, n2, r, [0x01, 0x01, 0x02], &a1, &a2),
+            be("e3c473989cd0e8c5d26c0b09da958f61")
+        );
+    }
+
+    #[test]
+    fn signature_is_the_most_significant_half_of_the_mac() {
+        let csrk = be("2b7e151628aed2a6abf7158809cf4f3c");
+        let pdu = [0xd2, 0x03, 0x00, 0x01, 0x02, 0x03];
+        assert_eq!(sign(csrk, &pdu, 5), [0x61, 0xf0, 0x73, 0xd3, 0xd0, 0x94, 0x67, 0x9e]);
+    }
+}
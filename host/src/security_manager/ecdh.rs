@@ -0,0 +1,140 @@
+//! Elliptic curve Diffie-Hellman on the P-256 curve, for LE Secure Connections pairing.
+//!
+//! The keys are those of the `p256` crate, exchanged with the peer as the coordinates of their
+//! points, most significant octet first.
+
+use p256::elliptic_curve::sec1::{EncodedPoint, FromEncodedPoint, ToEncodedPoint};
+use p256::{AffinePoint, NistP256};
+
+/// The private key of the debug mode of LE Secure Connections, published so that sniffers can
+/// decrypt the links paired with it. Never to be used in production.
+pub(crate) const DEBUG_SECRET_KEY: [u8; 32] = [
+    0x3f, 0x49, 0xf6, 0xd4, 0xa3, 0xc5, 0x5f, 0x38, 0x74, 0xc9, 0xb3, 0xe3, 0xd2, 0x10, 0x3f, 0x50, 0x4a, 0xff, 0x60,
+    0x7b, 0xeb, 0x40, 0xb7, 0x99, 0x58, 0x99, 0xb8, 0xa6, 0xcd, 0x3c, 0x1a, 0xbd,
+];
+
+/// The coordinates of the public key of the debug mode.
+pub(crate) const DEBUG_PUBLIC_KEY_X: [u8; 32] = [
+    0x20, 0xb0, 0x03, 0xd2, 0xf2, 0x97, 0xbe, 0x2c, 0x5e, 0x2c, 0x83, 0xa7, 0xe9, 0xf9, 0xa5, 0xb9, 0xef, 0xf4, 0x91,
+    0x11, 0xac, 0xf4, 0xfd, 0xdb, 0xcc, 0x03, 0x01, 0x48, 0x0e, 0x35, 0x9d, 0xe6,
+];
+pub(crate) const DEBUG_PUBLIC_KEY_Y: [u8; 32] = [
+    0xdc, 0x80, 0x9c, 0x49, 0x65, 0x2a, 0xeb, 0x6d, 0x63, 0x32, 0x9a, 0xbf, 0x5a, 0x52, 0x15, 0x5c, 0x76, 0x63, 0x45,
+    0xc2, 0x8f, 0xed, 0x30, 0x24, 0x74, 0x1c, 0x8e, 0xd0, 0x15, 0x89, 0xd2, 0x8b,
+];
+
+/// A public key, the coordinates of a point of the curve, most significant octet first.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PublicKey {
+    pub(crate) x: [u8; 32],
+    pub(crate) y: [u8; 32],
+}
+
+impl PublicKey {
+    /// The key of the coordinates received from a peer, none if they are not a point of the curve.
+    pub(crate) fn new(x: [u8; 32], y: [u8; 32]) -> Option<Self> {
+        let point: Option<p256::PublicKey> = p256::PublicKey::from_encoded_point(&encoded(&x, &y)).into();
+        point.map(|_| Self { x, y })
+    }
+
+    fn point(&self) -> p256::PublicKey {
+        // The coordinates were checked to be a point of the curve when the key was created.
+        let point: Option<p256::PublicKey> = p256::PublicKey::from_encoded_point(&encoded(&self.x, &self.y)).into();
+        unwrap!(point)
+    }
+}
+
+fn encoded(x: &[u8; 32], y: &[u8; 32]) -> EncodedPoint<NistP256> {
+    EncodedPoint::<NistP256>::from_affine_coordinates(x.into(), y.into(), false)
+}
+
+/// A private key, a scalar in [1, n - 1].
+#[derive(Clone)]
+pub(crate) struct SecretKey(p256::SecretKey);
+
+impl SecretKey {
+    /// The key of a scalar, most significant octet first, none if it is not in [1, n - 1].
+    pub(crate) fn new(scalar: &[u8; 32]) -> Option<Self> {
+        p256::SecretKey::from_bytes(scalar.into()).ok().map(Self)
+    }
+
+    pub(crate) fn public_key(&self) -> PublicKey {
+        let point = self.0.public_key().to_encoded_point(false);
+        // An uncompressed point of a public key, never the identity, has both coordinates.
+        PublicKey {
+            x: unwrap!(point.x()).as_slice().try_into().unwrap(),
+            y: unwrap!(point.y()).as_slice().try_into().unwrap(),
+        }
+    }
+
+    /// The shared secret with a peer, the X coordinate of the product of its key with ours.
+    pub(crate) fn dh_key(&self, peer: &PublicKey) -> Option<[u8; 32]> {
+        let point: AffinePoint = *peer.point().as_affine();
+        let shared = p256::ecdh::diffie_hellman(self.0.to_nonzero_scalar(), point);
+        shared.raw_secret_bytes().as_slice().try_into().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn be32(hex: &str) -> [u8; 32] {
+        let mut out = [0; 32];
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn debug_key_pair_matches_specification() {
+        assert_eq!(
+            DEBUG_SECRET_KEY,
+            be32("3f49f6d4a3c55f3874c9b3e3d2103f504aff607beb40b7995899b8a6cd3c1abd")
+        );
+        let public = SecretKey::new(&DEBUG_SECRET_KEY).unwrap().public_key();
+        assert_eq!(
+            public.x,
+            be32("20b003d2f297be2c5e2c83a7e9f9a5b9eff49111acf4fddbcc0301480e359de6")
+        );
+        assert_eq!(
+            public.y,
+            be32("dc809c49652aeb6d63329abf5a52155c766345c28fed3024741c8ed01589d28b")
+        );
+        assert_eq!((public.x, public.y), (DEBUG_PUBLIC_KEY_X, DEBUG_PUBLIC_KEY_Y));
+    }
+
+    #[test]
+    fn both_sides_compute_the_same_shared_secret() {
+        let a = SecretKey::new(&be32(
+            "3f49f6d4a3c55f3874c9b3e3d2103f504aff607beb40b7995899b8a6cd3c1abd",
+        ))
+        .unwrap();
+        let b = SecretKey::new(&be32(
+            "55188b3d32f6bb9a900afcfbeed4e72a59cb9ac2f19d7cfb6b4fdd49f47fc5fd",
+        ))
+        .unwrap();
+        let (pa, pb) = (a.public_key(), b.public_key());
+        assert_eq!(
+            pb.x,
+            be32("1ea1f0f01faf1d9609592284f19e4c0047b58afd8615a69f559077b22faaa190")
+        );
+        let expected = be32("ec0234a357c8ad05341010a60a397d9b99796b13b4f866f1868d34f373bfa698");
+        assert_eq!(a.dh_key(&pb), Some(expected));
+        assert_eq!(b.dh_key(&pa), Some(expected));
+    }
+
+    #[test]
+    fn points_off_the_curve_are_rejected() {
+        let x = be32("20b003d2f297be2c5e2c83a7e9f9a5b9eff49111acf4fddbcc0301480e359de6");
+        let y = be32("dc809c49652aeb6d63329abf5a52155c766345c28fed3024741c8ed01589d28b");
+        assert!(PublicKey::new(x, y).is_some());
+        let mut invalid = y;
+        invalid[31] ^= 1;
+        assert!(PublicKey::new(x, invalid).is_none());
+        assert!(PublicKey::new([0xff; 32], y).is_none());
+        assert!(SecretKey::new(&[0; 32]).is_none());
+        assert!(SecretKey::new(&[0xff; 32]).is_none());
+    }
+}
@@ -0,0 +1,32 @@
+//! Ready-made GATT services, with the servers adding them to an attribute table and the clients
+//! using them on a remote device.
+//!
+//! The servers are added to a table next to the services of the application, and updated with
+//! the values of the device. The clients discover the service over a
+//! [`GattClient`](crate::gatt::GattClient) whose task is running, whether the device is the
+//! central or the peripheral of the connection.
+pub mod ams;
+pub mod ancs;
+pub mod battery;
+pub mod blood_pressure;
+#[cfg(feature = "security")]
+pub mod bond_management;
+pub mod current_time;
+pub mod cycling_speed_cadence;
+pub mod device_information;
+pub mod environmental_sensing;
+#[cfg(feature = "security")]
+pub mod fast_pair;
+pub mod glucose;
+pub mod health_thermometer;
+pub mod heart_rate;
+pub mod hid;
+pub mod ieee11073;
+pub mod nus;
+pub mod ots;
+pub mod proximity;
+pub mod record_access;
+pub mod running_speed_cadence;
+pub mod sc_control_point;
+pub mod scan_parameters;
+pub mod smp;
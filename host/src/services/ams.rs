@@ -0,0 +1,454 @@
+//! Apple Media Service client, controlling the media player of an iOS device.
+//!
+//! The client registers for the attributes of the player, the queue and the track it follows,
+//! whose values are then notified by the iOS device on each change, and sends remote commands
+//! such as play, pause or next track. Like the
+//! [Apple Notification Center Service](crate::services::ancs), the service is only listed to an
+//! encrypted connection.
+use crate::Error;
+use crate::att::AttErrorCode;
+use crate::attribute::Characteristic;
+use crate::gatt::{GattClient, Notification, NotificationListener};
+use crate::types::uuid::Uuid;
+use crate::{BleHostError, Controller};
+
+/// UUID of the Apple Media Service, in the order of [`AMS_SOLICITATION`].
+const AMS_SERVICE_BYTES: [u8; 16] = 0x89d3502b_0f36_433a_8ef4_c502ad55f8dc_u128.to_le_bytes();
+
+/// UUID of the Apple Media Service.
+pub const AMS_SERVICE: Uuid = Uuid::new_long(AMS_SERVICE_BYTES);
+
+/// UUID of the remote command, written with the commands and notifying the commands supported.
+pub const AMS_REMOTE_COMMAND: Uuid = Uuid::new_long(0x9b3c81d8_57b1_4a8a_b8df_0e56f7ca51c2_u128.to_le_bytes());
+
+/// UUID of the entity update, written with the attributes to follow and notifying their values.
+pub const AMS_ENTITY_UPDATE: Uuid = Uuid::new_long(0x2f7cabce_808d_411f_9a0c_bb92ba96c102_u128.to_le_bytes());
+
+/// UUID of the entity attribute, reading the whole value of an attribute.
+pub const AMS_ENTITY_ATTRIBUTE: Uuid = Uuid::new_long(0xc6b2f38c_23ab_46d8_a6ab_a3a870bbd5d7_u128.to_le_bytes());
+
+/// Service solicitation UUIDs to advertise for the iOS device to list the service, with
+/// [`AdStructure::ServiceSolicitationUuids128`](crate::advertise::AdStructure::ServiceSolicitationUuids128).
+pub const AMS_SOLICITATION: [[u8; 16]; 1] = [AMS_SERVICE_BYTES];
+
+/// Error of a command while the media player is not in a valid state.
+pub const ERROR_INVALID_STATE: AttErrorCode = AttErrorCode::proprietary(0xa0);
+/// Error of a malformed command.
+pub const ERROR_INVALID_COMMAND: AttErrorCode = AttErrorCode::proprietary(0xa1);
+/// Error of a read of an attribute which is empty.
+pub const ERROR_ABSENT_ATTRIBUTE: AttErrorCode = AttErrorCode::proprietary(0xa2);
+
+const FLAG_TRUNCATED: u8 = 0x01;
+
+/// Command of the media player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum RemoteCommand {
+    /// Play.
+    Play = 0,
+    /// Pause.
+    Pause = 1,
+    /// Toggle between play and pause.
+    TogglePlayPause = 2,
+    /// Skip to the next track.
+    NextTrack = 3,
+    /// Go back to the previous track.
+    PreviousTrack = 4,
+    /// Turn the volume up.
+    VolumeUp = 5,
+    /// Turn the volume down.
+    VolumeDown = 6,
+    /// Move to the next repeat mode.
+    AdvanceRepeatMode = 7,
+    /// Move to the next shuffle mode.
+    AdvanceShuffleMode = 8,
+    /// Skip forward in the track.
+    SkipForward = 9,
+    /// Skip backward in the track.
+    SkipBackward = 10,
+    /// Like the track.
+    LikeTrack = 11,
+    /// Dislike the track.
+    DislikeTrack = 12,
+    /// Bookmark the track.
+    BookmarkTrack = 13,
+}
+
+/// Set of the commands supported by the media player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SupportedCommands(u16);
+
+impl SupportedCommands {
+    /// Decode the value of the remote command, listing the commands supported.
+    ///
+    /// Unknown commands are ignored.
+    pub fn decode(data: &[u8]) -> Self {
+        Self(
+            data.iter()
+                .filter(|&&id| id <= RemoteCommand::BookmarkTrack as u8)
+                .fold(0, |set, &id| set | (1 << id)),
+        )
+    }
+}
+
+bitfield_set!(SupportedCommands: u16, RemoteCommand, "command");
+
+/// Entity of the media player, with attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum EntityId {
+    /// The media player.
+    Player = 0,
+    /// The queue of tracks.
+    Queue = 1,
+    /// The current track.
+    Track = 2,
+}
+
+/// Attribute of an entity of the media player.
+///
+/// All values are strings, decoded by [`PlaybackInfo::parse`] and friends for those not meant to
+/// be displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EntityAttribute {
+    /// Name of the application playing.
+    PlayerName,
+    /// State, rate and elapsed time of the playback, see [`PlaybackInfo`].
+    PlayerPlaybackInfo,
+    /// Volume, from 0 to 1.
+    PlayerVolume,
+    /// Index of the track in the queue.
+    QueueIndex,
+    /// Number of tracks in the queue.
+    QueueCount,
+    /// Shuffle mode, see [`ShuffleMode`].
+    QueueShuffleMode,
+    /// Repeat mode, see [`RepeatMode`].
+    QueueRepeatMode,
+    /// Artist of the track.
+    TrackArtist,
+    /// Album of the track.
+    TrackAlbum,
+    /// Title of the track.
+    TrackTitle,
+    /// Duration of the track, in seconds.
+    TrackDuration,
+}
+
+impl EntityAttribute {
+    /// Entity of the attribute.
+    pub const fn entity(&self) -> EntityId {
+        match self {
+            Self::PlayerName | Self::PlayerPlaybackInfo | Self::PlayerVolume => EntityId::Player,
+            Self::QueueIndex | Self::QueueCount | Self::QueueShuffleMode | Self::QueueRepeatMode => EntityId::Queue,
+            Self::TrackArtist | Self::TrackAlbum | Self::TrackTitle | Self::TrackDuration => EntityId::Track,
+        }
+    }
+
+    /// Identifier of the attribute in its entity.
+    pub const fn id(&self) -> u8 {
+        match self {
+            Self::PlayerName | Self::QueueIndex | Self::TrackArtist => 0,
+            Self::PlayerPlaybackInfo | Self::QueueCount | Self::TrackAlbum => 1,
+            Self::PlayerVolume | Self::QueueShuffleMode | Self::TrackTitle => 2,
+            Self::QueueRepeatMode | Self::TrackDuration => 3,
+        }
+    }
+
+    fn decode(entity: u8, id: u8) -> Result<Self, Error> {
+        Ok(match (entity, id) {
+            (0, 0) => Self::PlayerName,
+            (0, 1) => Self::PlayerPlaybackInfo,
+            (0, 2) => Self::PlayerVolume,
+            (1, 0) => Self::QueueIndex,
+            (1, 1) => Self::QueueCount,
+            (1, 2) => Self::QueueShuffleMode,
+            (1, 3) => Self::QueueRepeatMode,
+            (2, 0) => Self::TrackArtist,
+            (2, 1) => Self::TrackAlbum,
+            (2, 2) => Self::TrackTitle,
+            (2, 3) => Self::TrackDuration,
+            _ => return Err(Error::InvalidValue),
+        })
+    }
+}
+
+/// Update of the value of an attribute, notified by the entity update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EntityUpdate<'a> {
+    /// Attribute updated.
+    pub attribute: EntityAttribute,
+    /// Whether the value was truncated to fit the notification, the whole value being read with
+    /// [`AmsClient::read_attribute`].
+    pub truncated: bool,
+    /// Value of the attribute.
+    pub value: &'a str,
+}
+
+impl<'a> EntityUpdate<'a> {
+    /// Decode the value of the entity update.
+    ///
+    /// A truncated value is cut to its last whole character.
+    pub fn decode(data: &'a [u8]) -> Result<Self, Error> {
+        let [entity, id, flags, value @ ..] = data else {
+            return Err(Error::InvalidValue);
+        };
+        let attribute = EntityAttribute::decode(*entity, *id)?;
+        let truncated = flags & FLAG_TRUNCATED != 0;
+        let value = match core::str::from_utf8(value) {
+            Ok(value) => value,
+            Err(e) if truncated && e.error_len().is_none() => {
+                unwrap!(core::str::from_utf8(&value[..e.valid_up_to()]).ok())
+            }
+            Err(_) => return Err(Error::InvalidValue),
+        };
+        Ok(Self {
+            attribute,
+            truncated,
+            value,
+        })
+    }
+}
+
+/// Notification of the entity update, holding a valid [`EntityUpdate`].
+pub struct EntityUpdateNotification<const MTU: usize> {
+    notification: Notification<MTU>,
+}
+
+impl<const MTU: usize> EntityUpdateNotification<MTU> {
+    /// The update notified.
+    pub fn update(&self) -> EntityUpdate<'_> {
+        unwrap!(EntityUpdate::decode(self.notification.as_ref()).ok())
+    }
+}
+
+/// State of the playback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PlaybackState {
+    /// Paused.
+    Paused,
+    /// Playing.
+    Playing,
+    /// Rewinding.
+    Rewinding,
+    /// Fast forwarding.
+    FastForwarding,
+}
+
+/// Value of [`EntityAttribute::PlayerPlaybackInfo`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PlaybackInfo {
+    /// State of the playback.
+    pub state: PlaybackState,
+    /// Rate of the playback, 1 when playing at normal speed.
+    pub rate: f32,
+    /// Time elapsed in the track, in seconds.
+    pub elapsed: f32,
+}
+
+impl PlaybackInfo {
+    /// Parse the value of the attribute, its state, rate and elapsed time separated by commas.
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        let mut fields = value.split(',');
+        let mut next = || fields.next().ok_or(Error::InvalidValue);
+        let state = match next()? {
+            "0" => PlaybackState::Paused,
+            "1" => PlaybackState::Playing,
+            "2" => PlaybackState::Rewinding,
+            "3" => PlaybackState::FastForwarding,
+            _ => return Err(Error::InvalidValue),
+        };
+        let rate = next()?.parse().map_err(|_| Error::InvalidValue)?;
+        let elapsed = next()?.parse().map_err(|_| Error::InvalidValue)?;
+        Ok(Self { state, rate, elapsed })
+    }
+}
+
+/// Value of [`EntityAttribute::QueueShuffleMode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ShuffleMode {
+    /// No shuffle.
+    Off,
+    /// Shuffle of the tracks.
+    One,
+    /// Shuffle of the albums.
+    All,
+}
+
+impl ShuffleMode {
+    /// Parse the value of the attribute.
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        match value {
+            "0" => Ok(Self::Off),
+            "1" => Ok(Self::One),
+            "2" => Ok(Self::All),
+            _ => Err(Error::InvalidValue),
+        }
+    }
+}
+
+/// Value of [`EntityAttribute::QueueRepeatMode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RepeatMode {
+    /// No repeat.
+    Off,
+    /// Repeat of the track.
+    One,
+    /// Repeat of the queue.
+    All,
+}
+
+impl RepeatMode {
+    /// Parse the value of the attribute.
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        match value {
+            "0" => Ok(Self::Off),
+            "1" => Ok(Self::One),
+            "2" => Ok(Self::All),
+            _ => Err(Error::InvalidValue),
+        }
+    }
+}
+
+// The characteristics have variable lengths, and are handled by hand.
+type RawCharacteristic = Characteristic<u8>;
+
+/// Apple Media Service client, following and controlling the media player of an iOS device.
+pub struct AmsClient<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize> {
+    gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>,
+    remote_command: RawCharacteristic,
+    entity_update: RawCharacteristic,
+    entity_attribute: RawCharacteristic,
+    commands: NotificationListener<'c, L2CAP_MTU>,
+    updates: NotificationListener<'c, L2CAP_MTU>,
+}
+
+impl<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize>
+    AmsClient<'c, 'd, C, MAX_SERVICES, L2CAP_MTU>
+{
+    /// Discover the service of the iOS device, and subscribe to its remote command and entity update.
+    ///
+    /// The GATT client must have room for one more service, and two free notification
+    /// subscribers. The connection must be encrypted, the iOS device rejecting the subscriptions
+    /// otherwise.
+    pub async fn new(gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>) -> Result<Self, BleHostError<C::Error>> {
+        let services = gatt.services_by_uuid(&AMS_SERVICE).await?;
+        let ams = services.first().cloned().ok_or(Error::NotFound)?;
+        let remote_command = gatt.characteristic_by_uuid(&ams, &AMS_REMOTE_COMMAND).await?;
+        let entity_update = gatt.characteristic_by_uuid(&ams, &AMS_ENTITY_UPDATE).await?;
+        let entity_attribute = gatt.characteristic_by_uuid(&ams, &AMS_ENTITY_ATTRIBUTE).await?;
+        let commands = gatt.subscribe(&remote_command, false).await?;
+        let updates = gatt.subscribe(&entity_update, false).await?;
+        Ok(Self {
+            gatt,
+            remote_command,
+            entity_update,
+            entity_attribute,
+            commands,
+            updates,
+        })
+    }
+
+    /// Send a command to the media player.
+    ///
+    /// Returns [`ERROR_INVALID_STATE`] if the media player is not running.
+    pub async fn send_command(&self, command: RemoteCommand) -> Result<(), BleHostError<C::Error>> {
+        self.gatt
+            .write_characteristic(&self.remote_command, &[command as u8])
+            .await
+    }
+
+    /// Wait for the next list of the commands supported, notified when the media player changes.
+    pub async fn next_supported_commands(&mut self) -> SupportedCommands {
+        SupportedCommands::decode(self.commands.next().await.as_ref())
+    }
+
+    /// Follow the attributes of an entity, replacing the attributes followed of the entity.
+    ///
+    /// The iOS device notifies their values right away, then on each change. Returns
+    /// [`Error::InvalidValue`] unless the attributes are all of the same entity.
+    pub async fn register(&self, attributes: &[EntityAttribute]) -> Result<(), BleHostError<C::Error>> {
+        let entity = attributes.first().ok_or(Error::InvalidValue)?.entity();
+        if attributes.len() > 4 || attributes.iter().any(|a| a.entity() != entity) {
+            return Err(Error::InvalidValue.into());
+        }
+        let mut command = [entity as u8; 5];
+        for (id, attribute) in command[1..].iter_mut().zip(attributes) {
+            *id = attribute.id();
+        }
+        self.gatt
+            .write_characteristic(&self.entity_update, &command[..1 + attributes.len()])
+            .await
+    }
+
+    /// Wait for the next update of an attribute followed, skipping malformed updates.
+    pub async fn next_update(&mut self) -> EntityUpdateNotification<L2CAP_MTU> {
+        loop {
+            let notification = self.updates.next().await;
+            if EntityUpdate::decode(notification.as_ref()).is_ok() {
+                return EntityUpdateNotification { notification };
+            }
+        }
+    }
+
+    /// Read the value of an attribute into the buffer, such as the whole value of a truncated update.
+    ///
+    /// The value read is at most the ATT MTU of the connection less one octet.
+    pub async fn read_attribute<'b>(
+        &self,
+        attribute: EntityAttribute,
+        buf: &'b mut [u8],
+    ) -> Result<&'b str, BleHostError<C::Error>> {
+        self.gatt
+            .write_characteristic(&self.entity_attribute, &[attribute.entity() as u8, attribute.id()])
+            .await?;
+        let len = self.gatt.read_characteristic(&self.entity_attribute, buf).await?;
+        core::str::from_utf8(&buf[..len]).map_err(|_| Error::InvalidValue.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_updates() {
+        let update = EntityUpdate::decode(&[0x02, 0x02, 0x00, b'S', b'o', b'n', b'g']).unwrap();
+        assert_eq!(update.attribute, EntityAttribute::TrackTitle);
+        assert!(!update.truncated);
+        assert_eq!(update.value, "Song");
+
+        // A truncated value cut in the middle of a character.
+        let update = EntityUpdate::decode(&[0x02, 0x00, 0x01, b'A', 0xc3]).unwrap();
+        assert_eq!(update.attribute, EntityAttribute::TrackArtist);
+        assert!(update.truncated);
+        assert_eq!(update.value, "A");
+
+        assert!(EntityUpdate::decode(&[0x02, 0x00, 0x00, b'A', 0xc3]).is_err());
+        assert!(EntityUpdate::decode(&[0x00, 0x03, 0x00]).is_err());
+    }
+
+    #[test]
+    fn parse_values() {
+        let info = PlaybackInfo::parse("1,1.0,42.5").unwrap();
+        assert_eq!(info.state, PlaybackState::Playing);
+        assert_eq!(info.rate, 1.0);
+        assert_eq!(info.elapsed, 42.5);
+        assert!(PlaybackInfo::parse("1,1.0").is_err());
+        assert!(matches!(RepeatMode::parse("2"), Ok(RepeatMode::All)));
+
+        let commands = SupportedCommands::decode(&[0, 1, 3, 20]);
+        assert!(commands.contains(RemoteCommand::Play));
+        assert!(commands.contains(RemoteCommand::NextTrack));
+        assert!(!commands.contains(RemoteCommand::VolumeUp));
+    }
+}
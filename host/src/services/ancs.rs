@@ -0,0 +1,456 @@
+//! Apple Notification Center Service client, showing the notifications of an iOS device.
+//!
+//! The iOS device is the server, and notifies the client of the notifications added, modified
+//! and removed through the notification source. The attributes of a notification, such as its
+//! title and message, are requested through the control point and received through the data
+//! source, in as many notifications as needed to hold them.
+//!
+//! The iOS device only lists the service to a client soliciting it in its advertising data, with
+//! [`ANCS_SOLICITATION`], and requires the connection to be encrypted before accepting the
+//! subscriptions of the client, which bonding with the device avoids repeating.
+use crate::Error;
+use crate::att::AttErrorCode;
+use crate::attribute::Characteristic;
+use crate::gatt::{GattClient, NotificationListener};
+use crate::types::uuid::Uuid;
+use crate::{BleHostError, Controller};
+
+/// UUID of the Apple Notification Center Service, in the order of [`ANCS_SOLICITATION`].
+const ANCS_SERVICE_BYTES: [u8; 16] = 0x7905f431_b5ce_4e99_a40f_4b1e122d00d0_u128.to_le_bytes();
+
+/// UUID of the Apple Notification Center Service.
+pub const ANCS_SERVICE: Uuid = Uuid::new_long(ANCS_SERVICE_BYTES);
+
+/// UUID of the notification source, notifying the events of the notifications.
+pub const ANCS_NOTIFICATION_SOURCE: Uuid = Uuid::new_long(0x9fbf120d_6301_42d9_8c58_25e699a21dbd_u128.to_le_bytes());
+
+/// UUID of the control point, written with the commands of the client.
+pub const ANCS_CONTROL_POINT: Uuid = Uuid::new_long(0x69d1d8f3_45e1_49a8_9821_9bbdfdaad9d9_u128.to_le_bytes());
+
+/// UUID of the data source, notifying the responses to the commands.
+pub const ANCS_DATA_SOURCE: Uuid = Uuid::new_long(0x22eac6e9_24d6_4bb5_be44_b36ace7c7bfb_u128.to_le_bytes());
+
+/// Service solicitation UUIDs to advertise for the iOS device to list the service, with
+/// [`AdStructure::ServiceSolicitationUuids128`](crate::advertise::AdStructure::ServiceSolicitationUuids128).
+pub const ANCS_SOLICITATION: [[u8; 16]; 1] = [ANCS_SERVICE_BYTES];
+
+/// Error of a command with an unknown identifier.
+pub const ERROR_UNKNOWN_COMMAND: AttErrorCode = AttErrorCode::proprietary(0xa0);
+/// Error of a malformed command.
+pub const ERROR_INVALID_COMMAND: AttErrorCode = AttErrorCode::proprietary(0xa1);
+/// Error of a command with an invalid parameter, such as an unknown notification.
+pub const ERROR_INVALID_PARAMETER: AttErrorCode = AttErrorCode::proprietary(0xa2);
+/// Error of an action which failed on the iOS device.
+pub const ERROR_ACTION_FAILED: AttErrorCode = AttErrorCode::proprietary(0xa3);
+
+/// Identifier of the attribute of an application with its display name.
+pub const APP_ATTRIBUTE_DISPLAY_NAME: u8 = 0;
+
+const COMMAND_GET_NOTIFICATION_ATTRIBUTES: u8 = 0;
+const COMMAND_GET_APP_ATTRIBUTES: u8 = 1;
+const COMMAND_PERFORM_NOTIFICATION_ACTION: u8 = 2;
+
+// Most attributes requested at once, the identifiers of the notification attributes.
+const MAX_ATTRIBUTES: usize = 8;
+
+/// Kind of event of a notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum EventId {
+    /// The notification was added.
+    Added = 0,
+    /// The notification was modified.
+    Modified = 1,
+    /// The notification was removed.
+    Removed = 2,
+}
+
+/// Flags of the event of a notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EventFlags(u8);
+
+impl EventFlags {
+    /// The notification is silent.
+    pub const SILENT: Self = Self(0x01);
+    /// The notification is important.
+    pub const IMPORTANT: Self = Self(0x02);
+    /// The notification existed before the client subscribed.
+    pub const PRE_EXISTING: Self = Self(0x04);
+    /// The notification has a positive action.
+    pub const POSITIVE_ACTION: Self = Self(0x08);
+    /// The notification has a negative action.
+    pub const NEGATIVE_ACTION: Self = Self(0x10);
+}
+
+bitfield_set!(EventFlags: u8, "flags");
+
+/// Category of a notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CategoryId(pub u8);
+
+impl CategoryId {
+    /// Another category.
+    pub const OTHER: Self = Self(0);
+    /// An incoming call.
+    pub const INCOMING_CALL: Self = Self(1);
+    /// A missed call.
+    pub const MISSED_CALL: Self = Self(2);
+    /// A voicemail.
+    pub const VOICEMAIL: Self = Self(3);
+    /// A social network or a message.
+    pub const SOCIAL: Self = Self(4);
+    /// A schedule, such as a calendar.
+    pub const SCHEDULE: Self = Self(5);
+    /// An email.
+    pub const EMAIL: Self = Self(6);
+    /// News.
+    pub const NEWS: Self = Self(7);
+    /// Health and fitness.
+    pub const HEALTH_AND_FITNESS: Self = Self(8);
+    /// Business and finance.
+    pub const BUSINESS_AND_FINANCE: Self = Self(9);
+    /// A location.
+    pub const LOCATION: Self = Self(10);
+    /// Entertainment.
+    pub const ENTERTAINMENT: Self = Self(11);
+}
+
+/// Event of a notification, notified by the notification source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NotificationEvent {
+    /// Kind of event.
+    pub event_id: EventId,
+    /// Flags of the event.
+    pub flags: EventFlags,
+    /// Category of the notification.
+    pub category: CategoryId,
+    /// Number of active notifications of the category.
+    pub category_count: u8,
+    /// Identifier of the notification, to request its attributes.
+    pub uid: u32,
+}
+
+impl NotificationEvent {
+    /// Decode the value of the notification source.
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let [event_id, flags, category, category_count, u0, u1, u2, u3, ..] = *data else {
+            return Err(Error::InvalidValue);
+        };
+        let event_id = match event_id {
+            0 => EventId::Added,
+            1 => EventId::Modified,
+            2 => EventId::Removed,
+            _ => return Err(Error::InvalidValue),
+        };
+        Ok(Self {
+            event_id,
+            flags: EventFlags(flags),
+            category: CategoryId(category),
+            category_count,
+            uid: u32::from_le_bytes([u0, u1, u2, u3]),
+        })
+    }
+}
+
+/// Attribute of a notification, requested with [`AncsClient::get_notification_attributes`].
+///
+/// The title, subtitle and message are truncated by the iOS device to their maximum length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NotificationAttribute {
+    /// Identifier of the application of the notification, to request its attributes.
+    AppIdentifier,
+    /// Title, of at most the given length.
+    Title(u16),
+    /// Subtitle, of at most the given length.
+    Subtitle(u16),
+    /// Message, of at most the given length.
+    Message(u16),
+    /// Length of the whole message, as a decimal string.
+    MessageSize,
+    /// Date, as a string formatted `yyyyMMdd'T'HHmmSS`.
+    Date,
+    /// Label of the positive action.
+    PositiveActionLabel,
+    /// Label of the negative action.
+    NegativeActionLabel,
+}
+
+impl NotificationAttribute {
+    /// Identifier of the attribute in the responses, for [`Attributes::get`].
+    pub const fn id(&self) -> u8 {
+        match self {
+            Self::AppIdentifier => 0,
+            Self::Title(_) => 1,
+            Self::Subtitle(_) => 2,
+            Self::Message(_) => 3,
+            Self::MessageSize => 4,
+            Self::Date => 5,
+            Self::PositiveActionLabel => 6,
+            Self::NegativeActionLabel => 7,
+        }
+    }
+
+    fn max_len(&self) -> Option<u16> {
+        match self {
+            Self::Title(len) | Self::Subtitle(len) | Self::Message(len) => Some(*len),
+            _ => None,
+        }
+    }
+}
+
+/// Action performed on a notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ActionId {
+    /// The positive action, such as accepting a call.
+    Positive = 0,
+    /// The negative action, such as dismissing a notification.
+    Negative = 1,
+}
+
+/// Attributes received in a response of the data source.
+#[derive(Debug, Clone, Copy)]
+pub struct Attributes<'b> {
+    data: &'b [u8],
+}
+
+impl<'b> Attributes<'b> {
+    /// Iterate over the identifiers and values of the attributes.
+    pub fn iter(&self) -> AttributesIter<'b> {
+        AttributesIter { data: self.data }
+    }
+
+    /// The value of an attribute, if received.
+    pub fn get(&self, id: u8) -> Option<&'b [u8]> {
+        self.iter().find(|(i, _)| *i == id).map(|(_, value)| value)
+    }
+
+    /// The value of an attribute as a string, if received and valid UTF-8.
+    pub fn get_str(&self, id: u8) -> Option<&'b str> {
+        self.get(id).and_then(|value| core::str::from_utf8(value).ok())
+    }
+}
+
+/// Iterator over the attributes of a response, yielding their identifiers and values.
+pub struct AttributesIter<'b> {
+    data: &'b [u8],
+}
+
+impl<'b> Iterator for AttributesIter<'b> {
+    type Item = (u8, &'b [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let [id, l0, l1, rest @ ..] = self.data else {
+            return None;
+        };
+        let len = u16::from_le_bytes([*l0, *l1]) as usize;
+        let value = rest.get(..len)?;
+        self.data = &rest[len..];
+        Some((*id, value))
+    }
+}
+
+/// Length of a response of the data source, once all of its attributes are received.
+///
+/// The response begins with a header of `header_len` octets, followed by `count` attributes,
+/// each with its identifier, the length of its value and the value.
+fn response_len(data: &[u8], header_len: usize, count: usize) -> Option<usize> {
+    let mut len = header_len;
+    for _ in 0..count {
+        let attribute = data.get(len..len + 3)?;
+        len += 3 + u16::from_le_bytes([attribute[1], attribute[2]]) as usize;
+    }
+    (len <= data.len()).then_some(len)
+}
+
+// The control point and the data source have variable lengths, and are handled by hand.
+type RawCharacteristic = Characteristic<u8>;
+
+/// Apple Notification Center Service client, receiving the notifications of an iOS device.
+pub struct AncsClient<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize> {
+    gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>,
+    control_point: RawCharacteristic,
+    notification_source: NotificationListener<'c, L2CAP_MTU>,
+    data_source: NotificationListener<'c, L2CAP_MTU>,
+}
+
+impl<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize>
+    AncsClient<'c, 'd, C, MAX_SERVICES, L2CAP_MTU>
+{
+    /// Discover the service of the iOS device, and subscribe to its notification and data sources.
+    ///
+    /// The GATT client must have room for one more service, and two free notification
+    /// subscribers. The connection must be encrypted, the iOS device rejecting the subscriptions
+    /// otherwise.
+    pub async fn new(gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>) -> Result<Self, BleHostError<C::Error>> {
+        let services = gatt.services_by_uuid(&ANCS_SERVICE).await?;
+        let ancs = services.first().cloned().ok_or(Error::NotFound)?;
+        let control_point = gatt.characteristic_by_uuid(&ancs, &ANCS_CONTROL_POINT).await?;
+        let notification_source: RawCharacteristic =
+            gatt.characteristic_by_uuid(&ancs, &ANCS_NOTIFICATION_SOURCE).await?;
+        let data_source: RawCharacteristic = gatt.characteristic_by_uuid(&ancs, &ANCS_DATA_SOURCE).await?;
+        // The data source is subscribed first, so that no response is missed once the events are received.
+        let data_source = gatt.subscribe(&data_source, false).await?;
+        let notification_source = gatt.subscribe(&notification_source, false).await?;
+        Ok(Self {
+            gatt,
+            control_point,
+            notification_source,
+            data_source,
+        })
+    }
+
+    /// Wait for the next event of a notification, skipping malformed events.
+    pub async fn next_event(&mut self) -> NotificationEvent {
+        loop {
+            if let Ok(event) = NotificationEvent::decode(self.notification_source.next().await.as_ref()) {
+                return event;
+            }
+        }
+    }
+
+    /// Request attributes of a notification, received into the buffer.
+    ///
+    /// Returns [`Error::InsufficientSpace`] if the attributes do not fit the buffer, and
+    /// [`ERROR_INVALID_PARAMETER`] if the notification was removed.
+    pub async fn get_notification_attributes<'b>(
+        &mut self,
+        uid: u32,
+        attributes: &[NotificationAttribute],
+        buf: &'b mut [u8],
+    ) -> Result<Attributes<'b>, BleHostError<C::Error>> {
+        if attributes.is_empty() || attributes.len() > MAX_ATTRIBUTES {
+            return Err(Error::InvalidValue.into());
+        }
+        let mut command = [0; 5 + 3 * MAX_ATTRIBUTES];
+        command[0] = COMMAND_GET_NOTIFICATION_ATTRIBUTES;
+        command[1..5].copy_from_slice(&uid.to_le_bytes());
+        let mut len = 5;
+        for attribute in attributes {
+            command[len] = attribute.id();
+            len += 1;
+            if let Some(max) = attribute.max_len() {
+                command[len..len + 2].copy_from_slice(&max.to_le_bytes());
+                len += 2;
+            }
+        }
+        self.request(&command[..len], &command[..5], attributes.len(), buf)
+            .await
+    }
+
+    /// Request the display name of an application, received into the buffer.
+    ///
+    /// The identifier of the application is the [`NotificationAttribute::AppIdentifier`] of its
+    /// notifications, and is at most 32 octets long.
+    pub async fn get_app_attributes<'b>(
+        &mut self,
+        app_id: &str,
+        buf: &'b mut [u8],
+    ) -> Result<Attributes<'b>, BleHostError<C::Error>> {
+        if app_id.len() > 32 || app_id.as_bytes().contains(&0) {
+            return Err(Error::InvalidValue.into());
+        }
+        let mut command = [0; 3 + 32];
+        command[0] = COMMAND_GET_APP_ATTRIBUTES;
+        command[1..1 + app_id.len()].copy_from_slice(app_id.as_bytes());
+        // The identifier is terminated by a null octet, left in place.
+        let header_len = 2 + app_id.len();
+        command[header_len] = APP_ATTRIBUTE_DISPLAY_NAME;
+        self.request(&command[..header_len + 1], &command[..header_len], 1, buf)
+            .await
+    }
+
+    /// Perform an action on a notification, such as accepting a call.
+    ///
+    /// The actions of a notification are listed in the flags of its events.
+    pub async fn perform_notification_action(&self, uid: u32, action: ActionId) -> Result<(), BleHostError<C::Error>> {
+        let [u0, u1, u2, u3] = uid.to_le_bytes();
+        let command = [COMMAND_PERFORM_NOTIFICATION_ACTION, u0, u1, u2, u3, action as u8];
+        self.gatt.write_characteristic(&self.control_point, &command).await
+    }
+
+    /// Write a command to the control point, and reassemble its response from the data source.
+    ///
+    /// The response begins with the header of the command, followed by the attributes requested.
+    async fn request<'b>(
+        &mut self,
+        command: &[u8],
+        header: &[u8],
+        count: usize,
+        buf: &'b mut [u8],
+    ) -> Result<Attributes<'b>, BleHostError<C::Error>> {
+        if buf.len() < header.len() {
+            return Err(Error::InsufficientSpace.into());
+        }
+        self.gatt.write_characteristic(&self.control_point, command).await?;
+        let mut len = 0;
+        loop {
+            let notification = self.data_source.next().await;
+            let data = notification.as_ref();
+            let end = len + data.len();
+            if end > buf.len() {
+                return Err(Error::InsufficientSpace.into());
+            }
+            buf[len..end].copy_from_slice(data);
+            len = end;
+            if len >= header.len() && buf[..header.len()] != *header {
+                return Err(Error::InvalidValue.into());
+            }
+            if let Some(total) = response_len(&buf[..len], header.len(), count) {
+                return Ok(Attributes {
+                    data: &buf[header.len()..total],
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_events() {
+        let event = NotificationEvent::decode(&[0x00, 0x18, 0x01, 0x02, 0x78, 0x56, 0x34, 0x12]).unwrap();
+        assert_eq!(event.event_id, EventId::Added);
+        assert!(event.flags.contains(EventFlags::POSITIVE_ACTION));
+        assert!(event.flags.contains(EventFlags::NEGATIVE_ACTION));
+        assert!(!event.flags.contains(EventFlags::SILENT));
+        assert_eq!(event.category, CategoryId::INCOMING_CALL);
+        assert_eq!(event.category_count, 2);
+        assert_eq!(event.uid, 0x12345678);
+
+        assert!(NotificationEvent::decode(&[0x03, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(NotificationEvent::decode(&[0x00, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn reassemble_responses() {
+        // Header of a response to get the title and the message of a notification.
+        let response = [
+            0x00, 0x01, 0x00, 0x00, 0x00, // Header
+            0x01, 0x02, 0x00, b'H', b'i', // Title
+            0x03, 0x05, 0x00, b'h', b'e', b'l', b'l', b'o', // Message
+        ];
+
+        for len in 0..response.len() {
+            assert_eq!(response_len(&response[..len], 5, 2), None);
+        }
+        assert_eq!(response_len(&response, 5, 2), Some(response.len()));
+
+        let attributes = Attributes { data: &response[5..] };
+        assert_eq!(attributes.get_str(NotificationAttribute::Title(64).id()), Some("Hi"));
+        assert_eq!(
+            attributes.get_str(NotificationAttribute::Message(64).id()),
+            Some("hello")
+        );
+        assert_eq!(attributes.get(NotificationAttribute::Date.id()), None);
+        assert_eq!(attributes.iter().count(), 2);
+    }
+}
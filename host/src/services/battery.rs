@@ -0,0 +1,207 @@
+//! Battery Service, exposing the charge level of a battery.
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+
+use crate::Error;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::gatt::{GattClient, NotificationListener};
+use crate::{BleHostError, Controller};
+
+/// The number of attributes added by the Battery Service
+/// BATTERY_SERVICE:   1
+/// └── BATTERY_LEVEL: 3
+///                  ---
+///                  = 4
+pub const BATTERY_SERVICE_ATTRIBUTE_COUNT: usize = 4;
+
+/// Storage of the battery level of a [`BatteryServer`].
+pub struct BatteryStorage {
+    level: [u8; 1],
+}
+
+impl BatteryStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self { level: [0] }
+    }
+}
+
+impl Default for BatteryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Battery Service server.
+///
+/// The level is a percentage of the full charge, read by the clients and notified to those
+/// subscribed with [`BatteryServer::set_level`].
+pub struct BatteryServer {
+    level: Characteristic<u8>,
+}
+
+impl BatteryServer {
+    /// Add the service to the attribute table, with the initial level of the battery.
+    ///
+    /// Returns [`Error::InvalidValue`] if the level is above 100.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut BatteryStorage,
+        level: u8,
+    ) -> Result<Self, Error> {
+        check_level(level)?;
+        let mut service = table.add_service(Service::new(service::BATTERY));
+        let level = service
+            .add_characteristic(
+                characteristic::BATTERY_LEVEL,
+                &[CharacteristicProp::Read, CharacteristicProp::Notify],
+                level,
+                &mut storage.level,
+            )
+            .build();
+        service.build();
+        Ok(Self { level })
+    }
+
+    /// The level of the battery.
+    pub fn level<M: RawMutex, const MAX: usize>(&self, server: &AttributeServer<'_, M, MAX>) -> Result<u8, Error> {
+        self.level.get(server)
+    }
+
+    /// Update the level of the battery, notifying the client if subscribed.
+    ///
+    /// Returns [`Error::InvalidValue`] if the level is above 100.
+    pub async fn set_level<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        level: u8,
+    ) -> Result<(), Error> {
+        check_level(level)?;
+        self.level.notify(server, connection, &level).await
+    }
+
+    /// Update the level of the battery without notifying, for instance while no client is connected.
+    ///
+    /// Returns [`Error::InvalidValue`] if the level is above 100.
+    pub fn update_level<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        level: u8,
+    ) -> Result<(), Error> {
+        check_level(level)?;
+        self.level.set(server, &level)
+    }
+}
+
+fn check_level(level: u8) -> Result<(), Error> {
+    if level > 100 { Err(Error::InvalidValue) } else { Ok(()) }
+}
+
+/// Battery Service client, reading the battery level of a remote device.
+pub struct BatteryClient<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize> {
+    gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>,
+    level: Characteristic<u8>,
+}
+
+impl<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize>
+    BatteryClient<'c, 'd, C, MAX_SERVICES, L2CAP_MTU>
+{
+    /// Discover the Battery Service of the remote device.
+    ///
+    /// The GATT client must have room for one more service.
+    pub async fn new(gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>) -> Result<Self, BleHostError<C::Error>> {
+        let services = gatt.services_by_uuid(&service::BATTERY.into()).await?;
+        let battery = services.first().cloned().ok_or(Error::NotFound)?;
+        let level = gatt
+            .characteristic_by_uuid(&battery, &characteristic::BATTERY_LEVEL.into())
+            .await?;
+        Ok(Self { gatt, level })
+    }
+
+    /// Read the level of the battery, as a percentage of its full charge.
+    pub async fn read_level(&self) -> Result<u8, BleHostError<C::Error>> {
+        let mut buf = [0; 1];
+        let len = self.gatt.read_characteristic(&self.level, &mut buf).await?;
+        if len == 0 {
+            return Err(Error::InvalidValue.into());
+        }
+        Ok(buf[0])
+    }
+
+    /// Subscribe to the notifications of the battery level.
+    ///
+    /// The GATT client must have a free notification subscriber.
+    pub async fn subscribe_level(&self) -> Result<BatteryLevelListener<'c, L2CAP_MTU>, BleHostError<C::Error>> {
+        let listener = self.gatt.subscribe(&self.level, false).await?;
+        Ok(BatteryLevelListener { listener })
+    }
+}
+
+/// Listener of the battery levels notified by a remote device.
+pub struct BatteryLevelListener<'c, const L2CAP_MTU: usize> {
+    listener: NotificationListener<'c, L2CAP_MTU>,
+}
+
+impl<const L2CAP_MTU: usize> BatteryLevelListener<'_, L2CAP_MTU> {
+    /// Wait for the next level notified, skipping empty notifications.
+    pub async fn next(&mut self) -> u8 {
+        loop {
+            if let Some(&level) = self.listener.next().await.as_ref().first() {
+                return level;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::att::AttErrorCode;
+    use crate::mock_client::MockClient;
+
+    #[test]
+    fn level_is_a_percentage() {
+        let mut rejected = BatteryStorage::new();
+        let mut storage = BatteryStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, BATTERY_SERVICE_ATTRIBUTE_COUNT> = AttributeTable::new();
+        assert!(matches!(
+            BatteryServer::build(&mut table, &mut rejected, 101),
+            Err(Error::InvalidValue)
+        ));
+        let battery = BatteryServer::build(&mut table, &mut storage, 80).unwrap();
+        let server = AttributeServer::new(table);
+        assert_eq!(battery.level(&server).unwrap(), 80);
+
+        battery.update_level(&server, 100).unwrap();
+        assert_eq!(battery.level(&server).unwrap(), 100);
+        assert!(matches!(battery.update_level(&server, 101), Err(Error::InvalidValue)));
+        assert_eq!(battery.level(&server).unwrap(), 100);
+    }
+
+    #[test]
+    fn server_exposes_level_to_clients() {
+        let mut storage = BatteryStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, BATTERY_SERVICE_ATTRIBUTE_COUNT> = AttributeTable::new();
+        let battery = BatteryServer::build(&mut table, &mut storage, 80).unwrap();
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+
+        assert_eq!(client.read(&server, battery.level.handle).unwrap(), [80]);
+        assert_eq!(
+            client.write(&server, battery.level.handle, &[50], |_, _| Ok(())),
+            Err(AttErrorCode::WRITE_NOT_PERMITTED)
+        );
+
+        let cccd = battery.level.cccd_handle.unwrap();
+        assert!(!client.is_subscribed(&server, cccd));
+        client.subscribe(&server, cccd, 0x0001).unwrap();
+        assert!(client.is_subscribed(&server, cccd));
+        battery.update_level(&server, 42).unwrap();
+        assert_eq!(client.read(&server, battery.level.handle).unwrap(), [42]);
+    }
+}
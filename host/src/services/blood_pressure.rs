@@ -0,0 +1,371 @@
+//! Blood Pressure Service, indicating the blood pressures measured by a monitor.
+//!
+//! Each measurement holds the systolic, diastolic and mean arterial pressures, along with the
+//! pulse rate, the user measured and the problems detected during the measurement if the monitor
+//! supports them. The measurements are indicated, the monitor waiting for the client to confirm
+//! them, while the pressure of the cuff is notified during a measurement.
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+
+use super::current_time::ExactTime;
+use super::ieee11073::SFloat;
+use crate::Error;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::cursor::WriteCursor;
+
+const FLAG_KPA: u8 = 0x01;
+const FLAG_TIME_STAMP: u8 = 0x02;
+const FLAG_PULSE_RATE: u8 = 0x04;
+const FLAG_USER_ID: u8 = 0x08;
+const FLAG_MEASUREMENT_STATUS: u8 = 0x10;
+
+const MEASUREMENT_LEN: usize = 19;
+
+/// Unit of a blood pressure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PressureUnit {
+    /// Millimetres of mercury.
+    #[default]
+    MmHg,
+    /// Kilopascals.
+    KPa,
+}
+
+/// Problems detected by a monitor during a measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MeasurementStatus(u16);
+
+impl MeasurementStatus {
+    /// The body moved during the measurement.
+    pub const BODY_MOVEMENT: Self = Self(0x0001);
+    /// The cuff was too loose.
+    pub const CUFF_TOO_LOOSE: Self = Self(0x0002);
+    /// The pulse was irregular.
+    pub const IRREGULAR_PULSE: Self = Self(0x0004);
+    /// The pulse rate was above the upper limit.
+    pub const PULSE_RATE_HIGH: Self = Self(0x0008);
+    /// The pulse rate was below the lower limit.
+    pub const PULSE_RATE_LOW: Self = Self(0x0010);
+    /// The position of the measurement was improper.
+    pub const IMPROPER_POSITION: Self = Self(0x0020);
+}
+
+bitfield_set!(MeasurementStatus: u16, "problems");
+
+/// Features of a blood pressure monitor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BloodPressureFeatures(u16);
+
+impl BloodPressureFeatures {
+    /// The monitor detects the movements of the body.
+    pub const BODY_MOVEMENT_DETECTION: Self = Self(0x0001);
+    /// The monitor detects a cuff too loose.
+    pub const CUFF_FIT_DETECTION: Self = Self(0x0002);
+    /// The monitor detects an irregular pulse.
+    pub const IRREGULAR_PULSE_DETECTION: Self = Self(0x0004);
+    /// The monitor detects a pulse rate out of its limits.
+    pub const PULSE_RATE_RANGE_DETECTION: Self = Self(0x0008);
+    /// The monitor detects an improper position of the measurement.
+    pub const MEASUREMENT_POSITION_DETECTION: Self = Self(0x0010);
+    /// The monitor is bonded with several clients.
+    pub const MULTIPLE_BOND: Self = Self(0x0020);
+}
+
+bitfield_set!(BloodPressureFeatures: u16, "features");
+
+/// A measurement of the blood pressure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BloodPressureMeasurement {
+    /// Systolic pressure.
+    pub systolic: SFloat,
+    /// Diastolic pressure.
+    pub diastolic: SFloat,
+    /// Mean arterial pressure.
+    pub mean_arterial: SFloat,
+    /// Unit of the pressures.
+    pub unit: PressureUnit,
+    /// Time of the measurement, the day of the week and the fractions of the second not being sent.
+    pub time_stamp: Option<ExactTime>,
+    /// Pulse rate, in beats per minute.
+    pub pulse_rate: Option<SFloat>,
+    /// User measured, 0xff if unknown.
+    pub user_id: Option<u8>,
+    /// Problems detected during the measurement.
+    pub status: Option<MeasurementStatus>,
+}
+
+impl BloodPressureMeasurement {
+    /// Create the pressure of the cuff during a measurement, the other pressures being unknown.
+    pub const fn cuff_pressure(pressure: SFloat, unit: PressureUnit) -> Self {
+        Self {
+            systolic: pressure,
+            diastolic: SFloat::NAN,
+            mean_arterial: SFloat::NAN,
+            unit,
+            time_stamp: None,
+            pulse_rate: None,
+            user_id: None,
+            status: None,
+        }
+    }
+
+    /// Encode the measurement, returning the length written.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut flags = 0;
+        if self.unit == PressureUnit::KPa {
+            flags |= FLAG_KPA;
+        }
+        if self.time_stamp.is_some() {
+            flags |= FLAG_TIME_STAMP;
+        }
+        if self.pulse_rate.is_some() {
+            flags |= FLAG_PULSE_RATE;
+        }
+        if self.user_id.is_some() {
+            flags |= FLAG_USER_ID;
+        }
+        if self.status.is_some() {
+            flags |= FLAG_MEASUREMENT_STATUS;
+        }
+        let mut w = WriteCursor::new(buf);
+        w.write(flags)?;
+        w.write(self.systolic.to_bits())?;
+        w.write(self.diastolic.to_bits())?;
+        w.write(self.mean_arterial.to_bits())?;
+        if let Some(time) = &self.time_stamp {
+            w.write(time.year)?;
+            w.append(&[time.month, time.day, time.hours, time.minutes, time.seconds])?;
+        }
+        if let Some(pulse_rate) = self.pulse_rate {
+            w.write(pulse_rate.to_bits())?;
+        }
+        if let Some(user_id) = self.user_id {
+            w.write(user_id)?;
+        }
+        if let Some(status) = self.status {
+            w.write(status.bits())?;
+        }
+        Ok(w.len())
+    }
+}
+
+/// Configuration of the Blood Pressure Service.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BloodPressureConfig {
+    /// Features of the monitor.
+    pub features: BloodPressureFeatures,
+    /// Whether the pressure of the cuff is notified during a measurement.
+    pub intermediate_cuff_pressure: bool,
+}
+
+impl BloodPressureConfig {
+    /// Number of attributes added by the service.
+    pub const fn attribute_count(&self) -> usize {
+        // The service, the declaration, value and CCCD of the measurement, and the feature.
+        let mut count = 1 + 3 + 2;
+        if self.intermediate_cuff_pressure {
+            count += 3;
+        }
+        count
+    }
+}
+
+/// Storage of the values of the characteristics of a [`BloodPressureServer`].
+pub struct BloodPressureStorage {
+    measurement: [u8; MEASUREMENT_LEN],
+    intermediate_cuff_pressure: [u8; MEASUREMENT_LEN],
+    features: [u8; 2],
+}
+
+impl BloodPressureStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            measurement: [0; MEASUREMENT_LEN],
+            intermediate_cuff_pressure: [0; MEASUREMENT_LEN],
+            features: [0; 2],
+        }
+    }
+}
+
+impl Default for BloodPressureStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Blood Pressure Service server.
+///
+/// The measurements are indicated with [`BloodPressureServer::indicate_measurement`], which waits
+/// for the client to confirm them, and the pressure of the cuff is notified with
+/// [`BloodPressureServer::notify_cuff_pressure`] while measuring.
+pub struct BloodPressureServer {
+    measurement: Characteristic<Vec<u8, MEASUREMENT_LEN>>,
+    intermediate_cuff_pressure: Option<Characteristic<Vec<u8, MEASUREMENT_LEN>>>,
+}
+
+impl BloodPressureServer {
+    /// Add the service to the attribute table.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut BloodPressureStorage,
+        config: BloodPressureConfig,
+    ) -> Self {
+        let BloodPressureStorage {
+            measurement,
+            intermediate_cuff_pressure,
+            features,
+        } = storage;
+        *features = config.features.bits().to_le_bytes();
+        let mut service = table.add_service(Service::new(service::BLOOD_PRESSURE));
+        let measurement = service
+            .add_characteristic(
+                characteristic::BLOOD_PRESSURE_MEASUREMENT,
+                &[CharacteristicProp::Indicate],
+                Vec::new(),
+                measurement,
+            )
+            .build();
+        let intermediate_cuff_pressure = config.intermediate_cuff_pressure.then(|| {
+            service
+                .add_characteristic(
+                    characteristic::INTERMEDIATE_CUFF_PRESSURE,
+                    &[CharacteristicProp::Notify],
+                    Vec::new(),
+                    intermediate_cuff_pressure,
+                )
+                .build()
+        });
+        service
+            .add_characteristic_ro(characteristic::BLOOD_PRESSURE_FEATURE, &*features)
+            .build();
+        service.build();
+        Self {
+            measurement,
+            intermediate_cuff_pressure,
+        }
+    }
+
+    /// Indicate a measurement to the client, if subscribed, and wait for its confirmation.
+    ///
+    /// Returns whether the client confirmed the measurement, the monitor keeping it to indicate it
+    /// later otherwise.
+    pub async fn indicate_measurement<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        measurement: &BloodPressureMeasurement,
+    ) -> Result<bool, Error> {
+        let value = encode(measurement)?;
+        self.measurement.indicate_confirmed(server, connection, &value).await
+    }
+
+    /// Notify the pressure of the cuff to the client during a measurement, if subscribed.
+    ///
+    /// Returns [`Error::NotSupported`] if the intermediate cuff pressure is not supported by the
+    /// service.
+    pub async fn notify_cuff_pressure<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        pressure: &BloodPressureMeasurement,
+    ) -> Result<(), Error> {
+        let characteristic = self.intermediate_cuff_pressure.as_ref().ok_or(Error::NotSupported)?;
+        let value = encode(pressure)?;
+        characteristic.notify(server, connection, &value).await
+    }
+}
+
+fn encode(measurement: &BloodPressureMeasurement) -> Result<Vec<u8, MEASUREMENT_LEN>, Error> {
+    let mut buf = [0; MEASUREMENT_LEN];
+    let len = measurement.encode(&mut buf)?;
+    Ok(unwrap!(Vec::from_slice(&buf[..len]).ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+
+    #[test]
+    fn encode_measurements() {
+        let mut buf = [0; MEASUREMENT_LEN];
+        let measurement = BloodPressureMeasurement {
+            systolic: SFloat::new(120, 0).unwrap(),
+            diastolic: SFloat::new(80, 0).unwrap(),
+            mean_arterial: SFloat::new(93, 0).unwrap(),
+            unit: PressureUnit::MmHg,
+            time_stamp: None,
+            pulse_rate: Some(SFloat::new(72, 0).unwrap()),
+            user_id: Some(1),
+            status: Some(MeasurementStatus::BODY_MOVEMENT.union(MeasurementStatus::IRREGULAR_PULSE)),
+        };
+        let len = measurement.encode(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            &[0x1c, 120, 0x00, 80, 0x00, 93, 0x00, 72, 0x00, 1, 0x05, 0x00]
+        );
+
+        let pressure = BloodPressureMeasurement::cuff_pressure(SFloat::new(150, 0).unwrap(), PressureUnit::KPa);
+        let len = pressure.encode(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &[0x01, 150, 0x00, 0xff, 0x07, 0xff, 0x07]);
+
+        let config = BloodPressureConfig {
+            features: BloodPressureFeatures::BODY_MOVEMENT_DETECTION,
+            intermediate_cuff_pressure: true,
+        };
+        assert_eq!(config.attribute_count(), 9);
+        let mut storage = BloodPressureStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 9> = AttributeTable::new();
+        let bps = BloodPressureServer::build(&mut table, &mut storage, config);
+        assert!(bps.intermediate_cuff_pressure.is_some());
+    }
+
+    #[test]
+    fn measurements_are_confirmed() {
+        let config = BloodPressureConfig::default();
+        let mut storage = BloodPressureStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 6> = AttributeTable::new();
+        let bps = BloodPressureServer::build(&mut table, &mut storage, config);
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+        let measurement = BloodPressureMeasurement {
+            systolic: SFloat::new(120, 0).unwrap(),
+            diastolic: SFloat::new(80, 0).unwrap(),
+            mean_arterial: SFloat::new(93, 0).unwrap(),
+            unit: PressureUnit::MmHg,
+            time_stamp: None,
+            pulse_rate: None,
+            user_id: None,
+            status: None,
+        };
+
+        // The monitor keeps the measurement until the client subscribes.
+        assert!(!block_on(bps.indicate_measurement(&server, client.connection(), &measurement)).unwrap());
+        client
+            .subscribe(&server, bps.measurement.cccd_handle.unwrap(), 0x0002)
+            .unwrap();
+        let (confirmed, indicated) = block_on(join(
+            bps.indicate_measurement(&server, client.connection(), &measurement),
+            client.confirm_next(&server),
+        ));
+        assert!(confirmed.unwrap());
+        assert_eq!(indicated.0, bps.measurement.handle);
+        assert_eq!(indicated.1, [0x00, 120, 0x00, 80, 0x00, 93, 0x00]);
+        assert!(matches!(
+            block_on(bps.notify_cuff_pressure(&server, client.connection(), &measurement)),
+            Err(Error::NotSupported)
+        ));
+    }
+}
@@ -0,0 +1,281 @@
+//! Bond Management Service, letting a client delete the bonds of the device.
+//!
+//! The server deletes the bonds kept by the security manager of the [`Stack`], as with
+//! [`Stack::remove_bond_information`] and [`Stack::clear_bonds`]. Applications persisting the
+//! bonds store them again after a deletion is returned. Each operation can require an
+//! authorization code, written by the client along with the operation, so that only the users
+//! knowing the code can clear the bonds, for instance from a companion application.
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+
+use crate::att::AttErrorCode;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::connection::Connection;
+use crate::security_manager::BondInformation;
+use crate::{Address, Controller, Stack};
+
+/// Error of a write of an unsupported operation to the control point.
+pub const ERROR_OPCODE_NOT_SUPPORTED: AttErrorCode = AttErrorCode::application(0x80);
+/// Error of an operation which failed to delete a bond.
+pub const ERROR_OPERATION_FAILED: AttErrorCode = AttErrorCode::application(0x81);
+
+/// The number of attributes added by the Bond Management Service
+/// BOND_MANAGEMENT_SERVICE:         1
+/// ├── BOND_MANAGEMENT_CONTROL_POINT: 2
+/// └── BOND_MANAGEMENT_FEATURE:       2
+///                                  ---
+///                                  = 5
+pub const BOND_MANAGEMENT_ATTRIBUTE_COUNT: usize = 5;
+
+// Operations on the bonds of the LE transport, the only one supported by the host.
+const OP_DELETE_REQUESTING_DEVICE: u8 = 0x03;
+const OP_DELETE_ALL: u8 = 0x06;
+const OP_DELETE_ALL_BUT_ACTIVE: u8 = 0x09;
+
+// Bits of the features, the operation supported being followed by the one requiring an
+// authorization code.
+const FEATURE_DELETE_REQUESTING_DEVICE: u32 = 1 << 4;
+const FEATURE_DELETE_ALL: u32 = 1 << 10;
+const FEATURE_DELETE_ALL_BUT_ACTIVE: u32 = 1 << 16;
+
+/// Support of an operation of the control point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OperationSupport<'a> {
+    /// The operation is not supported.
+    #[default]
+    Unsupported,
+    /// The operation is supported.
+    Supported,
+    /// The operation is supported, with an authorization code to be written along with it.
+    Authorized(&'a [u8]),
+}
+
+impl OperationSupport<'_> {
+    const fn features(&self, supported: u32) -> u32 {
+        match self {
+            Self::Unsupported => 0,
+            Self::Supported => supported,
+            Self::Authorized(_) => supported | (supported << 1),
+        }
+    }
+}
+
+/// Configuration of the Bond Management Service.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BondManagementConfig<'a> {
+    /// Deletion of the bond with the client.
+    pub delete_requesting_device: OperationSupport<'a>,
+    /// Deletion of all bonds.
+    pub delete_all: OperationSupport<'a>,
+    /// Deletion of all bonds except the bond with the client.
+    pub delete_all_but_active: OperationSupport<'a>,
+}
+
+impl BondManagementConfig<'_> {
+    /// Value of the features, listing the operations supported.
+    pub const fn features(&self) -> [u8; 3] {
+        let features = self.delete_requesting_device.features(FEATURE_DELETE_REQUESTING_DEVICE)
+            | self.delete_all.features(FEATURE_DELETE_ALL)
+            | self.delete_all_but_active.features(FEATURE_DELETE_ALL_BUT_ACTIVE);
+        let [f0, f1, f2, _] = features.to_le_bytes();
+        [f0, f1, f2]
+    }
+}
+
+/// Storage of the values of the characteristics of a [`BondManagementServer`].
+///
+/// The control point holds up to `LEN` octets, the operation and its authorization code.
+pub struct BondManagementStorage<const LEN: usize = 32> {
+    control_point: [u8; LEN],
+    features: [u8; 3],
+}
+
+impl<const LEN: usize> BondManagementStorage<LEN> {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            control_point: [0; LEN],
+            features: [0; 3],
+        }
+    }
+}
+
+impl<const LEN: usize> Default for BondManagementStorage<LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A deletion of bonds, performed by [`BondManagementServer::process`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BondDeletion {
+    /// The bond with the client was deleted.
+    RequestingDevice,
+    /// All bonds were deleted.
+    All,
+    /// All bonds except the bond with the client were deleted.
+    AllButActive,
+}
+
+/// Bond Management Service server.
+///
+/// The writes to the control point are handled with [`BondManagementServer::process`] before
+/// being accepted, deleting the bonds of the stack.
+pub struct BondManagementServer<'a, const LEN: usize = 32> {
+    config: BondManagementConfig<'a>,
+    control_point: Characteristic<Vec<u8, LEN>>,
+}
+
+impl<'a, const LEN: usize> BondManagementServer<'a, LEN> {
+    /// Add the service to the attribute table.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut BondManagementStorage<LEN>,
+        config: BondManagementConfig<'a>,
+    ) -> Self {
+        let BondManagementStorage {
+            control_point,
+            features,
+        } = storage;
+        *features = config.features();
+        let mut service = table.add_service(Service::new(service::BOND_MANAGEMENT));
+        let control_point = service
+            .add_characteristic(
+                characteristic::BOND_MANAGEMENT_CONTROL_POINT,
+                &[CharacteristicProp::Write],
+                Vec::new(),
+                control_point,
+            )
+            .build();
+        service
+            .add_characteristic_ro(characteristic::BOND_MANAGEMENT_FEATURE, &*features)
+            .build();
+        service.build();
+        Self { config, control_point }
+    }
+
+    /// Handle a write of the client to an attribute, returning `None` if it is not one of the service.
+    ///
+    /// The write is accepted when the deletion is returned, and rejected with the error otherwise.
+    /// The operations are only accepted over an encrypted connection, the client being otherwise
+    /// unknown to the device.
+    pub fn process<C: Controller>(
+        &self,
+        stack: &Stack<'_, C>,
+        connection: &Connection<'_>,
+        handle: u16,
+        data: &[u8],
+    ) -> Result<Option<BondDeletion>, AttErrorCode> {
+        if handle != self.control_point.handle {
+            return Ok(None);
+        }
+        if !connection.security_level().encrypted() {
+            return Err(AttErrorCode::INSUFFICIENT_ENCRYPTION);
+        }
+        self.perform(stack, connection.peer(), data).map(Some)
+    }
+
+    fn perform<C: Controller>(
+        &self,
+        stack: &Stack<'_, C>,
+        peer: Address,
+        data: &[u8],
+    ) -> Result<BondDeletion, AttErrorCode> {
+        let [op, code @ ..] = data else {
+            return Err(ERROR_OPCODE_NOT_SUPPORTED);
+        };
+        let (deletion, support) = match *op {
+            OP_DELETE_REQUESTING_DEVICE => (BondDeletion::RequestingDevice, self.config.delete_requesting_device),
+            OP_DELETE_ALL => (BondDeletion::All, self.config.delete_all),
+            OP_DELETE_ALL_BUT_ACTIVE => (BondDeletion::AllButActive, self.config.delete_all_but_active),
+            _ => return Err(ERROR_OPCODE_NOT_SUPPORTED),
+        };
+        match support {
+            OperationSupport::Unsupported => return Err(ERROR_OPCODE_NOT_SUPPORTED),
+            OperationSupport::Supported => {}
+            OperationSupport::Authorized(expected) if expected == code => {}
+            OperationSupport::Authorized(_) => return Err(AttErrorCode::INSUFFICIENT_AUTHORISATION),
+        }
+        // The client may connect with a private address, resolved by the key of its bond.
+        let is_client = |bond: &BondInformation| bond.matches(&peer);
+        for bond in stack.bonds() {
+            let delete = match deletion {
+                BondDeletion::RequestingDevice => is_client(&bond),
+                BondDeletion::All => true,
+                BondDeletion::AllButActive => !is_client(&bond),
+            };
+            if delete {
+                stack
+                    .remove_bond_information(&bond.identity)
+                    .map_err(|_| ERROR_OPERATION_FAILED)?;
+            }
+        }
+        Ok(deletion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::HostResources;
+    use crate::attribute_server::AttributeServer;
+    use crate::connection::SecurityLevel;
+    use crate::mock_client::MockClient;
+    use crate::mock_controller::MockController;
+    use crate::security_manager::Key;
+
+    #[test]
+    fn client_deletes_bonds_over_att() {
+        let config = BondManagementConfig {
+            delete_requesting_device: OperationSupport::Supported,
+            delete_all_but_active: OperationSupport::Authorized(b"1234"),
+            ..Default::default()
+        };
+        let mut resources: HostResources<1, 1, 27> = HostResources::new();
+        let stack = crate::new(MockController::new(), &mut resources);
+        let client = MockClient::new();
+        let others = [Address::random([1, 2, 3, 4, 5, 6]), Address::random([6, 5, 4, 3, 2, 1])];
+        for identity in [client.connection().peer(), others[0], others[1]] {
+            let bond = BondInformation::new(identity, Key::new(1), SecurityLevel::Encrypted);
+            unwrap!(stack.add_bond_information(bond));
+        }
+        let identities = || stack.bonds().iter().map(|b| b.identity).collect::<Vec<_, 3>>();
+
+        let mut storage: BondManagementStorage = BondManagementStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, BOND_MANAGEMENT_ATTRIBUTE_COUNT> = AttributeTable::new();
+        let bms = BondManagementServer::build(&mut table, &mut storage, config);
+        let server = AttributeServer::new(table);
+        let control_point = bms.control_point.handle;
+        let process = |handle, data: &[u8]| bms.process(&stack, client.connection(), handle, data).map(|_| ());
+
+        let (_, features) = unwrap!(client.read_by_type(&server, characteristic::BOND_MANAGEMENT_FEATURE));
+        assert_eq!(&features[..], &[0x10, 0x00, 0x03]);
+
+        // The client is unknown until the link is encrypted.
+        assert_eq!(
+            client.write(&server, control_point, &[0x03], process),
+            Err(AttErrorCode::INSUFFICIENT_ENCRYPTION)
+        );
+        client.encrypt(SecurityLevel::Encrypted);
+
+        assert_eq!(
+            client.write(&server, control_point, b"\x09123", process),
+            Err(AttErrorCode::INSUFFICIENT_AUTHORISATION)
+        );
+        assert_eq!(
+            client.write(&server, control_point, &[0x06], process),
+            Err(ERROR_OPCODE_NOT_SUPPORTED)
+        );
+        assert_eq!(identities().len(), 3);
+
+        assert_eq!(client.write(&server, control_point, b"\x091234", process), Ok(()));
+        assert_eq!(identities(), [client.connection().peer()]);
+
+        assert_eq!(client.write(&server, control_point, &[0x03], process), Ok(()));
+        assert!(identities().is_empty());
+    }
+}
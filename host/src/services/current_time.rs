@@ -0,0 +1,421 @@
+//! Current Time Service, sharing the time of a device such as a phone.
+//!
+//! The server reads its time from a [`Clock`] when the client reads it, and notifies the client
+//! when its time is adjusted. The client follows the time of the server to synchronize a device
+//! without its own time reference, such as a watch.
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+
+use crate::Error;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::cursor::ReadCursor;
+use crate::gatt::{GattClient, NotificationListener};
+use crate::{BleHostError, Controller};
+
+/// Length of the value of the current time.
+const CURRENT_TIME_LEN: usize = 10;
+
+/// A date and time, with its day of the week and the fractions of its second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ExactTime {
+    /// Year, from 1582 to 9999, or 0 if unknown.
+    pub year: u16,
+    /// Month of the year, from 1 to 12, or 0 if unknown.
+    pub month: u8,
+    /// Day of the month, from 1 to 31, or 0 if unknown.
+    pub day: u8,
+    /// Hours, from 0 to 23.
+    pub hours: u8,
+    /// Minutes, from 0 to 59.
+    pub minutes: u8,
+    /// Seconds, from 0 to 59.
+    pub seconds: u8,
+    /// Day of the week, from 1 for Monday to 7 for Sunday, or 0 if unknown.
+    pub day_of_week: u8,
+    /// Fractions of the second, in units of 1/256 s.
+    pub fractions256: u8,
+}
+
+/// Reasons of an adjustment of the time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AdjustReason(u8);
+
+impl AdjustReason {
+    /// No adjustment.
+    pub const NONE: Self = Self(0x00);
+    /// The time was set by the user.
+    pub const MANUAL: Self = Self(0x01);
+    /// The time was synchronized to an external reference, such as a network.
+    pub const EXTERNAL_REFERENCE: Self = Self(0x02);
+    /// The time zone changed.
+    pub const TIME_ZONE: Self = Self(0x04);
+    /// The daylight saving time started or ended.
+    pub const DST: Self = Self(0x08);
+}
+
+bitfield_set!(AdjustReason: u8, "reasons");
+
+/// The current time, with the reasons of its last adjustment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CurrentTime {
+    /// The time.
+    pub time: ExactTime,
+    /// Reasons of the adjustment, when notified after the time was adjusted.
+    pub adjust_reason: AdjustReason,
+}
+
+impl CurrentTime {
+    /// Decode the value of the current time.
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let mut r = ReadCursor::new(data);
+        let s = r.slice(CURRENT_TIME_LEN)?;
+        Ok(Self {
+            time: ExactTime {
+                year: u16::from_le_bytes([s[0], s[1]]),
+                month: s[2],
+                day: s[3],
+                hours: s[4],
+                minutes: s[5],
+                seconds: s[6],
+                day_of_week: s[7],
+                fractions256: s[8],
+            },
+            adjust_reason: AdjustReason(s[9]),
+        })
+    }
+
+    /// Encode the value of the current time.
+    pub fn to_bytes(&self) -> [u8; CURRENT_TIME_LEN] {
+        let t = &self.time;
+        let [y0, y1] = t.year.to_le_bytes();
+        [
+            y0,
+            y1,
+            t.month,
+            t.day,
+            t.hours,
+            t.minutes,
+            t.seconds,
+            t.day_of_week,
+            t.fractions256,
+            self.adjust_reason.0,
+        ]
+    }
+}
+
+/// Offsets of the local time from UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LocalTimeInformation {
+    /// Offset of the time zone, in units of 15 minutes, or -128 if unknown.
+    pub time_zone: i8,
+    /// Offset of the daylight saving time, in units of 15 minutes, or 255 if unknown.
+    pub dst_offset: u8,
+}
+
+impl LocalTimeInformation {
+    /// Decode the value of the local time information.
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let mut r = ReadCursor::new(data);
+        let s = r.slice(2)?;
+        Ok(Self {
+            time_zone: s[0] as i8,
+            dst_offset: s[1],
+        })
+    }
+
+    /// Encode the value of the local time information.
+    pub fn to_bytes(&self) -> [u8; 2] {
+        [self.time_zone as u8, self.dst_offset]
+    }
+}
+
+/// Source of the time of a [`CtsServer`].
+pub trait Clock {
+    /// The current local time.
+    fn now(&self) -> ExactTime;
+}
+
+/// Storage of the values of the characteristics of a [`CtsServer`].
+pub struct CtsStorage {
+    current_time: [u8; CURRENT_TIME_LEN],
+    local_time_information: [u8; 2],
+}
+
+impl CtsStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            current_time: [0; CURRENT_TIME_LEN],
+            local_time_information: [0; 2],
+        }
+    }
+}
+
+impl Default for CtsStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Number of attributes of the Current Time Service, with or without the local time information.
+pub const fn cts_attribute_count(local_time_information: bool) -> usize {
+    // The service, the declaration, value and CCCD of the current time, and the declaration and
+    // value of the local time information.
+    1 + 3 + if local_time_information { 2 } else { 0 }
+}
+
+/// Current Time Service server.
+///
+/// Reads of the current time are handled with [`CtsServer::process_read`] before being
+/// accepted, updating the value from the clock.
+pub struct CtsServer<K: Clock> {
+    clock: K,
+    current_time: Characteristic<[u8; CURRENT_TIME_LEN]>,
+}
+
+impl<K: Clock> CtsServer<K> {
+    /// Add the service to the attribute table, with the offsets of the local time if known.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut CtsStorage,
+        clock: K,
+        local_time_information: Option<LocalTimeInformation>,
+    ) -> Self {
+        let CtsStorage {
+            current_time,
+            local_time_information: local_store,
+        } = storage;
+        let mut service = table.add_service(Service::new(service::CURRENT_TIME));
+        let value = CurrentTime {
+            time: clock.now(),
+            adjust_reason: AdjustReason::NONE,
+        };
+        let current_time = service
+            .add_characteristic(
+                characteristic::CURRENT_TIME,
+                &[CharacteristicProp::Read, CharacteristicProp::Notify],
+                value.to_bytes(),
+                current_time,
+            )
+            .build();
+        if let Some(info) = local_time_information {
+            *local_store = info.to_bytes();
+            service
+                .add_characteristic_ro(characteristic::LOCAL_TIME_INFORMATION, &*local_store)
+                .build();
+        }
+        service.build();
+        Self { clock, current_time }
+    }
+
+    /// The clock of the server.
+    pub fn clock(&self) -> &K {
+        &self.clock
+    }
+
+    /// Handle a read of the client of an attribute, returning `false` if it is not the current time.
+    pub fn process_read<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        handle: u16,
+    ) -> Result<bool, Error> {
+        if handle != self.current_time.handle {
+            return Ok(false);
+        }
+        let value = CurrentTime {
+            time: self.clock.now(),
+            adjust_reason: AdjustReason::NONE,
+        };
+        self.current_time.set(server, &value.to_bytes())?;
+        Ok(true)
+    }
+
+    /// Notify the client of the time after it was adjusted, if subscribed.
+    pub async fn notify_adjusted<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        reason: AdjustReason,
+    ) -> Result<(), Error> {
+        let value = CurrentTime {
+            time: self.clock.now(),
+            adjust_reason: reason,
+        };
+        self.current_time.notify(server, connection, &value.to_bytes()).await
+    }
+}
+
+/// Current Time Service client, following the time of a remote device.
+pub struct CtsClient<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize> {
+    gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>,
+    current_time: Characteristic<[u8; CURRENT_TIME_LEN]>,
+    local_time_information: Option<Characteristic<[u8; 2]>>,
+}
+
+impl<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize>
+    CtsClient<'c, 'd, C, MAX_SERVICES, L2CAP_MTU>
+{
+    /// Discover the Current Time Service of the remote device.
+    ///
+    /// The GATT client must have room for one more service.
+    pub async fn new(gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>) -> Result<Self, BleHostError<C::Error>> {
+        let services = gatt.services_by_uuid(&service::CURRENT_TIME.into()).await?;
+        let cts = services.first().cloned().ok_or(Error::NotFound)?;
+        let current_time = gatt
+            .characteristic_by_uuid(&cts, &characteristic::CURRENT_TIME.into())
+            .await?;
+        let local_time_information = match gatt
+            .characteristic_by_uuid(&cts, &characteristic::LOCAL_TIME_INFORMATION.into())
+            .await
+        {
+            Ok(c) => Some(c),
+            Err(BleHostError::BleHost(Error::NotFound)) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            gatt,
+            current_time,
+            local_time_information,
+        })
+    }
+
+    /// Read the current time of the remote device.
+    pub async fn read_time(&self) -> Result<CurrentTime, BleHostError<C::Error>> {
+        let mut buf = [0; CURRENT_TIME_LEN];
+        let len = self.gatt.read_characteristic(&self.current_time, &mut buf).await?;
+        Ok(CurrentTime::decode(&buf[..len])?)
+    }
+
+    /// Read the offsets of the local time of the remote device from UTC, if exposed.
+    pub async fn read_local_time_information(&self) -> Result<Option<LocalTimeInformation>, BleHostError<C::Error>> {
+        let Some(c) = &self.local_time_information else {
+            return Ok(None);
+        };
+        let mut buf = [0; 2];
+        let len = self.gatt.read_characteristic(c, &mut buf).await?;
+        Ok(Some(LocalTimeInformation::decode(&buf[..len])?))
+    }
+
+    /// Subscribe to the notifications of the time, sent when it is adjusted.
+    ///
+    /// The GATT client must have a free notification subscriber.
+    pub async fn subscribe_time(&self) -> Result<CurrentTimeListener<'c, L2CAP_MTU>, BleHostError<C::Error>> {
+        let listener = self.gatt.subscribe(&self.current_time, false).await?;
+        Ok(CurrentTimeListener { listener })
+    }
+}
+
+/// Listener of the times notified by a remote device.
+pub struct CurrentTimeListener<'c, const L2CAP_MTU: usize> {
+    listener: NotificationListener<'c, L2CAP_MTU>,
+}
+
+impl<const L2CAP_MTU: usize> CurrentTimeListener<'_, L2CAP_MTU> {
+    /// Wait for the next time notified, skipping invalid notifications.
+    pub async fn next(&mut self) -> CurrentTime {
+        loop {
+            if let Ok(time) = CurrentTime::decode(self.listener.next().await.as_ref()) {
+                return time;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+
+    struct FixedClock(Cell<u8>);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> ExactTime {
+            ExactTime {
+                year: 2024,
+                month: 3,
+                day: 15,
+                hours: 12,
+                minutes: 30,
+                seconds: self.0.get(),
+                day_of_week: 5,
+                fractions256: 0,
+            }
+        }
+    }
+
+    #[test]
+    fn current_time_roundtrip() {
+        let time = CurrentTime {
+            time: FixedClock(Cell::new(45)).now(),
+            adjust_reason: AdjustReason::MANUAL.union(AdjustReason::TIME_ZONE),
+        };
+        let bytes = time.to_bytes();
+        assert_eq!(bytes, [0xe8, 0x07, 3, 15, 12, 30, 45, 5, 0, 0x05]);
+        assert_eq!(CurrentTime::decode(&bytes).unwrap(), time);
+        assert!(CurrentTime::decode(&bytes[..9]).is_err());
+
+        let info = LocalTimeInformation {
+            time_zone: -20,
+            dst_offset: 4,
+        };
+        assert_eq!(LocalTimeInformation::decode(&info.to_bytes()).unwrap(), info);
+    }
+
+    #[test]
+    fn reads_follow_the_clock() {
+        let mut storage = CtsStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, { cts_attribute_count(true) }> = AttributeTable::new();
+        let cts = CtsServer::build(
+            &mut table,
+            &mut storage,
+            FixedClock(Cell::new(0)),
+            Some(LocalTimeInformation::default()),
+        );
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+        let handle = cts.current_time.handle;
+
+        cts.clock().0.set(59);
+        assert!(!cts.process_read(&server, handle + 1).unwrap());
+        assert_eq!(client.read(&server, handle).unwrap()[6], 0);
+        assert!(cts.process_read(&server, handle).unwrap());
+        let time = CurrentTime::decode(&client.read(&server, handle).unwrap()).unwrap();
+        assert_eq!(time.time.seconds, 59);
+        assert_eq!(time.adjust_reason, AdjustReason::NONE);
+
+        let (_, info) = client
+            .read_by_type(&server, characteristic::LOCAL_TIME_INFORMATION)
+            .unwrap();
+        assert_eq!(info, LocalTimeInformation::default().to_bytes());
+    }
+
+    #[test]
+    fn adjustments_are_notified() {
+        let mut storage = CtsStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, { cts_attribute_count(false) }> = AttributeTable::new();
+        let cts = CtsServer::build(&mut table, &mut storage, FixedClock(Cell::new(10)), None);
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+
+        client
+            .subscribe(&server, cts.current_time.cccd_handle.unwrap(), 0x0001)
+            .unwrap();
+        block_on(cts.notify_adjusted(&server, client.connection(), AdjustReason::EXTERNAL_REFERENCE)).unwrap();
+        let (handle, value) = client.notified().unwrap();
+        assert_eq!(handle, cts.current_time.handle);
+        let time = CurrentTime::decode(&value).unwrap();
+        assert_eq!(time.time.seconds, 10);
+        assert_eq!(time.adjust_reason, AdjustReason::EXTERNAL_REFERENCE);
+    }
+}
@@ -0,0 +1,368 @@
+//! Cycling Speed and Cadence Service, notifying the revolutions measured by a bike sensor.
+//!
+//! Each measurement holds the cumulative revolutions of the wheel and of the crank, with the time
+//! of their last revolution, from which the client computes the speed and the cadence. The client
+//! sets the cumulative wheel revolutions, and moves the sensor to another location, through the
+//! [control point](super::sc_control_point).
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+
+use super::sc_control_point::{
+    ScControlPoint, ScControlPointEvent, ScControlPointStorage, ScProcedures, SensorLocation,
+};
+use crate::Error;
+use crate::att::AttErrorCode;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::cursor::WriteCursor;
+
+const FLAG_WHEEL_REVOLUTIONS: u8 = 0x01;
+const FLAG_CRANK_REVOLUTIONS: u8 = 0x02;
+
+const FEATURE_WHEEL_REVOLUTIONS: u16 = 0x0001;
+const FEATURE_CRANK_REVOLUTIONS: u16 = 0x0002;
+const FEATURE_MULTIPLE_SENSOR_LOCATIONS: u16 = 0x0004;
+
+const MEASUREMENT_LEN: usize = 11;
+
+/// Revolutions of a wheel or of a crank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Revolutions<T> {
+    /// Revolutions since the sensor was set up, wrapping around.
+    pub cumulative: T,
+    /// Time of the last revolution, in units of 1/1024 s, wrapping around.
+    pub last_event_time: u16,
+}
+
+/// A measurement of the revolutions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CscMeasurement {
+    /// Revolutions of the wheel, if supported.
+    pub wheel: Option<Revolutions<u32>>,
+    /// Revolutions of the crank, if supported.
+    pub crank: Option<Revolutions<u16>>,
+}
+
+impl CscMeasurement {
+    /// Encode the measurement, returning the length written.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut flags = 0;
+        if self.wheel.is_some() {
+            flags |= FLAG_WHEEL_REVOLUTIONS;
+        }
+        if self.crank.is_some() {
+            flags |= FLAG_CRANK_REVOLUTIONS;
+        }
+        let mut w = WriteCursor::new(buf);
+        w.write(flags)?;
+        if let Some(wheel) = self.wheel {
+            w.write(wheel.cumulative)?;
+            w.write(wheel.last_event_time)?;
+        }
+        if let Some(crank) = self.crank {
+            w.write(crank.cumulative)?;
+            w.write(crank.last_event_time)?;
+        }
+        Ok(w.len())
+    }
+}
+
+/// Configuration of the Cycling Speed and Cadence Service.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CyclingSpeedCadenceConfig<'a> {
+    /// Whether the measurements include the wheel revolutions, set with the control point.
+    pub wheel_revolutions: bool,
+    /// Whether the measurements include the crank revolutions.
+    pub crank_revolutions: bool,
+    /// Location of the sensor, if known.
+    pub location: Option<SensorLocation>,
+    /// Locations the client can move the sensor to with the control point, if several.
+    ///
+    /// The sensor is at the first one if its location is not given.
+    pub supported_locations: &'a [SensorLocation],
+}
+
+impl CyclingSpeedCadenceConfig<'_> {
+    /// Number of attributes added by the service.
+    pub const fn attribute_count(&self) -> usize {
+        // The service, the declaration, value and CCCD of the measurement, and the feature.
+        let mut count = 1 + 3 + 2;
+        if self.location.is_some() || !self.supported_locations.is_empty() {
+            count += 2;
+        }
+        if self.procedures().any() {
+            count += 3;
+        }
+        count
+    }
+
+    /// Value of the feature, listing the data and procedures supported.
+    pub const fn features(&self) -> u16 {
+        let mut features = 0;
+        if self.wheel_revolutions {
+            features |= FEATURE_WHEEL_REVOLUTIONS;
+        }
+        if self.crank_revolutions {
+            features |= FEATURE_CRANK_REVOLUTIONS;
+        }
+        if !self.supported_locations.is_empty() {
+            features |= FEATURE_MULTIPLE_SENSOR_LOCATIONS;
+        }
+        features
+    }
+
+    const fn procedures(&self) -> ScProcedures<'_> {
+        ScProcedures {
+            cumulative_value: self.wheel_revolutions,
+            calibration: false,
+            locations: self.supported_locations,
+        }
+    }
+}
+
+/// Storage of the values of the characteristics of a [`CyclingSpeedCadenceServer`].
+pub struct CyclingSpeedCadenceStorage {
+    measurement: [u8; MEASUREMENT_LEN],
+    features: [u8; 2],
+    control_point: ScControlPointStorage,
+}
+
+impl CyclingSpeedCadenceStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            measurement: [0; MEASUREMENT_LEN],
+            features: [0; 2],
+            control_point: ScControlPointStorage::new(),
+        }
+    }
+}
+
+impl Default for CyclingSpeedCadenceStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Cycling Speed and Cadence Service server.
+///
+/// The measurements are notified with [`CyclingSpeedCadenceServer::notify_measurement`], while
+/// the writes to the control point are handled with [`CyclingSpeedCadenceServer::process`]
+/// before being accepted, after which their response is indicated with
+/// [`CyclingSpeedCadenceServer::respond`].
+pub struct CyclingSpeedCadenceServer {
+    measurement: Characteristic<Vec<u8, MEASUREMENT_LEN>>,
+    location: Option<Characteristic<u8>>,
+    control_point: Option<ScControlPoint>,
+    wheel_revolutions: bool,
+    crank_revolutions: bool,
+}
+
+impl CyclingSpeedCadenceServer {
+    /// Add the service to the attribute table.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut CyclingSpeedCadenceStorage,
+        config: CyclingSpeedCadenceConfig<'_>,
+    ) -> Self {
+        let CyclingSpeedCadenceStorage {
+            measurement,
+            features,
+            control_point,
+        } = storage;
+        *features = config.features().to_le_bytes();
+        let mut service = table.add_service(Service::new(service::CYCLING_SPEED_AND_CADENCE));
+        let measurement = service
+            .add_characteristic(
+                characteristic::CSC_MEASUREMENT,
+                &[CharacteristicProp::Notify],
+                Vec::new(),
+                measurement,
+            )
+            .build();
+        service
+            .add_characteristic_ro(characteristic::CSC_FEATURE, &*features)
+            .build();
+        let (location, control_point) =
+            ScControlPoint::build(&mut service, control_point, config.location, config.procedures());
+        service.build();
+        Self {
+            measurement,
+            location,
+            control_point,
+            wheel_revolutions: config.wheel_revolutions,
+            crank_revolutions: config.crank_revolutions,
+        }
+    }
+
+    /// The sensor location characteristic, if any.
+    pub fn location(&self) -> Option<&Characteristic<u8>> {
+        self.location.as_ref()
+    }
+
+    /// Handle a write of the client to an attribute, returning `None` if it is not one of the service.
+    ///
+    /// The write is accepted when the event is returned, and rejected with the error otherwise.
+    pub fn process<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        handle: u16,
+        data: &[u8],
+    ) -> Result<Option<ScControlPointEvent>, AttErrorCode> {
+        match &mut self.control_point {
+            Some(control_point) => control_point.process(server, connection, handle, data),
+            None => Ok(None),
+        }
+    }
+
+    /// Report the failure of the procedure accepted, before its response is indicated.
+    pub fn fail(&mut self) {
+        if let Some(control_point) = &mut self.control_point {
+            control_point.fail();
+        }
+    }
+
+    /// Indicate the response of the procedure accepted, ending it.
+    pub async fn respond<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+    ) -> Result<(), Error> {
+        match &mut self.control_point {
+            Some(control_point) => control_point.respond(server, connection).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Notify a measurement to the client, if subscribed.
+    ///
+    /// Returns [`Error::InvalidValue`] if the measurement includes revolutions not supported by
+    /// the service.
+    pub async fn notify_measurement<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        measurement: &CscMeasurement,
+    ) -> Result<(), Error> {
+        if (measurement.wheel.is_some() && !self.wheel_revolutions)
+            || (measurement.crank.is_some() && !self.crank_revolutions)
+        {
+            return Err(Error::InvalidValue);
+        }
+        let mut buf = [0; MEASUREMENT_LEN];
+        let len = measurement.encode(&mut buf)?;
+        let value = unwrap!(Vec::from_slice(&buf[..len]).ok());
+        self.measurement.notify(server, connection, &value).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+    use crate::services::sc_control_point::ERROR_CCCD_IMPROPERLY_CONFIGURED;
+
+    #[test]
+    fn encode_measurements() {
+        let mut buf = [0; MEASUREMENT_LEN];
+        let measurement = CscMeasurement {
+            wheel: Some(Revolutions {
+                cumulative: 0x0102_0304,
+                last_event_time: 0x0506,
+            }),
+            crank: Some(Revolutions {
+                cumulative: 0x0708,
+                last_event_time: 0x090a,
+            }),
+        };
+        let len = measurement.encode(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            &[0x03, 0x04, 0x03, 0x02, 0x01, 0x06, 0x05, 0x08, 0x07, 0x0a, 0x09]
+        );
+
+        let config = CyclingSpeedCadenceConfig {
+            crank_revolutions: true,
+            ..Default::default()
+        };
+        assert_eq!(config.features(), 0x0002);
+        assert_eq!(config.attribute_count(), 6);
+        let mut storage = CyclingSpeedCadenceStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 6> = AttributeTable::new();
+        let cscs = CyclingSpeedCadenceServer::build(&mut table, &mut storage, config);
+        assert!(cscs.control_point.is_none());
+        assert!(cscs.location().is_none());
+    }
+
+    #[test]
+    fn client_sets_cumulative_value() {
+        let config = CyclingSpeedCadenceConfig {
+            wheel_revolutions: true,
+            ..Default::default()
+        };
+        assert_eq!(config.attribute_count(), 9);
+        let mut storage = CyclingSpeedCadenceStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 9> = AttributeTable::new();
+        let mut cscs = CyclingSpeedCadenceServer::build(&mut table, &mut storage, config);
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+        let (handle, cccd_handle) = {
+            let control_point = &cscs.control_point.as_ref().unwrap().characteristic;
+            (control_point.handle, control_point.cccd_handle.unwrap())
+        };
+        let set = [0x01, 0x10, 0x00, 0x00, 0x00];
+
+        // The procedure is rejected until the client subscribes to its responses.
+        assert_eq!(
+            client.write(&server, handle, &set, |handle, data| {
+                cscs.process(&server, client.connection(), handle, data).map(|_| ())
+            }),
+            Err(ERROR_CCCD_IMPROPERLY_CONFIGURED)
+        );
+        client.subscribe(&server, cccd_handle, 0x0002).unwrap();
+        let mut event = None;
+        client
+            .write(&server, handle, &set, |handle, data| {
+                event = cscs.process(&server, client.connection(), handle, data)?;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(event, Some(ScControlPointEvent::SetCumulativeValue(16)));
+        block_on(cscs.respond(&server, client.connection())).unwrap();
+        let indicated = client.notified().unwrap();
+        assert_eq!((indicated.0, &indicated.1[..]), (handle, &[0x10, 0x01, 0x01][..]));
+        client.confirm(&server);
+
+        client
+            .subscribe(&server, cscs.measurement.cccd_handle.unwrap(), 0x0001)
+            .unwrap();
+        let measurement = CscMeasurement {
+            wheel: Some(Revolutions {
+                cumulative: 17,
+                last_event_time: 0x0400,
+            }),
+            crank: None,
+        };
+        block_on(cscs.notify_measurement(&server, client.connection(), &measurement)).unwrap();
+        assert_eq!(client.notified().unwrap().1, [0x01, 17, 0, 0, 0, 0x00, 0x04]);
+        let measurement = CscMeasurement {
+            crank: Some(Revolutions {
+                cumulative: 1,
+                last_event_time: 0x0400,
+            }),
+            ..measurement
+        };
+        assert!(matches!(
+            block_on(cscs.notify_measurement(&server, client.connection(), &measurement)),
+            Err(Error::InvalidValue)
+        ));
+    }
+}
@@ -0,0 +1,245 @@
+//! Device Information Service, exposing the manufacturer and the versions of a device.
+//!
+//! The service is configured with [`DeviceInformationConfig`], whose characteristics are only
+//! added to the attribute table when set:
+//!
+//! ```rust,ignore
+//! const DEVICE_INFORMATION: DeviceInformationConfig = DeviceInformationConfig::new()
+//!     .manufacturer("Acme")
+//!     .model("Widget")
+//!     .firmware_revision("1.2.0");
+//!
+//! let mut storage = DeviceInformationStorage::new();
+//! let mut table: AttributeTable<'_, NoopRawMutex, { DEVICE_INFORMATION.attribute_count() }> = AttributeTable::new();
+//! DEVICE_INFORMATION.build(&mut table, &mut storage);
+//! ```
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+
+use crate::attribute::{AttributeTable, Service};
+
+/// Organization assigning the vendor identifier of a [`PnpId`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum VendorIdSource {
+    /// A company identifier assigned by the Bluetooth SIG.
+    Bluetooth = 0x01,
+    /// A vendor identifier assigned by the USB Implementer's Forum.
+    Usb = 0x02,
+}
+
+/// Plug and play identifier of the device, as found in the device identification of HID hosts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PnpId {
+    /// Organization assigning the vendor identifier.
+    pub vendor_id_source: VendorIdSource,
+    /// Identifier of the vendor of the device.
+    pub vendor_id: u16,
+    /// Identifier of the product, assigned by the vendor.
+    pub product_id: u16,
+    /// Version of the product, assigned by the vendor.
+    pub product_version: u16,
+}
+
+impl PnpId {
+    /// Encode the identifier as the value of its characteristic.
+    pub fn to_bytes(&self) -> [u8; 7] {
+        let [v0, v1] = self.vendor_id.to_le_bytes();
+        let [p0, p1] = self.product_id.to_le_bytes();
+        let [r0, r1] = self.product_version.to_le_bytes();
+        [self.vendor_id_source as u8, v0, v1, p0, p1, r0, r1]
+    }
+}
+
+/// Storage of the encoded [`PnpId`] of the service.
+pub struct DeviceInformationStorage {
+    pnp_id: [u8; 7],
+}
+
+impl DeviceInformationStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self { pnp_id: [0; 7] }
+    }
+}
+
+impl Default for DeviceInformationStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Configuration of the Device Information Service.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeviceInformationConfig<'a> {
+    manufacturer: Option<&'a str>,
+    model: Option<&'a str>,
+    serial_number: Option<&'a str>,
+    hardware_revision: Option<&'a str>,
+    firmware_revision: Option<&'a str>,
+    software_revision: Option<&'a str>,
+    pnp_id: Option<PnpId>,
+}
+
+impl<'a> DeviceInformationConfig<'a> {
+    /// Create a configuration without any characteristic.
+    pub const fn new() -> Self {
+        Self {
+            manufacturer: None,
+            model: None,
+            serial_number: None,
+            hardware_revision: None,
+            firmware_revision: None,
+            software_revision: None,
+            pnp_id: None,
+        }
+    }
+
+    /// Set the name of the manufacturer of the device.
+    pub const fn manufacturer(mut self, name: &'a str) -> Self {
+        self.manufacturer = Some(name);
+        self
+    }
+
+    /// Set the model number of the device.
+    pub const fn model(mut self, model: &'a str) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Set the serial number of the device.
+    pub const fn serial_number(mut self, serial_number: &'a str) -> Self {
+        self.serial_number = Some(serial_number);
+        self
+    }
+
+    /// Set the revision of the hardware of the device.
+    pub const fn hardware_revision(mut self, revision: &'a str) -> Self {
+        self.hardware_revision = Some(revision);
+        self
+    }
+
+    /// Set the revision of the firmware of the device.
+    pub const fn firmware_revision(mut self, revision: &'a str) -> Self {
+        self.firmware_revision = Some(revision);
+        self
+    }
+
+    /// Set the revision of the software of the device.
+    pub const fn software_revision(mut self, revision: &'a str) -> Self {
+        self.software_revision = Some(revision);
+        self
+    }
+
+    /// Set the plug and play identifier of the device, required by HID over GATT.
+    pub const fn pnp_id(mut self, pnp_id: PnpId) -> Self {
+        self.pnp_id = Some(pnp_id);
+        self
+    }
+
+    /// Number of attributes added by the service: 1 for the service, and 2 for each characteristic set.
+    pub const fn attribute_count(&self) -> usize {
+        let strings = [
+            self.manufacturer,
+            self.model,
+            self.serial_number,
+            self.hardware_revision,
+            self.firmware_revision,
+            self.software_revision,
+        ];
+        let mut count = 1;
+        let mut i = 0;
+        while i < strings.len() {
+            if strings[i].is_some() {
+                count += 2;
+            }
+            i += 1;
+        }
+        if self.pnp_id.is_some() {
+            count += 2;
+        }
+        count
+    }
+
+    /// Add the service to the attribute table.
+    pub fn build<M: RawMutex, const MAX: usize>(
+        self,
+        table: &mut AttributeTable<'a, M, MAX>,
+        storage: &'a mut DeviceInformationStorage,
+    ) {
+        let mut service = table.add_service(Service::new(service::DEVICE_INFORMATION));
+        for (uuid, value) in [
+            (characteristic::MANUFACTURER_NAME_STRING, self.manufacturer),
+            (characteristic::MODEL_NUMBER_STRING, self.model),
+            (characteristic::SERIAL_NUMBER_STRING, self.serial_number),
+            (characteristic::HARDWARE_REVISION_STRING, self.hardware_revision),
+            (characteristic::FIRMWARE_REVISION_STRING, self.firmware_revision),
+            (characteristic::SOFTWARE_REVISION_STRING, self.software_revision),
+        ] {
+            if let Some(value) = value {
+                service.add_characteristic_ro_bytes(uuid, value.as_bytes()).build();
+            }
+        }
+        if let Some(pnp_id) = self.pnp_id {
+            storage.pnp_id = pnp_id.to_bytes();
+            service
+                .add_characteristic_ro(characteristic::PNP_ID, &storage.pnp_id)
+                .build();
+        }
+        service.build();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::att::AttErrorCode;
+    use crate::attribute_server::AttributeServer;
+    use crate::mock_client::MockClient;
+
+    #[test]
+    fn only_configured_characteristics() {
+        const CONFIG: DeviceInformationConfig = DeviceInformationConfig::new()
+            .manufacturer("Acme")
+            .firmware_revision("1.2.0")
+            .pnp_id(PnpId {
+                vendor_id_source: VendorIdSource::Usb,
+                vendor_id: 0x1915,
+                product_id: 0xeeee,
+                product_version: 0x0001,
+            });
+        const COUNT: usize = CONFIG.attribute_count();
+        const { core::assert!(COUNT == 7) };
+
+        let mut storage = DeviceInformationStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, COUNT> = AttributeTable::new();
+        CONFIG.build(&mut table, &mut storage);
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+
+        let read = |uuid| client.read_by_type(&server, uuid).map(|(_, value)| value);
+        assert_eq!(read(characteristic::MANUFACTURER_NAME_STRING).unwrap(), b"Acme");
+        assert_eq!(read(characteristic::FIRMWARE_REVISION_STRING).unwrap(), b"1.2.0");
+        assert_eq!(
+            read(characteristic::PNP_ID).unwrap(),
+            [0x02, 0x15, 0x19, 0xee, 0xee, 0x01, 0x00]
+        );
+        assert_eq!(
+            read(characteristic::MODEL_NUMBER_STRING),
+            Err(AttErrorCode::ATTRIBUTE_NOT_FOUND)
+        );
+
+        // The strings are read only.
+        let (handle, _) = client
+            .read_by_type(&server, characteristic::MANUFACTURER_NAME_STRING)
+            .unwrap();
+        assert_eq!(
+            client.write(&server, handle, b"Evil", |_, _| Ok(())),
+            Err(AttErrorCode::WRITE_NOT_PERMITTED)
+        );
+    }
+}
@@ -0,0 +1,715 @@
+//! Environmental Sensing Service, notifying the measurements of environmental sensors.
+//!
+//! Each sensor is a characteristic of the service, such as the temperature or the humidity,
+//! described by an ES Measurement descriptor. Its ES Trigger Setting descriptors give the
+//! conditions on which a new value is notified to the client, for instance when the value changed
+//! or went above a threshold, combined as set by its ES Configuration descriptor. The server
+//! evaluates the conditions for each new value of the application, notifying only the values
+//! triggering them.
+use bt_hci::uuid::{characteristic, descriptors, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use crate::Error;
+use crate::att::AttErrorCode;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Descriptor, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+
+/// Error of a write of an invalid trigger setting or configuration.
+pub const ERROR_WRITE_REQUEST_REJECTED: AttErrorCode = AttErrorCode::application(0x80);
+/// Error of a write of an unknown trigger condition.
+pub const ERROR_CONDITION_NOT_SUPPORTED: AttErrorCode = AttErrorCode::application(0x81);
+
+/// Maximum number of trigger settings of a sensor.
+pub const MAX_TRIGGERS: usize = 3;
+
+// Largest value of a sensor, and of a trigger setting with its condition.
+const VALUE_LEN: usize = 4;
+const TRIGGER_LEN: usize = 1 + VALUE_LEN;
+const MEASUREMENT_LEN: usize = 11;
+
+const CONDITION_INACTIVE: u8 = 0x00;
+const CONDITION_FIXED_INTERVAL: u8 = 0x01;
+const CONDITION_MIN_INTERVAL: u8 = 0x02;
+const CONDITION_VALUE_CHANGED: u8 = 0x03;
+const CONDITION_LESS_THAN: u8 = 0x04;
+const CONDITION_LESS_OR_EQUAL: u8 = 0x05;
+const CONDITION_GREATER_THAN: u8 = 0x06;
+const CONDITION_GREATER_OR_EQUAL: u8 = 0x07;
+const CONDITION_EQUAL: u8 = 0x08;
+const CONDITION_NOT_EQUAL: u8 = 0x09;
+
+/// Kind of a sensor, giving its characteristic and the format of its values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SensorKind {
+    /// Temperature, in units of 0.01 °C, from -32768 to 32767.
+    Temperature,
+    /// Relative humidity, in units of 0.01 %, from 0 to 10000.
+    Humidity,
+    /// Pressure, in units of 0.1 Pa, from 0.
+    Pressure,
+    /// UV index, from 0 to 255.
+    UvIndex,
+}
+
+impl SensorKind {
+    /// UUID of the characteristic of the sensor.
+    pub const fn uuid(&self) -> bt_hci::uuid::BluetoothUuid16 {
+        match self {
+            Self::Temperature => characteristic::TEMPERATURE,
+            Self::Humidity => characteristic::HUMIDITY,
+            Self::Pressure => characteristic::PRESSURE,
+            Self::UvIndex => characteristic::UV_INDEX,
+        }
+    }
+
+    /// Length of the values of the sensor.
+    pub const fn value_len(&self) -> usize {
+        match self {
+            Self::Temperature | Self::Humidity => 2,
+            Self::Pressure => 4,
+            Self::UvIndex => 1,
+        }
+    }
+
+    const fn range(&self) -> (i32, i32) {
+        match self {
+            Self::Temperature => (i16::MIN as i32, i16::MAX as i32),
+            Self::Humidity => (0, 10000),
+            Self::Pressure => (0, i32::MAX),
+            Self::UvIndex => (0, u8::MAX as i32),
+        }
+    }
+
+    /// Encode a value of the sensor, returning [`Error::InvalidValue`] if out of its range.
+    pub fn encode(&self, value: i32) -> Result<Vec<u8, VALUE_LEN>, Error> {
+        let (min, max) = self.range();
+        if !(min..=max).contains(&value) {
+            return Err(Error::InvalidValue);
+        }
+        Ok(unwrap!(Vec::from_slice(&value.to_le_bytes()[..self.value_len()]).ok()))
+    }
+
+    /// Decode a value of the sensor.
+    pub fn decode(&self, data: &[u8]) -> Result<i32, Error> {
+        if data.len() != self.value_len() {
+            return Err(Error::InvalidValue);
+        }
+        let value = match *data {
+            [v] => v as i32,
+            [v0, v1] if *self == Self::Temperature => i16::from_le_bytes([v0, v1]) as i32,
+            [v0, v1] => u16::from_le_bytes([v0, v1]) as i32,
+            [v0, v1, v2, v3] => i32::from_le_bytes([v0, v1, v2, v3]),
+            _ => return Err(Error::InvalidValue),
+        };
+        let (min, max) = self.range();
+        if !(min..=max).contains(&value) {
+            return Err(Error::InvalidValue);
+        }
+        Ok(value)
+    }
+}
+
+/// Function sampling the values of a sensor over its measurement period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum SamplingFunction {
+    /// Unspecified.
+    #[default]
+    Unspecified = 0x00,
+    /// The instantaneous value.
+    Instantaneous = 0x01,
+    /// The arithmetic mean of the values.
+    ArithmeticMean = 0x02,
+    /// The root mean square of the values.
+    Rms = 0x03,
+    /// The maximum of the values.
+    Maximum = 0x04,
+    /// The minimum of the values.
+    Minimum = 0x05,
+    /// The sum of the values.
+    Accumulated = 0x06,
+    /// The number of values.
+    Count = 0x07,
+}
+
+/// Description of the measurements of a sensor, the value of its ES Measurement descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MeasurementDescriptor {
+    /// Function sampling the values over the measurement period.
+    pub sampling_function: SamplingFunction,
+    /// Period of the measurement, in seconds up to 2^24 - 1, or 0 if not used.
+    pub measurement_period: u32,
+    /// Interval between the measurements, in seconds up to 2^24 - 1, or 0 if not used.
+    pub update_interval: u32,
+    /// Application of the measurements, such as 0x01 for the air, or 0x00 if unspecified.
+    pub application: u8,
+    /// Uncertainty of the measurements, in units of 0.5 %, or 0xff if unknown.
+    pub uncertainty: u8,
+}
+
+impl MeasurementDescriptor {
+    /// Encode the descriptor.
+    pub fn to_bytes(&self) -> [u8; MEASUREMENT_LEN] {
+        let [p0, p1, p2, _] = self.measurement_period.to_le_bytes();
+        let [u0, u1, u2, _] = self.update_interval.to_le_bytes();
+        [
+            0,
+            0,
+            self.sampling_function as u8,
+            p0,
+            p1,
+            p2,
+            u0,
+            u1,
+            u2,
+            self.application,
+            self.uncertainty,
+        ]
+    }
+}
+
+/// Condition on which a value of a sensor is notified, the value of an ES Trigger Setting descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TriggerCondition {
+    /// The values are not notified.
+    Inactive,
+    /// The values are notified once every interval, in seconds up to 2^24 - 1.
+    FixedInterval(u32),
+    /// The changed values are notified, no sooner than an interval after the previous
+    /// notification, in seconds up to 2^24 - 1.
+    MinInterval(u32),
+    /// The values are notified when they changed.
+    ValueChanged,
+    /// The values less than the operand are notified.
+    LessThan(i32),
+    /// The values less than or equal to the operand are notified.
+    LessOrEqual(i32),
+    /// The values greater than the operand are notified.
+    GreaterThan(i32),
+    /// The values greater than or equal to the operand are notified.
+    GreaterOrEqual(i32),
+    /// The values equal to the operand are notified.
+    Equal(i32),
+    /// The values not equal to the operand are notified.
+    NotEqual(i32),
+}
+
+impl TriggerCondition {
+    /// Encode the condition, with its operand in the format of the values of a sensor.
+    pub fn encode(&self, kind: SensorKind) -> Result<Vec<u8, TRIGGER_LEN>, Error> {
+        let (condition, operand) = match *self {
+            Self::Inactive => (CONDITION_INACTIVE, None),
+            Self::FixedInterval(interval) => (CONDITION_FIXED_INTERVAL, Some(interval)),
+            Self::MinInterval(interval) => (CONDITION_MIN_INTERVAL, Some(interval)),
+            Self::ValueChanged => (CONDITION_VALUE_CHANGED, None),
+            Self::LessThan(v) => return Self::encode_value(CONDITION_LESS_THAN, kind, v),
+            Self::LessOrEqual(v) => return Self::encode_value(CONDITION_LESS_OR_EQUAL, kind, v),
+            Self::GreaterThan(v) => return Self::encode_value(CONDITION_GREATER_THAN, kind, v),
+            Self::GreaterOrEqual(v) => return Self::encode_value(CONDITION_GREATER_OR_EQUAL, kind, v),
+            Self::Equal(v) => return Self::encode_value(CONDITION_EQUAL, kind, v),
+            Self::NotEqual(v) => return Self::encode_value(CONDITION_NOT_EQUAL, kind, v),
+        };
+        let mut data = Vec::new();
+        unwrap!(data.push(condition).ok());
+        if let Some(interval) = operand {
+            if interval > 0xff_ffff {
+                return Err(Error::InvalidValue);
+            }
+            unwrap!(data.extend_from_slice(&interval.to_le_bytes()[..3]).ok());
+        }
+        Ok(data)
+    }
+
+    fn encode_value(condition: u8, kind: SensorKind, value: i32) -> Result<Vec<u8, TRIGGER_LEN>, Error> {
+        let mut data = Vec::new();
+        unwrap!(data.push(condition).ok());
+        unwrap!(data.extend_from_slice(&kind.encode(value)?).ok());
+        Ok(data)
+    }
+
+    /// Decode a condition, with its operand in the format of the values of a sensor.
+    pub fn decode(kind: SensorKind, data: &[u8]) -> Result<Self, AttErrorCode> {
+        let [condition, operand @ ..] = data else {
+            return Err(ERROR_WRITE_REQUEST_REJECTED);
+        };
+        let interval = || match *operand {
+            [i0, i1, i2] => Ok(u32::from_le_bytes([i0, i1, i2, 0])),
+            _ => Err(ERROR_WRITE_REQUEST_REJECTED),
+        };
+        let value = || kind.decode(operand).map_err(|_| ERROR_WRITE_REQUEST_REJECTED);
+        match *condition {
+            CONDITION_INACTIVE | CONDITION_VALUE_CHANGED if !operand.is_empty() => Err(ERROR_WRITE_REQUEST_REJECTED),
+            CONDITION_INACTIVE => Ok(Self::Inactive),
+            CONDITION_FIXED_INTERVAL => Ok(Self::FixedInterval(interval()?)),
+            CONDITION_MIN_INTERVAL => Ok(Self::MinInterval(interval()?)),
+            CONDITION_VALUE_CHANGED => Ok(Self::ValueChanged),
+            CONDITION_LESS_THAN => Ok(Self::LessThan(value()?)),
+            CONDITION_LESS_OR_EQUAL => Ok(Self::LessOrEqual(value()?)),
+            CONDITION_GREATER_THAN => Ok(Self::GreaterThan(value()?)),
+            CONDITION_GREATER_OR_EQUAL => Ok(Self::GreaterOrEqual(value()?)),
+            CONDITION_EQUAL => Ok(Self::Equal(value()?)),
+            CONDITION_NOT_EQUAL => Ok(Self::NotEqual(value()?)),
+            _ => Err(ERROR_CONDITION_NOT_SUPPORTED),
+        }
+    }
+
+    /// Whether a value triggers the condition, given the previous notification if any.
+    fn triggered(&self, value: i32, last: Option<(i32, Instant)>, now: Instant) -> bool {
+        let elapsed = |interval: u32| {
+            last.is_none_or(|(_, at)| now.saturating_duration_since(at) >= Duration::from_secs(interval as u64))
+        };
+        let changed = last.is_none_or(|(last, _)| last != value);
+        match *self {
+            Self::Inactive => false,
+            Self::FixedInterval(interval) => elapsed(interval),
+            Self::MinInterval(interval) => changed && elapsed(interval),
+            Self::ValueChanged => changed,
+            Self::LessThan(operand) => value < operand,
+            Self::LessOrEqual(operand) => value <= operand,
+            Self::GreaterThan(operand) => value > operand,
+            Self::GreaterOrEqual(operand) => value >= operand,
+            Self::Equal(operand) => value == operand,
+            Self::NotEqual(operand) => value != operand,
+        }
+    }
+}
+
+/// Combination of the trigger conditions of a sensor, the value of its ES Configuration descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum TriggerLogic {
+    /// The values triggering all conditions are notified.
+    And = 0x00,
+    /// The values triggering any condition are notified.
+    #[default]
+    Or = 0x01,
+}
+
+/// Configuration of a sensor of the Environmental Sensing Service.
+#[derive(Debug, Clone, Copy)]
+pub struct SensorConfig<'a> {
+    /// Kind of the sensor.
+    pub kind: SensorKind,
+    /// Description of the measurements, required if the service has several sensors of a kind.
+    pub measurement: Option<MeasurementDescriptor>,
+    /// Conditions on which the values are notified, up to [`MAX_TRIGGERS`].
+    ///
+    /// Each value is notified if there is none.
+    pub triggers: &'a [TriggerCondition],
+    /// Combination of the conditions, if there are several.
+    pub logic: TriggerLogic,
+    /// Whether the client can change the conditions and their combination.
+    pub writable: bool,
+}
+
+impl SensorConfig<'_> {
+    /// Configuration of a sensor notifying each value, without a measurement descriptor.
+    pub const fn new(kind: SensorKind) -> Self {
+        Self {
+            kind,
+            measurement: None,
+            triggers: &[],
+            logic: TriggerLogic::Or,
+            writable: false,
+        }
+    }
+
+    const fn attribute_count(&self) -> usize {
+        // The declaration, value and CCCD of the characteristic, and its descriptors.
+        let mut count = 3 + self.triggers.len();
+        if self.measurement.is_some() {
+            count += 1;
+        }
+        if self.triggers.len() > 1 {
+            count += 1;
+        }
+        count
+    }
+}
+
+/// Configuration of the Environmental Sensing Service.
+#[derive(Debug, Clone, Copy)]
+pub struct EnvironmentalSensingConfig<'a> {
+    /// Sensors of the service, each being a characteristic.
+    pub sensors: &'a [SensorConfig<'a>],
+}
+
+impl EnvironmentalSensingConfig<'_> {
+    /// Number of attributes added by the service.
+    pub const fn attribute_count(&self) -> usize {
+        let mut count = 1;
+        let mut i = 0;
+        while i < self.sensors.len() {
+            count += self.sensors[i].attribute_count();
+            i += 1;
+        }
+        count
+    }
+}
+
+struct SensorStorage {
+    value: [u8; VALUE_LEN],
+    measurement: [u8; MEASUREMENT_LEN],
+    triggers: [[u8; TRIGGER_LEN]; MAX_TRIGGERS],
+    logic: [u8; 1],
+}
+
+/// Storage of the values of the characteristics and descriptors of an [`EnvironmentalSensingServer`].
+///
+/// The storage holds up to `N` sensors.
+pub struct EnvironmentalSensingStorage<const N: usize> {
+    sensors: [SensorStorage; N],
+}
+
+impl<const N: usize> EnvironmentalSensingStorage<N> {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            sensors: [const {
+                SensorStorage {
+                    value: [0; VALUE_LEN],
+                    measurement: [0; MEASUREMENT_LEN],
+                    triggers: [[0; TRIGGER_LEN]; MAX_TRIGGERS],
+                    logic: [0; 1],
+                }
+            }; N],
+        }
+    }
+}
+
+impl<const N: usize> Default for EnvironmentalSensingStorage<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A write of the client, handled by [`EnvironmentalSensingServer::process`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EnvironmentalSensingEvent {
+    /// The client changed a trigger condition of a sensor.
+    TriggerChanged {
+        /// Index of the sensor in the configuration.
+        sensor: usize,
+        /// The new condition.
+        condition: TriggerCondition,
+    },
+    /// The client changed the combination of the trigger conditions of a sensor.
+    LogicChanged {
+        /// Index of the sensor in the configuration.
+        sensor: usize,
+        /// The new combination.
+        logic: TriggerLogic,
+    },
+}
+
+struct Sensor {
+    kind: SensorKind,
+    value: Characteristic<Vec<u8, VALUE_LEN>>,
+    triggers: Vec<(Descriptor<Vec<u8, TRIGGER_LEN>>, TriggerCondition), MAX_TRIGGERS>,
+    logic: Option<(Descriptor<u8>, TriggerLogic)>,
+    // Value and time of the last notification.
+    last: Option<(i32, Instant)>,
+}
+
+impl Sensor {
+    fn triggered(&self, value: i32, now: Instant) -> bool {
+        let mut conditions = self.triggers.iter().map(|(_, c)| c.triggered(value, self.last, now));
+        match self.logic.map(|(_, l)| l) {
+            _ if self.triggers.is_empty() => true,
+            Some(TriggerLogic::And) => conditions.all(|t| t),
+            Some(TriggerLogic::Or) | None => conditions.any(|t| t),
+        }
+    }
+}
+
+/// Environmental Sensing Service server.
+///
+/// The values of the sensors are updated with [`EnvironmentalSensingServer::update`], notifying
+/// the ones triggering their conditions, while the writes of the client to the descriptors are
+/// handled with [`EnvironmentalSensingServer::process`] before being accepted. The conditions are
+/// evaluated against the last value notified, whichever the connection it was notified to.
+pub struct EnvironmentalSensingServer<const N: usize> {
+    sensors: Vec<Sensor, N>,
+}
+
+impl<const N: usize> EnvironmentalSensingServer<N> {
+    /// Add the service to the attribute table.
+    ///
+    /// Panics if the configuration holds more than `N` sensors, or a sensor more than
+    /// [`MAX_TRIGGERS`] conditions or an operand out of the range of its values.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut EnvironmentalSensingStorage<N>,
+        config: EnvironmentalSensingConfig<'_>,
+    ) -> Self {
+        assert!(config.sensors.len() <= N, "too many sensors for the storage");
+        let mut sensors = Vec::new();
+        let mut service = table.add_service(Service::new(service::ENVIRONMENTAL_SENSING));
+        for (sensor, storage) in config.sensors.iter().zip(storage.sensors.iter_mut()) {
+            assert!(sensor.triggers.len() <= MAX_TRIGGERS, "too many triggers for a sensor");
+            let SensorStorage {
+                value,
+                measurement,
+                triggers,
+                logic,
+            } = storage;
+            let len = sensor.kind.value_len();
+            let mut characteristic = service.add_characteristic(
+                sensor.kind.uuid(),
+                &[CharacteristicProp::Read, CharacteristicProp::Notify],
+                unwrap!(Vec::from_slice(&[0; VALUE_LEN][..len]).ok()),
+                &mut value[..len],
+            );
+            if let Some(descriptor) = sensor.measurement {
+                *measurement = descriptor.to_bytes();
+                characteristic.add_descriptor_ro::<[u8; MEASUREMENT_LEN], _>(
+                    descriptors::ENVIRONMENTAL_SENSING_MEASUREMENT,
+                    &*measurement,
+                );
+            }
+            let props: &[CharacteristicProp] = if sensor.writable {
+                &[CharacteristicProp::Read, CharacteristicProp::Write]
+            } else {
+                &[CharacteristicProp::Read]
+            };
+            let mut handles = Vec::new();
+            for (condition, data) in sensor.triggers.iter().zip(triggers.iter_mut()) {
+                let descriptor =
+                    characteristic.add_descriptor(descriptors::ENVIRONMENTAL_SENSING_TRIGGER_SETTING, props, data);
+                unwrap!(handles.push((descriptor, *condition)).ok());
+            }
+            let logic = (sensor.triggers.len() > 1).then(|| {
+                *logic = [sensor.logic as u8];
+                let descriptor =
+                    characteristic.add_descriptor(descriptors::ENVIRONMENTAL_SENSING_CONFIGURATION, props, logic);
+                (descriptor, sensor.logic)
+            });
+            let sensor = Sensor {
+                kind: sensor.kind,
+                value: characteristic.build(),
+                triggers: handles,
+                logic,
+                last: None,
+            };
+            unwrap!(sensors.push(sensor).ok());
+        }
+        service.build();
+        for sensor in sensors.iter() {
+            for (descriptor, condition) in sensor.triggers.iter() {
+                unwrap!(table.set(descriptor, &unwrap!(condition.encode(sensor.kind).ok())).ok());
+            }
+        }
+        Self { sensors }
+    }
+
+    /// The trigger conditions of a sensor, as last written by the client.
+    pub fn triggers(&self, sensor: usize) -> impl Iterator<Item = TriggerCondition> + '_ {
+        self.sensors
+            .get(sensor)
+            .into_iter()
+            .flat_map(|s| s.triggers.iter().map(|(_, c)| *c))
+    }
+
+    /// Handle a write of the client to an attribute, returning `None` if it is not one of the service.
+    ///
+    /// The write is accepted when the event is returned, and rejected with the error otherwise.
+    pub fn process(&mut self, handle: u16, data: &[u8]) -> Result<Option<EnvironmentalSensingEvent>, AttErrorCode> {
+        for (index, sensor) in self.sensors.iter_mut().enumerate() {
+            let kind = sensor.kind;
+            if let Some((_, condition)) = sensor.triggers.iter_mut().find(|(d, _)| d.handle == handle) {
+                *condition = TriggerCondition::decode(kind, data)?;
+                return Ok(Some(EnvironmentalSensingEvent::TriggerChanged {
+                    sensor: index,
+                    condition: *condition,
+                }));
+            }
+            if let Some((_, logic)) = sensor.logic.as_mut().filter(|(d, _)| d.handle == handle) {
+                *logic = match data {
+                    [0x00] => TriggerLogic::And,
+                    [0x01] => TriggerLogic::Or,
+                    _ => return Err(ERROR_WRITE_REQUEST_REJECTED),
+                };
+                return Ok(Some(EnvironmentalSensingEvent::LogicChanged {
+                    sensor: index,
+                    logic: *logic,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Update the value of a sensor, notifying the client if it triggers the conditions.
+    ///
+    /// Returns whether the value was notified, or [`Error::InvalidValue`] if it is out of the
+    /// range of the sensor. The values triggering a condition on a fixed interval should be updated
+    /// at least as often.
+    pub async fn update<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        sensor: usize,
+        value: i32,
+    ) -> Result<bool, Error> {
+        let sensor = self.sensors.get_mut(sensor).ok_or(Error::NotFound)?;
+        let data = sensor.kind.encode(value)?;
+        let now = Instant::now();
+        if !sensor.triggered(value, now) {
+            sensor.value.set(server, &data)?;
+            return Ok(false);
+        }
+        sensor.value.notify(server, connection, &data).await?;
+        sensor.last = Some((value, now));
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+
+    #[test]
+    fn trigger_settings_roundtrip() {
+        let condition = TriggerCondition::GreaterThan(-250);
+        let data = condition.encode(SensorKind::Temperature).unwrap();
+        assert_eq!(&data[..], &[0x06, 0x06, 0xff]);
+        assert_eq!(TriggerCondition::decode(SensorKind::Temperature, &data), Ok(condition));
+
+        let data = TriggerCondition::FixedInterval(60)
+            .encode(SensorKind::Pressure)
+            .unwrap();
+        assert_eq!(&data[..], &[0x01, 60, 0, 0]);
+        assert!(TriggerCondition::LessThan(-1).encode(SensorKind::Humidity).is_err());
+
+        assert_eq!(
+            TriggerCondition::decode(SensorKind::Humidity, &[0x04, 0x10]),
+            Err(ERROR_WRITE_REQUEST_REJECTED)
+        );
+        assert_eq!(
+            TriggerCondition::decode(SensorKind::Humidity, &[0x0a]),
+            Err(ERROR_CONDITION_NOT_SUPPORTED)
+        );
+    }
+
+    #[test]
+    fn evaluate_triggers() {
+        let sensors = [
+            SensorConfig {
+                measurement: Some(MeasurementDescriptor::default()),
+                triggers: &[TriggerCondition::ValueChanged, TriggerCondition::GreaterOrEqual(3000)],
+                logic: TriggerLogic::And,
+                writable: true,
+                ..SensorConfig::new(SensorKind::Temperature)
+            },
+            SensorConfig {
+                triggers: &[TriggerCondition::MinInterval(10)],
+                ..SensorConfig::new(SensorKind::Humidity)
+            },
+        ];
+        let config = EnvironmentalSensingConfig { sensors: &sensors };
+        assert_eq!(config.attribute_count(), 12);
+        let mut storage: EnvironmentalSensingStorage<2> = EnvironmentalSensingStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 12> = AttributeTable::new();
+        let mut ess = EnvironmentalSensingServer::build(&mut table, &mut storage, config);
+        assert_eq!(table.get(&ess.sensors[0].triggers[1].0).unwrap(), [0x07, 0xb8, 0x0b]);
+        let trigger = ess.sensors[0].triggers[1].0.handle;
+
+        let t0 = Instant::from_secs(100);
+        let temperature = &mut ess.sensors[0];
+        assert!(!temperature.triggered(2500, t0));
+        assert!(temperature.triggered(3100, t0));
+        temperature.last = Some((3100, t0));
+        assert!(!temperature.triggered(3100, t0));
+
+        let humidity = &mut ess.sensors[1];
+        humidity.last = Some((5000, t0));
+        assert!(!humidity.triggered(5100, t0 + Duration::from_secs(5)));
+        assert!(!humidity.triggered(5000, t0 + Duration::from_secs(10)));
+        assert!(humidity.triggered(5100, t0 + Duration::from_secs(10)));
+
+        // The client combines the conditions of the temperature with a logical or.
+        let logic = ess.sensors[0].logic.unwrap().0.handle;
+        assert_eq!(
+            ess.process(logic, &[0x01]),
+            Ok(Some(EnvironmentalSensingEvent::LogicChanged {
+                sensor: 0,
+                logic: TriggerLogic::Or
+            }))
+        );
+        assert!(ess.sensors[0].triggered(2500, t0));
+        assert_eq!(ess.process(logic, &[0x02]), Err(ERROR_WRITE_REQUEST_REJECTED));
+        assert_eq!(
+            ess.process(trigger, &[0x08, 0x00, 0x00]),
+            Ok(Some(EnvironmentalSensingEvent::TriggerChanged {
+                sensor: 0,
+                condition: TriggerCondition::Equal(0)
+            }))
+        );
+        assert_eq!(ess.triggers(0).nth(1), Some(TriggerCondition::Equal(0)));
+    }
+
+    #[test]
+    fn client_sets_trigger_over_att() {
+        let sensors = [SensorConfig {
+            triggers: &[TriggerCondition::GreaterThan(3000)],
+            writable: true,
+            ..SensorConfig::new(SensorKind::Temperature)
+        }];
+        let config = EnvironmentalSensingConfig { sensors: &sensors };
+        let mut storage: EnvironmentalSensingStorage<1> = EnvironmentalSensingStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 12> = AttributeTable::new();
+        let mut ess = EnvironmentalSensingServer::build(&mut table, &mut storage, config);
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+        let value = ess.sensors[0].value.handle;
+        let trigger = ess.sensors[0].triggers[0].0.handle;
+        client
+            .subscribe(&server, ess.sensors[0].value.cccd_handle.unwrap(), 0x0001)
+            .unwrap();
+
+        assert_eq!(client.read(&server, trigger).unwrap(), [0x06, 0xb8, 0x0b]);
+        assert!(!block_on(ess.update(&server, client.connection(), 0, 2500)).unwrap());
+        assert!(client.notified().is_none());
+        assert_eq!(client.read(&server, value).unwrap(), [0xc4, 0x09]);
+
+        let mut event = None;
+        client
+            .write(&server, trigger, &[0x05, 0xd0, 0x07], |handle, data| {
+                event = ess.process(handle, data)?;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            event,
+            Some(EnvironmentalSensingEvent::TriggerChanged {
+                sensor: 0,
+                condition: TriggerCondition::LessOrEqual(2000)
+            })
+        );
+        assert_eq!(
+            client.write(&server, trigger, &[0x0a], |handle, data| ess
+                .process(handle, data)
+                .map(|_| ())),
+            Err(ERROR_CONDITION_NOT_SUPPORTED)
+        );
+        assert_eq!(client.read(&server, trigger).unwrap(), [0x05, 0xd0, 0x07]);
+
+        assert!(block_on(ess.update(&server, client.connection(), 0, 1500)).unwrap());
+        assert_eq!(
+            client.notified().unwrap(),
+            (value, Vec::from_slice(&[0xdc, 0x05]).unwrap())
+        );
+    }
+}
@@ -0,0 +1,596 @@
+//! Google Fast Pair service, letting Android devices pair with an accessory in one tap.
+//!
+//! The provider, the accessory, advertises its model ID while in pairing mode, and the filter of
+//! its account keys otherwise, so that the seekers of the accounts it already paired with
+//! recognize it. A seeker then proves its knowledge of the anti-spoofing key of the model, or of
+//! an account key, in the key-based pairing handshake, after which it confirms the passkey of the
+//! pairing and writes the account key to store.
+//!
+//! The AES-128, SHA-256 and P-256 ECDH operations of the handshake are those of the security
+//! manager. The application provides the anti-spoofing private key of the model, a seed for the
+//! random generator and the [`AccountKeyStore`] keeping the account keys.
+//!
+//! The pairing itself is performed by the security manager of the host, which pairs with
+//! Passkey Entry or Just Works but not Numeric Comparison. The passkey written by the seeker is
+//! therefore reported with [`FastPairEvent::Passkey`] for the application to check, instead of
+//! confirming the pairing, and seekers requiring Numeric Comparison do not pair. Account keys are
+//! only accepted once the link is encrypted, after the seeker paired or encrypted the link with
+//! its bond.
+use bt_hci::param::BdAddr;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+use rand_chacha::ChaCha12Rng;
+use rand_core::{CryptoRng, RngCore, SeedableRng};
+
+use crate::Error;
+use crate::att::AttErrorCode;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::security_manager::crypto::{Sha256, d, e};
+use crate::security_manager::ecdh::{PublicKey, SecretKey};
+use crate::types::uuid::Uuid;
+
+/// UUID of the Fast Pair service, in the order of its service data.
+pub const FAST_PAIR_SERVICE_DATA_UUID: [u8; 2] = [0x2c, 0xfe];
+
+/// UUID of the Fast Pair service.
+pub const FAST_PAIR_SERVICE: Uuid = Uuid::new_short(0xfe2c);
+
+/// UUID of the model ID characteristic.
+pub const FAST_PAIR_MODEL_ID: Uuid = Uuid::new_long(0xfe2c1233_8366_4814_8eb0_01de32100bea_u128.to_le_bytes());
+
+/// UUID of the key-based pairing characteristic.
+pub const FAST_PAIR_KEY_BASED_PAIRING: Uuid = Uuid::new_long(0xfe2c1234_8366_4814_8eb0_01de32100bea_u128.to_le_bytes());
+
+/// UUID of the passkey characteristic.
+pub const FAST_PAIR_PASSKEY: Uuid = Uuid::new_long(0xfe2c1235_8366_4814_8eb0_01de32100bea_u128.to_le_bytes());
+
+/// UUID of the account key characteristic.
+pub const FAST_PAIR_ACCOUNT_KEY: Uuid = Uuid::new_long(0xfe2c1236_8366_4814_8eb0_01de32100bea_u128.to_le_bytes());
+
+/// Number of attributes added by the Fast Pair service.
+///
+/// The service, the declaration and value of the model ID and account key, and the declaration,
+/// value and CCCD of the key-based pairing and passkey.
+pub const FAST_PAIR_ATTRIBUTE_COUNT: usize = 1 + 2 * 2 + 2 * 3;
+
+/// Error of a write rejected, such as a request no key decrypts.
+pub const ERROR_REQUEST_REJECTED: AttErrorCode = AttErrorCode::WRITE_REQUEST_REJECTED;
+
+/// Flag of a key-based pairing request for the provider to initiate the bonding.
+pub const FLAG_INITIATE_BONDING: u8 = 0x40;
+/// Flag of a key-based pairing request for the provider to notify its name.
+pub const FLAG_NOTIFY_NAME: u8 = 0x20;
+/// Flag of a key-based pairing request to write an account key to an existing bond.
+pub const FLAG_RETROACTIVE_ACCOUNT_KEY: u8 = 0x10;
+
+const MESSAGE_KEY_BASED_PAIRING_REQUEST: u8 = 0x00;
+const MESSAGE_KEY_BASED_PAIRING_RESPONSE: u8 = 0x01;
+const MESSAGE_SEEKER_PASSKEY: u8 = 0x02;
+const MESSAGE_PROVIDER_PASSKEY: u8 = 0x03;
+const ACCOUNT_KEY_TYPE: u8 = 0x04;
+
+// Length of a request with the public key of the seeker.
+const PUBLIC_KEY_LEN: usize = 64;
+
+// Failed key-based pairing requests after which the requests are ignored, until reset.
+const MAX_FAILURES: u8 = 10;
+
+/// An account key, written by a seeker and shared by the devices of its account.
+pub type AccountKey = [u8; 16];
+
+/// Store of the account keys of the provider.
+///
+/// The store keeps at least 5 keys, replacing the least recently used one once full.
+pub trait AccountKeyStore {
+    /// Number of keys.
+    fn count(&self) -> usize;
+
+    /// A key of the store.
+    fn key(&self, index: usize) -> Option<AccountKey>;
+
+    /// Add a key written by a seeker.
+    fn add(&mut self, key: AccountKey) -> Result<(), Error>;
+}
+
+/// Service data advertised in pairing mode, with the model ID of the provider.
+pub const fn model_id_service_data(model_id: u32) -> [u8; 3] {
+    let [_, m0, m1, m2] = model_id.to_be_bytes();
+    [m0, m1, m2]
+}
+
+/// Length of the service data advertised outside pairing mode, with a number of account keys.
+pub const fn account_key_service_data_len(keys: usize) -> usize {
+    if keys == 0 { 2 } else { 1 + 1 + filter_len(keys) + 2 }
+}
+
+const fn filter_len(keys: usize) -> usize {
+    keys * 6 / 5 + 3
+}
+
+/// Configuration of the Fast Pair service.
+#[derive(Debug, Clone, Copy)]
+pub struct FastPairConfig {
+    /// Model ID of the provider, registered with Google.
+    pub model_id: u32,
+    /// Public address of the provider, checked in the requests and sent in the responses.
+    pub address: BdAddr,
+}
+
+/// Storage of the values of the characteristics of a [`FastPairServer`].
+pub struct FastPairStorage {
+    model_id: [u8; 3],
+    key_based_pairing: [u8; 16 + PUBLIC_KEY_LEN],
+    passkey: [u8; 16],
+    account_key: [u8; 16],
+}
+
+impl FastPairStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            model_id: [0; 3],
+            key_based_pairing: [0; 16 + PUBLIC_KEY_LEN],
+            passkey: [0; 16],
+            account_key: [0; 16],
+        }
+    }
+}
+
+impl Default for FastPairStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A write of the seeker, handled by [`FastPairServer::process`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FastPairEvent {
+    /// The seeker passed the key-based pairing handshake, the response being notified with
+    /// [`FastPairServer::respond`].
+    KeyBasedPairing {
+        /// Flags of the request, such as [`FLAG_INITIATE_BONDING`].
+        flags: u8,
+        /// Address of the seeker, to bond with if requested.
+        seeker_address: Option<[u8; 6]>,
+    },
+    /// The seeker wrote the passkey of the pairing, to compare with the passkey displayed by the
+    /// pairing before sending the passkey of the provider with [`FastPairServer::send_passkey`].
+    Passkey(u32),
+    /// The seeker wrote an account key over the encrypted link, added to the store.
+    AccountKey,
+}
+
+/// Fast Pair service server.
+///
+/// The writes of the seeker are handled with [`FastPairServer::process`] before being accepted,
+/// and the handshake is reset with [`FastPairServer::reset`] once the seeker disconnects.
+pub struct FastPairServer<S: AccountKeyStore> {
+    config: FastPairConfig,
+    key_based_pairing: Characteristic<Vec<u8, { 16 + PUBLIC_KEY_LEN }>>,
+    passkey: Characteristic<[u8; 16]>,
+    account_key: Characteristic<[u8; 16]>,
+    anti_spoofing_key: SecretKey,
+    rng: ChaCha12Rng,
+    store: S,
+    pairing_mode: bool,
+    // Key shared with the seeker once the handshake passed.
+    shared_key: Option<[u8; 16]>,
+    failures: u8,
+    response: Option<[u8; 16]>,
+}
+
+impl<S: AccountKeyStore> FastPairServer<S> {
+    /// Add the service to the attribute table.
+    ///
+    /// The anti-spoofing private key of the model, registered with Google, is the P-256 scalar
+    /// most significant octet first, and the random generator is seeded from `rng`, which must
+    /// be cryptographically secure. Fails with [`Error::InvalidValue`] if the key is not a valid
+    /// scalar.
+    pub fn build<'d, M: RawMutex, const MAX: usize, R: RngCore + CryptoRng>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut FastPairStorage,
+        config: FastPairConfig,
+        anti_spoofing_key: &[u8; 32],
+        rng: &mut R,
+        store: S,
+    ) -> Result<Self, Error> {
+        let anti_spoofing_key = SecretKey::new(anti_spoofing_key).ok_or(Error::InvalidValue)?;
+        let mut seed = [0; 32];
+        rng.fill_bytes(&mut seed);
+        let FastPairStorage {
+            model_id,
+            key_based_pairing,
+            passkey,
+            account_key,
+        } = storage;
+        *model_id = model_id_service_data(config.model_id);
+        let mut service = table.add_service(Service::new(FAST_PAIR_SERVICE));
+        service.add_characteristic_ro(FAST_PAIR_MODEL_ID, &*model_id).build();
+        let key_based_pairing = service
+            .add_characteristic(
+                FAST_PAIR_KEY_BASED_PAIRING,
+                &[CharacteristicProp::Write, CharacteristicProp::Notify],
+                Vec::new(),
+                key_based_pairing,
+            )
+            .build();
+        let passkey = service
+            .add_characteristic(
+                FAST_PAIR_PASSKEY,
+                &[CharacteristicProp::Write, CharacteristicProp::Notify],
+                [0; 16],
+                passkey,
+            )
+            .build();
+        let account_key = service
+            .add_characteristic(
+                FAST_PAIR_ACCOUNT_KEY,
+                &[CharacteristicProp::Write],
+                [0; 16],
+                account_key,
+            )
+            .build();
+        service.build();
+        Ok(Self {
+            config,
+            key_based_pairing,
+            passkey,
+            account_key,
+            anti_spoofing_key,
+            rng: ChaCha12Rng::from_seed(seed),
+            store,
+            pairing_mode: false,
+            shared_key: None,
+            failures: 0,
+            response: None,
+        })
+    }
+
+    /// The store of the account keys.
+    pub fn store(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    /// Enter or leave pairing mode, in which the seekers without an account key are accepted.
+    ///
+    /// The provider advertises [`model_id_service_data`] in pairing mode, and
+    /// [`FastPairServer::account_key_service_data`] otherwise.
+    pub fn set_pairing_mode(&mut self, pairing_mode: bool) {
+        self.pairing_mode = pairing_mode;
+    }
+
+    /// Reset the handshake, once the seeker disconnected.
+    pub fn reset(&mut self) {
+        self.shared_key = None;
+        self.response = None;
+    }
+
+    /// Accept requests again after too many failed ones, typically a few minutes later.
+    pub fn reset_failures(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Write the service data advertised outside pairing mode, with the filter of the account
+    /// keys and a random salt, returning the data written.
+    ///
+    /// The buffer holds at least [`account_key_service_data_len`] octets.
+    pub fn account_key_service_data<'b>(&mut self, buf: &'b mut [u8]) -> Result<&'b [u8], Error> {
+        let salt = self.rng.next_u32() as u8;
+        account_key_service_data(&self.store, salt, buf)
+    }
+
+    /// Handle a write of the seeker to an attribute, returning `None` if it is not one of the service.
+    ///
+    /// The write is accepted when the event is returned, and rejected with the error otherwise.
+    pub fn process(
+        &mut self,
+        connection: &Connection<'_>,
+        handle: u16,
+        data: &[u8],
+    ) -> Result<Option<FastPairEvent>, AttErrorCode> {
+        let event = if handle == self.key_based_pairing.handle {
+            self.key_based_pairing(data)?
+        } else if handle == self.passkey.handle {
+            let block = self.decrypt(data)?;
+            if block[0] != MESSAGE_SEEKER_PASSKEY {
+                return Err(ERROR_REQUEST_REJECTED);
+            }
+            FastPairEvent::Passkey(u32::from_be_bytes([0, block[1], block[2], block[3]]))
+        } else if handle == self.account_key.handle {
+            if !connection.security_level().encrypted() {
+                return Err(AttErrorCode::INSUFFICIENT_ENCRYPTION);
+            }
+            let key = self.decrypt(data)?;
+            if key[0] != ACCOUNT_KEY_TYPE {
+                return Err(ERROR_REQUEST_REJECTED);
+            }
+            self.store.add(key).map_err(|_| AttErrorCode::INSUFFICIENT_RESOURCES)?;
+            FastPairEvent::AccountKey
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(event))
+    }
+
+    /// Notify the response of the key-based pairing handshake, once the request accepted.
+    pub async fn respond<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+    ) -> Result<(), Error> {
+        let Some(response) = self.response.take() else {
+            return Ok(());
+        };
+        let response = unwrap!(Vec::from_slice(&response).ok());
+        self.key_based_pairing.notify(server, connection, &response).await
+    }
+
+    /// Notify the passkey of the provider, displayed by the pairing, to the seeker.
+    pub async fn send_passkey<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        passkey: u32,
+    ) -> Result<(), Error> {
+        let key = self.shared_key.ok_or(Error::NotFound)?;
+        let [_, p0, p1, p2] = passkey.to_be_bytes();
+        let mut block = [0; 16];
+        block[..4].copy_from_slice(&[MESSAGE_PROVIDER_PASSKEY, p0, p1, p2]);
+        self.rng.fill_bytes(&mut block[4..]);
+        self.passkey.notify(server, connection, &encrypt(&key, block)).await
+    }
+
+    fn key_based_pairing(&mut self, data: &[u8]) -> Result<FastPairEvent, AttErrorCode> {
+        if self.failures >= MAX_FAILURES {
+            return Err(ERROR_REQUEST_REJECTED);
+        }
+        let (encrypted, public_key) = match data.len() {
+            16 => (data, None),
+            len if len == 16 + PUBLIC_KEY_LEN => {
+                let (encrypted, public_key) = data.split_at(16);
+                (
+                    encrypted,
+                    Some(unwrap!(<&[u8; PUBLIC_KEY_LEN]>::try_from(public_key).ok())),
+                )
+            }
+            _ => return Err(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH),
+        };
+        let encrypted: [u8; 16] = unwrap!(encrypted.try_into().ok());
+        let accepted = match public_key {
+            // The anti-spoofing key is only accepted in pairing mode.
+            Some(public_key) if self.pairing_mode => self
+                .shared_anti_spoofing_key(public_key)
+                .and_then(|key| self.decrypt_request(&key, encrypted).map(|request| (key, request))),
+            Some(_) => None,
+            None => (0..self.store.count())
+                .filter_map(|i| self.store.key(i))
+                .find_map(|key| self.decrypt_request(&key, encrypted).map(|request| (key, request))),
+        };
+        let Some((key, request)) = accepted else {
+            self.failures += 1;
+            return Err(ERROR_REQUEST_REJECTED);
+        };
+        let flags = request[1];
+        let seeker_address = (flags & FLAG_INITIATE_BONDING != 0).then(|| unwrap!(request[8..14].try_into().ok()));
+
+        let mut response = [0; 16];
+        response[0] = MESSAGE_KEY_BASED_PAIRING_RESPONSE;
+        response[1..7].copy_from_slice(&address_bytes(&self.config.address));
+        self.rng.fill_bytes(&mut response[7..]);
+        self.shared_key = Some(key);
+        self.response = Some(encrypt(&key, response));
+        Ok(FastPairEvent::KeyBasedPairing { flags, seeker_address })
+    }
+
+    // The anti-spoofing key shared with a seeker, the first 16 octets of the SHA-256 hash of the
+    // ECDH secret of its public key, none if the key is not a point of the curve.
+    fn shared_anti_spoofing_key(&self, public_key: &[u8; PUBLIC_KEY_LEN]) -> Option<[u8; 16]> {
+        let (x, y) = public_key.split_at(32);
+        let public_key = PublicKey::new(unwrap!(x.try_into().ok()), unwrap!(y.try_into().ok()))?;
+        let secret = self.anti_spoofing_key.dh_key(&public_key)?;
+        let mut sha = Sha256::new();
+        sha.update(&secret);
+        sha.finalize()[..16].try_into().ok()
+    }
+
+    // The request decrypted with a key, if it is one of the provider.
+    fn decrypt_request(&self, key: &[u8; 16], block: [u8; 16]) -> Option<[u8; 16]> {
+        let block = decrypt(key, block);
+        let valid = block[0] == MESSAGE_KEY_BASED_PAIRING_REQUEST && block[2..8] == address_bytes(&self.config.address);
+        valid.then_some(block)
+    }
+
+    fn decrypt(&self, data: &[u8]) -> Result<[u8; 16], AttErrorCode> {
+        let key = self.shared_key.ok_or(ERROR_REQUEST_REJECTED)?;
+        let block: [u8; 16] = data
+            .try_into()
+            .map_err(|_| AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH)?;
+        Ok(decrypt(&key, block))
+    }
+}
+
+// The keys and blocks are AES-128 keys and blocks, the first octet the most significant.
+fn encrypt(key: &[u8; 16], block: [u8; 16]) -> [u8; 16] {
+    e(u128::from_be_bytes(*key), u128::from_be_bytes(block)).to_be_bytes()
+}
+
+fn decrypt(key: &[u8; 16], block: [u8; 16]) -> [u8; 16] {
+    d(u128::from_be_bytes(*key), u128::from_be_bytes(block)).to_be_bytes()
+}
+
+// The address, most significant octet first as in the messages.
+fn address_bytes(address: &BdAddr) -> [u8; 6] {
+    let mut bytes: [u8; 6] = unwrap!(address.raw().try_into().ok());
+    bytes.reverse();
+    bytes
+}
+
+fn account_key_service_data<'b, S: AccountKeyStore>(store: &S, salt: u8, buf: &'b mut [u8]) -> Result<&'b [u8], Error> {
+    let count = store.count();
+    let len = account_key_service_data_len(count);
+    let buf = buf.get_mut(..len).ok_or(Error::InsufficientSpace)?;
+    buf.fill(0);
+    if count == 0 {
+        return Ok(buf);
+    }
+    let filter_len = filter_len(count);
+    // The version and flags, then the filter and the salt, each after its length and type.
+    buf[1] = (filter_len as u8) << 4;
+    buf[2 + filter_len] = 0x11;
+    buf[3 + filter_len] = salt;
+    let filter = &mut buf[2..2 + filter_len];
+    for key in (0..count).filter_map(|i| store.key(i)) {
+        let mut sha = Sha256::new();
+        sha.update(&key).update(&[salt]);
+        let hash = sha.finalize();
+        for word in hash.chunks_exact(4) {
+            let bit = u32::from_be_bytes([word[0], word[1], word[2], word[3]]) as usize % (filter_len * 8);
+            filter[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::connection::SecurityLevel;
+    use crate::mock_client::MockClient;
+
+    #[derive(Default)]
+    struct Store(heapless::Vec<AccountKey, 5>);
+
+    impl AccountKeyStore for Store {
+        fn count(&self) -> usize {
+            self.0.len()
+        }
+
+        fn key(&self, index: usize) -> Option<AccountKey> {
+            self.0.get(index).copied()
+        }
+
+        fn add(&mut self, key: AccountKey) -> Result<(), Error> {
+            self.0.push(key).map_err(|_| Error::InsufficientSpace)
+        }
+    }
+
+    #[test]
+    fn key_based_pairing_handshake_over_att() {
+        let config = FastPairConfig {
+            model_id: 0x123456,
+            address: BdAddr::new([0x66, 0x55, 0x44, 0x33, 0x22, 0x11]),
+        };
+        let anti_spoofing_key = [0x11; 32];
+        let mut rng = ChaCha12Rng::from_seed([0; 32]);
+        let mut storage = FastPairStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, FAST_PAIR_ATTRIBUTE_COUNT> = AttributeTable::new();
+        let mut fp = unwrap!(FastPairServer::build(
+            &mut table,
+            &mut storage,
+            config,
+            &anti_spoofing_key,
+            &mut rng,
+            Store::default()
+        ));
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+
+        // The seeker derives the key from its own key pair and the public key of the model.
+        let seeker = unwrap!(SecretKey::new(&[0x22; 32]));
+        let model = unwrap!(SecretKey::new(&anti_spoofing_key)).public_key();
+        let mut sha = Sha256::new();
+        sha.update(&unwrap!(seeker.dh_key(&model)));
+        let key: [u8; 16] = unwrap!(sha.finalize()[..16].try_into().ok());
+        let (handle, cccd) = (fp.key_based_pairing.handle, unwrap!(fp.key_based_pairing.cccd_handle));
+        let passkey_handle = fp.passkey.handle;
+        let account_key_handle = fp.account_key.handle;
+        let write = |fp: &mut FastPairServer<Store>, handle, data: &[u8]| {
+            let mut event = None;
+            client
+                .write(&server, handle, data, |handle, data| {
+                    event = fp.process(client.connection(), handle, data)?;
+                    Ok(())
+                })
+                .map(|_| unwrap!(event))
+        };
+
+        let (_, model_id) = unwrap!(client.read_by_type(&server, FAST_PAIR_MODEL_ID));
+        assert_eq!(&model_id[..], &[0x12, 0x34, 0x56]);
+        unwrap!(client.subscribe(&server, cccd, 0x0001));
+
+        let mut request = [0; 16 + PUBLIC_KEY_LEN];
+        request[..8].copy_from_slice(&[0x00, FLAG_INITIATE_BONDING, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        request[8..14].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        let plaintext: [u8; 16] = request[..16].try_into().unwrap();
+        request[..16].copy_from_slice(&encrypt(&key, plaintext));
+        let public_key = seeker.public_key();
+        request[16..48].copy_from_slice(&public_key.x);
+        request[48..].copy_from_slice(&public_key.y);
+
+        // The anti-spoofing key is only accepted in pairing mode.
+        assert_eq!(write(&mut fp, handle, &request), Err(ERROR_REQUEST_REJECTED));
+        fp.set_pairing_mode(true);
+        assert_eq!(
+            write(&mut fp, handle, &request),
+            Ok(FastPairEvent::KeyBasedPairing {
+                flags: FLAG_INITIATE_BONDING,
+                seeker_address: Some([1, 2, 3, 4, 5, 6])
+            })
+        );
+        unwrap!(block_on(fp.respond(&server, client.connection())));
+        let (notified, response) = unwrap!(client.notified());
+        assert_eq!(notified, handle);
+        let response = decrypt(&key, unwrap!(response[..].try_into()));
+        assert_eq!(response[..7], [0x01, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+
+        let mut passkey = [0; 16];
+        passkey[..4].copy_from_slice(&[MESSAGE_SEEKER_PASSKEY, 0x01, 0xe2, 0x40]);
+        assert_eq!(
+            write(&mut fp, passkey_handle, &encrypt(&key, passkey)),
+            Ok(FastPairEvent::Passkey(123456))
+        );
+
+        // The account key is only written once the seeker paired.
+        let mut account_key = [0x77; 16];
+        account_key[0] = ACCOUNT_KEY_TYPE;
+        assert_eq!(
+            write(&mut fp, account_key_handle, &encrypt(&key, account_key)),
+            Err(AttErrorCode::INSUFFICIENT_ENCRYPTION)
+        );
+        client.encrypt(SecurityLevel::Encrypted);
+        assert_eq!(
+            write(&mut fp, account_key_handle, &encrypt(&key, account_key)),
+            Ok(FastPairEvent::AccountKey)
+        );
+        assert_eq!(fp.store().key(0), Some(account_key));
+
+        // The account key is then accepted outside pairing mode.
+        fp.reset();
+        fp.set_pairing_mode(false);
+        assert!(write(&mut fp, handle, &encrypt(&account_key, plaintext)).is_ok());
+    }
+
+    #[test]
+    fn account_key_filter() {
+        let mut buf = [0xff; 16];
+        let data = account_key_service_data(&Store::default(), 0x42, &mut buf).unwrap();
+        assert_eq!(data, &[0x00, 0x00]);
+
+        let mut store = Store::default();
+        // The example of the specification.
+        store
+            .add([
+                0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0x00, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+            ])
+            .unwrap();
+        let data = account_key_service_data(&store, 0xc7, &mut buf).unwrap();
+        assert_eq!(data, &[0x00, 0x40, 0x0a, 0x42, 0x88, 0x10, 0x11, 0xc7]);
+        assert_eq!(data.len(), account_key_service_data_len(1));
+    }
+}
@@ -0,0 +1,554 @@
+//! Glucose Service, giving access to the measurements stored by a glucose meter.
+//!
+//! The meter keeps its measurements in a [`GlucoseStore`], each with a sequence number and the
+//! time it was taken, optionally with its context such as the meal it follows. The client
+//! retrieves them through the [Record Access Control Point](super::record_access), each
+//! measurement being notified followed by its context.
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+
+use super::current_time::ExactTime;
+use super::ieee11073::SFloat;
+use super::record_access::{RecordAccess, RecordAccessEvent, RecordAccessStorage, RecordStore};
+use crate::Error;
+use crate::att::AttErrorCode;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::cursor::WriteCursor;
+
+const FLAG_TIME_OFFSET: u8 = 0x01;
+const FLAG_CONCENTRATION: u8 = 0x02;
+const FLAG_MOL_PER_L: u8 = 0x04;
+const FLAG_SENSOR_STATUS: u8 = 0x08;
+const FLAG_CONTEXT_FOLLOWS: u8 = 0x10;
+
+const CONTEXT_FLAG_CARBOHYDRATE: u8 = 0x01;
+const CONTEXT_FLAG_MEAL: u8 = 0x02;
+const CONTEXT_FLAG_HBA1C: u8 = 0x40;
+
+const MEASUREMENT_LEN: usize = 17;
+const CONTEXT_LEN: usize = 9;
+
+/// Unit of a glucose concentration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum GlucoseUnit {
+    /// Kilograms per liter, usually with an exponent of -5 for mg/dL.
+    KgPerL,
+    /// Moles per liter, usually with an exponent of -3 for mmol/L.
+    MolPerL,
+}
+
+/// Type of a sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum SampleType {
+    /// Capillary whole blood.
+    CapillaryWholeBlood = 1,
+    /// Capillary plasma.
+    CapillaryPlasma = 2,
+    /// Venous whole blood.
+    VenousWholeBlood = 3,
+    /// Venous plasma.
+    VenousPlasma = 4,
+    /// Arterial whole blood.
+    ArterialWholeBlood = 5,
+    /// Arterial plasma.
+    ArterialPlasma = 6,
+    /// Undetermined whole blood.
+    UndeterminedWholeBlood = 7,
+    /// Undetermined plasma.
+    UndeterminedPlasma = 8,
+    /// Interstitial fluid.
+    InterstitialFluid = 9,
+    /// Control solution.
+    ControlSolution = 10,
+}
+
+/// Location of a sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum SampleLocation {
+    /// A finger.
+    Finger = 1,
+    /// An alternate site test, such as the forearm.
+    AlternateSiteTest = 2,
+    /// An earlobe.
+    Earlobe = 3,
+    /// A control solution.
+    ControlSolution = 4,
+    /// The location is not available.
+    NotAvailable = 15,
+}
+
+/// A glucose concentration, with its sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GlucoseConcentration {
+    /// Concentration, in the unit.
+    pub value: SFloat,
+    /// Unit of the concentration.
+    pub unit: GlucoseUnit,
+    /// Type of the sample.
+    pub sample_type: SampleType,
+    /// Location of the sample.
+    pub location: SampleLocation,
+}
+
+/// A glucose measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GlucoseMeasurement {
+    /// Sequence number of the measurement.
+    pub sequence_number: u16,
+    /// Time of the measurement, the day of the week and the fractions of the second not being sent.
+    pub base_time: ExactTime,
+    /// Offset of the time of the measurement from its base time, in minutes.
+    pub time_offset: Option<i16>,
+    /// Concentration measured, if any.
+    pub concentration: Option<GlucoseConcentration>,
+    /// Status of the sensor when measuring, such as 0x0001 for a low battery.
+    pub sensor_status: Option<u16>,
+}
+
+impl GlucoseMeasurement {
+    /// Encode the measurement, with whether its context follows, returning the length written.
+    pub fn encode(&self, context_follows: bool, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut flags = 0;
+        if self.time_offset.is_some() {
+            flags |= FLAG_TIME_OFFSET;
+        }
+        if let Some(concentration) = &self.concentration {
+            flags |= FLAG_CONCENTRATION;
+            if concentration.unit == GlucoseUnit::MolPerL {
+                flags |= FLAG_MOL_PER_L;
+            }
+        }
+        if self.sensor_status.is_some() {
+            flags |= FLAG_SENSOR_STATUS;
+        }
+        if context_follows {
+            flags |= FLAG_CONTEXT_FOLLOWS;
+        }
+        let time = &self.base_time;
+        let mut w = WriteCursor::new(buf);
+        w.write(flags)?;
+        w.write(self.sequence_number)?;
+        w.write(time.year)?;
+        w.append(&[time.month, time.day, time.hours, time.minutes, time.seconds])?;
+        if let Some(offset) = self.time_offset {
+            w.write(offset as u16)?;
+        }
+        if let Some(concentration) = &self.concentration {
+            w.write(concentration.value.to_bits())?;
+            w.write(concentration.sample_type as u8 | ((concentration.location as u8) << 4))?;
+        }
+        if let Some(status) = self.sensor_status {
+            w.write(status)?;
+        }
+        Ok(w.len())
+    }
+}
+
+/// Meal a measurement relates to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Meal {
+    /// Before a meal.
+    Preprandial = 1,
+    /// After a meal.
+    Postprandial = 2,
+    /// While fasting.
+    Fasting = 3,
+    /// A snack or a drink.
+    Casual = 4,
+    /// At bedtime.
+    Bedtime = 5,
+}
+
+/// Context of a glucose measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GlucoseContext {
+    /// Carbohydrates taken, with their identifier such as 1 for a breakfast, in kilograms.
+    pub carbohydrate: Option<(u8, SFloat)>,
+    /// Meal the measurement relates to.
+    pub meal: Option<Meal>,
+    /// Glycated haemoglobin, in percents.
+    pub hba1c: Option<SFloat>,
+}
+
+impl GlucoseContext {
+    /// Encode the context of a measurement, returning the length written.
+    pub fn encode(&self, sequence_number: u16, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut flags = 0;
+        if self.carbohydrate.is_some() {
+            flags |= CONTEXT_FLAG_CARBOHYDRATE;
+        }
+        if self.meal.is_some() {
+            flags |= CONTEXT_FLAG_MEAL;
+        }
+        if self.hba1c.is_some() {
+            flags |= CONTEXT_FLAG_HBA1C;
+        }
+        let mut w = WriteCursor::new(buf);
+        w.write(flags)?;
+        w.write(sequence_number)?;
+        if let Some((id, value)) = self.carbohydrate {
+            w.write(id)?;
+            w.write(value.to_bits())?;
+        }
+        if let Some(meal) = self.meal {
+            w.write(meal as u8)?;
+        }
+        if let Some(hba1c) = self.hba1c {
+            w.write(hba1c.to_bits())?;
+        }
+        Ok(w.len())
+    }
+}
+
+/// Store of the measurements of a [`GlucoseServer`].
+pub trait GlucoseStore: RecordStore {
+    /// A measurement stored.
+    fn measurement(&self, index: usize) -> GlucoseMeasurement;
+
+    /// The context of a measurement stored, if any.
+    fn context(&self, _index: usize) -> Option<GlucoseContext> {
+        None
+    }
+}
+
+/// Features of a glucose meter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GlucoseFeatures(u16);
+
+impl GlucoseFeatures {
+    /// The meter detects a low battery.
+    pub const LOW_BATTERY_DETECTION: Self = Self(0x0001);
+    /// The meter detects a malfunction of its sensor.
+    pub const SENSOR_MALFUNCTION_DETECTION: Self = Self(0x0002);
+    /// The meter detects a sample too small.
+    pub const SENSOR_SAMPLE_SIZE: Self = Self(0x0004);
+    /// The meter detects an error of insertion of the strip.
+    pub const STRIP_INSERTION_ERROR_DETECTION: Self = Self(0x0008);
+    /// The meter detects a wrong type of strip.
+    pub const STRIP_TYPE_ERROR_DETECTION: Self = Self(0x0010);
+    /// The meter detects a result out of its range.
+    pub const RESULT_HIGH_LOW_DETECTION: Self = Self(0x0020);
+    /// The meter detects a temperature out of its range.
+    pub const TEMPERATURE_HIGH_LOW_DETECTION: Self = Self(0x0040);
+    /// The meter detects a measurement interrupted.
+    pub const READ_INTERRUPT_DETECTION: Self = Self(0x0080);
+    /// The meter detects a fault of the device.
+    pub const GENERAL_DEVICE_FAULT: Self = Self(0x0100);
+    /// The meter detects a fault of its time.
+    pub const TIME_FAULT: Self = Self(0x0200);
+    /// The meter is bonded with several clients.
+    pub const MULTIPLE_BOND: Self = Self(0x0400);
+}
+
+bitfield_set!(GlucoseFeatures: u16, "features");
+
+/// Configuration of the Glucose Service.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GlucoseConfig {
+    /// Features of the meter.
+    pub features: GlucoseFeatures,
+    /// Whether the measurements may have a context.
+    pub context: bool,
+}
+
+impl GlucoseConfig {
+    /// Number of attributes added by the service.
+    pub const fn attribute_count(&self) -> usize {
+        // The service, the declaration, value and CCCD of the measurement and of the control
+        // point, and the feature.
+        let mut count = 1 + 3 + 3 + 2;
+        if self.context {
+            count += 3;
+        }
+        count
+    }
+}
+
+/// Storage of the values of the characteristics of a [`GlucoseServer`].
+pub struct GlucoseStorage {
+    measurement: [u8; MEASUREMENT_LEN],
+    context: [u8; CONTEXT_LEN],
+    features: [u8; 2],
+    record_access: RecordAccessStorage,
+}
+
+impl GlucoseStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            measurement: [0; MEASUREMENT_LEN],
+            context: [0; CONTEXT_LEN],
+            features: [0; 2],
+            record_access: RecordAccessStorage::new(),
+        }
+    }
+}
+
+impl Default for GlucoseStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Glucose Service server.
+///
+/// The writes to the control point are handled with [`GlucoseServer::process`] before being
+/// accepted, after which [`GlucoseServer::respond`] is called until it returns `false`,
+/// notifying a measurement of a report at a time before indicating the response. The other
+/// writes are handled between the calls, so that the client can abort a report. A new
+/// measurement is notified with [`GlucoseServer::notify_record`].
+pub struct GlucoseServer<S: GlucoseStore> {
+    measurement: Characteristic<Vec<u8, MEASUREMENT_LEN>>,
+    context: Option<Characteristic<Vec<u8, CONTEXT_LEN>>>,
+    record_access: RecordAccess,
+    store: S,
+}
+
+impl<S: GlucoseStore> GlucoseServer<S> {
+    /// Add the service to the attribute table.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut GlucoseStorage,
+        config: GlucoseConfig,
+        store: S,
+    ) -> Self {
+        let GlucoseStorage {
+            measurement,
+            context,
+            features,
+            record_access,
+        } = storage;
+        *features = config.features.bits().to_le_bytes();
+        let mut service = table.add_service(Service::new(service::GLUCOSE));
+        let measurement = service
+            .add_characteristic(
+                characteristic::GLUCOSE_MEASUREMENT,
+                &[CharacteristicProp::Notify],
+                Vec::new(),
+                measurement,
+            )
+            .build();
+        let context = config.context.then(|| {
+            service
+                .add_characteristic(
+                    characteristic::GLUCOSE_MEASUREMENT_CONTEXT,
+                    &[CharacteristicProp::Notify],
+                    Vec::new(),
+                    context,
+                )
+                .build()
+        });
+        service
+            .add_characteristic_ro(characteristic::GLUCOSE_FEATURE, &*features)
+            .build();
+        let record_access = RecordAccess::add(&mut service, record_access);
+        service.build();
+        Self {
+            measurement,
+            context,
+            record_access,
+            store,
+        }
+    }
+
+    /// The store of the measurements.
+    pub fn store(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    /// Handle a write of the client to an attribute, returning `None` if it is not one of the service.
+    ///
+    /// The write is accepted when the event is returned, and rejected with the error otherwise.
+    pub fn process<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        handle: u16,
+        data: &[u8],
+    ) -> Result<Option<RecordAccessEvent>, AttErrorCode> {
+        self.record_access
+            .process(server, connection, handle, data, &mut self.store)
+    }
+
+    /// Notify the next measurement of the report in progress, or indicate the response of the
+    /// procedure once there is none left.
+    ///
+    /// Returns whether the procedure is still in progress, to be called again.
+    pub async fn respond<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+    ) -> Result<bool, Error> {
+        if let Some(index) = self.record_access.next_record() {
+            if let Err(e) = self.notify_record(server, connection, index).await {
+                self.record_access.fail();
+                return Err(e);
+            }
+            return Ok(true);
+        }
+        self.record_access.respond(server, connection).await?;
+        Ok(false)
+    }
+
+    /// Notify a measurement stored to the client, followed by its context if any.
+    pub async fn notify_record<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        index: usize,
+    ) -> Result<(), Error> {
+        let measurement = self.store.measurement(index);
+        let context = self.context.as_ref().zip(self.store.context(index));
+        let mut buf = [0; MEASUREMENT_LEN];
+        let len = measurement.encode(context.is_some(), &mut buf)?;
+        let value = unwrap!(Vec::from_slice(&buf[..len]).ok());
+        self.measurement.notify(server, connection, &value).await?;
+        if let Some((characteristic, context)) = context {
+            let mut buf = [0; CONTEXT_LEN];
+            let len = context.encode(measurement.sequence_number, &mut buf)?;
+            let value = unwrap!(Vec::from_slice(&buf[..len]).ok());
+            characteristic.notify(server, connection, &value).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ops::Range;
+
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+
+    // Measurements with the sequence numbers from 1.
+    struct Meter(usize);
+
+    impl RecordStore for Meter {
+        fn count(&self) -> usize {
+            self.0
+        }
+
+        fn sequence_number(&self, index: usize) -> u16 {
+            index as u16 + 1
+        }
+
+        fn delete(&mut self, _records: Range<usize>) -> Result<(), Error> {
+            Err(Error::NotSupported)
+        }
+    }
+
+    impl GlucoseStore for Meter {
+        fn measurement(&self, index: usize) -> GlucoseMeasurement {
+            GlucoseMeasurement {
+                sequence_number: self.sequence_number(index),
+                base_time: ExactTime::default(),
+                time_offset: None,
+                concentration: None,
+                sensor_status: None,
+            }
+        }
+    }
+
+    #[test]
+    fn encode_measurements() {
+        let measurement = GlucoseMeasurement {
+            sequence_number: 7,
+            base_time: ExactTime {
+                year: 2024,
+                month: 3,
+                day: 14,
+                hours: 8,
+                minutes: 30,
+                seconds: 0,
+                ..Default::default()
+            },
+            time_offset: None,
+            concentration: Some(GlucoseConcentration {
+                value: SFloat::new(95, -5).unwrap(),
+                unit: GlucoseUnit::KgPerL,
+                sample_type: SampleType::CapillaryWholeBlood,
+                location: SampleLocation::Finger,
+            }),
+            sensor_status: None,
+        };
+        let mut buf = [0; MEASUREMENT_LEN];
+        let len = measurement.encode(true, &mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            &[0x12, 7, 0, 0xe8, 0x07, 3, 14, 8, 30, 0, 0x5f, 0xb0, 0x11]
+        );
+
+        let context = GlucoseContext {
+            meal: Some(Meal::Preprandial),
+            ..Default::default()
+        };
+        let mut buf = [0; CONTEXT_LEN];
+        let len = context.encode(7, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[0x02, 7, 0, 1]);
+    }
+
+    #[test]
+    fn client_reports_records() {
+        let config = GlucoseConfig::default();
+        let mut storage = GlucoseStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 9> = AttributeTable::new();
+        let mut gls = GlucoseServer::build(&mut table, &mut storage, config, Meter(3));
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+        let measurement = gls.measurement.handle;
+        let racp = gls.record_access.characteristic.handle;
+        client
+            .subscribe(&server, gls.measurement.cccd_handle.unwrap(), 0x0001)
+            .unwrap();
+        client
+            .subscribe(&server, gls.record_access.characteristic.cccd_handle.unwrap(), 0x0002)
+            .unwrap();
+
+        // Report the records from the second one.
+        let mut event = None;
+        client
+            .write(&server, racp, &[0x01, 0x03, 0x01, 2, 0], |handle, data| {
+                event = gls.process(&server, client.connection(), handle, data)?;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(event, Some(RecordAccessEvent::Report(1..3)));
+        while block_on(gls.respond(&server, client.connection())).unwrap() {}
+        for sequence_number in [2, 3] {
+            let notified = client.notified().unwrap();
+            assert_eq!(notified.0, measurement);
+            assert_eq!(notified.1[1..3], [sequence_number, 0]);
+        }
+        assert_eq!(
+            client.notified().unwrap(),
+            (racp, Vec::from_slice(&[0x06, 0x00, 0x01, 0x01]).unwrap())
+        );
+        client.confirm(&server);
+
+        // The store fails to delete the records.
+        client
+            .write(&server, racp, &[0x02, 0x01], |handle, data| {
+                gls.process(&server, client.connection(), handle, data).map(|_| ())
+            })
+            .unwrap();
+        assert!(!block_on(gls.respond(&server, client.connection())).unwrap());
+        assert_eq!(client.notified().unwrap().1, [0x06, 0x00, 0x02, 0x08]);
+    }
+}
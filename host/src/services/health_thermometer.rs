@@ -0,0 +1,456 @@
+//! Health Thermometer Service, indicating the temperatures measured by a thermometer.
+//!
+//! Each measurement is indicated, the thermometer waiting for the client to confirm it before
+//! sending the next one, and keeping it otherwise to send it later. A thermometer measuring
+//! periodically shares its measurement interval, which the client may change within the valid
+//! range of the thermometer.
+use bt_hci::uuid::{characteristic, descriptors, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+
+use super::current_time::ExactTime;
+use super::ieee11073::Float;
+use crate::Error;
+use crate::att::AttErrorCode;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::cursor::WriteCursor;
+
+const FLAG_FAHRENHEIT: u8 = 0x01;
+const FLAG_TIME_STAMP: u8 = 0x02;
+const FLAG_TEMPERATURE_TYPE: u8 = 0x04;
+
+const MEASUREMENT_LEN: usize = 13;
+
+/// Unit of a temperature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TemperatureUnit {
+    /// Degrees Celsius.
+    #[default]
+    Celsius,
+    /// Degrees Fahrenheit.
+    Fahrenheit,
+}
+
+/// Location of the measurement of a temperature on the body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum TemperatureType {
+    /// The armpit.
+    Armpit = 1,
+    /// The body in general.
+    Body = 2,
+    /// The ear, usually the ear lobe.
+    Ear = 3,
+    /// A finger.
+    Finger = 4,
+    /// The gastro-intestinal tract.
+    GastroIntestinalTract = 5,
+    /// The mouth.
+    Mouth = 6,
+    /// The rectum.
+    Rectum = 7,
+    /// A toe.
+    Toe = 8,
+    /// The ear drum.
+    Tympanum = 9,
+}
+
+/// A measurement of the temperature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TemperatureMeasurement {
+    /// Temperature measured.
+    pub temperature: Float,
+    /// Unit of the temperature.
+    pub unit: TemperatureUnit,
+    /// Time of the measurement, the day of the week and the fractions of the second not being sent.
+    pub time_stamp: Option<ExactTime>,
+    /// Location of the measurement, if not given by the service.
+    pub temperature_type: Option<TemperatureType>,
+}
+
+impl TemperatureMeasurement {
+    /// Encode the measurement, returning the length written.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut flags = 0;
+        if self.unit == TemperatureUnit::Fahrenheit {
+            flags |= FLAG_FAHRENHEIT;
+        }
+        if self.time_stamp.is_some() {
+            flags |= FLAG_TIME_STAMP;
+        }
+        if self.temperature_type.is_some() {
+            flags |= FLAG_TEMPERATURE_TYPE;
+        }
+        let mut w = WriteCursor::new(buf);
+        w.write(flags)?;
+        w.write(self.temperature.to_bits())?;
+        if let Some(time) = &self.time_stamp {
+            w.write(time.year)?;
+            w.append(&[time.month, time.day, time.hours, time.minutes, time.seconds])?;
+        }
+        if let Some(temperature_type) = self.temperature_type {
+            w.write(temperature_type as u8)?;
+        }
+        Ok(w.len())
+    }
+}
+
+/// Measurement interval of a thermometer measuring periodically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeasurementInterval {
+    /// Interval between the measurements, in seconds, or 0 if not measuring periodically.
+    pub interval: u16,
+    /// Range of the intervals the client can set, if it can change it.
+    pub valid_range: Option<(u16, u16)>,
+}
+
+/// Configuration of the Health Thermometer Service.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HealthThermometerConfig {
+    /// Location of the measurements, if always the same.
+    pub temperature_type: Option<TemperatureType>,
+    /// Whether the temperature is notified while being measured.
+    pub intermediate_temperature: bool,
+    /// Measurement interval, if measuring periodically.
+    pub measurement_interval: Option<MeasurementInterval>,
+}
+
+impl HealthThermometerConfig {
+    /// Number of attributes added by the service.
+    pub const fn attribute_count(&self) -> usize {
+        // The service, and the declaration, value and CCCD of the measurement.
+        let mut count = 1 + 3;
+        if self.temperature_type.is_some() {
+            count += 2;
+        }
+        if self.intermediate_temperature {
+            count += 3;
+        }
+        if let Some(interval) = &self.measurement_interval {
+            count += 3;
+            if interval.valid_range.is_some() {
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+/// Storage of the values of the characteristics of a [`HealthThermometerServer`].
+pub struct HealthThermometerStorage {
+    measurement: [u8; MEASUREMENT_LEN],
+    temperature_type: [u8; 1],
+    intermediate_temperature: [u8; MEASUREMENT_LEN],
+    measurement_interval: [u8; 2],
+    valid_range: [u8; 4],
+}
+
+impl HealthThermometerStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            measurement: [0; MEASUREMENT_LEN],
+            temperature_type: [0; 1],
+            intermediate_temperature: [0; MEASUREMENT_LEN],
+            measurement_interval: [0; 2],
+            valid_range: [0; 4],
+        }
+    }
+}
+
+impl Default for HealthThermometerStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A write of the client, handled by [`HealthThermometerServer::process`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HealthThermometerEvent {
+    /// The client changed the measurement interval, in seconds, 0 stopping the periodic measurements.
+    IntervalChanged(u16),
+}
+
+/// Health Thermometer Service server.
+///
+/// The measurements are indicated with [`HealthThermometerServer::indicate_measurement`], which
+/// waits for the client to confirm them, while the writes of the measurement interval are handled
+/// with [`HealthThermometerServer::process`] before being accepted.
+pub struct HealthThermometerServer {
+    measurement: Characteristic<Vec<u8, MEASUREMENT_LEN>>,
+    intermediate_temperature: Option<Characteristic<Vec<u8, MEASUREMENT_LEN>>>,
+    measurement_interval: Option<Characteristic<u16>>,
+    valid_range: Option<(u16, u16)>,
+}
+
+impl HealthThermometerServer {
+    /// Add the service to the attribute table.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut HealthThermometerStorage,
+        config: HealthThermometerConfig,
+    ) -> Self {
+        let HealthThermometerStorage {
+            measurement,
+            temperature_type,
+            intermediate_temperature,
+            measurement_interval,
+            valid_range,
+        } = storage;
+        let mut service = table.add_service(Service::new(service::HEALTH_THERMOMETER));
+        let measurement = service
+            .add_characteristic(
+                characteristic::TEMPERATURE_MEASUREMENT,
+                &[CharacteristicProp::Indicate],
+                Vec::new(),
+                measurement,
+            )
+            .build();
+        if let Some(kind) = config.temperature_type {
+            *temperature_type = [kind as u8];
+            service
+                .add_characteristic_ro(characteristic::TEMPERATURE_TYPE, &*temperature_type)
+                .build();
+        }
+        let intermediate_temperature = config.intermediate_temperature.then(|| {
+            service
+                .add_characteristic(
+                    characteristic::INTERMEDIATE_TEMPERATURE,
+                    &[CharacteristicProp::Notify],
+                    Vec::new(),
+                    intermediate_temperature,
+                )
+                .build()
+        });
+        let measurement_interval = config.measurement_interval.map(|interval| {
+            let props: &[CharacteristicProp] = if interval.valid_range.is_some() {
+                &[
+                    CharacteristicProp::Read,
+                    CharacteristicProp::Indicate,
+                    CharacteristicProp::Write,
+                ]
+            } else {
+                &[CharacteristicProp::Read, CharacteristicProp::Indicate]
+            };
+            let mut builder = service.add_characteristic(
+                characteristic::MEASUREMENT_INTERVAL,
+                props,
+                interval.interval,
+                measurement_interval,
+            );
+            if let Some((min, max)) = interval.valid_range {
+                valid_range[..2].copy_from_slice(&min.to_le_bytes());
+                valid_range[2..].copy_from_slice(&max.to_le_bytes());
+                builder.add_descriptor_ro::<[u8; 4], _>(descriptors::VALID_RANGE, &*valid_range);
+            }
+            builder.build()
+        });
+        service.build();
+        Self {
+            measurement,
+            intermediate_temperature,
+            measurement_interval,
+            valid_range: config.measurement_interval.and_then(|interval| interval.valid_range),
+        }
+    }
+
+    /// The measurement interval characteristic, if any.
+    pub fn measurement_interval(&self) -> Option<&Characteristic<u16>> {
+        self.measurement_interval.as_ref()
+    }
+
+    /// Handle a write of the client to an attribute, returning `None` if it is not one of the service.
+    ///
+    /// The write is accepted when the event is returned, and rejected with the error otherwise.
+    /// An interval out of the valid range is rejected with [`AttErrorCode::OUT_OF_RANGE`].
+    pub fn process(&self, handle: u16, data: &[u8]) -> Result<Option<HealthThermometerEvent>, AttErrorCode> {
+        if self.measurement_interval.as_ref().is_none_or(|c| c.handle != handle) {
+            return Ok(None);
+        }
+        let Some((min, max)) = self.valid_range else {
+            return Err(AttErrorCode::WRITE_NOT_PERMITTED);
+        };
+        let interval = match data {
+            [low, high] => u16::from_le_bytes([*low, *high]),
+            _ => return Err(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH),
+        };
+        if interval != 0 && !(min..=max).contains(&interval) {
+            return Err(AttErrorCode::OUT_OF_RANGE);
+        }
+        Ok(Some(HealthThermometerEvent::IntervalChanged(interval)))
+    }
+
+    /// Indicate a measurement to the client, if subscribed, and wait for its confirmation.
+    ///
+    /// Returns whether the client confirmed the measurement, the thermometer keeping it to indicate
+    /// it later otherwise.
+    pub async fn indicate_measurement<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        measurement: &TemperatureMeasurement,
+    ) -> Result<bool, Error> {
+        let value = encode(measurement)?;
+        self.measurement.indicate_confirmed(server, connection, &value).await
+    }
+
+    /// Notify the temperature being measured to the client, if subscribed.
+    ///
+    /// Returns [`Error::NotSupported`] if the intermediate temperature is not supported by the
+    /// service.
+    pub async fn notify_intermediate<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        measurement: &TemperatureMeasurement,
+    ) -> Result<(), Error> {
+        let characteristic = self.intermediate_temperature.as_ref().ok_or(Error::NotSupported)?;
+        let value = encode(measurement)?;
+        characteristic.notify(server, connection, &value).await
+    }
+
+    /// Change the measurement interval from the thermometer, indicating it to the client if
+    /// subscribed and waiting for its confirmation.
+    ///
+    /// Returns whether the client confirmed the interval, or [`Error::NotSupported`] if the
+    /// thermometer does not measure periodically.
+    pub async fn set_interval<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        interval: u16,
+    ) -> Result<bool, Error> {
+        let characteristic = self.measurement_interval.as_ref().ok_or(Error::NotSupported)?;
+        characteristic.indicate_confirmed(server, connection, &interval).await
+    }
+}
+
+fn encode(measurement: &TemperatureMeasurement) -> Result<Vec<u8, MEASUREMENT_LEN>, Error> {
+    let mut buf = [0; MEASUREMENT_LEN];
+    let len = measurement.encode(&mut buf)?;
+    Ok(unwrap!(Vec::from_slice(&buf[..len]).ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+
+    #[test]
+    fn encode_measurements() {
+        let mut buf = [0; MEASUREMENT_LEN];
+        let measurement = TemperatureMeasurement {
+            temperature: Float::new(3652, -2).unwrap(),
+            unit: TemperatureUnit::Celsius,
+            time_stamp: None,
+            temperature_type: Some(TemperatureType::Mouth),
+        };
+        let len = measurement.encode(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &[0x04, 0x44, 0x0e, 0x00, 0xfe, 6]);
+
+        let measurement = TemperatureMeasurement {
+            temperature: Float::new(986, -1).unwrap(),
+            unit: TemperatureUnit::Fahrenheit,
+            time_stamp: Some(ExactTime {
+                year: 2024,
+                month: 5,
+                day: 17,
+                hours: 8,
+                minutes: 30,
+                seconds: 0,
+                ..Default::default()
+            }),
+            temperature_type: None,
+        };
+        let len = measurement.encode(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            &[0x03, 0xda, 0x03, 0x00, 0xff, 0xe8, 0x07, 5, 17, 8, 30, 0]
+        );
+    }
+
+    #[test]
+    fn interval_writes_within_range() {
+        let config = HealthThermometerConfig {
+            measurement_interval: Some(MeasurementInterval {
+                interval: 60,
+                valid_range: Some((10, 3600)),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(config.attribute_count(), 8);
+        let mut storage = HealthThermometerStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 8> = AttributeTable::new();
+        let hts = HealthThermometerServer::build(&mut table, &mut storage, config);
+        let interval = *hts.measurement_interval().unwrap();
+        assert_eq!(table.get(&interval).unwrap(), 60);
+        assert_eq!(
+            hts.process(interval.handle, &[0x2c, 0x01]),
+            Ok(Some(HealthThermometerEvent::IntervalChanged(300)))
+        );
+        assert_eq!(
+            hts.process(interval.handle, &[0x00, 0x00]),
+            Ok(Some(HealthThermometerEvent::IntervalChanged(0)))
+        );
+        assert_eq!(
+            hts.process(interval.handle, &[0x05, 0x00]),
+            Err(AttErrorCode::OUT_OF_RANGE)
+        );
+        assert_eq!(
+            hts.process(interval.handle, &[0x05]),
+            Err(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH)
+        );
+        assert_eq!(hts.process(hts.measurement.handle, &[0x01]), Ok(None));
+    }
+
+    #[test]
+    fn intervals_are_confirmed() {
+        let config = HealthThermometerConfig {
+            measurement_interval: Some(MeasurementInterval {
+                interval: 60,
+                valid_range: Some((10, 3600)),
+            }),
+            ..Default::default()
+        };
+        let mut storage = HealthThermometerStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 8> = AttributeTable::new();
+        let hts = HealthThermometerServer::build(&mut table, &mut storage, config);
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+        let interval = *hts.measurement_interval().unwrap();
+
+        assert_eq!(client.read(&server, interval.handle).unwrap(), [60, 0]);
+        assert_eq!(
+            client.write(&server, interval.handle, &[0x05, 0x00], |handle, data| {
+                hts.process(handle, data).map(|_| ())
+            }),
+            Err(AttErrorCode::OUT_OF_RANGE)
+        );
+        client
+            .write(&server, interval.handle, &[0x2c, 0x01], |handle, data| {
+                hts.process(handle, data).map(|_| ())
+            })
+            .unwrap();
+        assert_eq!(client.read(&server, interval.handle).unwrap(), [0x2c, 0x01]);
+
+        client
+            .subscribe(&server, interval.cccd_handle.unwrap(), 0x0002)
+            .unwrap();
+        let (confirmed, indicated) = block_on(join(
+            hts.set_interval(&server, client.connection(), 120),
+            client.confirm_next(&server),
+        ));
+        assert!(confirmed.unwrap());
+        assert_eq!(indicated, (interval.handle, Vec::from_slice(&[120, 0]).unwrap()));
+    }
+}
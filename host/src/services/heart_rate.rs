@@ -0,0 +1,366 @@
+//! Heart Rate Service, notifying the heart rate measured by a sensor.
+//!
+//! Each measurement holds the heart rate, along with the state of the contact of the sensor with
+//! the skin, the energy expended since it was last reset by the client, and the RR intervals
+//! measured since the previous measurement.
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+
+use crate::Error;
+use crate::att::AttErrorCode;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::cursor::WriteCursor;
+
+/// Error of a write of an unknown value to the control point.
+pub const ERROR_CONTROL_POINT_NOT_SUPPORTED: AttErrorCode = AttErrorCode::application(0x80);
+
+const FLAG_HEART_RATE_U16: u8 = 0x01;
+const FLAG_CONTACT_DETECTED: u8 = 0x02;
+const FLAG_CONTACT_SUPPORTED: u8 = 0x04;
+const FLAG_ENERGY_EXPENDED: u8 = 0x08;
+const FLAG_RR_INTERVALS: u8 = 0x10;
+
+const CONTROL_RESET_ENERGY_EXPENDED: u8 = 0x01;
+
+/// Contact of the sensor with the skin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SensorContact {
+    /// The sensor does not detect its contact.
+    NotSupported,
+    /// The sensor is not in contact, or poorly.
+    NotDetected,
+    /// The sensor is in contact.
+    Detected,
+}
+
+/// Location of the sensor on the body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum BodySensorLocation {
+    /// Another location.
+    Other = 0,
+    /// The chest.
+    Chest = 1,
+    /// The wrist.
+    Wrist = 2,
+    /// A finger.
+    Finger = 3,
+    /// A hand.
+    Hand = 4,
+    /// An ear lobe.
+    EarLobe = 5,
+    /// A foot.
+    Foot = 6,
+}
+
+/// A measurement of the heart rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HeartRateMeasurement<'a> {
+    /// Heart rate, in beats per minute.
+    pub heart_rate: u16,
+    /// Contact of the sensor with the skin.
+    pub sensor_contact: SensorContact,
+    /// Energy expended since the last reset, in kilojoules, saturating at 0xffff.
+    ///
+    /// Usually included once every 10 measurements, when the energy expended is supported.
+    pub energy_expended: Option<u16>,
+    /// RR intervals since the previous measurement, from the oldest, in units of 1/1024 s.
+    pub rr_intervals: &'a [u16],
+}
+
+impl HeartRateMeasurement<'_> {
+    /// Encode the measurement, returning the length written.
+    ///
+    /// The oldest RR intervals are left out if they do not fit the buffer.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut flags = match self.sensor_contact {
+            SensorContact::NotSupported => 0,
+            SensorContact::NotDetected => FLAG_CONTACT_SUPPORTED,
+            SensorContact::Detected => FLAG_CONTACT_SUPPORTED | FLAG_CONTACT_DETECTED,
+        };
+        let heart_rate_len = if self.heart_rate > u8::MAX as u16 {
+            flags |= FLAG_HEART_RATE_U16;
+            2
+        } else {
+            1
+        };
+        if self.energy_expended.is_some() {
+            flags |= FLAG_ENERGY_EXPENDED;
+        }
+        let len = 1 + heart_rate_len + self.energy_expended.map_or(0, |_| 2);
+        let room = buf.len().checked_sub(len).ok_or(Error::InsufficientSpace)? / 2;
+        let rr_intervals = &self.rr_intervals[self.rr_intervals.len().saturating_sub(room)..];
+        if !rr_intervals.is_empty() {
+            flags |= FLAG_RR_INTERVALS;
+        }
+
+        let mut w = WriteCursor::new(buf);
+        w.write(flags)?;
+        if heart_rate_len == 2 {
+            w.write(self.heart_rate)?;
+        } else {
+            w.write(self.heart_rate as u8)?;
+        }
+        if let Some(energy) = self.energy_expended {
+            w.write(energy)?;
+        }
+        for &rr in rr_intervals {
+            w.write(rr)?;
+        }
+        Ok(w.len())
+    }
+}
+
+/// Configuration of the Heart Rate Service.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeartRateConfig {
+    /// Location of the sensor, if known.
+    pub body_sensor_location: Option<BodySensorLocation>,
+    /// Whether the measurements include the energy expended, reset with the control point.
+    pub energy_expended: bool,
+}
+
+impl HeartRateConfig {
+    /// Number of attributes added by the service.
+    pub const fn attribute_count(&self) -> usize {
+        // The service, and the declaration, value and CCCD of the measurement.
+        let mut count = 1 + 3;
+        if self.body_sensor_location.is_some() {
+            count += 2;
+        }
+        if self.energy_expended {
+            count += 2;
+        }
+        count
+    }
+}
+
+/// Storage of the values of the characteristics of a [`HeartRateServer`].
+///
+/// The measurements hold up to `LEN` octets, which defaults to the ATT MTU of 23 less the header
+/// of a notification.
+pub struct HeartRateStorage<const LEN: usize = 20> {
+    measurement: [u8; LEN],
+    body_sensor_location: [u8; 1],
+    control_point: [u8; 1],
+}
+
+impl<const LEN: usize> HeartRateStorage<LEN> {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            measurement: [0; LEN],
+            body_sensor_location: [0; 1],
+            control_point: [0; 1],
+        }
+    }
+}
+
+impl<const LEN: usize> Default for HeartRateStorage<LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A write of the client, handled by [`HeartRateServer::process`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HeartRateEvent {
+    /// The client reset the energy expended, to be counted again from 0.
+    ResetEnergyExpended,
+}
+
+/// Heart Rate Service server.
+///
+/// The measurements are notified with [`HeartRateServer::notify_measurement`], while the writes
+/// to the control point are handled with [`HeartRateServer::process`] before being accepted.
+pub struct HeartRateServer<const LEN: usize = 20> {
+    measurement: Characteristic<Vec<u8, LEN>>,
+    control_point: Option<Characteristic<u8>>,
+}
+
+impl<const LEN: usize> HeartRateServer<LEN> {
+    /// Add the service to the attribute table.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut HeartRateStorage<LEN>,
+        config: HeartRateConfig,
+    ) -> Self {
+        let HeartRateStorage {
+            measurement,
+            body_sensor_location,
+            control_point,
+        } = storage;
+        let mut service = table.add_service(Service::new(service::HEART_RATE));
+        let measurement = service
+            .add_characteristic(
+                characteristic::HEART_RATE_MEASUREMENT,
+                &[CharacteristicProp::Notify],
+                Vec::new(),
+                measurement,
+            )
+            .build();
+        if let Some(location) = config.body_sensor_location {
+            *body_sensor_location = [location as u8];
+            service
+                .add_characteristic_ro(characteristic::BODY_SENSOR_LOCATION, &*body_sensor_location)
+                .build();
+        }
+        let control_point = config.energy_expended.then(|| {
+            service
+                .add_characteristic(
+                    characteristic::HEART_RATE_CONTROL_POINT,
+                    &[CharacteristicProp::Write],
+                    0,
+                    control_point,
+                )
+                .build()
+        });
+        service.build();
+        Self {
+            measurement,
+            control_point,
+        }
+    }
+
+    /// Handle a write of the client to an attribute, returning `None` if it is not one of the service.
+    ///
+    /// The write is accepted when the event is returned, and rejected with the error otherwise.
+    pub fn process(&self, handle: u16, data: &[u8]) -> Result<Option<HeartRateEvent>, AttErrorCode> {
+        if self.control_point.as_ref().is_none_or(|c| c.handle != handle) {
+            return Ok(None);
+        }
+        match data {
+            [CONTROL_RESET_ENERGY_EXPENDED] => Ok(Some(HeartRateEvent::ResetEnergyExpended)),
+            _ => Err(ERROR_CONTROL_POINT_NOT_SUPPORTED),
+        }
+    }
+
+    /// Notify a measurement to the client, if subscribed.
+    ///
+    /// The oldest RR intervals are left out if the measurement does not fit the storage or the
+    /// ATT MTU of the connection. Returns [`Error::InvalidValue`] if the measurement includes the
+    /// energy expended while not supported by the service.
+    pub async fn notify_measurement<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        measurement: &HeartRateMeasurement<'_>,
+    ) -> Result<(), Error> {
+        if measurement.energy_expended.is_some() && self.control_point.is_none() {
+            return Err(Error::InvalidValue);
+        }
+        let mut buf = [0; LEN];
+        let max = LEN.min((connection.att_mtu() as usize).saturating_sub(3));
+        let len = measurement.encode(&mut buf[..max])?;
+        let value = unwrap!(Vec::from_slice(&buf[..len]).ok());
+        self.measurement.notify(server, connection, &value).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+
+    #[test]
+    fn encode_measurements() {
+        let mut buf = [0; 8];
+        let measurement = HeartRateMeasurement {
+            heart_rate: 72,
+            sensor_contact: SensorContact::Detected,
+            energy_expended: None,
+            rr_intervals: &[],
+        };
+        let len = measurement.encode(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &[0x06, 72]);
+
+        let measurement = HeartRateMeasurement {
+            heart_rate: 300,
+            sensor_contact: SensorContact::NotSupported,
+            energy_expended: Some(0x1234),
+            rr_intervals: &[0x0101, 0x0202, 0x0303],
+        };
+        let len = measurement.encode(&mut buf).unwrap();
+        // Only the latest interval fits.
+        assert_eq!(&buf[..len], &[0x19, 0x2c, 0x01, 0x34, 0x12, 0x03, 0x03]);
+        assert!(measurement.encode(&mut buf[..4]).is_err());
+    }
+
+    #[test]
+    fn control_point_resets_energy_expended() {
+        let config = HeartRateConfig {
+            body_sensor_location: Some(BodySensorLocation::Wrist),
+            energy_expended: true,
+        };
+        let mut storage: HeartRateStorage = HeartRateStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 8> = AttributeTable::new();
+        assert_eq!(config.attribute_count(), 8);
+        let hrs = HeartRateServer::build(&mut table, &mut storage, config);
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+
+        let (_, location) = client
+            .read_by_type(&server, characteristic::BODY_SENSOR_LOCATION)
+            .unwrap();
+        assert_eq!(location, [BodySensorLocation::Wrist as u8]);
+
+        let control_point = hrs.control_point.as_ref().unwrap().handle;
+        let on_write = |handle, data: &[u8]| {
+            assert_eq!(hrs.process(handle, data)?, Some(HeartRateEvent::ResetEnergyExpended));
+            Ok(())
+        };
+        client.write(&server, control_point, &[0x01], on_write).unwrap();
+        assert_eq!(
+            client.write(&server, control_point, &[0x02], on_write),
+            Err(ERROR_CONTROL_POINT_NOT_SUPPORTED)
+        );
+        assert_eq!(hrs.process(hrs.measurement.handle, &[0x01]), Ok(None));
+    }
+
+    #[test]
+    fn measurements_are_notified_to_subscribers() {
+        let config = HeartRateConfig::default();
+        let mut storage: HeartRateStorage = HeartRateStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 4> = AttributeTable::new();
+        let hrs = HeartRateServer::build(&mut table, &mut storage, config);
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+        let measurement = HeartRateMeasurement {
+            heart_rate: 72,
+            sensor_contact: SensorContact::Detected,
+            energy_expended: None,
+            rr_intervals: &[],
+        };
+
+        block_on(hrs.notify_measurement(&server, client.connection(), &measurement)).unwrap();
+        assert!(client.notified().is_none());
+
+        client
+            .subscribe(&server, hrs.measurement.cccd_handle.unwrap(), 0x0001)
+            .unwrap();
+        block_on(hrs.notify_measurement(&server, client.connection(), &measurement)).unwrap();
+        let notified = client.notified().unwrap();
+        assert_eq!(notified.0, hrs.measurement.handle);
+        assert_eq!(notified.1, [0x06, 72]);
+
+        // The energy expended is not supported without the control point.
+        let measurement = HeartRateMeasurement {
+            energy_expended: Some(1),
+            ..measurement
+        };
+        assert!(matches!(
+            block_on(hrs.notify_measurement(&server, client.connection(), &measurement)),
+            Err(Error::InvalidValue)
+        ));
+    }
+}
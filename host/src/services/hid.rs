@@ -0,0 +1,771 @@
+//! HID over GATT, for keyboards, mice, remotes and game controllers.
+//!
+//! The HID Service of the device describes its reports with a report map, in the format of the
+//! HID class of USB. The input reports are notified to the host with
+//! [`HidServer::send_input_report`], while the writes of the host to the output and feature
+//! reports, the protocol mode and the control point are handled with [`HidServer::process`].
+//!
+//! A device supporting a boot protocol also exposes the boot reports, used by hosts without a
+//! report map parser, such as BIOSes. The host selects the protocol with the protocol mode, after
+//! which the device sends its input with [`HidServer::send_boot_keyboard_input`] or
+//! [`HidServer::send_boot_mouse_input`] instead.
+//!
+//! The report maps of a keyboard, a mouse and a gamepad are provided as presets, along with the
+//! reports they describe. HID over GATT also requires the Battery Service and the Device
+//! Information Service with a PnP ID, from the sibling modules.
+use bt_hci::uuid::{characteristic, descriptors, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+
+use crate::Error;
+use crate::att::AttErrorCode;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+
+/// Version of the HID specification implemented, 1.11.
+const BCD_HID: u16 = 0x0111;
+
+const CONTROL_SUSPEND: u8 = 0x00;
+const CONTROL_EXIT_SUSPEND: u8 = 0x01;
+
+/// Length of the boot keyboard input report.
+pub const BOOT_KEYBOARD_INPUT_LEN: usize = 8;
+
+/// Maximum length of the boot mouse input report, of at least 3 octets.
+pub const BOOT_MOUSE_INPUT_MAX_LEN: usize = 8;
+
+/// Type of a report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ReportType {
+    /// Report sent by the device.
+    Input = 0x01,
+    /// Report written by the host, such as the LEDs of a keyboard.
+    Output = 0x02,
+    /// Report read and written by the host, for the configuration of the device.
+    Feature = 0x03,
+}
+
+/// A report of the report map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Report {
+    /// Identifier of the report in the report map, or 0 if the map uses no report identifiers.
+    pub id: u8,
+    /// Type of the report.
+    pub report_type: ReportType,
+}
+
+/// Boot protocol supported by the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BootProtocol {
+    /// Boot keyboard, with an input and an output report.
+    Keyboard,
+    /// Boot mouse, with an input report.
+    Mouse,
+}
+
+/// Protocol used by the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ProtocolMode {
+    /// The boot protocol, with the boot reports.
+    Boot = 0x00,
+    /// The report protocol, with the reports of the report map.
+    Report = 0x01,
+}
+
+/// Configuration of the HID Service.
+#[derive(Debug, Clone, Copy)]
+pub struct HidConfig<'a> {
+    /// Report map, describing the reports in the format of the HID class of USB.
+    pub report_map: &'a [u8],
+    /// Reports of the report map.
+    pub reports: &'a [Report],
+    /// Boot protocol supported, if any.
+    pub boot: Option<BootProtocol>,
+    /// Country code of the localized hardware, or 0.
+    pub country_code: u8,
+    /// Whether the device may wake up the host.
+    pub remote_wake: bool,
+    /// Whether the device advertises when bonded but not connected.
+    pub normally_connectable: bool,
+}
+
+impl<'a> HidConfig<'a> {
+    /// Create a configuration of the reports of the report map, without boot protocol.
+    pub const fn new(report_map: &'a [u8], reports: &'a [Report]) -> Self {
+        Self {
+            report_map,
+            reports,
+            boot: None,
+            country_code: 0,
+            remote_wake: false,
+            normally_connectable: false,
+        }
+    }
+
+    /// Number of attributes added by the service.
+    pub const fn attribute_count(&self) -> usize {
+        // The service, and the declaration and value of the information, report map and control point.
+        let mut count = 1 + 2 * 3;
+        let mut i = 0;
+        while i < self.reports.len() {
+            // The declaration, value and reference of the report, and the CCCD of an input report.
+            count += match self.reports[i].report_type {
+                ReportType::Input => 4,
+                _ => 3,
+            };
+            i += 1;
+        }
+        // The protocol mode along with the boot reports, the inputs with a CCCD.
+        count += match self.boot {
+            Some(BootProtocol::Keyboard) => 2 + 3 + 2,
+            Some(BootProtocol::Mouse) => 2 + 3,
+            None => 0,
+        };
+        count
+    }
+}
+
+/// Storage of the values of the characteristics of a [`HidServer`].
+///
+/// Each report holds up to `LEN` octets, its identifier included when the report map uses them.
+pub struct HidStorage<const REPORTS: usize, const LEN: usize = 64> {
+    reports: [[u8; LEN]; REPORTS],
+    references: [[u8; 2]; REPORTS],
+    information: [u8; 4],
+    control_point: [u8; 1],
+    protocol_mode: [u8; 1],
+    boot_input: [u8; BOOT_MOUSE_INPUT_MAX_LEN],
+    boot_output: [u8; 1],
+}
+
+impl<const REPORTS: usize, const LEN: usize> HidStorage<REPORTS, LEN> {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            reports: [[0; LEN]; REPORTS],
+            references: [[0; 2]; REPORTS],
+            information: [0; 4],
+            control_point: [0; 1],
+            protocol_mode: [0; 1],
+            boot_input: [0; BOOT_MOUSE_INPUT_MAX_LEN],
+            boot_output: [0; 1],
+        }
+    }
+}
+
+impl<const REPORTS: usize, const LEN: usize> Default for HidStorage<REPORTS, LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A write of the host, handled by [`HidServer::process`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HidEvent<'a> {
+    /// The host selected a protocol.
+    ProtocolMode(ProtocolMode),
+    /// The host entered its suspend state, so that the device may save power.
+    Suspend,
+    /// The host exited its suspend state.
+    ExitSuspend,
+    /// The host wrote an output report.
+    OutputReport {
+        /// Identifier of the report.
+        id: u8,
+        /// Value of the report.
+        data: &'a [u8],
+    },
+    /// The host wrote a feature report.
+    FeatureReport {
+        /// Identifier of the report.
+        id: u8,
+        /// Value of the report.
+        data: &'a [u8],
+    },
+    /// The host wrote the boot keyboard output report, holding the state of the LEDs.
+    BootKeyboardOutput(u8),
+}
+
+/// HID Service server.
+///
+/// Writes of the host are handled with [`HidServer::process`] before being accepted, keeping
+/// track of the protocol mode and suspend state of the host.
+pub struct HidServer<const REPORTS: usize, const LEN: usize = 64> {
+    reports: Vec<(Report, Characteristic<Vec<u8, LEN>>), REPORTS>,
+    control_point: Characteristic<u8>,
+    protocol_mode: Option<Characteristic<u8>>,
+    boot_keyboard_input: Option<Characteristic<Vec<u8, BOOT_MOUSE_INPUT_MAX_LEN>>>,
+    boot_keyboard_output: Option<Characteristic<u8>>,
+    boot_mouse_input: Option<Characteristic<Vec<u8, BOOT_MOUSE_INPUT_MAX_LEN>>>,
+    mode: ProtocolMode,
+    suspended: bool,
+}
+
+impl<const REPORTS: usize, const LEN: usize> HidServer<REPORTS, LEN> {
+    /// Add the service to the attribute table.
+    ///
+    /// Returns [`Error::InsufficientSpace`] if the configuration has more than `REPORTS` reports.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut HidStorage<REPORTS, LEN>,
+        config: HidConfig<'d>,
+    ) -> Result<Self, Error> {
+        if config.reports.len() > REPORTS {
+            return Err(Error::InsufficientSpace);
+        }
+        let HidStorage {
+            reports: report_values,
+            references,
+            information,
+            control_point,
+            protocol_mode,
+            boot_input,
+            boot_output,
+        } = storage;
+        let mut service = table.add_service(Service::new(service::HUMAN_INTERFACE_DEVICE));
+
+        let [b0, b1] = BCD_HID.to_le_bytes();
+        let flags = (config.remote_wake as u8) | ((config.normally_connectable as u8) << 1);
+        *information = [b0, b1, config.country_code, flags];
+        service
+            .add_characteristic_ro(characteristic::HID_INFORMATION, &*information)
+            .build();
+        service
+            .add_characteristic_ro_bytes(characteristic::REPORT_MAP, config.report_map)
+            .build();
+
+        let mut reports = Vec::new();
+        for ((report, value), reference) in config.reports.iter().zip(report_values).zip(references) {
+            let props: &[CharacteristicProp] = match report.report_type {
+                ReportType::Input => &[CharacteristicProp::Read, CharacteristicProp::Notify],
+                ReportType::Output => &[
+                    CharacteristicProp::Read,
+                    CharacteristicProp::Write,
+                    CharacteristicProp::WriteWithoutResponse,
+                ],
+                ReportType::Feature => &[CharacteristicProp::Read, CharacteristicProp::Write],
+            };
+            *reference = [report.id, report.report_type as u8];
+            let mut builder = service.add_characteristic(characteristic::REPORT, props, Vec::new(), value);
+            builder.add_descriptor_ro::<[u8; 2], _>(descriptors::REPORT_REFERENCE, &*reference);
+            unwrap!(reports.push((*report, builder.build())).ok());
+        }
+
+        let control_point = service
+            .add_characteristic(
+                characteristic::HID_CONTROL_POINT,
+                &[CharacteristicProp::WriteWithoutResponse],
+                0,
+                control_point,
+            )
+            .build();
+
+        let mut server = Self {
+            reports,
+            control_point,
+            protocol_mode: None,
+            boot_keyboard_input: None,
+            boot_keyboard_output: None,
+            boot_mouse_input: None,
+            mode: ProtocolMode::Report,
+            suspended: false,
+        };
+        if let Some(boot) = config.boot {
+            server.protocol_mode = Some(
+                service
+                    .add_characteristic(
+                        characteristic::PROTOCOL_MODE,
+                        &[CharacteristicProp::Read, CharacteristicProp::WriteWithoutResponse],
+                        ProtocolMode::Report as u8,
+                        protocol_mode,
+                    )
+                    .build(),
+            );
+            let input_props = [CharacteristicProp::Read, CharacteristicProp::Notify];
+            match boot {
+                BootProtocol::Keyboard => {
+                    server.boot_keyboard_input = Some(
+                        service
+                            .add_characteristic(
+                                characteristic::BOOT_KEYBOARD_INPUT_REPORT,
+                                &input_props,
+                                Vec::new(),
+                                &mut boot_input[..BOOT_KEYBOARD_INPUT_LEN],
+                            )
+                            .build(),
+                    );
+                    server.boot_keyboard_output = Some(
+                        service
+                            .add_characteristic(
+                                characteristic::BOOT_KEYBOARD_OUTPUT_REPORT,
+                                &[
+                                    CharacteristicProp::Read,
+                                    CharacteristicProp::Write,
+                                    CharacteristicProp::WriteWithoutResponse,
+                                ],
+                                0,
+                                boot_output,
+                            )
+                            .build(),
+                    );
+                }
+                BootProtocol::Mouse => {
+                    server.boot_mouse_input = Some(
+                        service
+                            .add_characteristic(
+                                characteristic::BOOT_MOUSE_INPUT_REPORT,
+                                &input_props,
+                                Vec::new(),
+                                boot_input,
+                            )
+                            .build(),
+                    );
+                }
+            }
+        }
+        service.build();
+        Ok(server)
+    }
+
+    /// Protocol selected by the host.
+    pub fn protocol_mode(&self) -> ProtocolMode {
+        self.mode
+    }
+
+    /// Whether the host is in its suspend state.
+    pub fn suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Handle a write of the host to an attribute, returning `None` if it is not one of the service.
+    ///
+    /// The write is accepted when the event is returned, and rejected with the error otherwise.
+    pub fn process<'a>(&mut self, handle: u16, data: &'a [u8]) -> Result<Option<HidEvent<'a>>, AttErrorCode> {
+        if handle == self.control_point.handle {
+            let event = match data {
+                [CONTROL_SUSPEND] => HidEvent::Suspend,
+                [CONTROL_EXIT_SUSPEND] => HidEvent::ExitSuspend,
+                _ => return Err(AttErrorCode::VALUE_NOT_ALLOWED),
+            };
+            self.suspended = event == HidEvent::Suspend;
+            return Ok(Some(event));
+        }
+        if self.protocol_mode.as_ref().is_some_and(|c| c.handle == handle) {
+            self.mode = match data {
+                [0x00] => ProtocolMode::Boot,
+                [0x01] => ProtocolMode::Report,
+                _ => return Err(AttErrorCode::VALUE_NOT_ALLOWED),
+            };
+            return Ok(Some(HidEvent::ProtocolMode(self.mode)));
+        }
+        if self.boot_keyboard_output.as_ref().is_some_and(|c| c.handle == handle) {
+            let &[leds] = data else {
+                return Err(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH);
+            };
+            return Ok(Some(HidEvent::BootKeyboardOutput(leds)));
+        }
+        let Some((report, _)) = self.reports.iter().find(|(_, c)| c.handle == handle) else {
+            return Ok(None);
+        };
+        if data.len() > LEN {
+            return Err(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH);
+        }
+        match report.report_type {
+            ReportType::Output => Ok(Some(HidEvent::OutputReport { id: report.id, data })),
+            ReportType::Feature => Ok(Some(HidEvent::FeatureReport { id: report.id, data })),
+            ReportType::Input => Err(AttErrorCode::WRITE_NOT_PERMITTED),
+        }
+    }
+
+    fn report(&self, id: u8, report_type: ReportType) -> Result<&Characteristic<Vec<u8, LEN>>, Error> {
+        self.reports
+            .iter()
+            .find(|(r, _)| r.id == id && r.report_type == report_type)
+            .map(|(_, c)| c)
+            .ok_or(Error::NotFound)
+    }
+
+    /// Update the value of a report read by the host, such as a feature report, without notifying.
+    pub fn set_report<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        id: u8,
+        report_type: ReportType,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        let value = Vec::from_slice(data).map_err(|_| Error::InsufficientSpace)?;
+        self.report(id, report_type)?.set(server, &value)
+    }
+
+    /// Send an input report of the report protocol, notifying the host if subscribed.
+    pub async fn send_input_report<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        id: u8,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        let value = Vec::from_slice(data).map_err(|_| Error::InsufficientSpace)?;
+        self.report(id, ReportType::Input)?
+            .notify(server, connection, &value)
+            .await
+    }
+
+    /// Send the boot keyboard input report, notifying the host if subscribed.
+    pub async fn send_boot_keyboard_input<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        report: &[u8; BOOT_KEYBOARD_INPUT_LEN],
+    ) -> Result<(), Error> {
+        let c = self.boot_keyboard_input.as_ref().ok_or(Error::NotSupported)?;
+        c.notify(server, connection, &unwrap!(Vec::from_slice(report).ok()))
+            .await
+    }
+
+    /// Send the boot mouse input report, of the buttons and the X and Y displacements followed
+    /// by any data of the device, notifying the host if subscribed.
+    pub async fn send_boot_mouse_input<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        report: &[u8],
+    ) -> Result<(), Error> {
+        let c = self.boot_mouse_input.as_ref().ok_or(Error::NotSupported)?;
+        if report.len() < 3 {
+            return Err(Error::InvalidValue);
+        }
+        let value = Vec::from_slice(report).map_err(|_| Error::InsufficientSpace)?;
+        c.notify(server, connection, &value).await
+    }
+}
+
+/// Report map of a keyboard compatible with the boot keyboard, with an input report of the
+/// modifiers and up to 6 keys pressed, and an output report of 5 LEDs.
+#[rustfmt::skip]
+pub const KEYBOARD_REPORT_MAP: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xa1, 0x01, // Collection (Application)
+    0x85, 0x01, //   Report ID (1)
+    0x05, 0x07, //   Usage Page (Keyboard/Keypad)
+    0x19, 0xe0, //   Usage Minimum (Left Control)
+    0x29, 0xe7, //   Usage Maximum (Right GUI)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x08, //   Report Size (8)
+    0x81, 0x01, //   Input (Constant)
+    0x95, 0x05, //   Report Count (5)
+    0x75, 0x01, //   Report Size (1)
+    0x05, 0x08, //   Usage Page (LEDs)
+    0x19, 0x01, //   Usage Minimum (Num Lock)
+    0x29, 0x05, //   Usage Maximum (Kana)
+    0x91, 0x02, //   Output (Data, Variable, Absolute)
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x03, //   Report Size (3)
+    0x91, 0x01, //   Output (Constant)
+    0x95, 0x06, //   Report Count (6)
+    0x75, 0x08, //   Report Size (8)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x65, //   Logical Maximum (101)
+    0x05, 0x07, //   Usage Page (Keyboard/Keypad)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x29, 0x65, //   Usage Maximum (101)
+    0x81, 0x00, //   Input (Data, Array)
+    0xc0,       // End Collection
+];
+
+/// Reports of [`KEYBOARD_REPORT_MAP`].
+pub const KEYBOARD_REPORTS: &[Report] = &[
+    Report {
+        id: 1,
+        report_type: ReportType::Input,
+    },
+    Report {
+        id: 1,
+        report_type: ReportType::Output,
+    },
+];
+
+/// Report map of a mouse compatible with the boot mouse, with an input report of 3 buttons, the
+/// X and Y displacements and the wheel.
+#[rustfmt::skip]
+pub const MOUSE_REPORT_MAP: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x02, // Usage (Mouse)
+    0xa1, 0x01, // Collection (Application)
+    0x85, 0x01, //   Report ID (1)
+    0x09, 0x01, //   Usage (Pointer)
+    0xa1, 0x00, //   Collection (Physical)
+    0x05, 0x09, //     Usage Page (Button)
+    0x19, 0x01, //     Usage Minimum (1)
+    0x29, 0x03, //     Usage Maximum (3)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x95, 0x03, //     Report Count (3)
+    0x75, 0x01, //     Report Size (1)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x95, 0x01, //     Report Count (1)
+    0x75, 0x05, //     Report Size (5)
+    0x81, 0x01, //     Input (Constant)
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x09, 0x30, //     Usage (X)
+    0x09, 0x31, //     Usage (Y)
+    0x09, 0x38, //     Usage (Wheel)
+    0x15, 0x81, //     Logical Minimum (-127)
+    0x25, 0x7f, //     Logical Maximum (127)
+    0x75, 0x08, //     Report Size (8)
+    0x95, 0x03, //     Report Count (3)
+    0x81, 0x06, //     Input (Data, Variable, Relative)
+    0xc0,       //   End Collection
+    0xc0,       // End Collection
+];
+
+/// Reports of [`MOUSE_REPORT_MAP`].
+pub const MOUSE_REPORTS: &[Report] = &[Report {
+    id: 1,
+    report_type: ReportType::Input,
+}];
+
+/// Report map of a gamepad, with an input report of 16 buttons and 4 axes.
+#[rustfmt::skip]
+pub const GAMEPAD_REPORT_MAP: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x05, // Usage (Gamepad)
+    0xa1, 0x01, // Collection (Application)
+    0x85, 0x01, //   Report ID (1)
+    0x05, 0x09, //   Usage Page (Button)
+    0x19, 0x01, //   Usage Minimum (1)
+    0x29, 0x10, //   Usage Maximum (16)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x10, //   Report Count (16)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x05, 0x01, //   Usage Page (Generic Desktop)
+    0x09, 0x30, //   Usage (X)
+    0x09, 0x31, //   Usage (Y)
+    0x09, 0x32, //   Usage (Z)
+    0x09, 0x35, //   Usage (Rz)
+    0x15, 0x81, //   Logical Minimum (-127)
+    0x25, 0x7f, //   Logical Maximum (127)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x04, //   Report Count (4)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0xc0,       // End Collection
+];
+
+/// Reports of [`GAMEPAD_REPORT_MAP`].
+pub const GAMEPAD_REPORTS: &[Report] = &[Report {
+    id: 1,
+    report_type: ReportType::Input,
+}];
+
+/// Input report of [`KEYBOARD_REPORT_MAP`], also the boot keyboard input report.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KeyboardReport {
+    /// Modifier keys pressed, from bit 0 for the left control to bit 7 for the right GUI.
+    pub modifiers: u8,
+    /// Usages of the keys pressed, or 0.
+    pub keys: [u8; 6],
+}
+
+impl KeyboardReport {
+    /// Encode the report.
+    pub fn to_bytes(&self) -> [u8; BOOT_KEYBOARD_INPUT_LEN] {
+        let mut report = [0; BOOT_KEYBOARD_INPUT_LEN];
+        report[0] = self.modifiers;
+        report[2..].copy_from_slice(&self.keys);
+        report
+    }
+}
+
+/// Input report of [`MOUSE_REPORT_MAP`], whose first 3 octets are the boot mouse input report.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MouseReport {
+    /// Buttons pressed, from bit 0 for the primary button.
+    pub buttons: u8,
+    /// Displacement along the X axis.
+    pub x: i8,
+    /// Displacement along the Y axis.
+    pub y: i8,
+    /// Displacement of the wheel.
+    pub wheel: i8,
+}
+
+impl MouseReport {
+    /// Encode the report.
+    pub fn to_bytes(&self) -> [u8; 4] {
+        [self.buttons, self.x as u8, self.y as u8, self.wheel as u8]
+    }
+}
+
+/// Input report of [`GAMEPAD_REPORT_MAP`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GamepadReport {
+    /// Buttons pressed, from bit 0 for the first button.
+    pub buttons: u16,
+    /// Position of the X axis.
+    pub x: i8,
+    /// Position of the Y axis.
+    pub y: i8,
+    /// Position of the Z axis.
+    pub z: i8,
+    /// Position of the Rz axis.
+    pub rz: i8,
+}
+
+impl GamepadReport {
+    /// Encode the report.
+    pub fn to_bytes(&self) -> [u8; 6] {
+        let [b0, b1] = self.buttons.to_le_bytes();
+        [b0, b1, self.x as u8, self.y as u8, self.z as u8, self.rz as u8]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+
+    const KEYBOARD: HidConfig = HidConfig {
+        boot: Some(BootProtocol::Keyboard),
+        remote_wake: true,
+        ..HidConfig::new(KEYBOARD_REPORT_MAP, KEYBOARD_REPORTS)
+    };
+
+    #[test]
+    fn keyboard_handles_writes() {
+        let mut storage: HidStorage<2, 8> = HidStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, { KEYBOARD.attribute_count() }> = AttributeTable::new();
+        let mut hid = HidServer::build(&mut table, &mut storage, KEYBOARD).unwrap();
+        let server = AttributeServer::new(table);
+        assert_eq!(hid.protocol_mode(), ProtocolMode::Report);
+
+        let output = hid.reports[1].1.handle;
+        assert_eq!(
+            hid.process(output, &[0x02]),
+            Ok(Some(HidEvent::OutputReport { id: 1, data: &[0x02] }))
+        );
+        let input = hid.reports[0].1.handle;
+        assert_eq!(hid.process(input, &[0]), Err(AttErrorCode::WRITE_NOT_PERMITTED));
+
+        let protocol_mode = hid.protocol_mode.as_ref().unwrap().handle;
+        assert_eq!(
+            hid.process(protocol_mode, &[0x00]),
+            Ok(Some(HidEvent::ProtocolMode(ProtocolMode::Boot)))
+        );
+        assert_eq!(hid.protocol_mode(), ProtocolMode::Boot);
+        assert_eq!(
+            hid.process(protocol_mode, &[0x02]),
+            Err(AttErrorCode::VALUE_NOT_ALLOWED)
+        );
+
+        let control_point = hid.control_point.handle;
+        assert_eq!(hid.process(control_point, &[0x00]), Ok(Some(HidEvent::Suspend)));
+        assert!(hid.suspended());
+        assert_eq!(hid.process(control_point, &[0x01]), Ok(Some(HidEvent::ExitSuspend)));
+        assert!(!hid.suspended());
+
+        let leds = hid.boot_keyboard_output.as_ref().unwrap().handle;
+        assert_eq!(hid.process(leds, &[0x01]), Ok(Some(HidEvent::BootKeyboardOutput(0x01))));
+        assert_eq!(hid.process(0xffff, &[0x01]), Ok(None));
+
+        hid.set_report(&server, 1, ReportType::Output, &[0x03]).unwrap();
+        assert!(matches!(
+            hid.set_report(&server, 2, ReportType::Output, &[0x03]),
+            Err(Error::NotFound)
+        ));
+        assert!(matches!(
+            hid.set_report(&server, 1, ReportType::Input, &[0; 9]),
+            Err(Error::InsufficientSpace)
+        ));
+    }
+
+    #[test]
+    fn host_selects_boot_protocol() {
+        let mut storage: HidStorage<2, 8> = HidStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, { KEYBOARD.attribute_count() }> = AttributeTable::new();
+        let mut hid = HidServer::build(&mut table, &mut storage, KEYBOARD).unwrap();
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+
+        let (_, report_map) = client.read_by_type(&server, characteristic::REPORT_MAP).unwrap();
+        assert_eq!(report_map, KEYBOARD_REPORT_MAP);
+        let (_, reference) = client.read_by_type(&server, descriptors::REPORT_REFERENCE).unwrap();
+        assert_eq!(reference, [1, ReportType::Input as u8]);
+
+        let protocol_mode = hid.protocol_mode.as_ref().unwrap().handle;
+        let (input, output) = (hid.reports[0].1.handle, hid.reports[1].1.handle);
+        assert_eq!(client.read(&server, protocol_mode).unwrap(), [0x01]);
+        let mut on_write = |handle, data: &[u8]| hid.process(handle, data).map(|_| ());
+        client
+            .write_without_response(&server, protocol_mode, &[0x00], &mut on_write)
+            .unwrap();
+        assert_eq!(
+            client.write_without_response(&server, protocol_mode, &[0x02], &mut on_write),
+            Err(AttErrorCode::VALUE_NOT_ALLOWED)
+        );
+        assert_eq!(client.read(&server, protocol_mode).unwrap(), [0x00]);
+
+        client.write(&server, output, &[0x02], &mut on_write).unwrap();
+        assert_eq!(client.read(&server, output).unwrap(), [0x02]);
+        assert_eq!(
+            client.write(&server, input, &[0x00], &mut on_write),
+            Err(AttErrorCode::WRITE_NOT_PERMITTED)
+        );
+        assert_eq!(hid.protocol_mode(), ProtocolMode::Boot);
+    }
+
+    #[test]
+    fn attribute_counts() {
+        assert_eq!(KEYBOARD.attribute_count(), 7 + 4 + 3 + 7);
+        let mouse = HidConfig::new(MOUSE_REPORT_MAP, MOUSE_REPORTS);
+        assert_eq!(mouse.attribute_count(), 7 + 4);
+        let mut storage: HidStorage<0> = HidStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 32> = AttributeTable::new();
+        assert!(matches!(
+            HidServer::build(&mut table, &mut storage, mouse),
+            Err(Error::InsufficientSpace)
+        ));
+    }
+
+    #[test]
+    fn preset_reports() {
+        let keyboard = KeyboardReport {
+            modifiers: 0x02,
+            keys: [0x04, 0, 0, 0, 0, 0],
+        };
+        assert_eq!(keyboard.to_bytes(), [0x02, 0, 0x04, 0, 0, 0, 0, 0]);
+        let mouse = MouseReport {
+            buttons: 0x01,
+            x: -1,
+            y: 2,
+            wheel: 0,
+        };
+        assert_eq!(mouse.to_bytes(), [0x01, 0xff, 0x02, 0x00]);
+        let gamepad = GamepadReport {
+            buttons: 0x8001,
+            x: 127,
+            ..Default::default()
+        };
+        assert_eq!(gamepad.to_bytes(), [0x01, 0x80, 0x7f, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,122 @@
+//! Medical number formats of IEEE 11073-20601, used by the health services.
+use crate::Error;
+
+/// A 16-bit floating point number, a 12-bit mantissa scaled by a power of ten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SFloat(u16);
+
+impl SFloat {
+    /// Not a number, such as an invalid measurement.
+    pub const NAN: Self = Self(0x07ff);
+    /// Not at this resolution, a value out of the range of the format.
+    pub const NRES: Self = Self(0x0800);
+    /// Positive infinity.
+    pub const INFINITY: Self = Self(0x07fe);
+    /// Negative infinity.
+    pub const NEG_INFINITY: Self = Self(0x0802);
+
+    /// Create a number, `mantissa * 10^exponent`.
+    ///
+    /// Returns [`Error::InvalidValue`] if the mantissa is out of -2045 to 2045, or the exponent
+    /// out of -8 to 7.
+    pub const fn new(mantissa: i16, exponent: i8) -> Result<Self, Error> {
+        if mantissa < -2045 || mantissa > 2045 || exponent < -8 || exponent > 7 {
+            return Err(Error::InvalidValue);
+        }
+        Ok(Self((((exponent as u16) & 0x0f) << 12) | ((mantissa as u16) & 0x0fff)))
+    }
+
+    /// Create a number from its encoding.
+    pub const fn from_bits(bits: u16) -> Self {
+        Self(bits)
+    }
+
+    /// The encoding of the number.
+    pub const fn to_bits(self) -> u16 {
+        self.0
+    }
+
+    /// The mantissa and the exponent of the number, or `None` if it is a special value.
+    pub const fn parts(self) -> Option<(i16, i8)> {
+        let mantissa = ((self.0 << 4) as i16) >> 4;
+        if mantissa >= 0x07fe || mantissa <= -0x07fe {
+            return None;
+        }
+        Some((mantissa, ((self.0 as i16) >> 12) as i8))
+    }
+}
+
+/// A 32-bit floating point number, a 24-bit mantissa scaled by a power of ten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Float(u32);
+
+impl Float {
+    /// Not a number, such as an invalid measurement.
+    pub const NAN: Self = Self(0x007f_ffff);
+    /// Not at this resolution, a value out of the range of the format.
+    pub const NRES: Self = Self(0x0080_0000);
+    /// Positive infinity.
+    pub const INFINITY: Self = Self(0x007f_fffe);
+    /// Negative infinity.
+    pub const NEG_INFINITY: Self = Self(0x0080_0002);
+
+    /// Create a number, `mantissa * 10^exponent`.
+    ///
+    /// Returns [`Error::InvalidValue`] if the mantissa is out of -8388605 to 8388605.
+    pub const fn new(mantissa: i32, exponent: i8) -> Result<Self, Error> {
+        if mantissa < -0x007f_fffd || mantissa > 0x007f_fffd {
+            return Err(Error::InvalidValue);
+        }
+        Ok(Self(
+            ((exponent as u8 as u32) << 24) | ((mantissa as u32) & 0x00ff_ffff),
+        ))
+    }
+
+    /// Create a number from its encoding.
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// The encoding of the number.
+    pub const fn to_bits(self) -> u32 {
+        self.0
+    }
+
+    /// The mantissa and the exponent of the number, or `None` if it is a special value.
+    pub const fn parts(self) -> Option<(i32, i8)> {
+        let mantissa = ((self.0 << 8) as i32) >> 8;
+        if mantissa >= 0x007f_fffe || mantissa <= -0x007f_fffe {
+            return None;
+        }
+        Some((mantissa, (self.0 >> 24) as i8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sfloat_parts() {
+        let value = SFloat::new(-125, -1).unwrap();
+        assert_eq!(value.to_bits(), 0xff83);
+        assert_eq!(value.parts(), Some((-125, -1)));
+        assert_eq!(SFloat::new(2045, 7).unwrap().parts(), Some((2045, 7)));
+        assert_eq!(SFloat::NAN.parts(), None);
+        assert_eq!(SFloat::NEG_INFINITY.parts(), None);
+        assert!(SFloat::new(2046, 0).is_err());
+        assert!(SFloat::new(-2046, 0).is_err());
+    }
+
+    #[test]
+    fn float_parts() {
+        let value = Float::new(3652, -2).unwrap();
+        assert_eq!(value.to_bits(), 0xfe00_0e44);
+        assert_eq!(value.parts(), Some((3652, -2)));
+        assert_eq!(Float::new(-1, 3).unwrap().parts(), Some((-1, 3)));
+        assert_eq!(Float::NRES.parts(), None);
+        assert!(Float::new(0x0080_0000, 0).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+//! Nordic UART Service, a serial stream over GATT.
+//!
+//! The client writes its data to the RX characteristic of the server, and receives the data of
+//! the server in notifications of the TX characteristic. Both ends expose the stream with the
+//! `embedded_io_async` traits, splitting the data written into chunks fitting the ATT MTU of the
+//! connection.
+//!
+//! The server buffers the data received, and only accepts a write once its data is buffered, so
+//! that a client writing faster than the data is read is slowed down by the pending writes.
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::pipe::Pipe;
+use heapless::Vec;
+
+use crate::Error;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::gatt::{GattClient, Notification, NotificationListener};
+use crate::types::uuid::Uuid;
+use crate::{BleHostError, Controller};
+
+/// UUID of the Nordic UART Service.
+pub const NUS_SERVICE: Uuid = Uuid::new_long(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e_u128.to_le_bytes());
+
+/// UUID of the RX characteristic, written by the client.
+pub const NUS_RX: Uuid = Uuid::new_long(0x6e400002_b5a3_f393_e0a9_e50e24dcca9e_u128.to_le_bytes());
+
+/// UUID of the TX characteristic, notified by the server.
+pub const NUS_TX: Uuid = Uuid::new_long(0x6e400003_b5a3_f393_e0a9_e50e24dcca9e_u128.to_le_bytes());
+
+/// Number of attributes added by the Nordic UART Service.
+///
+/// The service, the declaration and value of RX, and the declaration, value and CCCD of TX.
+pub const NUS_ATTRIBUTE_COUNT: usize = 6;
+
+// Header of a notification or a write, before the data.
+const ATT_HEADER_LEN: usize = 3;
+
+/// Storage of the values of the characteristics of a [`NusServer`].
+///
+/// The values hold up to `LEN` octets, which defaults to the largest ATT MTU of 247 less the
+/// header of a notification.
+pub struct NusStorage<const LEN: usize = 244> {
+    rx: [u8; LEN],
+    tx: [u8; LEN],
+}
+
+impl<const LEN: usize> NusStorage<LEN> {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            rx: [0; LEN],
+            tx: [0; LEN],
+        }
+    }
+}
+
+impl<const LEN: usize> Default for NusStorage<LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Nordic UART Service server.
+///
+/// The writes of the client are handled with [`NusServer::process`], buffering up to `RX`
+/// octets until read from the stream of [`NusServer::stream`].
+pub struct NusServer<M: RawMutex, const RX: usize = 256, const LEN: usize = 244> {
+    rx: Characteristic<Vec<u8, LEN>>,
+    tx: Characteristic<Vec<u8, LEN>>,
+    received: Pipe<M, RX>,
+}
+
+impl<M: RawMutex, const RX: usize, const LEN: usize> NusServer<M, RX, LEN> {
+    /// Add the service to the attribute table.
+    pub fn build<'d, MT: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, MT, MAX>,
+        storage: &'d mut NusStorage<LEN>,
+    ) -> Self {
+        let NusStorage { rx, tx } = storage;
+        let mut service = table.add_service(Service::new(NUS_SERVICE));
+        let rx = service
+            .add_characteristic(
+                NUS_RX,
+                &[CharacteristicProp::Write, CharacteristicProp::WriteWithoutResponse],
+                Vec::new(),
+                rx,
+            )
+            .build();
+        let tx = service
+            .add_characteristic(NUS_TX, &[CharacteristicProp::Notify], Vec::new(), tx)
+            .build();
+        service.build();
+        Self {
+            rx,
+            tx,
+            received: Pipe::new(),
+        }
+    }
+
+    /// Handle a write of the client to an attribute, returning `false` if it is not one of the service.
+    ///
+    /// The data written to RX is buffered, waiting for room in the buffer if needed, after which
+    /// the write is to be accepted.
+    pub async fn process(&self, handle: u16, data: &[u8]) -> bool {
+        if handle != self.rx.handle {
+            return false;
+        }
+        self.received.write_all(data).await;
+        true
+    }
+
+    /// The stream of a connection to the client.
+    pub fn stream<'a, 's, 'c, MT: RawMutex, const MAX: usize>(
+        &'a self,
+        server: &'a AttributeServer<'s, MT, MAX>,
+        connection: &'a Connection<'c>,
+    ) -> NusStream<'a, 's, 'c, M, MT, RX, LEN, MAX> {
+        NusStream {
+            nus: self,
+            server,
+            connection,
+        }
+    }
+}
+
+/// Stream of the Nordic UART Service server, to the client of a connection.
+///
+/// The data written is lost while the client is not subscribed to the notifications of TX.
+pub struct NusStream<'a, 's, 'c, M: RawMutex, MT: RawMutex, const RX: usize, const LEN: usize, const MAX: usize> {
+    nus: &'a NusServer<M, RX, LEN>,
+    server: &'a AttributeServer<'s, MT, MAX>,
+    connection: &'a Connection<'c>,
+}
+
+impl<M: RawMutex, MT: RawMutex, const RX: usize, const LEN: usize, const MAX: usize> embedded_io::ErrorType
+    for NusStream<'_, '_, '_, M, MT, RX, LEN, MAX>
+{
+    type Error = Error;
+}
+
+impl<M: RawMutex, MT: RawMutex, const RX: usize, const LEN: usize, const MAX: usize> embedded_io_async::Read
+    for NusStream<'_, '_, '_, M, MT, RX, LEN, MAX>
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        Ok(self.nus.received.read(buf).await)
+    }
+}
+
+impl<M: RawMutex, MT: RawMutex, const RX: usize, const LEN: usize, const MAX: usize> embedded_io_async::Write
+    for NusStream<'_, '_, '_, M, MT, RX, LEN, MAX>
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let mtu = (self.connection.att_mtu() as usize).saturating_sub(ATT_HEADER_LEN);
+        let len = buf.len().min(mtu).min(LEN);
+        let value = unwrap!(Vec::from_slice(&buf[..len]).ok());
+        self.nus.tx.notify(self.server, self.connection, &value).await?;
+        Ok(len)
+    }
+}
+
+// The values of RX and TX have variable lengths, and are handled by hand.
+type RawCharacteristic = Characteristic<u8>;
+
+/// Nordic UART Service client, a stream to the server of a remote device.
+pub struct NusClient<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize> {
+    gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>,
+    rx: RawCharacteristic,
+    listener: NotificationListener<'c, L2CAP_MTU>,
+    // Notification partially read, with the offset of its remaining data.
+    pending: Option<(Notification<L2CAP_MTU>, usize)>,
+}
+
+impl<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize>
+    NusClient<'c, 'd, C, MAX_SERVICES, L2CAP_MTU>
+{
+    /// Discover the Nordic UART Service of the remote device, and subscribe to the notifications of TX.
+    ///
+    /// The GATT client must have room for one more service, and a free notification subscriber.
+    pub async fn new(gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>) -> Result<Self, BleHostError<C::Error>> {
+        let services = gatt.services_by_uuid(&NUS_SERVICE).await?;
+        let nus = services.first().cloned().ok_or(Error::NotFound)?;
+        let rx = gatt.characteristic_by_uuid(&nus, &NUS_RX).await?;
+        let tx: RawCharacteristic = gatt.characteristic_by_uuid(&nus, &NUS_TX).await?;
+        let listener = gatt.subscribe(&tx, false).await?;
+        Ok(Self {
+            gatt,
+            rx,
+            listener,
+            pending: None,
+        })
+    }
+}
+
+impl<C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize> embedded_io::ErrorType
+    for NusClient<'_, '_, C, MAX_SERVICES, L2CAP_MTU>
+{
+    type Error = BleHostError<C::Error>;
+}
+
+impl<C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize> embedded_io_async::Read
+    for NusClient<'_, '_, C, MAX_SERVICES, L2CAP_MTU>
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let (notification, offset) = loop {
+            match &mut self.pending {
+                Some(pending) => break pending,
+                None => {
+                    let notification = self.listener.next().await;
+                    if !notification.as_ref().is_empty() {
+                        self.pending = Some((notification, 0));
+                    }
+                }
+            }
+        };
+        let data = &notification.as_ref()[*offset..];
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        *offset += len;
+        if *offset == notification.as_ref().len() {
+            self.pending = None;
+        }
+        Ok(len)
+    }
+}
+
+impl<C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize> embedded_io_async::Write
+    for NusClient<'_, '_, C, MAX_SERVICES, L2CAP_MTU>
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let mtu = (self.gatt.connection().att_mtu() as usize).saturating_sub(ATT_HEADER_LEN);
+        let len = buf.len().min(mtu);
+        self.gatt
+            .write_characteristic_without_response(&self.rx, &buf[..len])
+            .await?;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+
+    #[test]
+    fn uuids() {
+        let Uuid::Uuid128(bytes) = NUS_SERVICE else {
+            panic!("unexpected uuid");
+        };
+        assert_eq!(bytes[..2], [0x9e, 0xca]);
+        assert_eq!(bytes[12..], [0x01, 0x00, 0x40, 0x6e]);
+    }
+
+    #[test]
+    fn server_buffers_writes() {
+        let mut storage: NusStorage = NusStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, NUS_ATTRIBUTE_COUNT> = AttributeTable::new();
+        let nus: NusServer<NoopRawMutex, 8> = NusServer::build(&mut table, &mut storage);
+
+        assert!(block_on(nus.process(nus.rx.handle, b"hello")));
+        assert!(!block_on(nus.process(nus.tx.handle, b"hello")));
+        let mut buf = [0; 8];
+        assert_eq!(nus.received.try_read(&mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"hello");
+    }
+
+    #[test]
+    fn stream_over_att() {
+        use embedded_io_async::{Read, Write};
+
+        let mut storage: NusStorage = NusStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, NUS_ATTRIBUTE_COUNT> = AttributeTable::new();
+        let nus: NusServer<NoopRawMutex, 8> = NusServer::build(&mut table, &mut storage);
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+        let mut stream = nus.stream(&server, client.connection());
+
+        let on_write = |handle, data: &[u8]| {
+            assert!(block_on(nus.process(handle, data)));
+            Ok(())
+        };
+        client.write(&server, nus.rx.handle, b"hello", on_write).unwrap();
+        client
+            .write_without_response(&server, nus.rx.handle, b"!", on_write)
+            .unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(block_on(stream.read(&mut buf)).unwrap(), 6);
+        assert_eq!(&buf[..6], b"hello!");
+
+        client.subscribe(&server, nus.tx.cccd_handle.unwrap(), 0x0001).unwrap();
+        let data = [0x55; 32];
+        let len = block_on(stream.write(&data)).unwrap();
+        assert_eq!(len, client.connection().att_mtu() as usize - ATT_HEADER_LEN);
+        assert_eq!(
+            client.notified().unwrap(),
+            (nus.tx.handle, Vec::from_slice(&data[..len]).unwrap())
+        );
+    }
+}
@@ -0,0 +1,699 @@
+//! Object Transfer Service, transferring the objects of a device over an L2CAP channel.
+//!
+//! The objects are listed by the [`ObjectStore`] of the application. The client selects one of
+//! them with the object list control point, reads its metadata from the characteristics of the
+//! service, and requests to read or write its data with the object action control point. The data
+//! is then transferred over a credit based L2CAP channel opened by the client on [`OTS_PSM`],
+//! which suits large objects such as files or firmware images.
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+
+use crate::att::AttErrorCode;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::l2cap::L2capChannel;
+use crate::types::uuid::Uuid;
+use crate::{BleHostError, Controller, Error, Stack};
+
+/// PSM of the L2CAP channel transferring the data of the objects.
+pub const OTS_PSM: u16 = 0x0025;
+
+/// Number of attributes added by the Object Transfer Service.
+///
+/// The service, the declaration and value of the features, name, type, size, ID and properties,
+/// and the declaration, value and CCCD of the two control points.
+pub const OTS_ATTRIBUTE_COUNT: usize = 1 + 6 * 2 + 2 * 3;
+
+/// Longest name of an object.
+pub const OBJECT_NAME_MAX_LEN: usize = 120;
+
+// Identifier of the first object, the lower ones being reserved.
+const FIRST_OBJECT_ID: u64 = 0x100;
+
+// Longest value of the control points, a write of the object action control point.
+const CONTROL_POINT_LEN: usize = 10;
+
+const OACP_READ: u8 = 0x05;
+const OACP_WRITE: u8 = 0x06;
+const OACP_RESPONSE: u8 = 0x60;
+const OACP_WRITE_MODE_TRUNCATE: u8 = 0x02;
+
+const OLCP_FIRST: u8 = 0x01;
+const OLCP_LAST: u8 = 0x02;
+const OLCP_PREVIOUS: u8 = 0x03;
+const OLCP_NEXT: u8 = 0x04;
+const OLCP_GO_TO: u8 = 0x05;
+const OLCP_REQUEST_NUMBER_OF_OBJECTS: u8 = 0x07;
+const OLCP_RESPONSE: u8 = 0x70;
+
+// Features of the server: read, write, append and truncate of the object action control point,
+// and go to and request number of objects of the object list control point.
+const OACP_FEATURES: u32 = (1 << 4) | (1 << 5) | (1 << 6) | (1 << 7);
+const OLCP_FEATURES: u32 = (1 << 0) | (1 << 2);
+
+/// Result of a procedure of the object action control point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ActionResult {
+    /// The procedure succeeded.
+    Success = 0x01,
+    /// The procedure is not supported.
+    OpcodeNotSupported = 0x02,
+    /// A parameter of the procedure is invalid, such as a range outside the object.
+    InvalidParameter = 0x03,
+    /// No object is selected.
+    InvalidObject = 0x05,
+    /// The procedure is not permitted by the properties of the object.
+    ProcedureNotPermitted = 0x08,
+    /// The object is locked by a transfer in progress.
+    ObjectLocked = 0x09,
+}
+
+/// Result of a procedure of the object list control point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ListResult {
+    /// The procedure succeeded.
+    Success = 0x01,
+    /// The procedure is not supported.
+    OpcodeNotSupported = 0x02,
+    /// A parameter of the procedure is invalid.
+    InvalidParameter = 0x03,
+    /// The procedure failed, such as moving without a selected object.
+    OperationFailed = 0x04,
+    /// The selection is at the beginning or the end of the list.
+    OutOfBounds = 0x05,
+    /// The list is empty.
+    NoObject = 0x07,
+    /// No object has the identifier.
+    ObjectIdNotFound = 0x08,
+}
+
+/// Properties of an object, listing the procedures permitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ObjectProperties(u32);
+
+impl ObjectProperties {
+    /// The object can be deleted.
+    pub const DELETE: Self = Self(1 << 0);
+    /// The object can be executed.
+    pub const EXECUTE: Self = Self(1 << 1);
+    /// The object can be read.
+    pub const READ: Self = Self(1 << 2);
+    /// The object can be written.
+    pub const WRITE: Self = Self(1 << 3);
+    /// Data can be appended to the object, up to its allocated size.
+    pub const APPEND: Self = Self(1 << 4);
+    /// The object can be truncated when written.
+    pub const TRUNCATE: Self = Self(1 << 5);
+    /// The object can be patched.
+    pub const PATCH: Self = Self(1 << 6);
+    /// The object is marked.
+    pub const MARK: Self = Self(1 << 7);
+}
+
+bitfield_set!(ObjectProperties: u32, "properties");
+
+/// Metadata of an object, exposed by the characteristics of the service once selected.
+#[derive(Debug, Clone)]
+pub struct ObjectMetadata<'a> {
+    /// Name of the object, truncated to [`OBJECT_NAME_MAX_LEN`] octets.
+    pub name: &'a str,
+    /// Type of the object.
+    pub object_type: Uuid,
+    /// Length of the data of the object.
+    pub current_size: u32,
+    /// Length of the data the object can hold.
+    pub allocated_size: u32,
+    /// Procedures permitted on the object.
+    pub properties: ObjectProperties,
+}
+
+/// Store of the objects of the device, transferred by an [`OtsServer`].
+///
+/// The objects are identified by their index in the list, from 0.
+pub trait ObjectStore {
+    /// Number of objects.
+    fn count(&self) -> usize;
+
+    /// Metadata of an object.
+    fn metadata(&self, index: usize) -> Option<ObjectMetadata<'_>>;
+
+    /// Read the data of an object from an offset, returning the length read.
+    fn read(&mut self, index: usize, offset: u32, buf: &mut [u8]) -> Result<usize, Error>;
+
+    /// Write the data of an object at an offset.
+    fn write(&mut self, index: usize, offset: u32, data: &[u8]) -> Result<(), Error>;
+
+    /// Truncate an object to a length, at the end of a write requesting it.
+    fn truncate(&mut self, index: usize, len: u32) -> Result<(), Error>;
+}
+
+/// Storage of the values of the characteristics of an [`OtsServer`].
+pub struct OtsStorage {
+    features: [u8; 8],
+    name: [u8; OBJECT_NAME_MAX_LEN],
+    object_type: [u8; 16],
+    size: [u8; 8],
+    id: [u8; 6],
+    properties: [u8; 4],
+    action: [u8; CONTROL_POINT_LEN],
+    list: [u8; CONTROL_POINT_LEN],
+}
+
+impl OtsStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            features: [0; 8],
+            name: [0; OBJECT_NAME_MAX_LEN],
+            object_type: [0; 16],
+            size: [0; 8],
+            id: [0; 6],
+            properties: [0; 4],
+            action: [0; CONTROL_POINT_LEN],
+            list: [0; CONTROL_POINT_LEN],
+        }
+    }
+}
+
+impl Default for OtsStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A write of the client to a control point, handled by [`OtsServer::process`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OtsEvent {
+    /// The client selected an object.
+    Selected(usize),
+    /// The client requested to read a range of the selected object, to be sent by
+    /// [`OtsServer::transfer`].
+    Read {
+        /// Offset of the range.
+        offset: u32,
+        /// Length of the range.
+        length: u32,
+    },
+    /// The client requested to write a range of the selected object, to be received by
+    /// [`OtsServer::transfer`].
+    Write {
+        /// Offset of the range.
+        offset: u32,
+        /// Length of the range.
+        length: u32,
+        /// Whether the object is truncated to the end of the range once written.
+        truncate: bool,
+    },
+    /// The procedure was rejected, or did not change the selection, with its result indicated
+    /// to the client.
+    Responded,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Transfer {
+    index: usize,
+    offset: u32,
+    length: u32,
+    // Whether the data is received from the client, truncating the object if set.
+    write: Option<bool>,
+}
+
+/// Object Transfer Service server.
+///
+/// The writes to the control points are handled with [`OtsServer::process`] before being
+/// accepted, after which their response is indicated with [`OtsServer::respond`]. The data of
+/// an accepted read or write is then transferred with [`OtsServer::transfer`].
+pub struct OtsServer<S: ObjectStore> {
+    name: Characteristic<Vec<u8, OBJECT_NAME_MAX_LEN>>,
+    object_type: Characteristic<Uuid>,
+    size: Characteristic<[u8; 8]>,
+    id: Characteristic<[u8; 6]>,
+    properties: Characteristic<u32>,
+    action: Characteristic<Vec<u8, CONTROL_POINT_LEN>>,
+    list: Characteristic<Vec<u8, CONTROL_POINT_LEN>>,
+    store: S,
+    selected: Option<usize>,
+    transfer: Option<Transfer>,
+    // Response of the last write to a control point, with whether it was the action control point.
+    response: Option<(bool, Vec<u8, CONTROL_POINT_LEN>)>,
+}
+
+impl<S: ObjectStore> OtsServer<S> {
+    /// Add the service to the attribute table.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut OtsStorage,
+        store: S,
+    ) -> Self {
+        let OtsStorage {
+            features,
+            name,
+            object_type,
+            size,
+            id,
+            properties,
+            action,
+            list,
+        } = storage;
+        features[..4].copy_from_slice(&OACP_FEATURES.to_le_bytes());
+        features[4..].copy_from_slice(&OLCP_FEATURES.to_le_bytes());
+        let mut service = table.add_service(Service::new(service::OBJECT_TRANSFER));
+        service
+            .add_characteristic_ro(characteristic::OTS_FEATURE, &*features)
+            .build();
+        let name = service
+            .add_characteristic(
+                characteristic::OBJECT_NAME,
+                &[CharacteristicProp::Read],
+                Vec::new(),
+                name,
+            )
+            .build();
+        let object_type = service
+            .add_characteristic(
+                characteristic::OBJECT_TYPE,
+                &[CharacteristicProp::Read],
+                Uuid::new_short(0),
+                object_type,
+            )
+            .build();
+        let size = service
+            .add_characteristic(characteristic::OBJECT_SIZE, &[CharacteristicProp::Read], [0; 8], size)
+            .build();
+        let id = service
+            .add_characteristic(characteristic::OBJECT_ID, &[CharacteristicProp::Read], [0; 6], id)
+            .build();
+        let properties = service
+            .add_characteristic(
+                characteristic::OBJECT_PROPERTIES,
+                &[CharacteristicProp::Read],
+                0,
+                properties,
+            )
+            .build();
+        let action = service
+            .add_characteristic(
+                characteristic::OBJECT_ACTION_CONTROL_POINT,
+                &[CharacteristicProp::Write, CharacteristicProp::Indicate],
+                Vec::new(),
+                action,
+            )
+            .build();
+        let list = service
+            .add_characteristic(
+                characteristic::OBJECT_LIST_CONTROL_POINT,
+                &[CharacteristicProp::Write, CharacteristicProp::Indicate],
+                Vec::new(),
+                list,
+            )
+            .build();
+        service.build();
+        Self {
+            name,
+            object_type,
+            size,
+            id,
+            properties,
+            action,
+            list,
+            store,
+            selected: None,
+            transfer: None,
+            response: None,
+        }
+    }
+
+    /// The store of the objects.
+    pub fn store(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    /// The index of the object selected, if any.
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    /// Handle a write of the client to an attribute, returning `None` if it is not one of the service.
+    ///
+    /// The metadata of an object selected is updated in the attribute table.
+    pub fn process<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        handle: u16,
+        data: &[u8],
+    ) -> Result<Option<OtsEvent>, AttErrorCode> {
+        let event = if handle == self.action.handle {
+            let opcode = data.first().copied().unwrap_or(0);
+            let (event, result) = match self.action(data) {
+                Ok(event) => (event, ActionResult::Success),
+                Err(result) => (OtsEvent::Responded, result),
+            };
+            let response = unwrap!(Vec::from_slice(&[OACP_RESPONSE, opcode, result as u8]).ok());
+            self.response = Some((true, response));
+            event
+        } else if handle == self.list.handle {
+            let opcode = data.first().copied().unwrap_or(0);
+            let mut response: Vec<u8, CONTROL_POINT_LEN> = Vec::new();
+            let (event, result) = match self.list(data) {
+                Ok(Some(index)) if self.selected != Some(index) => {
+                    self.selected = Some(index);
+                    self.update_metadata(server).map_err(|_| AttErrorCode::UNLIKELY_ERROR)?;
+                    (OtsEvent::Selected(index), ListResult::Success)
+                }
+                Ok(_) => (OtsEvent::Responded, ListResult::Success),
+                Err(result) => (OtsEvent::Responded, result),
+            };
+            unwrap!(response.extend_from_slice(&[OLCP_RESPONSE, opcode, result as u8]).ok());
+            if opcode == OLCP_REQUEST_NUMBER_OF_OBJECTS && result == ListResult::Success {
+                let count = self.store.count() as u32;
+                unwrap!(response.extend_from_slice(&count.to_le_bytes()).ok());
+            }
+            self.response = Some((false, response));
+            event
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(event))
+    }
+
+    /// Indicate the response of the last write to a control point, once accepted.
+    pub async fn respond<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+    ) -> Result<(), Error> {
+        match self.response.take() {
+            Some((true, response)) => self.action.indicate(server, connection, &response).await,
+            Some((false, response)) => self.list.indicate(server, connection, &response).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Transfer the data of the read or write accepted over the channel opened by the client,
+    /// doing nothing if none is pending.
+    ///
+    /// The data is read from or written to the store through the buffer, which must hold at
+    /// least the MTU of the channel. The size of a written object is updated once transferred.
+    pub async fn transfer<M: RawMutex, T: Controller, const MAX: usize, const TX_MTU: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        stack: &Stack<'_, T>,
+        channel: &mut L2capChannel<'_>,
+        buf: &mut [u8],
+    ) -> Result<(), BleHostError<T::Error>> {
+        let Some(transfer) = self.transfer.take() else {
+            return Ok(());
+        };
+        let end = transfer.offset + transfer.length;
+        let mut offset = transfer.offset;
+        match transfer.write {
+            None => {
+                while offset < end {
+                    let len = buf.len().min((end - offset) as usize);
+                    let len = self.store.read(transfer.index, offset, &mut buf[..len])?;
+                    if len == 0 {
+                        return Err(Error::InvalidValue.into());
+                    }
+                    channel.send::<T, TX_MTU>(stack, &buf[..len]).await?;
+                    offset += len as u32;
+                }
+            }
+            Some(truncate) => {
+                while offset < end {
+                    let len = channel.receive(stack, buf).await?;
+                    let len = len.min((end - offset) as usize);
+                    self.store.write(transfer.index, offset, &buf[..len])?;
+                    offset += len as u32;
+                }
+                if truncate {
+                    self.store.truncate(transfer.index, end)?;
+                }
+                self.update_metadata(server)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn action(&mut self, data: &[u8]) -> Result<OtsEvent, ActionResult> {
+        let (&opcode, params) = data.split_first().ok_or(ActionResult::OpcodeNotSupported)?;
+        if opcode != OACP_READ && opcode != OACP_WRITE {
+            return Err(ActionResult::OpcodeNotSupported);
+        }
+        let index = self.selected.ok_or(ActionResult::InvalidObject)?;
+        let metadata = self.store.metadata(index).ok_or(ActionResult::InvalidObject)?;
+        if self.transfer.is_some() {
+            return Err(ActionResult::ObjectLocked);
+        }
+        let [o0, o1, o2, o3, l0, l1, l2, l3, rest @ ..] = params else {
+            return Err(ActionResult::InvalidParameter);
+        };
+        let offset = u32::from_le_bytes([*o0, *o1, *o2, *o3]);
+        let length = u32::from_le_bytes([*l0, *l1, *l2, *l3]);
+        let end = offset.checked_add(length).ok_or(ActionResult::InvalidParameter)?;
+        let properties = metadata.properties;
+        let (event, write) = if opcode == OACP_READ {
+            if !properties.contains(ObjectProperties::READ) {
+                return Err(ActionResult::ProcedureNotPermitted);
+            }
+            if end > metadata.current_size {
+                return Err(ActionResult::InvalidParameter);
+            }
+            (OtsEvent::Read { offset, length }, None)
+        } else {
+            let [mode] = rest else {
+                return Err(ActionResult::InvalidParameter);
+            };
+            let truncate = mode & OACP_WRITE_MODE_TRUNCATE != 0;
+            if offset > metadata.current_size || end > metadata.allocated_size {
+                return Err(ActionResult::InvalidParameter);
+            }
+            if !properties.contains(ObjectProperties::WRITE)
+                || (truncate && !properties.contains(ObjectProperties::TRUNCATE))
+                || (end > metadata.current_size && !properties.contains(ObjectProperties::APPEND))
+            {
+                return Err(ActionResult::ProcedureNotPermitted);
+            }
+            (
+                OtsEvent::Write {
+                    offset,
+                    length,
+                    truncate,
+                },
+                Some(truncate),
+            )
+        };
+        self.transfer = Some(Transfer {
+            index,
+            offset,
+            length,
+            write,
+        });
+        Ok(event)
+    }
+
+    // The object to select, if the procedure selects one.
+    fn list(&self, data: &[u8]) -> Result<Option<usize>, ListResult> {
+        let (&opcode, params) = data.split_first().ok_or(ListResult::OpcodeNotSupported)?;
+        let count = self.store.count();
+        let index = match opcode {
+            OLCP_REQUEST_NUMBER_OF_OBJECTS => return Ok(None),
+            OLCP_FIRST | OLCP_LAST | OLCP_PREVIOUS | OLCP_NEXT | OLCP_GO_TO if count == 0 => {
+                return Err(ListResult::NoObject);
+            }
+            OLCP_FIRST => 0,
+            OLCP_LAST => count - 1,
+            OLCP_PREVIOUS => {
+                let selected = self.selected.ok_or(ListResult::OperationFailed)?;
+                selected.checked_sub(1).ok_or(ListResult::OutOfBounds)?
+            }
+            OLCP_NEXT => {
+                let selected = self.selected.ok_or(ListResult::OperationFailed)?;
+                Some(selected + 1)
+                    .filter(|&i| i < count)
+                    .ok_or(ListResult::OutOfBounds)?
+            }
+            OLCP_GO_TO => {
+                let [i0, i1, i2, i3, i4, i5] = *params else {
+                    return Err(ListResult::InvalidParameter);
+                };
+                let id = u64::from_le_bytes([i0, i1, i2, i3, i4, i5, 0, 0]);
+                id.checked_sub(FIRST_OBJECT_ID)
+                    .map(|i| i as usize)
+                    .filter(|&i| i < count)
+                    .ok_or(ListResult::ObjectIdNotFound)?
+            }
+            _ => return Err(ListResult::OpcodeNotSupported),
+        };
+        if self.transfer.is_some() {
+            return Err(ListResult::OperationFailed);
+        }
+        Ok(Some(index))
+    }
+
+    fn update_metadata<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+    ) -> Result<(), Error> {
+        let Some(index) = self.selected else {
+            return Ok(());
+        };
+        let metadata = self.store.metadata(index).ok_or(Error::NotFound)?;
+        let name = metadata.name.as_bytes();
+        let name = unwrap!(Vec::from_slice(&name[..name.len().min(OBJECT_NAME_MAX_LEN)]).ok());
+        self.name.set(server, &name)?;
+        self.object_type.set(server, &metadata.object_type)?;
+        let mut size = [0; 8];
+        size[..4].copy_from_slice(&metadata.current_size.to_le_bytes());
+        size[4..].copy_from_slice(&metadata.allocated_size.to_le_bytes());
+        self.size.set(server, &size)?;
+        let id = (FIRST_OBJECT_ID + index as u64).to_le_bytes();
+        self.id.set(server, &[id[0], id[1], id[2], id[3], id[4], id[5]])?;
+        self.properties.set(server, &metadata.properties.bits())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+
+    struct Store {
+        data: [u8; 16],
+        len: u32,
+    }
+
+    impl ObjectStore for Store {
+        fn count(&self) -> usize {
+            2
+        }
+
+        fn metadata(&self, index: usize) -> Option<ObjectMetadata<'_>> {
+            let properties = match index {
+                0 => ObjectProperties::READ,
+                1 => ObjectProperties::READ.union(ObjectProperties::WRITE),
+                _ => return None,
+            };
+            Some(ObjectMetadata {
+                name: if index == 0 { "log" } else { "firmware" },
+                object_type: Uuid::new_short(0x2aca),
+                current_size: self.len,
+                allocated_size: self.data.len() as u32,
+                properties,
+            })
+        }
+
+        fn read(&mut self, _index: usize, offset: u32, buf: &mut [u8]) -> Result<usize, Error> {
+            let data = &self.data[offset as usize..self.len as usize];
+            let len = data.len().min(buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            Ok(len)
+        }
+
+        fn write(&mut self, _index: usize, offset: u32, data: &[u8]) -> Result<(), Error> {
+            self.data[offset as usize..][..data.len()].copy_from_slice(data);
+            self.len = self.len.max(offset + data.len() as u32);
+            Ok(())
+        }
+
+        fn truncate(&mut self, _index: usize, len: u32) -> Result<(), Error> {
+            self.len = len;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn select_and_request_transfers() {
+        let mut storage = OtsStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, OTS_ATTRIBUTE_COUNT> = AttributeTable::new();
+        let mut ots = OtsServer::build(&mut table, &mut storage, Store { data: [0; 16], len: 8 });
+        let server = AttributeServer::new(table);
+        let (action, list) = (ots.action.handle, ots.list.handle);
+
+        // Reading without a selected object.
+        let read = [OACP_READ, 0, 0, 0, 0, 4, 0, 0, 0];
+        assert_eq!(ots.process(&server, action, &read), Ok(Some(OtsEvent::Responded)));
+        assert_eq!(ots.response.as_ref().unwrap().1, [OACP_RESPONSE, OACP_READ, 0x05]);
+
+        assert_eq!(
+            ots.process(&server, list, &[OLCP_LAST]),
+            Ok(Some(OtsEvent::Selected(1)))
+        );
+        assert_eq!(ots.name.get(&server).unwrap(), b"firmware");
+        assert_eq!(ots.id.get(&server).unwrap(), [0x01, 0x01, 0, 0, 0, 0]);
+        assert_eq!(ots.process(&server, list, &[OLCP_NEXT]), Ok(Some(OtsEvent::Responded)));
+        assert_eq!(ots.response.as_ref().unwrap().1, [OLCP_RESPONSE, OLCP_NEXT, 0x05]);
+        ots.process(&server, list, &[OLCP_REQUEST_NUMBER_OF_OBJECTS]).unwrap();
+        assert_eq!(
+            ots.response.as_ref().unwrap().1,
+            [OLCP_RESPONSE, OLCP_REQUEST_NUMBER_OF_OBJECTS, 0x01, 2, 0, 0, 0]
+        );
+
+        // Reading past the end of the object, then appending without permission.
+        let read = [OACP_READ, 6, 0, 0, 0, 4, 0, 0, 0];
+        assert_eq!(ots.process(&server, action, &read), Ok(Some(OtsEvent::Responded)));
+        assert_eq!(ots.response.as_ref().unwrap().1, [OACP_RESPONSE, OACP_READ, 0x03]);
+        let write = [OACP_WRITE, 6, 0, 0, 0, 4, 0, 0, 0, 0];
+        assert_eq!(ots.process(&server, action, &write), Ok(Some(OtsEvent::Responded)));
+        assert_eq!(ots.response.as_ref().unwrap().1, [OACP_RESPONSE, OACP_WRITE, 0x08]);
+
+        let write = [OACP_WRITE, 4, 0, 0, 0, 4, 0, 0, 0, 0];
+        assert_eq!(
+            ots.process(&server, action, &write),
+            Ok(Some(OtsEvent::Write {
+                offset: 4,
+                length: 4,
+                truncate: false
+            }))
+        );
+        assert_eq!(ots.response.as_ref().unwrap().1, [OACP_RESPONSE, OACP_WRITE, 0x01]);
+        // The object is locked until transferred.
+        assert_eq!(ots.process(&server, list, &[OLCP_FIRST]), Ok(Some(OtsEvent::Responded)));
+        assert_eq!(ots.selected(), Some(1));
+    }
+
+    #[test]
+    fn responses_are_indicated_over_att() {
+        let mut storage = OtsStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, OTS_ATTRIBUTE_COUNT> = AttributeTable::new();
+        let mut ots = OtsServer::build(&mut table, &mut storage, Store { data: [0; 16], len: 8 });
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+        let (list, name) = (ots.list.handle, ots.name.handle);
+
+        client
+            .subscribe(&server, ots.list.cccd_handle.unwrap(), 0x0002)
+            .unwrap();
+        let mut event = None;
+        client
+            .write(&server, list, &[OLCP_FIRST], |handle, data| {
+                event = ots.process(&server, handle, data)?;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(event, Some(OtsEvent::Selected(0)));
+        assert_eq!(client.read(&server, name).unwrap(), b"log");
+
+        block_on(ots.respond(&server, client.connection())).unwrap();
+        let indicated = client.notified().unwrap();
+        assert_eq!(indicated.0, list);
+        assert_eq!(indicated.1, [OLCP_RESPONSE, OLCP_FIRST, 0x01]);
+        client.confirm(&server);
+
+        // The metadata is read only.
+        assert_eq!(
+            client.write(&server, name, b"boot", |_, _| Ok(())),
+            Err(AttErrorCode::WRITE_NOT_PERMITTED)
+        );
+    }
+}
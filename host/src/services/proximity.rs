@@ -0,0 +1,452 @@
+//! Services of the proximity and find me profiles, alerting when a device gets out of range.
+//!
+//! The Immediate Alert Service lets a client make a device alert right away, such as keys
+//! beeping when looked for. The Link Loss Service sets how the device alerts if the connection is
+//! lost, and the TX Power Service shares the power it transmits at. A client follows the distance
+//! to a device from the loss of the path between them, the transmit power of the device less the
+//! RSSI of its packets, alerting with a [`PathLossMonitor`] when it gets too high.
+use bt_hci::cmd::status::ReadRssi;
+use bt_hci::controller::ControllerCmdSync;
+use bt_hci::param::Status;
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+
+use crate::att::AttErrorCode;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::gatt::GattClient;
+use crate::types::gatt_traits::AsGatt;
+use crate::{BleHostError, Controller, Error, Stack};
+
+/// The number of attributes added by the Immediate Alert Service
+/// IMMEDIATE_ALERT_SERVICE: 1
+/// └── ALERT_LEVEL:         2
+///                        ---
+///                        = 3
+pub const IMMEDIATE_ALERT_ATTRIBUTE_COUNT: usize = 3;
+
+/// The number of attributes added by the Link Loss Service
+/// LINK_LOSS_SERVICE: 1
+/// └── ALERT_LEVEL:   2
+///                  ---
+///                  = 3
+pub const LINK_LOSS_ATTRIBUTE_COUNT: usize = 3;
+
+/// The number of attributes added by the TX Power Service
+/// TX_POWER_SERVICE:   1
+/// └── TX_POWER_LEVEL: 2
+///                   ---
+///                   = 3
+pub const TX_POWER_ATTRIBUTE_COUNT: usize = 3;
+
+/// Level of an alert.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum AlertLevel {
+    /// No alert.
+    #[default]
+    None = 0,
+    /// A mild alert.
+    Mild = 1,
+    /// A high alert.
+    High = 2,
+}
+
+impl TryFrom<u8> for AlertLevel {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Error> {
+        match value {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Mild),
+            2 => Ok(Self::High),
+            _ => Err(Error::InvalidValue),
+        }
+    }
+}
+
+fn alert_level(data: &[u8]) -> Result<AlertLevel, AttErrorCode> {
+    match data {
+        [level] => AlertLevel::try_from(*level).map_err(|_| AttErrorCode::OUT_OF_RANGE),
+        _ => Err(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH),
+    }
+}
+
+/// Storage of the alert level of an [`ImmediateAlertServer`] or a [`LinkLossServer`].
+pub struct AlertLevelStorage {
+    level: [u8; 1],
+}
+
+impl AlertLevelStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self { level: [0] }
+    }
+}
+
+impl Default for AlertLevelStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Immediate Alert Service server.
+///
+/// The alerts written by the client are handled with [`ImmediateAlertServer::process`], the
+/// device alerting at their level until the client writes [`AlertLevel::None`] or disconnects.
+pub struct ImmediateAlertServer {
+    level: Characteristic<u8>,
+}
+
+impl ImmediateAlertServer {
+    /// Add the service to the attribute table.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut AlertLevelStorage,
+    ) -> Self {
+        let mut service = table.add_service(Service::new(service::IMMEDIATE_ALERT));
+        let level = service
+            .add_characteristic(
+                characteristic::ALERT_LEVEL,
+                &[CharacteristicProp::WriteWithoutResponse],
+                0,
+                &mut storage.level,
+            )
+            .build();
+        service.build();
+        Self { level }
+    }
+
+    /// Handle a write of the client to an attribute, returning `None` if it is not one of the service.
+    ///
+    /// The write is accepted when the level to alert at is returned, and rejected with the error
+    /// otherwise.
+    pub fn process(&self, handle: u16, data: &[u8]) -> Result<Option<AlertLevel>, AttErrorCode> {
+        if handle != self.level.handle {
+            return Ok(None);
+        }
+        alert_level(data).map(Some)
+    }
+}
+
+/// Link Loss Service server.
+///
+/// The client writes the level the device alerts at when the connection is lost, handled with
+/// [`LinkLossServer::process`]. Once disconnected, [`LinkLossServer::alert_on_disconnect`] tells
+/// whether the device should alert.
+pub struct LinkLossServer {
+    level: Characteristic<u8>,
+}
+
+impl LinkLossServer {
+    /// Add the service to the attribute table, with the level to alert at by default.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut AlertLevelStorage,
+        level: AlertLevel,
+    ) -> Self {
+        let mut service = table.add_service(Service::new(service::LINK_LOSS));
+        let level = service
+            .add_characteristic(
+                characteristic::ALERT_LEVEL,
+                &[CharacteristicProp::Read, CharacteristicProp::Write],
+                level as u8,
+                &mut storage.level,
+            )
+            .build();
+        service.build();
+        Self { level }
+    }
+
+    /// Handle a write of the client to an attribute, returning `None` if it is not one of the service.
+    ///
+    /// The write is accepted when the level is returned, and rejected with the error otherwise.
+    pub fn process(&self, handle: u16, data: &[u8]) -> Result<Option<AlertLevel>, AttErrorCode> {
+        if handle != self.level.handle {
+            return Ok(None);
+        }
+        alert_level(data).map(Some)
+    }
+
+    /// The level to alert at when the connection is lost.
+    pub fn level<M: RawMutex, const MAX: usize>(&self, server: &AttributeServer<'_, M, MAX>) -> AlertLevel {
+        self.level
+            .get(server)
+            .ok()
+            .and_then(|level| AlertLevel::try_from(level).ok())
+            .unwrap_or_default()
+    }
+
+    /// The level to alert at after a disconnection, if the connection was lost rather than closed.
+    pub fn alert_on_disconnect<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        reason: Status,
+    ) -> Option<AlertLevel> {
+        let lost = reason == Status::CONN_TIMEOUT || reason == Status::LMP_LL_RESPONSE_TIMEOUT;
+        let level = self.level(server);
+        (lost && level != AlertLevel::None).then_some(level)
+    }
+}
+
+/// Storage of the transmit power of a [`TxPowerServer`].
+pub struct TxPowerStorage {
+    level: [u8; 1],
+}
+
+impl TxPowerStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self { level: [0] }
+    }
+}
+
+impl Default for TxPowerStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// TX Power Service server.
+///
+/// The level is the power the device transmits at on the connection, in dBm, such as the one
+/// returned by [`Connection::tx_power`].
+pub struct TxPowerServer {
+    level: Characteristic<i8>,
+}
+
+impl TxPowerServer {
+    /// Add the service to the attribute table, with the transmit power.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut TxPowerStorage,
+        level: i8,
+    ) -> Self {
+        let mut service = table.add_service(Service::new(service::TX_POWER));
+        let level = service
+            .add_characteristic(
+                characteristic::TX_POWER_LEVEL,
+                &[CharacteristicProp::Read],
+                level,
+                &mut storage.level,
+            )
+            .build();
+        service.build();
+        Self { level }
+    }
+
+    /// Set the transmit power, in dBm.
+    pub fn set_level<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        level: i8,
+    ) -> Result<(), Error> {
+        self.level.set(server, &level)
+    }
+}
+
+/// Client of the proximity services of a remote device.
+///
+/// Each service is optional, the operations on a service the remote device does not have
+/// returning [`Error::NotFound`].
+pub struct ProximityClient<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize> {
+    gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>,
+    immediate_alert: Option<Characteristic<u8>>,
+    link_loss: Option<Characteristic<u8>>,
+    tx_power: Option<Characteristic<i8>>,
+}
+
+impl<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize>
+    ProximityClient<'c, 'd, C, MAX_SERVICES, L2CAP_MTU>
+{
+    /// Discover the proximity services of the remote device.
+    ///
+    /// The GATT client must have room for three more services.
+    pub async fn new(gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>) -> Result<Self, BleHostError<C::Error>> {
+        let immediate_alert = Self::discover(gatt, service::IMMEDIATE_ALERT, characteristic::ALERT_LEVEL).await?;
+        let link_loss = Self::discover(gatt, service::LINK_LOSS, characteristic::ALERT_LEVEL).await?;
+        let tx_power = Self::discover(gatt, service::TX_POWER, characteristic::TX_POWER_LEVEL).await?;
+        Ok(Self {
+            gatt,
+            immediate_alert,
+            link_loss,
+            tx_power,
+        })
+    }
+
+    async fn discover<T: AsGatt>(
+        gatt: &GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>,
+        service: bt_hci::uuid::BluetoothUuid16,
+        characteristic: bt_hci::uuid::BluetoothUuid16,
+    ) -> Result<Option<Characteristic<T>>, BleHostError<C::Error>> {
+        let services = gatt.services_by_uuid(&service.into()).await?;
+        let Some(service) = services.first().cloned() else {
+            return Ok(None);
+        };
+        let characteristic = gatt.characteristic_by_uuid(&service, &characteristic.into()).await?;
+        Ok(Some(characteristic))
+    }
+
+    /// Make the remote device alert right away, or stop alerting with [`AlertLevel::None`].
+    pub async fn alert(&self, level: AlertLevel) -> Result<(), BleHostError<C::Error>> {
+        let characteristic = self.immediate_alert.as_ref().ok_or(Error::NotFound)?;
+        self.gatt
+            .write_characteristic_without_response(characteristic, &[level as u8])
+            .await
+    }
+
+    /// Set the level the remote device alerts at if the connection is lost.
+    pub async fn set_link_loss_level(&self, level: AlertLevel) -> Result<(), BleHostError<C::Error>> {
+        let characteristic = self.link_loss.as_ref().ok_or(Error::NotFound)?;
+        self.gatt.write_characteristic(characteristic, &[level as u8]).await
+    }
+
+    /// Read the power the remote device transmits at, in dBm.
+    pub async fn read_tx_power(&self) -> Result<i8, BleHostError<C::Error>> {
+        let characteristic = self.tx_power.as_ref().ok_or(Error::NotFound)?;
+        let mut buf = [0; 1];
+        let len = self.gatt.read_characteristic(characteristic, &mut buf).await?;
+        if len == 0 {
+            return Err(Error::InvalidValue.into());
+        }
+        Ok(buf[0] as i8)
+    }
+}
+
+/// The loss of the path to a remote device, its transmit power less the RSSI of its packets, in dB.
+pub fn path_loss(tx_power: i8, rssi: i8) -> i16 {
+    tx_power as i16 - rssi as i16
+}
+
+/// Read the loss of the path to the remote device of a connection, in dB, from its transmit power.
+pub async fn read_path_loss<T>(
+    connection: &Connection<'_>,
+    stack: &Stack<'_, T>,
+    tx_power: i8,
+) -> Result<i16, BleHostError<T::Error>>
+where
+    T: ControllerCmdSync<ReadRssi>,
+{
+    let rssi = connection.rssi(stack).await?;
+    Ok(path_loss(tx_power, rssi))
+}
+
+/// Monitor of the loss of the path to a remote device, alerting while it is too far.
+///
+/// The alert starts when the path loss goes above the threshold, and stops once it goes back
+/// below the threshold less the hysteresis, so that the alert does not flicker at the threshold.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PathLossMonitor {
+    threshold: i16,
+    hysteresis: u8,
+    level: AlertLevel,
+    alerting: bool,
+}
+
+impl PathLossMonitor {
+    /// Create a monitor alerting at a level above a path loss threshold, in dB.
+    pub const fn new(threshold: i16, hysteresis: u8, level: AlertLevel) -> Self {
+        Self {
+            threshold,
+            hysteresis,
+            level,
+            alerting: false,
+        }
+    }
+
+    /// Whether the monitor is alerting.
+    pub fn alerting(&self) -> bool {
+        self.alerting
+    }
+
+    /// Update the monitor with a path loss, returning the level to alert at if it changed.
+    ///
+    /// The level is typically written to the remote device with [`ProximityClient::alert`], so
+    /// that both devices alert.
+    pub fn update(&mut self, path_loss: i16) -> Option<AlertLevel> {
+        if !self.alerting && path_loss > self.threshold {
+            self.alerting = true;
+            Some(self.level)
+        } else if self.alerting && path_loss < self.threshold - self.hysteresis as i16 {
+            self.alerting = false;
+            Some(AlertLevel::None)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+
+    #[test]
+    fn link_loss_alerts_when_lost() {
+        let mut storage = AlertLevelStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, LINK_LOSS_ATTRIBUTE_COUNT> = AttributeTable::new();
+        let lls = LinkLossServer::build(&mut table, &mut storage, AlertLevel::Mild);
+        let server = AttributeServer::new(table);
+        let handle = lls.level.handle;
+        assert_eq!(lls.process(handle, &[2]), Ok(Some(AlertLevel::High)));
+        assert_eq!(lls.process(handle, &[3]), Err(AttErrorCode::OUT_OF_RANGE));
+        assert_eq!(
+            lls.alert_on_disconnect(&server, Status::CONN_TIMEOUT),
+            Some(AlertLevel::Mild)
+        );
+        assert_eq!(
+            lls.alert_on_disconnect(&server, Status::REMOTE_USER_TERMINATED_CONN),
+            None
+        );
+    }
+
+    #[test]
+    fn client_writes_alert_levels() {
+        let mut alert_storage = AlertLevelStorage::new();
+        let mut link_loss_storage = AlertLevelStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 6> = AttributeTable::new();
+        let ias = ImmediateAlertServer::build(&mut table, &mut alert_storage);
+        let lls = LinkLossServer::build(&mut table, &mut link_loss_storage, AlertLevel::Mild);
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+        let on_write = |handle, data: &[u8]| match (ias.process(handle, data), lls.process(handle, data)) {
+            (Err(e), _) | (_, Err(e)) => Err(e),
+            _ => Ok(()),
+        };
+
+        client
+            .write_without_response(&server, ias.level.handle, &[2], on_write)
+            .unwrap();
+        // The immediate alert is not readable.
+        assert_eq!(
+            client.read(&server, ias.level.handle),
+            Err(AttErrorCode::READ_NOT_PERMITTED)
+        );
+
+        assert_eq!(client.read(&server, lls.level.handle).unwrap(), [1]);
+        assert_eq!(
+            client.write(&server, lls.level.handle, &[3], on_write),
+            Err(AttErrorCode::OUT_OF_RANGE)
+        );
+        client.write(&server, lls.level.handle, &[0], on_write).unwrap();
+        assert_eq!(lls.level(&server), AlertLevel::None);
+        assert_eq!(lls.alert_on_disconnect(&server, Status::CONN_TIMEOUT), None);
+    }
+
+    #[test]
+    fn path_loss_alerts_with_hysteresis() {
+        assert_eq!(path_loss(4, -70), 74);
+        let mut monitor = PathLossMonitor::new(80, 6, AlertLevel::High);
+        assert_eq!(monitor.update(74), None);
+        assert_eq!(monitor.update(85), Some(AlertLevel::High));
+        assert_eq!(monitor.update(77), None);
+        assert!(monitor.alerting());
+        assert_eq!(monitor.update(73), Some(AlertLevel::None));
+        assert!(!monitor.alerting());
+    }
+}
@@ -0,0 +1,417 @@
+//! Record Access Control Point, giving a client access to the records stored by a health sensor.
+//!
+//! The client writes a procedure to the control point, selecting records by their sequence
+//! number: the server reports them one notification at a time, deletes them from its
+//! [`RecordStore`], or reports how many there are. The response of the procedure is then
+//! indicated. A single procedure is in progress at a time, though a report can be aborted by the
+//! client before it completes.
+//!
+//! The control point is shared by several services, such as the Glucose Service, each notifying
+//! its own records. It can be added to other services with [`RecordAccess::add`].
+use core::ops::Range;
+
+use bt_hci::uuid::characteristic;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+
+use crate::Error;
+use crate::att::AttErrorCode;
+use crate::attribute::{Characteristic, CharacteristicProp, ServiceBuilder};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+
+const OP_REPORT_RECORDS: u8 = 0x01;
+const OP_DELETE_RECORDS: u8 = 0x02;
+const OP_ABORT: u8 = 0x03;
+const OP_REPORT_NUMBER_OF_RECORDS: u8 = 0x04;
+const OP_NUMBER_OF_RECORDS_RESPONSE: u8 = 0x05;
+const OP_RESPONSE: u8 = 0x06;
+
+const OPERATOR_NULL: u8 = 0x00;
+const OPERATOR_ALL: u8 = 0x01;
+const OPERATOR_LESS_OR_EQUAL: u8 = 0x02;
+const OPERATOR_GREATER_OR_EQUAL: u8 = 0x03;
+const OPERATOR_WITHIN_RANGE: u8 = 0x04;
+const OPERATOR_FIRST: u8 = 0x05;
+const OPERATOR_LAST: u8 = 0x06;
+
+const FILTER_SEQUENCE_NUMBER: u8 = 0x01;
+
+// Longest write, selecting a range of sequence numbers.
+const CONTROL_POINT_LEN: usize = 7;
+
+/// Result of a procedure, indicated in its response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum RecordAccessResult {
+    /// The procedure completed.
+    Success = 0x01,
+    /// The procedure is not supported.
+    OpCodeNotSupported = 0x02,
+    /// The operator is not valid for the procedure.
+    InvalidOperator = 0x03,
+    /// The operator is not supported.
+    OperatorNotSupported = 0x04,
+    /// The operand is not valid.
+    InvalidOperand = 0x05,
+    /// No record matches the operator.
+    NoRecordsFound = 0x06,
+    /// The procedure could not be aborted.
+    AbortUnsuccessful = 0x07,
+    /// The procedure could not complete, such as a report aborted.
+    ProcedureNotCompleted = 0x08,
+    /// The filter of the operand is not supported.
+    OperandNotSupported = 0x09,
+}
+
+/// Store of the records of a sensor, accessed through a [`RecordAccess`].
+///
+/// The records are indexed from the oldest, with increasing sequence numbers.
+pub trait RecordStore {
+    /// Number of records stored.
+    fn count(&self) -> usize;
+
+    /// Sequence number of a record.
+    fn sequence_number(&self, index: usize) -> u16;
+
+    /// Delete records, the following ones taking their indexes.
+    fn delete(&mut self, records: Range<usize>) -> Result<(), Error>;
+}
+
+/// A procedure written by the client to the control point, once accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordAccessEvent {
+    /// The client requested a report of records, notified by the server of the service.
+    Report(Range<usize>),
+    /// The client deleted records from the store.
+    Deleted(Range<usize>),
+    /// The procedure only needs a response, for instance to abort a report.
+    Responded,
+}
+
+/// Storage of the value of a [`RecordAccess`].
+pub struct RecordAccessStorage {
+    value: [u8; CONTROL_POINT_LEN],
+}
+
+impl RecordAccessStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            value: [0; CONTROL_POINT_LEN],
+        }
+    }
+}
+
+impl Default for RecordAccessStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Record Access Control Point of a service.
+///
+/// The writes of the client are handled with [`RecordAccess::process`] before being accepted.
+/// The records of a report are then taken with [`RecordAccess::next_record`] and notified by the
+/// service one at a time, handling the writes of the client in between so that it can abort the
+/// report. The response is indicated with [`RecordAccess::respond`] once there is none left.
+pub struct RecordAccess {
+    pub(crate) characteristic: Characteristic<Vec<u8, CONTROL_POINT_LEN>>,
+    // Records left to report.
+    report: Option<Range<usize>>,
+    // Response of the procedure in progress.
+    response: Option<Vec<u8, CONTROL_POINT_LEN>>,
+}
+
+impl RecordAccess {
+    /// Add the control point to a service.
+    pub fn add<'d, M: RawMutex, const MAX: usize>(
+        service: &mut ServiceBuilder<'_, 'd, M, MAX>,
+        storage: &'d mut RecordAccessStorage,
+    ) -> Self {
+        let characteristic = service
+            .add_characteristic(
+                characteristic::RECORD_ACCESS_CONTROL_POINT,
+                &[CharacteristicProp::Write, CharacteristicProp::Indicate],
+                Vec::new(),
+                &mut storage.value,
+            )
+            .build();
+        Self {
+            characteristic,
+            report: None,
+            response: None,
+        }
+    }
+
+    /// Whether a procedure is in progress, until its response is indicated.
+    pub fn in_progress(&self) -> bool {
+        self.report.is_some() || self.response.is_some()
+    }
+
+    /// Handle a write of the client to an attribute, returning `None` if it is not the control point.
+    ///
+    /// The write is accepted when the event is returned, and rejected with the error otherwise.
+    pub fn process<M: RawMutex, const MAX: usize, S: RecordStore>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        handle: u16,
+        data: &[u8],
+        store: &mut S,
+    ) -> Result<Option<RecordAccessEvent>, AttErrorCode> {
+        if handle != self.characteristic.handle {
+            return Ok(None);
+        }
+        if self
+            .characteristic
+            .cccd_handle
+            .is_none_or(|cccd| !server.should_notify(connection, cccd))
+        {
+            return Err(AttErrorCode::CCCD_IMPROPERLY_CONFIGURED);
+        }
+        self.perform(data, store).map(Some)
+    }
+
+    fn perform<S: RecordStore>(&mut self, data: &[u8], store: &mut S) -> Result<RecordAccessEvent, AttErrorCode> {
+        let [op, operator, operand @ ..] = data else {
+            return Err(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH);
+        };
+        if *op == OP_ABORT {
+            if self.report.is_none() && self.response.is_some() {
+                return Err(AttErrorCode::PROCEDURE_ALREADY_IN_PROGRESS);
+            }
+            let result = if *operator != OPERATOR_NULL || !operand.is_empty() {
+                RecordAccessResult::InvalidOperator
+            } else {
+                // The response of the report aborted is not indicated.
+                self.report = None;
+                RecordAccessResult::Success
+            };
+            self.response = Some(Self::response(*op, result));
+            return Ok(RecordAccessEvent::Responded);
+        }
+        if self.in_progress() {
+            return Err(AttErrorCode::PROCEDURE_ALREADY_IN_PROGRESS);
+        }
+        let records = match *op {
+            OP_REPORT_RECORDS | OP_DELETE_RECORDS | OP_REPORT_NUMBER_OF_RECORDS => {
+                Self::select(*operator, operand, store)
+            }
+            _ => Err(RecordAccessResult::OpCodeNotSupported),
+        };
+        let (event, response) = match (records, *op) {
+            (Ok(records), OP_REPORT_NUMBER_OF_RECORDS) => {
+                let [c0, c1] = (records.len().min(u16::MAX as usize) as u16).to_le_bytes();
+                let response = unwrap!(Vec::from_slice(&[OP_NUMBER_OF_RECORDS_RESPONSE, OPERATOR_NULL, c0, c1]).ok());
+                (RecordAccessEvent::Responded, response)
+            }
+            (Ok(records), _) if records.is_empty() => (
+                RecordAccessEvent::Responded,
+                Self::response(*op, RecordAccessResult::NoRecordsFound),
+            ),
+            (Ok(records), OP_REPORT_RECORDS) => {
+                self.report = Some(records.clone());
+                (
+                    RecordAccessEvent::Report(records),
+                    Self::response(*op, RecordAccessResult::Success),
+                )
+            }
+            (Ok(records), _) => match store.delete(records.clone()) {
+                Ok(()) => (
+                    RecordAccessEvent::Deleted(records),
+                    Self::response(*op, RecordAccessResult::Success),
+                ),
+                Err(_) => (
+                    RecordAccessEvent::Responded,
+                    Self::response(*op, RecordAccessResult::ProcedureNotCompleted),
+                ),
+            },
+            (Err(result), _) => (RecordAccessEvent::Responded, Self::response(*op, result)),
+        };
+        self.response = Some(response);
+        Ok(event)
+    }
+
+    /// The records selected by an operator and its operand.
+    fn select<S: RecordStore>(operator: u8, operand: &[u8], store: &S) -> Result<Range<usize>, RecordAccessResult> {
+        let count = store.count();
+        // Index of the first record with a sequence number not before the given one.
+        let position = |sequence_number: u16| {
+            let (mut low, mut high) = (0, count);
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if store.sequence_number(mid) < sequence_number {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            low
+        };
+        match operator {
+            OPERATOR_ALL | OPERATOR_FIRST | OPERATOR_LAST if !operand.is_empty() => {
+                Err(RecordAccessResult::InvalidOperand)
+            }
+            OPERATOR_ALL => Ok(0..count),
+            OPERATOR_FIRST => Ok(0..count.min(1)),
+            OPERATOR_LAST => Ok(count.saturating_sub(1)..count),
+            OPERATOR_LESS_OR_EQUAL | OPERATOR_GREATER_OR_EQUAL | OPERATOR_WITHIN_RANGE => {
+                let (min, max) = match (operator, operand) {
+                    (_, [filter, ..]) if *filter != FILTER_SEQUENCE_NUMBER => {
+                        return Err(RecordAccessResult::OperandNotSupported);
+                    }
+                    (OPERATOR_LESS_OR_EQUAL, &[_, m0, m1]) => (0, u16::from_le_bytes([m0, m1])),
+                    (OPERATOR_GREATER_OR_EQUAL, &[_, m0, m1]) => (u16::from_le_bytes([m0, m1]), u16::MAX),
+                    (OPERATOR_WITHIN_RANGE, &[_, m0, m1, n0, n1]) => {
+                        (u16::from_le_bytes([m0, m1]), u16::from_le_bytes([n0, n1]))
+                    }
+                    _ => return Err(RecordAccessResult::InvalidOperand),
+                };
+                if min > max {
+                    return Err(RecordAccessResult::InvalidOperand);
+                }
+                Ok(position(min)..max.checked_add(1).map_or(count, position))
+            }
+            OPERATOR_NULL => Err(RecordAccessResult::InvalidOperator),
+            _ => Err(RecordAccessResult::OperatorNotSupported),
+        }
+    }
+
+    fn response(op: u8, result: RecordAccessResult) -> Vec<u8, CONTROL_POINT_LEN> {
+        unwrap!(Vec::from_slice(&[OP_RESPONSE, OPERATOR_NULL, op, result as u8]).ok())
+    }
+
+    /// The index of the next record of the report in progress, to be notified.
+    pub fn next_record(&mut self) -> Option<usize> {
+        let report = self.report.as_mut()?;
+        let next = report.next();
+        if report.start == report.end {
+            self.report = None;
+        }
+        next
+    }
+
+    /// Report the failure of the report in progress, such as a record failing to be notified.
+    pub fn fail(&mut self) {
+        if self.report.take().is_some() {
+            self.response = Some(Self::response(
+                OP_REPORT_RECORDS,
+                RecordAccessResult::ProcedureNotCompleted,
+            ));
+        }
+    }
+
+    /// Indicate the response of the procedure in progress once all records are reported, ending it.
+    pub async fn respond<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+    ) -> Result<(), Error> {
+        if self.report.is_some() {
+            return Ok(());
+        }
+        match self.response.take() {
+            Some(response) => self.characteristic.indicate(server, connection, &response).await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::attribute::{AttributeTable, Service};
+
+    struct Records(Vec<u16, 8>);
+
+    impl RecordStore for Records {
+        fn count(&self) -> usize {
+            self.0.len()
+        }
+
+        fn sequence_number(&self, index: usize) -> u16 {
+            self.0[index]
+        }
+
+        fn delete(&mut self, records: Range<usize>) -> Result<(), Error> {
+            let kept: Vec<u16, 8> = self
+                .0
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !records.contains(i))
+                .map(|(_, s)| *s)
+                .collect();
+            self.0 = kept;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn procedures_select_records() {
+        let mut storage = RecordAccessStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 4> = AttributeTable::new();
+        let mut service = table.add_service(Service::new(bt_hci::uuid::service::GLUCOSE));
+        let mut racp = RecordAccess::add(&mut service, &mut storage);
+        service.build();
+        let mut store = Records(Vec::from_slice(&[3, 4, 7, 9, 10]).unwrap());
+
+        // Report the records from 4 to 9, aborted after the first one.
+        assert_eq!(
+            racp.perform(&[0x01, 0x04, 0x01, 4, 0, 9, 0], &mut store),
+            Ok(RecordAccessEvent::Report(1..4))
+        );
+        assert_eq!(
+            racp.perform(&[0x04, 0x01], &mut store),
+            Err(AttErrorCode::PROCEDURE_ALREADY_IN_PROGRESS)
+        );
+        assert_eq!(racp.next_record(), Some(1));
+        assert_eq!(
+            racp.perform(&[0x03, 0x00], &mut store),
+            Ok(RecordAccessEvent::Responded)
+        );
+        assert_eq!(racp.next_record(), None);
+        assert_eq!(racp.response.take().unwrap(), [0x06, 0x00, 0x03, 0x01]);
+
+        assert_eq!(
+            racp.perform(&[0x04, 0x03, 0x01, 8, 0], &mut store),
+            Ok(RecordAccessEvent::Responded)
+        );
+        assert_eq!(racp.response.take().unwrap(), [0x05, 0x00, 2, 0]);
+
+        assert_eq!(
+            racp.perform(&[0x02, 0x02, 0x01, 4, 0], &mut store),
+            Ok(RecordAccessEvent::Deleted(0..2))
+        );
+        assert_eq!(&store.0[..], &[7, 9, 10]);
+        racp.response = None;
+
+        assert_eq!(
+            racp.perform(&[0x01, 0x06], &mut store),
+            Ok(RecordAccessEvent::Report(2..3))
+        );
+        assert_eq!(racp.next_record(), Some(2));
+        assert_eq!(racp.next_record(), None);
+        assert!(racp.report.is_none());
+        assert_eq!(racp.response.take().unwrap(), [0x06, 0x00, 0x01, 0x01]);
+
+        assert_eq!(
+            racp.perform(&[0x01, 0x02, 0x02, 0, 0], &mut store),
+            Ok(RecordAccessEvent::Responded)
+        );
+        assert_eq!(racp.response.take().unwrap(), [0x06, 0x00, 0x01, 0x09]);
+        assert_eq!(
+            racp.perform(&[0x01, 0x02, 0x01, 5, 0], &mut store),
+            Ok(RecordAccessEvent::Responded)
+        );
+        assert_eq!(racp.response.take().unwrap(), [0x06, 0x00, 0x01, 0x06]);
+        assert_eq!(
+            racp.perform(&[0x07, 0x00], &mut store),
+            Ok(RecordAccessEvent::Responded)
+        );
+        assert_eq!(racp.response.take().unwrap(), [0x06, 0x00, 0x07, 0x02]);
+    }
+}
@@ -0,0 +1,367 @@
+//! Running Speed and Cadence Service, notifying the speed and cadence measured by a foot pod.
+//!
+//! Each measurement holds the speed and the cadence, along with the length of the strides and the
+//! total distance if the sensor measures them. The client sets the total distance, calibrates the
+//! sensor, and moves it to another location, through the [control point](super::sc_control_point).
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+
+use super::sc_control_point::{
+    ScControlPoint, ScControlPointEvent, ScControlPointStorage, ScProcedures, SensorLocation,
+};
+use crate::Error;
+use crate::att::AttErrorCode;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::cursor::WriteCursor;
+
+const FLAG_STRIDE_LENGTH: u8 = 0x01;
+const FLAG_TOTAL_DISTANCE: u8 = 0x02;
+const FLAG_RUNNING: u8 = 0x04;
+
+const FEATURE_STRIDE_LENGTH: u16 = 0x0001;
+const FEATURE_TOTAL_DISTANCE: u16 = 0x0002;
+const FEATURE_WALKING_OR_RUNNING: u16 = 0x0004;
+const FEATURE_CALIBRATION: u16 = 0x0008;
+const FEATURE_MULTIPLE_SENSOR_LOCATIONS: u16 = 0x0010;
+
+const MEASUREMENT_LEN: usize = 10;
+
+/// A measurement of the speed and cadence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RscMeasurement {
+    /// Speed, in units of 1/256 m/s.
+    pub speed: u16,
+    /// Cadence, in steps per minute.
+    pub cadence: u8,
+    /// Length of the strides, in units of 0.01 m, if supported.
+    pub stride_length: Option<u16>,
+    /// Distance since the sensor was set up, in units of 0.1 m, if supported.
+    pub total_distance: Option<u32>,
+    /// Whether the user is running rather than walking, if supported.
+    pub running: bool,
+}
+
+impl RscMeasurement {
+    /// Encode the measurement, returning the length written.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut flags = 0;
+        if self.stride_length.is_some() {
+            flags |= FLAG_STRIDE_LENGTH;
+        }
+        if self.total_distance.is_some() {
+            flags |= FLAG_TOTAL_DISTANCE;
+        }
+        if self.running {
+            flags |= FLAG_RUNNING;
+        }
+        let mut w = WriteCursor::new(buf);
+        w.write(flags)?;
+        w.write(self.speed)?;
+        w.write(self.cadence)?;
+        if let Some(stride_length) = self.stride_length {
+            w.write(stride_length)?;
+        }
+        if let Some(total_distance) = self.total_distance {
+            w.write(total_distance)?;
+        }
+        Ok(w.len())
+    }
+}
+
+/// Configuration of the Running Speed and Cadence Service.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunningSpeedCadenceConfig<'a> {
+    /// Whether the measurements include the length of the strides.
+    pub stride_length: bool,
+    /// Whether the measurements include the total distance, set with the control point.
+    pub total_distance: bool,
+    /// Whether the measurements tell whether the user is walking or running.
+    pub walking_or_running: bool,
+    /// Whether the client can calibrate the sensor with the control point.
+    pub calibration: bool,
+    /// Location of the sensor, if known.
+    pub location: Option<SensorLocation>,
+    /// Locations the client can move the sensor to with the control point, if several.
+    ///
+    /// The sensor is at the first one if its location is not given.
+    pub supported_locations: &'a [SensorLocation],
+}
+
+impl RunningSpeedCadenceConfig<'_> {
+    /// Number of attributes added by the service.
+    pub const fn attribute_count(&self) -> usize {
+        // The service, the declaration, value and CCCD of the measurement, and the feature.
+        let mut count = 1 + 3 + 2;
+        if self.location.is_some() || !self.supported_locations.is_empty() {
+            count += 2;
+        }
+        if self.procedures().any() {
+            count += 3;
+        }
+        count
+    }
+
+    /// Value of the feature, listing the data and procedures supported.
+    pub const fn features(&self) -> u16 {
+        let mut features = 0;
+        if self.stride_length {
+            features |= FEATURE_STRIDE_LENGTH;
+        }
+        if self.total_distance {
+            features |= FEATURE_TOTAL_DISTANCE;
+        }
+        if self.walking_or_running {
+            features |= FEATURE_WALKING_OR_RUNNING;
+        }
+        if self.calibration {
+            features |= FEATURE_CALIBRATION;
+        }
+        if !self.supported_locations.is_empty() {
+            features |= FEATURE_MULTIPLE_SENSOR_LOCATIONS;
+        }
+        features
+    }
+
+    const fn procedures(&self) -> ScProcedures<'_> {
+        ScProcedures {
+            cumulative_value: self.total_distance,
+            calibration: self.calibration,
+            locations: self.supported_locations,
+        }
+    }
+}
+
+/// Storage of the values of the characteristics of a [`RunningSpeedCadenceServer`].
+pub struct RunningSpeedCadenceStorage {
+    measurement: [u8; MEASUREMENT_LEN],
+    features: [u8; 2],
+    control_point: ScControlPointStorage,
+}
+
+impl RunningSpeedCadenceStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            measurement: [0; MEASUREMENT_LEN],
+            features: [0; 2],
+            control_point: ScControlPointStorage::new(),
+        }
+    }
+}
+
+impl Default for RunningSpeedCadenceStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Running Speed and Cadence Service server.
+///
+/// The measurements are notified with [`RunningSpeedCadenceServer::notify_measurement`], while
+/// the writes to the control point are handled with [`RunningSpeedCadenceServer::process`]
+/// before being accepted, after which their response is indicated with
+/// [`RunningSpeedCadenceServer::respond`]. The response of a calibration is indicated once it
+/// completed, after reporting its failure with [`RunningSpeedCadenceServer::fail`] if needed.
+pub struct RunningSpeedCadenceServer {
+    measurement: Characteristic<Vec<u8, MEASUREMENT_LEN>>,
+    location: Option<Characteristic<u8>>,
+    control_point: Option<ScControlPoint>,
+    stride_length: bool,
+    total_distance: bool,
+}
+
+impl RunningSpeedCadenceServer {
+    /// Add the service to the attribute table.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut RunningSpeedCadenceStorage,
+        config: RunningSpeedCadenceConfig<'_>,
+    ) -> Self {
+        let RunningSpeedCadenceStorage {
+            measurement,
+            features,
+            control_point,
+        } = storage;
+        *features = config.features().to_le_bytes();
+        let mut service = table.add_service(Service::new(service::RUNNING_SPEED_AND_CADENCE));
+        let measurement = service
+            .add_characteristic(
+                characteristic::RSC_MEASUREMENT,
+                &[CharacteristicProp::Notify],
+                Vec::new(),
+                measurement,
+            )
+            .build();
+        service
+            .add_characteristic_ro(characteristic::RSC_FEATURE, &*features)
+            .build();
+        let (location, control_point) =
+            ScControlPoint::build(&mut service, control_point, config.location, config.procedures());
+        service.build();
+        Self {
+            measurement,
+            location,
+            control_point,
+            stride_length: config.stride_length,
+            total_distance: config.total_distance,
+        }
+    }
+
+    /// The sensor location characteristic, if any.
+    pub fn location(&self) -> Option<&Characteristic<u8>> {
+        self.location.as_ref()
+    }
+
+    /// Handle a write of the client to an attribute, returning `None` if it is not one of the service.
+    ///
+    /// The write is accepted when the event is returned, and rejected with the error otherwise.
+    pub fn process<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        handle: u16,
+        data: &[u8],
+    ) -> Result<Option<ScControlPointEvent>, AttErrorCode> {
+        match &mut self.control_point {
+            Some(control_point) => control_point.process(server, connection, handle, data),
+            None => Ok(None),
+        }
+    }
+
+    /// Report the failure of the procedure accepted, before its response is indicated.
+    pub fn fail(&mut self) {
+        if let Some(control_point) = &mut self.control_point {
+            control_point.fail();
+        }
+    }
+
+    /// Indicate the response of the procedure accepted, ending it.
+    pub async fn respond<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+    ) -> Result<(), Error> {
+        match &mut self.control_point {
+            Some(control_point) => control_point.respond(server, connection).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Notify a measurement to the client, if subscribed.
+    ///
+    /// Returns [`Error::InvalidValue`] if the measurement includes data not supported by the
+    /// service.
+    pub async fn notify_measurement<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        measurement: &RscMeasurement,
+    ) -> Result<(), Error> {
+        if (measurement.stride_length.is_some() && !self.stride_length)
+            || (measurement.total_distance.is_some() && !self.total_distance)
+        {
+            return Err(Error::InvalidValue);
+        }
+        let mut buf = [0; MEASUREMENT_LEN];
+        let len = measurement.encode(&mut buf)?;
+        let value = unwrap!(Vec::from_slice(&buf[..len]).ok());
+        self.measurement.notify(server, connection, &value).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+    use crate::services::sc_control_point::ERROR_PROCEDURE_ALREADY_IN_PROGRESS;
+
+    #[test]
+    fn encode_measurements() {
+        let mut buf = [0; MEASUREMENT_LEN];
+        let measurement = RscMeasurement {
+            speed: 0x0380,
+            cadence: 170,
+            stride_length: None,
+            total_distance: Some(12345),
+            running: true,
+        };
+        let len = measurement.encode(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &[0x06, 0x80, 0x03, 170, 0x39, 0x30, 0x00, 0x00]);
+
+        let config = RunningSpeedCadenceConfig {
+            total_distance: true,
+            walking_or_running: true,
+            calibration: true,
+            location: Some(SensorLocation::InShoe),
+            ..Default::default()
+        };
+        assert_eq!(config.features(), 0x000e);
+        assert_eq!(config.attribute_count(), 11);
+        let mut storage = RunningSpeedCadenceStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 11> = AttributeTable::new();
+        let rscs = RunningSpeedCadenceServer::build(&mut table, &mut storage, config);
+        assert!(rscs.control_point.is_some());
+        assert_eq!(
+            table.get(rscs.location().unwrap()).unwrap(),
+            SensorLocation::InShoe as u8
+        );
+    }
+
+    #[test]
+    fn failed_calibration_is_indicated() {
+        let config = RunningSpeedCadenceConfig {
+            calibration: true,
+            ..Default::default()
+        };
+        let mut storage = RunningSpeedCadenceStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 9> = AttributeTable::new();
+        let mut rscs = RunningSpeedCadenceServer::build(&mut table, &mut storage, config);
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+        let (handle, cccd_handle) = {
+            let control_point = &rscs.control_point.as_ref().unwrap().characteristic;
+            (control_point.handle, control_point.cccd_handle.unwrap())
+        };
+        client.subscribe(&server, cccd_handle, 0x0002).unwrap();
+
+        let mut event = None;
+        client
+            .write(&server, handle, &[0x02], |handle, data| {
+                event = rscs.process(&server, client.connection(), handle, data)?;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(event, Some(ScControlPointEvent::StartCalibration));
+        // Another procedure waits for the calibration to complete.
+        assert_eq!(
+            client.write(&server, handle, &[0x02], |handle, data| {
+                rscs.process(&server, client.connection(), handle, data).map(|_| ())
+            }),
+            Err(ERROR_PROCEDURE_ALREADY_IN_PROGRESS)
+        );
+
+        rscs.fail();
+        block_on(rscs.respond(&server, client.connection())).unwrap();
+        assert_eq!(client.notified().unwrap().1, [0x10, 0x02, 0x04]);
+        client.confirm(&server);
+        // The stride length is not supported.
+        let measurement = RscMeasurement {
+            speed: 0x0380,
+            cadence: 170,
+            stride_length: Some(100),
+            total_distance: None,
+            running: false,
+        };
+        assert!(matches!(
+            block_on(rscs.notify_measurement(&server, client.connection(), &measurement)),
+            Err(Error::InvalidValue)
+        ));
+    }
+}
@@ -0,0 +1,359 @@
+//! Speed and Cadence Control Point, shared by the cycling and running speed and cadence services.
+//!
+//! The client writes a procedure to the control point, such as setting the cumulative value of
+//! the sensor or moving it to another location, and the server indicates its response. A single
+//! procedure is in progress at a time, from the write until its response is indicated.
+use bt_hci::uuid::characteristic;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+
+use crate::Error;
+use crate::att::AttErrorCode;
+use crate::attribute::{Characteristic, CharacteristicProp, ServiceBuilder};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+
+/// Error of a write to the control point while the previous procedure is in progress.
+pub const ERROR_PROCEDURE_ALREADY_IN_PROGRESS: AttErrorCode = AttErrorCode::application(0x80);
+/// Error of a write to the control point while the client did not enable its indications.
+pub const ERROR_CCCD_IMPROPERLY_CONFIGURED: AttErrorCode = AttErrorCode::application(0x81);
+
+const OP_SET_CUMULATIVE_VALUE: u8 = 0x01;
+const OP_START_CALIBRATION: u8 = 0x02;
+const OP_UPDATE_SENSOR_LOCATION: u8 = 0x03;
+const OP_REQUEST_SUPPORTED_SENSOR_LOCATIONS: u8 = 0x04;
+const OP_RESPONSE: u8 = 0x10;
+
+const RESULT_SUCCESS: u8 = 0x01;
+const RESULT_OP_CODE_NOT_SUPPORTED: u8 = 0x02;
+const RESULT_INVALID_PARAMETER: u8 = 0x03;
+const RESULT_OPERATION_FAILED: u8 = 0x04;
+
+/// Number of sensor locations.
+pub const SENSOR_LOCATION_COUNT: usize = 17;
+
+// Longest response, listing all sensor locations.
+const CONTROL_POINT_LEN: usize = 3 + SENSOR_LOCATION_COUNT;
+
+/// Location of a sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum SensorLocation {
+    /// Another location.
+    Other = 0,
+    /// The top of a shoe.
+    TopOfShoe = 1,
+    /// In a shoe.
+    InShoe = 2,
+    /// The hip.
+    Hip = 3,
+    /// The front wheel.
+    FrontWheel = 4,
+    /// The left crank.
+    LeftCrank = 5,
+    /// The right crank.
+    RightCrank = 6,
+    /// The left pedal.
+    LeftPedal = 7,
+    /// The right pedal.
+    RightPedal = 8,
+    /// The front hub.
+    FrontHub = 9,
+    /// The rear dropout.
+    RearDropout = 10,
+    /// The chainstay.
+    Chainstay = 11,
+    /// The rear wheel.
+    RearWheel = 12,
+    /// The rear hub.
+    RearHub = 13,
+    /// The chest.
+    Chest = 14,
+    /// The spider.
+    Spider = 15,
+    /// The chain ring.
+    ChainRing = 16,
+}
+
+impl TryFrom<u8> for SensorLocation {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Error> {
+        Ok(match value {
+            0 => Self::Other,
+            1 => Self::TopOfShoe,
+            2 => Self::InShoe,
+            3 => Self::Hip,
+            4 => Self::FrontWheel,
+            5 => Self::LeftCrank,
+            6 => Self::RightCrank,
+            7 => Self::LeftPedal,
+            8 => Self::RightPedal,
+            9 => Self::FrontHub,
+            10 => Self::RearDropout,
+            11 => Self::Chainstay,
+            12 => Self::RearWheel,
+            13 => Self::RearHub,
+            14 => Self::Chest,
+            15 => Self::Spider,
+            16 => Self::ChainRing,
+            _ => return Err(Error::InvalidValue),
+        })
+    }
+}
+
+/// A procedure written by the client to the control point, once accepted.
+///
+/// The response of the procedure is then indicated by the server of the service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScControlPointEvent {
+    /// The client set the cumulative value of the sensor, to be counted from.
+    ///
+    /// The value is the wheel revolutions of a cycling sensor, or the total distance of a running
+    /// sensor in units of 0.1 m.
+    SetCumulativeValue(u32),
+    /// The client started the calibration of the sensor, responded once it completed.
+    StartCalibration,
+    /// The client moved the sensor to another location, updated in its characteristic.
+    SensorLocationUpdated(SensorLocation),
+    /// The procedure only needs a response, for instance as it is not supported.
+    Responded,
+}
+
+/// Procedures supported by the control point.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ScProcedures<'a> {
+    pub(crate) cumulative_value: bool,
+    pub(crate) calibration: bool,
+    pub(crate) locations: &'a [SensorLocation],
+}
+
+impl ScProcedures<'_> {
+    pub(crate) const fn any(&self) -> bool {
+        self.cumulative_value || self.calibration || !self.locations.is_empty()
+    }
+}
+
+pub(crate) struct ScControlPointStorage {
+    control_point: [u8; CONTROL_POINT_LEN],
+    location: [u8; 1],
+}
+
+impl ScControlPointStorage {
+    pub(crate) const fn new() -> Self {
+        Self {
+            control_point: [0; CONTROL_POINT_LEN],
+            location: [0; 1],
+        }
+    }
+}
+
+/// Add the sensor location, if known or the sensor supports several locations.
+fn add_sensor_location<'d, M: RawMutex, const MAX: usize>(
+    service: &mut ServiceBuilder<'_, 'd, M, MAX>,
+    storage: &'d mut [u8; 1],
+    location: Option<SensorLocation>,
+    locations: &[SensorLocation],
+) -> Option<Characteristic<u8>> {
+    let location = location.or(locations.first().copied())?;
+    Some(
+        service
+            .add_characteristic(
+                characteristic::SENSOR_LOCATION,
+                &[CharacteristicProp::Read],
+                location as u8,
+                storage,
+            )
+            .build(),
+    )
+}
+
+/// The control point of a service, with its sensor location.
+pub(crate) struct ScControlPoint {
+    pub(crate) characteristic: Characteristic<Vec<u8, CONTROL_POINT_LEN>>,
+    location: Option<Characteristic<u8>>,
+    cumulative_value: bool,
+    calibration: bool,
+    locations: Vec<SensorLocation, SENSOR_LOCATION_COUNT>,
+    // Response of the procedure in progress.
+    response: Option<Vec<u8, CONTROL_POINT_LEN>>,
+}
+
+impl ScControlPoint {
+    /// Add the sensor location and the control point, if any procedure is supported.
+    pub(crate) fn build<'d, M: RawMutex, const MAX: usize>(
+        service: &mut ServiceBuilder<'_, 'd, M, MAX>,
+        storage: &'d mut ScControlPointStorage,
+        location: Option<SensorLocation>,
+        procedures: ScProcedures<'_>,
+    ) -> (Option<Characteristic<u8>>, Option<Self>) {
+        let ScControlPointStorage {
+            control_point,
+            location: location_storage,
+        } = storage;
+        let location = add_sensor_location(service, location_storage, location, procedures.locations);
+        if !procedures.any() {
+            return (location, None);
+        }
+        let characteristic = service
+            .add_characteristic(
+                characteristic::SC_CONTROL_POINT,
+                &[CharacteristicProp::Write, CharacteristicProp::Indicate],
+                Vec::new(),
+                control_point,
+            )
+            .build();
+        let mut locations = Vec::new();
+        for l in procedures.locations.iter().take(SENSOR_LOCATION_COUNT) {
+            unwrap!(locations.push(*l).ok());
+        }
+        let control_point = Self {
+            characteristic,
+            location,
+            cumulative_value: procedures.cumulative_value,
+            calibration: procedures.calibration,
+            locations,
+            response: None,
+        };
+        (location, Some(control_point))
+    }
+
+    /// Handle a write of the client to an attribute, returning `None` if it is not the control point.
+    pub(crate) fn process<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        handle: u16,
+        data: &[u8],
+    ) -> Result<Option<ScControlPointEvent>, AttErrorCode> {
+        if handle != self.characteristic.handle {
+            return Ok(None);
+        }
+        if self
+            .characteristic
+            .cccd_handle
+            .is_none_or(|cccd| !server.should_notify(connection, cccd))
+        {
+            return Err(ERROR_CCCD_IMPROPERLY_CONFIGURED);
+        }
+        self.perform(server, data).map(Some)
+    }
+
+    fn perform<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        data: &[u8],
+    ) -> Result<ScControlPointEvent, AttErrorCode> {
+        if self.response.is_some() {
+            return Err(ERROR_PROCEDURE_ALREADY_IN_PROGRESS);
+        }
+        let [op, parameter @ ..] = data else {
+            return Err(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH);
+        };
+        let mut response = unwrap!(Vec::from_slice(&[OP_RESPONSE, *op, RESULT_SUCCESS]).ok());
+        let (event, result) = match (*op, parameter) {
+            (OP_SET_CUMULATIVE_VALUE, &[v0, v1, v2, v3]) if self.cumulative_value => (
+                ScControlPointEvent::SetCumulativeValue(u32::from_le_bytes([v0, v1, v2, v3])),
+                RESULT_SUCCESS,
+            ),
+            (OP_START_CALIBRATION, []) if self.calibration => (ScControlPointEvent::StartCalibration, RESULT_SUCCESS),
+            (OP_UPDATE_SENSOR_LOCATION, &[location]) if !self.locations.is_empty() => {
+                match SensorLocation::try_from(location) {
+                    Ok(location) if self.locations.contains(&location) => {
+                        if let Some(c) = &self.location {
+                            c.set(server, &(location as u8))
+                                .map_err(|_| AttErrorCode::UNLIKELY_ERROR)?;
+                        }
+                        (ScControlPointEvent::SensorLocationUpdated(location), RESULT_SUCCESS)
+                    }
+                    _ => (ScControlPointEvent::Responded, RESULT_INVALID_PARAMETER),
+                }
+            }
+            (OP_REQUEST_SUPPORTED_SENSOR_LOCATIONS, []) if !self.locations.is_empty() => {
+                for l in self.locations.iter() {
+                    unwrap!(response.push(*l as u8).ok());
+                }
+                (ScControlPointEvent::Responded, RESULT_SUCCESS)
+            }
+            (OP_SET_CUMULATIVE_VALUE, _) if self.cumulative_value => {
+                (ScControlPointEvent::Responded, RESULT_INVALID_PARAMETER)
+            }
+            (OP_UPDATE_SENSOR_LOCATION | OP_REQUEST_SUPPORTED_SENSOR_LOCATIONS, _) if !self.locations.is_empty() => {
+                (ScControlPointEvent::Responded, RESULT_INVALID_PARAMETER)
+            }
+            (OP_START_CALIBRATION, _) if self.calibration => (ScControlPointEvent::Responded, RESULT_INVALID_PARAMETER),
+            _ => (ScControlPointEvent::Responded, RESULT_OP_CODE_NOT_SUPPORTED),
+        };
+        response[2] = result;
+        self.response = Some(response);
+        Ok(event)
+    }
+
+    /// Report the failure of the procedure in progress, such as a calibration.
+    pub(crate) fn fail(&mut self) {
+        if let Some(response) = &mut self.response {
+            response.truncate(3);
+            response[2] = RESULT_OPERATION_FAILED;
+        }
+    }
+
+    /// Indicate the response of the procedure in progress, ending it.
+    pub(crate) async fn respond<M: RawMutex, const MAX: usize>(
+        &mut self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+    ) -> Result<(), Error> {
+        match self.response.take() {
+            Some(response) => self.characteristic.indicate(server, connection, &response).await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::attribute::{AttributeTable, Service};
+
+    #[test]
+    fn procedures_respond() {
+        let mut storage = ScControlPointStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, 6> = AttributeTable::new();
+        let procedures = ScProcedures {
+            cumulative_value: true,
+            locations: &[SensorLocation::LeftCrank, SensorLocation::RightCrank],
+            ..Default::default()
+        };
+        let mut service = table.add_service(Service::new(bt_hci::uuid::service::CYCLING_SPEED_AND_CADENCE));
+        let (location, control_point) = ScControlPoint::build(&mut service, &mut storage, None, procedures);
+        service.build();
+        let (location, mut cp) = (location.unwrap(), control_point.unwrap());
+        let server = AttributeServer::<_, 6>::new(table);
+        assert_eq!(location.get(&server).unwrap(), SensorLocation::LeftCrank as u8);
+
+        assert_eq!(
+            cp.perform(&server, &[0x01, 0x10, 0x00, 0x00, 0x00]),
+            Ok(ScControlPointEvent::SetCumulativeValue(16))
+        );
+        assert_eq!(cp.perform(&server, &[0x04]), Err(ERROR_PROCEDURE_ALREADY_IN_PROGRESS));
+        cp.response = None;
+
+        assert_eq!(
+            cp.perform(&server, &[0x03, 0x06]),
+            Ok(ScControlPointEvent::SensorLocationUpdated(SensorLocation::RightCrank))
+        );
+        assert_eq!(location.get(&server).unwrap(), SensorLocation::RightCrank as u8);
+        cp.response = None;
+
+        assert_eq!(cp.perform(&server, &[0x03, 0x04]), Ok(ScControlPointEvent::Responded));
+        assert_eq!(cp.response.take().unwrap(), [0x10, 0x03, 0x03]);
+        assert_eq!(cp.perform(&server, &[0x04]), Ok(ScControlPointEvent::Responded));
+        assert_eq!(cp.response.take().unwrap(), [0x10, 0x04, 0x01, 0x05, 0x06]);
+        assert_eq!(cp.perform(&server, &[0x02]), Ok(ScControlPointEvent::Responded));
+        assert_eq!(cp.response.take().unwrap(), [0x10, 0x02, 0x02]);
+    }
+}
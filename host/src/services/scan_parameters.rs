@@ -0,0 +1,277 @@
+//! Scan Parameters Service, through which a client tells the server how it scans.
+//!
+//! The client writes the interval and the window of its scanning, from which a server such as a
+//! HID device knows how long it takes the client to find it when advertising to reconnect. The
+//! server asks the client to write them again through the scan refresh, for instance when it
+//! needs the latest ones before choosing how to advertise.
+use bt_hci::uuid::{characteristic, service};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::Duration;
+
+use crate::att::AttErrorCode;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::cursor::ReadCursor;
+use crate::gatt::{GattClient, NotificationListener};
+use crate::{BleHostError, Controller, Error};
+
+const REFRESH_REQUIRED: u8 = 0x00;
+
+/// Interval and window of the scanning of a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ScanIntervalWindow {
+    /// Scan interval.
+    pub interval: Duration,
+    /// Scan window, no longer than the interval.
+    pub window: Duration,
+}
+
+impl ScanIntervalWindow {
+    /// Decode the interval and the window written by a client.
+    ///
+    /// Returns [`Error::InvalidValue`] if they are out of 2.5 ms to 10.24 s, or if the window is
+    /// longer than the interval.
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        if data.len() != 4 {
+            return Err(Error::InvalidValue);
+        }
+        let mut r = ReadCursor::new(data);
+        let interval: u16 = r.read()?;
+        let window: u16 = r.read()?;
+        if !(0x0004..=0x4000).contains(&interval) || !(0x0004..=interval).contains(&window) {
+            return Err(Error::InvalidValue);
+        }
+        Ok(Self {
+            interval: Duration::from_micros(interval as u64 * 625),
+            window: Duration::from_micros(window as u64 * 625),
+        })
+    }
+
+    /// Encode the interval and the window, in units of 0.625 ms.
+    pub fn to_bytes(&self) -> [u8; 4] {
+        let interval = bt_hci::param::Duration::<625>::from(self.interval).as_u16();
+        let window = bt_hci::param::Duration::<625>::from(self.window).as_u16();
+        let [a, b] = interval.to_le_bytes();
+        let [c, d] = window.to_le_bytes();
+        [a, b, c, d]
+    }
+}
+
+/// Number of attributes of the Scan Parameters Service, with or without the scan refresh.
+pub const fn scps_attribute_count(refresh: bool) -> usize {
+    // The service, the declaration and value of the scan interval window, and the declaration,
+    // value and CCCD of the scan refresh.
+    1 + 2 + if refresh { 3 } else { 0 }
+}
+
+/// Storage of the values of the characteristics of a [`ScanParametersServer`].
+pub struct ScanParametersStorage {
+    interval_window: [u8; 4],
+    refresh: [u8; 1],
+}
+
+impl ScanParametersStorage {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self {
+            interval_window: [0; 4],
+            refresh: [0; 1],
+        }
+    }
+}
+
+impl Default for ScanParametersStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A write of the client, handled by [`ScanParametersServer::process`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScanParametersEvent {
+    /// The client wrote how it scans.
+    IntervalWindow(ScanIntervalWindow),
+}
+
+/// Scan Parameters Service server.
+///
+/// The writes of the client are handled with [`ScanParametersServer::process`] before being
+/// accepted, and the client is asked to write them again with [`ScanParametersServer::refresh`].
+pub struct ScanParametersServer {
+    interval_window: Characteristic<[u8; 4]>,
+    refresh: Option<Characteristic<u8>>,
+}
+
+impl ScanParametersServer {
+    /// Add the service to the attribute table, with the scan refresh if the server may need the
+    /// latest scan parameters.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut ScanParametersStorage,
+        refresh: bool,
+    ) -> Self {
+        let ScanParametersStorage {
+            interval_window,
+            refresh: refresh_store,
+        } = storage;
+        let mut service = table.add_service(Service::new(service::SCAN_PARAMETERS));
+        let interval_window = service
+            .add_characteristic(
+                characteristic::SCAN_INTERVAL_WINDOW,
+                &[CharacteristicProp::WriteWithoutResponse],
+                [0; 4],
+                interval_window,
+            )
+            .build();
+        let refresh = refresh.then(|| {
+            service
+                .add_characteristic(
+                    characteristic::SCAN_REFRESH,
+                    &[CharacteristicProp::Notify],
+                    REFRESH_REQUIRED,
+                    refresh_store,
+                )
+                .build()
+        });
+        service.build();
+        Self {
+            interval_window,
+            refresh,
+        }
+    }
+
+    /// Handle a write of the client to an attribute, returning `None` if it is not one of the service.
+    ///
+    /// The write is accepted when the event is returned, and ignored with the error otherwise, the
+    /// client not expecting a response.
+    pub fn process(&self, handle: u16, data: &[u8]) -> Result<Option<ScanParametersEvent>, AttErrorCode> {
+        if handle != self.interval_window.handle {
+            return Ok(None);
+        }
+        let interval_window = ScanIntervalWindow::decode(data).map_err(|_| AttErrorCode::VALUE_NOT_ALLOWED)?;
+        Ok(Some(ScanParametersEvent::IntervalWindow(interval_window)))
+    }
+
+    /// Ask the client to write its scan parameters again, if subscribed.
+    ///
+    /// Returns [`Error::NotSupported`] if the scan refresh is not supported by the service.
+    pub async fn refresh<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+    ) -> Result<(), Error> {
+        let refresh = self.refresh.as_ref().ok_or(Error::NotSupported)?;
+        refresh.notify(server, connection, &REFRESH_REQUIRED).await
+    }
+}
+
+/// Scan Parameters Service client, telling a remote device how the local device scans.
+pub struct ScanParametersClient<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize> {
+    gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>,
+    interval_window: Characteristic<[u8; 4]>,
+    refresh: Option<Characteristic<u8>>,
+}
+
+impl<'c, 'd, C: Controller, const MAX_SERVICES: usize, const L2CAP_MTU: usize>
+    ScanParametersClient<'c, 'd, C, MAX_SERVICES, L2CAP_MTU>
+{
+    /// Discover the Scan Parameters Service of the remote device.
+    ///
+    /// The GATT client must have room for one more service.
+    pub async fn new(gatt: &'c GattClient<'d, C, MAX_SERVICES, L2CAP_MTU>) -> Result<Self, BleHostError<C::Error>> {
+        let services = gatt.services_by_uuid(&service::SCAN_PARAMETERS.into()).await?;
+        let scps = services.first().cloned().ok_or(Error::NotFound)?;
+        let interval_window = gatt
+            .characteristic_by_uuid(&scps, &characteristic::SCAN_INTERVAL_WINDOW.into())
+            .await?;
+        let refresh = match gatt
+            .characteristic_by_uuid(&scps, &characteristic::SCAN_REFRESH.into())
+            .await
+        {
+            Ok(c) => Some(c),
+            Err(BleHostError::BleHost(Error::NotFound)) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            gatt,
+            interval_window,
+            refresh,
+        })
+    }
+
+    /// Tell the remote device how the local device scans.
+    pub async fn write_interval_window(
+        &self,
+        interval_window: &ScanIntervalWindow,
+    ) -> Result<(), BleHostError<C::Error>> {
+        self.gatt
+            .write_characteristic_without_response(&self.interval_window, &interval_window.to_bytes())
+            .await
+    }
+
+    /// Subscribe to the scan refresh, returning `None` if the remote device does not support it.
+    ///
+    /// The GATT client must have a free notification subscriber.
+    pub async fn subscribe_refresh(
+        &self,
+    ) -> Result<Option<ScanRefreshListener<'c, L2CAP_MTU>>, BleHostError<C::Error>> {
+        let Some(refresh) = &self.refresh else {
+            return Ok(None);
+        };
+        let listener = self.gatt.subscribe(refresh, false).await?;
+        Ok(Some(ScanRefreshListener { listener }))
+    }
+}
+
+/// Listener of the scan refreshes asked by a remote device.
+pub struct ScanRefreshListener<'c, const L2CAP_MTU: usize> {
+    listener: NotificationListener<'c, L2CAP_MTU>,
+}
+
+impl<const L2CAP_MTU: usize> ScanRefreshListener<'_, L2CAP_MTU> {
+    /// Wait for the remote device to ask for the scan parameters, to be written again with
+    /// [`ScanParametersClient::write_interval_window`].
+    pub async fn next(&mut self) {
+        while self.listener.next().await.as_ref() != [REFRESH_REQUIRED] {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+
+    #[test]
+    fn interval_window_writes() {
+        let interval_window = ScanIntervalWindow {
+            interval: Duration::from_millis(60),
+            window: Duration::from_millis(30),
+        };
+        assert_eq!(interval_window.to_bytes(), [0x60, 0x00, 0x30, 0x00]);
+        assert_eq!(
+            ScanIntervalWindow::decode(&interval_window.to_bytes()).unwrap(),
+            interval_window
+        );
+        // The window is longer than the interval.
+        assert!(ScanIntervalWindow::decode(&[0x30, 0x00, 0x60, 0x00]).is_err());
+        assert!(ScanIntervalWindow::decode(&[0x02, 0x00, 0x02, 0x00]).is_err());
+
+        let mut storage = ScanParametersStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, { scps_attribute_count(true) }> = AttributeTable::new();
+        let scps = ScanParametersServer::build(&mut table, &mut storage, true);
+        let handle = scps.interval_window.handle;
+        assert_eq!(
+            scps.process(handle, &[0x60, 0x00, 0x30, 0x00]),
+            Ok(Some(ScanParametersEvent::IntervalWindow(interval_window)))
+        );
+        assert_eq!(
+            scps.process(handle, &[0x60, 0x00]),
+            Err(AttErrorCode::VALUE_NOT_ALLOWED)
+        );
+        assert_eq!(scps.process(scps.refresh.unwrap().handle, &[0x00]), Ok(None));
+    }
+}
@@ -0,0 +1,363 @@
+//! Simple Management Protocol service, the Bluetooth transport of mcumgr.
+//!
+//! The service is the one of Zephyr, so that the mcumgr tools can manage the device, for instance
+//! to upload a firmware image. Each request of the client is a frame, an [`SmpHeader`] followed
+//! by its CBOR payload, written in as many writes as needed to fit the ATT MTU. The server
+//! reassembles the frames, leaving the handling of their payload to the application, and notifies
+//! the frames of the responses in as many notifications as needed.
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use heapless::Vec;
+
+use crate::Error;
+use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+use crate::attribute_server::AttributeServer;
+use crate::connection::Connection;
+use crate::types::uuid::Uuid;
+
+/// UUID of the SMP service.
+pub const SMP_SERVICE: Uuid = Uuid::new_long(0x8d53dc1d_1db7_4cd3_868b_8a527460aa84_u128.to_le_bytes());
+
+/// UUID of the SMP characteristic, written with the requests and notifying the responses.
+pub const SMP_CHARACTERISTIC: Uuid = Uuid::new_long(0xda2e7828_fbce_4e01_ae9e_261174997c48_u128.to_le_bytes());
+
+/// Number of attributes added by the SMP service.
+///
+/// The service, and the declaration, value and CCCD of the characteristic.
+pub const SMP_ATTRIBUTE_COUNT: usize = 4;
+
+/// Length of the header of a frame.
+pub const SMP_HEADER_LEN: usize = 8;
+
+// Header of a notification, before the data.
+const ATT_HEADER_LEN: usize = 3;
+
+/// Group of the commands of the operating system, such as echo and reset.
+pub const GROUP_OS: u16 = 0;
+/// Group of the commands of the firmware images, such as upload.
+pub const GROUP_IMAGE: u16 = 1;
+/// Group of the commands of the statistics.
+pub const GROUP_STATS: u16 = 2;
+/// Group of the commands of the settings.
+pub const GROUP_SETTINGS: u16 = 3;
+/// Group of the commands of the file system.
+pub const GROUP_FS: u16 = 8;
+/// Group of the commands of the shell.
+pub const GROUP_SHELL: u16 = 9;
+
+/// Operation of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum SmpOp {
+    /// Request to read.
+    Read = 0,
+    /// Response to a read.
+    ReadResponse = 1,
+    /// Request to write.
+    Write = 2,
+    /// Response to a write.
+    WriteResponse = 3,
+}
+
+/// Header of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SmpHeader {
+    /// Operation of the frame.
+    pub op: SmpOp,
+    /// Version of the protocol, 1 for the current one and 0 for the original one.
+    pub version: u8,
+    /// Flags of the frame.
+    pub flags: u8,
+    /// Length of the payload following the header.
+    pub len: u16,
+    /// Group of the command.
+    pub group: u16,
+    /// Sequence number of the request, repeated in its response.
+    pub seq: u8,
+    /// Identifier of the command in its group.
+    pub id: u8,
+}
+
+impl SmpHeader {
+    /// Decode the header at the beginning of a frame.
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let [op, flags, l0, l1, g0, g1, seq, id, ..] = *data else {
+            return Err(Error::InvalidValue);
+        };
+        let op = match op & 0x07 {
+            0 => SmpOp::Read,
+            1 => SmpOp::ReadResponse,
+            2 => SmpOp::Write,
+            3 => SmpOp::WriteResponse,
+            _ => return Err(Error::InvalidValue),
+        };
+        Ok(Self {
+            op,
+            version: (data[0] >> 3) & 0x03,
+            flags,
+            len: u16::from_be_bytes([l0, l1]),
+            group: u16::from_be_bytes([g0, g1]),
+            seq,
+            id,
+        })
+    }
+
+    /// Encode the header, its fields being big endian.
+    pub fn to_bytes(&self) -> [u8; SMP_HEADER_LEN] {
+        let [l0, l1] = self.len.to_be_bytes();
+        let [g0, g1] = self.group.to_be_bytes();
+        [
+            self.op as u8 | ((self.version & 0x03) << 3),
+            self.flags,
+            l0,
+            l1,
+            g0,
+            g1,
+            self.seq,
+            self.id,
+        ]
+    }
+
+    /// The header of the response to a request, with the length of its payload.
+    pub fn response(&self, len: u16) -> Self {
+        let op = match self.op {
+            SmpOp::Read | SmpOp::ReadResponse => SmpOp::ReadResponse,
+            SmpOp::Write | SmpOp::WriteResponse => SmpOp::WriteResponse,
+        };
+        Self { op, len, ..*self }
+    }
+}
+
+/// A request reassembled by [`SmpServer::process`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SmpRequest<'a> {
+    /// Header of the request.
+    pub header: SmpHeader,
+    /// CBOR payload of the request.
+    pub payload: &'a [u8],
+}
+
+/// Storage of the value of the characteristic of an [`SmpServer`].
+///
+/// The value holds up to `LEN` octets, which defaults to the largest ATT MTU of 247 less the
+/// header of a notification.
+pub struct SmpStorage<const LEN: usize = 244> {
+    value: [u8; LEN],
+}
+
+impl<const LEN: usize> SmpStorage<LEN> {
+    /// Create the storage.
+    pub const fn new() -> Self {
+        Self { value: [0; LEN] }
+    }
+}
+
+impl<const LEN: usize> Default for SmpStorage<LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// SMP service server.
+///
+/// The writes of the client are handled with [`SmpServer::process`], reassembling frames of up
+/// to `FRAME` octets, the longer ones being dropped. The default fits the upload of a firmware
+/// image in chunks of the size used by the mcumgr tools over Bluetooth.
+pub struct SmpServer<const FRAME: usize = 512, const LEN: usize = 244> {
+    characteristic: Characteristic<Vec<u8, LEN>>,
+    frame: Vec<u8, FRAME>,
+    // Whether the frame is complete, and returned by the last write.
+    complete: bool,
+    // Length left to drop of a frame too long for the buffer.
+    dropping: usize,
+}
+
+impl<const FRAME: usize, const LEN: usize> SmpServer<FRAME, LEN> {
+    /// Add the service to the attribute table.
+    pub fn build<'d, M: RawMutex, const MAX: usize>(
+        table: &mut AttributeTable<'d, M, MAX>,
+        storage: &'d mut SmpStorage<LEN>,
+    ) -> Self {
+        let mut service = table.add_service(Service::new(SMP_SERVICE));
+        let characteristic = service
+            .add_characteristic(
+                SMP_CHARACTERISTIC,
+                &[CharacteristicProp::WriteWithoutResponse, CharacteristicProp::Notify],
+                Vec::new(),
+                &mut storage.value,
+            )
+            .build();
+        service.build();
+        Self {
+            characteristic,
+            frame: Vec::new(),
+            complete: false,
+            dropping: 0,
+        }
+    }
+
+    /// Handle a write of the client to an attribute, returning the request once reassembled.
+    ///
+    /// Returns `None` if the write is not one of the service, or the request is not complete yet.
+    pub fn process(&mut self, handle: u16, data: &[u8]) -> Option<SmpRequest<'_>> {
+        if handle != self.characteristic.handle {
+            return None;
+        }
+        if self.complete {
+            self.frame.clear();
+            self.complete = false;
+        }
+        if self.dropping > 0 {
+            self.dropping = self.dropping.saturating_sub(data.len());
+            return None;
+        }
+        if self.frame.is_empty() {
+            // The frame begins with its header, giving its length.
+            let Ok(header) = SmpHeader::decode(data) else {
+                warn!("[smp] dropping write without header");
+                return None;
+            };
+            let len = SMP_HEADER_LEN + header.len as usize;
+            if len > FRAME {
+                warn!("[smp] dropping frame of {} octets", len);
+                self.dropping = len.saturating_sub(data.len());
+                return None;
+            }
+        }
+        if self.frame.extend_from_slice(data).is_err() {
+            warn!("[smp] dropping frame longer than its header");
+            self.frame.clear();
+            return None;
+        }
+        let header = SmpHeader::decode(&self.frame).ok()?;
+        let len = SMP_HEADER_LEN + header.len as usize;
+        if self.frame.len() < len {
+            return None;
+        }
+        if self.frame.len() > len {
+            warn!("[smp] dropping frame longer than its header");
+            self.frame.clear();
+            return None;
+        }
+        self.complete = true;
+        Some(SmpRequest {
+            header,
+            payload: &self.frame[SMP_HEADER_LEN..],
+        })
+    }
+
+    /// Notify the response to a request to the client, with its CBOR payload.
+    ///
+    /// The frame is split into notifications fitting the storage and the ATT MTU of the connection.
+    pub async fn respond<M: RawMutex, const MAX: usize>(
+        &self,
+        server: &AttributeServer<'_, M, MAX>,
+        connection: &Connection<'_>,
+        request: &SmpHeader,
+        payload: &[u8],
+    ) -> Result<(), Error> {
+        let len = u16::try_from(payload.len()).map_err(|_| Error::InsufficientSpace)?;
+        let header = request.response(len).to_bytes();
+        let mtu = LEN.min((connection.att_mtu() as usize).saturating_sub(ATT_HEADER_LEN));
+        if mtu == 0 {
+            return Err(Error::InsufficientSpace);
+        }
+        let mut data = header.iter().chain(payload).copied().peekable();
+        while data.peek().is_some() {
+            let chunk: Vec<u8, LEN> = data.by_ref().take(mtu).collect();
+            self.characteristic.notify(server, connection, &chunk).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_client::MockClient;
+
+    #[test]
+    fn header_roundtrip() {
+        // Echo request of the operating system group, with the version 1 of the protocol.
+        let data = [0x0a, 0x00, 0x00, 0x06, 0x00, 0x00, 0x2a, 0x00];
+        let header = SmpHeader::decode(&data).unwrap();
+        assert_eq!(header.op, SmpOp::Write);
+        assert_eq!(header.version, 1);
+        assert_eq!(header.len, 6);
+        assert_eq!(header.group, GROUP_OS);
+        assert_eq!(header.seq, 0x2a);
+        assert_eq!(header.to_bytes(), data);
+        assert_eq!(
+            header.response(3).to_bytes(),
+            [0x0b, 0x00, 0x00, 0x03, 0x00, 0x00, 0x2a, 0x00]
+        );
+    }
+
+    #[test]
+    fn reassemble_requests() {
+        let mut storage: SmpStorage = SmpStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, SMP_ATTRIBUTE_COUNT> = AttributeTable::new();
+        let mut smp: SmpServer<16> = SmpServer::build(&mut table, &mut storage);
+        let handle = smp.characteristic.handle;
+
+        let header = [0x0a, 0x00, 0x00, 0x04, 0x00, 0x01, 0x01, 0x01];
+        assert_eq!(smp.process(handle, &header), None);
+        assert_eq!(smp.process(handle, &[1, 2]), None);
+        let request = smp.process(handle, &[3, 4]).unwrap();
+        assert_eq!(request.header.group, GROUP_IMAGE);
+        assert_eq!(request.payload, &[1, 2, 3, 4]);
+
+        // A frame too long for the buffer is dropped with its writes.
+        let header = [0x0a, 0x00, 0x00, 0x10, 0x00, 0x01, 0x02, 0x01];
+        assert_eq!(smp.process(handle, &header), None);
+        assert_eq!(smp.process(handle, &[0; 16]), None);
+        let header = [0x0a, 0x00, 0x00, 0x01, 0x00, 0x00, 0x03, 0x00];
+        assert_eq!(smp.process(handle, &header), None);
+        assert_eq!(smp.process(handle, &[5]).unwrap().payload, &[5]);
+    }
+
+    #[test]
+    fn echo_over_att() {
+        let mut storage: SmpStorage = SmpStorage::new();
+        let mut table: AttributeTable<'_, NoopRawMutex, SMP_ATTRIBUTE_COUNT> = AttributeTable::new();
+        let mut smp: SmpServer<64> = SmpServer::build(&mut table, &mut storage);
+        let server = AttributeServer::new(table);
+        let client = MockClient::new();
+        let handle = smp.characteristic.handle;
+        client
+            .subscribe(&server, smp.characteristic.cccd_handle.unwrap(), 0x0001)
+            .unwrap();
+
+        let mut request = [0; SMP_HEADER_LEN + 24];
+        request[..SMP_HEADER_LEN].copy_from_slice(&[0x02, 0x00, 0x00, 0x18, 0x00, 0x00, 0x07, 0x00]);
+        let mut header = None;
+        for chunk in request.chunks(20) {
+            client
+                .write_without_response(&server, handle, chunk, |handle, data| {
+                    header = smp.process(handle, data).map(|request| request.header);
+                    Ok(())
+                })
+                .unwrap();
+        }
+        let header = header.unwrap();
+        assert_eq!(header.op, SmpOp::Write);
+        assert_eq!(header.seq, 7);
+
+        // The response is split to the ATT MTU of the connection.
+        block_on(smp.respond(&server, client.connection(), &header, &[0xa5; 24])).unwrap();
+        let first = client.notified().unwrap();
+        assert_eq!(first.0, handle);
+        assert_eq!(
+            &first.1[..SMP_HEADER_LEN],
+            [0x03, 0x00, 0x00, 0x18, 0x00, 0x00, 0x07, 0x00]
+        );
+        assert_eq!(first.1.len(), 20);
+        assert_eq!(client.notified().unwrap().1.len(), 12);
+        assert!(client.notified().is_none());
+    }
+}
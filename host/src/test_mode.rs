@@ -0,0 +1,169 @@
+//! Direct test mode, for RF testing of the controller.
+//!
+//! Tests are started with [`Stack::start_transmitter_test`] or [`Stack::start_receiver_test`] and
+//! run until [`Stack::end_test`], while the host is not advertising, scanning or connected.
+use bt_hci::cmd::le::LeTestEnd;
+use bt_hci::controller::ControllerCmdSync;
+
+use crate::hci::{LeReceiverTestV1, LeReceiverTestV2, LeTransmitterTestV1, LeTransmitterTestV2, LeTransmitterTestV4};
+use crate::{BleHostError, Controller, Error, Stack};
+
+/// Highest RF channel, numbered from 2402 MHz in steps of 2 MHz.
+const MAX_CHANNEL: u8 = 39;
+
+/// Payload of the test packets sent by the transmitter.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TestPayload {
+    /// Pseudo-random bit sequence 9.
+    Prbs9 = 0x00,
+    /// Repeated `11110000`.
+    Pattern11110000 = 0x01,
+    /// Repeated `10101010`.
+    Pattern10101010 = 0x02,
+    /// Pseudo-random bit sequence 15.
+    Prbs15 = 0x03,
+    /// Repeated `11111111`.
+    AllOnes = 0x04,
+    /// Repeated `00000000`.
+    AllZeros = 0x05,
+    /// Repeated `00001111`.
+    Pattern00001111 = 0x06,
+    /// Repeated `01010101`.
+    Pattern01010101 = 0x07,
+}
+
+/// PHY used by a test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TestPhy {
+    /// LE 1M.
+    #[default]
+    Le1M,
+    /// LE 2M.
+    Le2M,
+    /// LE Coded with S=8 coding.
+    LeCodedS8,
+    /// LE Coded with S=2 coding. The receiver accepts both codings.
+    LeCodedS2,
+}
+
+impl TestPhy {
+    fn tx(self) -> u8 {
+        match self {
+            Self::Le1M => 0x01,
+            Self::Le2M => 0x02,
+            Self::LeCodedS8 => 0x03,
+            Self::LeCodedS2 => 0x04,
+        }
+    }
+
+    fn rx(self) -> u8 {
+        match self {
+            Self::Le1M => 0x01,
+            Self::Le2M => 0x02,
+            Self::LeCodedS8 | Self::LeCodedS2 => 0x03,
+        }
+    }
+}
+
+/// Parameters of a transmitter test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TransmitterTest {
+    /// RF channel to transmit on, from 0 (2402 MHz) to 39 (2480 MHz).
+    pub channel: u8,
+    /// Length of the payload of the test packets.
+    pub length: u8,
+    /// Payload of the test packets.
+    pub payload: TestPayload,
+    /// PHY to transmit on.
+    pub phy: TestPhy,
+    /// Transmit power level in dBm, or the controller default if not set.
+    ///
+    /// The controller selects the closest power level it supports.
+    pub tx_power: Option<i8>,
+}
+
+/// Parameters of a receiver test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReceiverTest {
+    /// RF channel to receive on, from 0 (2402 MHz) to 39 (2480 MHz).
+    pub channel: u8,
+    /// PHY to receive on.
+    pub phy: TestPhy,
+    /// Whether the transmitter uses a stable modulation index.
+    pub stable_modulation_index: bool,
+}
+
+impl<C: Controller> Stack<'_, C> {
+    /// Start a transmitter test, sending test packets until the test is ended.
+    ///
+    /// The oldest version of the command supporting the parameters is used, so that controllers
+    /// implementing earlier versions of the specification can be tested.
+    pub async fn start_transmitter_test(&self, test: &TransmitterTest) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeTransmitterTestV1>
+            + ControllerCmdSync<LeTransmitterTestV2>
+            + ControllerCmdSync<LeTransmitterTestV4>,
+    {
+        if test.channel > MAX_CHANNEL {
+            return Err(Error::InvalidValue.into());
+        }
+        let payload = test.payload as u8;
+        match (test.phy, test.tx_power) {
+            (TestPhy::Le1M, None) => {
+                self.host
+                    .command(LeTransmitterTestV1::new(test.channel, test.length, payload))
+                    .await
+            }
+            (phy, None) => {
+                self.host
+                    .command(LeTransmitterTestV2::new(test.channel, test.length, payload, phy.tx()))
+                    .await
+            }
+            (phy, Some(tx_power)) => {
+                self.host
+                    .command(LeTransmitterTestV4::new(
+                        test.channel,
+                        test.length,
+                        payload,
+                        phy.tx(),
+                        0,
+                        0,
+                        0,
+                        tx_power,
+                    ))
+                    .await
+            }
+        }
+    }
+
+    /// Start a receiver test, counting the test packets received until the test is ended.
+    pub async fn start_receiver_test(&self, test: &ReceiverTest) -> Result<(), BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeReceiverTestV1> + ControllerCmdSync<LeReceiverTestV2>,
+    {
+        if test.channel > MAX_CHANNEL {
+            return Err(Error::InvalidValue.into());
+        }
+        match (test.phy, test.stable_modulation_index) {
+            (TestPhy::Le1M, false) => self.host.command(LeReceiverTestV1::new(test.channel)).await,
+            (phy, stable) => {
+                self.host
+                    .command(LeReceiverTestV2::new(test.channel, phy.rx(), stable as u8))
+                    .await
+            }
+        }
+    }
+
+    /// End the running test, returning the number of packets received by a receiver test.
+    pub async fn end_test(&self) -> Result<u16, BleHostError<C::Error>>
+    where
+        C: ControllerCmdSync<LeTestEnd>,
+    {
+        self.host.command(LeTestEnd::new()).await
+    }
+}
@@ -0,0 +1,74 @@
+//! HCI transports, connecting the host to a controller over a serial line, USB or a socket.
+//!
+//! The transports implement the [`Transport`](bt_hci::transport::Transport) trait, and are used
+//! with an [`ExternalController`](bt_hci::controller::ExternalController) like any other.
+use bt_hci::event::Event;
+use bt_hci::{ControllerToHostPacket, FromHciBytesError, PacketKind};
+use embedded_io::ReadExactError;
+
+#[cfg(feature = "command-metrics")]
+pub mod credits;
+pub mod h4;
+pub mod h5;
+#[cfg(feature = "hci-socket")]
+pub mod hci_socket;
+pub mod sdio;
+#[cfg(feature = "tcp")]
+pub mod tcp;
+pub mod usb;
+
+/// Error of a serial transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E: embedded_io::Error> {
+    /// Error reading from the serial line.
+    Read(E),
+    /// Error writing to the serial line.
+    Write(E),
+    /// The serial line was closed while reading a packet.
+    Eof,
+    /// The packet is larger than the buffers of the transport.
+    TooLarge,
+}
+
+impl<E: embedded_io::Error> embedded_io::Error for Error<E> {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Self::Read(e) | Self::Write(e) => e.kind(),
+            Self::Eof => embedded_io::ErrorKind::BrokenPipe,
+            Self::TooLarge => embedded_io::ErrorKind::InvalidInput,
+        }
+    }
+}
+
+/// LE meta subevents delivered to the host as unknown events, to be parsed by the host.
+///
+/// `bt-hci` reads the BIG handle of the LE Create BIG Complete, LE Terminate BIG Complete, LE BIG
+/// Sync Established and LE BIG Sync Lost events as two octets instead of one, and does not know the
+/// LE Periodic Advertising Subevent Data Request and LE Periodic Advertising Response Report events.
+const HOST_PARSED_LE_SUBEVENTS: [core::ops::RangeInclusive<u8>; 2] = [0x1b..=0x1e, 0x27..=0x28];
+
+/// Parse a packet read from the controller, without its packet indicator.
+pub(crate) fn parse_packet(
+    kind: PacketKind,
+    data: &[u8],
+) -> Result<(ControllerToHostPacket<'_>, &[u8]), FromHciBytesError> {
+    if let (PacketKind::Event, [0x3e, len, subevent, ..]) = (kind, data) {
+        if HOST_PARSED_LE_SUBEVENTS.iter().any(|r| r.contains(subevent)) {
+            let end = 2 + *len as usize;
+            let params = data.get(2..end).ok_or(FromHciBytesError::InvalidSize)?;
+            let event = Event::Unknown { code: 0x3e, params };
+            return Ok((ControllerToHostPacket::Event(event), &data[end..]));
+        }
+    }
+    ControllerToHostPacket::from_hci_bytes_with_kind(kind, data)
+}
+
+impl<E: embedded_io::Error> From<ReadExactError<E>> for Error<E> {
+    fn from(e: ReadExactError<E>) -> Self {
+        match e {
+            ReadExactError::UnexpectedEof => Self::Eof,
+            ReadExactError::Other(e) => Self::Read(e),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Tracking of the command credits granted by a controller.
+//!
+//! The controller grants credits for commands in the Num_HCI_Command_Packets field of its Command
+//! Complete and Command Status events. These events are taken by the
+//! [`ExternalController`](bt_hci::controller::ExternalController) before reaching the host, so the
+//! credits are read from its transport instead. A [`CommandCredits`] shared by the transport and
+//! the host, with [`Stack::set_command_credits`](crate::Stack::set_command_credits), reports them
+//! in the [`CommandMetrics`](crate::prelude::CommandMetrics).
+use core::cell::Cell;
+
+use bt_hci::event::Event;
+use bt_hci::transport::Transport;
+use bt_hci::{ControllerToHostPacket, HostToControllerPacket};
+
+/// Command credits granted by a controller.
+#[derive(Debug, Default)]
+pub struct CommandCredits {
+    current: Cell<Option<u8>>,
+    min: Cell<Option<u8>>,
+}
+
+impl CommandCredits {
+    /// Create a new instance, with no credits granted yet.
+    pub const fn new() -> Self {
+        Self {
+            current: Cell::new(None),
+            min: Cell::new(None),
+        }
+    }
+
+    /// Track the credits granted by the controller through the transport.
+    pub fn track<T>(&self, transport: T) -> CommandCreditsTransport<'_, T> {
+        CommandCreditsTransport {
+            transport,
+            credits: self,
+        }
+    }
+
+    /// Credits granted by the last Command Complete or Command Status event.
+    pub fn current(&self) -> Option<u8> {
+        self.current.get()
+    }
+
+    /// Lowest number of credits granted, zero meaning the controller stopped taking commands.
+    pub fn min(&self) -> Option<u8> {
+        self.min.get()
+    }
+
+    fn granted(&self, credits: u8) {
+        self.current.set(Some(credits));
+        self.min
+            .set(Some(self.min.get().map_or(credits, |min| min.min(credits))));
+    }
+}
+
+/// Transport recording the command credits granted by the controller through another one.
+pub struct CommandCreditsTransport<'d, T> {
+    transport: T,
+    credits: &'d CommandCredits,
+}
+
+impl<T: embedded_io::ErrorType> embedded_io::ErrorType for CommandCreditsTransport<'_, T> {
+    type Error = T::Error;
+}
+
+impl<T: Transport> Transport for CommandCreditsTransport<'_, T> {
+    async fn read<'a>(&self, rx: &'a mut [u8]) -> Result<ControllerToHostPacket<'a>, Self::Error> {
+        let packet = self.transport.read(rx).await?;
+        match &packet {
+            ControllerToHostPacket::Event(Event::CommandComplete(e)) => self.credits.granted(e.num_hci_cmd_pkts),
+            ControllerToHostPacket::Event(Event::CommandStatus(e)) => self.credits.granted(e.num_hci_cmd_pkts),
+            _ => {}
+        }
+        Ok(packet)
+    }
+
+    async fn write<P: HostToControllerPacket>(&self, tx: &P) -> Result<(), Self::Error> {
+        self.transport.write(tx).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::transport::h4::H4Transport;
+
+    struct Sink;
+
+    impl embedded_io::ErrorType for Sink {
+        type Error = Infallible;
+    }
+
+    impl embedded_io_async::Write for Sink {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn records_credits_of_command_events() {
+        #[rustfmt::skip]
+        let stream: &[u8] = &[
+            // Command complete of a reset, granting 2 credits.
+            0x04, 0x0e, 0x04, 0x02, 0x03, 0x0c, 0x00,
+            // Command status of a create connection, granting none.
+            0x04, 0x0f, 0x04, 0x00, 0x00, 0x0d, 0x20,
+            // Disconnection complete.
+            0x04, 0x05, 0x04, 0x00, 0x01, 0x00, 0x13,
+        ];
+        let credits = CommandCredits::new();
+        let h4: H4Transport<NoopRawMutex, _, _> = H4Transport::new(stream, Sink);
+        let transport = credits.track(h4);
+        let mut rx = [0; 16];
+
+        assert_eq!(credits.current(), None);
+        block_on(transport.read(&mut rx)).unwrap();
+        assert_eq!((credits.current(), credits.min()), (Some(2), Some(2)));
+        block_on(transport.read(&mut rx)).unwrap();
+        assert_eq!((credits.current(), credits.min()), (Some(0), Some(0)));
+        block_on(transport.read(&mut rx)).unwrap();
+        assert_eq!((credits.current(), credits.min()), (Some(0), Some(0)));
+    }
+}
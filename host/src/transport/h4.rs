@@ -0,0 +1,208 @@
+//! The UART transport (H4), sending each HCI packet after a packet indicator octet.
+//!
+//! Packets are framed by the length fields of their headers. A stream which loses its framing,
+//! for instance after the controller was reset or bytes were dropped by the UART, is
+//! resynchronized by skipping octets until a known packet indicator is found. Packets too large
+//! for the receive buffer, or that can not be parsed, are discarded instead of failing the
+//! transport.
+use bt_hci::transport::{Transport, WithIndicator};
+use bt_hci::{ControllerToHostPacket, HostToControllerPacket, PacketKind, WriteHci};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+
+use super::Error;
+
+/// HCI transport over a serial line split into a reader and a writer.
+pub struct H4Transport<M: RawMutex, R, W> {
+    reader: Mutex<M, R>,
+    writer: Mutex<M, W>,
+}
+
+impl<M: RawMutex, R, W> H4Transport<M, R, W> {
+    /// Create a transport over the serial line.
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<M: RawMutex, R, W, E> embedded_io::ErrorType for H4Transport<M, R, W>
+where
+    R: embedded_io::ErrorType<Error = E>,
+    W: embedded_io::ErrorType<Error = E>,
+    E: embedded_io::Error,
+{
+    type Error = Error<E>;
+}
+
+impl<M: RawMutex, R, W, E> Transport for H4Transport<M, R, W>
+where
+    R: embedded_io_async::Read<Error = E>,
+    W: embedded_io_async::Write<Error = E>,
+    E: embedded_io::Error,
+{
+    async fn read<'a>(&self, rx: &'a mut [u8]) -> Result<ControllerToHostPacket<'a>, Self::Error> {
+        let mut r = self.reader.lock().await;
+        let (kind, len) = loop {
+            if let Some((kind, len)) = read_frame(&mut *r, rx).await? {
+                match super::parse_packet(kind, &rx[..len]) {
+                    Ok(_) => break (kind, len),
+                    Err(e) => warn!("[h4] discarding invalid packet: {:?}", e),
+                }
+            }
+        };
+        // The packet was parsed above, parsing again to return it with the lifetime of the buffer.
+        let (packet, _) = unwrap!(super::parse_packet(kind, &rx[..len]).ok());
+        Ok(packet)
+    }
+
+    async fn write<T: HostToControllerPacket>(&self, tx: &T) -> Result<(), Self::Error> {
+        let mut w = self.writer.lock().await;
+        WithIndicator::new(tx)
+            .write_hci_async(&mut *w)
+            .await
+            .map_err(Error::Write)?;
+        w.flush().await.map_err(Error::Write)
+    }
+}
+
+// Read the next packet into the buffer, returning its kind and length.
+//
+// Returns `None` when the framing was lost or the packet was discarded.
+async fn read_frame<R: embedded_io_async::Read>(
+    r: &mut R,
+    rx: &mut [u8],
+) -> Result<Option<(PacketKind, usize)>, Error<R::Error>> {
+    let mut indicator = [0];
+    r.read_exact(&mut indicator).await?;
+    let (kind, header_len) = match indicator[0] {
+        0x02 => (PacketKind::AclData, 4),
+        0x03 => (PacketKind::SyncData, 3),
+        0x04 => (PacketKind::Event, 2),
+        0x05 => (PacketKind::IsoData, 4),
+        other => {
+            warn!("[h4] unexpected packet indicator {:02x}, resynchronizing", other);
+            return Ok(None);
+        }
+    };
+
+    let mut header = [0; 4];
+    let header = &mut header[..header_len];
+    r.read_exact(header).await?;
+    let payload_len = match kind {
+        PacketKind::AclData => u16::from_le_bytes([header[2], header[3]]) as usize,
+        PacketKind::IsoData => (u16::from_le_bytes([header[2], header[3]]) & 0x3fff) as usize,
+        PacketKind::SyncData => header[2] as usize,
+        _ => header[1] as usize,
+    };
+
+    let len = header_len + payload_len;
+    let Some(buf) = rx.get_mut(..len) else {
+        warn!(
+            "[h4] discarding packet of {} octets, larger than the receive buffer",
+            len
+        );
+        let mut scratch = [0; 16];
+        let mut remaining = payload_len;
+        while remaining > 0 {
+            let n = remaining.min(scratch.len());
+            r.read_exact(&mut scratch[..n]).await?;
+            remaining -= n;
+        }
+        return Ok(None);
+    };
+    buf[..header_len].copy_from_slice(header);
+    r.read_exact(&mut buf[header_len..]).await?;
+    Ok(Some((kind, len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+
+    use bt_hci::cmd::Cmd;
+    use bt_hci::cmd::controller_baseband::Reset;
+    use bt_hci::event::Event;
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Sink(heapless::Vec<u8, 32>);
+
+    impl embedded_io::ErrorType for Sink {
+        type Error = Infallible;
+    }
+
+    impl embedded_io_async::Write for Sink {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            let n = buf.len().min(self.0.capacity() - self.0.len());
+            self.0.extend_from_slice(&buf[..n]).unwrap();
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn read_resynchronizes() {
+        #[rustfmt::skip]
+        let stream: &[u8] = &[
+            // Garbage before the first packet.
+            0x00, 0xff,
+            // Command complete of a reset.
+            0x04, 0x0e, 0x04, 0x01, 0x03, 0x0c, 0x00,
+            // ACL packet larger than the receive buffer.
+            0x02, 0x01, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            // LE meta event of an unknown subevent.
+            0x04, 0x3e, 0x01, 0x7f,
+            // ACL packet.
+            0x02, 0x01, 0x20, 0x02, 0x00, 0xaa, 0xbb,
+        ];
+        let transport: H4Transport<NoopRawMutex, _, _> = H4Transport::new(stream, Sink::default());
+        let mut rx = [0; 16];
+
+        let packet = block_on(transport.read(&mut rx)).unwrap();
+        let ControllerToHostPacket::Event(Event::CommandComplete(event)) = packet else {
+            panic!("unexpected packet");
+        };
+        assert_eq!(event.cmd_opcode, Reset::OPCODE);
+
+        let packet = block_on(transport.read(&mut rx)).unwrap();
+        let ControllerToHostPacket::Acl(acl) = packet else {
+            panic!("unexpected packet");
+        };
+        assert_eq!(acl.handle().raw(), 0x001);
+        assert_eq!(acl.data(), &[0xaa, 0xbb]);
+
+        assert_eq!(block_on(transport.read(&mut rx)).err(), Some(Error::Eof));
+    }
+
+    #[test]
+    fn host_parsed_events_are_left_to_the_host() {
+        // LE BIG Sync Lost, with its one octet BIG handle, and LE Periodic Advertising Subevent
+        // Data Request, unknown to bt-hci.
+        let stream: &[u8] = &[
+            0x04, 0x3e, 0x03, 0x1e, 0x01, 0x13, 0x04, 0x3e, 0x04, 0x27, 0x00, 0x02, 0x01,
+        ];
+        let transport: H4Transport<NoopRawMutex, _, _> = H4Transport::new(stream, Sink::default());
+        let mut rx = [0; 16];
+
+        for expected in [&[0x1e, 0x01, 0x13][..], &[0x27, 0x00, 0x02, 0x01]] {
+            let packet = block_on(transport.read(&mut rx)).unwrap();
+            let ControllerToHostPacket::Event(Event::Unknown { code, params }) = packet else {
+                panic!("unexpected packet");
+            };
+            assert_eq!(code, 0x3e);
+            assert_eq!(params, expected);
+        }
+    }
+
+    #[test]
+    fn write_adds_indicator() {
+        let transport: H4Transport<NoopRawMutex, &[u8], _> = H4Transport::new(&[][..], Sink::default());
+        block_on(transport.write(&Reset::new())).unwrap();
+        assert_eq!(transport.writer.try_lock().unwrap().0, [0x01, 0x03, 0x0c, 0x00]);
+    }
+}
@@ -0,0 +1,598 @@
+//! The three-wire UART transport (H5), for serial lines without hardware flow control.
+//!
+//! Packets are framed with SLIP and carry a header with sequence numbers, so that the reliable
+//! packets (commands, events, ACL and ISO data) lost or corrupted on the line are detected and
+//! retransmitted. Writes of reliable packets return once the controller acknowledged them, and
+//! up to the negotiated window of packets may be waiting for an acknowledgment at once.
+//!
+//! The link is established by the first write, and again after the controller was reset. Reads
+//! must be polled for writes to complete, as they receive the acknowledgments and answer the
+//! link establishment of the controller; the host runner does so.
+//!
+//! The transport does not request the data integrity check nor out-of-frame flow control. A
+//! controller adding the check anyway has it skipped, the header checksum of the packets still
+//! being verified.
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::Poll;
+
+use bt_hci::transport::Transport;
+use bt_hci::{ControllerToHostPacket, HostToControllerPacket, PacketKind, WriteHci};
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::waitqueue::MultiWakerRegistration;
+use embassy_time::{Duration, Timer};
+
+use super::Error;
+
+const SLIP_DELIMITER: u8 = 0xc0;
+const SLIP_ESCAPE: u8 = 0xdb;
+const SLIP_ESCAPED_DELIMITER: u8 = 0xdc;
+const SLIP_ESCAPED_ESCAPE: u8 = 0xdd;
+
+const TYPE_ACK: u8 = 0x00;
+const TYPE_COMMAND: u8 = 0x01;
+const TYPE_ACL: u8 = 0x02;
+const TYPE_SYNC: u8 = 0x03;
+const TYPE_EVENT: u8 = 0x04;
+const TYPE_ISO: u8 = 0x05;
+const TYPE_LINK_CONTROL: u8 = 0x0f;
+
+const LINK_SYNC: [u8; 2] = [0x01, 0x7e];
+const LINK_SYNC_RESPONSE: [u8; 2] = [0x02, 0x7d];
+const LINK_CONFIG: [u8; 2] = [0x03, 0xfc];
+const LINK_CONFIG_RESPONSE: [u8; 2] = [0x04, 0x7b];
+const LINK_WAKEUP: [u8; 2] = [0x05, 0xfa];
+const LINK_WOKEN: [u8; 2] = [0x06, 0xf9];
+
+const SYNC_INTERVAL: Duration = Duration::from_millis(100);
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Link {
+    Uninitialized,
+    Initialized,
+    Active,
+}
+
+struct State {
+    link: Link,
+    // Incremented when the link is reset, invalidating the sequence numbers in flight.
+    epoch: u32,
+    window: u8,
+    // Sequence number of the next reliable packet sent.
+    tx_seq: u8,
+    // Sequence number of the oldest reliable packet sent and not acknowledged.
+    tx_unacked: u8,
+    // Sequence number of the next reliable packet expected, acknowledging the ones before.
+    rx_ack: u8,
+    wakers: MultiWakerRegistration<8>,
+}
+
+impl State {
+    fn in_flight(&self) -> u8 {
+        self.tx_seq.wrapping_sub(self.tx_unacked) & 0x07
+    }
+
+    fn acked(&self, seq: u8) -> bool {
+        seq.wrapping_sub(self.tx_unacked) & 0x07 >= self.in_flight()
+    }
+
+    fn ack(&mut self, ack: u8) {
+        if ack != self.tx_unacked && ack.wrapping_sub(self.tx_unacked) & 0x07 <= self.in_flight() {
+            self.tx_unacked = ack;
+            self.wakers.wake();
+        }
+    }
+
+    fn set_link(&mut self, link: Link) {
+        self.link = link;
+        self.wakers.wake();
+    }
+
+    fn reset(&mut self, window: u8) {
+        self.epoch = self.epoch.wrapping_add(1);
+        self.window = window;
+        self.tx_seq = 0;
+        self.tx_unacked = 0;
+        self.rx_ack = 0;
+        self.set_link(Link::Uninitialized);
+    }
+}
+
+// Writer escaping the octets of a frame.
+struct SlipWriter<'w, W>(&'w mut W);
+
+impl<W: embedded_io::ErrorType> embedded_io::ErrorType for SlipWriter<'_, W> {
+    type Error = W::Error;
+}
+
+impl<W: embedded_io_async::Write> embedded_io_async::Write for SlipWriter<'_, W> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let mut out = [0; 32];
+        let mut len = 0;
+        let n = buf.len().min(out.len() / 2);
+        for b in &buf[..n] {
+            let escaped: &[u8] = match *b {
+                SLIP_DELIMITER => &[SLIP_ESCAPE, SLIP_ESCAPED_DELIMITER],
+                SLIP_ESCAPE => &[SLIP_ESCAPE, SLIP_ESCAPED_ESCAPE],
+                _ => core::slice::from_ref(b),
+            };
+            out[len..len + escaped.len()].copy_from_slice(escaped);
+            len += escaped.len();
+        }
+        self.0.write_all(&out[..len]).await?;
+        Ok(n)
+    }
+}
+
+// Payload written as is, as opposed to the length prefixed slices of bt-hci.
+struct Raw<'a>(&'a [u8]);
+
+impl WriteHci for Raw<'_> {
+    fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    fn write_hci<W: embedded_io::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(self.0)
+    }
+
+    async fn write_hci_async<W: embedded_io_async::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(self.0).await
+    }
+}
+
+fn header(seq: u8, ack: u8, reliable: bool, ty: u8, len: usize) -> [u8; 4] {
+    let h0 = seq | (ack << 3) | if reliable { 0x80 } else { 0 };
+    let h1 = ty | ((len as u8 & 0x0f) << 4);
+    let h2 = (len >> 4) as u8;
+    [h0, h1, h2, !(h0.wrapping_add(h1).wrapping_add(h2))]
+}
+
+async fn write_frame<W: embedded_io_async::Write, P: WriteHci>(
+    w: &mut W,
+    header: [u8; 4],
+    payload: &P,
+) -> Result<(), Error<W::Error>> {
+    w.write_all(&[SLIP_DELIMITER]).await.map_err(Error::Write)?;
+    let mut slip = SlipWriter(w);
+    embedded_io_async::Write::write_all(&mut slip, &header)
+        .await
+        .map_err(Error::Write)?;
+    payload.write_hci_async(&mut slip).await.map_err(Error::Write)?;
+    w.write_all(&[SLIP_DELIMITER]).await.map_err(Error::Write)?;
+    w.flush().await.map_err(Error::Write)
+}
+
+// Reader of the frames of a serial line, keeping the octets read past the end of a frame.
+struct Reader<R> {
+    inner: R,
+    buf: [u8; 32],
+    pos: usize,
+    end: usize,
+}
+
+impl<R: embedded_io_async::Read> Reader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: [0; 32],
+            pos: 0,
+            end: 0,
+        }
+    }
+
+    async fn byte(&mut self) -> Result<u8, Error<R::Error>> {
+        if self.pos == self.end {
+            let n = self.inner.read(&mut self.buf).await.map_err(Error::Read)?;
+            if n == 0 {
+                return Err(Error::Eof);
+            }
+            self.pos = 0;
+            self.end = n;
+        }
+        self.pos += 1;
+        Ok(self.buf[self.pos - 1])
+    }
+
+    // Read the next frame into the buffer, returning its length.
+    //
+    // Returns `None` for frames too large for the buffer or wrongly escaped.
+    async fn frame(&mut self, rx: &mut [u8]) -> Result<Option<usize>, Error<R::Error>> {
+        let mut len = 0;
+        let mut valid = true;
+        loop {
+            let b = match self.byte().await? {
+                SLIP_DELIMITER if len == 0 && valid => continue,
+                SLIP_DELIMITER => return Ok(valid.then_some(len)),
+                SLIP_ESCAPE => match self.byte().await? {
+                    SLIP_ESCAPED_DELIMITER => SLIP_DELIMITER,
+                    SLIP_ESCAPED_ESCAPE => SLIP_ESCAPE,
+                    // Also ends the frame when the escape is followed by a delimiter.
+                    SLIP_DELIMITER => return Ok(None),
+                    _ => {
+                        valid = false;
+                        continue;
+                    }
+                },
+                b => b,
+            };
+            match rx.get_mut(len) {
+                Some(dest) if valid => {
+                    *dest = b;
+                    len += 1;
+                }
+                _ => valid = false,
+            }
+        }
+    }
+}
+
+/// HCI transport over a serial line without flow control, split into a reader and a writer.
+///
+/// `WINDOW` is the number of reliable packets which may be sent before waiting for the
+/// acknowledgment of the controller, between 1 and 7. The controller may choose a smaller window
+/// when the link is established.
+pub struct H5Transport<M: RawMutex, R, W, const WINDOW: usize = 4> {
+    reader: Mutex<M, Reader<R>>,
+    writer: Mutex<M, W>,
+    // Held by the writer establishing the link.
+    link: Mutex<M, ()>,
+    state: BlockingMutex<M, RefCell<State>>,
+}
+
+impl<M: RawMutex, R, W, const WINDOW: usize> H5Transport<M, R, W, WINDOW>
+where
+    R: embedded_io_async::Read,
+    W: embedded_io_async::Write,
+{
+    /// Create a transport over the serial line.
+    pub fn new(reader: R, writer: W) -> Self {
+        const { core::assert!(WINDOW >= 1 && WINDOW <= 7, "the window must hold from 1 to 7 packets") };
+        Self {
+            reader: Mutex::new(Reader::new(reader)),
+            writer: Mutex::new(writer),
+            link: Mutex::new(()),
+            state: BlockingMutex::new(RefCell::new(State {
+                link: Link::Uninitialized,
+                epoch: 0,
+                window: WINDOW as u8,
+                tx_seq: 0,
+                tx_unacked: 0,
+                rx_ack: 0,
+                wakers: MultiWakerRegistration::new(),
+            })),
+        }
+    }
+
+    fn with_state<F: FnOnce(&mut State) -> T, T>(&self, f: F) -> T {
+        self.state.lock(|s| f(&mut s.borrow_mut()))
+    }
+
+    // Wait for the state to satisfy the condition, and return the output of it.
+    async fn wait_state<F: FnMut(&mut State) -> Option<T>, T>(&self, mut f: F) -> T {
+        poll_fn(|cx| {
+            self.with_state(|s| match f(s) {
+                Some(value) => Poll::Ready(value),
+                None => {
+                    s.wakers.register(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    fn config(&self) -> [u8; 3] {
+        // Version 1.0, without out-of-frame flow control nor data integrity check.
+        [LINK_CONFIG[0], LINK_CONFIG[1], WINDOW as u8]
+    }
+
+    async fn send_unreliable(&self, ty: u8, payload: &[u8]) -> Result<(), Error<W::Error>> {
+        let mut w = self.writer.lock().await;
+        let ack = self.with_state(|s| s.rx_ack);
+        write_frame(&mut *w, header(0, ack, false, ty, payload.len()), &Raw(payload)).await
+    }
+
+    async fn establish(&self) -> Result<(), Error<W::Error>> {
+        if self.with_state(|s| s.link) == Link::Active {
+            return Ok(());
+        }
+        let _guard = self.link.lock().await;
+        loop {
+            let link = self.with_state(|s| s.link);
+            match link {
+                Link::Uninitialized => self.send_unreliable(TYPE_LINK_CONTROL, &LINK_SYNC).await?,
+                Link::Initialized => self.send_unreliable(TYPE_LINK_CONTROL, &self.config()).await?,
+                Link::Active => return Ok(()),
+            }
+            let changed = self.wait_state(|s| (s.link != link).then_some(()));
+            select(changed, Timer::after(SYNC_INTERVAL)).await;
+        }
+    }
+
+    async fn link_control(&self, payload: &[u8]) -> Result<(), Error<W::Error>> {
+        let Some(message) = payload.get(..2) else {
+            return Ok(());
+        };
+        if message == LINK_SYNC {
+            self.with_state(|s| {
+                if s.link == Link::Active {
+                    warn!("[h5] controller reset, establishing the link again");
+                    s.reset(WINDOW as u8);
+                }
+            });
+            self.send_unreliable(TYPE_LINK_CONTROL, &LINK_SYNC_RESPONSE).await?;
+        } else if message == LINK_SYNC_RESPONSE {
+            self.with_state(|s| {
+                if s.link == Link::Uninitialized {
+                    s.set_link(Link::Initialized);
+                }
+            });
+        } else if message == LINK_CONFIG {
+            let [_, _, config] = self.config();
+            let response = [LINK_CONFIG_RESPONSE[0], LINK_CONFIG_RESPONSE[1], config];
+            self.send_unreliable(TYPE_LINK_CONTROL, &response).await?;
+        } else if message == LINK_CONFIG_RESPONSE {
+            // A response without configuration is from a controller supporting a window of 1.
+            let window = payload.get(2).map(|c| c & 0x07).unwrap_or(1).clamp(1, WINDOW as u8);
+            self.with_state(|s| {
+                if s.link == Link::Initialized {
+                    s.window = window;
+                    s.set_link(Link::Active);
+                }
+            });
+        } else if message == LINK_WAKEUP {
+            self.send_unreliable(TYPE_LINK_CONTROL, &LINK_WOKEN).await?;
+        }
+        Ok(())
+    }
+
+    // Handle a received frame, returning the kind and length of the HCI packet it carries.
+    async fn receive(&self, frame: &[u8]) -> Result<Option<(PacketKind, usize)>, Error<W::Error>> {
+        let Some(&[h0, h1, h2, h3]) = frame.get(..4) else {
+            return Ok(None);
+        };
+        if h0.wrapping_add(h1).wrapping_add(h2).wrapping_add(h3) != 0xff {
+            warn!("[h5] discarding packet with invalid header checksum");
+            return Ok(None);
+        }
+        let len = (h1 >> 4) as usize | ((h2 as usize) << 4);
+        let integrity = if h0 & 0x40 != 0 { 2 } else { 0 };
+        if frame.len() != 4 + len + integrity {
+            warn!("[h5] discarding packet of invalid length");
+            return Ok(None);
+        }
+
+        let (seq, ack, reliable, ty) = (h0 & 0x07, (h0 >> 3) & 0x07, h0 & 0x80 != 0, h1 & 0x0f);
+        if ty == TYPE_LINK_CONTROL {
+            self.link_control(&frame[4..4 + len]).await?;
+            return Ok(None);
+        }
+
+        let (accept, send_ack) = self.with_state(|s| {
+            if s.link != Link::Active {
+                return (false, false);
+            }
+            s.ack(ack);
+            if !reliable {
+                (true, false)
+            } else if seq == s.rx_ack {
+                s.rx_ack = (seq + 1) & 0x07;
+                (true, true)
+            } else {
+                // Acknowledged again, in case the previous acknowledgment was lost.
+                debug!("[h5] discarding out of order packet {}", seq);
+                (false, true)
+            }
+        });
+        if send_ack {
+            self.send_unreliable(TYPE_ACK, &[]).await?;
+        }
+        if !accept {
+            return Ok(None);
+        }
+
+        let kind = match ty {
+            TYPE_ACK => return Ok(None),
+            TYPE_ACL => PacketKind::AclData,
+            TYPE_SYNC => PacketKind::SyncData,
+            TYPE_EVENT => PacketKind::Event,
+            TYPE_ISO => PacketKind::IsoData,
+            _ => {
+                warn!("[h5] discarding packet of unexpected type {}", ty);
+                return Ok(None);
+            }
+        };
+        Ok(Some((kind, len)))
+    }
+}
+
+impl<M: RawMutex, R, W, E, const WINDOW: usize> embedded_io::ErrorType for H5Transport<M, R, W, WINDOW>
+where
+    R: embedded_io::ErrorType<Error = E>,
+    W: embedded_io::ErrorType<Error = E>,
+    E: embedded_io::Error,
+{
+    type Error = Error<E>;
+}
+
+impl<M: RawMutex, R, W, E, const WINDOW: usize> Transport for H5Transport<M, R, W, WINDOW>
+where
+    R: embedded_io_async::Read<Error = E>,
+    W: embedded_io_async::Write<Error = E>,
+    E: embedded_io::Error,
+{
+    async fn read<'a>(&self, rx: &'a mut [u8]) -> Result<ControllerToHostPacket<'a>, Self::Error> {
+        let mut r = self.reader.lock().await;
+        let (kind, len) = loop {
+            let Some(n) = r.frame(rx).await? else {
+                warn!("[h5] discarding invalid frame");
+                continue;
+            };
+            if let Some((kind, len)) = self.receive(&rx[..n]).await? {
+                match super::parse_packet(kind, &rx[4..4 + len]) {
+                    Ok(_) => break (kind, len),
+                    Err(e) => warn!("[h5] discarding invalid packet: {:?}", e),
+                }
+            }
+        };
+        // The packet was parsed above, parsing again to return it with the lifetime of the buffer.
+        let (packet, _) = unwrap!(super::parse_packet(kind, &rx[4..4 + len]).ok());
+        Ok(packet)
+    }
+
+    async fn write<T: HostToControllerPacket>(&self, tx: &T) -> Result<(), Self::Error> {
+        let ty = match T::KIND {
+            PacketKind::Cmd => TYPE_COMMAND,
+            PacketKind::AclData => TYPE_ACL,
+            PacketKind::SyncData => TYPE_SYNC,
+            PacketKind::Event => TYPE_EVENT,
+            PacketKind::IsoData => TYPE_ISO,
+        };
+        loop {
+            self.establish().await?;
+            if ty == TYPE_SYNC {
+                // Synchronous data is not retransmitted, as it would arrive too late.
+                let mut w = self.writer.lock().await;
+                let ack = self.with_state(|s| s.rx_ack);
+                return write_frame(&mut *w, header(0, ack, false, ty, tx.size()), tx).await;
+            }
+
+            self.wait_state(|s| (s.link != Link::Active || s.in_flight() < s.window).then_some(()))
+                .await;
+            let mut w = self.writer.lock().await;
+            let Some((epoch, seq)) = self.with_state(|s| {
+                (s.link == Link::Active && s.in_flight() < s.window).then(|| {
+                    let seq = s.tx_seq;
+                    s.tx_seq = (seq + 1) & 0x07;
+                    (s.epoch, seq)
+                })
+            }) else {
+                continue;
+            };
+
+            loop {
+                let ack = self.with_state(|s| s.rx_ack);
+                write_frame(&mut *w, header(seq, ack, true, ty, tx.size()), tx).await?;
+                drop(w);
+                let acked = self.wait_state(|s| {
+                    if s.epoch != epoch {
+                        Some(false)
+                    } else {
+                        s.acked(seq).then_some(true)
+                    }
+                });
+                match select(acked, Timer::after(RETRANSMIT_TIMEOUT)).await {
+                    Either::First(true) => return Ok(()),
+                    // The link was reset, the packet is sent again once it is established.
+                    Either::First(false) => break,
+                    Either::Second(_) => debug!("[h5] retransmitting packet {}", seq),
+                }
+                w = self.writer.lock().await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bt_hci::cmd::Cmd;
+    use bt_hci::cmd::controller_baseband::Reset;
+    use bt_hci::event::Event;
+    use embassy_futures::block_on;
+    use embassy_futures::join::join3;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_sync::pipe::Pipe;
+
+    use super::*;
+
+    #[test]
+    fn slip_framing() {
+        let pipe: Pipe<NoopRawMutex, 32> = Pipe::new();
+        let header = header(3, 5, true, TYPE_ACL, 3);
+        assert_eq!(header[..3], [0xab, 0x32, 0x00]);
+        block_on(write_frame(&mut &pipe, header, &Raw(&[0xc0, 0x01, 0xdb]))).unwrap();
+        let mut buf = [0; 32];
+        let n = pipe.try_read(&mut buf).unwrap();
+        assert_eq!(&buf[1..5], &header);
+        assert_eq!(&buf[5..n], &[0xdb, 0xdc, 0x01, 0xdb, 0xdd, 0xc0]);
+
+        let stream: &[u8] = &[
+            0xc0, 0xc0, 1, 0xdb, 0xdc, 2, 0xc0, 0xc0, 1, 2, 3, 4, 5, 0xc0, 3, 0xdb, 0xc0,
+        ];
+        let mut reader = Reader::new(stream);
+        let mut rx = [0; 4];
+        assert_eq!(block_on(reader.frame(&mut rx)).unwrap(), Some(3));
+        assert_eq!(&rx[..3], &[1, 0xc0, 2]);
+        assert_eq!(block_on(reader.frame(&mut rx)).unwrap(), None);
+        assert_eq!(block_on(reader.frame(&mut rx)).unwrap(), None);
+        assert_eq!(block_on(reader.frame(&mut rx)), Err(Error::Eof));
+    }
+
+    #[test]
+    fn establish_and_retransmit() {
+        let to_host: Pipe<NoopRawMutex, 64> = Pipe::new();
+        let to_controller: Pipe<NoopRawMutex, 64> = Pipe::new();
+        let transport: H5Transport<NoopRawMutex, _, _, 4> = H5Transport::new(&to_host, &to_controller);
+
+        // Controller dropping the first transmission of the command, and acknowledging the second
+        // with its completion.
+        let controller = async {
+            let mut reader = Reader::new(&to_controller);
+            let mut writer = &to_host;
+            let mut commands = 0;
+            let mut frame = [0; 32];
+            loop {
+                let n = reader.frame(&mut frame).await.unwrap().unwrap();
+                let (h0, ty, payload) = (frame[0], frame[1] & 0x0f, &frame[4..n]);
+                match (ty, payload) {
+                    (TYPE_LINK_CONTROL, [0x01, 0x7e]) => {
+                        let response = Raw(&LINK_SYNC_RESPONSE);
+                        write_frame(&mut writer, header(0, 0, false, ty, 2), &response)
+                            .await
+                            .unwrap();
+                    }
+                    (TYPE_LINK_CONTROL, [0x03, 0xfc, config]) => {
+                        assert_eq!(*config, 4);
+                        let response = Raw(&[0x04, 0x7b, 0x02]);
+                        write_frame(&mut writer, header(0, 0, false, ty, 3), &response)
+                            .await
+                            .unwrap();
+                    }
+                    (TYPE_COMMAND, [0x03, 0x0c, 0x00]) => {
+                        assert_eq!(h0 & 0x87, 0x80);
+                        commands += 1;
+                        if commands == 2 {
+                            let event = Raw(&[0x0e, 0x04, 0x01, 0x03, 0x0c, 0x00]);
+                            write_frame(&mut writer, header(0, 1, true, TYPE_EVENT, 6), &event)
+                                .await
+                                .unwrap();
+                        }
+                    }
+                    (TYPE_ACK, []) => {
+                        assert_eq!((h0 >> 3) & 0x07, 1);
+                        return commands;
+                    }
+                    _ => panic!("unexpected frame"),
+                }
+            }
+        };
+        let host = async {
+            let mut rx = [0; 32];
+            let packet = transport.read(&mut rx).await.unwrap();
+            let ControllerToHostPacket::Event(Event::CommandComplete(event)) = packet else {
+                panic!("unexpected packet");
+            };
+            assert_eq!(event.cmd_opcode, Reset::OPCODE);
+        };
+
+        let (commands, _, written) = block_on(join3(controller, host, transport.write(&Reset::new())));
+        assert_eq!(commands, 2);
+        assert!(written.is_ok());
+        assert_eq!(transport.with_state(|s| (s.link, s.window)), (Link::Active, 2));
+    }
+}
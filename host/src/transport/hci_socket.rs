@@ -0,0 +1,186 @@
+//! The HCI user channel socket of Linux, to run the host on a computer with a real controller.
+//!
+//! The user channel gives the application exclusive access to a controller, bypassing the
+//! Bluetooth stack of the kernel. Opening it needs the `CAP_NET_ADMIN` capability, and the
+//! controller to be down (for instance with `hciconfig hci0 down`), while the daemons of the
+//! system Bluetooth stack may take it back when running.
+//!
+//! The socket is registered with the tokio reactor, so the transport must be used within a tokio
+//! runtime.
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::vec;
+
+use bt_hci::transport::{Transport, WithIndicator};
+use bt_hci::{ControllerToHostPacket, FromHciBytes, HostToControllerPacket, PacketKind, WriteHci};
+use tokio::io::Interest;
+use tokio::io::unix::AsyncFd;
+
+use super::Error;
+
+const BTPROTO_HCI: libc::c_int = 1;
+const HCI_CHANNEL_USER: u16 = 1;
+
+#[repr(C)]
+struct SockaddrHci {
+    hci_family: libc::sa_family_t,
+    hci_dev: u16,
+    hci_channel: u16,
+}
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+// Parse a packet read from the socket, starting with its packet indicator.
+fn parse(data: &[u8]) -> Result<(ControllerToHostPacket<'_>, &[u8]), bt_hci::FromHciBytesError> {
+    let (kind, data) = PacketKind::from_hci_bytes(data)?;
+    super::parse_packet(kind, data)
+}
+
+/// HCI transport over the user channel socket of a Linux controller.
+pub struct HciSocket {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl HciSocket {
+    /// Open the user channel of a controller, given its index: 0 for `hci0`.
+    pub fn open(dev: u16) -> io::Result<Self> {
+        // SAFETY: the arguments are plain values, and the descriptor returned is owned below.
+        let fd = check(unsafe {
+            libc::socket(
+                libc::AF_BLUETOOTH,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                BTPROTO_HCI,
+            )
+        })?;
+        // SAFETY: the descriptor was just created, and is owned by nothing else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let addr = SockaddrHci {
+            hci_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+            hci_dev: dev,
+            hci_channel: HCI_CHANNEL_USER,
+        };
+        // SAFETY: the address is valid for the length given.
+        check(unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                (&addr as *const SockaddrHci).cast(),
+                size_of::<SockaddrHci>() as libc::socklen_t,
+            )
+        })?;
+        Self::from_fd(fd)
+    }
+
+    /// Use a socket already opened, for instance by a privileged process.
+    ///
+    /// The socket must send and receive one HCI packet per message, each starting with its
+    /// packet indicator. It is set to non-blocking mode.
+    pub fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        // SAFETY: the descriptor is owned, and valid for the duration of the calls.
+        let flags = check(unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) })?;
+        check(unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) })?;
+        Ok(Self {
+            fd: AsyncFd::with_interest(fd, Interest::READABLE | Interest::WRITABLE)?,
+        })
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fd
+            .async_io(Interest::READABLE, |fd| {
+                // SAFETY: the buffer is valid for writes of its length.
+                let n = unsafe { libc::recv(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            })
+            .await
+    }
+
+    async fn send(&self, data: &[u8]) -> io::Result<()> {
+        self.fd
+            .async_io(Interest::WRITABLE, |fd| {
+                // SAFETY: the data is valid for reads of its length.
+                let n = unsafe { libc::send(fd.as_raw_fd(), data.as_ptr().cast(), data.len(), 0) };
+                if n < 0 { Err(io::Error::last_os_error()) } else { Ok(()) }
+            })
+            .await
+    }
+}
+
+impl embedded_io::ErrorType for HciSocket {
+    type Error = Error<io::Error>;
+}
+
+impl Transport for HciSocket {
+    async fn read<'a>(&self, rx: &'a mut [u8]) -> Result<ControllerToHostPacket<'a>, Self::Error> {
+        let len = loop {
+            let n = self.recv(rx).await.map_err(Error::Read)?;
+            if n == 0 {
+                return Err(Error::Eof);
+            }
+            match parse(&rx[..n]) {
+                Ok(_) => break n,
+                Err(e) => warn!("[hci] discarding invalid packet: {:?}", e),
+            }
+        };
+        // The packet was parsed above, parsing again to return it with the lifetime of the buffer.
+        let (packet, _) = unwrap!(parse(&rx[..len]).ok());
+        Ok(packet)
+    }
+
+    async fn write<T: HostToControllerPacket>(&self, tx: &T) -> Result<(), Self::Error> {
+        let packet = WithIndicator::new(tx);
+        // Room for the length octet of slices left out of their size by bt-hci.
+        let mut buf = vec![0; packet.size() + 8];
+        let total = buf.len();
+        let mut w = &mut buf[..];
+        packet
+            .write_hci(&mut w)
+            .map_err(|_| Error::Write(io::ErrorKind::InvalidInput.into()))?;
+        let len = total - w.len();
+        self.send(&buf[..len]).await.map_err(Error::Write)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bt_hci::cmd::Cmd;
+    use bt_hci::cmd::controller_baseband::Reset;
+    use bt_hci::event::Event;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn packet_per_message() {
+        let mut fds = [0; 2];
+        check(unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) }).unwrap();
+        let (host, controller) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        let socket = HciSocket::from_fd(host).unwrap();
+
+        socket.write(&Reset::new()).await.unwrap();
+        let mut buf = [0u8; 16];
+        let n = unsafe { libc::recv(controller.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+        assert_eq!(&buf[..n as usize], &[0x01, 0x03, 0x0c, 0x00]);
+
+        let event: [u8; 7] = [0x04, 0x0e, 0x04, 0x01, 0x03, 0x0c, 0x00];
+        for message in [&[0x04, 0x3e, 0x01, 0x7f][..], &event] {
+            unsafe { libc::send(controller.as_raw_fd(), message.as_ptr().cast(), message.len(), 0) };
+        }
+        let mut rx = [0; 16];
+        let packet = socket.read(&mut rx).await.unwrap();
+        let ControllerToHostPacket::Event(Event::CommandComplete(event)) = packet else {
+            panic!("unexpected packet");
+        };
+        assert_eq!(event.cmd_opcode, Reset::OPCODE);
+
+        drop(controller);
+        assert!(matches!(socket.read(&mut rx).await, Err(Error::Eof)));
+    }
+}
@@ -0,0 +1,316 @@
+//! The SDIO transport, for Wi-Fi and Bluetooth combo chips exposing HCI on an SDIO function.
+//!
+//! The Bluetooth function of the chip carries packets prefixed by a header of their length and
+//! service, through a data register and a few packet control registers. Functions of the type B
+//! class are switched to this type A mode when the transport is created.
+//!
+//! The transport is written against the [`SdioFunction`] and [`SdioInterrupt`] traits, to be
+//! implemented over the SDIO host of the application once the card is initialized and the
+//! function enabled.
+use core::future::Future;
+
+use bt_hci::transport::Transport;
+use bt_hci::{ControllerToHostPacket, HostToControllerPacket, PacketKind};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+
+use super::Error;
+
+const REG_DATA: u32 = 0x00;
+const REG_RX_PACKET_CONTROL: u32 = 0x10;
+const REG_TX_PACKET_CONTROL: u32 = 0x11;
+const REG_INTERRUPT: u32 = 0x13;
+const REG_INTERRUPT_ENABLE: u32 = 0x14;
+const REG_MODE: u32 = 0x20;
+
+const PACKET_CONTROL_ACK: u8 = 0x00;
+const PACKET_CONTROL_RETRY: u8 = 0x01;
+const INTERRUPT_PACKET: u8 = 0x01;
+
+// Attempts at transferring a packet before failing.
+const ATTEMPTS: usize = 3;
+
+/// The Bluetooth function of an SDIO card.
+pub trait SdioFunction: embedded_io::ErrorType {
+    /// Read a register of the function, with CMD52.
+    fn read_register(&mut self, addr: u32) -> impl Future<Output = Result<u8, Self::Error>>;
+
+    /// Write a register of the function, with CMD52.
+    fn write_register(&mut self, addr: u32, value: u8) -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// Read from a register into the buffer, with CMD53 at a fixed address.
+    fn read_fifo(&mut self, addr: u32, buf: &mut [u8]) -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// Write the data to a register, with CMD53 at a fixed address.
+    fn write_fifo(&mut self, addr: u32, data: &[u8]) -> impl Future<Output = Result<(), Self::Error>>;
+}
+
+/// The interrupt of an SDIO card.
+pub trait SdioInterrupt {
+    /// Wait for the card to signal an interrupt.
+    fn wait(&mut self) -> impl Future<Output = ()>;
+}
+
+/// Class of the Bluetooth function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SdioClass {
+    /// The Bluetooth type A class.
+    TypeA,
+    /// The Bluetooth type B class, used in type A mode.
+    TypeB,
+}
+
+/// HCI transport over the Bluetooth function of an SDIO card.
+///
+/// `PACKET_LEN` is the size of the largest packet sent, its SDIO header of 4 octets included.
+pub struct SdioTransport<M: RawMutex, F, I, const PACKET_LEN: usize = 263> {
+    function: Mutex<M, F>,
+    interrupt: Mutex<M, I>,
+}
+
+impl<M: RawMutex, F: SdioFunction, I: SdioInterrupt, const PACKET_LEN: usize> SdioTransport<M, F, I, PACKET_LEN> {
+    /// Create a transport over the function, and enable its packet interrupt.
+    pub async fn new(mut function: F, interrupt: I, class: SdioClass) -> Result<Self, Error<F::Error>> {
+        if class == SdioClass::TypeB {
+            function.write_register(REG_MODE, 0x00).await.map_err(Error::Write)?;
+        }
+        function
+            .write_register(REG_INTERRUPT_ENABLE, INTERRUPT_PACKET)
+            .await
+            .map_err(Error::Write)?;
+        Ok(Self {
+            function: Mutex::new(function),
+            interrupt: Mutex::new(interrupt),
+        })
+    }
+}
+
+// Read the packet of the function into the buffer, returning its kind and length.
+//
+// Returns `None` for packets discarded.
+async fn read_packet<F: SdioFunction>(f: &mut F, rx: &mut [u8]) -> Result<Option<(u8, usize)>, F::Error> {
+    let mut header = [0; 4];
+    f.read_fifo(REG_DATA, &mut header).await?;
+    let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+    let Some(len) = len.checked_sub(header.len()) else {
+        warn!("[sdio] discarding packet of invalid length");
+        return Ok(None);
+    };
+    let Some(buf) = rx.get_mut(..len) else {
+        warn!(
+            "[sdio] discarding packet of {} octets, larger than the receive buffer",
+            len
+        );
+        let mut scratch = [0; 32];
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = remaining.min(scratch.len());
+            f.read_fifo(REG_DATA, &mut scratch[..n]).await?;
+            remaining -= n;
+        }
+        return Ok(None);
+    };
+    f.read_fifo(REG_DATA, buf).await?;
+    Ok(Some((header[3], len)))
+}
+
+impl<M: RawMutex, F, I, const PACKET_LEN: usize> embedded_io::ErrorType for SdioTransport<M, F, I, PACKET_LEN>
+where
+    F: embedded_io::ErrorType,
+{
+    type Error = Error<F::Error>;
+}
+
+impl<M: RawMutex, F, I, const PACKET_LEN: usize> Transport for SdioTransport<M, F, I, PACKET_LEN>
+where
+    F: SdioFunction,
+    I: SdioInterrupt,
+{
+    async fn read<'a>(&self, rx: &'a mut [u8]) -> Result<ControllerToHostPacket<'a>, Self::Error> {
+        let mut interrupt = self.interrupt.lock().await;
+        let (kind, len) = 'outer: loop {
+            interrupt.wait().await;
+            let mut f = self.function.lock().await;
+            let pending = f.read_register(REG_INTERRUPT).await.map_err(Error::Read)?;
+            if pending & INTERRUPT_PACKET == 0 {
+                continue;
+            }
+            f.write_register(REG_INTERRUPT, INTERRUPT_PACKET)
+                .await
+                .map_err(Error::Write)?;
+
+            let mut attempt = 1;
+            let packet = loop {
+                match read_packet(&mut *f, rx).await {
+                    Ok(packet) => break packet,
+                    Err(e) if attempt == ATTEMPTS => return Err(Error::Read(e)),
+                    Err(_) => {
+                        warn!("[sdio] error reading packet, retrying");
+                        f.write_register(REG_RX_PACKET_CONTROL, PACKET_CONTROL_RETRY)
+                            .await
+                            .map_err(Error::Write)?;
+                        attempt += 1;
+                    }
+                }
+            };
+            f.write_register(REG_RX_PACKET_CONTROL, PACKET_CONTROL_ACK)
+                .await
+                .map_err(Error::Write)?;
+
+            let Some((service, len)) = packet else {
+                continue;
+            };
+            let kind = match service {
+                0x02 => PacketKind::AclData,
+                0x03 => PacketKind::SyncData,
+                0x04 => PacketKind::Event,
+                0x05 => PacketKind::IsoData,
+                _ => {
+                    warn!("[sdio] discarding packet of unexpected service {}", service);
+                    continue;
+                }
+            };
+            match super::parse_packet(kind, &rx[..len]) {
+                Ok(_) => break 'outer (kind, len),
+                Err(e) => warn!("[sdio] discarding invalid packet: {:?}", e),
+            }
+        };
+        // The packet was parsed above, parsing again to return it with the lifetime of the buffer.
+        let (packet, _) = unwrap!(super::parse_packet(kind, &rx[..len]).ok());
+        Ok(packet)
+    }
+
+    async fn write<T: HostToControllerPacket>(&self, tx: &T) -> Result<(), Self::Error> {
+        let mut buf = [0; PACKET_LEN];
+        let mut w = &mut buf[4..];
+        tx.write_hci(&mut w).map_err(|_| Error::TooLarge)?;
+        let len = PACKET_LEN - w.len();
+        let [l0, l1, l2, _] = (len as u32).to_le_bytes();
+        buf[..4].copy_from_slice(&[l0, l1, l2, T::KIND as u8]);
+
+        let mut f = self.function.lock().await;
+        let mut attempt = 1;
+        loop {
+            match f.write_fifo(REG_DATA, &buf[..len]).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt == ATTEMPTS => return Err(Error::Write(e)),
+                Err(_) => {
+                    warn!("[sdio] error writing packet, retrying");
+                    f.write_register(REG_TX_PACKET_CONTROL, PACKET_CONTROL_RETRY)
+                        .await
+                        .map_err(Error::Write)?;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bt_hci::cmd::Cmd;
+    use bt_hci::cmd::controller_baseband::Reset;
+    use bt_hci::event::Event;
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use heapless::Vec;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct BusError;
+
+    impl embedded_io::Error for BusError {
+        fn kind(&self) -> embedded_io::ErrorKind {
+            embedded_io::ErrorKind::Other
+        }
+    }
+
+    // Function receiving the packets in order, failing the first read of data.
+    #[derive(Default)]
+    struct Function {
+        rx: &'static [u8],
+        fail_read: bool,
+        registers: Vec<(u32, u8), 16>,
+        tx: Vec<u8, 32>,
+    }
+
+    impl embedded_io::ErrorType for Function {
+        type Error = BusError;
+    }
+
+    impl SdioFunction for Function {
+        async fn read_register(&mut self, addr: u32) -> Result<u8, Self::Error> {
+            assert_eq!(addr, REG_INTERRUPT);
+            Ok(INTERRUPT_PACKET)
+        }
+
+        async fn write_register(&mut self, addr: u32, value: u8) -> Result<(), Self::Error> {
+            self.registers.push((addr, value)).unwrap();
+            Ok(())
+        }
+
+        async fn read_fifo(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+            assert_eq!(addr, REG_DATA);
+            if self.fail_read && buf.len() > 4 {
+                self.fail_read = false;
+                // The card sends the packet again from its start.
+                return Err(BusError);
+            }
+            let (data, rest) = self.rx.split_at(buf.len());
+            buf.copy_from_slice(data);
+            self.rx = rest;
+            Ok(())
+        }
+
+        async fn write_fifo(&mut self, addr: u32, data: &[u8]) -> Result<(), Self::Error> {
+            assert_eq!(addr, REG_DATA);
+            self.tx.extend_from_slice(data).unwrap();
+            Ok(())
+        }
+    }
+
+    struct Interrupt;
+
+    impl SdioInterrupt for Interrupt {
+        async fn wait(&mut self) {}
+    }
+
+    #[test]
+    fn packets_with_service_header() {
+        let function = Function {
+            #[rustfmt::skip]
+            rx: &[
+                // Header of the packet failing to be read, then sent again.
+                0x0a, 0x00, 0x00, 0x04,
+                0x0a, 0x00, 0x00, 0x04, 0x0e, 0x04, 0x01, 0x03, 0x0c, 0x00,
+            ],
+            fail_read: true,
+            ..Default::default()
+        };
+        let transport: SdioTransport<NoopRawMutex, _, _, 16> =
+            block_on(SdioTransport::new(function, Interrupt, SdioClass::TypeB)).unwrap();
+
+        let mut rx = [0; 16];
+        let packet = block_on(transport.read(&mut rx)).unwrap();
+        let ControllerToHostPacket::Event(Event::CommandComplete(event)) = packet else {
+            panic!("unexpected packet");
+        };
+        assert_eq!(event.cmd_opcode, Reset::OPCODE);
+
+        block_on(transport.write(&Reset::new())).unwrap();
+        let f = transport.function.try_lock().unwrap();
+        assert_eq!(f.tx, [0x07, 0x00, 0x00, 0x01, 0x03, 0x0c, 0x00]);
+        assert_eq!(
+            f.registers,
+            [
+                (REG_MODE, 0x00),
+                (REG_INTERRUPT_ENABLE, INTERRUPT_PACKET),
+                (REG_INTERRUPT, INTERRUPT_PACKET),
+                (REG_RX_PACKET_CONTROL, PACKET_CONTROL_RETRY),
+                (REG_RX_PACKET_CONTROL, PACKET_CONTROL_ACK),
+            ]
+        );
+    }
+}
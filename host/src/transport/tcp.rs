@@ -0,0 +1,130 @@
+//! The UART transport (H4) over TCP, to run the host against an emulated controller.
+//!
+//! Virtual controllers, such as those of QEMU and Zephyr, and proxies forwarding a controller
+//! attached to another machine, expose the H4 packet stream on a TCP socket. The stream is
+//! registered with the tokio reactor, so the transport must be used within a tokio runtime.
+use std::io;
+
+use bt_hci::transport::Transport;
+use bt_hci::{ControllerToHostPacket, HostToControllerPacket};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use super::Error;
+use super::h4::H4Transport;
+
+struct Reader(OwnedReadHalf);
+
+impl embedded_io::ErrorType for Reader {
+    type Error = io::Error;
+}
+
+impl embedded_io_async::Read for Reader {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        loop {
+            self.0.readable().await?;
+            match self.0.try_read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+        }
+    }
+}
+
+struct Writer(OwnedWriteHalf);
+
+impl embedded_io::ErrorType for Writer {
+    type Error = io::Error;
+}
+
+impl embedded_io_async::Write for Writer {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        loop {
+            self.0.writable().await?;
+            match self.0.try_write(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+        }
+    }
+}
+
+/// HCI transport over a TCP connection to a controller.
+pub struct TcpTransport<M: RawMutex> {
+    inner: H4Transport<M, Reader, Writer>,
+}
+
+impl<M: RawMutex> TcpTransport<M> {
+    /// Connect to the controller listening at the address.
+    pub async fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::from_stream(TcpStream::connect(addr).await?)
+    }
+
+    /// Use a connection already established, for instance accepted from a controller connecting
+    /// to the host.
+    pub fn from_stream(stream: TcpStream) -> io::Result<Self> {
+        // Packets are written as a whole, and should not wait for more data to be sent.
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            inner: H4Transport::new(Reader(reader), Writer(writer)),
+        })
+    }
+}
+
+impl<M: RawMutex> embedded_io::ErrorType for TcpTransport<M> {
+    type Error = Error<io::Error>;
+}
+
+impl<M: RawMutex> Transport for TcpTransport<M> {
+    async fn read<'a>(&self, rx: &'a mut [u8]) -> Result<ControllerToHostPacket<'a>, Self::Error> {
+        self.inner.read(rx).await
+    }
+
+    async fn write<T: HostToControllerPacket>(&self, tx: &T) -> Result<(), Self::Error> {
+        self.inner.write(tx).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bt_hci::cmd::Cmd;
+    use bt_hci::cmd::controller_baseband::Reset;
+    use bt_hci::event::Event;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn h4_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let transport: TcpTransport<NoopRawMutex> =
+            TcpTransport::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (controller, _) = listener.accept().await.unwrap();
+
+        transport.write(&Reset::new()).await.unwrap();
+        let mut buf = [0; 4];
+        let mut len = 0;
+        while len < buf.len() {
+            controller.readable().await.unwrap();
+            len += controller.try_read(&mut buf[len..]).unwrap_or(0);
+        }
+        assert_eq!(buf, [0x01, 0x03, 0x0c, 0x00]);
+
+        controller.writable().await.unwrap();
+        controller
+            .try_write(&[0x04, 0x0e, 0x04, 0x01, 0x03, 0x0c, 0x00])
+            .unwrap();
+        let mut rx = [0; 16];
+        let packet = transport.read(&mut rx).await.unwrap();
+        let ControllerToHostPacket::Event(Event::CommandComplete(event)) = packet else {
+            panic!("unexpected packet");
+        };
+        assert_eq!(event.cmd_opcode, Reset::OPCODE);
+
+        drop(controller);
+        assert!(matches!(transport.read(&mut rx).await, Err(Error::Eof)));
+    }
+}
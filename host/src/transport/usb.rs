@@ -0,0 +1,346 @@
+//! The USB transport, for dongles and controllers attached over USB.
+//!
+//! The Bluetooth interface of a USB controller carries commands in class requests on the control
+//! endpoint, events on an interrupt IN endpoint, and ACL data on a pair of bulk endpoints. The
+//! transport is written against the [`ControlPipe`], [`InPipe`] and [`OutPipe`] traits, to be
+//! implemented over the USB host stack of the application once the controller is enumerated
+//! and its interface claimed.
+//!
+//! Synchronous data, carried by isochronous endpoints, and ISO data are not supported.
+use core::future::Future;
+
+use bt_hci::transport::Transport;
+use bt_hci::{ControllerToHostPacket, HostToControllerPacket, PacketKind, WriteHci};
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+
+/// The control endpoint of a controller, carrying commands.
+pub trait ControlPipe: embedded_io::ErrorType {
+    /// Send the data in a class request to the Bluetooth interface.
+    ///
+    /// The request is sent with a `bmRequestType` of 0x20, and a `bRequest`, `wValue` and
+    /// `wIndex` of 0.
+    fn send(&mut self, data: &[u8]) -> impl Future<Output = Result<(), Self::Error>>;
+}
+
+/// An IN endpoint of a controller, the interrupt endpoint of events or the bulk endpoint of ACL data.
+pub trait InPipe: embedded_io::ErrorType {
+    /// Receive a transfer into the buffer, returning its length.
+    ///
+    /// A packet larger than the maximum packet size of the endpoint is received in several
+    /// transfers. The transport waits for both IN endpoints at once, so a receive cancelled
+    /// before completing must not lose data.
+    fn receive(&mut self, buf: &mut [u8]) -> impl Future<Output = Result<usize, Self::Error>>;
+}
+
+/// An OUT endpoint of a controller, the bulk endpoint of ACL data.
+pub trait OutPipe: embedded_io::ErrorType {
+    /// Send the data in a transfer.
+    fn send(&mut self, data: &[u8]) -> impl Future<Output = Result<(), Self::Error>>;
+}
+
+/// Error of the USB transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E: embedded_io::Error> {
+    /// Error of an endpoint.
+    Usb(E),
+    /// The packet kind is not carried by the transport.
+    Unsupported,
+    /// The packet is larger than the buffers of the transport.
+    TooLarge,
+}
+
+impl<E: embedded_io::Error> embedded_io::Error for Error<E> {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Self::Usb(e) => e.kind(),
+            Self::Unsupported => embedded_io::ErrorKind::Unsupported,
+            Self::TooLarge => embedded_io::ErrorKind::InvalidInput,
+        }
+    }
+}
+
+// Packet being reassembled from the transfers of an IN endpoint.
+struct Reassembly<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    // Remaining octets of a packet too large for the buffer.
+    discard: usize,
+}
+
+impl<const N: usize> Reassembly<N> {
+    const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+            discard: 0,
+        }
+    }
+
+    fn free(&mut self) -> &mut [u8] {
+        &mut self.buf[self.len..]
+    }
+
+    // Account for a received transfer, returning the length of the completed packet.
+    //
+    // The total length of a packet is given by its header length and the length field of it.
+    fn received(&mut self, n: usize, header_len: usize, total_len: impl Fn(&[u8]) -> usize) -> Option<usize> {
+        if self.discard > 0 {
+            self.discard = self.discard.saturating_sub(n);
+            return None;
+        }
+        self.len += n;
+        if self.len < header_len {
+            return None;
+        }
+        let total = total_len(&self.buf[..header_len]);
+        if total > N {
+            warn!(
+                "[usb] discarding packet of {} octets, larger than the receive buffer",
+                total
+            );
+            self.discard = total - self.len;
+            self.len = 0;
+            None
+        } else if self.len >= total {
+            if self.len > total {
+                warn!("[usb] discarding {} octets after the end of a packet", self.len - total);
+            }
+            self.len = 0;
+            Some(total)
+        } else {
+            None
+        }
+    }
+}
+
+struct Rx<E, B, const ACL_LEN: usize> {
+    events: E,
+    bulk_in: B,
+    event: Reassembly<257>,
+    acl: Reassembly<ACL_LEN>,
+}
+
+/// HCI transport over the endpoints of a USB controller.
+///
+/// `ACL_LEN` is the size of the largest ACL packet sent or received, header included.
+pub struct UsbTransport<M: RawMutex, C, E, B, O, const ACL_LEN: usize = 259> {
+    control: Mutex<M, C>,
+    bulk_out: Mutex<M, O>,
+    rx: Mutex<M, Rx<E, B, ACL_LEN>>,
+}
+
+impl<M: RawMutex, C, E, B, O, const ACL_LEN: usize> UsbTransport<M, C, E, B, O, ACL_LEN> {
+    /// Create a transport over the control endpoint, the interrupt endpoint of events and the bulk
+    /// endpoints of a controller.
+    pub fn new(control: C, events: E, bulk_in: B, bulk_out: O) -> Self {
+        Self {
+            control: Mutex::new(control),
+            bulk_out: Mutex::new(bulk_out),
+            rx: Mutex::new(Rx {
+                events,
+                bulk_in,
+                event: Reassembly::new(),
+                acl: Reassembly::new(),
+            }),
+        }
+    }
+}
+
+impl<M: RawMutex, C, E, B, O, X, const ACL_LEN: usize> embedded_io::ErrorType for UsbTransport<M, C, E, B, O, ACL_LEN>
+where
+    C: embedded_io::ErrorType<Error = X>,
+    E: embedded_io::ErrorType<Error = X>,
+    B: embedded_io::ErrorType<Error = X>,
+    O: embedded_io::ErrorType<Error = X>,
+    X: embedded_io::Error,
+{
+    type Error = Error<X>;
+}
+
+impl<M: RawMutex, C, E, B, O, X, const ACL_LEN: usize> Transport for UsbTransport<M, C, E, B, O, ACL_LEN>
+where
+    C: ControlPipe<Error = X>,
+    E: InPipe<Error = X>,
+    B: InPipe<Error = X>,
+    O: OutPipe<Error = X>,
+    X: embedded_io::Error,
+{
+    async fn read<'a>(&self, rx: &'a mut [u8]) -> Result<ControllerToHostPacket<'a>, Self::Error> {
+        let mut guard = self.rx.lock().await;
+        let Rx {
+            events,
+            bulk_in,
+            event,
+            acl,
+        } = &mut *guard;
+        let (kind, len) = loop {
+            let (kind, packet) = match select(events.receive(event.free()), bulk_in.receive(acl.free())).await {
+                Either::First(n) => {
+                    let n = n.map_err(Error::Usb)?;
+                    let Some(len) = event.received(n, 2, |h| 2 + h[1] as usize) else {
+                        continue;
+                    };
+                    (PacketKind::Event, &event.buf[..len])
+                }
+                Either::Second(n) => {
+                    let n = n.map_err(Error::Usb)?;
+                    let Some(len) = acl.received(n, 4, |h| 4 + u16::from_le_bytes([h[2], h[3]]) as usize) else {
+                        continue;
+                    };
+                    (PacketKind::AclData, &acl.buf[..len])
+                }
+            };
+            let Some(dest) = rx.get_mut(..packet.len()) else {
+                warn!(
+                    "[usb] discarding packet of {} octets, larger than the read buffer",
+                    packet.len()
+                );
+                continue;
+            };
+            dest.copy_from_slice(packet);
+            match super::parse_packet(kind, dest) {
+                Ok(_) => break (kind, packet.len()),
+                Err(e) => warn!("[usb] discarding invalid packet: {:?}", e),
+            }
+        };
+        // The packet was parsed above, parsing again to return it with the lifetime of the buffer.
+        let (packet, _) = unwrap!(super::parse_packet(kind, &rx[..len]).ok());
+        Ok(packet)
+    }
+
+    async fn write<T: HostToControllerPacket>(&self, tx: &T) -> Result<(), Self::Error> {
+        match T::KIND {
+            PacketKind::Cmd => {
+                // The parameters of a command are at most 255 octets.
+                let mut buf = [0; 258];
+                let len = encode(tx, &mut buf)?;
+                self.control.lock().await.send(&buf[..len]).await.map_err(Error::Usb)
+            }
+            PacketKind::AclData => {
+                let mut buf = [0; ACL_LEN];
+                let len = encode(tx, &mut buf)?;
+                self.bulk_out.lock().await.send(&buf[..len]).await.map_err(Error::Usb)
+            }
+            _ => Err(Error::Unsupported),
+        }
+    }
+}
+
+// Encode the packet into the buffer, returning its length.
+fn encode<T: WriteHci, E: embedded_io::Error>(packet: &T, buf: &mut [u8]) -> Result<usize, Error<E>> {
+    let total = buf.len();
+    let mut w = &mut buf[..];
+    packet.write_hci(&mut w).map_err(|_| Error::TooLarge)?;
+    Ok(total - w.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+
+    use bt_hci::cmd::Cmd;
+    use bt_hci::cmd::controller_baseband::Reset;
+    use bt_hci::data::{AclBroadcastFlag, AclPacket, AclPacketBoundary};
+    use bt_hci::event::Event;
+    use bt_hci::param::ConnHandle;
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+
+    // Pipe receiving the transfers in order, then never completing.
+    struct Transfers(&'static [&'static [u8]]);
+
+    impl embedded_io::ErrorType for Transfers {
+        type Error = Infallible;
+    }
+
+    impl InPipe for Transfers {
+        async fn receive(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let Some((transfer, rest)) = self.0.split_first() else {
+                return core::future::pending().await;
+            };
+            self.0 = rest;
+            let n = transfer.len().min(buf.len());
+            buf[..n].copy_from_slice(&transfer[..n]);
+            Ok(n)
+        }
+    }
+
+    #[derive(Default)]
+    struct Sent(heapless::Vec<heapless::Vec<u8, 16>, 2>);
+
+    impl embedded_io::ErrorType for Sent {
+        type Error = Infallible;
+    }
+
+    impl ControlPipe for Sent {
+        async fn send(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.0.push(heapless::Vec::from_slice(data).unwrap()).unwrap();
+            Ok(())
+        }
+    }
+
+    impl OutPipe for Sent {
+        async fn send(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.0.push(heapless::Vec::from_slice(data).unwrap()).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn read_reassembles_transfers() {
+        let events = Transfers(&[&[0x0e, 0x04, 0x01], &[0x03, 0x0c, 0x00]]);
+        let bulk_in = Transfers(&[
+            // Larger than the buffer.
+            &[0x01, 0x20, 0x08, 0x00, 0, 0, 0, 0],
+            &[0, 0, 0, 0],
+            &[0x01, 0x20, 0x02, 0x00, 0xaa, 0xbb],
+        ]);
+        let transport: UsbTransport<NoopRawMutex, _, _, _, _, 8> =
+            UsbTransport::new(Sent::default(), events, bulk_in, Sent::default());
+        let mut rx = [0; 16];
+
+        let packet = block_on(transport.read(&mut rx)).unwrap();
+        let ControllerToHostPacket::Event(Event::CommandComplete(event)) = packet else {
+            panic!("unexpected packet");
+        };
+        assert_eq!(event.cmd_opcode, Reset::OPCODE);
+
+        let packet = block_on(transport.read(&mut rx)).unwrap();
+        let ControllerToHostPacket::Acl(acl) = packet else {
+            panic!("unexpected packet");
+        };
+        assert_eq!(acl.data(), &[0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn write_selects_endpoint() {
+        let transport: UsbTransport<NoopRawMutex, _, _, _, _, 8> =
+            UsbTransport::new(Sent::default(), Transfers(&[]), Transfers(&[]), Sent::default());
+        block_on(transport.write(&Reset::new())).unwrap();
+        let acl = AclPacket::new(
+            ConnHandle::new(1),
+            AclPacketBoundary::FirstNonFlushable,
+            AclBroadcastFlag::PointToPoint,
+            &[1, 2, 3],
+        );
+        block_on(transport.write(&acl)).unwrap();
+        let long = AclPacket::new(
+            ConnHandle::new(1),
+            AclPacketBoundary::FirstNonFlushable,
+            AclBroadcastFlag::PointToPoint,
+            &[0; 5],
+        );
+        assert_eq!(block_on(transport.write(&long)), Err(Error::TooLarge));
+
+        assert_eq!(transport.control.try_lock().unwrap().0, [&[0x03, 0x0c, 0x00][..]]);
+        assert_eq!(
+            transport.bulk_out.try_lock().unwrap().0,
+            [&[0x01, 0x00, 0x03, 0x00, 1, 2, 3][..]]
+        );
+    }
+}
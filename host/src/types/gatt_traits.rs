@@ -179,7 +179,7 @@ impl AsGatt for &'static str {
     }
 }
 
-impl AsGatt for &'static [u8] {
+impl AsGatt for &[u8] {
     const MIN_SIZE: usize = 0;
     const MAX_SIZE: usize = usize::MAX;
 
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use tokio::select;
+use trouble_host::prelude::*;
+use trouble_host::services::battery::{BatteryClient, BatteryServer, BatteryStorage};
+
+mod common;
+
+const CONNECTIONS_MAX: usize = 1;
+const L2CAP_CHANNELS_MAX: usize = 3;
+
+#[tokio::test]
+async fn battery_client_reads_and_subscribes_to_level() {
+    let _ = env_logger::try_init();
+    let adapters = common::find_controllers();
+    let peripheral = adapters[0].clone();
+    let central = adapters[1].clone();
+
+    let peripheral_address: Address = Address::random([0xff, 0x9f, 0x1a, 0x05, 0xe4, 0xfe]);
+
+    let local = tokio::task::LocalSet::new();
+
+    // Spawn peripheral
+    let peripheral = local.spawn_local(async move {
+        let controller_peripheral = common::create_controller(&peripheral).await;
+
+        let mut resources: HostResources<CONNECTIONS_MAX, L2CAP_CHANNELS_MAX, 27> = HostResources::new();
+        let stack = trouble_host::new(controller_peripheral, &mut resources).set_random_address(peripheral_address);
+        let Host {
+            mut peripheral,
+            mut runner,
+            ..
+        } = stack.build();
+
+        let id = b"Trouble";
+        let appearance = [0x80, 0x07];
+        let mut storage = BatteryStorage::new();
+
+        let mut table: AttributeTable<'_, NoopRawMutex, 10> = AttributeTable::new();
+        let mut svc = table.add_service(Service::new(0x1800u16));
+        let _ = svc.add_characteristic_ro(0x2a00u16, id);
+        let _ = svc.add_characteristic_ro(0x2a01u16, &appearance);
+        svc.build();
+
+        // Generic attribute service (mandatory)
+        table.add_service(Service::new(0x1801u16));
+
+        let battery = BatteryServer::build(&mut table, &mut storage, 80).unwrap();
+
+        let server = AttributeServer::<NoopRawMutex, 10>::new(table);
+        select! {
+            r = runner.run() => {
+                r
+            }
+            r = async {
+                let mut adv_data = [0; 31];
+                AdStructure::encode_slice(
+                    &[AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED)],
+                    &mut adv_data[..],
+                ).unwrap();
+
+                println!("[peripheral] advertising");
+                let acceptor = peripheral.advertise(&Default::default(), Advertisement::ConnectableScannableUndirected {
+                    adv_data: &adv_data[..],
+                    scan_data: &[],
+                }).await?;
+                let conn = acceptor.accept().await?;
+                println!("[peripheral] connected");
+                loop {
+                    match conn.next().await {
+                        ConnectionEvent::Disconnected { reason } => {
+                            println!("Disconnected: {:?}", reason);
+                            break;
+                        }
+                        ConnectionEvent::Gatt { data } => match data.process(&server).await {
+                            Ok(Some(GattEvent::Read(event))) => {
+                                event.accept().unwrap().send().await;
+                            }
+                            Ok(Some(GattEvent::Write(event))) => {
+                                event.accept().unwrap().send().await;
+                                println!("[peripheral] client subscribed");
+                                // Leave the client time to listen once its subscription is confirmed.
+                                tokio::time::sleep(Duration::from_secs(1)).await;
+                                battery.set_level(&server, &conn, 42).await.unwrap();
+                                println!("[peripheral] level notified");
+                                // NOTE: Ensure that adapter gets polled again
+                                tokio::time::sleep(Duration::from_secs(2)).await;
+                                break;
+                            }
+                            _ => {}
+                        },
+                        _ => {}
+                    }
+                }
+                Ok(())
+            } => {
+                r
+            }
+        }
+    });
+
+    // Spawn central
+    let central = local.spawn_local(async move {
+        let controller_central = common::create_controller(&central).await;
+        let mut resources: HostResources<CONNECTIONS_MAX, L2CAP_CHANNELS_MAX, 27> = HostResources::new();
+        let stack = trouble_host::new(controller_central, &mut resources);
+        let Host {
+            mut central,
+            mut runner,
+            ..
+        } = stack.build();
+
+        select! {
+            r = runner.run() => {
+                r
+            }
+            r = async {
+                let config = ConnectConfig {
+                    connect_params: Default::default(),
+                    scan_config: ScanConfig {
+                        active: true,
+                        filter_accept_list: &[(peripheral_address.kind, &peripheral_address.addr)],
+                        ..Default::default()
+                    },
+                };
+
+                println!("[central] connecting");
+                let conn = central.connect(&config).await.unwrap();
+                println!("[central] connected");
+                tokio::time::sleep(Duration::from_secs(5)).await;
+
+                println!("[central] creating gatt client");
+                let client = GattClient::<common::Controller, 10, 27>::new(&stack, &conn).await.unwrap();
+
+                select! {
+                    r = async {
+                        client.task().await
+                    } => {
+                        r
+                    }
+                    r = async {
+                        println!("[central] discovering battery service");
+                        let battery = BatteryClient::new(&client).await.unwrap();
+                        assert_eq!(battery.read_level().await.unwrap(), 80);
+                        println!("[central] level read");
+
+                        let mut listener = battery.subscribe_level().await.unwrap();
+                        assert_eq!(listener.next().await, 42);
+                        println!("[central] level notified");
+                        Ok(())
+                    } => {
+                        r
+                    }
+                }
+            } => {
+                r
+            }
+        }
+    });
+
+    match tokio::time::timeout(Duration::from_secs(30), local).await {
+        Ok(_) => match tokio::join!(central, peripheral) {
+            (Err(e1), Err(e2)) => {
+                println!("Central error: {:?}", e1);
+                println!("Peripheral error: {:?}", e2);
+                panic!();
+            }
+            (Err(e), _) => {
+                println!("Central error: {:?}", e);
+                panic!();
+            }
+            (_, Err(e)) => {
+                println!("Peripheral error: {:?}", e);
+                panic!();
+            }
+            _ => {
+                println!("Test completed successfully");
+            }
+        },
+        Err(e) => {
+            println!("Test timed out: {:?}", e);
+            panic!();
+        }
+    }
+}
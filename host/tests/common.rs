@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use bt_hci::controller::ExternalController;
 use bt_hci::transport::SerialTransport;
@@ -31,7 +31,7 @@ pub fn find_controllers() -> Vec<PathBuf> {
 
 #[allow(unused)]
 pub(crate) async fn create_controller(
-    port: &PathBuf,
+    port: &Path,
 ) -> ExternalController<
     SerialTransport<NoopRawMutex, FromTokio<ReadHalf<SerialStream>>, FromTokio<WriteHalf<SerialStream>>>,
     10,
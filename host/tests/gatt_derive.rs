@@ -146,6 +146,7 @@ async fn gatt_client_server() {
                                     }
                                 }
                             }
+                            _ => {}
                         }
                     }
                     // NOTE: Ensure that adapter gets polled again
@@ -212,7 +213,7 @@ async fn gatt_client_server() {
                         println!("[central] read value: {}", data[0]);
                         data[0] = data[0].wrapping_add(1);
                         println!("[central] write value: {}", data[0]);
-                        if let Err(BleHostError::BleHost(Error::Att(AttErrorCode::VALUE_NOT_ALLOWED))) = client.write_characteristic(&c, &data[..]).await {
+                        if let Err(BleHostError::BleHost(Error::Gatt(GattError { procedure: GattProcedure::Write, code: AttErrorCode::VALUE_NOT_ALLOWED, .. }))) = client.write_characteristic(&c, &data[..]).await {
                             println!("[central] Frist write was rejected by write callback as expected.");
                         } else {
                             println!("[central] First write was expected to be rejected by server write callback!");
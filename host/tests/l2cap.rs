@@ -1,3 +1,6 @@
+// The advertise and connect loops run once, and the failure branches assert false.
+#![allow(clippy::never_loop, clippy::assertions_on_constants)]
+
 use tokio::select;
 use tokio::time::Duration;
 use trouble_host::prelude::*;
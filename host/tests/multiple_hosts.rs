@@ -0,0 +1,136 @@
+//! Runs two hosts over two controllers in one application.
+use core::convert::Infallible;
+use std::cell::Cell;
+
+use bt_hci::FromHciBytes;
+use bt_hci::cmd::{self, AsyncCmd, SyncCmd};
+use bt_hci::controller::{ControllerCmdAsync, ControllerCmdSync};
+use embassy_futures::join::join;
+use embassy_futures::select::select;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use trouble_host::prelude::*;
+
+/// A controller completing every command with zeroed return parameters, and never sending events.
+#[derive(Default)]
+struct NullController {
+    commands: Cell<usize>,
+}
+
+impl embedded_io::ErrorType for NullController {
+    type Error = Infallible;
+}
+
+impl bt_hci::controller::Controller for NullController {
+    async fn write_acl_data(&self, _: &bt_hci::data::AclPacket<'_>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn write_sync_data(&self, _: &bt_hci::data::SyncPacket<'_>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn write_iso_data(&self, _: &bt_hci::data::IsoPacket<'_>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn read<'a>(&self, _: &'a mut [u8]) -> Result<bt_hci::ControllerToHostPacket<'a>, Self::Error> {
+        core::future::pending().await
+    }
+}
+
+impl<C: SyncCmd> ControllerCmdSync<C> for NullController {
+    async fn exec(&self, _: &C) -> Result<C::Return, cmd::Error<Self::Error>> {
+        self.commands.set(self.commands.get() + 1);
+        Ok(C::Return::from_hci_bytes(&[0; 255]).unwrap().0)
+    }
+}
+
+impl<C: AsyncCmd> ControllerCmdAsync<C> for NullController {
+    async fn exec(&self, _: &C) -> Result<(), cmd::Error<Self::Error>> {
+        self.commands.set(self.commands.get() + 1);
+        Ok(())
+    }
+}
+
+#[gatt_server(mutex_type = NoopRawMutex)]
+struct ScanServer {
+    status: ScanStatusService,
+}
+
+#[gatt_service(uuid = "7e701cf1-b1df-42a1-bb5f-6a1028c793b0")]
+struct ScanStatusService {
+    #[characteristic(uuid = "7e701cf2-b1df-42a1-bb5f-6a1028c793b0", read, notify)]
+    seen: u16,
+}
+
+#[gatt_server(mutex_type = NoopRawMutex)]
+struct LinkServer {
+    link: LinkService,
+}
+
+#[gatt_service(uuid = "7e711cf1-b1df-42a1-bb5f-6a1028c793b0")]
+struct LinkService {
+    #[characteristic(uuid = "7e711cf2-b1df-42a1-bb5f-6a1028c793b0", read, write, notify)]
+    data: [u8; 8],
+}
+
+#[test]
+fn two_hosts_over_two_controllers() {
+    let mut scan_resources: HostResources<1, 2, 27> = HostResources::new();
+    let scan_stack = trouble_host::new(NullController::default(), &mut scan_resources)
+        .set_random_address(Address::random([0xff, 0x8f, 0x1a, 0x05, 0xe4, 0x01]));
+    let mut link_resources: HostResources<1, 2, 251> = HostResources::new();
+    let link_stack = trouble_host::new(NullController::default(), &mut link_resources)
+        .set_random_address(Address::random([0xff, 0x8f, 0x1a, 0x05, 0xe4, 0x02]));
+
+    let mut scan_storage = ScanServerStorage::new();
+    let scan_server = ScanServer::new_default_in("scanner", &mut scan_storage).unwrap();
+    let mut link_storage = LinkServerStorage::new();
+    let link_server = LinkServer::new_default_in("link", &mut link_storage).unwrap();
+    scan_server.set(&scan_server.status.seen, &3).unwrap();
+    link_server.set(&link_server.link.data, &[1; 8]).unwrap();
+    assert_eq!(scan_server.get(&scan_server.status.seen).unwrap(), 3);
+    assert_eq!(link_server.get(&link_server.link.data).unwrap(), [1; 8]);
+
+    let Host {
+        runner: mut scan_runner,
+        ..
+    } = scan_stack.build();
+    let Host {
+        runner: mut link_runner,
+        ..
+    } = link_stack.build();
+    embassy_futures::block_on(select(
+        join(scan_runner.run(), link_runner.run()),
+        join(scan_stack.capabilities(), link_stack.capabilities()),
+    ));
+}
+
+#[test]
+fn service_type_is_constructed_once() {
+    #[gatt_service(uuid = "7e721cf1-b1df-42a1-bb5f-6a1028c793b0")]
+    struct OnceService {
+        #[characteristic(uuid = "7e721cf2-b1df-42a1-bb5f-6a1028c793b0", read)]
+        value: u8,
+    }
+
+    let mut first: AttributeTable<NoopRawMutex, 4> = AttributeTable::new();
+    let _ = OnceService::new(&mut first);
+    let second = std::panic::catch_unwind(|| {
+        let mut second: AttributeTable<NoopRawMutex, 4> = AttributeTable::new();
+        let _ = OnceService::new(&mut second);
+    });
+    assert!(second.is_err());
+}
+
+#[test]
+fn server_type_on_two_hosts() {
+    let mut first_storage = LinkServerStorage::new();
+    let first = LinkServer::new_default_in("first", &mut first_storage).unwrap();
+    let mut second_storage = LinkServerStorage::new();
+    let second = LinkServer::new_default_in("second", &mut second_storage).unwrap();
+    first.set(&first.link.data, &[1; 8]).unwrap();
+    second.set(&second.link.data, &[2; 8]).unwrap();
+    assert_eq!(first.get(&first.link.data).unwrap(), [1; 8]);
+    assert_eq!(second.get(&second.link.data).unwrap(), [2; 8]);
+}
@@ -0,0 +1,201 @@
+//! The Nordic SoftDevice Controller (SDC) as the controller of a trouble host.
+//!
+//! The [`nrf_sdc::SoftdeviceController`] implements the traits of the `bt-hci` crate, so it is
+//! passed to `trouble_host::new` as is. This crate builds it with the roles of the application
+//! and with buffers matching the L2CAP MTU of the `HostResources` of the host, since the
+//! controller rejects packets larger than its buffers.
+//!
+//! ```rust,ignore
+//! let mpsl = MPSL.init(unwrap!(mpsl::MultiprotocolServiceLayer::new(mpsl_p, Irqs, trouble_nrf_sdc::lfclk_rc())));
+//! spawner.must_spawn(mpsl_task(&*mpsl));
+//!
+//! let mut sdc_mem = sdc::Mem::<3312>::new();
+//! let config = trouble_nrf_sdc::Config::new().peripheral(1);
+//! let sdc = unwrap!(trouble_nrf_sdc::build::<L2CAP_MTU, _>(config, sdc_p, &mut rng, mpsl, &mut sdc_mem));
+//!
+//! let mut resources: HostResources<1, 2, L2CAP_MTU> = HostResources::new();
+//! let stack = trouble_host::new(sdc, &mut resources);
+//! ```
+//!
+//! The multiprotocol service layer (MPSL) must be run by a task of the application, as in the
+//! `nrf-sdc` examples. The memory needed by the controller depends on its configuration, and
+//! [`build`] fails if `Mem` is too small.
+#![no_std]
+#![warn(missing_docs)]
+
+use embassy_nrf::peripherals::RNG;
+use embassy_nrf::rng::Rng;
+use nrf_sdc::mpsl::MultiprotocolServiceLayer;
+pub use nrf_sdc::{self, Error, Mem, Peripherals, SoftdeviceController, mpsl};
+
+/// Largest L2CAP MTU of the buffers of the controller.
+pub const MAX_L2CAP_MTU: usize = 251;
+
+/// The roles and buffers of a SoftDevice Controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    #[cfg(feature = "peripheral")]
+    advertise: bool,
+    #[cfg(feature = "peripheral")]
+    extended_advertising: bool,
+    #[cfg(feature = "peripheral")]
+    peripheral_count: u8,
+    #[cfg(feature = "central")]
+    scan: bool,
+    #[cfg(feature = "central")]
+    extended_scanning: bool,
+    #[cfg(feature = "central")]
+    central_count: u8,
+    tx_queue: u8,
+    rx_queue: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Config {
+    /// A controller without any role, queueing 3 packets in each direction per link.
+    pub const fn new() -> Self {
+        Self {
+            #[cfg(feature = "peripheral")]
+            advertise: false,
+            #[cfg(feature = "peripheral")]
+            extended_advertising: false,
+            #[cfg(feature = "peripheral")]
+            peripheral_count: 0,
+            #[cfg(feature = "central")]
+            scan: false,
+            #[cfg(feature = "central")]
+            extended_scanning: false,
+            #[cfg(feature = "central")]
+            central_count: 0,
+            tx_queue: 3,
+            rx_queue: 3,
+        }
+    }
+
+    /// Support advertising, as a broadcaster or a peripheral.
+    #[cfg(feature = "peripheral")]
+    pub const fn advertise(mut self) -> Self {
+        self.advertise = true;
+        self
+    }
+
+    /// Support extended advertising, such as several advertising sets.
+    #[cfg(feature = "peripheral")]
+    pub const fn extended_advertising(mut self) -> Self {
+        self.advertise = true;
+        self.extended_advertising = true;
+        self
+    }
+
+    /// Support scanning, as an observer or a central.
+    #[cfg(feature = "central")]
+    pub const fn scan(mut self) -> Self {
+        self.scan = true;
+        self
+    }
+
+    /// Support extended scanning, and extended connections if the central role is supported.
+    #[cfg(feature = "central")]
+    pub const fn extended_scanning(mut self) -> Self {
+        self.scan = true;
+        self.extended_scanning = true;
+        self
+    }
+
+    /// Support the peripheral role, advertising and accepting up to `connections` connections.
+    #[cfg(feature = "peripheral")]
+    pub const fn peripheral(mut self, connections: u8) -> Self {
+        self.advertise = true;
+        self.peripheral_count = connections;
+        self
+    }
+
+    /// Support the central role, scanning and establishing up to `connections` connections.
+    #[cfg(feature = "central")]
+    pub const fn central(mut self, connections: u8) -> Self {
+        self.scan = true;
+        self.central_count = connections;
+        self
+    }
+
+    /// Number of L2CAP packets queued by the controller per link, when sent and received.
+    ///
+    /// Larger queues send and receive more packets per connection event, using more memory.
+    pub const fn queues(mut self, tx: u8, rx: u8) -> Self {
+        self.tx_queue = tx;
+        self.rx_queue = rx;
+        self
+    }
+}
+
+/// Build a SoftDevice Controller with a configuration, with buffers of `L2CAP_MTU` bytes.
+///
+/// `L2CAP_MTU` must be the L2CAP MTU of the `HostResources` of the host, at most
+/// [`MAX_L2CAP_MTU`].
+pub fn build<'d, const L2CAP_MTU: usize, const N: usize>(
+    config: Config,
+    p: Peripherals<'d>,
+    rng: &'d mut Rng<RNG>,
+    mpsl: &'d MultiprotocolServiceLayer,
+    mem: &'d mut Mem<N>,
+) -> Result<SoftdeviceController<'d>, Error> {
+    const {
+        assert!(
+            L2CAP_MTU <= MAX_L2CAP_MTU,
+            "the L2CAP MTU is larger than the controller buffers"
+        )
+    };
+
+    let mut builder = nrf_sdc::Builder::new()?;
+    #[cfg(feature = "peripheral")]
+    {
+        if config.advertise {
+            builder = builder.support_adv()?;
+        }
+        if config.extended_advertising {
+            builder = builder.support_ext_adv()?;
+        }
+        if config.peripheral_count > 0 {
+            builder = builder
+                .support_peripheral()?
+                .peripheral_count(config.peripheral_count)?;
+        }
+    }
+    #[cfg(feature = "central")]
+    {
+        if config.scan {
+            builder = builder.support_scan()?;
+        }
+        if config.extended_scanning {
+            builder = builder.support_ext_scan()?;
+        }
+        if config.central_count > 0 {
+            builder = builder.support_central()?.central_count(config.central_count)?;
+            if config.extended_scanning {
+                builder = builder.support_ext_central()?;
+            }
+        }
+    }
+    builder
+        .buffer_cfg(L2CAP_MTU as u8, L2CAP_MTU as u8, config.tx_queue, config.rx_queue)?
+        .build(p, rng, mpsl, mem)
+}
+
+/// Low frequency clock of the MPSL, running from the internal RC oscillator.
+///
+/// Boards with a 32.768 kHz crystal can use it instead, with a configuration of their own.
+pub fn lfclk_rc() -> mpsl::raw::mpsl_clock_lfclk_cfg_t {
+    mpsl::raw::mpsl_clock_lfclk_cfg_t {
+        source: mpsl::raw::MPSL_CLOCK_LF_SRC_RC as u8,
+        rc_ctiv: mpsl::raw::MPSL_RECOMMENDED_RC_CTIV as u8,
+        rc_temp_ctiv: mpsl::raw::MPSL_RECOMMENDED_RC_TEMP_CTIV as u8,
+        accuracy_ppm: mpsl::raw::MPSL_DEFAULT_CLOCK_ACCURACY_PPM as u16,
+        skip_wait_lfclk_started: mpsl::raw::MPSL_DEFAULT_SKIP_WAIT_LFCLK_STARTED != 0,
+    }
+}